@@ -1,5 +1,26 @@
 use criterion::{black_box, criterion_group, criterion_main, Benchmark, Criterion};
-use walrus::Module;
+use walrus::{FunctionBuilder, Module, ValType};
+
+/// A module with 100k small functions, each with a few arguments and locals.
+fn many_functions() -> Vec<u8> {
+    let mut module = Module::default();
+    let ty = module
+        .types
+        .add(&[ValType::I32, ValType::I64], &[ValType::I32]);
+    for _ in 0..100_000 {
+        let args = vec![
+            module.locals.add(ValType::I32),
+            module.locals.add(ValType::I64),
+        ];
+        let tmp = module.locals.add(ValType::I32);
+        let mut builder = FunctionBuilder::new();
+        let arg = builder.local_get(args[0]);
+        let set = builder.local_set(tmp, arg);
+        let get = builder.local_get(tmp);
+        builder.finish(ty, args, vec![set, get], &mut module);
+    }
+    module.emit_wasm().unwrap()
+}
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench(
@@ -15,6 +36,18 @@ fn criterion_benchmark(c: &mut Criterion) {
             });
         }),
     );
+
+    let input_wasm = many_functions();
+    c.bench(
+        "parse",
+        Benchmark::new("100k-functions", move |b| {
+            b.iter(|| {
+                let module = Module::from_buffer(black_box(&input_wasm)).unwrap();
+                black_box(module);
+            });
+        })
+        .sample_size(10),
+    );
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -22,6 +22,12 @@ pub struct Config {
     scratch: tempfile::NamedTempFile,
 }
 
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
 impl Config {
     /// The default fuel level.
     pub const DEFAULT_FUEL: usize = 64;
@@ -67,19 +73,19 @@ impl Config {
     }
 
     fn interp(&self, wasm: &[u8]) -> String {
-        fs::write(self.scratch.path(), &wasm).unwrap();
+        fs::write(self.scratch.path(), wasm).unwrap();
         wasm_interp(self.scratch.path())
     }
 
     fn round_trip_through_walrus(&self, wasm: &[u8]) -> Vec<u8> {
-        walrus::Module::from_buffer(&wasm)
+        walrus::Module::from_buffer(wasm)
             .unwrap()
             .emit_wasm()
             .unwrap()
     }
 
     fn run_one(&self, wat: &str) -> Option<FailingTestCase> {
-        let wasm = self.wat2wasm(&wat);
+        let wasm = self.wat2wasm(wat);
         let expected = self.interp(&wasm);
 
         let walrus_wasm = self.round_trip_through_walrus(&wasm);
@@ -252,7 +258,7 @@ where
         self.wat.push_str(&operator.to_string());
 
         for op in immediates.into_iter() {
-            self.wat.push_str(" ");
+            self.wat.push(' ');
             self.wat.push_str(op.as_ref());
         }
 
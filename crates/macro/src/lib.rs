@@ -31,6 +31,7 @@ pub fn walrus_expr(_attr: TokenStream, input: TokenStream) -> TokenStream {
     let display = create_display(&variants);
     let dot = create_dot(&variants);
     let builder = create_builder(&variants);
+    let operand_order = create_operand_order(&variants);
 
     let expanded = quote! {
         #types
@@ -39,6 +40,7 @@ pub fn walrus_expr(_attr: TokenStream, input: TokenStream) -> TokenStream {
         #display
         #dot
         #builder
+        #operand_order
     };
 
     TokenStream::from(expanded)
@@ -55,6 +57,7 @@ struct WalrusVariantOpts {
     display_name: Option<syn::Ident>,
     display_extra: Option<syn::Ident>,
     dot_name: Option<syn::Ident>,
+    operand_order: Option<Vec<syn::Ident>>,
 }
 
 #[derive(Default)]
@@ -112,7 +115,7 @@ impl Parse for WalrusFieldOpts {
                 if attr == "skip_visit" {
                     return Ok(Attr::SkipVisit);
                 }
-                return Err(Error::new(attr.span(), "unexpected attribute"));
+                Err(Error::new(attr.span(), "unexpected attribute"))
             }
         }
     }
@@ -124,6 +127,7 @@ impl Parse for WalrusVariantOpts {
             DisplayName(syn::Ident),
             DisplayExtra(syn::Ident),
             DotName(syn::Ident),
+            OperandOrder(Vec<syn::Ident>),
         }
 
         let attrs = Punctuated::<_, syn::token::Comma>::parse_terminated(input)?;
@@ -133,6 +137,7 @@ impl Parse for WalrusVariantOpts {
                 Attr::DisplayName(ident) => ret.display_name = Some(ident),
                 Attr::DisplayExtra(ident) => ret.display_extra = Some(ident),
                 Attr::DotName(ident) => ret.dot_name = Some(ident),
+                Attr::OperandOrder(fields) => ret.operand_order = Some(fields),
             }
         }
         return Ok(ret);
@@ -155,7 +160,13 @@ impl Parse for WalrusVariantOpts {
                     let name = input.call(Ident::parse_any)?;
                     return Ok(Attr::DotName(name));
                 }
-                return Err(Error::new(attr.span(), "unexpected attribute"));
+                if attr == "operand_order" {
+                    let content;
+                    syn::parenthesized!(content in input);
+                    let fields = Punctuated::<Ident, Token![,]>::parse_terminated(&content)?;
+                    return Ok(Attr::OperandOrder(fields.into_iter().collect()));
+                }
+                Err(Error::new(attr.span(), "unexpected attribute"))
             }
         }
     }
@@ -176,7 +187,7 @@ fn walrus_attrs(attrs: &mut Vec<syn::Attribute>) -> TokenStream {
         ret.extend(group.stream());
         ret.extend(quote! { , });
     }
-    return ret.into();
+    ret.into()
 }
 
 fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl quote::ToTokens {
@@ -466,8 +477,8 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
             }
         });
 
-        let doc = format!("Visit `{}`.", name.to_string());
-        let doc_id = format!("Visit `{}Id`.", name.to_string());
+        let doc = format!("Visit `{}`.", name);
+        let doc_id = format!("Visit `{}Id`.", name);
         visitor_trait_methods.push(quote! {
             #[doc=#doc]
             fn #method_name(&mut self, expr: &#name) {
@@ -504,6 +515,15 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
 
     quote! {
         /// A visitor walks over an IR expression tree.
+        ///
+        /// There is a `visit_*` method for every kind of expression, and for
+        /// every kind of id an expression can refer to. The default
+        /// implementations recurse into an expression's operands, so only
+        /// the methods for the interesting parts of the IR need overriding.
+        /// Expressions added to the IR in the future will also get default
+        /// methods, so implementations of this trait keep compiling.
+        ///
+        /// See `dfs_in_order` for starting a traversal.
         pub trait Visitor<'expr>: Sized {
             /// Return the local function we're visiting
             fn local_function(&self) -> &'expr crate::LocalFunction;
@@ -548,6 +568,16 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 // ...
             }
 
+            /// Visit `ElementId`.
+            fn visit_element_id(&mut self, element: &crate::ElementId) {
+                // ...
+            }
+
+            /// Visit `TagId`.
+            fn visit_tag_id(&mut self, tag: &crate::TagId) {
+                // ...
+            }
+
             /// Visit `TypeId`
             fn visit_type_id(&mut self, ty: &crate::TypeId) {
                 // ...
@@ -562,6 +592,9 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
         }
 
         /// A visitor walks over a mutable IR expression tree.
+        ///
+        /// This has the same default methods and stability guarantees as
+        /// `Visitor`. See `dfs_pre_order_mut` for starting a traversal.
         pub trait VisitorMut: Sized {
             /// Return the local function we're visiting
             fn local_function_mut(&mut self) -> &mut crate::LocalFunction;
@@ -606,6 +639,16 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 // ...
             }
 
+            /// Visit `ElementId`.
+            fn visit_element_id_mut(&mut self, element: &mut crate::ElementId) {
+                // ...
+            }
+
+            /// Visit `TagId`.
+            fn visit_tag_id_mut(&mut self, tag: &mut crate::TagId) {
+                // ...
+            }
+
             /// Visit `TypeId`
             fn visit_type_id_mut(&mut self, ty: &mut crate::TypeId) {
                 // ...
@@ -814,7 +857,7 @@ fn create_display(variants: &[WalrusVariant]) -> impl quote::ToTokens {
             }
 
             fn visit_local_id(&mut self, local: &crate::LocalId) {
-                self.id(*local);
+                self.local(*local);
             }
 
             fn visit_memory_id(&mut self, memory: &crate::MemoryId) {
@@ -841,6 +884,14 @@ fn create_display(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 self.id(*data);
             }
 
+            fn visit_element_id(&mut self, element: &crate::ElementId) {
+                self.id(*element);
+            }
+
+            fn visit_tag_id(&mut self, tag: &crate::TagId) {
+                self.id(*tag);
+            }
+
             fn visit_value(&mut self, value: &crate::ir::Value) {
                 self.f.push_str(" ");
                 self.f.push_str(&value.to_string());
@@ -929,6 +980,14 @@ fn create_dot(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 self.id(*data);
             }
 
+            fn visit_element_id(&mut self, element: &crate::ElementId) {
+                self.id(*element);
+            }
+
+            fn visit_tag_id(&mut self, tag: &crate::TagId) {
+                self.id(*tag);
+            }
+
             fn visit_value(&mut self, value: &crate::ir::Value) {
                 self.out.push_str(" ");
                 self.out.push_str(&value.to_string());
@@ -945,7 +1004,7 @@ fn create_builder(variants: &[WalrusVariant]) -> impl quote::ToTokens {
         let name = &variant.syn.ident;
 
         let mut method_name = name.to_string().to_snake_case();
-        if method_name == "return" || method_name == "const" {
+        if method_name == "return" || method_name == "const" || method_name == "try" {
             method_name.push('_');
         } else if method_name == "block" {
             continue;
@@ -969,9 +1028,61 @@ fn create_builder(variants: &[WalrusVariant]) -> impl quote::ToTokens {
         });
     }
     quote! {
-        #[allow(missing_docs)]
+        #[allow(missing_docs, clippy::too_many_arguments)]
         impl crate::FunctionBuilder {
             #(#builder_methods)*
         }
     }
 }
+
+fn create_operand_order(variants: &[WalrusVariant]) -> impl quote::ToTokens {
+    let mut arms = Vec::new();
+    for variant in variants {
+        let name = &variant.syn.ident;
+        let operands: Vec<String> = visit_fields(variant, true)
+            .filter(|(method_name, _, _)| method_name == "visit_expr_id")
+            .map(|(_, field_name, _)| field_name.to_string())
+            .collect();
+
+        // The order defaults to the order the fields are declared in, but can
+        // be given explicitly when they're evaluated in some other order.
+        let order = match &variant.opts.operand_order {
+            Some(fields) => {
+                let order: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+                let mut sorted = order.clone();
+                sorted.sort();
+                let mut expected = operands.clone();
+                expected.sort();
+                if sorted != expected {
+                    panic!(
+                        "#[walrus(operand_order(..))] on `{}` must list each of its operands once",
+                        name
+                    );
+                }
+                order
+            }
+            None => operands,
+        };
+
+        arms.push(quote! {
+            Expr::#name(_) => &[ #( #order ),* ],
+        });
+    }
+    quote! {
+        impl Expr {
+            /// The names of this expression's operand fields, in the order
+            /// their values are evaluated and pushed onto the stack when it's
+            /// emitted.
+            ///
+            /// This is the order of the operands of the corresponding wasm
+            /// instruction, which isn't always the order the fields are
+            /// declared in. A field holding a list of operands, such as a
+            /// call's `args`, stands for all of them, evaluated first to last.
+            pub fn operand_order(&self) -> &'static [&'static str] {
+                match self {
+                    #( #arms )*
+                }
+            }
+        }
+    }
+}
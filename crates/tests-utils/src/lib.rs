@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::{Once, ONCE_INIT};
+use std::sync::Once;
 
 pub const FEATURES: &[&str] = &[
     "--enable-threads",
@@ -27,7 +27,7 @@ fn require_wat2wasm() {
 
 /// Compile the `.wat` file at the given path into a `.wasm`.
 pub fn wat2wasm(path: &Path) -> Vec<u8> {
-    static CHECK: Once = ONCE_INIT;
+    static CHECK: Once = Once::new();
     CHECK.call_once(require_wat2wasm);
 
     let file = tempfile::NamedTempFile::new().unwrap();
@@ -70,7 +70,7 @@ fn require_wasm2wat() {
 
 /// Disassemble the `.wasm` file at the given path into a `.wat`.
 pub fn wasm2wat(path: &Path) -> String {
-    static CHECK: Once = ONCE_INIT;
+    static CHECK: Once = Once::new();
     CHECK.call_once(require_wasm2wat);
 
     let mut cmd = Command::new("wasm2wat");
@@ -103,7 +103,7 @@ fn require_wasm_interp() {
 
 /// Run the wasm-interp on the given wat file.
 pub fn wasm_interp(path: &Path) -> String {
-    static CHECK: Once = ONCE_INIT;
+    static CHECK: Once = Once::new();
     CHECK.call_once(require_wasm_interp);
 
     let mut cmd = Command::new("wasm-interp");
@@ -147,3 +147,74 @@ impl TestResult for Result<(), failure::Error> {
         panic!("test failed");
     }
 }
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> u32 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= u32::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// Split a `.wasm` binary into its sections, as each one's id and payload.
+pub fn sections(wasm: &[u8]) -> Vec<(u8, &[u8])> {
+    assert_eq!(&wasm[..4], b"\0asm", "not a wasm binary");
+    let mut sections = Vec::new();
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let len = read_u32(wasm, &mut pos) as usize;
+        sections.push((id, &wasm[pos..pos + len]));
+        pos += len;
+    }
+    sections
+}
+
+/// The payload of the section with the given id, which must be in `wasm`.
+pub fn section(wasm: &[u8], id: u8) -> &[u8] {
+    match sections(wasm).into_iter().find(|(i, _)| *i == id) {
+        Some((_, payload)) => payload,
+        None => panic!("no section with id {}", id),
+    }
+}
+
+/// The bodies of the code section in order, each starting with its locals.
+pub fn function_bodies(wasm: &[u8]) -> Vec<&[u8]> {
+    let code = section(wasm, 10);
+    let mut pos = 0;
+    let count = read_u32(code, &mut pos);
+    let mut bodies = Vec::new();
+    for _ in 0..count {
+        let len = read_u32(code, &mut pos) as usize;
+        bodies.push(&code[pos..pos + len]);
+        pos += len;
+    }
+    bodies
+}
+
+/// Build a `.wasm` binary out of the given sections, each one's id and
+/// payload, with their sizes padded to five bytes the way walrus emits them.
+///
+/// Modules built this way come out of walrus byte-for-byte unchanged, as long
+/// as it writes the sections the same way.
+pub fn module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    for (id, payload) in sections {
+        wasm.push(*id);
+        let mut len = payload.len() as u32;
+        for i in 0..5 {
+            let more = if i < 4 { 0x80 } else { 0 };
+            wasm.push((len & 0x7f) as u8 | more);
+            len >>= 7;
+        }
+        wasm.extend_from_slice(payload);
+    }
+    wasm
+}
@@ -0,0 +1,196 @@
+//! Tests for blocks that take parameters.
+
+use std::fs;
+use walrus::ir::Expr;
+use walrus::{ExportItem, FunctionBuilder, Module, ModuleConfig, ValType};
+use walrus_tests_utils::{function_bodies, section, wasm_interp};
+
+/// Build a module exporting a function which passes `i32.const 1` through a
+/// block of type `[i32] -> [i32]`.
+fn module_with_block_params(loop_: bool) -> Module {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let one = builder.i32_const(1);
+    let params = Box::new([ValType::I32]);
+    let results = Box::new([ValType::I32]);
+    let block = if loop_ {
+        builder.loop_with_params(params, results).id()
+    } else {
+        builder.block(params, results).id()
+    };
+    let func = builder.finish(ty, vec![], vec![one, block.into()], &mut module);
+    module.exports.add("f", ExportItem::Function(func));
+    module
+}
+
+/// Run the exported function with the interpreter, returning its output.
+fn run(module: &Module) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("block_params.wasm");
+    fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+    wasm_interp(&path)
+}
+
+#[test]
+fn block_type_is_a_type_index() {
+    let module = module_with_block_params(false);
+    assert!(module
+        .types
+        .find(&[ValType::I32], &[ValType::I32])
+        .is_some());
+
+    let wasm = module.emit_wasm().unwrap();
+    // The block's type is the second entry in the type section.
+    let types = [0x02, 0x60, 0x00, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x01, 0x7f];
+    assert_eq!(section(&wasm, 1), types);
+    let body = [0x00, 0x41, 0x01, 0x02, 0x01, 0x0b, 0x0b];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+    assert!(run(&module).contains("f() => i32:1"));
+}
+
+#[test]
+fn loop_type_is_a_type_index() {
+    let module = module_with_block_params(true);
+    let wasm = module.emit_wasm().unwrap();
+    let body = [0x00, 0x41, 0x01, 0x03, 0x01, 0x0b, 0x0b];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+    assert!(run(&module).contains("f() => i32:1"));
+}
+
+#[test]
+fn missing_block_types_are_an_error() {
+    let mut module = module_with_block_params(false);
+    let ty = module.types.find(&[ValType::I32], &[ValType::I32]).unwrap();
+    module.types.delete(ty);
+    let err = module.emit_wasm().unwrap_err();
+    assert!(
+        err.to_string().contains("isn't in the module's types"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn gc_keeps_block_types() {
+    let mut module = module_with_block_params(false);
+    walrus::passes::gc::run(&mut module);
+    assert!(module
+        .types
+        .find(&[ValType::I32], &[ValType::I32])
+        .is_some());
+}
+
+#[test]
+fn type_index_block_types_are_parsed() {
+    #[rustfmt::skip]
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // (type (func (result i32)))
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        // (func (type 0) (block (type 0) i32.const 7))
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x02, 0x00, 0x41, 0x07, 0x0b, 0x0b,
+    ];
+    let module = Module::from_buffer(&wasm).unwrap();
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let entry = func.block(func.entry_block());
+    let block = match func.get(entry.exprs[0]) {
+        Expr::Block(block) => block,
+        e => panic!("expected a block, found {:?}", e),
+    };
+    assert!(block.params.is_empty());
+    assert_eq!(&block.results[..], [ValType::I32]);
+
+    // A single result is emitted as a value type instead.
+    let wasm = module.emit_wasm().unwrap();
+    let body = [0x00, 0x02, 0x7f, 0x41, 0x07, 0x0b, 0x0b];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+}
+
+#[test]
+fn block_params_are_parsed() {
+    #[rustfmt::skip]
+    let body = [
+        0x00,
+        0x41, 0x01,
+        // block (type 0) local.get 0 i32.add end
+        0x02, 0x00, 0x20, 0x00, 0x6a, 0x0b,
+        // local.get 0 if (type 0) i32.const 2 i32.mul end
+        0x20, 0x00, 0x04, 0x00, 0x41, 0x02, 0x6c, 0x0b,
+        0x0b,
+    ];
+    #[rustfmt::skip]
+    let mut wasm = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // (type (func (param i32) (result i32)))
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, body.len() as u8 + 2, 0x01, body.len() as u8,
+    ];
+    wasm.extend_from_slice(&body);
+
+    let module = Module::from_buffer(&wasm).unwrap();
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    // The `if` takes the block's result, and is evaluated just after it.
+    let entry = func.block(func.entry_block());
+    let if_else = match func.get(entry.exprs[0]) {
+        Expr::WithSideEffects(e) => match func.get(e.value) {
+            Expr::IfElse(e) => e,
+            e => panic!("expected an if, found {:?}", e),
+        },
+        e => panic!("expected the if's operands, found {:?}", e),
+    };
+    let consequent = func.block(if_else.consequent);
+    assert_eq!(&consequent.params[..], [ValType::I32]);
+    assert!(matches!(func.get(consequent.exprs[0]), Expr::Binop(_)));
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+}
+
+#[test]
+fn loop_params_are_parsed() {
+    // Counts down from the function's parameter, passing the count to each
+    // iteration of the loop as its parameter.
+    #[rustfmt::skip]
+    let body = [
+        0x00,
+        0x20, 0x00,
+        // loop (type 0)
+        0x03, 0x00,
+        // i32.const 1 i32.sub local.tee 0 local.get 0 br_if 0
+        0x41, 0x01, 0x6b, 0x22, 0x00, 0x20, 0x00, 0x0d, 0x00,
+        0x0b,
+        0x0b,
+    ];
+    #[rustfmt::skip]
+    let mut wasm = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // (type (func (param i32) (result i32)))
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x0a, body.len() as u8 + 2, 0x01, body.len() as u8,
+    ];
+    wasm.extend_from_slice(&body);
+
+    let module = Module::from_buffer(&wasm).unwrap();
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let entry = func.block(func.entry_block());
+    let block = match func.get(entry.exprs[0]) {
+        Expr::WithSideEffects(e) => match func.get(e.value) {
+            Expr::Block(block) => block,
+            e => panic!("expected a loop, found {:?}", e),
+        },
+        e => panic!("expected the loop's operands, found {:?}", e),
+    };
+    assert_eq!(&block.params[..], [ValType::I32]);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+}
@@ -0,0 +1,83 @@
+//! Tests for embedding build ids in modules.
+
+use walrus::ir::Value;
+use walrus::{BuildIdStyle, FunctionBuilder, InitExpr, Module, ValType};
+
+/// A module exporting a function which returns `value`.
+fn module(value: i32) -> Module {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let value = builder.i32_const(value);
+    let f = builder.finish(ty, vec![], vec![value], &mut module);
+    module.exports.add("f", f);
+    module
+}
+
+fn content_hash(module: &mut Module) -> Vec<u8> {
+    module.add_build_id(BuildIdStyle::ContentHash).unwrap();
+    module.build_id().unwrap().to_vec()
+}
+
+#[test]
+fn content_hashes_are_reproducible() {
+    let mut module = module(1);
+    assert_eq!(module.build_id(), None);
+    let id = content_hash(&mut module);
+    assert_eq!(id.len(), 16);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+    assert!(wasmparser::validate(&wasm, None));
+
+    // Hashing again ignores the existing build id.
+    assert_eq!(content_hash(&mut module), id);
+    assert_eq!(content_hash(&mut self::module(1)), id);
+
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(parsed.build_id(), Some(&id[..]));
+}
+
+#[test]
+fn changes_change_the_content_hash() {
+    let mut module = module(1);
+    let id = content_hash(&mut module);
+
+    let init = InitExpr::Value(Value::I64(0));
+    module.globals.add_local(ValType::I64, false, init);
+    let changed = content_hash(&mut module);
+    assert_ne!(changed, id);
+
+    // So does a different constant in a function body.
+    assert_ne!(content_hash(&mut self::module(2)), id);
+}
+
+#[test]
+fn provided_ids() {
+    let mut module = module(1);
+    module
+        .add_build_id(BuildIdStyle::Bytes(b"release-42".to_vec()))
+        .unwrap();
+    assert_eq!(module.build_id(), Some(&b"release-42"[..]));
+
+    let wasm = module.emit_wasm().unwrap();
+    let mut parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(parsed.build_id(), Some(&b"release-42"[..]));
+
+    // There's only ever one build id.
+    parsed
+        .add_build_id(BuildIdStyle::Bytes(vec![1, 2]))
+        .unwrap();
+    assert_eq!(parsed.build_id(), Some(&[1, 2][..]));
+    let wasm = parsed.emit_wasm().unwrap();
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    let sections = parsed
+        .customs
+        .iter()
+        .filter(|(_, s)| s.name() == "build_id");
+    assert_eq!(sections.count(), 1);
+
+    let mut module = parsed;
+    module.remove_build_id();
+    assert_eq!(module.build_id(), None);
+}
@@ -0,0 +1,43 @@
+//! Tests for looking functions up by the names they're exported with.
+
+use walrus::{ExportItem, FunctionBuilder, Module, ValType, WrongExportKind};
+
+#[test]
+fn get_func() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let imported = module.add_import_func("env", "imported", ty);
+    let memory = module.memories.add_local(false, 1, None);
+    module.exports.add("f", f);
+    module.exports.add("reexported", imported);
+    let export = module.exports.add("memory", memory);
+
+    assert_eq!(module.exports.get_func("f").unwrap(), f);
+    assert_eq!(module.exports.get_func("reexported").unwrap(), imported);
+    assert_eq!(module.exports.find("memory"), Some(export));
+    assert_eq!(module.exports.find("missing"), None);
+
+    let err = module.exports.get_func("memory").unwrap_err();
+    let err = err.downcast::<WrongExportKind>().unwrap();
+    assert_eq!(err.name, "memory");
+    assert_eq!(err.item, ExportItem::Memory(memory));
+    assert_eq!(
+        err.to_string(),
+        "the export `memory` is a memory, not a function"
+    );
+
+    let err = module.exports.get_func("missing").unwrap_err();
+    assert!(err.downcast::<WrongExportKind>().is_err());
+}
+
+#[test]
+fn deleted_exports_arent_found() {
+    let mut module = Module::default();
+    let global = module.add_import_global("env", "g", ValType::I32, false);
+    let export = module.exports.add("g", global);
+    assert_eq!(module.exports.find("g"), Some(export));
+    module.exports.delete(export);
+    assert_eq!(module.exports.find("g"), None);
+    assert!(module.exports.get_func("g").is_err());
+}
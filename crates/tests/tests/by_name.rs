@@ -0,0 +1,53 @@
+//! Tests for looking functions up by the names in the `name` section.
+
+use walrus::{AmbiguousName, Module};
+
+/// A module with three `(func)`s, the first two of which are named `dup` and
+/// the third `other`.
+fn module() -> Module {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x03, 0x04, 0x03, 0x00, 0x00, 0x00]); // function section
+    wasm.extend(&[0x0a, 0x0a, 0x03]); // code section
+    for _ in 0..3 {
+        wasm.extend(&[0x02, 0x00, 0x0b]);
+    }
+    wasm.extend(&[0x00, 0x19, 0x04, b'n', b'a', b'm', b'e']); // name section
+    wasm.extend(&[0x01, 0x12, 0x03]); // function names
+    wasm.extend(&[0x00, 0x03, b'd', b'u', b'p']);
+    wasm.extend(&[0x01, 0x03, b'd', b'u', b'p']);
+    wasm.extend(&[0x02, 0x05, b'o', b't', b'h', b'e', b'r']);
+    Module::from_buffer(&wasm).unwrap()
+}
+
+#[test]
+fn duplicate_names() {
+    let module = module();
+    let ids = module.funcs.iter().map(|f| f.id()).collect::<Vec<_>>();
+
+    assert_eq!(
+        module.funcs.iter_by_name("dup").collect::<Vec<_>>(),
+        [ids[0], ids[1]]
+    );
+    assert_eq!(module.funcs.by_name("dup"), Some(ids[0]));
+
+    let err = module.funcs.by_name_unique("dup").unwrap_err();
+    let err = err.downcast::<AmbiguousName>().unwrap();
+    assert_eq!(err.name, "dup");
+    assert_eq!(err.functions, [ids[0], ids[1]]);
+}
+
+#[test]
+fn unique_and_missing_names() {
+    let module = module();
+    let other = module.funcs.iter().nth(2).unwrap().id();
+
+    assert_eq!(
+        module.funcs.iter_by_name("other").collect::<Vec<_>>(),
+        [other]
+    );
+    assert_eq!(module.funcs.by_name_unique("other").unwrap(), Some(other));
+
+    assert_eq!(module.funcs.iter_by_name("missing").count(), 0);
+    assert_eq!(module.funcs.by_name_unique("missing").unwrap(), None);
+}
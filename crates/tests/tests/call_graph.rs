@@ -0,0 +1,153 @@
+//! Tests for the call graph analysis.
+
+use walrus::analysis::{CallGraph, CallGraphOptions};
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, FunctionTable, InitExpr, Module};
+use walrus::{TableKind, TypeId, ValType};
+
+fn calling(module: &mut Module, ty: TypeId, callees: &[FunctionId]) -> FunctionId {
+    let mut builder = FunctionBuilder::new();
+    let body = callees
+        .iter()
+        .map(|f| builder.call(*f, Box::new([])))
+        .collect();
+    builder.finish(ty, vec![], body, module)
+}
+
+#[test]
+fn direct_calls() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let import = module.add_import_func("env", "f", ty);
+    let leaf = calling(&mut module, ty, &[import]);
+    let a = calling(&mut module, ty, &[leaf, leaf]);
+    let b = calling(&mut module, ty, &[a, leaf]);
+
+    let graph = CallGraph::new(&module);
+    assert_eq!(graph.callees(b), [leaf, a]);
+    assert_eq!(graph.callees(a), [leaf]);
+    assert!(graph.callees(import).is_empty());
+    assert_eq!(graph.callers(leaf), [a, b]);
+    assert_eq!(graph.callers(import), [leaf]);
+    assert!(graph.callers(b).is_empty());
+    assert!(!graph.is_recursive(a));
+
+    let order = graph.reverse_topological_order().collect::<Vec<_>>();
+    assert_eq!(order, [import, leaf, a, b]);
+}
+
+#[test]
+fn recursion() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let leaf = calling(&mut module, ty, &[]);
+
+    // `a` and `b` call each other, and `c` calls itself, so they need
+    // patching up once they all exist.
+    let a = calling(&mut module, ty, &[leaf]);
+    let b = calling(&mut module, ty, &[a]);
+    let c = calling(&mut module, ty, &[b]);
+    for (caller, callee) in [(a, b), (c, c)].iter() {
+        let func = match &mut module.funcs.get_mut(*caller).kind {
+            FunctionKind::Local(f) => f,
+            _ => unreachable!(),
+        };
+        let call = func.builder_mut().call(*callee, Box::new([]));
+        let entry = func.entry_block();
+        func.block_mut(entry).exprs.push(call);
+    }
+
+    let graph = CallGraph::new(&module);
+    assert!(!graph.is_recursive(leaf));
+    assert!(graph.is_recursive(a));
+    assert!(graph.is_recursive(b));
+    assert!(graph.is_recursive(c));
+    assert_eq!(graph.scc(a), [a, b]);
+    assert_eq!(graph.scc(c), [c]);
+    assert_eq!(graph.sccs(), [vec![leaf], vec![a, b], vec![c]]);
+
+    let order = graph.reverse_topological_order().collect::<Vec<_>>();
+    assert_eq!(order, [leaf, a, b, c]);
+}
+
+#[test]
+fn indirect_calls() {
+    let mut module = Module::default();
+    let unit = module.types.add(&[], &[]);
+    let other = module.types.add(&[], &[ValType::I32]);
+    let table = module
+        .tables
+        .add_local(2, None, TableKind::Function(FunctionTable::default()));
+
+    let in_table = calling(&mut module, unit, &[]);
+    let wrong_type = FunctionBuilder::new().finish(other, vec![], vec![], &mut module);
+    let passive = calling(&mut module, unit, &[]);
+    let not_in_table = calling(&mut module, unit, &[]);
+    let zero = InitExpr::Value(Value::I32(0));
+    module
+        .elements
+        .add_active(table, zero, &[in_table, wrong_type]);
+    module.elements.add_passive(&[passive]);
+
+    let mut builder = FunctionBuilder::new();
+    let index = builder.i32_const(0);
+    let call = builder.call_indirect(unit, table, index, Box::new([]));
+    let caller = builder.finish(unit, vec![], vec![call], &mut module);
+
+    let graph = CallGraph::new(&module);
+    assert_eq!(graph.callees(caller), [in_table, passive]);
+    assert_eq!(graph.callers(in_table), [caller]);
+    assert!(graph.callers(wrong_type).is_empty());
+    assert!(graph.callers(not_in_table).is_empty());
+
+    let options = CallGraphOptions {
+        indirect_calls: false,
+    };
+    let graph = CallGraph::with_options(&module, &options);
+    assert!(graph.callees(caller).is_empty());
+}
+
+#[test]
+fn deep_call_chains() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let mut chain = vec![calling(&mut module, ty, &[])];
+    for _ in 0..100_000 {
+        let next = calling(&mut module, ty, &[*chain.last().unwrap()]);
+        chain.push(next);
+    }
+
+    let graph = CallGraph::new(&module);
+    assert_eq!(graph.sccs().len(), chain.len());
+    let order = graph.reverse_topological_order().collect::<Vec<_>>();
+    assert_eq!(order, chain);
+}
+
+#[test]
+fn local_functions_in_topological_order() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let import = module.add_import_func("env", "f", ty);
+    let leaf = calling(&mut module, ty, &[import]);
+    let a = calling(&mut module, ty, &[leaf]);
+    let b = calling(&mut module, ty, &[a]);
+    let c = calling(&mut module, ty, &[b]);
+    let d = calling(&mut module, ty, &[]);
+    let func = match &mut module.funcs.get_mut(a).kind {
+        FunctionKind::Local(f) => f,
+        _ => unreachable!(),
+    };
+    let call = func.builder_mut().call(b, Box::new([]));
+    let entry = func.entry_block();
+    func.block_mut(entry).exprs.push(call);
+
+    let groups = module
+        .funcs
+        .iter_local_topological(&module)
+        .map(|group| group.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(groups, [vec![leaf], vec![a, b], vec![c], vec![d]]);
+
+    let layers = module.funcs.local_topological_layers(&module);
+    assert_eq!(layers, [vec![leaf, d], vec![a, b], vec![c]]);
+}
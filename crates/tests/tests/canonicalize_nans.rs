@@ -0,0 +1,215 @@
+//! Tests for collapsing NaN constants to the canonical NaN.
+
+use walrus::ir::*;
+use walrus::passes::{canonicalize_nans, NanMode};
+use walrus::{FunctionBuilder, InitExpr, Module, ModuleConfig, ValType};
+use walrus_tests_utils::{function_bodies, section};
+
+const F32_NAN: u32 = 0x7f80_0001;
+const F32_CANONICAL: u32 = 0x7fc0_0000;
+const F64_NEG_NAN: u64 = 0xfff0_0000_0000_0001;
+const F64_NEG_CANONICAL: u64 = 0xfff8_0000_0000_0000;
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+/// A module with a single exported function with the given body, which
+/// builds expressions with no results.
+fn module(body: impl FnOnce(&mut FunctionBuilder) -> Vec<ExprId>) -> Module {
+    let mut module = Module::with_config(config());
+    let ty = module.types.add(&[], &[]);
+    let mut builder = FunctionBuilder::new();
+    let exprs = body(&mut builder);
+    let func = builder.finish(ty, vec![], exprs, &mut module);
+    module.exports.add("f", func);
+    module
+}
+
+fn f32_const(bits: u32) -> Vec<u8> {
+    let mut bytes = vec![0x43];
+    bytes.extend_from_slice(&bits.to_le_bytes());
+    bytes
+}
+
+fn f64_const(bits: u64) -> Vec<u8> {
+    let mut bytes = vec![0x44];
+    bytes.extend_from_slice(&bits.to_le_bytes());
+    bytes
+}
+
+fn v128_const(bits: u128) -> Vec<u8> {
+    let mut bytes = vec![0xfd, 0x0c];
+    bytes.extend_from_slice(&bits.to_le_bytes());
+    bytes
+}
+
+/// The body of a function without locals made of the given instructions.
+fn body(instrs: &[&[u8]]) -> Vec<u8> {
+    let mut body = vec![0x00];
+    for instr in instrs {
+        body.extend_from_slice(instr);
+    }
+    body.push(0x0b);
+    body
+}
+
+/// Emit `module`, check that it parses back to the same bytes, and return
+/// its only function body.
+fn emit(module: &Module) -> Vec<u8> {
+    let wasm = module.emit_wasm().unwrap();
+    let parsed = config().parse(&wasm).unwrap();
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+    function_bodies(&wasm)[0].to_vec()
+}
+
+/// `f32.add` of a NaN and one, and `f64.mul` of the negation of a negative
+/// NaN and one, both dropped.
+fn arithmetic(builder: &mut FunctionBuilder) -> Vec<ExprId> {
+    let nan = builder.f32_const(f32::from_bits(F32_NAN));
+    let one = builder.f32_const(1.0);
+    let sum = builder.binop(BinaryOp::F32Add, nan, one);
+    let nan = builder.f64_const(f64::from_bits(F64_NEG_NAN));
+    let neg = builder.unop(UnaryOp::F64Neg, nan);
+    let one = builder.f64_const(1.0);
+    let product = builder.binop(BinaryOp::F64Mul, neg, one);
+    vec![builder.drop(sum), builder.drop(product)]
+}
+
+#[test]
+fn nans_in_arithmetic_are_canonicalized() {
+    for mode in [NanMode::Conservative, NanMode::Aggressive].iter() {
+        let mut module = module(arithmetic);
+        assert_eq!(canonicalize_nans(&mut module, *mode), 2);
+        // The sign of the negative NaN is kept.
+        let expected = body(&[
+            &f32_const(F32_CANONICAL),
+            &f32_const(1f32.to_bits()),
+            &[0x92, 0x1a], // f32.add, drop
+            &f64_const(F64_NEG_CANONICAL),
+            &[0x9a], // f64.neg
+            &f64_const(1f64.to_bits()),
+            &[0xa2, 0x1a], // f64.mul, drop
+        ]);
+        assert_eq!(emit(&module), expected);
+        assert!(wasmparser::validate(&module.emit_wasm().unwrap(), None));
+
+        assert_eq!(canonicalize_nans(&mut module, *mode), 0);
+    }
+}
+
+#[test]
+fn reinterpreted_nans_are_only_canonicalized_when_aggressive() {
+    let reinterpret = |builder: &mut FunctionBuilder| {
+        let nan = builder.f32_const(f32::from_bits(F32_NAN));
+        let abs = builder.unop(UnaryOp::F32Abs, nan);
+        let bits = builder.unop(UnaryOp::I32ReinterpretF32, abs);
+        vec![builder.drop(bits)]
+    };
+
+    // f32.abs, i32.reinterpret_f32, drop
+    let rest: &[u8] = &[0x8b, 0xbc, 0x1a];
+
+    let mut module = module(reinterpret);
+    assert_eq!(canonicalize_nans(&mut module, NanMode::Conservative), 0);
+    assert_eq!(emit(&module), body(&[&f32_const(F32_NAN), rest]));
+
+    assert_eq!(canonicalize_nans(&mut module, NanMode::Aggressive), 1);
+    assert_eq!(emit(&module), body(&[&f32_const(F32_CANONICAL), rest]));
+}
+
+#[test]
+fn other_values_are_untouched() {
+    let zeros = |builder: &mut FunctionBuilder| {
+        let zero = builder.f32_const(-0.0);
+        let nan = builder.f32_const(f32::from_bits(F32_CANONICAL));
+        let sum = builder.binop(BinaryOp::F32Add, zero, nan);
+        let zero = builder.f64_const(-0.0);
+        vec![builder.drop(sum), builder.drop(zero)]
+    };
+
+    let mut module = module(zeros);
+    assert_eq!(canonicalize_nans(&mut module, NanMode::Aggressive), 0);
+    let expected = body(&[
+        &f32_const(0x8000_0000),
+        &f32_const(F32_CANONICAL),
+        &[0x92, 0x1a], // f32.add, drop
+        &f64_const(0x8000_0000_0000_0000),
+        &[0x1a], // drop
+    ]);
+    assert_eq!(emit(&module), expected);
+}
+
+#[test]
+fn globals_are_only_canonicalized_when_aggressive() {
+    let mut module = module(|_| vec![]);
+    let nan = InitExpr::Value(Value::F32(f32::from_bits(F32_NAN)));
+    let global = module.globals.add_local(ValType::F32, false, nan);
+    module.exports.add("g", global);
+
+    // One immutable `f32` global with the given initializer.
+    let globals = |bits| {
+        let mut section = vec![0x01, 0x7d, 0x00];
+        section.extend(f32_const(bits));
+        section.push(0x0b);
+        section
+    };
+
+    assert_eq!(canonicalize_nans(&mut module, NanMode::Conservative), 0);
+    assert_eq!(
+        section(&module.emit_wasm().unwrap(), 6),
+        &globals(F32_NAN)[..]
+    );
+    assert_eq!(canonicalize_nans(&mut module, NanMode::Aggressive), 1);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(section(&wasm, 6), &globals(F32_CANONICAL)[..]);
+    let parsed = config().parse(&wasm).unwrap();
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn vector_lanes_follow_the_shape_of_their_use() {
+    // Lanes, from lowest: a NaN, one, an integer and another NaN.
+    let lanes = [F32_NAN, 1f32.to_bits(), 7, 0xff80_0002];
+    let bits = lanes
+        .iter()
+        .enumerate()
+        .fold(0u128, |v, (i, lane)| v | u128::from(*lane) << (32 * i));
+    let canonical = [F32_CANONICAL, 1f32.to_bits(), 7, 0xffc0_0000]
+        .iter()
+        .enumerate()
+        .fold(0u128, |v, (i, lane)| v | u128::from(*lane) << (32 * i));
+    let vectors = |op| {
+        module(move |builder| {
+            let a = builder.const_(Value::V128(bits));
+            let b = builder.const_(Value::V128(0));
+            let sum = builder.binop(op, a, b);
+            vec![builder.drop(sum)]
+        })
+    };
+
+    // The operands are followed by the addition and a drop.
+    let f32x4_add: &[u8] = &[0xfd, 0xe4, 0x01, 0x1a];
+    let i32x4_add: &[u8] = &[0xfd, 0xae, 0x01, 0x1a];
+
+    for mode in [NanMode::Conservative, NanMode::Aggressive].iter() {
+        let mut module = vectors(BinaryOp::F32x4Add);
+        assert_eq!(canonicalize_nans(&mut module, *mode), 1);
+        assert_eq!(
+            emit(&module),
+            body(&[&v128_const(canonical), &v128_const(0), f32x4_add])
+        );
+
+        // Integer lanes are never touched.
+        let mut module = vectors(BinaryOp::I32x4Add);
+        assert_eq!(canonicalize_nans(&mut module, *mode), 0);
+        assert_eq!(
+            emit(&module),
+            body(&[&v128_const(bits), &v128_const(0), i32x4_add])
+        );
+    }
+}
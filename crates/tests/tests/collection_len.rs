@@ -0,0 +1,47 @@
+//! Tests for the sizes of a module's collections.
+
+use walrus::ir::Value;
+use walrus::{FunctionBuilder, InitExpr, Module, ValType};
+
+#[test]
+fn collections_count_their_items() {
+    let mut module = Module::default();
+    assert!(module.funcs.is_empty());
+    assert!(module.types.is_empty());
+    assert!(module.locals.is_empty());
+
+    module.funcs.reserve(10);
+    module.locals.reserve(10);
+    let ty = module.types.add(&[ValType::I32], &[]);
+    module.add_import_func("env", "f", ty);
+    for _ in 0..10 {
+        let arg = module.locals.add(ValType::I32);
+        FunctionBuilder::new().finish(ty, vec![arg], vec![], &mut module);
+    }
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    module.exports.add("g", global);
+
+    assert_eq!(module.funcs.len(), 11);
+    assert_eq!(module.types.len(), 1);
+    assert_eq!(module.locals.len(), 10);
+    assert_eq!(module.imports.len(), 1);
+    assert_eq!(module.globals.len(), 1);
+    assert_eq!(module.exports.len(), 1);
+    assert!(module.memories.is_empty());
+    assert!(module.tables.is_empty());
+    assert!(module.data.is_empty());
+    assert!(module.elements.is_empty());
+
+    // Reserving space once there are items is allowed, but does nothing.
+    module.funcs.reserve(100);
+    let id = module.funcs.iter_local().next().unwrap().0;
+    module.funcs.delete(id);
+    assert_eq!(module.funcs.len(), 10);
+
+    let wasm = module.emit_wasm().unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.funcs.len(), 10);
+    assert_eq!(module.globals.len(), 1);
+}
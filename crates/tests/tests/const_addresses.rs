@@ -0,0 +1,140 @@
+//! Tests for finding and rebasing constant memory addresses.
+
+use walrus::ir::*;
+use walrus::passes::{const_addresses, rebase_const_addresses};
+use walrus::passes::{ConstAddrKind, RebaseOptions};
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, Module, ValType};
+
+/// A module with the strings "hello" at 1024 and "world" at 2048 in memory,
+/// and a function
+///
+/// ```wat
+/// (func
+///   (drop (i32.load8_u offset=1 (i32.const 1024)))
+///   (call $puts (i32.const 2048))
+///   (call $puts (i32.const 7))
+///   (i32.store (i32.const 4096) (i32.const 1024))
+///   (drop (i32.const 1024)))
+/// ```
+fn fixture() -> (Module, FunctionId, Vec<ExprId>) {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(1024, b"hello\0".to_vec());
+    data.add_absolute(2048, b"world\0".to_vec());
+
+    let puts_ty = module.types.add(&[ValType::I32], &[]);
+    let puts = module.add_import_func("env", "puts", puts_ty);
+    let ty = module.types.add(&[], &[]);
+
+    let mut builder = FunctionBuilder::new();
+    let mut consts = Vec::new();
+    let mut exprs = Vec::new();
+    let mut i32_const = |builder: &mut FunctionBuilder, n| {
+        let expr = builder.i32_const(n);
+        consts.push(expr);
+        expr
+    };
+
+    let address = i32_const(&mut builder, 1024);
+    let kind = LoadKind::I32_8 {
+        kind: ExtendedLoad::ZeroExtend,
+    };
+    let arg = MemArg {
+        align: 1,
+        offset: 1,
+    };
+    let load = builder.load(memory, kind, arg, address);
+    exprs.push(builder.drop(load));
+
+    for n in &[2048, 7] {
+        let arg = i32_const(&mut builder, *n);
+        exprs.push(builder.call(puts, Box::new([arg])));
+    }
+
+    let address = i32_const(&mut builder, 4096);
+    let value = i32_const(&mut builder, 1024);
+    let kind = StoreKind::I32 { atomic: false };
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    exprs.push(builder.store(memory, kind, arg, address, value));
+
+    let value = i32_const(&mut builder, 1024);
+    exprs.push(builder.drop(value));
+
+    let func = builder.finish(ty, vec![], exprs, &mut module);
+    (module, func, consts)
+}
+
+fn value(module: &Module, func: FunctionId, expr: ExprId) -> i32 {
+    let func = match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    match func.get(expr) {
+        Expr::Const(Const {
+            value: Value::I32(n),
+        }) => *n,
+        _ => panic!("not an i32 constant"),
+    }
+}
+
+#[test]
+fn finds_addresses() {
+    let (module, func, consts) = fixture();
+    let mut uses = const_addresses(&module)
+        .into_iter()
+        .map(|u| {
+            assert_eq!(u.func, func);
+            (u.expr, u.address, u.kind)
+        })
+        .collect::<Vec<_>>();
+    uses.sort_by_key(|u| u.1);
+    assert_eq!(
+        uses,
+        [
+            (consts[4], 1024, ConstAddrKind::StoredValue),
+            (consts[0], 1025, ConstAddrKind::MemoryOperand),
+            (consts[1], 2048, ConstAddrKind::CallArgument { index: 0 }),
+            (consts[3], 4096, ConstAddrKind::MemoryOperand),
+        ]
+    );
+}
+
+#[test]
+fn rebases_memory_operands() {
+    let (mut module, func, consts) = fixture();
+    let options = RebaseOptions::default();
+    let n = rebase_const_addresses(&mut module, 1024..1030, 16, &options).unwrap();
+    assert_eq!(n, 1);
+    assert_eq!(value(&module, func, consts[0]), 1040);
+    assert_eq!(value(&module, func, consts[4]), 1024);
+    assert_eq!(value(&module, func, consts[5]), 1024);
+}
+
+#[test]
+fn rebases_heuristic_uses_when_asked() {
+    let (mut module, func, consts) = fixture();
+    let options = RebaseOptions {
+        call_arguments: true,
+        stored_values: true,
+    };
+    let n = rebase_const_addresses(&mut module, 1024..4096, -1024, &options).unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(value(&module, func, consts[0]), 0);
+    assert_eq!(value(&module, func, consts[1]), 1024);
+    assert_eq!(value(&module, func, consts[2]), 7);
+    assert_eq!(value(&module, func, consts[3]), 4096);
+    assert_eq!(value(&module, func, consts[4]), 0);
+    assert_eq!(value(&module, func, consts[5]), 1024);
+}
+
+#[test]
+fn rebasing_out_of_range_fails() {
+    let (mut module, func, consts) = fixture();
+    let options = RebaseOptions::default();
+    assert!(rebase_const_addresses(&mut module, 0..2000, -2000, &options).is_err());
+    assert_eq!(value(&module, func, consts[0]), 1024);
+}
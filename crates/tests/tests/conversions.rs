@@ -0,0 +1,96 @@
+//! Tests for converting between walrus and `wasmparser` types.
+
+use std::convert::TryFrom;
+use walrus::{Module, TableKind, UnsupportedType, ValType};
+
+/// Every `ValType`, listed by a `match` so that adding a variant without
+/// adding it here fails to compile.
+fn all_val_types() -> Vec<ValType> {
+    let all = vec![
+        ValType::I32,
+        ValType::I64,
+        ValType::F32,
+        ValType::F64,
+        ValType::V128,
+        ValType::Externref,
+        ValType::Funcref,
+    ];
+    for ty in &all {
+        match ty {
+            ValType::I32
+            | ValType::I64
+            | ValType::F32
+            | ValType::F64
+            | ValType::V128
+            | ValType::Externref
+            | ValType::Funcref => {}
+            // `wasmparser` has no typed references, so they can't round trip.
+            ValType::Ref { .. } => {}
+        }
+    }
+    all
+}
+
+#[test]
+fn val_types_round_trip() {
+    for ty in all_val_types() {
+        let parser_ty = wasmparser::Type::from(ty);
+        assert_eq!(ValType::try_from(parser_ty), Ok(ty));
+    }
+}
+
+#[test]
+fn unrepresentable_val_types() {
+    for ty in &[wasmparser::Type::Func, wasmparser::Type::EmptyBlockType] {
+        assert_eq!(
+            ValType::try_from(*ty),
+            Err(UnsupportedType {
+                ty: *ty,
+                expected: "value type",
+            })
+        );
+    }
+}
+
+#[test]
+fn table_element_types() {
+    match TableKind::try_from(wasmparser::Type::AnyFunc) {
+        Ok(TableKind::Function(t)) => assert!(t.elements.is_empty()),
+        other => panic!("unexpected table kind: {:?}", other),
+    }
+    match TableKind::try_from(wasmparser::Type::AnyRef) {
+        Ok(TableKind::Externref(_)) => {}
+        other => panic!("unexpected table kind: {:?}", other),
+    }
+    assert_eq!(
+        TableKind::try_from(wasmparser::Type::I32).unwrap_err(),
+        UnsupportedType {
+            ty: wasmparser::Type::I32,
+            expected: "table element type",
+        }
+    );
+}
+
+#[test]
+fn memories_tables_and_globals() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(true, 1, Some(2));
+    let memory = wasmparser::MemoryType::from(module.memories.get(memory));
+    assert!(memory.shared);
+    assert_eq!(memory.limits.initial, 1);
+    assert_eq!(memory.limits.maximum, Some(2));
+
+    let kind = TableKind::try_from(wasmparser::Type::AnyRef).unwrap();
+    let table = module.tables.add_local(3, None, kind);
+    let table = wasmparser::TableType::from(module.tables.get(table));
+    assert_eq!(table.element_type, wasmparser::Type::AnyRef);
+    assert_eq!(table.limits.initial, 3);
+    assert_eq!(table.limits.maximum, None);
+
+    for ty in all_val_types() {
+        let global = module.add_import_global("env", "g", ty, true);
+        let global = wasmparser::GlobalType::from(module.globals.get(global));
+        assert_eq!(ValType::try_from(global.content_type), Ok(ty));
+        assert!(global.mutable);
+    }
+}
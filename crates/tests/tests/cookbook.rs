@@ -0,0 +1,283 @@
+//! Recipes for common transformations, written against the public API only.
+//!
+//! Each recipe is a function that transforms a module, and each test applies
+//! one to a small module, emits it, and checks the structure of the module
+//! parsed back from the emitted bytes. They're meant to be copied from, and
+//! keeping them compiling catches changes to the API which would break code
+//! like them.
+
+use walrus::ir::*;
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, GlobalId, ImportKind};
+use walrus::{InitExpr, LocalFunction, MemoryId, Module, ValType};
+
+/// Add an exported function called `name` which calls the imported function
+/// `import_module`.`import_name` with its arguments, and returns what that
+/// returns.
+fn export_wrapper_of_import(
+    module: &mut Module,
+    import_module: &str,
+    import_name: &str,
+    name: &str,
+) -> Option<FunctionId> {
+    let import = module.imports.find(import_module, import_name)?;
+    let imported = match module.imports.get(import).kind {
+        ImportKind::Function(id) => id,
+        _ => return None,
+    };
+    let ty = module.funcs.get(imported).ty();
+
+    let params = module.types.get(ty).params().to_vec();
+    let args = params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let mut builder = FunctionBuilder::new();
+    let operands = args
+        .iter()
+        .map(|arg| builder.local_get(*arg))
+        .collect::<Vec<_>>();
+    let call = builder.call(imported, operands.into_boxed_slice());
+    let wrapper = builder.finish(ty, args, vec![call], module);
+
+    module.funcs.get_mut(wrapper).name = Some(name.to_string());
+    module.exports.add(name, wrapper);
+    Some(wrapper)
+}
+
+/// Make every exported function call `hook` before it does anything else.
+fn call_hook_in_exports(module: &mut Module, hook: FunctionId) {
+    let exported = module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            ExportItem::Function(id) => Some(id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for id in exported {
+        if let Some(func) = local_mut(module, id) {
+            let call = func.builder_mut().call(hook, Box::new([]));
+            let entry = func.entry_block();
+            func.block_mut(entry).exprs.insert(0, call);
+        }
+    }
+}
+
+/// Move every data segment in `memory` at the offset `from` to the offset
+/// `to`, returning how many were moved.
+fn move_data(module: &mut Module, memory: MemoryId, from: i32, to: i32) -> usize {
+    let data = &mut module.memories.get_mut(memory).data;
+    let mut moved = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let offset = data.iter().nth(index).map(|(offset, _)| offset);
+        match offset {
+            Some(InitExpr::Value(Value::I32(offset))) if offset == from => {
+                moved.push(data.remove(index).1);
+            }
+            _ => index += 1,
+        }
+    }
+    let count = moved.len();
+    for bytes in moved {
+        data.add_absolute(to as u32, bytes);
+    }
+    count
+}
+
+/// Add an exported, mutable global called `name` which counts the calls to
+/// every local function.
+fn add_call_counter(module: &mut Module, name: &str) -> GlobalId {
+    let counter = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    module.exports.add(name, counter);
+
+    let ids = module
+        .funcs
+        .iter_local()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    for id in ids {
+        let func = local_mut(module, id).unwrap();
+        let builder = func.builder_mut();
+        let count = builder.global_get(counter);
+        let one = builder.i32_const(1);
+        let sum = builder.binop(BinaryOp::I32Add, count, one);
+        let increment = builder.global_set(counter, sum);
+        let entry = func.entry_block();
+        func.block_mut(entry).exprs.insert(0, increment);
+    }
+    counter
+}
+
+fn local_mut(module: &mut Module, id: FunctionId) -> Option<&mut LocalFunction> {
+    match &mut module.funcs.get_mut(id).kind {
+        FunctionKind::Local(func) => Some(func),
+        _ => None,
+    }
+}
+
+fn local(module: &Module, id: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(id).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("not a local function"),
+    }
+}
+
+/// A module importing `env`.`log` and `env`.`hook`, with an exported function
+/// `run` and an unexported one, and a memory with data at offsets 8 and 16.
+fn module() -> Module {
+    let mut module = Module::default();
+    let log_ty = module.types.add(&[ValType::I32], &[]);
+    module.add_import_func("env", "log", log_ty);
+    let unit = module.types.add(&[], &[]);
+    module.add_import_func("env", "hook", unit);
+
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(8, b"eight".to_vec());
+    data.add_absolute(16, b"sixteen".to_vec());
+
+    for name in ["run", "helper"].iter() {
+        let mut builder = FunctionBuilder::new();
+        let nop = builder.i32_const(0);
+        let nop = builder.drop(nop);
+        let func = builder.finish(unit, vec![], vec![nop], &mut module);
+        module.funcs.get_mut(func).name = Some(name.to_string());
+        if *name == "run" {
+            module.exports.add("run", func);
+        }
+    }
+    module
+}
+
+fn round_trip(module: &Module) -> Module {
+    Module::from_buffer(&module.emit_wasm().unwrap()).unwrap()
+}
+
+fn exported_func(module: &Module, name: &str) -> FunctionId {
+    module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            ExportItem::Function(id) if &*e.name == name => Some(id),
+            _ => None,
+        })
+        .unwrap()
+}
+
+fn import_named(module: &Module, name: &str) -> FunctionId {
+    match module
+        .imports
+        .get(module.imports.find("env", name).unwrap())
+        .kind
+    {
+        ImportKind::Function(id) => id,
+        _ => panic!("not a function import"),
+    }
+}
+
+/// The body of a function, as the expressions of its entry block.
+fn body(func: &LocalFunction) -> Vec<&Expr> {
+    func.block(func.entry_block())
+        .exprs
+        .iter()
+        .map(|e| func.get(*e))
+        .collect()
+}
+
+#[test]
+fn wrap_an_import() {
+    let mut module = module();
+    export_wrapper_of_import(&mut module, "env", "log", "log_wrapper").unwrap();
+
+    let module = round_trip(&module);
+    let wrapper = exported_func(&module, "log_wrapper");
+    let log = import_named(&module, "log");
+    assert_eq!(module.funcs.get(wrapper).ty(), module.funcs.get(log).ty());
+
+    let func = local(&module, wrapper);
+    match &body(func)[..] {
+        [Expr::Call(call)] => {
+            assert_eq!(call.func, log);
+            match func.get(call.args[0]) {
+                Expr::LocalGet(get) => assert_eq!(get.local, func.args[0]),
+                e => panic!("expected a local.get, found {:?}", e),
+            }
+        }
+        exprs => panic!("expected a call, found {:?}", exprs),
+    }
+}
+
+#[test]
+fn call_a_hook_in_exports() {
+    let mut module = module();
+    let hook = import_named(&module, "hook");
+    call_hook_in_exports(&mut module, hook);
+
+    let module = round_trip(&module);
+    let hook = import_named(&module, "hook");
+    let run = local(&module, exported_func(&module, "run"));
+    match &body(run)[..] {
+        [Expr::Call(call), Expr::Drop(_)] => assert_eq!(call.func, hook),
+        exprs => panic!("expected a call to the hook, found {:?}", exprs),
+    }
+    let helper = local(&module, module.funcs.by_name("helper").unwrap());
+    assert_eq!(body(helper).len(), 1);
+}
+
+#[test]
+fn move_a_data_blob() {
+    let mut module = module();
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(move_data(&mut module, memory, 16, 1024), 1);
+
+    let module = round_trip(&module);
+    let memory = module.memories.iter().next().unwrap();
+    let segments = memory
+        .data
+        .iter()
+        .map(|(offset, bytes)| match offset {
+            InitExpr::Value(Value::I32(offset)) => (offset, bytes.to_vec()),
+            _ => panic!("expected a constant offset"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        segments,
+        [(8, b"eight".to_vec()), (1024, b"sixteen".to_vec())]
+    );
+}
+
+#[test]
+fn count_calls_in_a_global() {
+    let mut module = module();
+    add_call_counter(&mut module, "calls");
+
+    let module = round_trip(&module);
+    let counter = module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            ExportItem::Global(id) if &*e.name == "calls" => Some(id),
+            _ => None,
+        })
+        .unwrap();
+    assert!(module.globals.get(counter).mutable);
+
+    for (_, func) in module.funcs.iter_local() {
+        match &body(func)[..] {
+            [Expr::GlobalSet(set), Expr::Drop(_)] => {
+                assert_eq!(set.global, counter);
+                match func.get(set.value) {
+                    Expr::Binop(Binop {
+                        op: BinaryOp::I32Add,
+                        ..
+                    }) => {}
+                    e => panic!("expected an add, found {:?}", e),
+                }
+            }
+            exprs => panic!("expected an increment, found {:?}", exprs),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Tests for memories with custom page sizes.
+
+use walrus::passes::{check_data_overlap, validate, Overlap};
+use walrus::{Module, ModuleConfig};
+use walrus_tests_utils::section;
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+fn module() -> Module {
+    Module::with_config(config())
+}
+
+/// Parse `wasm` back, check that it emits the same bytes again, and return
+/// the page size of its only memory.
+fn parse_page_size(wasm: &[u8]) -> Option<u32> {
+    let module = config().parse(wasm).unwrap();
+    validate::run(&module).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+    let memory = module.memories.iter().next().unwrap();
+    memory.page_size_log2
+}
+
+#[test]
+fn page_size_is_emitted() {
+    let mut module = module();
+    let memory = module.memories.add_local(false, 100, Some(200));
+    module.memories.get_mut(memory).page_size_log2 = Some(0);
+    module.exports.add("memory", memory);
+    validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm().unwrap();
+    // One memory with a maximum and a page size of 2^0 bytes.
+    assert_eq!(section(&wasm, 5), &[0x01, 0x09, 0x64, 0xc8, 0x01, 0x00][..]);
+    assert_eq!(parse_page_size(&wasm), Some(0));
+}
+
+#[test]
+fn default_page_size_is_unchanged() {
+    let mut module = module();
+    module.memories.add_local(false, 1, None);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(section(&wasm, 5), &[0x01, 0x00, 0x01][..]);
+    assert_eq!(parse_page_size(&wasm), None);
+}
+
+#[test]
+fn imported_page_size_is_emitted() {
+    let mut module = module();
+    let memory = module.add_import_memory("env", "memory", false, 1, None);
+    module.memories.get_mut(memory).page_size_log2 = Some(16);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        section(&wasm, 2),
+        &[
+            0x01, // one import
+            0x03, b'e', b'n', b'v', 0x06, b'm', b'e', b'm', b'o', b'r', b'y', //
+            0x02, 0x08, 0x01, 0x10, // a memory of one page of 2^16 bytes
+        ][..]
+    );
+    assert_eq!(parse_page_size(&wasm), Some(16));
+
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    let (_, range) = &layout.imports[0];
+    assert_eq!(&wasm[range.clone()], &section(&wasm, 2)[1..]);
+}
+
+#[test]
+fn page_sizes_of_tables_are_rejected() {
+    // (import "env" "t" (table 1 funcref)), with and without a page size.
+    let import = |limits: &[u8]| {
+        let mut payload = vec![0x01, 0x03, b'e', b'n', b'v', 0x01, b't', 0x01, 0x70];
+        payload.extend_from_slice(limits);
+        walrus_tests_utils::module(&[(2, &payload)])
+    };
+    config().parse(&import(&[0x00, 0x01])).unwrap();
+    let err = config().parse(&import(&[0x08, 0x01, 0x10])).unwrap_err();
+    let msg = format!("{}", err.find_root_cause());
+    assert!(msg.contains("custom page size"), "{}", msg);
+}
+
+#[test]
+fn only_1_and_65536_byte_pages_are_valid() {
+    for &(page_size_log2, valid) in [(0, true), (16, true), (12, false)].iter() {
+        let mut module = module();
+        let memory = module.memories.add_local(false, 1, None);
+        module.memories.get_mut(memory).page_size_log2 = Some(page_size_log2);
+        assert_eq!(validate::run(&module).is_ok(), valid);
+    }
+
+    // 1-byte pages allow far more pages than 64KiB ones.
+    let mut module = module();
+    let memory = module.memories.add_local(false, 1 << 20, None);
+    assert!(validate::run(&module).is_err());
+    module.memories.get_mut(memory).page_size_log2 = Some(0);
+    validate::run(&module).unwrap();
+}
+
+#[test]
+fn sizes_are_measured_in_custom_pages() {
+    let mut module = module();
+    let memory = module.memories.add_local(false, 16, None);
+    let mem = module.memories.get_mut(memory);
+    mem.page_size_log2 = Some(0);
+    assert_eq!(mem.page_size(), 1);
+    mem.data.add_absolute(8, vec![0; 16]);
+
+    match &check_data_overlap(&module)[..] {
+        [Overlap::OutOfBounds {
+            range, memory_size, ..
+        }] => {
+            assert_eq!(*range, 8..24);
+            assert_eq!(*memory_size, 16);
+        }
+        problems => panic!("unexpected problems {:?}", problems),
+    }
+}
@@ -0,0 +1,55 @@
+//! Tests for controlling where custom sections end up in the emitted wasm.
+
+use walrus::{FunctionBuilder, Module, ModuleConfig, Placement, RawCustomSection, Section};
+
+/// List the sections of the given wasm, using the name for custom sections
+/// and the id for everything else.
+fn sections(wasm: &[u8]) -> Vec<String> {
+    walrus_tests_utils::sections(wasm)
+        .into_iter()
+        .map(|(id, payload)| match id {
+            // The name is short enough that its length is a single byte.
+            0 => String::from_utf8(payload[1..][..payload[0] as usize].to_vec()).unwrap(),
+            _ => id.to_string(),
+        })
+        .collect()
+}
+
+fn raw(name: &str) -> RawCustomSection {
+    RawCustomSection {
+        name: name.to_string(),
+        data: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn custom_sections_are_placed() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config.clone());
+
+    let ty = module.types.add(&[], &[]);
+    FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+
+    let end = module.customs.add(raw("end"));
+    let after_code = module.customs.add(raw("after-code"));
+    let start = module.customs.add(raw("start"));
+    let default = module.customs.add(raw("default"));
+    module.customs.set_placement(end, Placement::End);
+    module
+        .customs
+        .set_placement(after_code, Placement::AfterSection(Section::Code));
+    module.customs.set_placement(start, Placement::Start);
+    assert_eq!(module.customs.placement(default), Placement::End);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        sections(&wasm),
+        ["start", "1", "3", "10", "after-code", "end", "default"]
+    );
+
+    // Parsing keeps the placement of the custom sections that came before
+    // known sections, and re-emitting gives back the same module.
+    let module = config.parse(&wasm).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
@@ -22,7 +22,7 @@ impl CustomSection for HelloCustomSection {
         "hello"
     }
 
-    fn data(&self, _: &IdsToIndices) -> Cow<[u8]> {
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
         let data = format!("Hello, {}!", self.0);
         data.into_bytes().into()
     }
@@ -0,0 +1,80 @@
+//! Tests for when the data count section is emitted.
+
+use walrus::{FunctionBuilder, Module, ModuleConfig};
+
+/// The contents of the data count section of `wasm`, if it has one.
+fn data_count(wasm: &[u8]) -> Option<u32> {
+    let mut reader = wasmparser::ModuleReader::new(wasm).unwrap();
+    while !reader.eof() {
+        let section = reader.read().unwrap();
+        if let wasmparser::SectionCode::DataCount = section.code {
+            return Some(section.get_data_count_section_content().unwrap());
+        }
+    }
+    None
+}
+
+fn active_only(config: ModuleConfig) -> Module {
+    let mut module = Module::with_config(config);
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(0, b"hi".to_vec());
+    module
+}
+
+#[test]
+fn only_emitted_when_needed() {
+    let wasm = active_only(ModuleConfig::new()).emit_wasm().unwrap();
+    assert_eq!(data_count(&wasm), None);
+
+    // (func (memory.init $data (i32.const 0) (i32.const 0) (i32.const 2)))
+    let mut module = active_only(ModuleConfig::new());
+    let memory = module.memories.iter().next().unwrap().id();
+    let data = module.data.add_passive(b"yo".to_vec());
+    let ty = module.types.add(&[], &[]);
+    let mut builder = FunctionBuilder::new();
+    let dst = builder.i32_const(0);
+    let src = builder.i32_const(0);
+    let len = builder.i32_const(2);
+    let init = builder.memory_init(memory, data, dst, src, len);
+    let func = builder.finish(ty, vec![], vec![init], &mut module);
+    module.exports.add("init", func);
+    let wasm = module.emit_wasm().unwrap();
+    // One active and one passive segment.
+    assert_eq!(data_count(&wasm), Some(2));
+}
+
+#[test]
+fn can_be_forced_on_or_off() {
+    let mut config = ModuleConfig::new();
+    config.force_data_count(Some(true));
+    let wasm = active_only(config).emit_wasm().unwrap();
+    assert_eq!(data_count(&wasm), Some(1));
+
+    let mut config = ModuleConfig::new();
+    config.force_data_count(Some(false));
+    let mut module = active_only(config);
+    module.data.add_passive(b"yo".to_vec());
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(data_count(&wasm), None);
+}
+
+#[test]
+fn parsed_data_count_round_trips() {
+    let mut config = ModuleConfig::new();
+    config.force_data_count(Some(true));
+    let wasm = active_only(config).emit_wasm().unwrap();
+
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert!(module.data.parsed_data_count());
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(data_count(&wasm), Some(1));
+
+    let mut config = ModuleConfig::new();
+    config.force_data_count(Some(false));
+    let wasm = config.parse(&wasm).unwrap().emit_wasm().unwrap();
+    assert_eq!(data_count(&wasm), None);
+}
@@ -0,0 +1,71 @@
+//! Tests for detecting overlapping active data segments.
+
+use walrus::ir::Value;
+use walrus::passes::{check_data_overlap, ActiveSegment, Overlap};
+use walrus::{InitExpr, Module, ValType};
+
+#[test]
+fn disjoint_segments() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(0, vec![0; 8]);
+    data.add_absolute(8, vec![0; 8]);
+    data.add_absolute(4, vec![]);
+    assert!(check_data_overlap(&module).is_empty());
+}
+
+#[test]
+fn overlapping_segments() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(16, vec![0; 8]);
+    data.add_absolute(0, vec![0; 4]);
+    data.add_absolute(20, vec![0; 8]);
+    data.add_absolute(0, vec![0; 32]);
+
+    let segment = |index| ActiveSegment { memory, index };
+    let overlap = |first, first_range, second, second_range| Overlap::Overlapping {
+        first: segment(first),
+        first_range,
+        second: segment(second),
+        second_range,
+    };
+    assert_eq!(
+        check_data_overlap(&module),
+        [
+            overlap(0, 16..24, 2, 20..28),
+            overlap(0, 16..24, 3, 0..32),
+            overlap(1, 0..4, 3, 0..32),
+            overlap(2, 20..28, 3, 0..32),
+        ]
+    );
+}
+
+#[test]
+fn out_of_bounds_and_unknown_extent() {
+    let mut module = Module::default();
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(65532, vec![0; 8]);
+    data.add_relative(global, vec![0; 8]);
+
+    assert_eq!(
+        check_data_overlap(&module),
+        [
+            Overlap::OutOfBounds {
+                segment: ActiveSegment { memory, index: 0 },
+                range: 65532..65540,
+                memory_size: 65536,
+            },
+            Overlap::UnknownExtent {
+                segment: ActiveSegment { memory, index: 1 },
+                global,
+            },
+        ]
+    );
+}
@@ -0,0 +1,105 @@
+//! Tests for removing stores which are overwritten before they're observed.
+
+use walrus::ir::*;
+use walrus::passes::dead_store_elimination;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, InitExpr, MemoryId, Module, ValType};
+
+/// Build a function out of `i32.store (i32.const 16) (i32.const n)`s, with a
+/// call to an imported function after each store whose `n` is in `calls`.
+fn stores(shared: bool, values: &[i32], calls: &[i32]) -> (Module, FunctionId, Vec<ExprId>) {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(shared, 1, Some(1));
+    let ty = module.types.add(&[], &[]);
+    let f = module.add_import_func("env", "f", ty);
+
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    for n in values {
+        exprs.push(store(&mut builder, memory, *n));
+        if calls.contains(n) {
+            exprs.push(builder.call(f, Box::new([])));
+        }
+    }
+    let func = builder.finish(ty, vec![], exprs.clone(), &mut module);
+    (module, func, exprs)
+}
+
+fn store(builder: &mut FunctionBuilder, memory: MemoryId, n: i32) -> ExprId {
+    store_at(builder, memory, 16, n)
+}
+
+fn store_at(builder: &mut FunctionBuilder, memory: MemoryId, address: i32, n: i32) -> ExprId {
+    let address = builder.i32_const(address);
+    let value = builder.i32_const(n);
+    let kind = StoreKind::I32 { atomic: false };
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    builder.store(memory, kind, arg, address, value)
+}
+
+fn body(module: &Module, func: FunctionId) -> Vec<ExprId> {
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l.block(l.entry_block()).exprs.clone(),
+        _ => panic!("not a local function"),
+    }
+}
+
+#[test]
+fn back_to_back_stores() {
+    let (mut module, func, exprs) = stores(false, &[1, 2, 3], &[]);
+    assert_eq!(dead_store_elimination(&mut module), 2);
+    assert_eq!(body(&module, func), [exprs[2]]);
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn intervening_call() {
+    let (mut module, func, exprs) = stores(false, &[1, 2, 3], &[1]);
+    assert_eq!(dead_store_elimination(&mut module), 1);
+    assert_eq!(body(&module, func), [exprs[0], exprs[1], exprs[3]]);
+}
+
+#[test]
+fn shared_memory() {
+    let (mut module, func, exprs) = stores(true, &[1, 2], &[]);
+    assert_eq!(dead_store_elimination(&mut module), 0);
+    assert_eq!(body(&module, func), exprs);
+}
+
+#[test]
+fn trapping_stores_across_side_effects() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, Some(1));
+    let global = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    let local = module.locals.add(ValType::I32);
+    let ty = module.types.add(&[], &[]);
+
+    // Both stores are past the end of the memory, so the first one traps,
+    // and removing it would set the global before trapping.
+    let mut builder = FunctionBuilder::new();
+    let one = builder.i32_const(1);
+    let exprs = vec![
+        store_at(&mut builder, memory, 65536, 1),
+        builder.global_set(global, one),
+        store_at(&mut builder, memory, 65536, 2),
+    ];
+    let func = builder.finish(ty, vec![], exprs.clone(), &mut module);
+    assert_eq!(dead_store_elimination(&mut module), 0);
+    assert_eq!(body(&module, func), exprs);
+
+    // Setting a local can't be observed once the function has trapped.
+    let mut builder = FunctionBuilder::new();
+    let one = builder.i32_const(1);
+    let exprs = vec![
+        store_at(&mut builder, memory, 65536, 1),
+        builder.local_set(local, one),
+        store_at(&mut builder, memory, 65536, 2),
+    ];
+    let func = builder.finish(ty, vec![], exprs.clone(), &mut module);
+    assert_eq!(dead_store_elimination(&mut module), 1);
+    assert_eq!(body(&module, func), [exprs[1], exprs[2]]);
+}
@@ -0,0 +1,44 @@
+//! Tests for preserving the original locals declarations of functions.
+
+use walrus::{Module, ModuleConfig};
+
+/// A module with two `(func)`s whose bodies were replaced with `unreachable`,
+/// but which still declare their original locals.
+fn snipped() -> Vec<u8> {
+    walrus_tests_utils::module(&[
+        (0x01, &[0x01, 0x60, 0x00, 0x00]),
+        (0x03, &[0x02, 0x00, 0x00]),
+        (
+            0x0a,
+            &[
+                0x02, // two bodies
+                0x07, 0x02, 0x02, 0x7f, 0x01, 0x7c, 0x00, 0x0b, // (local i32 i32 f64)
+                0x05, 0x01, 0x01, 0x7e, 0x00, 0x0b, // (local i64)
+            ],
+        ),
+    ])
+}
+
+fn round_trip(preserve: bool) -> Vec<u8> {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false)
+        .preserve_declared_locals(preserve);
+    let module = config.parse(&snipped()).unwrap();
+    module.emit_wasm().unwrap()
+}
+
+#[test]
+fn preserved() {
+    assert_eq!(round_trip(true), snipped());
+}
+
+#[test]
+fn dropped_by_default() {
+    let wasm = round_trip(false);
+    let bodies = walrus_tests_utils::function_bodies(&wasm);
+    assert_eq!(bodies, [&[0x00, 0x00, 0x0b][..], &[0x00, 0x00, 0x0b][..]]);
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.locals.iter().count(), 0);
+}
@@ -0,0 +1,146 @@
+//! Tests for merging functions with identical bodies.
+
+use walrus::ir::*;
+use walrus::passes::{dedup_functions, dedup_functions_with, DedupOptions};
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, FunctionTable, InitExpr};
+use walrus::{LocalFunction, Module, TableKind, TypeId, ValType};
+
+/// A function adding `n` to its argument.
+fn add(module: &mut Module, ty: TypeId, n: i32) -> FunctionId {
+    let arg = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let get = builder.local_get(arg);
+    let n = builder.i32_const(n);
+    let sum = builder.binop(BinaryOp::I32Add, get, n);
+    builder.finish(ty, vec![arg], vec![sum], module)
+}
+
+/// A function calling each of `callees` with 0, and adding up the results.
+fn caller(module: &mut Module, ty: TypeId, callees: &[FunctionId]) -> FunctionId {
+    let arg = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let mut sum = builder.local_get(arg);
+    for callee in callees {
+        let zero = builder.i32_const(0);
+        let call = builder.call(*callee, Box::new([zero]));
+        sum = builder.binop(BinaryOp::I32Add, sum, call);
+    }
+    builder.finish(ty, vec![arg], vec![sum], module)
+}
+
+/// The functions `func` calls, in order.
+fn callees(module: &Module, func: FunctionId) -> Vec<FunctionId> {
+    struct Calls<'a> {
+        func: &'a LocalFunction,
+        calls: Vec<FunctionId>,
+    }
+
+    impl<'a> Visitor<'a> for Calls<'a> {
+        fn local_function(&self) -> &'a LocalFunction {
+            self.func
+        }
+
+        fn visit_call(&mut self, e: &Call) {
+            self.calls.push(e.func);
+            e.visit(self);
+        }
+    }
+
+    let func = match &module.funcs.get(func).kind {
+        FunctionKind::Local(f) => f,
+        _ => panic!("not a local function"),
+    };
+    let mut calls = Calls {
+        func,
+        calls: Vec::new(),
+    };
+    dfs_in_order(&mut calls, func, func.entry_block().into());
+    calls.calls
+}
+
+#[test]
+fn identical_functions_are_merged() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let a = add(&mut module, ty, 1);
+    let b = add(&mut module, ty, 1);
+    let c = add(&mut module, ty, 2);
+    module.funcs.get_mut(b).name = Some("b".to_string());
+    let main = caller(&mut module, ty, &[a, b, c]);
+    module.exports.add("main", main);
+    module.exports.add("b", b);
+
+    let stats = dedup_functions(&mut module);
+    assert_eq!(stats.removed, 1);
+    assert!(stats.bytes_saved > 0);
+    assert_eq!(module.funcs.iter_local().count(), 3);
+    assert_eq!(callees(&module, main), [a, a, c]);
+    let export = module.exports.find("b").unwrap();
+    assert_eq!(module.exports.get(export).item, ExportItem::Function(a));
+
+    assert_eq!(dedup_functions(&mut module), Default::default());
+    assert!(wasmparser::validate(&module.emit_wasm().unwrap(), None));
+}
+
+#[test]
+fn callers_of_merged_functions_are_merged_next_time() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let a = add(&mut module, ty, 1);
+    let b = add(&mut module, ty, 1);
+    let calls_a = caller(&mut module, ty, &[a]);
+    let calls_b = caller(&mut module, ty, &[b]);
+    module.exports.add("a", calls_a);
+    module.exports.add("b", calls_b);
+
+    assert_eq!(dedup_functions(&mut module).removed, 1);
+    assert_eq!(dedup_functions(&mut module).removed, 1);
+    assert_eq!(dedup_functions(&mut module).removed, 0);
+    for export in module.exports.iter() {
+        assert_eq!(export.item, ExportItem::Function(calls_a));
+    }
+}
+
+#[test]
+fn local_types_matter() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let mut funcs = Vec::new();
+    for local_ty in [ValType::I32, ValType::I64, ValType::I32].iter() {
+        // The same body, with a fresh local each time.
+        let local = module.locals.add(*local_ty);
+        let mut builder = FunctionBuilder::new();
+        let get = builder.local_get(local);
+        let drop = builder.drop(get);
+        funcs.push(builder.finish(ty, vec![], vec![drop], &mut module));
+    }
+
+    assert_eq!(dedup_functions(&mut module).removed, 1);
+    assert!(module.funcs.iter().any(|f| f.id() == funcs[0]));
+    assert!(module.funcs.iter().any(|f| f.id() == funcs[1]));
+    assert!(!module.funcs.iter().any(|f| f.id() == funcs[2]));
+}
+
+#[test]
+fn functions_in_tables_are_only_merged_when_allowed() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let a = add(&mut module, ty, 1);
+    let b = add(&mut module, ty, 1);
+    let table = module
+        .tables
+        .add_local(2, None, TableKind::Function(FunctionTable::default()));
+    let offset = InitExpr::Value(Value::I32(0));
+    module.elements.add_active(table, offset, &[a, b]);
+    module.exports.add("table", table);
+
+    assert_eq!(dedup_functions(&mut module).removed, 0);
+
+    let options = DedupOptions {
+        merge_address_taken: true,
+    };
+    assert_eq!(dedup_functions_with(&mut module, &options).removed, 1);
+    let segments = module.element_segments();
+    assert_eq!(segments[0].members(), [Some(a), Some(a)]);
+    assert!(wasmparser::validate(&module.emit_wasm().unwrap(), None));
+}
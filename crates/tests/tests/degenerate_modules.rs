@@ -0,0 +1,143 @@
+//! Tests for modules with nothing, or next to nothing, in them.
+
+use walrus::dot::Dot;
+use walrus::{ExportItem, FunctionBuilder, ImportKind, Module, ModuleConfig, ValType};
+
+fn validate(wasm: &[u8]) {
+    assert!(wasmparser::validate(wasm, None), "emitted invalid wasm");
+}
+
+/// Check that `module` emits valid wasm with a layout covering all of it, and
+/// that it survives validation, GC and being parsed again.
+fn check(mut module: Module) {
+    walrus::passes::validate::run(&module).unwrap();
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    validate(&wasm);
+    let sections = layout.sections.iter().map(|s| s.range.len()).sum::<usize>();
+    assert_eq!(8 + sections, wasm.len());
+    for func in module.funcs.iter() {
+        let mut dot = String::new();
+        func.dot(&mut dot);
+        assert!(dot.starts_with("digraph {") && dot.trim_end().ends_with('}'));
+    }
+
+    let mut reparsed = ModuleConfig::new()
+        .generate_producers_section(false)
+        .parse(&wasm)
+        .unwrap();
+    walrus::passes::gc::run(&mut reparsed);
+    validate(&reparsed.emit_wasm().unwrap());
+
+    walrus::passes::gc::run(&mut module);
+    walrus::passes::validate::run(&module).unwrap();
+    validate(&module.emit_wasm().unwrap());
+}
+
+#[test]
+fn empty() {
+    check(Module::default());
+
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let module = Module::with_config(config);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(wasm, [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.funcs.iter().count(), 0);
+}
+
+#[test]
+fn empty_sections() {
+    let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    let function_section = [0x03, 0x01, 0x00];
+    let code_section = [0x0a, 0x01, 0x00];
+    let data_section = [0x0b, 0x01, 0x00];
+
+    let sections: &[&[&[u8]]] = &[
+        &[&function_section, &code_section],
+        &[&function_section],
+        &[&code_section],
+        &[&data_section],
+    ];
+    for sections in sections {
+        let mut wasm = header.to_vec();
+        for section in sections.iter() {
+            wasm.extend_from_slice(section);
+        }
+        let module = Module::from_buffer(&wasm).unwrap();
+        assert_eq!(module.funcs.iter().count(), 0);
+        check(module);
+    }
+
+    // A function section declaring functions still needs their bodies.
+    let mut wasm = header.to_vec();
+    wasm.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+    wasm.extend_from_slice(&[0x03, 0x02, 0x01, 0x00]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
+
+#[test]
+fn imports_only() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let f = module.add_import_func("env", "f", ty);
+    module.funcs.get_mut(f).name = Some("f".to_string());
+    module.add_import_global("env", "g", ValType::I64, false);
+    module.add_import_memory("env", "memory", false, 1, None);
+    check(module);
+}
+
+#[test]
+fn data_only() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(8, b"data".to_vec());
+    module.exports.add("memory", ExportItem::Memory(memory));
+    check(module);
+
+    // A memory with no data, which GC deletes since it isn't exported.
+    let mut module = Module::default();
+    module.memories.add_local(false, 1, Some(2));
+    check(module);
+}
+
+#[test]
+fn exports_of_imports_only() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::F32]);
+    let f = module.add_import_func("env", "f", ty);
+    let g = module.add_import_global("env", "g", ValType::I32, false);
+    let memory = module.add_import_memory("env", "memory", false, 1, None);
+    module.exports.add("f", f);
+    module.exports.add("g", g);
+    module.exports.add("memory", memory);
+    check(module);
+}
+
+#[test]
+fn start_function_only() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let start = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.start = Some(start);
+    check(module);
+}
+
+#[test]
+fn named_function_whose_import_was_deleted() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let f = module.add_import_func("env", "f", ty);
+    module.funcs.get_mut(f).name = Some("f".to_string());
+    let import = module.imports.find("env", "f").unwrap();
+    match module.imports.get(import).kind {
+        ImportKind::Function(id) => assert_eq!(id, f),
+        _ => panic!("expected a function import"),
+    }
+    module.imports.delete(import);
+    check(module);
+}
@@ -0,0 +1,116 @@
+//! Tests for deleting a function along with its uses.
+
+use walrus::ir::*;
+use walrus::{ElementKind, FunctionBuilder, FunctionId, FunctionInUse, FunctionKind};
+use walrus::{FunctionTable, FunctionUse, Module, TableKind, ValType};
+
+/// A module with a function `target` which is called twice by `caller`, is
+/// in a function table and a passive segment, and is exported and the start
+/// function.
+fn fixture() -> (Module, FunctionId, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+
+    let target = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+
+    let mut builder = FunctionBuilder::new();
+    let first = builder.call(target, Box::new([]));
+    let second = builder.call(target, Box::new([]));
+    let caller = builder.finish(ty, vec![], vec![first, second], &mut module);
+
+    let table = FunctionTable {
+        elements: vec![Some(caller), Some(target)],
+        ..FunctionTable::default()
+    };
+    module.tables.add_local(2, None, TableKind::Function(table));
+    module.elements.add_passive(&[target, caller]);
+    module.exports.add("target", target);
+    module.exports.add("caller", caller);
+    module.start = Some(target);
+    (module, target, caller)
+}
+
+#[test]
+fn uses_are_listed() {
+    let (module, target, caller) = fixture();
+    let uses = module.function_uses(target);
+    let calls = uses
+        .iter()
+        .filter(|u| match u {
+            FunctionUse::Call { caller: c, .. } => *c == caller,
+            _ => false,
+        })
+        .count();
+    assert_eq!(calls, 2);
+    assert_eq!(uses.len(), 6);
+    assert!(uses.contains(&FunctionUse::Start));
+    assert_eq!(module.function_uses(caller).len(), 3);
+}
+
+#[test]
+fn delete_if_unused_refuses_used_functions() {
+    let (mut module, target, _) = fixture();
+    let err = module.delete_function_if_unused(target).unwrap_err();
+    let err = err.downcast::<FunctionInUse>().unwrap();
+    assert_eq!(err.function, target);
+    assert_eq!(err.uses, module.function_uses(target));
+    assert!(err.to_string().contains("the start function"));
+    // Nothing was deleted.
+    assert!(module.funcs.iter().any(|f| f.id() == target));
+    assert_eq!(module.exports.iter().count(), 2);
+
+    let ty = module.types.add(&[], &[]);
+    let unused = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.delete_function_if_unused(unused).unwrap();
+    assert!(module.funcs.iter().all(|f| f.id() != unused));
+}
+
+#[test]
+fn cascade_removes_every_use() {
+    let (mut module, target, caller) = fixture();
+    module.delete_function_cascade(target);
+
+    assert!(module.funcs.iter().all(|f| f.id() != target));
+    assert!(module.function_uses(target).is_empty());
+    let func = match &module.funcs.get(caller).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("expected a local function"),
+    };
+    for e in func.block(func.entry_block()).exprs.iter() {
+        match func.get(*e) {
+            Expr::Unreachable(_) => {}
+            e => panic!("expected `unreachable`, found {:?}", e),
+        }
+    }
+    let exports = module
+        .exports
+        .iter()
+        .map(|e| e.name.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(exports, ["caller"]);
+    assert_eq!(module.start, None);
+    match &module.tables.iter().next().unwrap().kind {
+        TableKind::Function(table) => assert_eq!(table.elements, [Some(caller), None]),
+        _ => panic!("expected a function table"),
+    }
+    let segment = module.elements.iter().next().unwrap();
+    match &segment.kind {
+        ElementKind::Passive => {}
+        k => panic!("expected a passive segment, found {:?}", k),
+    }
+    assert_eq!(segment.members, [None, Some(caller)]);
+
+    // Nothing refers to the deleted function, so the module can be emitted.
+    let wasm = module.emit_wasm().unwrap();
+    Module::from_buffer(&wasm).unwrap();
+}
+
+#[test]
+fn cascade_deletes_imports() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let log = module.add_import_func("env", "log", ty);
+    module.delete_function_cascade(log);
+    assert!(module.imports.find("env", "log").is_none());
+    assert_eq!(module.funcs.iter().count(), 0);
+}
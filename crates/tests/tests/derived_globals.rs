@@ -0,0 +1,113 @@
+//! Tests for globals initialized with the value of an imported global.
+
+use walrus::ir::Value;
+use walrus::{ConstValue, GlobalKind, ImportKind, InitExpr, Module, ModuleConfig, ValType};
+
+#[test]
+fn derived_globals_round_trip() {
+    let mut module = Module::default();
+    let base = module.add_import_global("env", "__memory_base", ValType::I32, false);
+    let derived = module
+        .globals
+        .add_derived(ValType::I32, true, base)
+        .unwrap();
+    module.exports.add("derived", derived);
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_relative(base, b"hello".to_vec());
+
+    let wasm = module.emit_wasm().unwrap();
+    let module = ModuleConfig::new()
+        .generate_producers_section(false)
+        .parse(&wasm)
+        .unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+
+    let base = module.imports.iter().find_map(|i| match i.kind {
+        ImportKind::Global(g) => Some(g),
+        _ => None,
+    });
+    let base = base.unwrap();
+    let derived = module.globals.iter().find(|g| g.id() != base).unwrap();
+    assert!(derived.mutable);
+    match derived.kind {
+        GlobalKind::Local(InitExpr::Global(g)) => assert_eq!(g, base),
+        _ => panic!("expected a `global.get` initializer"),
+    }
+    match module.globals.initial_value(derived.id()).unwrap() {
+        ConstValue::Imported(g) => assert_eq!(g, base),
+        v => panic!("expected the imported global, found {:?}", v),
+    }
+
+    let memory = module.memories.iter().next().unwrap();
+    let (offset, data) = memory.data.iter().next().unwrap();
+    assert_eq!(data, b"hello");
+    match module.globals.eval(offset).unwrap() {
+        ConstValue::Imported(g) => assert_eq!(g, base),
+        v => panic!("expected the imported global, found {:?}", v),
+    }
+}
+
+#[test]
+fn sources_must_be_imported_immutable_and_the_same_type() {
+    let mut module = Module::default();
+    let mutable = module.add_import_global("env", "mutable", ValType::I32, true);
+    let immutable = module.add_import_global("env", "immutable", ValType::I32, false);
+    let local = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(1)));
+
+    assert!(module
+        .globals
+        .add_derived(ValType::I32, false, mutable)
+        .is_err());
+    assert!(module
+        .globals
+        .add_derived(ValType::I32, false, local)
+        .is_err());
+    assert!(module
+        .globals
+        .add_derived(ValType::I64, false, immutable)
+        .is_err());
+    assert!(module
+        .globals
+        .add_derived(ValType::I32, false, immutable)
+        .is_ok());
+}
+
+#[test]
+fn hand_built_initializers_are_validated() {
+    let mut module = Module::default();
+    let mutable = module.add_import_global("env", "mutable", ValType::I32, true);
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Global(mutable));
+    module.exports.add("g", global);
+    let wasm = module.emit_wasm().unwrap();
+    assert!(Module::from_buffer(&wasm).is_err());
+}
+
+#[test]
+fn eval() {
+    let mut module = Module::default();
+    let constant = module
+        .globals
+        .add_local(ValType::I64, false, InitExpr::Value(Value::I64(7)));
+    match module.globals.initial_value(constant).unwrap() {
+        ConstValue::Known(Value::I64(7)) => {}
+        v => panic!("expected a constant, found {:?}", v),
+    }
+
+    // Hand-built initializers can read each other in a cycle.
+    let a = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    let b = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Global(a));
+    module.globals.get_mut(a).kind = GlobalKind::Local(InitExpr::Global(b));
+    assert!(module.globals.initial_value(a).is_err());
+}
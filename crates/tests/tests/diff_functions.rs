@@ -0,0 +1,130 @@
+//! Tests for comparing the bodies of two functions.
+
+use walrus::ir::matcher::*;
+use walrus::ir::*;
+use walrus::{diff_functions, FunctionBuilder, FunctionId, FunctionKind, Module, SubtreeChange};
+use walrus::{LocalFunction, ValType};
+
+/// A function computing
+///
+/// ```text
+/// a = 7; a * 8 + (a + 1)
+/// ```
+///
+/// with `extra` unused locals allocated before `a`, so that its id differs.
+fn module(extra: usize) -> (Module, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    for _ in 0..extra {
+        module.locals.add(ValType::I32);
+    }
+    let a = module.locals.add(ValType::I32);
+
+    let mut builder = FunctionBuilder::new();
+    let seven = builder.i32_const(7);
+    let set = builder.local_set(a, seven);
+    let get = builder.local_get(a);
+    let eight = builder.i32_const(8);
+    let mul = builder.binop(BinaryOp::I32Mul, get, eight);
+    let get = builder.local_get(a);
+    let one = builder.i32_const(1);
+    let add = builder.binop(BinaryOp::I32Add, get, one);
+    let sum = builder.binop(BinaryOp::I32Add, mul, add);
+    let func = builder.finish(ty, vec![], vec![set, sum], &mut module);
+    (module, func)
+}
+
+fn local_mut(module: &mut Module, func: FunctionId) -> &mut LocalFunction {
+    match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+#[test]
+fn identical_functions() {
+    let (a, f) = module(0);
+    let (b, g) = module(3);
+    let diff = diff_functions((&a, f), (&b, g));
+    assert!(diff.is_empty());
+    assert_eq!(diff.size_delta(), 0);
+}
+
+#[test]
+fn peephole_optimized_copy() {
+    let (a, f) = module(0);
+    let (mut b, g) = module(2);
+
+    // x * 8 => x << 3
+    let mul = binop(
+        BinaryOp::I32Mul,
+        any::<Pattern>().bind("x"),
+        const_i32(exactly(8)),
+    );
+    let replaced = replace_all(local_mut(&mut b, g), &mul, |func, m| {
+        let k = func.builder_mut().i32_const(3);
+        Some(Expr::Binop(Binop {
+            op: BinaryOp::I32Shl,
+            lhs: m["x"],
+            rhs: k,
+        }))
+    });
+    assert_eq!(replaced, 1);
+
+    let diff = diff_functions((&a, f), (&b, g));
+    assert_eq!(diff.changes().len(), 1);
+    match &diff.changes()[0] {
+        SubtreeChange::Changed {
+            old_text,
+            new_text,
+            old_size,
+            new_size,
+            ..
+        } => {
+            assert_eq!(old_text, "(I32Mul (local.get 0) (const 8))");
+            assert_eq!(new_text, "(I32Shl (local.get 0) (const 3))");
+            assert_eq!(old_size, new_size);
+        }
+        change => panic!("unexpected change: {:?}", change),
+    }
+    assert_eq!(diff.size_delta(), 0);
+
+    let expected = [
+        "--- old (~16 bytes)",
+        "+++ new (~16 bytes)",
+        " (block",
+        "   (local.set 0 (const 7))",
+        "   (I32Add",
+        "-    (I32Mul (local.get 0) (const 8))",
+        "+    (I32Shl (local.get 0) (const 3))",
+        "     (I32Add (local.get 0) (const 1))",
+        "   )",
+        " )",
+        "",
+    ];
+    assert_eq!(diff.to_string(), expected.join("\n"));
+}
+
+#[test]
+fn inserted_and_deleted_subtrees() {
+    let (a, f) = module(0);
+    let (mut b, g) = module(0);
+
+    // Remove the `local.set`, and add a `drop` of a constant at the end.
+    let func = local_mut(&mut b, g);
+    let entry = func.entry_block();
+    let one = func.builder_mut().i32_const(1);
+    let dropped = func.builder_mut().drop(one);
+    let set = func.block_mut(entry).exprs.remove(0);
+    func.block_mut(entry).exprs.push(dropped);
+
+    let diff = diff_functions((&a, f), (&b, g));
+    match diff.changes() {
+        [SubtreeChange::Deleted { old, text, .. }, SubtreeChange::Inserted { new, .. }] => {
+            assert_eq!(old.index(), set.index());
+            assert_eq!(text, "(local.set 0 (const 7))");
+            assert_eq!(*new, dropped);
+        }
+        changes => panic!("unexpected changes: {:?}", changes),
+    }
+}
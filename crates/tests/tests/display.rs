@@ -0,0 +1,188 @@
+//! Snapshot tests for displaying a function's IR.
+
+use walrus::ir::BinaryOp;
+use walrus::{DisplayOptions, FunctionBuilder, FunctionKind, Module, ValType};
+
+/// A function computing
+///
+/// ```text
+/// a = a * 3 + (10 - (b + 1))
+/// ```
+///
+/// and returning `a`, where each expression's id is its position in the
+/// order they're built here.
+fn module() -> Module {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let a = module.locals.add(ValType::I32);
+    let b = module.locals.add(ValType::I32);
+
+    let mut builder = FunctionBuilder::new();
+    let get_a = builder.local_get(a);
+    let three = builder.i32_const(3);
+    let mul = builder.binop(BinaryOp::I32Mul, get_a, three);
+    let ten = builder.i32_const(10);
+    let get_b = builder.local_get(b);
+    let one = builder.i32_const(1);
+    let add = builder.binop(BinaryOp::I32Add, get_b, one);
+    let sub = builder.binop(BinaryOp::I32Sub, ten, add);
+    let sum = builder.binop(BinaryOp::I32Add, mul, sub);
+    let set = builder.local_set(a, sum);
+    let get = builder.local_get(a);
+    builder.finish(ty, vec![], vec![set, get], &mut module);
+    module
+}
+
+fn display(opts: &DisplayOptions) -> String {
+    let module = module();
+    let func = module.funcs.iter().next().unwrap();
+    match &func.kind {
+        FunctionKind::Local(l) => l.display(opts),
+        _ => unreachable!(),
+    }
+}
+
+fn assert_lines(actual: &str, expected: &[&str]) {
+    assert_eq!(actual, expected.join("\n"), "actual:\n{}", actual);
+}
+
+#[test]
+fn every_expression_on_its_own_line_by_default() {
+    let expected = [
+        "        (func",
+        "(; 11;)   (block",
+        "(;  9;)     (local.set 0",
+        "(;  8;)       (I32Add",
+        "(;  2;)         (I32Mul",
+        "(;  0;)           (local.get 0)",
+        "(;  1;)           (const 3)",
+        "                )",
+        "(;  7;)         (I32Sub",
+        "(;  3;)           (const 10)",
+        "(;  6;)           (I32Add",
+        "(;  4;)             (local.get 1)",
+        "(;  5;)             (const 1)",
+        "                  )",
+        "                )",
+        "              )",
+        "            )",
+        "(; 10;)     (local.get 0)",
+        "          )",
+        "        )",
+    ];
+    assert_lines(&display(&DisplayOptions::new()), &expected);
+}
+
+#[test]
+fn fits_what_it_can_within_the_width() {
+    let expected = [
+        "        (func",
+        "(; 11;)   (block",
+        "(;  9;)     (local.set 0",
+        "(;  8;)       (I32Add",
+        "(;  2;)         (I32Mul (local.get 0) (const 3))",
+        "(;  7;)         (I32Sub",
+        "(;  3;)           (const 10)",
+        "(;  6;)           (I32Add (local.get 1) (const 1))",
+        "                )",
+        "              )",
+        "            )",
+        "(; 10;)     (local.get 0)",
+        "          )",
+        "        )",
+    ];
+    assert_lines(&display(DisplayOptions::new().width(60)), &expected);
+}
+
+#[test]
+fn wider_lines() {
+    let expected = [
+        "        (func",
+        "(; 11;)   (block",
+        "(;  9;)     (local.set 0",
+        "(;  8;)       (I32Add",
+        "(;  2;)         (I32Mul (local.get 0) (const 3))",
+        "(;  7;)         (I32Sub (const 10) (I32Add (local.get 1) (const 1)))",
+        "              )",
+        "            )",
+        "(; 10;)     (local.get 0)",
+        "          )",
+        "        )",
+    ];
+    assert_lines(&display(DisplayOptions::new().width(100)), &expected);
+}
+
+#[test]
+fn operands_under_their_operator() {
+    let expected = [
+        "        (func",
+        "(; 11;)  (block",
+        "(;  9;)   (local.set 0",
+        "(;  8;)    (I32Add",
+        "(;  2;)     (I32Mul (local.get 0) (const 3))",
+        "(;  7;)     (I32Sub",
+        "(;  3;)      (const 10)",
+        "(;  6;)      (I32Add (local.get 1) (const 1))",
+        "            )",
+        "           )",
+        "          )",
+        "(; 10;)   (local.get 0)",
+        "         )",
+        "        )",
+    ];
+    assert_lines(
+        &display(DisplayOptions::new().width(60).indent(1)),
+        &expected,
+    );
+}
+
+#[test]
+fn elided_levels() {
+    let expected = [
+        "        (func",
+        "(; 11;)   (block",
+        "(;  9;)     (local.set 0",
+        "(;  8;)       (I32Add",
+        "(;  2;)         (... 3 exprs)",
+        "(;  7;)         (... 5 exprs)",
+        "              )",
+        "            )",
+        "(; 10;)     (local.get 0)",
+        "          )",
+        "        )",
+    ];
+    assert_lines(
+        &display(DisplayOptions::new().max_depth(Some(3))),
+        &expected,
+    );
+}
+
+#[test]
+fn display_uses_the_formatter_width() {
+    let module = module();
+    let func = module.funcs.iter().next().unwrap();
+    assert_eq!(func.to_string(), display(&DisplayOptions::new()));
+    assert_eq!(
+        format!("{:60}", func),
+        display(DisplayOptions::new().width(60))
+    );
+}
+
+#[test]
+fn signatures() {
+    let mut module = Module::default();
+    let ty = module
+        .types
+        .add(&[ValType::I32, ValType::I32], &[ValType::I64]);
+    let unit = module.types.add(&[], &[]);
+    assert_eq!(module.types.get(ty).to_string(), "[i32, i32] -> [i64]");
+    assert_eq!(module.types.get(unit).to_string(), "[] -> []");
+
+    let import = module.add_import_func("env", "foo", ty);
+    module.funcs.get_mut(import).name = Some("foo".to_string());
+    let local = FunctionBuilder::new().finish(unit, vec![], vec![], &mut module);
+    let foo = module.funcs.get(import);
+    assert_eq!(foo.display_signature(&module), "foo: [i32, i32] -> [i64]");
+    let anonymous = module.funcs.get(local);
+    assert_eq!(anonymous.display_signature(&module), "[] -> []");
+}
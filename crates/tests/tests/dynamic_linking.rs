@@ -0,0 +1,230 @@
+//! Tests for sharing a memory and function table between modules.
+
+use walrus::ir::*;
+use walrus::{check_link_compat, ExportItem, FunctionBuilder, FunctionTable};
+use walrus::{LinkIssue, LinkItem, Module, ResizableLimits, TableKind, ValType};
+
+const PAGES: ResizableLimits = ResizableLimits {
+    initial: 2,
+    maximum: Some(16),
+};
+const ELEMENTS: ResizableLimits = ResizableLimits {
+    initial: 4,
+    maximum: None,
+};
+
+/// A module with its own memory, holding a data segment, and function table,
+/// and a function loading from the memory.
+fn side_module() -> Module {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(16, b"side".to_vec());
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let address = builder.i32_const(16);
+    let load = builder.load(
+        memory,
+        LoadKind::I32 { atomic: false },
+        MemArg {
+            align: 4,
+            offset: 0,
+        },
+        address,
+    );
+    let func = builder.finish(ty, vec![], vec![load], &mut module);
+    match &mut module.tables.get_mut(table).kind {
+        TableKind::Function(t) => t.elements.push(Some(func)),
+        _ => unreachable!(),
+    }
+    module.exports.add("f", ExportItem::Function(func));
+    module
+}
+
+fn main_module() -> Module {
+    let mut module = Module::default();
+    module.memories.add_local(false, 4, Some(16));
+    module
+        .tables
+        .add_local(8, None, TableKind::Function(FunctionTable::default()));
+    module
+        .export_memory_and_table("memory", "__indirect_function_table")
+        .unwrap();
+    module
+}
+
+#[test]
+fn import_keeps_data_and_elements() {
+    let mut side = side_module();
+    side.import_memory_and_table(
+        "env",
+        "memory",
+        "__indirect_function_table",
+        PAGES,
+        ELEMENTS,
+    )
+    .unwrap();
+    assert_eq!(side.memories.iter().count(), 1);
+    assert_eq!(side.tables.iter().count(), 1);
+
+    let wasm = side.emit_wasm().unwrap();
+    let side = Module::from_buffer(&wasm).unwrap();
+
+    let imports = side
+        .imports
+        .iter()
+        .map(|i| (&*i.module, &*i.name))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        imports,
+        [("env", "memory"), ("env", "__indirect_function_table")]
+    );
+
+    let memory = side.memories.get(side.memories.ids().next().unwrap());
+    assert!(memory.import.is_some());
+    assert_eq!((memory.initial, memory.maximum), (2, Some(16)));
+    let data = memory
+        .data
+        .iter()
+        .map(|(_, d)| d.to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(data, [b"side".to_vec()]);
+
+    let table = side.tables.iter().next().unwrap();
+    assert!(table.import.is_some());
+    assert_eq!((table.initial, table.maximum), (4, None));
+    assert_eq!(table.kind.unwrap_function().elements.len(), 1);
+}
+
+#[test]
+fn compatible_modules() {
+    let main = main_module();
+    let mut side = side_module();
+    side.import_memory_and_table(
+        "env",
+        "memory",
+        "__indirect_function_table",
+        PAGES,
+        ELEMENTS,
+    )
+    .unwrap();
+    assert!(check_link_compat(&main, &side).is_empty());
+}
+
+#[test]
+fn export_adds_missing_items() {
+    let mut module = Module::default();
+    let (memory, table) = module.export_memory_and_table("memory", "table").unwrap();
+    let exports = module
+        .exports
+        .iter()
+        .map(|e| (e.name.to_string(), e.item))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        exports,
+        [
+            ("memory".to_string(), ExportItem::Memory(memory)),
+            ("table".to_string(), ExportItem::Table(table)),
+        ]
+    );
+
+    // Exporting again is fine, but exporting something else with one of the
+    // names isn't.
+    module.export_memory_and_table("memory", "table").unwrap();
+    assert!(module.export_memory_and_table("table", "memory").is_err());
+}
+
+#[test]
+fn incompatible_modules() {
+    let main = main_module();
+
+    // The side module defines its own memory and table, and also imports a
+    // table with a larger initial size than the main module's, and a memory
+    // under the wrong name.
+    let mut side = side_module();
+    side.add_import_table(
+        "env",
+        "__indirect_function_table",
+        10,
+        None,
+        TableKind::Function(FunctionTable::default()),
+    );
+    side.add_import_memory("env", "mem", false, 1, None);
+
+    let issues = check_link_compat(&main, &side);
+    assert_eq!(
+        issues,
+        [
+            LinkIssue::NotImported {
+                item: LinkItem::Memory
+            },
+            LinkIssue::NotImported {
+                item: LinkItem::Table
+            },
+            LinkIssue::Limits {
+                item: LinkItem::Table,
+                name: "__indirect_function_table".to_string(),
+                exported: ResizableLimits {
+                    initial: 8,
+                    maximum: None
+                },
+                imported: ResizableLimits {
+                    initial: 10,
+                    maximum: None
+                },
+            },
+            LinkIssue::MissingExport {
+                item: LinkItem::Memory,
+                name: "mem".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn shared_and_maximum_mismatches() {
+    let main = main_module();
+    let mut side = Module::default();
+    side.add_import_memory("env", "memory", true, 1, Some(8));
+    side.add_import_table(
+        "env",
+        "memory",
+        1,
+        None,
+        TableKind::Function(FunctionTable::default()),
+    );
+
+    let issues = check_link_compat(&main, &side);
+    assert_eq!(
+        issues,
+        [
+            LinkIssue::Limits {
+                item: LinkItem::Memory,
+                name: "memory".to_string(),
+                exported: ResizableLimits {
+                    initial: 4,
+                    maximum: Some(16)
+                },
+                imported: ResizableLimits {
+                    initial: 1,
+                    maximum: Some(8)
+                },
+            },
+            LinkIssue::Shared {
+                name: "memory".to_string(),
+                exported: false,
+            },
+            LinkIssue::WrongKind {
+                item: LinkItem::Table,
+                name: "memory".to_string(),
+            },
+        ]
+    );
+}
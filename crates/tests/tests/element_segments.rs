@@ -0,0 +1,306 @@
+//! Tests for the encoding of each form of element segment.
+
+use walrus::ir::Value;
+use walrus::{ElementKind, FunctionBuilder, FunctionId, FunctionTable, InitExpr, Module};
+use walrus::{ModuleConfig, SegmentSource, TableKind, ValType};
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+/// A module with a single `(func)`.
+fn fixture() -> (Module, FunctionId) {
+    let mut module = Module::with_config(config());
+    let ty = module.types.add(&[], &[]);
+    let func = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    (module, func)
+}
+
+/// The members of every segment of `module` which isn't stored in a table's
+/// layout, with its kind and type.
+fn elements(module: &Module) -> Vec<(String, ValType, Vec<Option<FunctionId>>)> {
+    module
+        .elements
+        .iter()
+        .map(|e| (format!("{:?}", e.kind), e.ty, e.members.clone()))
+        .collect()
+}
+
+#[test]
+fn mvp_round_trip_is_byte_stable() {
+    let wasm = walrus_tests_utils::module(&[
+        (0x01, &[0x01, 0x60, 0x00, 0x00]),
+        (0x03, &[0x01, 0x00]),
+        (0x04, &[0x01, 0x70, 0x00, 0x02]),
+        (0x09, &[0x01, 0x00, 0x41, 0x01, 0x0b, 0x01, 0x00]),
+        (0x0a, &[0x01, 0x02, 0x00, 0x0b]),
+    ]);
+
+    let module = config().parse(&wasm).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn active_segment_for_other_table() {
+    let (mut module, func) = fixture();
+    module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let mut table = FunctionTable::default();
+    table.elements.push(Some(func));
+    module.tables.add_local(1, None, TableKind::Function(table));
+
+    let wasm = module.emit_wasm().unwrap();
+    // flags, table index, offset, elemkind, then the function indices
+    assert_eq!(
+        walrus_tests_utils::section(&wasm, 0x09),
+        [0x01, 0x02, 0x01, 0x41, 0x00, 0x0b, 0x00, 0x01, 0x00]
+    );
+
+    let module = config().parse(&wasm).unwrap();
+    let layouts = module
+        .tables
+        .iter()
+        .map(|t| match &t.kind {
+            TableKind::Function(t) => t.elements.len(),
+            k => panic!("unexpected table {:?}", k),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(layouts, [0, 1]);
+}
+
+#[test]
+fn passive_function_indices() {
+    let (mut module, func) = fixture();
+    module
+        .elements
+        .add(ElementKind::Passive, ValType::Funcref, vec![Some(func)]);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        walrus_tests_utils::section(&wasm, 0x09),
+        [0x01, 0x01, 0x00, 0x01, 0x00]
+    );
+
+    let module = config().parse(&wasm).unwrap();
+    let func = module.funcs.iter().next().unwrap().id();
+    assert_eq!(
+        elements(&module),
+        [("Passive".to_string(), ValType::Funcref, vec![Some(func)])]
+    );
+}
+
+#[test]
+fn passive_expressions() {
+    let (mut module, func) = fixture();
+    module.elements.add(
+        ElementKind::Passive,
+        ValType::Funcref,
+        vec![Some(func), None],
+    );
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        walrus_tests_utils::section(&wasm, 0x09),
+        [0x01, 0x05, 0x70, 0x02, 0xd2, 0x00, 0x0b, 0xd0, 0x70, 0x0b]
+    );
+
+    let module = config().parse(&wasm).unwrap();
+    let func = module.funcs.iter().next().unwrap().id();
+    assert_eq!(
+        elements(&module),
+        [(
+            "Passive".to_string(),
+            ValType::Funcref,
+            vec![Some(func), None]
+        )]
+    );
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn declared_segments() {
+    let (mut module, func) = fixture();
+    module
+        .elements
+        .add(ElementKind::Declared, ValType::Funcref, vec![Some(func)]);
+    module
+        .elements
+        .add(ElementKind::Declared, ValType::Externref, vec![None]);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        walrus_tests_utils::section(&wasm, 0x09),
+        [0x02, 0x03, 0x00, 0x01, 0x00, 0x07, 0x6f, 0x01, 0xd0, 0x6f, 0x0b]
+    );
+
+    let module = config().parse(&wasm).unwrap();
+    let func = module.funcs.iter().next().unwrap().id();
+    assert_eq!(
+        elements(&module),
+        [
+            ("Declared".to_string(), ValType::Funcref, vec![Some(func)]),
+            ("Declared".to_string(), ValType::Externref, vec![None]),
+        ]
+    );
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn active_expressions() {
+    let (mut module, func) = fixture();
+    let table = module
+        .tables
+        .add_local(2, None, TableKind::Function(FunctionTable::default()));
+    let offset = InitExpr::Value(Value::I32(0));
+    module.elements.add(
+        ElementKind::Active { table, offset },
+        ValType::Funcref,
+        vec![None, Some(func)],
+    );
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        walrus_tests_utils::section(&wasm, 0x09),
+        [0x01, 0x04, 0x41, 0x00, 0x0b, 0x02, 0xd0, 0x70, 0x0b, 0xd2, 0x00, 0x0b]
+    );
+
+    // The `ref.null` keeps the segment out of the table's layout.
+    let module = config().parse(&wasm).unwrap();
+    let func = module.funcs.iter().next().unwrap().id();
+    let table = module.tables.iter().next().unwrap().id();
+    let segments = module.element_segments();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].table(), Some(table));
+    assert_eq!(segments[0].members(), [None, Some(func)]);
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn invalid_flags_are_an_error() {
+    let wasm = walrus_tests_utils::module(&[(0x09, &[0x01, 0x08, 0x00])]);
+    let err = config().parse(&wasm).unwrap_err();
+    assert!(format!("{:?}", err).contains("invalid flags"), "{:?}", err);
+}
+
+#[test]
+fn gc_keeps_declared_functions() {
+    let (mut module, func) = fixture();
+    module
+        .elements
+        .add(ElementKind::Declared, ValType::Funcref, vec![Some(func)]);
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.funcs.iter().count(), 1);
+    assert_eq!(module.elements.iter().count(), 1);
+}
+
+#[test]
+fn uniform_views_of_every_segment() {
+    let (mut module, func) = fixture();
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(4)));
+    let mut layout = FunctionTable {
+        elements: vec![None, Some(func), Some(func), None, Some(func)],
+        ..FunctionTable::default()
+    };
+    layout
+        .relative_elements
+        .push((global, vec![Some(func), None]));
+    let table = module
+        .tables
+        .add_local(8, None, TableKind::Function(layout));
+    let passive = module.elements.add_passive(&[func]);
+    let active = module
+        .elements
+        .add_active(table, InitExpr::Value(Value::I32(6)), &[func]);
+
+    let segments = module.element_segments();
+    let summary = segments
+        .iter()
+        .map(|s| (s.source(), s.table(), s.members().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                SegmentSource::Layout { table, start: 1 },
+                Some(table),
+                vec![Some(func), Some(func)]
+            ),
+            (
+                SegmentSource::Layout { table, start: 4 },
+                Some(table),
+                vec![Some(func)]
+            ),
+            (
+                SegmentSource::Relative { table, index: 0 },
+                Some(table),
+                vec![Some(func), None]
+            ),
+            (
+                SegmentSource::Element(active),
+                Some(table),
+                vec![Some(func)]
+            ),
+            (SegmentSource::Element(passive), None, vec![Some(func)]),
+        ]
+    );
+    let offsets = segments
+        .iter()
+        .map(|s| match s.offset() {
+            Some(InitExpr::Value(Value::I32(n))) => Some(n),
+            Some(InitExpr::Global(g)) => {
+                assert_eq!(g, global);
+                Some(-1)
+            }
+            Some(e) => panic!("unexpected offset {:?}", e),
+            None => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(offsets, [Some(1), Some(4), Some(-1), Some(6), None]);
+    match segments[4].kind() {
+        ElementKind::Passive => {}
+        k => panic!("expected a passive segment, found {:?}", k),
+    }
+
+    // Every segment is emitted.
+    let wasm = module.emit_wasm().unwrap();
+    let module = config().parse(&wasm).unwrap();
+    assert_eq!(module.element_segments().len(), 5);
+}
+
+#[test]
+fn editing_a_member_changes_one_entry() {
+    let wasm = walrus_tests_utils::module(&[
+        (0x01, &[0x01, 0x60, 0x00, 0x00]),
+        (0x03, &[0x02, 0x00, 0x00]),
+        (0x04, &[0x01, 0x70, 0x00, 0x03]),
+        (
+            0x09,
+            &[0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, 0x00, 0x01, 0x00],
+        ),
+        (0x0a, &[0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b]),
+    ]);
+
+    let mut module = config().parse(&wasm).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+
+    let mut segments = module.element_segments_mut();
+    assert_eq!(segments.len(), 1);
+    let first = segments[0].members()[0];
+    segments[0].members_mut()[1] = first;
+
+    // Only the second function index in the element section changes.
+    let emitted = module.emit_wasm().unwrap();
+    let before = walrus_tests_utils::sections(&wasm);
+    let after = walrus_tests_utils::sections(&emitted);
+    assert_eq!(before.len(), after.len());
+    for ((id, old), (_, new)) in before.iter().zip(&after) {
+        if *id == 0x09 {
+            assert_eq!(*new, [0x01, 0x00, 0x41, 0x00, 0x0b, 0x03, 0x00, 0x00, 0x00]);
+        } else {
+            assert_eq!(old, new);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Tests for the exception handling proposal: tags, `try`, `throw` and
+//! `rethrow`.
+
+use walrus::ir::*;
+use walrus::passes::gc;
+use walrus::{ExportItem, FunctionBuilder, Module, ModuleConfig, TagKind, ValType};
+use walrus_tests_utils::{function_bodies, section};
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+fn module() -> Module {
+    Module::with_config(config())
+}
+
+#[test]
+fn try_catch_throw_rethrow() {
+    let mut module = module();
+    let tag_ty = module.types.add(&[ValType::I32], &[]);
+    let empty = module.types.add(&[], &[]);
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let imported = module.add_import_tag("env", "t", empty);
+    let tag = module.tags.add_local(tag_ty);
+    module.exports.add("e", tag);
+
+    // (try (result i32)
+    //   (do (throw $tag (i32.const 7)))
+    //   (catch $tag)
+    //   (catch_all (rethrow 0)))
+    let mut builder = FunctionBuilder::new();
+    let body = {
+        let mut body = builder.try_block(Box::new([]), Box::new([ValType::I32]));
+        let seven = body.i32_const(7);
+        let throw = body.throw(tag, Box::new([seven]));
+        body.expr(throw);
+        body.id()
+    };
+    // The thrown value is the handler's parameter, which it passes through.
+    let catch = builder
+        .try_block(Box::new([ValType::I32]), Box::new([ValType::I32]))
+        .id();
+    let catch_all = {
+        let mut handler = builder.try_block(Box::new([]), Box::new([ValType::I32]));
+        let id = handler.id();
+        let rethrow = handler.rethrow(id);
+        handler.expr(rethrow);
+        id
+    };
+    let try_ = builder.try_(
+        body,
+        Box::new([tag]),
+        Box::new([catch]),
+        Some(catch_all),
+        None,
+    );
+    builder.finish(ty, vec![], vec![try_], &mut module);
+
+    // (block $b (try (do (throw $imported)) (delegate $b)))
+    let mut builder = FunctionBuilder::new();
+    let mut outer = builder.block(Box::new([]), Box::new([]));
+    let outer_id = outer.id();
+    let body = {
+        let mut body = outer.try_block(Box::new([]), Box::new([]));
+        let throw = body.throw(imported, Box::new([]));
+        body.expr(throw);
+        body.id()
+    };
+    let delegate = outer.try_(body, Box::new([]), Box::new([]), None, Some(outer_id));
+    outer.expr(delegate);
+    drop(outer);
+    builder.finish(empty, vec![], vec![outer_id.into()], &mut module);
+
+    let wasm = module.emit_wasm().unwrap();
+    // The imported tag comes first in the tag index space.
+    assert_eq!(section(&wasm, 2), b"\x01\x03env\x01t\x04\x00\x01");
+    assert_eq!(section(&wasm, 13), [0x01, 0x00, 0x00]);
+    assert_eq!(section(&wasm, 7), b"\x01\x01e\x04\x01");
+    let bodies = [
+        &[
+            0x00, 0x06, 0x7f, 0x41, 0x07, 0x08, 0x01, 0x07, 0x01, 0x19, 0x09, 0x00, 0x0b, 0x0b,
+        ][..],
+        &[
+            0x00, 0x02, 0x40, 0x06, 0x40, 0x08, 0x00, 0x18, 0x00, 0x0b, 0x0b,
+        ][..],
+    ];
+    assert_eq!(function_bodies(&wasm), bodies);
+
+    // Parsing it back gives the same tags and `try`s, which are emitted the
+    // same way again.
+    let parsed = config().parse(&wasm).unwrap();
+    let tags = parsed.tags.iter().collect::<Vec<_>>();
+    assert_eq!(tags.len(), 2);
+    assert!(matches!(tags[0].kind, TagKind::Import(_)));
+    assert!(matches!(tags[1].kind, TagKind::Local));
+    assert_eq!(parsed.types.get(tags[1].ty).params(), [ValType::I32]);
+    match parsed.exports.iter().next().unwrap().item {
+        ExportItem::Tag(id) => assert_eq!(id, tags[1].id()),
+        _ => panic!("not a tag export"),
+    }
+
+    let mut funcs = parsed.funcs.iter().map(|f| f.kind.unwrap_local());
+    let func = funcs.next().unwrap();
+    let try_ = match func.block(func.entry_block()).exprs[..] {
+        [expr] => match func.get(expr) {
+            Expr::Try(try_) => try_.clone(),
+            e => panic!("not a `try`: {:?}", e),
+        },
+        ref exprs => panic!("not a single `try`: {:?}", exprs),
+    };
+    assert_eq!(&try_.catch_tags[..], [tags[1].id()]);
+    assert_eq!(try_.catches.len(), 1);
+    assert_eq!(try_.delegate, None);
+    let catch_all = try_.catch_all.unwrap();
+    match func.block(catch_all).exprs[..] {
+        [expr] => match func.get(expr) {
+            Expr::Rethrow(rethrow) => assert_eq!(rethrow.block, catch_all),
+            e => panic!("not a `rethrow`: {:?}", e),
+        },
+        ref exprs => panic!("not a single `rethrow`: {:?}", exprs),
+    }
+    let func = funcs.next().unwrap();
+    let block = match func.block(func.entry_block()).exprs[..] {
+        [block] => block,
+        ref exprs => panic!("not a single `block`: {:?}", exprs),
+    };
+    let inner = match func.get(block) {
+        Expr::Block(block) => &block.exprs[..],
+        e => panic!("not a `block`: {:?}", e),
+    };
+    match inner {
+        [expr] => match func.get(*expr) {
+            Expr::Try(try_) => assert_eq!(try_.delegate.map(ExprId::from), Some(block)),
+            e => panic!("not a `try`: {:?}", e),
+        },
+        exprs => panic!("not a single `try`: {:?}", exprs),
+    }
+
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn handlers_using_caught_values() {
+    let tags = [0x01, 0x00, 0x00];
+    let round_trip = |types: &[u8], code: &[u8]| {
+        let wasm =
+            walrus_tests_utils::module(&[(1, types), (3, &[0x01, 0x01]), (13, &tags), (10, code)]);
+        let module = config().parse(&wasm).unwrap();
+        assert_eq!(module.emit_wasm().unwrap(), wasm);
+    };
+
+    // (try (do) (catch $tag (drop)))
+    let types = [0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x00];
+    round_trip(
+        &types,
+        &[0x01, 0x08, 0x00, 0x06, 0x40, 0x07, 0x00, 0x1a, 0x0b, 0x0b],
+    );
+
+    // (try (result i32) (do (i32.const 1)) (catch $tag))
+    let types = [0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x01, 0x7f];
+    round_trip(
+        &types,
+        &[
+            0x01, 0x09, 0x00, 0x06, 0x7f, 0x41, 0x01, 0x07, 0x00, 0x0b, 0x0b,
+        ],
+    );
+
+    // (try (result i32)
+    //   (do (i32.const 1))
+    //   (catch $tag (i32.add (i32.const 2))))
+    round_trip(
+        &types,
+        &[
+            0x01, 0x0c, 0x00, 0x06, 0x7f, 0x41, 0x01, 0x07, 0x00, 0x41, 0x02, 0x6a, 0x0b, 0x0b,
+        ],
+    );
+
+    // (i32.const 3)
+    // (try (param i32) (result i32)
+    //   (do (i32.sub (i32.const 1)))
+    //   (catch $tag (i32.mul (i32.const 2))))
+    #[rustfmt::skip]
+    let types = [
+        0x03,
+        0x60, 0x01, 0x7f, 0x00,
+        0x60, 0x00, 0x01, 0x7f,
+        0x60, 0x01, 0x7f, 0x01, 0x7f,
+    ];
+    round_trip(
+        &types,
+        &[
+            0x01, 0x0f, 0x00, 0x41, 0x03, 0x06, 0x02, 0x41, 0x01, 0x6b, 0x07, 0x00, 0x41, 0x02,
+            0x6c, 0x0b, 0x0b,
+        ],
+    );
+}
+
+#[test]
+fn misplaced_handlers_are_rejected() {
+    let ty = [0x01, 0x04, 0x60, 0x00, 0x00];
+    for body in [
+        &[0x00, 0x19, 0x0b][..],                     // catch_all
+        &[0x00, 0x06, 0x40, 0x19, 0x19, 0x0b, 0x0b], // catch_all catch_all
+        &[0x00, 0x02, 0x40, 0x18, 0x00, 0x0b],       // block .. delegate
+        &[0x00, 0x02, 0x40, 0x09, 0x00, 0x0b, 0x0b], // rethrow out of a block
+    ]
+    .iter()
+    {
+        let mut code = vec![0x01, body.len() as u8];
+        code.extend_from_slice(body);
+        let wasm = walrus_tests_utils::module(&[(1, &ty), (3, &[0x01, 0x00]), (10, &code)]);
+        assert!(config().parse(&wasm).is_err());
+    }
+}
+
+#[test]
+fn gc_removes_unused_tags() {
+    let mut module = module();
+    let ty = module.types.add(&[], &[]);
+    module.add_import_tag("env", "t", ty);
+    let used = module.tags.add_local(ty);
+    module.tags.add_local(ty);
+
+    let mut builder = FunctionBuilder::new();
+    let throw = builder.throw(used, Box::new([]));
+    let func = builder.finish(ty, vec![], vec![throw], &mut module);
+    module.exports.add("f", func);
+
+    gc::run(&mut module);
+    let ids: Vec<_> = module.tags.ids().collect();
+    assert_eq!(ids, [used]);
+    assert!(module.imports.is_empty());
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn tag_types_have_no_results() {
+    let mut module = module();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    module.tags.add_local(ty);
+    assert!(walrus::passes::validate::run(&module).is_err());
+}
@@ -0,0 +1,137 @@
+//! Tests for extended constant expressions in initializers and offsets.
+
+use walrus::ir::Value;
+use walrus::passes::validate;
+use walrus::{ConstValue, GlobalKind, InitExpr, InitOp, Module, ModuleConfig, ValType};
+use walrus_tests_utils::section;
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+fn module() -> Module {
+    Module::with_config(config())
+}
+
+#[test]
+fn const_eval_wraps_and_reads_globals() {
+    let mut module = module();
+    let base = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(i32::MAX)));
+    let imported = module.add_import_global("env", "base", ValType::I64, false);
+
+    // base * 2 + 3
+    let wrapped = InitExpr::Extended(vec![
+        InitOp::Global(base),
+        InitOp::Value(Value::I32(2)),
+        InitOp::I32Mul,
+        InitOp::Value(Value::I32(3)),
+        InitOp::I32Add,
+    ]);
+    match module.globals.const_eval(&wrapped) {
+        Some(Value::I32(1)) => {}
+        v => panic!("expected 1, found {:?}", v),
+    }
+
+    let relative = InitExpr::Extended(vec![
+        InitOp::Global(imported),
+        InitOp::Value(Value::I64(8)),
+        InitOp::I64Sub,
+    ]);
+    assert!(module.globals.const_eval(&relative).is_none());
+    match module.globals.eval(relative).unwrap() {
+        ConstValue::Unknown => {}
+        v => panic!("expected an unknown value, found {:?}", v),
+    }
+}
+
+#[test]
+fn extended_offsets_are_emitted_and_parsed() {
+    let mut module = module();
+    let base = module.add_import_global("env", "base", ValType::I32, false);
+    let memory = module.memories.add_local(false, 1, None);
+    module.memories.get_mut(memory).data.add_extended(
+        vec![
+            InitOp::Global(base),
+            InitOp::Value(Value::I32(16)),
+            InitOp::I32Add,
+        ],
+        b"hi".to_vec(),
+    );
+    let global = module.globals.add_local(
+        ValType::I32,
+        false,
+        InitExpr::Extended(vec![
+            InitOp::Global(base),
+            InitOp::Value(Value::I32(4)),
+            InitOp::I32Mul,
+        ]),
+    );
+    module.exports.add("memory", memory);
+    module.exports.add("g", global);
+    validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm().unwrap();
+    // The global's initializer: `global.get 0; i32.const 4; i32.mul; end`.
+    assert_eq!(
+        section(&wasm, 6),
+        &[0x01, 0x7f, 0x00, 0x23, 0x00, 0x41, 0x04, 0x6c, 0x0b][..]
+    );
+    // The data segment's offset: `global.get 0; i32.const 16; i32.add; end`.
+    assert_eq!(
+        section(&wasm, 11),
+        &[0x01, 0x00, 0x23, 0x00, 0x41, 0x10, 0x6a, 0x0b, 0x02, b'h', b'i'][..]
+    );
+
+    let parsed = config().parse(&wasm).unwrap();
+    let memory = parsed.memories.iter().next().unwrap();
+    let (offset, data) = memory.data.iter().next().unwrap();
+    assert_eq!(data, b"hi");
+    match offset {
+        InitExpr::Extended(ops) => assert_eq!(ops.len(), 3),
+        e => panic!("expected an extended offset, found {:?}", e),
+    }
+    let inits = parsed.globals.iter().filter_map(|g| match &g.kind {
+        GlobalKind::Local(init) => Some(init),
+        GlobalKind::Import(_) => None,
+    });
+    match inits.collect::<Vec<_>>()[..] {
+        [InitExpr::Extended(ops)] => assert_eq!(ops.len(), 3),
+        ref inits => panic!("expected an extended initializer, found {:?}", inits),
+    }
+    validate::run(&parsed).unwrap();
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn ill_typed_expressions_are_rejected() {
+    let mut module = module();
+    module.globals.add_local(
+        ValType::I32,
+        false,
+        InitExpr::Extended(vec![
+            InitOp::Value(Value::I32(1)),
+            InitOp::Value(Value::I64(2)),
+            InitOp::I32Add,
+        ]),
+    );
+    assert!(validate::run(&module).is_err());
+
+    let mut module = self::module();
+    let mutable = module.add_import_global("env", "mutable", ValType::I32, true);
+    module.globals.add_local(
+        ValType::I32,
+        false,
+        InitExpr::Extended(vec![
+            InitOp::Global(mutable),
+            InitOp::Value(Value::I32(1)),
+            InitOp::I32Add,
+        ]),
+    );
+    assert!(validate::run(&module).is_err());
+}
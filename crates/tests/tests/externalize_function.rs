@@ -0,0 +1,88 @@
+//! Tests for turning local functions into imports.
+
+use walrus::ir::*;
+use walrus::{ExportItem, FunctionBuilder, FunctionKind, FunctionTable, ImportKind};
+use walrus::{Module, TableKind, ValType};
+
+#[test]
+fn externalize_a_called_function() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+
+    // (func $double (param i32) (result i32) (local i32)
+    //   (local.set 1 (i32.add (local.get 0) (local.get 0)))
+    //   (local.get 1))
+    let arg = module.locals.add(ValType::I32);
+    let tmp = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let lhs = builder.local_get(arg);
+    let rhs = builder.local_get(arg);
+    let sum = builder.binop(BinaryOp::I32Add, lhs, rhs);
+    let set = builder.local_set(tmp, sum);
+    let get = builder.local_get(tmp);
+    let double = builder.finish(ty, vec![arg], vec![set, get], &mut module);
+    module.funcs.get_mut(double).name = Some("double".to_string());
+
+    // (func $quadruple (export "quadruple") (param i32) (result i32)
+    //   (call $double (call $double (local.get 0))))
+    let arg = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let get = builder.local_get(arg);
+    let inner = builder.call(double, Box::new([get]));
+    let outer = builder.call(double, Box::new([inner]));
+    let quadruple = builder.finish(ty, vec![arg], vec![outer], &mut module);
+    module.exports.add("quadruple", quadruple);
+
+    let mut table = FunctionTable::default();
+    table.elements.push(Some(double));
+    module.tables.add_local(1, None, TableKind::Function(table));
+
+    let import = module
+        .externalize_function(double, "host", "double")
+        .unwrap();
+    assert_eq!(module.imports.find("host", "double"), Some(import));
+    match &module.funcs.get(double).kind {
+        FunctionKind::Import(i) => {
+            assert_eq!(i.import, import);
+            assert_eq!(i.ty, ty);
+        }
+        _ => panic!("expected an import"),
+    }
+
+    // Externalizing it again fails, since it's no longer local.
+    assert!(module
+        .externalize_function(double, "host", "again")
+        .is_err());
+
+    let module = Module::from_buffer(&module.emit_wasm().unwrap()).unwrap();
+    assert_eq!(module.funcs.iter_local().count(), 1);
+    let import = module.imports.iter().next().unwrap();
+    assert_eq!((&*import.module, &*import.name), ("host", "double"));
+    let double = match import.kind {
+        ImportKind::Function(f) => f,
+        _ => panic!("expected a function import"),
+    };
+    assert_eq!(module.funcs.get(double).name.as_deref(), Some("double"));
+
+    // Its caller and the table still refer to it.
+    let quadruple = module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            ExportItem::Function(f) => Some(f),
+            _ => None,
+        })
+        .unwrap();
+    let func = match &module.funcs.get(quadruple).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("expected a local function"),
+    };
+    match func.get(func.block(func.entry_block()).exprs[0]) {
+        Expr::Call(call) => assert_eq!(call.func, double),
+        e => panic!("expected a call, found {:?}", e),
+    }
+    match &module.tables.iter().next().unwrap().kind {
+        TableKind::Function(table) => assert_eq!(table.elements, [Some(double)]),
+        _ => panic!("expected a function table"),
+    };
+}
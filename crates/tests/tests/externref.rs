@@ -0,0 +1,137 @@
+//! Tests for `externref` tables, globals and element segments.
+
+use walrus::ir::RefType;
+use walrus::passes::{gc, validate};
+use walrus::{ElementKind, ExternrefTable, GlobalKind, InitExpr, Module, ModuleConfig};
+use walrus::{TableKind, ValType};
+use walrus_tests_utils::section;
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+fn module() -> Module {
+    Module::with_config(config())
+}
+
+#[test]
+fn externref_table_and_global() {
+    let mut module = module();
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Externref(ExternrefTable::default()));
+    let global = module.globals.add_local(
+        ValType::Externref,
+        true,
+        InitExpr::RefNull(RefType::Externref),
+    );
+    module.exports.add("t", table);
+    module.exports.add("g", global);
+    validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm().unwrap();
+    // One table of `externref`, with a minimum of 1.
+    assert_eq!(section(&wasm, 4), [0x01, 0x6f, 0x00, 0x01]);
+    // A mutable `externref` initialized to `ref.null`.
+    assert_eq!(section(&wasm, 6), [0x01, 0x6f, 0x01, 0xd0, 0x6f, 0x0b]);
+
+    let parsed = config().parse(&wasm).unwrap();
+    let table = parsed.tables.iter().next().unwrap();
+    assert!(matches!(table.kind, TableKind::Externref(_)));
+    let global = parsed.globals.iter().next().unwrap();
+    assert_eq!(global.ty, ValType::Externref);
+    match global.kind {
+        GlobalKind::Local(InitExpr::RefNull(RefType::Externref)) => {}
+        ref kind => panic!("not a null `externref`: {:?}", kind),
+    }
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn imported_externref_table_and_global() {
+    let mut module = module();
+    let kind = TableKind::Externref(ExternrefTable::default());
+    module.add_import_table("env", "t", 1, Some(2), kind);
+    module.add_import_global("env", "g", ValType::Externref, false);
+
+    let wasm = module.emit_wasm().unwrap();
+    let parsed = config().parse(&wasm).unwrap();
+    let table = parsed.tables.iter().next().unwrap();
+    assert!(matches!(table.kind, TableKind::Externref(_)));
+    assert_eq!((table.initial, table.maximum), (1, Some(2)));
+    assert!(table.import.is_some());
+    let global = parsed.globals.iter().next().unwrap();
+    assert_eq!(global.ty, ValType::Externref);
+    assert!(matches!(global.kind, GlobalKind::Import(_)));
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn global_type_must_match_ref_null() {
+    let mut module = module();
+    module.globals.add_local(
+        ValType::Funcref,
+        false,
+        InitExpr::RefNull(RefType::Externref),
+    );
+    assert!(validate::run(&module).is_err());
+}
+
+#[test]
+fn externref_segments_only_hold_nulls() {
+    let mut module = module();
+    let ty = module.types.add(&[], &[]);
+    let func = walrus::FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module
+        .elements
+        .add(ElementKind::Passive, ValType::Externref, vec![None]);
+    validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm().unwrap();
+    // A passive segment of expressions, holding one `ref.null extern`.
+    assert_eq!(
+        section(&wasm, 9),
+        [0x01, 0x05, 0x6f, 0x01, 0xd0, 0x6f, 0x0b]
+    );
+    let parsed = config().parse(&wasm).unwrap();
+    let elem = parsed.elements.iter().next().unwrap();
+    assert_eq!(elem.ty, ValType::Externref);
+    assert_eq!(elem.members, [None]);
+
+    module
+        .elements
+        .add(ElementKind::Passive, ValType::Externref, vec![Some(func)]);
+    assert!(validate::run(&module).is_err());
+}
+
+#[test]
+fn active_segment_must_match_table() {
+    let mut module = module();
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Externref(ExternrefTable::default()));
+    let offset = InitExpr::Value(walrus::ir::Value::I32(0));
+    module.elements.add_active(table, offset, &[]);
+    assert!(validate::run(&module).is_err());
+}
+
+#[test]
+fn gc_keeps_exported_externref_table() {
+    let mut module = module();
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Externref(ExternrefTable::default()));
+    module
+        .tables
+        .add_local(1, None, TableKind::Externref(ExternrefTable::default()));
+    module.exports.add("t", table);
+
+    gc::run(&mut module);
+    let tables: Vec<_> = module.tables.iter().map(|t| t.id()).collect();
+    assert_eq!(tables, [table]);
+    module.emit_wasm().unwrap();
+}
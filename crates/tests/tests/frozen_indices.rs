@@ -0,0 +1,102 @@
+//! Tests for freezing function indices before emitting a module.
+
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, Module, ModuleConfig, ValType};
+
+/// A module with an imported function, followed by a small function named
+/// `small` and a larger one named `large`.
+fn fixture() -> (Module, FunctionId, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    module.add_import_func("env", "imported", ty);
+
+    let small = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.funcs.get_mut(small).name = Some("small".to_string());
+
+    let mut builder = FunctionBuilder::new();
+    let body = (0..10)
+        .map(|i| {
+            let value = builder.i32_const(i);
+            builder.drop(value)
+        })
+        .collect();
+    let large = builder.finish(ty, vec![], body, &mut module);
+    module.funcs.get_mut(large).name = Some("large".to_string());
+    (module, small, large)
+}
+
+/// Make `func` much larger, so that it'd be emitted first if indices weren't
+/// frozen.
+fn grow(module: &mut Module, func: FunctionId) {
+    let func = match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(f) => f,
+        _ => unreachable!(),
+    };
+    let entry = func.entry_block();
+    for i in 0..100 {
+        let builder = func.builder_mut();
+        let value = builder.i64_const(i);
+        let drop = builder.drop(value);
+        func.block_mut(entry).exprs.push(drop);
+    }
+}
+
+#[test]
+fn frozen_indices_are_used_when_emitting() {
+    let (mut module, small, large) = fixture();
+    let indices = module.assign_indices();
+    assert_eq!(indices.func(large), Some(1));
+    assert_eq!(indices.func(small), Some(2));
+    assert_eq!(indices.funcs().len(), 3);
+    assert_eq!(module.frozen_indices().unwrap().func(small), Some(2));
+
+    // Bake the index of `small` into memory, and grow it past `large`.
+    let index = indices.func(small).unwrap();
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(0, index.to_le_bytes().to_vec());
+    module.exports.add("memory", memory);
+    grow(&mut module, small);
+    assert_eq!(module.function_index(small), Some(2));
+
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    assert!(wasmparser::validate(&wasm, None));
+    let data = &wasm[layout.data[0].1.clone()];
+    let baked = u32::from(data[0]) | u32::from(data[1]) << 8;
+
+    let mut config = ModuleConfig::new();
+    config.retain_index_mapping(true);
+    let parsed = config.parse(&wasm).unwrap();
+    let id = parsed.input_indices().unwrap().get_func(baked).unwrap();
+    assert_eq!(parsed.funcs.get(id).name.as_ref().unwrap(), "small");
+
+    // Once unfrozen, the larger function comes first again.
+    module.unfreeze_indices();
+    assert!(module.frozen_indices().is_none());
+    assert_eq!(module.function_index(small), Some(1));
+}
+
+#[test]
+fn adding_or_removing_functions_after_freezing_fails() {
+    let (mut module, small, _) = fixture();
+    module.assign_indices();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let added = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    assert!(module.emit_wasm().is_err());
+
+    // Freezing again takes the new function into account.
+    let indices = module.assign_indices();
+    assert_eq!(indices.func(added), Some(3));
+    module.emit_wasm().unwrap();
+
+    module.funcs.delete(small);
+    assert!(module.emit_wasm().is_err());
+    let import = module.imports.find("env", "imported").unwrap();
+    module.imports.delete(import);
+    assert!(module.emit_wasm().is_err());
+
+    module.unfreeze_indices();
+    module.emit_wasm().unwrap();
+}
@@ -0,0 +1,123 @@
+//! Tests that `Module::function_index_order` and `Module::function_indices`
+//! match the indices functions are emitted with.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, ImportKind, Module, ModuleConfig, ValType};
+
+/// A module with imported functions around an imported global, and local
+/// functions of different sizes, all named after the order they were added
+/// in.
+fn fixture(config: ModuleConfig) -> Module {
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[], &[]);
+
+    let a = module.add_import_func("env", "a", ty);
+    module.funcs.get_mut(a).name = Some("import0".to_string());
+    module.add_import_global("env", "g", ValType::I32, false);
+    let b = module.add_import_func("env", "b", ty);
+    module.funcs.get_mut(b).name = Some("import1".to_string());
+
+    for (i, size) in [1, 5, 3, 8, 0].iter().enumerate() {
+        let mut builder = FunctionBuilder::new();
+        let mut body = Vec::new();
+        for _ in 0..*size {
+            body.push(builder.call(a, Box::new([])));
+        }
+        let f = builder.finish(ty, vec![], body, &mut module);
+        module.funcs.get_mut(f).name = Some(format!("local{}", i));
+    }
+    module
+}
+
+/// Check that every function ends up at the index `function_index_order`
+/// gives it.
+fn check(module: &Module) {
+    let predicted = module
+        .function_index_order()
+        .map(|(i, f)| (i, f.name.clone().unwrap()))
+        .collect::<Vec<_>>();
+    let indices = module.function_indices();
+    assert_eq!(indices.funcs().len(), predicted.len());
+    for (i, f) in module.function_index_order() {
+        assert_eq!(indices.func(f.id()), Some(i));
+        assert_eq!(indices.funcs()[i as usize], f.id());
+    }
+
+    let mut config = ModuleConfig::new();
+    config.retain_index_mapping(true);
+    let emitted = config.parse(&module.emit_wasm().unwrap()).unwrap();
+    let indices = emitted.input_indices().unwrap();
+    let actual = (0..predicted.len() as u32)
+        .map(|i| {
+            let id = indices.get_func(i).unwrap();
+            (i, emitted.funcs.get(id).name.clone().unwrap())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(predicted, actual);
+}
+
+#[test]
+fn sorted_by_size() {
+    let module = fixture(ModuleConfig::new());
+    check(&module);
+    let names = module
+        .function_index_order()
+        .map(|(_, f)| f.name.clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["import0", "import1", "local3", "local1", "local2", "local0", "local4"]
+    );
+}
+
+#[test]
+fn original_order() {
+    let mut config = ModuleConfig::new();
+    config.preserve_function_order(true);
+    let module = fixture(config);
+    check(&module);
+    let names = module
+        .function_index_order()
+        .map(|(_, f)| f.name.clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["import0", "import1", "local0", "local1", "local2", "local3", "local4"]
+    );
+}
+
+#[test]
+fn preserved_bodies() {
+    let mut config = ModuleConfig::new();
+    config.preserve_original_bodies(true);
+    let wasm = fixture(ModuleConfig::new()).emit_wasm().unwrap();
+    let mut module = config.parse(&wasm).unwrap();
+    check(&module);
+
+    // New functions go after the original ones, however big they are.
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let mut sum = builder.i32_const(0);
+    for i in 0..20 {
+        let value = builder.i32_const(i);
+        sum = builder.binop(BinaryOp::I32Add, sum, value);
+    }
+    let f = builder.finish(ty, vec![], vec![sum], &mut module);
+    module.funcs.get_mut(f).name = Some("new".to_string());
+    check(&module);
+    assert_eq!(module.function_index(f), Some(7));
+}
+
+#[test]
+fn deleted_imports() {
+    let mut module = fixture(ModuleConfig::new());
+    let import = module.imports.find("env", "b").unwrap();
+    let func = match module.imports.get(import).kind {
+        ImportKind::Function(f) => f,
+        _ => panic!("expected a function import"),
+    };
+    module.imports.delete(import);
+    assert_eq!(module.function_index(func), None);
+    module.funcs.delete(func);
+    check(&module);
+}
@@ -0,0 +1,80 @@
+//! Tests for user data attached to functions.
+
+use rayon::prelude::*;
+use std::sync::Arc;
+use walrus::{FunctionBuilder, Module, ValType};
+
+#[derive(Debug, PartialEq)]
+struct Cost(u32);
+
+#[derive(Debug, PartialEq)]
+struct DontTouch;
+
+#[test]
+fn values_are_keyed_by_type() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let metadata = &mut module.funcs.get_mut(f).metadata;
+
+    assert!(metadata.is_empty());
+    assert_eq!(metadata.insert(Cost(1)), None);
+    assert_eq!(metadata.insert(Cost(2)), Some(Cost(1)));
+    metadata.insert(DontTouch);
+    assert_eq!(metadata.len(), 2);
+    assert_eq!(metadata.get::<Cost>(), Some(&Cost(2)));
+    assert!(metadata.contains::<DontTouch>());
+    assert!(!metadata.contains::<u32>());
+
+    *metadata.get_or_insert_with(|| 0u32) += 5;
+    *metadata.get_or_insert_with(|| 0u32) += 5;
+    assert_eq!(metadata.get::<u32>(), Some(&10));
+
+    assert_eq!(metadata.remove::<DontTouch>(), Some(DontTouch));
+    assert_eq!(metadata.remove::<DontTouch>(), None);
+    assert_eq!(metadata.len(), 2);
+}
+
+#[test]
+fn parallel_updates() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    for i in 0..100 {
+        let arg = module.locals.add(ValType::I32);
+        let f = FunctionBuilder::new().finish(ty, vec![arg], vec![], &mut module);
+        module.funcs.get_mut(f).metadata.insert(Cost(i));
+    }
+    module.funcs.par_iter_mut().for_each(|f| {
+        let cost = f.metadata.get::<Cost>().unwrap().0;
+        f.metadata.insert(Cost(cost * 2));
+    });
+    let total = module
+        .funcs
+        .iter()
+        .map(|f| f.metadata.get::<Cost>().unwrap().0)
+        .sum::<u32>();
+    assert_eq!(total, 2 * (0..100).sum::<u32>());
+}
+
+#[test]
+fn deleting_a_function_drops_its_metadata() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let shared = Arc::new(());
+    module.funcs.get_mut(f).metadata.insert(shared.clone());
+    assert_eq!(Arc::strong_count(&shared), 2);
+
+    module.funcs.delete(f);
+    assert_eq!(Arc::strong_count(&shared), 1);
+}
+
+#[test]
+fn metadata_isnt_emitted() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let before = module.emit_wasm().unwrap();
+    module.funcs.get_mut(f).metadata.insert(Cost(1));
+    assert_eq!(module.emit_wasm().unwrap(), before);
+}
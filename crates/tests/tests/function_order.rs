@@ -0,0 +1,88 @@
+//! Tests for emitting local functions in their original order.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType};
+
+fn config(preserve: bool) -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_producers_section(false)
+        .preserve_function_order(preserve);
+    config
+}
+
+/// A module with a small, a big and a medium-sized function, in that order,
+/// each of a different type so that a mismatch between the function and code
+/// sections fails validation.
+fn fixture(config: ModuleConfig) -> Module {
+    let mut module = Module::with_config(config);
+
+    let ty = module.types.add(&[], &[]);
+    let small = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.funcs.get_mut(small).name = Some("small".to_string());
+
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let mut sum = builder.i32_const(0);
+    for i in 1..20 {
+        let value = builder.i32_const(i);
+        sum = builder.binop(BinaryOp::I32Add, sum, value);
+    }
+    let call = builder.call(small, Box::new([]));
+    let big = builder.finish(ty, vec![], vec![call, sum], &mut module);
+    module.funcs.get_mut(big).name = Some("big".to_string());
+
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let arg = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let call = builder.call(big, Box::new([]));
+    let drop = builder.drop(call);
+    let medium = builder.finish(ty, vec![arg], vec![drop], &mut module);
+    module.funcs.get_mut(medium).name = Some("medium".to_string());
+    module.exports.add("medium", medium);
+
+    module
+}
+
+/// The names of the module's functions, in index order.
+fn names(module: &Module) -> Vec<String> {
+    module
+        .funcs
+        .iter()
+        .map(|f| f.name.clone().unwrap())
+        .collect()
+}
+
+#[test]
+fn functions_are_sorted_by_size_by_default() {
+    let wasm = fixture(config(false)).emit_wasm().unwrap();
+    let module = config(false).parse(&wasm).unwrap();
+    assert_eq!(names(&module), ["big", "medium", "small"]);
+}
+
+#[test]
+fn original_order_round_trips() {
+    let wasm = fixture(config(true)).emit_wasm().unwrap();
+    let module = config(true).parse(&wasm).unwrap();
+    assert_eq!(names(&module), ["small", "big", "medium"]);
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn new_functions_go_last() {
+    let wasm = fixture(config(true)).emit_wasm().unwrap();
+    let mut module = config(true).parse(&wasm).unwrap();
+
+    let ty = module.types.add(&[], &[]);
+    let mut builder = FunctionBuilder::new();
+    let mut body = Vec::new();
+    for _ in 0..10 {
+        let value = builder.i32_const(0);
+        body.push(builder.drop(value));
+    }
+    let new = builder.finish(ty, vec![], body, &mut module);
+    module.funcs.get_mut(new).name = Some("new".to_string());
+
+    let module = config(true).parse(&module.emit_wasm().unwrap()).unwrap();
+    assert_eq!(names(&module), ["small", "big", "medium", "new"]);
+}
@@ -0,0 +1,151 @@
+//! Tests for typed function references, `call_ref` and `ref.as_non_null`.
+
+use walrus::ir::Expr;
+use walrus::passes::{gc, trap_sites, validate, TrapKind};
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType};
+use walrus_tests_utils::{function_bodies, section};
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+fn module() -> Module {
+    Module::with_config(config())
+}
+
+#[test]
+fn call_ref_round_trips_to_bytes() {
+    let mut module = module();
+    let callee_ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let reference = ValType::Ref {
+        nullable: true,
+        ty: callee_ty,
+    };
+    let ty = module
+        .types
+        .add(&[reference, ValType::I32], &[ValType::I32]);
+    let func_ref = module.locals.add(reference);
+    let arg = module.locals.add(ValType::I32);
+
+    // (call_ref $callee_ty (local.get $arg) (ref.as_non_null (local.get $func_ref)))
+    let mut builder = FunctionBuilder::new();
+    let value = builder.local_get(arg);
+    let callee = builder.local_get(func_ref);
+    let callee = builder.ref_as_non_null(callee);
+    let call = builder.call_ref(callee_ty, callee, Box::new([value]));
+    let func = builder.finish(ty, vec![func_ref, arg], vec![call], &mut module);
+    module.exports.add("f", func);
+    validate::run(&module).unwrap();
+
+    let sites = trap_sites(&module);
+    assert_eq!(sites.len(), 2);
+    assert!(sites.iter().all(|s| s.kind == TrapKind::NullReference));
+
+    let wasm = module.emit_wasm().unwrap();
+    // The caller's type takes a `(ref null 0)`.
+    assert_eq!(
+        section(&wasm, 1),
+        &[
+            0x02, // two types
+            0x60, 0x01, 0x7f, 0x01, 0x7f, // (func (param i32) (result i32))
+            0x60, 0x02, 0x63, 0x00, 0x7f, 0x01, 0x7f,
+        ][..]
+    );
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x01, 0x20, 0x00, 0xd4, 0x14, 0x00, 0x0b][..]]
+    );
+
+    let parsed = config().parse(&wasm).unwrap();
+    let (_, func) = parsed.funcs.iter_local().next().unwrap();
+    let block = func.block(func.entry_block());
+    let call = match &block.exprs[..] {
+        [e] => match func.get(*e) {
+            Expr::CallRef(call) => call,
+            e => panic!("expected a call_ref, found {:?}", e),
+        },
+        exprs => panic!("expected one expression, found {:?}", exprs),
+    };
+    assert_eq!(parsed.types.get(call.ty).params(), &[ValType::I32]);
+    assert_eq!(call.args.len(), 1);
+    match func.get(call.func) {
+        Expr::RefAsNonNull(_) => {}
+        e => panic!("expected a ref.as_non_null, found {:?}", e),
+    }
+    validate::run(&parsed).unwrap();
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn parsing_call_ref_of_a_mismatched_reference_fails() {
+    let mut module = module();
+    let callee_ty = module.types.add(&[], &[]);
+    let other_ty = module.types.add(&[], &[ValType::I32]);
+    let reference = ValType::Ref {
+        nullable: false,
+        ty: callee_ty,
+    };
+    let ty = module.types.add(&[reference], &[]);
+    let func_ref = module.locals.add(reference);
+
+    // (drop (call_ref $other_ty (local.get $func_ref)))
+    let mut builder = FunctionBuilder::new();
+    let callee = builder.local_get(func_ref);
+    let call = builder.call_ref(other_ty, callee, Box::new([]));
+    let drop = builder.drop(call);
+    let func = builder.finish(ty, vec![func_ref], vec![drop], &mut module);
+    module.exports.add("f", func);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert!(config().parse(&wasm).is_err());
+}
+
+#[test]
+fn call_ref_type_must_match_reference() {
+    let mut module = module();
+    let callee_ty = module.types.add(&[], &[]);
+    let other_ty = module.types.add(&[], &[ValType::I32]);
+    let func_ref = module.locals.add(ValType::Ref {
+        nullable: false,
+        ty: callee_ty,
+    });
+
+    let mut builder = FunctionBuilder::new();
+    let callee = builder.local_get(func_ref);
+    let call = builder.call_ref(other_ty, callee, Box::new([]));
+    let drop = builder.drop(call);
+    builder.finish(callee_ty, vec![], vec![drop], &mut module);
+    assert!(validate::run(&module).is_err());
+}
+
+#[test]
+fn gc_keeps_types_of_typed_references() {
+    let mut module = module();
+    let inner = module.types.add(&[ValType::F64], &[]);
+    let middle = module.types.add(
+        &[ValType::Ref {
+            nullable: true,
+            ty: inner,
+        }],
+        &[],
+    );
+    let reference = ValType::Ref {
+        nullable: false,
+        ty: middle,
+    };
+    let ty = module.types.add(&[reference], &[]);
+    module.types.add(&[ValType::F32], &[]);
+    let arg = module.locals.add(reference);
+    let func = FunctionBuilder::new().finish(ty, vec![arg], vec![], &mut module);
+    module.exports.add("f", func);
+
+    gc::run(&mut module);
+    let mut types: Vec<_> = module.types.iter().map(|t| t.id()).collect();
+    types.sort();
+    assert_eq!(types, [inner, middle, ty]);
+    module.emit_wasm().unwrap();
+}
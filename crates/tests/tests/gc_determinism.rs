@@ -0,0 +1,89 @@
+//! Tests that GC gives the same output however the module's arenas were
+//! laid out before it ran.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, InitExpr, Module, ValType};
+use walrus_tests_utils::function_bodies;
+
+/// A module with two exported functions sharing a callee, a chain of
+/// functions that nothing uses, and used and unused globals, types and
+/// imports.
+fn fixture() -> Vec<u8> {
+    let mut module = Module::default();
+    let unit = module.types.add(&[], &[]);
+    let int = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let used_import = module.add_import_func("env", "used", unit);
+    module.add_import_func("env", "unused", int);
+    let used_global = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(1)));
+    module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(2)));
+
+    let mut builder = FunctionBuilder::new();
+    let get = builder.global_get(used_global);
+    let drop = builder.drop(get);
+    let call = builder.call(used_import, Box::new([]));
+    let shared = builder.finish(unit, vec![], vec![drop, call], &mut module);
+
+    let mut dead: Option<FunctionId> = None;
+    for _ in 0..3 {
+        let mut builder = FunctionBuilder::new();
+        let body = match dead {
+            Some(f) => vec![builder.call(f, Box::new([]))],
+            None => vec![],
+        };
+        dead = Some(builder.finish(unit, vec![], body, &mut module));
+    }
+
+    for name in ["a", "b"].iter() {
+        let mut builder = FunctionBuilder::new();
+        let call = builder.call(shared, Box::new([]));
+        let f = builder.finish(unit, vec![], vec![call], &mut module);
+        module.exports.add(name, f);
+    }
+    module.emit_wasm().unwrap()
+}
+
+fn gc_and_emit(mut module: Module) -> Vec<u8> {
+    walrus::passes::gc::run(&mut module);
+    module.emit_wasm().unwrap()
+}
+
+#[test]
+fn gc_is_deterministic() {
+    let wasm = fixture();
+    let expected = gc_and_emit(Module::from_buffer(&wasm).unwrap());
+    // GC deleted the unused functions, so it had something to do.
+    assert_eq!(function_bodies(&wasm).len(), 6);
+    assert_eq!(function_bodies(&expected).len(), 3);
+    assert_eq!(gc_and_emit(Module::from_buffer(&wasm).unwrap()), expected);
+
+    // Fill the arenas with extra items, some deleted and some left for GC to
+    // delete, so that the used items are at different positions and the
+    // exports' ids are interleaved with deleted ones.
+    let mut module = Module::from_buffer(&wasm).unwrap();
+    let ty = module.types.add(&[ValType::F64], &[]);
+    let arg = module.locals.add(ValType::F64);
+    for i in 0..10 {
+        let f = FunctionBuilder::new().finish(ty, vec![arg], vec![], &mut module);
+        let export = module.exports.add(&format!("dummy{}", i), f);
+        module.exports.delete(export);
+        if i % 2 == 0 {
+            module.funcs.delete(f);
+        }
+        module
+            .globals
+            .add_local(ValType::F64, false, InitExpr::Value(Value::F64(0.0)));
+    }
+    assert_eq!(gc_and_emit(module), expected);
+}
+
+#[test]
+fn gc_is_idempotent() {
+    let wasm = fixture();
+    let once = gc_and_emit(Module::from_buffer(&wasm).unwrap());
+    let twice = gc_and_emit(Module::from_buffer(&once).unwrap());
+    assert_eq!(once, twice);
+}
@@ -0,0 +1,161 @@
+//! Tests for passing through types and function bodies using the GC proposal.
+
+use walrus::{FunctionKind, LocalFunction, Module, ModuleConfig};
+use walrus_tests_utils::{function_bodies, section};
+
+/// The type section:
+///   (type (struct (field (mut i32))))
+///   (type (func))
+///   (rec (type (array i64)))
+#[rustfmt::skip]
+const TYPES: &[u8] = &[
+    0x03,
+    0x5f, 0x01, 0x7f, 0x01,
+    0x60, 0x00, 0x00,
+    0x4e, 0x01, 0x5e, 0x7e, 0x00,
+];
+
+/// The code section.
+#[rustfmt::skip]
+const CODE: &[u8] = &[
+    0x03,
+    // (func unreachable)
+    0x03, 0x00, 0x00, 0x0b,
+    // (func)
+    0x02, 0x00, 0x0b,
+    // (func call 1 struct.new_default 0 drop)
+    0x08, 0x00, 0x10, 0x01, 0xfb, 0x01, 0x00, 0x1a, 0x0b,
+];
+
+/// A module with the types and code above, whose three functions are of type
+/// 1, and which exports the last one as "run".
+fn wasm() -> Vec<u8> {
+    walrus_tests_utils::module(&[
+        (1, TYPES),
+        (3, &[0x03, 0x01, 0x01, 0x01]),
+        (7, &[0x01, 0x03, b'r', b'u', b'n', 0x00, 0x02]),
+        (10, CODE),
+    ])
+}
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false)
+        .preserve_function_order(true)
+        .allow_gc_types(true);
+    config
+}
+
+fn local_mut<'a>(module: &'a mut Module, name: &str) -> &'a mut LocalFunction {
+    let id = module.exports.get_func(name).unwrap();
+    match &mut module.funcs.get_mut(id).kind {
+        FunctionKind::Local(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn round_trips_unchanged() {
+    let wasm = wasm();
+    assert!(Module::from_buffer(&wasm).is_err());
+
+    let module = config().parse(&wasm).unwrap();
+    let opaque = module
+        .types
+        .iter()
+        .map(|ty| ty.is_opaque())
+        .collect::<Vec<_>>();
+    assert_eq!(opaque, [true, false, true]);
+    let raw = module
+        .funcs
+        .iter_local()
+        .map(|(_, f)| f.is_raw())
+        .collect::<Vec<_>>();
+    assert_eq!(raw, [false, false, true]);
+
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn indices_are_updated() {
+    let mut module = config().parse(&wasm()).unwrap();
+    walrus::passes::gc::run(&mut module);
+    let run = module.exports.find("run").unwrap();
+    module.exports.get_mut(run).name = "start".into();
+    let wasm = module.emit_wasm().unwrap();
+
+    // Only the unused function is gone, so the call is to function 0 now.
+    assert_eq!(module.funcs.iter_local().count(), 2);
+    assert_eq!(
+        function_bodies(&wasm),
+        [
+            &[0x00, 0x0b][..],
+            &[0x00, 0x10, 0x00, 0xfb, 0x01, 0x00, 0x1a, 0x0b][..],
+        ]
+    );
+    // The type section is unchanged.
+    assert_eq!(section(&wasm, 1), TYPES);
+
+    let module = config().parse(&wasm).unwrap();
+    assert!(module.exports.get_func("start").is_ok());
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn edits_are_rejected() {
+    let mut module = config().parse(&wasm()).unwrap();
+    let func = local_mut(&mut module, "run");
+    let entry = func.entry_block();
+    let body = func.block(entry).exprs[0];
+    func.block_mut(entry).exprs.push(body);
+    let err = module.emit_wasm().unwrap_err();
+    assert!(err.to_string().contains("can't be changed"), "{}", err);
+
+    let mut module = config().parse(&wasm()).unwrap();
+    let array = module.types.iter().last().unwrap().id();
+    module.types.delete(array);
+    let err = module.emit_wasm().unwrap_err();
+    assert!(err.to_string().contains("GC proposal"), "{}", err);
+}
+
+#[test]
+#[should_panic(expected = "raw function bodies can't be changed")]
+fn raw_bodies_cant_be_extended() {
+    let mut module = config().parse(&wasm()).unwrap();
+    local_mut(&mut module, "run").builder_mut();
+}
+
+#[test]
+fn tags_in_raw_bodies_are_updated() {
+    // Two tags of type 1, and a function throwing the second one after
+    // using a GC instruction.
+    #[rustfmt::skip]
+    let code: &[u8] = &[
+        0x01,
+        // (func struct.new_default 0 drop throw 1)
+        0x08, 0x00, 0xfb, 0x01, 0x00, 0x1a, 0x08, 0x01, 0x0b,
+    ];
+    let wasm = walrus_tests_utils::module(&[
+        (1, TYPES),
+        (3, &[0x01, 0x01]),
+        (13, &[0x02, 0x00, 0x01, 0x00, 0x01]),
+        (7, &[0x01, 0x03, b'r', b'u', b'n', 0x00, 0x00]),
+        (10, code),
+    ]);
+
+    let mut module = config().parse(&wasm).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+
+    // The unused tag is removed, so the thrown one is tag 0 now.
+    walrus::passes::gc::run(&mut module);
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(section(&wasm, 13), &[0x01, 0x00, 0x01][..]);
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0xfb, 0x01, 0x00, 0x1a, 0x08, 0x00, 0x0b][..]]
+    );
+    let module = config().parse(&wasm).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
@@ -0,0 +1,145 @@
+//! Tests for inferring the results of blocks wrapping existing expressions.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, LocalFunction, Module, ValType};
+
+/// Build a `(func (result i32))` with the given body, returning the ids of
+/// interesting expressions alongside it.
+fn build<F>(module: &mut Module, body: F) -> (FunctionId, Vec<ExprId>)
+where
+    F: FnOnce(&mut Module, &mut FunctionBuilder) -> Vec<ExprId>,
+{
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let exprs = body(module, &mut builder);
+    let func = builder.finish(ty, vec![], exprs.clone(), module);
+    (func, exprs)
+}
+
+fn local(module: &Module, id: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(id).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn infer(module: &Module, func: FunctionId, exprs: &[ExprId]) -> Vec<ValType> {
+    local(module, func)
+        .infer_block_results(module, exprs)
+        .unwrap()
+}
+
+#[test]
+fn empty() {
+    let mut module = Module::default();
+    let (func, _) = build(&mut module, |_, b| vec![b.i32_const(1)]);
+    assert!(infer(&module, func, &[]).is_empty());
+}
+
+#[test]
+fn statements_then_value() {
+    let mut module = Module::default();
+    let local = module.locals.add(ValType::I64);
+    let (func, exprs) = build(&mut module, |_, b| {
+        let value = b.i64_const(1);
+        let set = b.local_set(local, value);
+        let get = b.local_get(local);
+        vec![set, get]
+    });
+    assert_eq!(infer(&module, func, &exprs), [ValType::I64]);
+    assert!(infer(&module, func, &exprs[..1]).is_empty());
+}
+
+#[test]
+fn call_results() {
+    let mut module = Module::default();
+    let callee_ty = module.types.add(&[], &[ValType::F32]);
+    let callee = module.add_import_func("env", "f", callee_ty);
+    let (func, exprs) = build(&mut module, |_, b| vec![b.call(callee, Box::new([]))]);
+    assert_eq!(infer(&module, func, &exprs), [ValType::F32]);
+}
+
+#[test]
+fn ends_in_br() {
+    let mut module = Module::default();
+    let (func, exprs) = build(&mut module, |_, b| {
+        let mut block = b.block(Box::new([]), Box::new([]));
+        let id = block.id();
+        let value = block.i32_const(1);
+        let value = (*block).drop(value);
+        let br = block.br(id, Box::new([]));
+        block.expr(value);
+        block.expr(br);
+        drop(block);
+        let one = b.i32_const(1);
+        vec![id.into(), one, value, br]
+    });
+    assert!(infer(&module, func, &exprs[2..]).is_empty());
+    // Values pushed before the branch don't matter.
+    assert!(infer(&module, func, &[exprs[1], exprs[3]]).is_empty());
+}
+
+#[test]
+fn ends_in_return() {
+    let mut module = Module::default();
+    let (func, exprs) = build(&mut module, |_, b| {
+        let value = b.i32_const(1);
+        let ret = b.return_(Box::new([value]));
+        let after = b.f64_const(2.0);
+        vec![ret, after]
+    });
+    assert!(infer(&module, func, &exprs[..1]).is_empty());
+    // Anything after an unreachable point is still pushed.
+    assert_eq!(infer(&module, func, &exprs), [ValType::F64]);
+}
+
+#[test]
+fn multiple_values() {
+    let mut module = Module::default();
+    let (func, exprs) = build(&mut module, |_, b| {
+        let a = b.i32_const(1);
+        let c = b.f64_const(2.0);
+        vec![a, c]
+    });
+    assert_eq!(infer(&module, func, &exprs), [ValType::I32, ValType::F64]);
+}
+
+#[test]
+fn block_params_are_consumed() {
+    let mut module = Module::default();
+    let (func, exprs) = build(&mut module, |module, b| {
+        module.types.add(&[ValType::I32], &[ValType::I64]);
+        let one = b.i32_const(1);
+        let block = b
+            .block(Box::new([ValType::I32]), Box::new([ValType::I64]))
+            .id();
+        vec![one, block.into()]
+    });
+    assert_eq!(infer(&module, func, &exprs), [ValType::I64]);
+    assert!(local(&module, func)
+        .infer_block_results(&module, &exprs[1..])
+        .is_err());
+}
+
+#[test]
+fn wrap_in_block() {
+    let mut module = Module::default();
+    let local_id = module.locals.add(ValType::I32);
+    let (func, exprs) = build(&mut module, |_, b| {
+        let value = b.i32_const(1);
+        let set = b.local_set(local_id, value);
+        let get = b.local_get(local_id);
+        vec![set, get]
+    });
+    let (block, expr) = module.wrap_in_block(func, exprs, BlockKind::Block).unwrap();
+    assert_eq!(ExprId::from(block), expr);
+
+    let f = match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    assert_eq!(f.block(block).results.to_vec(), [ValType::I32]);
+    let entry = f.entry_block();
+    f.block_mut(entry).exprs = vec![expr];
+    module.emit_wasm().unwrap();
+}
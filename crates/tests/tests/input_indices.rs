@@ -0,0 +1,51 @@
+//! Tests for retaining the map from original indices to walrus IDs.
+
+use walrus::{Module, ModuleConfig};
+
+/// A module importing one function and defining two more, with names for all
+/// three in its `name` section.
+fn fixture() -> Vec<u8> {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x02, 0x07, 0x01, 0x01, b'm', 0x01, b'f', 0x00, 0x00]); // import section
+    wasm.extend(&[0x03, 0x03, 0x02, 0x00, 0x00]); // function section
+    wasm.extend(&[0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b]); // code section
+    wasm.extend(&[
+        0x00, 0x17, // custom section
+        0x04, b'n', b'a', b'm', b'e', // "name"
+        0x01, 0x10, // function names subsection
+        0x03, // three names
+        0x00, 0x03, b'i', b'm', b'p', //
+        0x01, 0x03, b'f', b'o', b'o', //
+        0x02, 0x03, b'b', b'a', b'r', //
+    ]);
+    wasm
+}
+
+#[test]
+fn not_retained_by_default() {
+    let module = Module::from_buffer(&fixture()).unwrap();
+    assert!(module.input_indices().is_none());
+}
+
+#[test]
+fn translate_raw_function_index() {
+    let mut config = ModuleConfig::new();
+    config.retain_index_mapping(true);
+    let mut module = config.parse(&fixture()).unwrap();
+
+    // Pretend this came from some external symbol file.
+    let side_file = "2\n";
+    let index = side_file.trim().parse::<u32>().unwrap();
+
+    let id = module.input_indices().unwrap().get_func(index).unwrap();
+    assert_eq!(module.funcs.get(id).name.as_ref().unwrap(), "bar");
+    assert!(module.input_indices().unwrap().get_func(3).is_err());
+
+    // Later edits don't affect the mapping.
+    let foo = module.funcs.by_name("foo").unwrap();
+    module.funcs.delete(foo);
+    let indices = module.input_indices().unwrap();
+    assert_eq!(indices.get_func(1).unwrap(), foo);
+    assert_eq!(indices.get_func(2).unwrap(), id);
+}
@@ -0,0 +1,210 @@
+//! Tests for sharing identical constants, `local.get`s and `global.get`s
+//! within a function.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, InitExpr, LocalFunction};
+use walrus::{Module, ModuleConfig, ValType};
+
+/// A module with a function which stores `(local.get 0) + 1` at address 0,
+/// and `$g + 1` at address 4, over and over again.
+fn constant_heavy() -> Vec<u8> {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(7)));
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let arg = module.locals.add(ValType::I32);
+
+    let mut builder = FunctionBuilder::new();
+    let mut body = Vec::new();
+    let kind = StoreKind::I32 { atomic: false };
+    let memarg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    for _ in 0..100 {
+        let address = builder.i32_const(0);
+        let get = builder.local_get(arg);
+        let one = builder.i32_const(1);
+        let value = builder.binop(BinaryOp::I32Add, get, one);
+        body.push(builder.store(memory, kind, memarg, address, value));
+
+        let address = builder.i32_const(4);
+        let get = builder.global_get(global);
+        let one = builder.i32_const(1);
+        let value = builder.binop(BinaryOp::I32Add, get, one);
+        body.push(builder.store(memory, kind, memarg, address, value));
+    }
+    let func = builder.finish(ty, vec![arg], body, &mut module);
+    module.exports.add("f", func);
+    module.emit_wasm().unwrap()
+}
+
+fn local(module: &mut Module, id: FunctionId) -> &mut LocalFunction {
+    match &mut module.funcs.get_mut(id).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn i32_value(func: &LocalFunction, id: ExprId) -> i32 {
+    match func.get(id) {
+        Expr::Const(Const {
+            value: Value::I32(n),
+        }) => *n,
+        e => panic!("expected an i32 constant, found {:?}", e),
+    }
+}
+
+#[test]
+fn parsing_shares_leaves_without_changing_the_output() {
+    let wasm = constant_heavy();
+    let plain = ModuleConfig::new().parse(&wasm).unwrap();
+    let interned = ModuleConfig::new()
+        .intern_leaf_exprs(true)
+        .parse(&wasm)
+        .unwrap();
+
+    let (_, plain_func) = plain.funcs.iter_local().next().unwrap();
+    let (_, interned_func) = interned.funcs.iter_local().next().unwrap();
+    // Each pair of stores has two `i32.add`s and two stores of their own, and
+    // otherwise only uses the same handful of leaves.
+    assert!(plain_func.allocated_exprs() > 1000);
+    assert!(interned_func.allocated_exprs() < 410);
+
+    assert_eq!(plain.emit_wasm().unwrap(), interned.emit_wasm().unwrap());
+}
+
+#[test]
+fn only_identical_leaves_are_shared() {
+    let mut module = Module::default();
+    let a = module.locals.add(ValType::I32);
+    let b = module.locals.add(ValType::I32);
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+
+    let mut builder = FunctionBuilder::new();
+    let before = builder.i32_const(1);
+    builder.set_interning(true);
+
+    let one = builder.i32_const(1);
+    assert_ne!(one, before);
+    assert_eq!(builder.i32_const(1), one);
+    assert!(builder.is_interned(one));
+    assert!(!builder.is_interned(before));
+    assert_ne!(builder.i32_const(2), one);
+    assert_ne!(builder.i64_const(1), builder.i32_const(1));
+    assert_ne!(builder.f32_const(0.0), builder.f32_const(-0.0));
+    assert_eq!(builder.f64_const(1.5), builder.f64_const(1.5));
+
+    assert_eq!(builder.local_get(a), builder.local_get(a));
+    assert_ne!(builder.local_get(a), builder.local_get(b));
+    assert_eq!(builder.global_get(global), builder.global_get(global));
+
+    // Only leaves are shared.
+    let add = |builder: &mut FunctionBuilder| {
+        let lhs = builder.local_get(a);
+        builder.binop(BinaryOp::I32Add, lhs, one)
+    };
+    assert_ne!(add(&mut builder), add(&mut builder));
+    assert_ne!(builder.local_tee(a, one), builder.local_tee(a, one));
+
+    builder.set_interning(false);
+    assert!(!builder.is_interned(one));
+    assert_ne!(builder.i32_const(1), builder.i32_const(1));
+}
+
+/// A function that drops `(i32.add (i32.const 5) (i32.const 5))`, with the
+/// constant interned.
+fn shared_constant() -> (Module, FunctionId, ExprId, ExprId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let mut builder = FunctionBuilder::new();
+    builder.set_interning(true);
+    let lhs = builder.i32_const(5);
+    let rhs = builder.i32_const(5);
+    assert_eq!(lhs, rhs);
+    let add = builder.binop(BinaryOp::I32Add, lhs, rhs);
+    let drop = builder.drop(add);
+    let func = builder.finish(ty, vec![], vec![drop], &mut module);
+    (module, func, add, lhs)
+}
+
+fn operands(func: &LocalFunction, add: ExprId) -> (i32, i32) {
+    match func.get(add) {
+        Expr::Binop(e) => (i32_value(func, e.lhs), i32_value(func, e.rhs)),
+        e => panic!("expected a binop, found {:?}", e),
+    }
+}
+
+#[test]
+fn mutating_an_interned_leaf_changes_every_use() {
+    let (mut module, func, add, five) = shared_constant();
+    let func = local(&mut module, func);
+    assert!(func.is_interned(five));
+
+    *func.get_mut(five) = Expr::Const(Const {
+        value: Value::I32(6),
+    });
+    assert_eq!(operands(func, add), (6, 6));
+
+    // The mutated expression isn't handed out any more, for either its old
+    // value or its new one.
+    assert!(!func.is_interned(five));
+    let new_five = func.builder_mut().i32_const(5);
+    let six = func.builder_mut().i32_const(6);
+    assert_ne!(new_five, five);
+    assert_ne!(six, five);
+    assert_eq!(i32_value(func, new_five), 5);
+}
+
+#[test]
+fn replacing_one_use_of_an_interned_leaf() {
+    let (mut module, func, add, five) = shared_constant();
+    let func = local(&mut module, func);
+
+    let six = func.builder_mut().i32_const(6);
+    match func.get_mut(add) {
+        Expr::Binop(e) => e.rhs = six,
+        _ => unreachable!(),
+    }
+    assert_eq!(operands(func, add), (5, 6));
+    assert!(func.is_interned(five));
+}
+
+#[test]
+fn rewriting_addresses_doesnt_fold_into_interned_constants() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let ty = module.types.add(&[], &[]);
+    let mut builder = FunctionBuilder::new();
+    builder.set_interning(true);
+    let kind = LoadKind::I32 { atomic: false };
+    let arg = MemArg {
+        align: 4,
+        offset: 0xffff_fff0,
+    };
+    let mut loads = Vec::new();
+    let mut body = Vec::new();
+    for _ in 0..2 {
+        let address = builder.i32_const(8);
+        let load = builder.load(memory, kind, arg, address);
+        loads.push(load);
+        body.push(builder.drop(load));
+    }
+    let func = builder.finish(ty, vec![], body, &mut module);
+
+    // The offsets would overflow, so 0x20 is added to each address instead.
+    let func = local(&mut module, func);
+    func.rewrite_memargs(memory, |_, _| Rewrite::AddToOffset(0x20));
+    for load in loads {
+        let address = match func.get(load) {
+            Expr::Load(e) => e.address,
+            e => panic!("expected a load, found {:?}", e),
+        };
+        assert_eq!(operands(func, address), (8, 0x20));
+    }
+    module.emit_wasm().unwrap();
+}
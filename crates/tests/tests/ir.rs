@@ -9,10 +9,7 @@ fn run(wat_path: &Path) -> Result<(), failure::Error> {
 
     let local_funcs: Vec<_> = module
         .functions()
-        .filter(|f| match f.kind {
-            walrus::FunctionKind::Local(_) => true,
-            _ => false,
-        })
+        .filter(|f| matches!(f.kind, walrus::FunctionKind::Local(_)))
         .collect();
     assert_eq!(local_funcs.len(), 1);
 
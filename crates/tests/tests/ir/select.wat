@@ -10,8 +10,8 @@
 ;; NEXT:    (block
 ;; NEXT:      (select
 ;; NEXT:        (local.get 0)
-;; NEXT:        (const 1)
 ;; NEXT:        (const 2)
+;; NEXT:        (const 1)
 ;; NEXT:      )
 ;; NEXT:    )
 ;; NEXT:  )
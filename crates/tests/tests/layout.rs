@@ -0,0 +1,125 @@
+//! Tests for the byte layout reported by `Module::emit_wasm_with_layout`.
+
+use walrus::{ExportItem, FunctionBuilder, Module, ModuleConfig, ValType};
+
+#[test]
+fn layout_of_functions_data_imports_and_exports() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let imported = module.add_import_func("env", "f", ty);
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(0, b"active".to_vec());
+    let passive = module.data.add_passive(b"passive".to_vec());
+
+    let mut builder = FunctionBuilder::new();
+    let one = builder.i32_const(1);
+    let a = builder.finish(ty, vec![], vec![one], &mut module);
+    module.funcs.get_mut(a).name = Some("a".to_string());
+    let mut builder = FunctionBuilder::new();
+    let call = builder.call(imported, Box::new([]));
+    let b = builder.finish(ty, vec![], vec![call], &mut module);
+    let a_export = module.exports.add("a", ExportItem::Function(a));
+    let b_export = module.exports.add("b", ExportItem::Function(b));
+
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    assert_eq!(wasm, module.emit_wasm().unwrap());
+
+    let sections = layout.sections.iter().map(|s| s.range.len()).sum::<usize>();
+    assert_eq!(8 + sections, wasm.len());
+    let ids = layout.sections.iter().map(|s| s.id).collect::<Vec<_>>();
+    assert_eq!(ids, [1, 2, 3, 5, 7, 12, 10, 11, 0]);
+    assert_eq!(layout.sections[8].name.as_ref().unwrap(), "name");
+
+    let bodies = layout
+        .functions
+        .iter()
+        .map(|(id, range)| (*id, &wasm[range.clone()]))
+        .collect::<Vec<_>>();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies.contains(&(a, &[0x00, 0x41, 0x01, 0x0b][..])));
+    assert!(bodies.contains(&(b, &[0x00, 0x10, 0x00, 0x0b][..])));
+
+    let data = layout
+        .data
+        .iter()
+        .map(|(id, range)| (*id, &wasm[range.clone()]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        data,
+        [(None, &b"active"[..]), (Some(passive), &b"passive"[..])]
+    );
+
+    assert_eq!(layout.imports.len(), 1);
+    assert_eq!(
+        layout.imports[0].0,
+        module.imports.iter().next().unwrap().id()
+    );
+    let exports = layout
+        .exports
+        .iter()
+        .map(|(id, range)| (*id, &wasm[range.clone()]))
+        .collect::<Vec<_>>();
+    assert_eq!(exports[0], (a_export, &[0x01, b'a', 0x00, 0x01][..]));
+    assert_eq!(exports[1].0, b_export);
+}
+
+#[test]
+fn layout_of_imported_tags() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let ty = module.types.add(&[ValType::I32], &[]);
+    module.add_import_tag("env", "t", ty);
+    module.add_import_func("env", "f", ty);
+
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    let imports = layout
+        .imports
+        .iter()
+        .map(|(_, range)| &wasm[range.clone()])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        imports,
+        [
+            &[0x03, b'e', b'n', b'v', 0x01, b't', 0x04, 0x00, 0x00][..],
+            &[0x03, b'e', b'n', b'v', 0x01, b'f', 0x00, 0x00][..],
+        ]
+    );
+}
+
+#[test]
+fn layout_of_imported_typed_reference_globals() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let ty = module.types.add(&[], &[]);
+    for &nullable in &[true, false] {
+        let ty = ValType::Ref { nullable, ty };
+        module.add_import_global("env", "g", ty, false);
+    }
+    module.add_import_func("env", "f", ty);
+
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+    let imports = layout
+        .imports
+        .iter()
+        .map(|(_, range)| &wasm[range.clone()])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        imports,
+        [
+            &[0x03, b'e', b'n', b'v', 0x01, b'g', 0x03, 0x63, 0x00, 0x00][..],
+            &[0x03, b'e', b'n', b'v', 0x01, b'g', 0x03, 0x64, 0x00, 0x00][..],
+            &[0x03, b'e', b'n', b'v', 0x01, b'f', 0x00, 0x00][..],
+        ]
+    );
+}
@@ -0,0 +1,108 @@
+//! Tests for the public LEB128 encoding utilities.
+
+use walrus::encode::*;
+use walrus::{FunctionBuilder, Module, ValType};
+
+#[test]
+fn unsigned_round_trip() {
+    for &value in [0, 1, 63, 64, 127, 128, 300, 16_384, u32::MAX].iter() {
+        let mut buf = Vec::new();
+        leb128_u32(&mut buf, value);
+        assert_eq!(buf.len(), leb128_len(value), "{}", value);
+        assert_eq!(read_leb128_u32(&buf).unwrap(), (value, buf.len()));
+
+        let mut padded = Vec::new();
+        leb128_u32_padded(&mut padded, value);
+        assert_eq!(padded.len(), MAX_U32_LENGTH);
+        assert_eq!(read_leb128_u32(&padded).unwrap(), (value, MAX_U32_LENGTH));
+    }
+}
+
+#[test]
+fn signed_round_trip() {
+    let values = [
+        0,
+        1,
+        -1,
+        63,
+        64,
+        -64,
+        -65,
+        i64::from(i32::MAX),
+        i64::from(i32::MIN),
+        i64::MAX,
+        i64::MIN,
+    ];
+    for &value in values.iter() {
+        let mut buf = Vec::new();
+        leb128_i64(&mut buf, value);
+        assert_eq!(buf.len(), leb128_signed_len(value), "{}", value);
+        assert_eq!(read_leb128_i64(&buf).unwrap(), (value, buf.len()));
+
+        if let Some(value) = Some(value as i32).filter(|v| i64::from(*v) == value) {
+            let mut buf32 = Vec::new();
+            leb128_i32(&mut buf32, value);
+            assert_eq!(buf32, buf);
+            assert_eq!(read_leb128_i32(&buf32).unwrap(), (value, buf32.len()));
+        } else {
+            assert!(read_leb128_i32(&buf).is_err());
+        }
+    }
+}
+
+#[test]
+fn malformed() {
+    // Truncated.
+    assert!(read_leb128_u32(&[]).is_err());
+    assert!(read_leb128_u32(&[0x80, 0x80]).is_err());
+    assert!(read_leb128_i64(&[0xff]).is_err());
+    // Too long.
+    assert!(read_leb128_u32(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]).is_err());
+    // Too big.
+    assert!(read_leb128_u32(&[0xff, 0xff, 0xff, 0xff, 0x1f]).is_err());
+    assert!(
+        read_leb128_i64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err()
+    );
+}
+
+#[test]
+fn padded_at() {
+    let mut buf = vec![0xaa; 8];
+    leb128_u32_padded_at(&mut buf, 2, 624_485);
+    assert_eq!(buf, [0xaa, 0xaa, 0xe5, 0x8e, 0xa6, 0x80, 0x00, 0xaa]);
+}
+
+/// The sizes of emitted sections are padded LEB128s, and immediates are the
+/// shortest ones.
+#[test]
+fn matches_emitted_module() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I64]);
+    let mut builder = FunctionBuilder::new();
+    let a = builder.i32_const(-123_456);
+    let a = builder.drop(a);
+    let b = builder.i64_const(1 << 40);
+    builder.finish(ty, vec![], vec![a, b], &mut module);
+    let (wasm, layout) = module.emit_wasm_with_layout().unwrap();
+
+    for section in layout.sections.iter() {
+        let (size, len) = read_leb128_u32(&wasm[section.range.start + 1..]).unwrap();
+        assert_eq!(len, MAX_U32_LENGTH);
+        assert_eq!(size as usize, section.payload.len());
+        assert_eq!(section.payload.start, section.range.start + 1 + len);
+
+        let mut padded = Vec::new();
+        leb128_u32_padded(&mut padded, size);
+        assert_eq!(
+            padded,
+            &wasm[section.range.start + 1..section.payload.start]
+        );
+    }
+
+    let mut expected = vec![0x41];
+    leb128_i32(&mut expected, -123_456);
+    expected.push(0x1a);
+    expected.push(0x42);
+    leb128_i64(&mut expected, 1 << 40);
+    assert!(wasm.windows(expected.len()).any(|w| w == &expected[..]));
+}
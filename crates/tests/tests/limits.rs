@@ -0,0 +1,177 @@
+//! Tests for function metrics and checking modules against engine limits.
+
+use rayon::prelude::*;
+use walrus::ir::*;
+use walrus::{EngineLimits, FunctionBuilder, FunctionId, FunctionKind, FunctionMetrics};
+use walrus::{Limit, LimitViolation, Module, ValType};
+
+/// `unreachable` inside `depth` nested blocks.
+fn nested(builder: &mut FunctionBuilder, depth: u32) -> ExprId {
+    let mut expr = builder.unreachable();
+    for _ in 0..depth {
+        let mut block = builder.block(Box::new([]), Box::new([]));
+        block.expr(expr);
+        expr = block.id().into();
+    }
+    expr
+}
+
+/// A function named `name` with nested blocks, and `locals` locals after its
+/// `params` parameters.
+fn function(
+    module: &mut Module,
+    name: &str,
+    params: usize,
+    locals: usize,
+    depth: u32,
+) -> FunctionId {
+    let ty = module.types.add(&vec![ValType::I32; params], &[]);
+    let args = (0..params)
+        .map(|_| module.locals.add(ValType::I32))
+        .collect();
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    for _ in 0..locals {
+        let local = module.locals.add(ValType::I64);
+        let value = builder.i64_const(0);
+        exprs.push(builder.local_set(local, value));
+    }
+    exprs.push(nested(&mut builder, depth));
+    let func = builder.finish(ty, args, exprs, module);
+    module.funcs.get_mut(func).name = Some(name.to_string());
+    func
+}
+
+fn metrics(module: &Module, func: FunctionId) -> FunctionMetrics {
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l.metrics(),
+        _ => panic!("not a local function"),
+    }
+}
+
+#[test]
+fn metrics_of_nested_blocks() {
+    let mut module = Module::default();
+    let func = function(&mut module, "f", 0, 0, 3);
+    assert_eq!(
+        metrics(&module, func),
+        FunctionMetrics {
+            // The entry block, three blocks and an `unreachable`.
+            exprs: 5,
+            max_nesting_depth: 3,
+            calls: 0,
+            memory_accesses: 0,
+            consts: 0,
+            control_flow: 4,
+            locals: 0,
+            used_locals: vec![],
+            // No locals, three `block`s with their `end`s, an `unreachable`
+            // and the body's `end`.
+            estimated_size: 12,
+        }
+    );
+
+    let func = function(&mut module, "g", 2, 3, 0);
+    let metrics = metrics(&module, func);
+    assert_eq!(metrics.max_nesting_depth, 0);
+    assert_eq!(metrics.locals, 5);
+    // The parameters aren't used.
+    assert_eq!(metrics.used_locals.len(), 3);
+    assert_eq!(metrics.consts, 3);
+}
+
+#[test]
+fn metrics_of_a_fixture() {
+    let wasm = walrus_tests_utils::wat2wasm("tests/metrics/calls_and_memory.wat".as_ref());
+    let module = Module::from_buffer(&wasm).unwrap();
+    let func = module.exports.get_func("g").unwrap();
+    let metrics = metrics(&module, func);
+
+    // The entry block and `block`, the `local.set` of a load, the store of a
+    // constant, and the `call_indirect` of a `call` and a constant, with
+    // three `local.get`s between them.
+    assert_eq!(metrics.exprs, 12);
+    assert_eq!(metrics.max_nesting_depth, 1);
+    assert_eq!(metrics.calls, 2);
+    assert_eq!(metrics.memory_accesses, 2);
+    assert_eq!(metrics.consts, 2);
+    assert_eq!(metrics.control_flow, 1);
+    assert_eq!(metrics.locals, 2);
+    assert_eq!(metrics.used_locals.len(), 2);
+
+    let all = module
+        .funcs
+        .par_iter_local()
+        .map(|(_, f)| f.metrics().exprs)
+        .sum::<u64>();
+    assert_eq!(all, 12);
+}
+
+#[test]
+fn small_modules_are_within_the_default_limits() {
+    let mut module = Module::default();
+    function(&mut module, "f", 2, 3, 10);
+    assert!(module.check_limits(&EngineLimits::default()).is_empty());
+}
+
+#[test]
+fn one_over_a_limit_is_one_violation() {
+    let mut module = Module::default();
+    function(&mut module, "shallow", 0, 0, 2);
+    let deep = function(&mut module, "deep", 0, 0, 3);
+    let limits = EngineLimits {
+        max_nesting_depth: 2,
+        ..EngineLimits::default()
+    };
+    let violations = module.check_limits(&limits);
+    assert_eq!(
+        violations,
+        [LimitViolation {
+            function: Some(deep),
+            name: Some("deep".to_string()),
+            limit: Limit::NestingDepth,
+            value: 3,
+            max: 2,
+        }]
+    );
+    assert_eq!(
+        violations[0].to_string(),
+        "function `deep` exceeds the limit on block nesting depth: 3 > 2"
+    );
+
+    let mut module = Module::default();
+    function(&mut module, "few", 2, 1, 0);
+    let many = function(&mut module, "many", 2, 2, 0);
+    let limits = EngineLimits {
+        max_function_locals: 3,
+        ..EngineLimits::default()
+    };
+    let violations = module.check_limits(&limits);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].function, Some(many));
+    assert_eq!(violations[0].limit, Limit::FunctionLocals);
+    assert_eq!(violations[0].value, 4);
+
+    let mut module = Module::default();
+    let wide = function(&mut module, "wide", 3, 0, 0);
+    let limits = EngineLimits {
+        max_function_params: 2,
+        ..EngineLimits::default()
+    };
+    let violations = module.check_limits(&limits);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].function, Some(wide));
+    assert_eq!(violations[0].limit, Limit::FunctionParams);
+
+    let limits = EngineLimits {
+        max_functions: 0,
+        ..EngineLimits::default()
+    };
+    let violations = module.check_limits(&limits);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].function, None);
+    assert_eq!(
+        violations[0].to_string(),
+        "module exceeds the limit on functions: 1 > 0"
+    );
+}
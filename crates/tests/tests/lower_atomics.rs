@@ -0,0 +1,397 @@
+//! Tests for lowering atomic memory operations into plain ones.
+
+use std::fs;
+use walrus::ir::*;
+use walrus::passes::{lower_atomics, lower_atomics_with, validate};
+use walrus::passes::{LowerAtomicsOptions, WaitResult};
+use walrus::{ExportItem, FunctionBuilder, LocalFunction, MemoryId, Module, ValType};
+use walrus_tests_utils::wasm_interp;
+
+const INITIAL: i64 = 0x0123_4567_89ab_cdef;
+const OPERAND: i64 = 0x1111_2222_3333_4478;
+
+const WIDTHS: [AtomicWidth; 7] = [
+    AtomicWidth::I32,
+    AtomicWidth::I32_8,
+    AtomicWidth::I32_16,
+    AtomicWidth::I64,
+    AtomicWidth::I64_8,
+    AtomicWidth::I64_16,
+    AtomicWidth::I64_32,
+];
+
+fn arg(width: u32) -> MemArg {
+    MemArg {
+        align: width,
+        offset: 0,
+    }
+}
+
+/// A module with a shared memory, and one exported function per body built by
+/// `body`.
+fn module(
+    n: usize,
+    mut body: impl FnMut(&mut FunctionBuilder, MemoryId, usize) -> (Vec<ExprId>, ValType),
+) -> Module {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(true, 1, Some(1));
+    for i in 0..n {
+        let mut builder = FunctionBuilder::new();
+        let (exprs, ty) = body(&mut builder, memory, i);
+        let ty = module.types.add(&[], &[ty]);
+        let func = builder.finish(ty, vec![], exprs, &mut module);
+        module
+            .exports
+            .add(&format!("f{}", i), ExportItem::Function(func));
+    }
+    module
+}
+
+/// Build a module with one function, lower it, and check that the result
+/// validates and round-trips.
+fn lowered(
+    options: &LowerAtomicsOptions,
+    ty: ValType,
+    body: impl FnOnce(&mut FunctionBuilder, MemoryId) -> Vec<ExprId>,
+) -> Module {
+    let mut body = Some(body);
+    let mut module = module(1, |builder, memory, _| {
+        ((body.take().unwrap())(builder, memory), ty)
+    });
+    assert!(lower_atomics_with(&mut module, options) > 0);
+    validate::run(&module).unwrap();
+    let memory = module.memories.iter().next().unwrap();
+    assert!(!memory.shared);
+    assert_eq!(memory.maximum, Some(1));
+    Module::from_buffer(&module.emit_wasm().unwrap()).unwrap();
+    module
+}
+
+/// The function in a module built by `lowered`, and its body.
+fn body(module: &Module) -> (&LocalFunction, Vec<ExprId>) {
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let exprs = func.block(func.entry_block()).exprs.clone();
+    (func, exprs)
+}
+
+fn with_side_effects(func: &LocalFunction, id: ExprId) -> &WithSideEffects {
+    match func.get(id) {
+        Expr::WithSideEffects(e) => e,
+        e => panic!("expected a lowered operation, found {:?}", e),
+    }
+}
+
+#[test]
+fn rmw_becomes_load_and_store() {
+    let module = lowered(&Default::default(), ValType::I32, |builder, memory| {
+        let address = builder.i32_const(0);
+        let value = builder.i32_const(1);
+        let width = AtomicWidth::I32_8;
+        vec![builder.atomic_rmw(memory, AtomicOp::Add, width, arg(1), address, value)]
+    });
+    let (func, exprs) = body(&module);
+    let e = with_side_effects(func, exprs[0]);
+
+    assert_eq!(e.before.len(), 2);
+    for set in e.before.iter() {
+        match func.get(*set) {
+            Expr::LocalSet(_) => {}
+            _ => panic!("expected the operands to be stored in locals"),
+        }
+    }
+    match func.get(e.value) {
+        Expr::LocalTee(tee) => match func.get(tee.value) {
+            Expr::Load(Load {
+                kind:
+                    LoadKind::I32_8 {
+                        kind: ExtendedLoad::ZeroExtend,
+                    },
+                ..
+            }) => {}
+            _ => panic!("expected a plain zero-extending load"),
+        },
+        _ => panic!("expected the old value to be kept in a local"),
+    }
+    assert_eq!(e.after.len(), 1);
+    match func.get(e.after[0]) {
+        Expr::Store(store) => {
+            assert!(!store.kind.atomic());
+            assert_eq!(store.kind.width(), 1);
+            match func.get(store.value) {
+                Expr::Binop(Binop {
+                    op: BinaryOp::I32Add,
+                    ..
+                }) => {}
+                _ => panic!("expected the new value to be computed"),
+            }
+        }
+        _ => panic!("expected a store"),
+    }
+}
+
+#[test]
+fn xchg_stores_the_operand() {
+    let module = lowered(&Default::default(), ValType::I64, |builder, memory| {
+        let address = builder.i32_const(0);
+        let value = builder.i64_const(1);
+        let width = AtomicWidth::I64;
+        vec![builder.atomic_rmw(memory, AtomicOp::Xchg, width, arg(8), address, value)]
+    });
+    let (func, exprs) = body(&module);
+    let e = with_side_effects(func, exprs[0]);
+    match func.get(e.after[0]) {
+        Expr::Store(store) => match func.get(store.value) {
+            Expr::LocalGet(_) => {}
+            _ => panic!("expected the operand to be stored as is"),
+        },
+        _ => panic!("expected a store"),
+    }
+}
+
+#[test]
+fn cmpxchg_becomes_conditional_store() {
+    let module = lowered(&Default::default(), ValType::I64, |builder, memory| {
+        let address = builder.i32_const(0);
+        let expected = builder.i64_const(1);
+        let replacement = builder.i64_const(2);
+        let width = AtomicWidth::I64_32;
+        vec![builder.cmpxchg(memory, width, arg(4), address, expected, replacement)]
+    });
+    let (func, exprs) = body(&module);
+    let e = with_side_effects(func, exprs[0]);
+
+    assert_eq!(e.before.len(), 3);
+    match func.get(e.value) {
+        Expr::LocalTee(_) => {}
+        _ => panic!("expected the old value to be kept in a local"),
+    }
+    assert_eq!(e.after.len(), 1);
+    match func.get(e.after[0]) {
+        Expr::IfElse(swap) => {
+            match func.get(swap.condition) {
+                Expr::Binop(Binop {
+                    op: BinaryOp::I64Eq,
+                    rhs,
+                    ..
+                }) => match func.get(*rhs) {
+                    Expr::Binop(Binop {
+                        op: BinaryOp::I64And,
+                        ..
+                    }) => {}
+                    _ => panic!("expected the expected value to be wrapped"),
+                },
+                _ => panic!("expected a comparison"),
+            }
+            let stores = &func.block(swap.consequent).exprs;
+            assert_eq!(stores.len(), 1);
+            match func.get(stores[0]) {
+                Expr::Store(store) => assert!(!store.kind.atomic()),
+                _ => panic!("expected a store"),
+            }
+            assert!(func.block(swap.alternative).exprs.is_empty());
+        }
+        _ => panic!("expected a conditional store"),
+    }
+}
+
+#[test]
+fn notify_wakes_nobody() {
+    let module = lowered(&Default::default(), ValType::I32, |builder, memory| {
+        let address = builder.i32_const(0);
+        let count = builder.i32_const(1);
+        vec![builder.atomic_notify(memory, arg(4), address, count)]
+    });
+    let (func, exprs) = body(&module);
+    let e = with_side_effects(func, exprs[0]);
+    assert_eq!(e.before.len(), 2);
+    match func.get(e.value) {
+        Expr::Const(Const {
+            value: Value::I32(0),
+        }) => {}
+        _ => panic!("expected zero"),
+    }
+}
+
+#[test]
+fn wait_results() {
+    for &(wait, result) in &[
+        (WaitResult::NotEqual, 1),
+        (WaitResult::TimedOut, 2),
+        (WaitResult::Compare, 0),
+    ] {
+        let options = LowerAtomicsOptions { wait };
+        let module = lowered(&options, ValType::I32, |builder, memory| {
+            let address = builder.i32_const(0);
+            let expected = builder.i64_const(1);
+            let timeout = builder.i64_const(-1);
+            vec![builder.atomic_wait(memory, arg(8), address, expected, timeout, true)]
+        });
+        let (func, exprs) = body(&module);
+        let e = with_side_effects(func, exprs[0]);
+        assert_eq!(e.before.len(), 3);
+        match func.get(e.value) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => assert_eq!(*n, result),
+            Expr::IfElse(compare) if wait == WaitResult::Compare => {
+                match func.get(compare.condition) {
+                    Expr::Binop(Binop {
+                        op: BinaryOp::I64Ne,
+                        lhs,
+                        ..
+                    }) => match func.get(*lhs) {
+                        Expr::Load(load) => assert!(!load.kind.atomic()),
+                        _ => panic!("expected a load"),
+                    },
+                    _ => panic!("expected a comparison"),
+                }
+            }
+            _ => panic!("unexpected result for {:?}", wait),
+        }
+    }
+}
+
+#[test]
+fn atomic_loads_and_stores_become_plain() {
+    let module = lowered(&Default::default(), ValType::I64, |builder, memory| {
+        let address = builder.i32_const(0);
+        let value = builder.i32_const(1);
+        let kind = StoreKind::I32_16 { atomic: true };
+        let store = builder.store(memory, kind, arg(2), address, value);
+        let address = builder.i32_const(0);
+        let kind = LoadKind::I64_16 {
+            kind: ExtendedLoad::ZeroExtendAtomic,
+        };
+        vec![store, builder.load(memory, kind, arg(2), address)]
+    });
+    let (func, exprs) = body(&module);
+    match func.get(exprs[0]) {
+        Expr::Store(store) => assert!(!store.kind.atomic()),
+        _ => panic!("expected a store"),
+    }
+    match func.get(exprs[1]) {
+        Expr::Load(load) => assert!(!load.kind.atomic()),
+        _ => panic!("expected a load"),
+    }
+}
+
+#[test]
+fn nested_operations_get_their_own_locals() {
+    let module = lowered(&Default::default(), ValType::I32, |builder, memory| {
+        let rmw = |builder: &mut FunctionBuilder, value| {
+            let address = builder.i32_const(0);
+            let width = AtomicWidth::I32;
+            builder.atomic_rmw(memory, AtomicOp::Add, width, arg(4), address, value)
+        };
+        let value = builder.i32_const(1);
+        let inner = rmw(builder, value);
+        let outer = rmw(builder, inner);
+        let value = builder.i32_const(1);
+        let sibling = rmw(builder, value);
+        let sibling = builder.drop(sibling);
+        vec![sibling, outer]
+    });
+    // The address, operand and old value of each of the two nested
+    // operations, while the sibling's locals are reused.
+    assert_eq!(module.locals.iter().count(), 6);
+}
+
+#[test]
+fn lowered_operations_are_equivalent() {
+    // Each width, and either a read-modify-write operator or the expected
+    // value of a compare-exchange.
+    let mut cases = Vec::new();
+    for &width in WIDTHS.iter() {
+        for &op in &[
+            AtomicOp::Add,
+            AtomicOp::Sub,
+            AtomicOp::And,
+            AtomicOp::Or,
+            AtomicOp::Xor,
+            AtomicOp::Xchg,
+        ] {
+            cases.push((width, Ok(op)));
+        }
+        let bits = width.bytes() * 8;
+        let low = if bits == 64 {
+            INITIAL
+        } else {
+            INITIAL & ((1 << bits) - 1)
+        };
+        // Matches once wrapped to the width of the operation.
+        let high = if bits == 32 && width.result_type() == ValType::I32 {
+            0
+        } else {
+            1i64.checked_shl(bits).unwrap_or(0)
+        };
+        cases.push((width, Err(low | high)));
+        // Doesn't match.
+        cases.push((width, Err(low ^ 1)));
+    }
+
+    // Each function stores `INITIAL` at address 0, performs its operation
+    // there, stores the old value at address 8, and returns a mix of both.
+    let build = || {
+        module(cases.len(), |builder, memory, i| {
+            let (width, op) = cases[i];
+            let ty = width.result_type();
+            let constant = |builder: &mut FunctionBuilder, n: i64| match ty {
+                ValType::I32 => builder.i32_const(n as i32),
+                _ => builder.i64_const(n),
+            };
+            let i64_store = StoreKind::I64 { atomic: false };
+            let i64_load = LoadKind::I64 { atomic: false };
+
+            let address = builder.i32_const(0);
+            let value = builder.i64_const(INITIAL);
+            let init = builder.store(memory, i64_store, arg(8), address, value);
+            let address = builder.i32_const(8);
+            let value = builder.i64_const(0);
+            let clear = builder.store(memory, i64_store, arg(8), address, value);
+
+            let address = builder.i32_const(0);
+            let memarg = arg(width.bytes());
+            let atomic = match op {
+                Ok(op) => {
+                    let value = constant(builder, OPERAND);
+                    builder.atomic_rmw(memory, op, width, memarg, address, value)
+                }
+                Err(expected) => {
+                    let expected = constant(builder, expected);
+                    let replacement = constant(builder, OPERAND);
+                    builder.cmpxchg(memory, width, memarg, address, expected, replacement)
+                }
+            };
+            let kind = match ty {
+                ValType::I32 => StoreKind::I32 { atomic: false },
+                _ => i64_store,
+            };
+            let address = builder.i32_const(8);
+            let old = builder.store(memory, kind, arg(4), address, atomic);
+
+            let address = builder.i32_const(0);
+            let new = builder.load(memory, i64_load, arg(8), address);
+            let address = builder.i32_const(8);
+            let old_value = builder.load(memory, i64_load, arg(8), address);
+            let by = builder.i64_const(32);
+            let rotated = builder.binop(BinaryOp::I64Rotl, old_value, by);
+            let result = builder.binop(BinaryOp::I64Xor, new, rotated);
+            (vec![init, clear, old, result], ValType::I64)
+        })
+    };
+
+    let original = build();
+    let mut lowered = build();
+    assert_eq!(lower_atomics(&mut lowered), cases.len());
+    validate::run(&lowered).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut outputs = Vec::new();
+    for (name, m) in &[("original.wasm", original), ("lowered.wasm", lowered)] {
+        let path = dir.path().join(name);
+        fs::write(&path, m.emit_wasm().unwrap()).unwrap();
+        outputs.push(wasm_interp(&path));
+    }
+    assert_eq!(outputs[0].lines().count(), cases.len());
+    assert_eq!(outputs[0], outputs[1]);
+}
@@ -0,0 +1,202 @@
+//! Tests for lowering the bulk memory operations.
+
+use std::fs;
+use walrus::ir::*;
+use walrus::passes::{lower_bulk_memory, validate, LoweringStyle};
+use walrus::{DataId, ExportItem, FunctionBuilder, MemoryId, Module, ValType};
+use walrus_tests_utils::{wasm2wat, wasm_interp};
+
+/// The bulk memory operation performed by a test function.
+#[derive(Copy, Clone)]
+enum Op {
+    Init(usize, i32, i32, i32),
+    Copy(i32, i32, i32),
+    Fill(i32, i32, i32),
+    DropAndInit(usize, usize, i32, i32, i32),
+    Nothing,
+}
+
+/// More than fits in any memory, wherever it starts.
+const HUGE: i32 = 0x7fff_0000;
+
+/// Each function performs its operation and then returns a checksum of the
+/// memory starting at an address.
+const CASES: &[(Op, i32)] = &[
+    // Part of a segment.
+    (Op::Init(0, 100, 3, 20), 64),
+    // Overlapping ranges, with the destination after the source.
+    (Op::Copy(101, 100, 16), 64),
+    // Overlapping ranges, with the destination before the source.
+    (Op::Copy(96, 104, 20), 64),
+    // Only the low byte of the value is written.
+    (Op::Fill(110, 0x1ab, 7), 64),
+    // Nothing, right at the end of a segment.
+    (Op::Init(1, 0, 5, 0), 0),
+    // Nothing, right at the end of memory.
+    (Op::Copy(65536, 0, 0), 0),
+    (Op::Fill(65536, 0, 0), 0),
+    // Lowered `data.drop`s don't stop later initializations.
+    (Op::DropAndInit(1, 0, 120, 0, 8), 64),
+    // Out of bounds, which must trap before writing anything.
+    (Op::Copy(65530, 0, HUGE), 0),
+    (Op::Fill(65530, 0xff, HUGE), 0),
+    (Op::Init(0, -16, 0, 16), 0),
+    (Op::Init(0, 0, 30, 16), 0),
+    (Op::Nothing, 65536 - 128),
+];
+
+/// A module with one page of memory, an active segment, two passive segments,
+/// and a function for each case.
+fn module(
+    mut op: impl FnMut(&mut FunctionBuilder, MemoryId, &[DataId], Op) -> Vec<ExprId>,
+) -> Module {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let active = (0..64u32).map(|i| (i * 7 + 3) as u8).collect();
+    module.memories.get_mut(memory).data.add_absolute(0, active);
+    let data = [
+        module
+            .data
+            .add((0..40u32).map(|i| (i * 13 + 1) as u8).collect()),
+        module.data.add(vec![0xaa; 5]),
+    ];
+
+    let ty = module.types.add(&[], &[ValType::I64]);
+    for (i, &(case, start)) in CASES.iter().enumerate() {
+        let mut builder = FunctionBuilder::new();
+        let mut exprs = op(&mut builder, memory, &data, case);
+        exprs.push(checksum(&mut builder, memory, start));
+        let func = builder.finish(ty, vec![], exprs, &mut module);
+        module
+            .exports
+            .add(&format!("f{}", i), ExportItem::Function(func));
+    }
+    module
+}
+
+/// Mix the 16 words of memory starting at `start` together.
+fn checksum(builder: &mut FunctionBuilder, memory: MemoryId, start: i32) -> ExprId {
+    let kind = LoadKind::I64 { atomic: false };
+    let arg = MemArg {
+        align: 8,
+        offset: 0,
+    };
+    let address = builder.i32_const(start);
+    let mut sum = builder.load(memory, kind, arg, address);
+    for word in 1..16 {
+        let by = builder.i64_const(7);
+        let rotated = builder.binop(BinaryOp::I64Rotl, sum, by);
+        let address = builder.i32_const(start + word * 8);
+        let value = builder.load(memory, kind, arg, address);
+        sum = builder.binop(BinaryOp::I64Xor, rotated, value);
+    }
+    sum
+}
+
+/// The case's bulk memory operation.
+fn bulk(builder: &mut FunctionBuilder, memory: MemoryId, data: &[DataId], op: Op) -> Vec<ExprId> {
+    let consts = |builder: &mut FunctionBuilder, a, b, c| {
+        (
+            builder.i32_const(a),
+            builder.i32_const(b),
+            builder.i32_const(c),
+        )
+    };
+    match op {
+        Op::Init(segment, dst, offset, n) => {
+            let (dst, offset, n) = consts(builder, dst, offset, n);
+            vec![builder.memory_init(memory, data[segment], dst, offset, n)]
+        }
+        Op::Copy(dst, src, n) => {
+            let (dst, src, n) = consts(builder, dst, src, n);
+            vec![builder.memory_copy(memory, memory, dst, src, n)]
+        }
+        Op::Fill(dst, value, n) => {
+            let (dst, value, n) = consts(builder, dst, value, n);
+            vec![builder.memory_fill(memory, dst, value, n)]
+        }
+        Op::DropAndInit(dropped, segment, dst, offset, n) => {
+            let drop = builder.data_drop(data[dropped]);
+            let mut exprs = bulk(builder, memory, data, Op::Init(segment, dst, offset, n));
+            exprs.insert(0, drop);
+            exprs
+        }
+        Op::Nothing => Vec::new(),
+    }
+}
+
+/// Run every exported function.
+fn run(name: &str, module: &Module) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(name);
+    fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+    wasm_interp(&path)
+}
+
+#[test]
+fn lowered_operations_are_equivalent() {
+    // Out of bounds operations are replaced with `unreachable`, so that the
+    // expected results don't depend on whether an engine writes part of the
+    // range before trapping, and so that the traps have the same message as
+    // those in the generated helpers.
+    let expected = run(
+        "expected.wasm",
+        &module(|builder, memory, data, op| match op {
+            Op::Copy(_, _, HUGE) | Op::Fill(_, _, HUGE) => vec![builder.unreachable()],
+            Op::Init(0, -16, ..) | Op::Init(0, 0, 30, _) => vec![builder.unreachable()],
+            _ => bulk(builder, memory, data, op),
+        }),
+    );
+    assert_eq!(expected.lines().count(), CASES.len());
+
+    for style in &[LoweringStyle::ActiveSegments, LoweringStyle::Stores] {
+        let mut module = module(bulk);
+        let funcs = module.funcs.iter().count();
+        // Every case has one operation, except for one with none and one with
+        // two.
+        assert_eq!(lower_bulk_memory(&mut module, *style).unwrap(), CASES.len());
+        validate::run(&module).unwrap();
+
+        // One copy, one fill and one initialization of each segment, shared
+        // between all the functions using them.
+        assert_eq!(module.funcs.iter().count(), funcs + 4);
+        assert_eq!(module.data.iter().count(), 0);
+        let memory = module.memories.iter().next().unwrap();
+        match style {
+            LoweringStyle::ActiveSegments => {
+                assert_eq!(memory.initial, 2);
+                assert_eq!(memory.data.iter().count(), 3);
+            }
+            LoweringStyle::Stores => {
+                assert_eq!(memory.initial, 1);
+                assert_eq!(memory.data.iter().count(), 1);
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lowered.wasm");
+        fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+        let wat = wasm2wat(&path);
+        for op in &["memory.copy", "memory.fill", "memory.init", "data.drop"] {
+            assert!(!wat.contains(op), "{} left in {:?} lowering", op, style);
+        }
+
+        let lowered = run("lowered.wasm", &module);
+        for (i, (lowered, expected)) in lowered.lines().zip(expected.lines()).enumerate() {
+            assert_eq!(lowered, expected, "case {} in {:?} lowering", i, style);
+        }
+    }
+}
+
+#[test]
+fn segments_must_fit_in_memory() {
+    let mut module = module(bulk);
+    let memory = module.memories.iter().next().unwrap().id();
+    module.memories.get_mut(memory).maximum = Some(1);
+    assert!(lower_bulk_memory(&mut module, LoweringStyle::ActiveSegments).is_err());
+    assert_eq!(module.data.iter().count(), 2);
+    assert_eq!(module.memories.get(memory).initial, 1);
+
+    lower_bulk_memory(&mut module, LoweringStyle::Stores).unwrap();
+    validate::run(&module).unwrap();
+}
@@ -0,0 +1,374 @@
+//! Tests for lowering functions and blocks with multiple results.
+
+use std::fs;
+use walrus::ir::*;
+use walrus::passes::{lower_multi_value, lower_multi_value_with, validate};
+use walrus::passes::{Boundary, ExtraResults, LowerMultiValueOptions};
+use walrus::ValType::{F64, I32, I64};
+use walrus::{ExportItem, FunctionBuilder, FunctionId, LocalId, Module, ModuleConfig};
+use walrus::{GlobalKind, InitExpr, ValType, WasmFeatures};
+use walrus_tests_utils::wasm_interp;
+
+/// Add a function whose body is built by `body`, given its parameters.
+fn func(
+    module: &mut Module,
+    params: &[ValType],
+    results: &[ValType],
+    body: impl FnOnce(&mut FunctionBuilder, &[LocalId]) -> Vec<ExprId>,
+) -> FunctionId {
+    let args: Vec<_> = params.iter().map(|ty| module.locals.add(*ty)).collect();
+    let ty = module.types.add(params, results);
+    let mut builder = FunctionBuilder::new();
+    let exprs = body(&mut builder, &args);
+    builder.finish(ty, args, exprs, module)
+}
+
+/// `(a, b) -> a * 1000 + b`, with `b` converted to an `i32`.
+fn pack(module: &mut Module, ty: ValType) -> FunctionId {
+    func(module, &[I32, ty], &[I32], |b, args| {
+        let a = b.local_get(args[0]);
+        let k = b.i32_const(1000);
+        let a = b.binop(BinaryOp::I32Mul, a, k);
+        let mut c = b.local_get(args[1]);
+        match ty {
+            I64 => c = b.unop(UnaryOp::I32WrapI64, c),
+            F64 => c = b.unop(UnaryOp::I32TruncSF64, c),
+            _ => {}
+        }
+        vec![b.binop(BinaryOp::I32Add, a, c)]
+    })
+}
+
+/// Export a function passing the results of `f`, called with `args`, to
+/// `consumer`.
+fn export(module: &mut Module, name: &str, f: FunctionId, args: &[i32], consumer: FunctionId) {
+    let wrapper = func(module, &[], &[I32], |b, _| {
+        let args: Vec<_> = args.iter().map(|arg| b.i32_const(*arg)).collect();
+        let call = b.call(f, args.into_boxed_slice());
+        vec![b.call(consumer, Box::new([call]))]
+    });
+    module.exports.add(name, ExportItem::Function(wrapper));
+}
+
+/// A module with a memory and a function for each way of producing multiple
+/// values, along with the exports and the results they should return.
+fn module() -> (Module, Vec<(&'static str, i32)>) {
+    let mut module = Module::default();
+    module.memories.add_local(false, 1, None);
+    let pack_i32 = pack(&mut module, I32);
+    let pack_i64 = pack(&mut module, I64);
+    let pack_f64 = pack(&mut module, F64);
+    let mut expected = Vec::new();
+
+    // (x + 1, x, x * 3), combined as a + b * 10 + c * 100.
+    let three = func(&mut module, &[I32], &[I32, I64, I32], |b, args| {
+        let x = b.local_get(args[0]);
+        let one = b.i32_const(1);
+        let a = b.binop(BinaryOp::I32Add, x, one);
+        let x = b.local_get(args[0]);
+        let c = b.unop(UnaryOp::I64ExtendUI32, x);
+        let x = b.local_get(args[0]);
+        let three = b.i32_const(3);
+        let d = b.binop(BinaryOp::I32Mul, x, three);
+        vec![a, c, d]
+    });
+    let combine = func(&mut module, &[I32, I64, I32], &[I32], |b, args| {
+        let a = b.local_get(args[0]);
+        let c = b.local_get(args[1]);
+        let c = b.unop(UnaryOp::I32WrapI64, c);
+        let ten = b.i32_const(10);
+        let c = b.binop(BinaryOp::I32Mul, c, ten);
+        let d = b.local_get(args[2]);
+        let hundred = b.i32_const(100);
+        let d = b.binop(BinaryOp::I32Mul, d, hundred);
+        let sum = b.binop(BinaryOp::I32Add, a, c);
+        vec![b.binop(BinaryOp::I32Add, sum, d)]
+    });
+    export(&mut module, "three", three, &[4], combine);
+    expected.push(("three", 1245));
+
+    let swap = func(&mut module, &[I32, I32], &[I32, I32], |b, args| {
+        vec![b.local_get(args[1]), b.local_get(args[0])]
+    });
+
+    // (100, 200) through a branch out of an `if` when x is 0, and (x, x + 1)
+    // otherwise.
+    let branch = func(&mut module, &[I32], &[I32, I32], |b, args| {
+        let mut outer = b.block(Box::new([]), Box::new([I32, I32]));
+        let id = outer.id();
+        let x = outer.local_get(args[0]);
+        let zero = outer.unop(UnaryOp::I32Eqz, x);
+        let consequent = {
+            let mut then = outer.if_else_block(Box::new([]), Box::new([]));
+            let a = then.i32_const(100);
+            let c = then.i32_const(200);
+            let br = then.br(id, Box::new([a, c]));
+            then.expr(br);
+            then.id()
+        };
+        let alternative = outer.if_else_block(Box::new([]), Box::new([])).id();
+        let if_else = outer.if_else(zero, consequent, alternative);
+        outer.expr(if_else);
+        let x = outer.local_get(args[0]);
+        outer.expr(x);
+        let x = outer.local_get(args[0]);
+        let one = outer.i32_const(1);
+        let next = outer.binop(BinaryOp::I32Add, x, one);
+        outer.expr(next);
+        vec![id.into()]
+    });
+    export(&mut module, "branch_taken", branch, &[0], pack_i32);
+    export(&mut module, "branch_not_taken", branch, &[5], pack_i32);
+    expected.push(("branch_taken", 100_200));
+    expected.push(("branch_not_taken", 5006));
+
+    // (x, 1) when x > 10, and (1, x) otherwise, since a `br_if` which isn't
+    // taken leaves its arguments for `swap`.
+    let br_if = func(&mut module, &[I32], &[I32, I32], |b, args| {
+        let mut block = b.block(Box::new([]), Box::new([I32, I32]));
+        let id = block.id();
+        let x = block.local_get(args[0]);
+        let ten = block.i32_const(10);
+        let condition = block.binop(BinaryOp::I32GtS, x, ten);
+        let x = block.local_get(args[0]);
+        let one = block.i32_const(1);
+        let br_if = block.br_if(condition, id, Box::new([x, one]));
+        let swapped = block.call(swap, Box::new([br_if]));
+        block.expr(swapped);
+        vec![id.into()]
+    });
+    export(&mut module, "br_if_taken", br_if, &[20], pack_i32);
+    export(&mut module, "br_if_not_taken", br_if, &[3], pack_i32);
+    expected.push(("br_if_taken", 20001));
+    expected.push(("br_if_not_taken", 1003));
+
+    // (x, x + 100) out of the outer block when x isn't 1, and swapped out of
+    // the inner one when it is.
+    let br_table = func(&mut module, &[I32], &[I32, I32], |b, args| {
+        let mut outer = b.block(Box::new([]), Box::new([I32, I32]));
+        let outer_id = outer.id();
+        let inner = {
+            let mut inner = outer.block(Box::new([]), Box::new([I32, I32]));
+            let inner_id = inner.id();
+            let x = inner.local_get(args[0]);
+            let y = inner.local_get(args[0]);
+            let hundred = inner.i32_const(100);
+            let y = inner.binop(BinaryOp::I32Add, y, hundred);
+            let which = inner.local_get(args[0]);
+            let blocks = Box::new([outer_id, inner_id]);
+            let br_table = inner.br_table(which, blocks, outer_id, Box::new([x, y]));
+            inner.expr(br_table);
+            inner_id
+        };
+        let swapped = outer.call(swap, Box::new([ExprId::from(inner)]));
+        outer.expr(swapped);
+        vec![outer_id.into()]
+    });
+    export(&mut module, "br_table_0", br_table, &[0], pack_i32);
+    export(&mut module, "br_table_1", br_table, &[1], pack_i32);
+    export(&mut module, "br_table_default", br_table, &[5], pack_i32);
+    expected.push(("br_table_0", 100));
+    expected.push(("br_table_1", 101_001));
+    expected.push(("br_table_default", 5105));
+
+    // (x, 1.5) when x isn't 0, and (7, 2.5) otherwise.
+    let if_else = func(&mut module, &[I32], &[I32, F64], |b, args| {
+        let consequent = {
+            let mut then = b.if_else_block(Box::new([]), Box::new([I32, F64]));
+            let x = then.local_get(args[0]);
+            then.expr(x);
+            let half = then.f64_const(1.5);
+            then.expr(half);
+            then.id()
+        };
+        let alternative = {
+            let mut else_ = b.if_else_block(Box::new([]), Box::new([I32, F64]));
+            let seven = else_.i32_const(7);
+            else_.expr(seven);
+            let half = else_.f64_const(2.5);
+            else_.expr(half);
+            else_.id()
+        };
+        let x = b.local_get(args[0]);
+        vec![b.if_else(x, consequent, alternative)]
+    });
+    export(&mut module, "if_then", if_else, &[1], pack_f64);
+    export(&mut module, "if_else", if_else, &[0], pack_f64);
+    expected.push(("if_then", 1001));
+    expected.push(("if_else", 7002));
+
+    let loop_ = func(&mut module, &[I32], &[I32, I32], |b, args| {
+        let mut loop_ = b.loop_(Box::new([I32, I32]));
+        let x = loop_.local_get(args[0]);
+        loop_.expr(x);
+        let three = loop_.i32_const(3);
+        loop_.expr(three);
+        vec![loop_.id().into()]
+    });
+    export(&mut module, "loop", loop_, &[4], pack_i32);
+    expected.push(("loop", 4003));
+
+    // (x, 9) through a `return` when x isn't 0, and (1, 2) otherwise.
+    let return_ = func(&mut module, &[I32], &[I32, I64], |b, args| {
+        let consequent = {
+            let mut then = b.if_else_block(Box::new([]), Box::new([]));
+            let x = then.local_get(args[0]);
+            let nine = then.i64_const(9);
+            let ret = then.return_(Box::new([x, nine]));
+            then.expr(ret);
+            then.id()
+        };
+        let alternative = b.if_else_block(Box::new([]), Box::new([])).id();
+        let x = b.local_get(args[0]);
+        let if_else = b.if_else(x, consequent, alternative);
+        vec![if_else, b.i32_const(1), b.i64_const(2)]
+    });
+    export(&mut module, "return", return_, &[6], pack_i64);
+    export(&mut module, "fall_through", return_, &[0], pack_i64);
+    expected.push(("return", 6009));
+    expected.push(("fall_through", 1002));
+
+    // The tenth and eleventh Fibonacci numbers, through nested calls each
+    // taking the previous call's results.
+    let step = func(&mut module, &[I32, I32], &[I32, I32], |b, args| {
+        let a = b.local_get(args[0]);
+        let c = b.local_get(args[1]);
+        let sum = b.binop(BinaryOp::I32Add, a, c);
+        vec![b.local_get(args[1]), sum]
+    });
+    let fib = func(&mut module, &[], &[I32, I32], |b, _| {
+        let zero = b.i32_const(0);
+        let one = b.i32_const(1);
+        let mut pair = b.call(step, Box::new([zero, one]));
+        for _ in 1..10 {
+            pair = b.call(step, Box::new([pair]));
+        }
+        vec![pair]
+    });
+    export(&mut module, "fib", fib, &[], pack_i32);
+    expected.push(("fib", 55089));
+
+    (module, expected)
+}
+
+fn run(module: &Module) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lowered.wasm");
+    fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+    wasm_interp(&path)
+}
+
+#[test]
+fn lowered_results_are_equivalent() {
+    for &memory in &[false, true] {
+        let (mut module, expected) = module();
+        assert!(module.used_features().multi_value);
+
+        let extra_results = if memory {
+            let memory = module.memories.iter().next().unwrap().id();
+            ExtraResults::Memory {
+                memory,
+                address: 1024,
+            }
+        } else {
+            ExtraResults::Globals
+        };
+        let options = LowerMultiValueOptions {
+            extra_results,
+            ..Default::default()
+        };
+        // Ten functions and six blocks.
+        assert_eq!(lower_multi_value_with(&mut module, &options).unwrap(), 16);
+        validate::run(&module).unwrap();
+        assert!(module.types.iter().all(|ty| ty.results().len() <= 1));
+        assert_eq!(module.used_features(), WasmFeatures::default());
+        // One global for each of the i32, i64 and f64 extra results.
+        let globals = if memory { 0 } else { 3 };
+        assert_eq!(module.globals.iter().count(), globals);
+
+        let mut config = ModuleConfig::new();
+        config.wasm_features(WasmFeatures::default());
+        config.parse(&module.emit_wasm().unwrap()).unwrap();
+
+        let output = run(&module);
+        assert_eq!(output.lines().count(), expected.len());
+        for (line, (name, result)) in output.lines().zip(expected) {
+            assert_eq!(line, format!("{}() => i32:{}", name, result));
+        }
+    }
+}
+
+#[test]
+fn exported_functions_need_a_policy() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let f = func(&mut module, &[], &[I32, I32], |b, _| {
+        vec![b.i32_const(1), b.i32_const(2)]
+    });
+    module.exports.add("f", ExportItem::Function(f));
+
+    assert!(lower_multi_value(&mut module).is_err());
+    let ty = module.types.get(module.funcs.get(f).ty());
+    assert_eq!(ty.results(), [I32, I32]);
+
+    let options = LowerMultiValueOptions {
+        extra_results: ExtraResults::Memory { memory, address: 0 },
+        boundary: Boundary::ChangeSignature,
+    };
+    assert_eq!(lower_multi_value_with(&mut module, &options).unwrap(), 1);
+    let ty = module.types.get(module.funcs.get(f).ty());
+    assert_eq!(ty.results(), [I32]);
+    assert_eq!(run(&module).trim(), "f() => i32:1");
+}
+
+#[test]
+fn modules_without_multiple_results_are_untouched() {
+    let mut module = Module::default();
+    let f = func(&mut module, &[I32], &[I32], |b, args| {
+        vec![b.local_get(args[0])]
+    });
+    module.exports.add("f", ExportItem::Function(f));
+    let before = module.emit_wasm().unwrap();
+    assert_eq!(lower_multi_value(&mut module).unwrap(), 0);
+    assert_eq!(module.emit_wasm().unwrap(), before);
+}
+
+#[test]
+fn extra_references_are_kept_in_globals() {
+    let module = || {
+        let mut module = Module::default();
+        let f = func(&mut module, &[], &[I32, ValType::Externref], |b, _| {
+            vec![b.i32_const(1), b.ref_null(RefType::Externref)]
+        });
+        let first = func(
+            &mut module,
+            &[I32, ValType::Externref],
+            &[I32],
+            |b, args| vec![b.local_get(args[0])],
+        );
+        export(&mut module, "f", f, &[], first);
+        module
+    };
+
+    // Memory can't hold references.
+    let mut memory = module();
+    let options = LowerMultiValueOptions {
+        extra_results: ExtraResults::Memory {
+            memory: memory.memories.add_local(false, 1, None),
+            address: 0,
+        },
+        ..Default::default()
+    };
+    assert!(lower_multi_value_with(&mut memory, &options).is_err());
+
+    let mut module = module();
+    assert_eq!(lower_multi_value(&mut module).unwrap(), 1);
+    validate::run(&module).unwrap();
+    let globals: Vec<_> = module.globals.iter().collect();
+    assert_eq!(globals.len(), 1);
+    assert_eq!(globals[0].ty, ValType::Externref);
+    match globals[0].kind {
+        GlobalKind::Local(InitExpr::RefNull(RefType::Externref)) => {}
+        ref kind => panic!("unexpected initializer: {:?}", kind),
+    }
+}
@@ -0,0 +1,167 @@
+//! Tests for lowering the non-trapping float-to-int conversions.
+
+use std::fs;
+use walrus::ir::*;
+use walrus::passes::lower_trunc_sat;
+use walrus::{ExportItem, FunctionBuilder, Module, ValType, WasmFeatures};
+use walrus_tests_utils::wasm_interp;
+
+/// A module with one exported function per expression built by `body`.
+fn module(
+    n: usize,
+    mut body: impl FnMut(&mut FunctionBuilder, usize) -> (ExprId, ValType),
+) -> Module {
+    let mut module = Module::default();
+    for i in 0..n {
+        let mut builder = FunctionBuilder::new();
+        let (expr, ty) = body(&mut builder, i);
+        let ty = module.types.add(&[], &[ty]);
+        let func = builder.finish(ty, vec![], vec![expr], &mut module);
+        module
+            .exports
+            .add(&format!("f{}", i), ExportItem::Function(func));
+    }
+    module
+}
+
+fn pow2(n: i32) -> f64 {
+    2f64.powi(n)
+}
+
+#[test]
+fn lowered_conversions_are_equivalent() {
+    use walrus::ir::UnaryOp::*;
+    use walrus::ir::Value::*;
+
+    let nan32 = F32(f32::NAN);
+    let nan64 = F64(f64::NAN);
+    let inf32 = f32::INFINITY;
+    let inf64 = f64::INFINITY;
+
+    // Each conversion, an input, and the expected result. Boundaries are
+    // written as powers of two, which are exact in both float types.
+    let cases = [
+        (I32TruncSSatF32, nan32, I32(0)),
+        (I32TruncSSatF32, F32(-0.0), I32(0)),
+        (I32TruncSSatF32, F32(-1.5), I32(-1)),
+        (
+            I32TruncSSatF32,
+            F32((pow2(31) - 128.0) as f32),
+            I32(2147483520),
+        ),
+        (I32TruncSSatF32, F32(pow2(31) as f32), I32(i32::MAX)),
+        (I32TruncSSatF32, F32(-pow2(31) as f32), I32(i32::MIN)),
+        (
+            I32TruncSSatF32,
+            F32(-(pow2(31) + 256.0) as f32),
+            I32(i32::MIN),
+        ),
+        (I32TruncSSatF32, F32(inf32), I32(i32::MAX)),
+        (I32TruncSSatF32, F32(-inf32), I32(i32::MIN)),
+        (I32TruncUSatF32, nan32, I32(0)),
+        (I32TruncUSatF32, F32(-0.0), I32(0)),
+        (I32TruncUSatF32, F32(-0.9), I32(0)),
+        (I32TruncUSatF32, F32(-1.0), I32(0)),
+        (
+            I32TruncUSatF32,
+            F32((pow2(32) - 256.0) as f32),
+            I32(4294967040u32 as i32),
+        ),
+        (I32TruncUSatF32, F32(pow2(32) as f32), I32(-1)),
+        (I32TruncUSatF32, F32(inf32), I32(-1)),
+        (I32TruncSSatF64, nan64, I32(0)),
+        (I32TruncSSatF64, F64(2147483647.9), I32(i32::MAX)),
+        (I32TruncSSatF64, F64(2147483648.0), I32(i32::MAX)),
+        (I32TruncSSatF64, F64(-2147483648.9), I32(i32::MIN)),
+        (I32TruncSSatF64, F64(-2147483649.0), I32(i32::MIN)),
+        (I32TruncSSatF64, F64(-0.0), I32(0)),
+        (I32TruncUSatF64, nan64, I32(0)),
+        (I32TruncUSatF64, F64(-0.5), I32(0)),
+        (I32TruncUSatF64, F64(4294967295.9), I32(-1)),
+        (I32TruncUSatF64, F64(4294967296.0), I32(-1)),
+        (I32TruncUSatF64, F64(-inf64), I32(0)),
+        (I64TruncSSatF32, nan32, I64(0)),
+        (
+            I64TruncSSatF32,
+            F32((pow2(63) - pow2(39)) as f32),
+            I64(9223371487098961920),
+        ),
+        (I64TruncSSatF32, F32(pow2(63) as f32), I64(i64::MAX)),
+        (I64TruncSSatF32, F32(-pow2(63) as f32), I64(i64::MIN)),
+        (I64TruncSSatF32, F32(-inf32), I64(i64::MIN)),
+        (I64TruncUSatF32, nan32, I64(0)),
+        (I64TruncUSatF32, F32(-0.0), I64(0)),
+        (I64TruncUSatF32, F32(1.5), I64(1)),
+        (I64TruncUSatF32, F32(pow2(64) as f32), I64(-1)),
+        (I64TruncSSatF64, nan64, I64(0)),
+        (
+            I64TruncSSatF64,
+            F64(pow2(63) - 1024.0),
+            I64(9223372036854774784),
+        ),
+        (I64TruncSSatF64, F64(pow2(63)), I64(i64::MAX)),
+        (I64TruncSSatF64, F64(-pow2(63)), I64(i64::MIN)),
+        (I64TruncSSatF64, F64(-inf64), I64(i64::MIN)),
+        (I64TruncUSatF64, nan64, I64(0)),
+        (I64TruncUSatF64, F64(-1.0), I64(0)),
+        (I64TruncUSatF64, F64(pow2(64) - 2048.0), I64(-2048)),
+        (I64TruncUSatF64, F64(pow2(64)), I64(-1)),
+        (I64TruncUSatF64, F64(inf64), I64(-1)),
+    ];
+
+    let mut lowered = module(cases.len(), |builder, i| {
+        let (op, input, expected) = cases[i];
+        let input = builder.const_(input);
+        (builder.unop(op, input), expected.ty())
+    });
+    assert!(lowered.used_features().saturating_float_to_int);
+    assert_eq!(lower_trunc_sat(&mut lowered), cases.len());
+    assert_eq!(lowered.used_features(), WasmFeatures::default());
+    // One scratch local for each float type.
+    assert_eq!(lowered.locals.iter().count(), 2);
+
+    let expected = module(cases.len(), |builder, i| {
+        let (_, _, expected) = cases[i];
+        (builder.const_(expected), expected.ty())
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut outputs = Vec::new();
+    for (name, m) in &[("lowered.wasm", lowered), ("expected.wasm", expected)] {
+        let path = dir.path().join(name);
+        fs::write(&path, m.emit_wasm().unwrap()).unwrap();
+        outputs.push(wasm_interp(&path));
+    }
+    assert_eq!(outputs[0].lines().count(), cases.len());
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn nested_conversions() {
+    // (i32.trunc_sat_f32_s (f32.convert_i32_s (i32.trunc_sat_f32_u (f32.const -7))))
+    let mut lowered = module(1, |builder, _| {
+        let x = builder.f32_const(-7.0);
+        let x = builder.unop(UnaryOp::I32TruncUSatF32, x);
+        let x = builder.unop(UnaryOp::F32ConvertSI32, x);
+        (builder.unop(UnaryOp::I32TruncSSatF32, x), ValType::I32)
+    });
+    assert_eq!(lower_trunc_sat(&mut lowered), 2);
+    assert_eq!(lowered.locals.iter().count(), 1);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested.wasm");
+    fs::write(&path, lowered.emit_wasm().unwrap()).unwrap();
+    assert!(wasm_interp(&path).trim().ends_with("i32:0"));
+}
+
+#[test]
+fn modules_without_conversions_are_untouched() {
+    let mut module = module(1, |builder, _| {
+        let x = builder.f32_const(1.5);
+        (builder.unop(UnaryOp::I32TruncSF32, x), ValType::I32)
+    });
+    let before = module.emit_wasm().unwrap();
+    assert_eq!(lower_trunc_sat(&mut module), 0);
+    assert_eq!(module.locals.iter().count(), 0);
+    assert_eq!(module.emit_wasm().unwrap(), before);
+}
@@ -0,0 +1,92 @@
+//! Tests for precise errors on malformed function bodies.
+
+use walrus::{MalformedBodyKind, MalformedFunctionBody, Module};
+
+/// A module importing one function and defining one more, whose code section
+/// is given as raw bytes.
+fn module_with_code_section(code: &[u8]) -> Vec<u8> {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x02, 0x07, 0x01, 0x01, b'm', 0x01, b'f', 0x00, 0x00]); // import section
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]); // function section
+    wasm.push(0x0a);
+    wasm.push(code.len() as u8);
+    wasm.extend(code);
+    wasm
+}
+
+/// A module whose only local function has the given body.
+fn module_with_body(body: &[u8]) -> Vec<u8> {
+    let mut code = vec![0x01, body.len() as u8];
+    code.extend(body);
+    module_with_code_section(&code)
+}
+
+fn malformed(wasm: &[u8]) -> MalformedFunctionBody {
+    let err = Module::from_buffer(wasm).unwrap_err();
+    match err
+        .find_root_cause()
+        .downcast_ref::<MalformedFunctionBody>()
+    {
+        Some(e) => e.clone(),
+        None => panic!("not a malformed function body error: {}", err),
+    }
+}
+
+#[test]
+fn well_formed_body() {
+    Module::from_buffer(&module_with_body(&[0x00, 0x0b])).unwrap();
+}
+
+#[test]
+fn too_many_locals() {
+    let wasm = module_with_body(&[
+        0x02, // two local declarations
+        0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, // 2^32 - 1 i32 locals
+        0x01, 0x7f, // and one more i32 local
+        0x0b,
+    ]);
+    let err = malformed(&wasm);
+    assert_eq!(err.kind, MalformedBodyKind::TooManyLocals);
+    assert_eq!(err.function, 1);
+    // The offset of the second declaration.
+    assert_eq!(err.offset, wasm.len() - 3);
+}
+
+#[test]
+fn trailing_bytes_after_end() {
+    let wasm = module_with_body(&[0x00, 0x0b, 0x01]);
+    let err = malformed(&wasm);
+    assert_eq!(err.kind, MalformedBodyKind::TrailingBytes);
+    assert_eq!(err.function, 1);
+    assert_eq!(err.offset, wasm.len() - 1);
+}
+
+#[test]
+fn body_missing_final_end() {
+    let wasm = module_with_body(&[0x00, 0x01]);
+    let err = malformed(&wasm);
+    assert_eq!(err.kind, MalformedBodyKind::BodySizeMismatch);
+    assert_eq!(err.function, 1);
+    assert_eq!(err.offset, wasm.len());
+}
+
+#[test]
+fn body_size_past_end_of_section() {
+    // Declares a 16 byte body, but only two bytes follow.
+    let wasm = module_with_code_section(&[0x01, 0x10, 0x00, 0x0b]);
+    let err = malformed(&wasm);
+    assert_eq!(err.kind, MalformedBodyKind::BodySizeMismatch);
+    assert_eq!(err.function, 1);
+}
+
+#[test]
+fn malformed_body_size() {
+    // A body size with its unused bits set, which isn't a size mismatch.
+    let wasm = module_with_code_section(&[0x01, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x00, 0x0b]);
+    let err = Module::from_buffer(&wasm).unwrap_err();
+    assert!(err
+        .find_root_cause()
+        .downcast_ref::<MalformedFunctionBody>()
+        .is_none());
+}
@@ -0,0 +1,145 @@
+//! Tests that parsing malformed modules fails with an error rather than
+//! panicking.
+
+use walrus::ir::*;
+use walrus::{ExportItem, FunctionBuilder, Module, ValType};
+
+const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+const TYPE_SECTION: [u8; 6] = [0x01, 0x04, 0x01, 0x60, 0x00, 0x00];
+const FUNCTION_SECTION: [u8; 4] = [0x03, 0x02, 0x01, 0x00];
+const IMPORT_SECTION: [u8; 9] = [0x02, 0x07, 0x01, 0x01, b'a', 0x01, b'b', 0x00, 0x00];
+const CODE_SECTION: [u8; 6] = [0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b];
+
+/// A small module using a few different sections.
+fn small_module() -> Vec<u8> {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(8, b"hello".to_vec());
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let x = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let address = builder.local_get(x);
+    let load = builder.load(
+        memory,
+        LoadKind::I32 { atomic: false },
+        MemArg {
+            align: 4,
+            offset: 8,
+        },
+        address,
+    );
+    let func = builder.finish(ty, vec![x], vec![load], &mut module);
+    module.exports.add("f", ExportItem::Function(func));
+    module.emit_wasm().unwrap()
+}
+
+fn concat(sections: &[&[u8]]) -> Vec<u8> {
+    let mut wasm = HEADER.to_vec();
+    for section in sections {
+        wasm.extend_from_slice(section);
+    }
+    wasm
+}
+
+#[test]
+fn truncations_dont_panic() {
+    let wasm = small_module();
+    Module::from_buffer(&wasm).unwrap();
+    for len in 0..wasm.len() {
+        let _ = Module::from_buffer(&wasm[..len]);
+    }
+}
+
+#[test]
+fn mutations_dont_panic() {
+    let wasm = small_module();
+    for i in 0..wasm.len() {
+        for byte in [0x00, 0x01, 0x7f, 0x80, 0xff, wasm[i] ^ 0x01]
+            .iter()
+            .cloned()
+        {
+            let mut mutated = wasm.clone();
+            mutated[i] = byte;
+            let _ = Module::from_buffer(&mutated);
+        }
+    }
+}
+
+#[test]
+fn imports_after_function_section() {
+    let wasm = concat(&[
+        &TYPE_SECTION,
+        &FUNCTION_SECTION,
+        &IMPORT_SECTION,
+        &CODE_SECTION,
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
+
+#[test]
+fn duplicate_function_section() {
+    let wasm = concat(&[
+        &TYPE_SECTION,
+        &FUNCTION_SECTION,
+        &FUNCTION_SECTION,
+        &CODE_SECTION,
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
+
+#[test]
+fn huge_counts() {
+    // A type section claiming 2^32 - 1 entries, with none of them there.
+    let wasm = concat(&[&[0x01, 0x05, 0xff, 0xff, 0xff, 0xff, 0x0f]]);
+    assert!(Module::from_buffer(&wasm).is_err());
+
+    // A local declaration of 2^32 - 1 locals, in a function with a
+    // parameter.
+    let wasm = concat(&[
+        &[0x01, 0x05, 0x01, 0x60, 0x01, 0x7f, 0x00],
+        &FUNCTION_SECTION,
+        &[
+            0x0a, 0x0a, 0x01, 0x08, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b,
+        ],
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
+
+#[test]
+fn element_offsets_outside_the_table() {
+    // A table, and two functions for an active segment to put in it.
+    let prefix: [&[u8]; 3] = [
+        &TYPE_SECTION,
+        &[0x03, 0x03, 0x02, 0x00, 0x00],
+        &[0x04, 0x04, 0x01, 0x70, 0x00, 0x00],
+    ];
+    let code: &[u8] = &[0x0a, 0x07, 0x02, 0x02, 0x00, 0x0b, 0x02, 0x00, 0x0b];
+
+    // An offset of `i32.const -52`.
+    let wasm = concat(&[
+        prefix[0],
+        prefix[1],
+        prefix[2],
+        &[
+            0x09, 0x87, 0x80, 0x80, 0x80, 0x00, 0x01, 0x00, 0x41, 0x4c, 0x0b, 0x01, 0x01,
+        ],
+        code,
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+
+    // An offset of `i32.const 0x7fffffff`.
+    let wasm = concat(&[
+        prefix[0],
+        prefix[1],
+        prefix[2],
+        &[
+            0x09, 0x0b, 0x01, 0x00, 0x41, 0xff, 0xff, 0xff, 0xff, 0x07, 0x0b, 0x01, 0x01,
+        ],
+        code,
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
@@ -0,0 +1,201 @@
+//! Tests for finding and replacing expressions with patterns.
+
+use std::fs;
+use walrus::ir::matcher::*;
+use walrus::ir::*;
+use walrus::passes::validate;
+use walrus::ValType;
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, LocalFunction, Module};
+use walrus_tests_utils::wasm_interp;
+
+fn local(module: &Module, func: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn local_mut(module: &mut Module, func: FunctionId) -> &mut LocalFunction {
+    match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+/// An exported function returning
+///
+/// ```text
+/// (a + 0) + (a + 5) + ((a * 8) + (a * 6)) + (0 + a)
+/// ```
+///
+/// where `a` is 7.
+fn module() -> (Module, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let a = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let seven = builder.i32_const(7);
+    let set = builder.local_set(a, seven);
+
+    let mut op = |op, lhs: Option<i32>, rhs: Option<i32>| {
+        let mut operand = |n: Option<i32>| match n {
+            Some(n) => builder.i32_const(n),
+            None => builder.local_get(a),
+        };
+        let lhs = operand(lhs);
+        let rhs = operand(rhs);
+        builder.binop(op, lhs, rhs)
+    };
+    let plus_zero = op(BinaryOp::I32Add, None, Some(0));
+    let plus_five = op(BinaryOp::I32Add, None, Some(5));
+    let times_eight = op(BinaryOp::I32Mul, None, Some(8));
+    let times_six = op(BinaryOp::I32Mul, None, Some(6));
+    let zero_plus = op(BinaryOp::I32Add, Some(0), None);
+
+    let add = |builder: &mut FunctionBuilder, lhs, rhs| builder.binop(BinaryOp::I32Add, lhs, rhs);
+    let sum = add(&mut builder, plus_zero, plus_five);
+    let products = add(&mut builder, times_eight, times_six);
+    let sum = add(&mut builder, sum, products);
+    let sum = add(&mut builder, sum, zero_plus);
+
+    let func = builder.finish(ty, vec![], vec![set, sum], &mut module);
+    module.exports.add("f", ExportItem::Function(func));
+    (module, func)
+}
+
+fn run(module: &Module) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("matcher.wasm");
+    fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+    wasm_interp(&path)
+}
+
+/// `i32.add`s of a `local.get` and an `i32.const`, found by hand.
+struct AddsOfLocals<'a> {
+    func: &'a LocalFunction,
+    found: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for AddsOfLocals<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, id: &ExprId) {
+        if let Expr::Binop(Binop {
+            op: BinaryOp::I32Add,
+            lhs,
+            rhs,
+        }) = self.func.get(*id)
+        {
+            let local = matches!(self.func.get(*lhs), Expr::LocalGet(_));
+            let constant = matches!(
+                self.func.get(*rhs),
+                Expr::Const(Const {
+                    value: Value::I32(_),
+                })
+            );
+            if local && constant {
+                self.found.push(*id);
+            }
+        }
+        id.visit(self);
+    }
+}
+
+#[test]
+fn find_all_agrees_with_a_visitor() {
+    let (module, func) = module();
+    let func = local(&module, func);
+
+    let mut visitor = AddsOfLocals {
+        func,
+        found: Vec::new(),
+    };
+    dfs_in_order(&mut visitor, func, func.entry_block().into());
+    assert_eq!(visitor.found.len(), 2);
+
+    let pattern = binop(
+        BinaryOp::I32Add,
+        local_get(any()).bind("local"),
+        const_i32(any()).bind("n"),
+    );
+    let matches = find_all(func, &pattern);
+    let found = matches.iter().map(|m| m.expr).collect::<Vec<_>>();
+    assert_eq!(found, visitor.found);
+
+    for m in &matches {
+        let (lhs, rhs) = match func.get(m.expr) {
+            Expr::Binop(e) => (e.lhs, e.rhs),
+            _ => unreachable!(),
+        };
+        assert_eq!(m["local"], lhs);
+        assert_eq!(m.get("n"), Some(rhs));
+        assert_eq!(m.get("x"), None);
+        assert_eq!(
+            m.captures().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["local", "n"]
+        );
+    }
+
+    let constant = binop(BinaryOp::I32Add, any(), const_i32(exactly(5)));
+    assert_eq!(find_all(func, &constant).len(), 1);
+    assert!(find_all(func, &binop(BinaryOp::I64Add, any(), any())).is_empty());
+}
+
+#[test]
+fn peephole_rules() {
+    let (mut module, func) = module();
+    let expected = run(&module);
+    assert_eq!(expected.trim(), "f() => i32:124");
+
+    // x + 0 => x, either way round.
+    let add_zero = binop(
+        BinaryOp::I32Add,
+        any::<Pattern>().bind("x"),
+        const_i32(exactly(0)),
+    )
+    .or(binop(
+        BinaryOp::I32Add,
+        const_i32(exactly(0)),
+        any::<Pattern>().bind("x"),
+    ));
+    let replaced = replace_all(local_mut(&mut module, func), &add_zero, |func, m| {
+        Some(func.get(m["x"]).clone())
+    });
+    assert_eq!(replaced, 2);
+
+    // x * 2^k => x << k
+    let mul = binop(
+        BinaryOp::I32Mul,
+        any::<Pattern>().bind("x"),
+        const_i32(any()).bind("n"),
+    );
+    let replaced = replace_all(local_mut(&mut module, func), &mul, |func, m| {
+        let n = match func.get(m["n"]) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => *n,
+            _ => unreachable!(),
+        };
+        if n <= 0 || n & (n - 1) != 0 {
+            return None;
+        }
+        let k = func.builder_mut().i32_const(n.trailing_zeros() as i32);
+        Some(Expr::Binop(Binop {
+            op: BinaryOp::I32Shl,
+            lhs: m["x"],
+            rhs: k,
+        }))
+    });
+    assert_eq!(replaced, 1);
+
+    let f = local(&module, func);
+    assert!(find_all(f, &add_zero).is_empty());
+    assert_eq!(find_all(f, &mul).len(), 1);
+    let shl = binop(BinaryOp::I32Shl, local_get(any()), const_i32(exactly(3)));
+    assert_eq!(find_all(f, &shl).len(), 1);
+
+    validate::run(&module).unwrap();
+    assert_eq!(run(&module), expected);
+}
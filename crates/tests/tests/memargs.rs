@@ -0,0 +1,95 @@
+//! Tests for rewriting the `MemArg`s of memory operations.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionKind, Module, ValType};
+
+#[test]
+fn shift_offsets() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let ptr = module.locals.add(ValType::I32);
+
+    // (func (param $ptr i32)
+    //   (drop (i32.load offset=4 (local.get $ptr)))
+    //   (i32.store offset=0xfffffff0 (local.get $ptr) (i32.const 1))
+    //   (drop (i32.load offset=0xfffffff0 (i32.const 16))))
+    let mut builder = FunctionBuilder::new();
+    let kind = LoadKind::I32 { atomic: false };
+    let arg = |offset| MemArg { align: 4, offset };
+
+    let address = builder.local_get(ptr);
+    let small = builder.load(memory, kind, arg(4), address);
+    let dropped = builder.drop(small);
+
+    let address = builder.local_get(ptr);
+    let value = builder.i32_const(1);
+    let store_kind = StoreKind::I32 { atomic: false };
+    let overflowing = builder.store(memory, store_kind, arg(0xffff_fff0), address, value);
+
+    let constant = builder.i32_const(16);
+    let folded = builder.load(memory, kind, arg(0xffff_fff0), constant);
+    let dropped_folded = builder.drop(folded);
+
+    let body = vec![dropped, overflowing, dropped_folded];
+    let func = builder.finish(ty, vec![ptr], body, &mut module);
+
+    let local = match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    let mut loads = 0;
+    let mut stores = 0;
+    local.rewrite_memargs(memory, |_, kind| {
+        match kind {
+            LoadStoreKind::Load(_) => loads += 1,
+            LoadStoreKind::Store(_) => stores += 1,
+            _ => panic!("unexpected memory operation"),
+        }
+        Rewrite::AddToOffset(0x10000)
+    });
+    assert_eq!((loads, stores), (2, 1));
+
+    match local.get(small) {
+        Expr::Load(e) => assert_eq!(e.arg.offset, 0x10004),
+        _ => panic!("expected a load"),
+    }
+    match local.get(overflowing) {
+        Expr::Store(e) => {
+            assert_eq!(e.arg.offset, 0xffff_fff0);
+            match local.get(e.address) {
+                Expr::Binop(Binop {
+                    op: BinaryOp::I32Add,
+                    lhs,
+                    rhs,
+                }) => {
+                    assert_eq!(*lhs, address);
+                    match local.get(*rhs) {
+                        Expr::Const(Const {
+                            value: Value::I32(0x10000),
+                        }) => {}
+                        _ => panic!("expected the shift to be added to the address"),
+                    }
+                }
+                _ => panic!("expected an i32.add"),
+            }
+        }
+        _ => panic!("expected a store"),
+    }
+    match local.get(folded) {
+        Expr::Load(e) => {
+            assert_eq!(e.arg.offset, 0xffff_fff0);
+            assert_eq!(e.address, constant);
+            match local.get(e.address) {
+                Expr::Const(Const {
+                    value: Value::I32(0x10010),
+                }) => {}
+                _ => panic!("expected the shift to be folded into the address"),
+            }
+        }
+        _ => panic!("expected a load"),
+    }
+
+    let wasm = module.emit_wasm().unwrap();
+    Module::from_buffer(&wasm).unwrap();
+}
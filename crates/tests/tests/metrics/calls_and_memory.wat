@@ -0,0 +1,18 @@
+(module
+  (type $t (func (param i32) (result i32)))
+  (import "env" "f" (func $f (type $t)))
+  (memory 1)
+  (table 1 funcref)
+  (func (export "g") (type $t) (local i32)
+    block
+      local.get 0
+      i32.load
+      local.set 1
+      local.get 1
+      i32.const 4
+      i32.store
+    end
+    local.get 1
+    call $f
+    i32.const 0
+    call_indirect (type $t)))
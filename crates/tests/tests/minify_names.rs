@@ -0,0 +1,191 @@
+//! Tests for minifying names and restoring them from a name map.
+
+use walrus::passes::{apply_name_map, minify_names, MinifyOptions, NameMap};
+use walrus::{FunctionBuilder, Module, ValType};
+
+/// A module with an imported function `env.log`, exported functions
+/// `compute` and `helper\twith a tab`, and an unnamed function. `compute`
+/// has locals named `x` and `y` and an unnamed one.
+fn module() -> Module {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let log = module.add_import_func("env", "log", ty);
+    module.funcs.get_mut(log).name = Some("log".to_string());
+
+    let x = module.locals.add(ValType::I32);
+    let y = module.locals.add(ValType::I32);
+    let unnamed = module.locals.add(ValType::I32);
+    module.locals.get_mut(x).name = Some("x".to_string());
+    module.locals.get_mut(y).name = Some("y".to_string());
+    let mut builder = FunctionBuilder::new();
+    let get = builder.local_get(x);
+    let set = builder.local_set(y, get);
+    let get = builder.local_get(unnamed);
+    let call = builder.call(log, Box::new([get]));
+    let compute = builder.finish(ty, vec![x], vec![set, call], &mut module);
+    module.funcs.get_mut(compute).name = Some("compute".to_string());
+    module.exports.add("compute", compute);
+
+    let unit = module.types.add(&[], &[]);
+    let helper = FunctionBuilder::new().finish(unit, vec![], vec![], &mut module);
+    module.funcs.get_mut(helper).name = Some("helper\twith a tab".to_string());
+    module.exports.add("helper", helper);
+
+    FunctionBuilder::new().finish(unit, vec![], vec![], &mut module);
+    module
+}
+
+/// The names of the module's functions, locals and exports, each sorted.
+fn names(module: &Module) -> Vec<Vec<String>> {
+    let mut functions = module
+        .funcs
+        .iter()
+        .filter_map(|f| f.name.clone())
+        .collect::<Vec<_>>();
+    let mut locals = module
+        .locals
+        .iter()
+        .filter_map(|l| l.name.clone())
+        .collect::<Vec<_>>();
+    let mut exports = module
+        .exports
+        .iter()
+        .map(|e| e.name.to_string())
+        .collect::<Vec<_>>();
+    functions.sort();
+    locals.sort();
+    exports.sort();
+    vec![functions, locals, exports]
+}
+
+fn round_trip(module: &Module) -> Module {
+    Module::from_buffer(&module.emit_wasm().unwrap()).unwrap()
+}
+
+#[test]
+fn minify_and_restore() {
+    let mut module = module();
+    let original = names(&module);
+
+    let map = minify_names(&mut module, &MinifyOptions::default()).unwrap();
+    assert_eq!(
+        names(&module),
+        [
+            vec!["a", "b", "c"],
+            vec!["a", "b"],
+            vec!["compute", "helper"]
+        ]
+    );
+    assert_eq!(map.functions.len(), 3);
+    assert_eq!(map.locals.len(), 2);
+    assert!(map.exports.is_empty());
+    // Imports are still found by their module and field names.
+    assert!(module.imports.find("env", "log").is_some());
+
+    assert_eq!(apply_name_map(&mut module, &map), 5);
+    assert_eq!(names(&module), original);
+}
+
+#[test]
+fn restore_after_parsing() {
+    let mut module = module();
+    let original = names(&round_trip(&module));
+
+    let map = minify_names(&mut module, &MinifyOptions::default()).unwrap();
+    let text = map.to_string();
+
+    let mut parsed = round_trip(&module);
+    assert_eq!(names(&parsed)[0], ["a", "b", "c"]);
+    let map = text.parse::<NameMap>().unwrap();
+    apply_name_map(&mut parsed, &map);
+    assert_eq!(names(&parsed), original);
+}
+
+#[test]
+fn rename_exports() {
+    let mut module = module();
+    let original = names(&module);
+    let options = MinifyOptions {
+        rename_exports: true,
+        alphabet: "xy".to_string(),
+        ..MinifyOptions::default()
+    };
+    let map = minify_names(&mut module, &options).unwrap();
+    assert_eq!(
+        names(&module),
+        [vec!["x", "xx", "y"], vec!["x", "y"], vec!["x", "y"]]
+    );
+    assert_eq!(map.exports["x"], "compute");
+    assert_eq!(map.exports["y"], "helper");
+
+    apply_name_map(&mut module, &map);
+    assert_eq!(names(&module), original);
+}
+
+#[test]
+fn drop_name_section() {
+    let mut module = module();
+    let options = MinifyOptions {
+        keep_name_section: false,
+        ..MinifyOptions::default()
+    };
+    minify_names(&mut module, &options).unwrap();
+    let parsed = round_trip(&module);
+    assert!(names(&parsed)[0].is_empty());
+    assert!(names(&parsed)[1].is_empty());
+    assert_eq!(names(&parsed)[2], ["compute", "helper"]);
+}
+
+#[test]
+fn generated_names_are_distinct() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    for i in 0..1000 {
+        let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+        module.funcs.get_mut(f).name = Some(format!("f{}", i));
+    }
+    let options = MinifyOptions {
+        alphabet: "ab".to_string(),
+        ..MinifyOptions::default()
+    };
+    let map = minify_names(&mut module, &options).unwrap();
+    assert_eq!(map.functions.len(), 1000);
+    let mut minified = names(&module).remove(0);
+    minified.dedup();
+    assert_eq!(minified.len(), 1000);
+    assert!(minified.iter().all(|n| n.len() <= 9));
+}
+
+#[test]
+fn bad_alphabets() {
+    for alphabet in ["", "aba"].iter() {
+        let mut module = module();
+        let options = MinifyOptions {
+            alphabet: alphabet.to_string(),
+            ..MinifyOptions::default()
+        };
+        assert!(minify_names(&mut module, &options).is_err());
+        assert_eq!(names(&module)[0], ["compute", "helper\twith a tab", "log"]);
+    }
+}
+
+#[test]
+fn name_map_text_format() {
+    let mut map = NameMap::default();
+    map.functions
+        .insert("a".to_string(), "tab\there\\".to_string());
+    map.locals
+        .insert("a".to_string(), "line\nbreak".to_string());
+    map.exports.insert("b".to_string(), "run".to_string());
+    let text = map.to_string();
+    assert_eq!(
+        text,
+        "func\ta\ttab\\there\\\\\nlocal\ta\tline\\nbreak\nexport\tb\trun\n"
+    );
+    assert_eq!(text.parse::<NameMap>().unwrap(), map);
+
+    assert!("func\ta".parse::<NameMap>().is_err());
+    assert!("global\ta\tb".parse::<NameMap>().is_err());
+    assert!("func\ta\tb\\q".parse::<NameMap>().is_err());
+    assert!("func\ta\tb\nfunc\ta\tc".parse::<NameMap>().is_err());
+}
@@ -0,0 +1,127 @@
+//! Tests for modules with more than one memory.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, LocalFunction, MemoryId, Module, ModuleConfig, ValType};
+use walrus_tests_utils::function_bodies;
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+/// A module importing memory 0 and defining memory 1.
+fn module() -> Module {
+    let mut module = Module::with_config(config());
+    module.add_import_memory("env", "memory", false, 1, None);
+    module.memories.add_local(false, 1, None);
+    module
+}
+
+#[test]
+fn memory_indices_are_emitted() {
+    let mut module = module();
+    let ids: Vec<_> = module.memories.iter().map(|m| m.id()).collect();
+    let (imported, local) = (ids[0], ids[1]);
+    let ty = module.types.add(&[], &[ValType::I32]);
+
+    let mut builder = FunctionBuilder::new();
+    let zero = builder.i32_const(0);
+    let len = builder.i32_const(4);
+    let src = builder.i32_const(8);
+    let copy = builder.memory_copy(imported, local, zero, src, len);
+    let zero = builder.i32_const(0);
+    let value = builder.i32_const(1);
+    let len = builder.i32_const(4);
+    let fill = builder.memory_fill(local, zero, value, len);
+    let zero = builder.i32_const(0);
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    let load = builder.load(local, LoadKind::I32 { atomic: false }, arg, zero);
+    let address = builder.i32_const(0);
+    let store = builder.store(local, StoreKind::I32 { atomic: false }, arg, address, load);
+    let data = module.data.add_passive(b"data".to_vec());
+    let zero = builder.i32_const(0);
+    let offset = builder.i32_const(1);
+    let len = builder.i32_const(2);
+    let init = builder.memory_init(local, data, zero, offset, len);
+    let size = builder.memory_size(local);
+    builder.finish(ty, vec![], vec![copy, fill, store, init, size], &mut module);
+
+    let wasm = module.emit_wasm().unwrap();
+    #[rustfmt::skip]
+    let body = [
+        0x00,
+        // `memory.copy` takes the destination memory first.
+        0x41, 0x00, 0x41, 0x08, 0x41, 0x04, 0xfc, 0x0a, 0x01, 0x00,
+        0x41, 0x00, 0x41, 0x01, 0x41, 0x04, 0xfc, 0x0b, 0x01,
+        // The alignment flags have bit 6 set, and are followed by the memory.
+        0x41, 0x00, 0x41, 0x00, 0x28, 0x42, 0x01, 0x00, 0x36, 0x42, 0x01, 0x00,
+        0x41, 0x00, 0x41, 0x01, 0x41, 0x02, 0xfc, 0x08, 0x00, 0x01,
+        0x3f, 0x01,
+        0x0b,
+    ];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+
+    // The memory indices are parsed back, and emitted the same way again.
+    let parsed = config().parse(&wasm).unwrap();
+    assert_eq!(parsed.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn round_trip_with_imported_memory() {
+    let mut module = module();
+    let ids: Vec<_> = module.memories.iter().map(|m| m.id()).collect();
+    let (imported, local) = (ids[0], ids[1]);
+    let ty = module.types.add(&[], &[ValType::I32]);
+
+    // (drop (memory.grow 1 (i32.load (i32.const 0))))
+    // (memory.size 1)
+    let mut builder = FunctionBuilder::new();
+    let zero = builder.i32_const(0);
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    let load = builder.load(imported, LoadKind::I32 { atomic: false }, arg, zero);
+    let grow = builder.memory_grow(local, load);
+    let drop = builder.drop(grow);
+    let size = builder.memory_size(local);
+    builder.finish(ty, vec![], vec![drop, size], &mut module);
+
+    let wasm = module.emit_wasm().unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.memories.len(), 2);
+    let imported = module.memories.iter().find(|m| m.import.is_some()).unwrap();
+    let local = module.memories.iter().find(|m| m.import.is_none()).unwrap();
+
+    let func = module.funcs.iter_local().next().unwrap().1;
+    let mut memories = Memories {
+        func,
+        memories: Vec::new(),
+    };
+    dfs_in_order(&mut memories, func, func.entry_block().into());
+    let mut expected = vec![imported.id(), local.id(), local.id()];
+    expected.sort();
+    memories.memories.sort();
+    assert_eq!(memories.memories, expected);
+}
+
+struct Memories<'a> {
+    func: &'a LocalFunction,
+    memories: Vec<MemoryId>,
+}
+
+impl<'a> Visitor<'a> for Memories<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_memory_id(&mut self, id: &MemoryId) {
+        self.memories.push(*id);
+    }
+}
@@ -0,0 +1,123 @@
+//! Tests for parsing malformed or unusual `name` custom sections.
+
+use walrus::{Module, ModuleConfig};
+
+/// A module with a single `(func)`, followed by a `name` custom section made
+/// up of the given raw subsections.
+fn module_with_name_subsections(subsections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]); // function section
+    wasm.extend(&[0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b]); // code section
+
+    let mut payload = string("name");
+    for (id, data) in subsections {
+        payload.push(*id);
+        payload.push(data.len() as u8);
+        payload.extend(data);
+    }
+    wasm.push(0x00);
+    wasm.push(payload.len() as u8);
+    wasm.extend(payload);
+    wasm
+}
+
+fn string(s: &str) -> Vec<u8> {
+    let mut ret = vec![s.len() as u8];
+    ret.extend(s.as_bytes());
+    ret
+}
+
+fn module_name(name: &str) -> (u8, Vec<u8>) {
+    (0, string(name))
+}
+
+fn function_names(names: &[(u8, &str)]) -> (u8, Vec<u8>) {
+    let mut data = vec![names.len() as u8];
+    for (index, name) in names {
+        data.push(*index);
+        data.extend(string(name));
+    }
+    (1, data)
+}
+
+fn parse(wasm: &[u8]) -> Module {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    config.parse(wasm).unwrap()
+}
+
+fn function_name(module: &Module) -> Option<&str> {
+    module.funcs.iter().next().unwrap().name.as_deref()
+}
+
+#[test]
+fn out_of_order_subsections() {
+    let wasm = module_with_name_subsections(&[function_names(&[(0, "foo")]), module_name("m")]);
+    let module = parse(&wasm);
+    assert_eq!(module.name.as_deref(), Some("m"));
+    assert_eq!(function_name(&module), Some("foo"));
+}
+
+#[test]
+fn duplicate_subsections_keep_the_first() {
+    let wasm = module_with_name_subsections(&[
+        function_names(&[(0, "first")]),
+        function_names(&[(0, "second")]),
+    ]);
+    let module = parse(&wasm);
+    assert_eq!(function_name(&module), Some("first"));
+}
+
+#[test]
+fn unknown_subsections_round_trip() {
+    let wasm = module_with_name_subsections(&[
+        (7, vec![1, 2, 3]),
+        function_names(&[(0, "foo")]),
+        (9, vec![]),
+    ]);
+    let module = parse(&wasm);
+    assert_eq!(function_name(&module), Some("foo"));
+    let expected = vec![(7, vec![1, 2, 3]), (9, vec![])];
+    assert_eq!(module.unknown_name_subsections, expected);
+
+    let module = parse(&module.emit_wasm().unwrap());
+    assert_eq!(function_name(&module), Some("foo"));
+    assert_eq!(module.unknown_name_subsections, expected);
+}
+
+#[test]
+fn unknown_subsections_are_dropped_when_indices_change() {
+    let wasm = module_with_name_subsections(&[(7, vec![0]), function_names(&[(0, "foo")])]);
+    let mut module = parse(&wasm);
+
+    // The import goes before the local function, so the index of the
+    // function in the unknown subsection would be stale.
+    let ty = module.types.add(&[], &[]);
+    module.add_import_func("m", "f", ty);
+
+    let module = parse(&module.emit_wasm().unwrap());
+    let local = module.funcs.iter_local().next().unwrap().0;
+    assert_eq!(module.funcs.get(local).name.as_deref(), Some("foo"));
+    assert!(module.unknown_name_subsections.is_empty());
+}
+
+#[test]
+fn truncated_entries_are_skipped() {
+    // Claims two entries, but the second one is cut off in the middle of its
+    // name.
+    let (id, mut data) = function_names(&[(0, "foo")]);
+    data[0] = 2;
+    data.extend(&[0x00, 0x05, b'b']);
+    let wasm = module_with_name_subsections(&[(id, data), module_name("m")]);
+    let module = parse(&wasm);
+    assert_eq!(module.name.as_deref(), Some("m"));
+    assert_eq!(function_name(&module), Some("foo"));
+}
+
+#[test]
+fn out_of_bounds_entries_are_skipped() {
+    let wasm = module_with_name_subsections(&[function_names(&[(5, "bar"), (0, "foo")])]);
+    let module = parse(&wasm);
+    assert_eq!(function_name(&module), Some("foo"));
+}
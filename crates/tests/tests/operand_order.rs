@@ -0,0 +1,358 @@
+//! Tests that expressions' operands are emitted in the order that
+//! `Expr::operand_order` says they're evaluated in.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, FunctionTable, LocalFunction};
+use walrus::{InitExpr, Module, TableKind, ValType};
+use walrus_tests_utils::function_bodies;
+
+/// Operands are `i64.const`s of distinct values starting from this, so that
+/// where each one ends up in the emitted code can be found.
+const MARKER: i64 = 0x1234_5678_9abc_0000;
+
+struct Case {
+    builder: FunctionBuilder,
+    /// Each operand's encoding, and the name of the field it's in.
+    operands: Vec<(Vec<u8>, &'static str)>,
+}
+
+impl Case {
+    fn operand(&mut self, field: &'static str) -> ExprId {
+        let value = MARKER + self.operands.len() as i64;
+        let mut encoding = vec![0x42];
+        leb128(value, &mut encoding);
+        self.operands.push((encoding, field));
+        self.builder.i64_const(value)
+    }
+
+    fn operands(&mut self, field: &'static str) -> Box<[ExprId]> {
+        vec![self.operand(field), self.operand(field)].into_boxed_slice()
+    }
+}
+
+fn leb128(mut value: i64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        out.push(if done { byte } else { byte | 0x80 });
+        if done {
+            return;
+        }
+    }
+}
+
+/// Build an expression with `build`, emit it, and check that its operands
+/// appear in the emitted code in the order `operand_order` gives.
+///
+/// `build` is given a block to use as the target of any branches.
+fn check(build: impl FnOnce(&mut Module, &mut Case, BlockId) -> ExprId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let mut case = Case {
+        builder: FunctionBuilder::new(),
+        operands: Vec::new(),
+    };
+    // Branch to a block around the expression, so that the target is in
+    // scope.
+    let target = case.builder.block(Box::new([]), Box::new([])).id();
+    let expr = build(&mut module, &mut case, target);
+    let func = case
+        .builder
+        .finish(ty, vec![], vec![target.into()], &mut module);
+    local(&mut module, func).block_mut(target).exprs.push(expr);
+    let wasm = module.emit_wasm().unwrap();
+
+    let mut found = case
+        .operands
+        .iter()
+        .map(|(encoding, field)| {
+            let position = wasm
+                .windows(encoding.len())
+                .position(|w| w == &encoding[..])
+                .unwrap();
+            (position, *field)
+        })
+        .collect::<Vec<_>>();
+    found.sort();
+    let mut emitted = found.into_iter().map(|(_, f)| f).collect::<Vec<_>>();
+    emitted.dedup();
+
+    assert_eq!(emitted, local(&mut module, func).get(expr).operand_order());
+}
+
+fn local(module: &mut Module, func: FunctionId) -> &mut LocalFunction {
+    match &mut module.funcs.get_mut(func).kind {
+        FunctionKind::Local(f) => f,
+        _ => panic!("not a local function"),
+    }
+}
+
+#[test]
+fn calls() {
+    check(|module, case, _| {
+        let ty = module.types.add(&[ValType::I64, ValType::I64], &[]);
+        let f = module.add_import_func("env", "f", ty);
+        let args = case.operands("args");
+        case.builder.call(f, args)
+    });
+    check(|module, case, _| {
+        let ty = module.types.add(&[ValType::I64, ValType::I64], &[]);
+        let table = module
+            .tables
+            .add_local(1, None, TableKind::Function(FunctionTable::default()));
+        let func = case.operand("func");
+        let args = case.operands("args");
+        case.builder.call_indirect(ty, table, func, args)
+    });
+}
+
+#[test]
+fn variables() {
+    check(|module, case, _| {
+        let local = module.locals.add(ValType::I64);
+        let value = case.operand("value");
+        case.builder.local_set(local, value)
+    });
+    check(|module, case, _| {
+        let local = module.locals.add(ValType::I64);
+        let value = case.operand("value");
+        case.builder.local_tee(local, value)
+    });
+    check(|module, case, _| {
+        let global = module
+            .globals
+            .add_local(ValType::I64, true, InitExpr::Value(Value::I64(0)));
+        let value = case.operand("value");
+        case.builder.global_set(global, value)
+    });
+}
+
+#[test]
+fn operators() {
+    check(|_, case, _| {
+        let lhs = case.operand("lhs");
+        let rhs = case.operand("rhs");
+        case.builder.binop(BinaryOp::I64Sub, lhs, rhs)
+    });
+    check(|_, case, _| {
+        let expr = case.operand("expr");
+        case.builder.unop(UnaryOp::I64Eqz, expr)
+    });
+    check(|_, case, _| {
+        let condition = case.operand("condition");
+        let consequent = case.operand("consequent");
+        let alternative = case.operand("alternative");
+        case.builder
+            .select(condition, consequent, alternative, None)
+    });
+    check(|_, case, _| {
+        let expr = case.operand("expr");
+        case.builder.drop(expr)
+    });
+    check(|_, case, _| {
+        let before = vec![case.operand("before")];
+        let value = case.operand("value");
+        let after = vec![case.operand("after")];
+        case.builder.with_side_effects(before, value, after)
+    });
+}
+
+#[test]
+fn branches() {
+    check(|_, case, target| {
+        let args = case.operands("args");
+        case.builder.br(target, args)
+    });
+    check(|_, case, target| {
+        let condition = case.operand("condition");
+        let args = case.operands("args");
+        case.builder.br_if(condition, target, args)
+    });
+    check(|_, case, target| {
+        let which = case.operand("which");
+        let args = case.operands("args");
+        case.builder
+            .br_table(which, Box::new([target]), target, args)
+    });
+    check(|_, case, _| {
+        let values = case.operands("values");
+        case.builder.return_(values)
+    });
+}
+
+#[test]
+fn memory() {
+    let arg = MemArg {
+        align: 8,
+        offset: 0,
+    };
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let pages = case.operand("pages");
+        case.builder.memory_grow(memory, pages)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let data = module.data.add(vec![1, 2, 3]);
+        let memory_offset = case.operand("memory_offset");
+        let data_offset = case.operand("data_offset");
+        let len = case.operand("len");
+        case.builder
+            .memory_init(memory, data, memory_offset, data_offset, len)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let dst_offset = case.operand("dst_offset");
+        let src_offset = case.operand("src_offset");
+        let len = case.operand("len");
+        case.builder
+            .memory_copy(memory, memory, dst_offset, src_offset, len)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let offset = case.operand("offset");
+        let value = case.operand("value");
+        let len = case.operand("len");
+        case.builder.memory_fill(memory, offset, value, len)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let address = case.operand("address");
+        case.builder
+            .load(memory, LoadKind::I64 { atomic: false }, arg, address)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(false, 1, None);
+        let address = case.operand("address");
+        let value = case.operand("value");
+        case.builder.store(
+            memory,
+            StoreKind::I64 { atomic: false },
+            arg,
+            address,
+            value,
+        )
+    });
+}
+
+#[test]
+fn atomics() {
+    let arg = MemArg {
+        align: 8,
+        offset: 0,
+    };
+    check(|module, case, _| {
+        let memory = module.memories.add_local(true, 1, Some(1));
+        let address = case.operand("address");
+        let value = case.operand("value");
+        case.builder
+            .atomic_rmw(memory, AtomicOp::Add, AtomicWidth::I64, arg, address, value)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(true, 1, Some(1));
+        let address = case.operand("address");
+        let expected = case.operand("expected");
+        let replacement = case.operand("replacement");
+        case.builder.cmpxchg(
+            memory,
+            AtomicWidth::I64,
+            arg,
+            address,
+            expected,
+            replacement,
+        )
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(true, 1, Some(1));
+        let address = case.operand("address");
+        let count = case.operand("count");
+        case.builder.atomic_notify(memory, arg, address, count)
+    });
+    check(|module, case, _| {
+        let memory = module.memories.add_local(true, 1, Some(1));
+        let address = case.operand("address");
+        let expected = case.operand("expected");
+        let timeout = case.operand("timeout");
+        case.builder
+            .atomic_wait(memory, arg, address, expected, timeout, true)
+    });
+}
+
+#[test]
+fn tables() {
+    let table = |module: &mut Module| {
+        module
+            .tables
+            .add_local(1, None, TableKind::Function(FunctionTable::default()))
+    };
+    check(|module, case, _| {
+        let table = table(module);
+        let index = case.operand("index");
+        case.builder.table_get(table, index)
+    });
+    check(|module, case, _| {
+        let table = table(module);
+        let index = case.operand("index");
+        let value = case.operand("value");
+        case.builder.table_set(table, index, value)
+    });
+    check(|module, case, _| {
+        let table = table(module);
+        let amount = case.operand("amount");
+        let value = case.operand("value");
+        case.builder.table_grow(table, amount, value)
+    });
+    check(|_, case, _| {
+        let value = case.operand("value");
+        case.builder.ref_is_null(value)
+    });
+}
+
+#[test]
+fn simd() {
+    check(|_, case, _| {
+        let mask = case.operand("mask");
+        let v1 = case.operand("v1");
+        let v2 = case.operand("v2");
+        case.builder.v128_bitselect(mask, v1, v2)
+    });
+    check(|_, case, _| {
+        let lo = case.operand("lo");
+        let hi = case.operand("hi");
+        case.builder.v128_shuffle([0; 16], lo, hi)
+    });
+}
+
+#[test]
+fn select_parses_consequent_first() {
+    // (func (param i32) (result i32)
+    //   (select (i32.const 1) (i32.const 2) (local.get 0)))
+    let body = [0x00, 0x41, 0x01, 0x41, 0x02, 0x20, 0x00, 0x1b, 0x0b];
+    let wasm = walrus_tests_utils::module(&[
+        (0x01, &[0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f]),
+        (0x03, &[0x01, 0x00]),
+        (0x0a, &[&[0x01, 0x09][..], &body].concat()),
+    ]);
+    let module = Module::from_buffer(&wasm).unwrap();
+
+    // `select` returns its first operand when the condition is true.
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let select = func.block(func.entry_block()).exprs[0];
+    let e = match func.get(select) {
+        Expr::Select(e) => e,
+        _ => panic!("expected a select"),
+    };
+    let value = |id| match func.get(id) {
+        Expr::Const(Const {
+            value: Value::I32(n),
+        }) => *n,
+        _ => panic!("expected a constant"),
+    };
+    assert_eq!(value(e.consequent), 1);
+    assert_eq!(value(e.alternative), 2);
+
+    // And it's emitted in the same order it was parsed in.
+    let emitted = module.emit_wasm().unwrap();
+    assert_eq!(function_bodies(&emitted), [&body[..]]);
+}
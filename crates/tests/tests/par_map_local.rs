@@ -0,0 +1,121 @@
+//! Tests for mapping over local functions in parallel.
+
+use std::thread;
+use std::time::Duration;
+use walrus::ir::{Expr, Value};
+use walrus::{FunctionBuilder, FunctionId, InitExpr, Module, ValType};
+
+/// A module with `n` functions, each returning its position.
+fn module(n: usize) -> (Module, Vec<FunctionId>) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let ids = (0..n)
+        .map(|i| {
+            let mut builder = FunctionBuilder::new();
+            let value = builder.i32_const(i as i32);
+            builder.finish(ty, vec![], vec![value], &mut module)
+        })
+        .collect();
+    (module, ids)
+}
+
+fn pool() -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(8)
+        .build()
+        .unwrap()
+}
+
+/// Take longer for some functions than others, so that they finish out of
+/// order.
+fn stall(id: FunctionId) {
+    thread::sleep(Duration::from_micros((7 - id.index() % 7) as u64 * 50));
+}
+
+#[test]
+fn results_are_sorted_by_id() {
+    let (module, ids) = module(200);
+    let pool = pool();
+    for _ in 0..5 {
+        let results = pool.install(|| {
+            module.funcs.par_map_local(|id, func| {
+                stall(id);
+                (id, func.size())
+            })
+        });
+        assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+        assert!(results.iter().all(|(id, (same, _))| id == same));
+    }
+}
+
+#[test]
+fn first_error_is_reported_with_its_function() {
+    let (module, ids) = module(200);
+    let pool = pool();
+    let failing = [ids[150], ids[42], ids[199]];
+    for _ in 0..5 {
+        let result = pool.install(|| {
+            module.funcs.try_par_map_local(|id, _| {
+                stall(id);
+                if failing.contains(&id) {
+                    Err(id.index())
+                } else {
+                    Ok(id.index())
+                }
+            })
+        });
+        assert_eq!(result.unwrap_err(), (ids[42], ids[42].index()));
+    }
+
+    let result = module.funcs.try_par_map_local(|id, _| Ok::<_, ()>(id));
+    let results = result.unwrap();
+    assert_eq!(results.len(), ids.len());
+    assert!(results
+        .iter()
+        .zip(&ids)
+        .all(|((id, same), i)| id == same && id == i));
+}
+
+#[test]
+fn emitting_is_deterministic() {
+    let (module, _) = module(200);
+    let expected = module.emit_wasm().unwrap();
+    let pool = pool();
+    for _ in 0..5 {
+        assert_eq!(pool.install(|| module.emit_wasm().unwrap()), expected);
+    }
+}
+
+#[test]
+fn functions_can_be_changed_while_reading_the_module() {
+    let (mut module, ids) = module(200);
+    let init = InitExpr::Value(Value::I32(7));
+    let global = module.globals.add_local(ValType::I32, false, init);
+
+    // Replace every body with a `global.get` of the only global, looked up
+    // through the rest of the module.
+    let pool = pool();
+    let results = pool.install(|| {
+        module.par_map_local_mut(|info, id, func| {
+            stall(id);
+            let global = info.globals.iter().next().unwrap().id();
+            let get = func.builder_mut().global_get(global);
+            let entry = func.entry_block();
+            func.block_mut(entry).exprs = vec![get];
+            info.types.get(func.ty).results().len()
+        })
+    });
+    assert_eq!(results.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+    assert!(results.iter().all(|(_, results)| *results == 1));
+
+    let (funcs, info) = module.split_funcs_mut();
+    assert_eq!(info.globals.get(global).ty, ValType::I32);
+    for (_, func) in funcs.iter_local() {
+        let entry = func.block(func.entry_block());
+        match func.get(entry.exprs[0]) {
+            Expr::GlobalGet(g) => assert_eq!(g.global, global),
+            _ => panic!("expected a global.get"),
+        }
+    }
+    assert!(wasmparser::validate(&module.emit_wasm().unwrap(), None));
+}
@@ -0,0 +1,216 @@
+//! Tests for running pipelines of passes with `PassManager`.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use walrus::ir::*;
+use walrus::passes::{builtin, Pass, PassManager, PassStats};
+use walrus::{ExportItem, FunctionBuilder, FunctionKind, LocalFunction, Module, Result, ValType};
+
+/// Folds `i32.add` of two constants into a constant.
+struct ConstFold;
+
+impl Pass for ConstFold {
+    fn name(&self) -> &str {
+        "const-fold"
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        struct Fold<'a> {
+            func: &'a mut LocalFunction,
+            changed: bool,
+        }
+
+        impl VisitorMut for Fold<'_> {
+            fn local_function_mut(&mut self) -> &mut LocalFunction {
+                self.func
+            }
+
+            fn visit_expr_mut(&mut self, expr: &mut Expr) {
+                expr.visit_mut(self);
+                let (lhs, rhs) = match expr {
+                    Expr::Binop(Binop {
+                        op: BinaryOp::I32Add,
+                        lhs,
+                        rhs,
+                    }) => (*lhs, *rhs),
+                    _ => return,
+                };
+                let value = match (self.func.get(lhs), self.func.get(rhs)) {
+                    (Expr::Const(a), Expr::Const(b)) => match (&a.value, &b.value) {
+                        (Value::I32(a), Value::I32(b)) => Value::I32(a.wrapping_add(*b)),
+                        _ => return,
+                    },
+                    _ => return,
+                };
+                *expr = Expr::Const(Const { value });
+                self.changed = true;
+            }
+        }
+
+        let mut changed = false;
+        for func in module.funcs.iter_mut() {
+            if let FunctionKind::Local(func) = &mut func.kind {
+                let entry = func.entry_block().into();
+                let mut fold = Fold {
+                    func,
+                    changed: false,
+                };
+                dfs_pre_order_mut(&mut fold, entry);
+                changed |= fold.changed;
+            }
+        }
+        Ok(PassStats { changed })
+    }
+}
+
+/// Removes dropped constants.
+struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &str {
+        "dce"
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        let mut changed = false;
+        for func in module.funcs.iter_mut() {
+            if let FunctionKind::Local(func) = &mut func.kind {
+                let entry = func.entry_block();
+                let exprs = func.block(entry).exprs.clone();
+                let kept = exprs
+                    .iter()
+                    .cloned()
+                    .filter(|e| match func.get(*e) {
+                        Expr::Drop(d) => !func.get(d.expr).is_const(),
+                        _ => true,
+                    })
+                    .collect::<Vec<_>>();
+                changed |= kept.len() != exprs.len();
+                func.block_mut(entry).exprs = kept;
+            }
+        }
+        Ok(PassStats { changed })
+    }
+}
+
+/// Wraps a pass, checking that it never makes the emitted module bigger.
+struct NonIncreasing<P> {
+    pass: P,
+    size: Rc<Cell<usize>>,
+}
+
+impl<P: Pass> Pass for NonIncreasing<P> {
+    fn name(&self) -> &str {
+        self.pass.name()
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        let stats = self.pass.run(module)?;
+        let size = module.emit_wasm()?.len();
+        assert!(size <= self.size.get(), "`{}` grew the module", self.name());
+        self.size.set(size);
+        Ok(stats)
+    }
+}
+
+/// A module exporting
+///
+/// ```wat
+/// (func (result i32)
+///   (drop (i32.add (i32.const 1) (i32.const 2)))
+///   (drop (i32.const 5))
+///   (i32.add (i32.const 3) (i32.const 4)))
+/// ```
+///
+/// along with an unused function.
+fn fixture() -> Module {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    let (a, b) = (builder.i32_const(1), builder.i32_const(2));
+    let add = builder.binop(BinaryOp::I32Add, a, b);
+    exprs.push(builder.drop(add));
+    let five = builder.i32_const(5);
+    exprs.push(builder.drop(five));
+    let (a, b) = (builder.i32_const(3), builder.i32_const(4));
+    exprs.push(builder.binop(BinaryOp::I32Add, a, b));
+    let f = builder.finish(ty, vec![], exprs, &mut module);
+    module.exports.add("f", ExportItem::Function(f));
+
+    let mut builder = FunctionBuilder::new();
+    let unused = builder.i32_const(0);
+    builder.finish(ty, vec![], vec![unused], &mut module);
+    module
+}
+
+#[test]
+fn gc_const_fold_dce() {
+    let mut module = fixture();
+    let size = Rc::new(Cell::new(module.emit_wasm().unwrap().len()));
+
+    let mut manager = PassManager::new();
+    manager
+        .validate_between_passes(true)
+        .time_passes(true)
+        .add(Box::new(NonIncreasing {
+            pass: builtin("gc").unwrap(),
+            size: size.clone(),
+        }))
+        .add_fixpoint(vec![
+            Box::new(NonIncreasing {
+                pass: ConstFold,
+                size: size.clone(),
+            }),
+            Box::new(NonIncreasing {
+                pass: Dce,
+                size: size.clone(),
+            }),
+        ]);
+    let reports = manager.run(&mut module).unwrap();
+
+    let names = reports.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["gc", "const-fold", "dce"]);
+    assert!(reports.iter().all(|r| r.stats.changed));
+    assert!(reports.iter().all(|r| r.duration.is_some()));
+    // The fixpoint group runs once more to notice nothing changes.
+    assert_eq!(reports[1].runs, 2);
+    assert_eq!(reports[2].runs, 2);
+
+    assert_eq!(module.funcs.iter().count(), 1);
+    let func = match &module.funcs.iter().next().unwrap().kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    let exprs = &func.block(func.entry_block()).exprs;
+    assert_eq!(exprs.len(), 1);
+    assert!(func.get(exprs[0]).is_const());
+}
+
+#[test]
+fn invalid_module_blames_the_pass() {
+    struct Breaks;
+
+    impl Pass for Breaks {
+        fn name(&self) -> &str {
+            "breaks"
+        }
+
+        fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+            // An exported function whose signature the validator rejects as a
+            // start function.
+            let f = module.exports.iter().next().unwrap().item;
+            if let ExportItem::Function(f) = f {
+                module.start = Some(f);
+            }
+            Ok(PassStats { changed: true })
+        }
+    }
+
+    let mut module = fixture();
+    let mut manager = PassManager::new();
+    manager.validate_between_passes(true).add(Box::new(Breaks));
+    let err = manager.run(&mut module).unwrap_err();
+    assert!(err.to_string().contains("after pass `breaks`"));
+}
@@ -0,0 +1,208 @@
+//! Tests for lazily initializing memory from passive data segments.
+
+use walrus::ir::*;
+use walrus::passes::ActiveSegment;
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, InitExpr, InitTarget};
+use walrus::{LocalFunction, MemoryId, Module};
+
+#[test]
+fn lazy_initializer() {
+    let blob = b"hello, world".to_vec();
+
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let data = module.data.add_passive(blob.clone());
+    let ty = module.types.add(&[], &[]);
+
+    // (func
+    //   (memory.init $data (i32.const 1024) (i32.const 0) (i32.const 12))
+    //   (data.drop $data))
+    let mut builder = FunctionBuilder::new();
+    let dst = builder.i32_const(1024);
+    let src = builder.i32_const(0);
+    let len = builder.i32_const(blob.len() as i32);
+    let init = builder.memory_init(memory, data, dst, src, len);
+    let dropped = builder.data_drop(data);
+    let init = builder.finish(ty, vec![], vec![init, dropped], &mut module);
+    module.exports.add("init", ExportItem::Function(init));
+
+    let wasm = module.emit_wasm().unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+
+    let data = module.data.iter().collect::<Vec<_>>();
+    assert_eq!(data.len(), 1);
+    assert_eq!(data[0].value, blob);
+
+    let func = match &module.funcs.iter().next().unwrap().kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    let exprs = &func.block(func.entry_block()).exprs;
+    match (func.get(exprs[0]), func.get(exprs[1])) {
+        (Expr::MemoryInit(init), Expr::DataDrop(dropped)) => {
+            assert_eq!(init.data, data[0].id());
+            assert_eq!(dropped.data, data[0].id());
+        }
+        _ => panic!("expected memory.init and data.drop"),
+    }
+}
+
+fn local(module: &Module, func: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+/// The first `len` bytes of `memory` once its active segments are applied and
+/// then, if there is one, the start function's top-level `memory.init`s of
+/// constant ranges have run.
+fn image(module: &Module, memory: MemoryId, len: usize) -> Vec<u8> {
+    let mut image = vec![0; len];
+    let mut write = |offset: usize, bytes: &[u8]| {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    for (offset, bytes) in module.memories.get(memory).data.iter() {
+        match offset {
+            InitExpr::Value(Value::I32(n)) => write(n as usize, bytes),
+            _ => panic!("non-constant offset"),
+        }
+    }
+    if let Some(start) = module.start {
+        let func = local(module, start);
+        let constant = |id| match func.get(id) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => *n as usize,
+            _ => panic!("non-constant operand"),
+        };
+        for expr in func.block(func.entry_block()).exprs.iter() {
+            if let Expr::MemoryInit(init) = func.get(*expr) {
+                assert_eq!(init.memory, memory);
+                let src = constant(init.data_offset);
+                let len = constant(init.len);
+                let bytes = &module.data.get(init.data).value[src..src + len];
+                write(constant(init.memory_offset), bytes);
+            }
+        }
+    }
+    image
+}
+
+fn segments() -> (Module, MemoryId) {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let data = &mut module.memories.get_mut(memory).data;
+    data.add_absolute(16, b"first".to_vec());
+    data.add_absolute(0, b"second segment".to_vec());
+    data.add_absolute(32, b"third".to_vec());
+    (module, memory)
+}
+
+#[test]
+fn make_data_passive_preserves_the_memory_image() {
+    let (mut module, memory) = segments();
+    let before = image(&module, memory, 64);
+
+    let segment = |index| ActiveSegment { memory, index };
+    let (first, init) = module
+        .make_data_passive(segment(0), InitTarget::Start)
+        .unwrap();
+    assert_eq!(module.start, Some(init));
+    assert_eq!(module.data.get(first).value, b"first");
+    let (third, appended) = module
+        .make_data_passive(segment(1), InitTarget::Append(init))
+        .unwrap();
+    assert_eq!(appended, init);
+    assert_eq!(module.data.get(third).value, b"third");
+    assert_eq!(module.memories.get(memory).data.iter().count(), 1);
+    assert_eq!(image(&module, memory, 64), before);
+
+    let func = local(&module, init);
+    let data = func
+        .block(func.entry_block())
+        .exprs
+        .iter()
+        .map(|e| match func.get(*e) {
+            Expr::MemoryInit(e) => e.data,
+            Expr::DataDrop(e) => e.data,
+            _ => panic!("expected memory.init or data.drop"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(data, [first, first, third, third]);
+
+    // The passive segments, and the data count section they need, survive a
+    // round trip.
+    let wasm = module.emit_wasm().unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.data.iter().count(), 2);
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(image(&module, memory, 64), before);
+}
+
+#[test]
+fn make_data_passive_calls_the_previous_start_function() {
+    let (mut module, memory) = segments();
+    let ty = module.types.add(&[], &[]);
+    let old_start = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.start = Some(old_start);
+
+    let segment = ActiveSegment { memory, index: 2 };
+    let (_, init) = module
+        .make_data_passive(segment, InitTarget::Start)
+        .unwrap();
+    assert_eq!(module.start, Some(init));
+    let func = local(&module, init);
+    let last = *func.block(func.entry_block()).exprs.last().unwrap();
+    match func.get(last) {
+        Expr::Call(call) => assert_eq!(call.func, old_start),
+        _ => panic!("expected a call of the previous start function"),
+    }
+
+    let (_, other) = module
+        .make_data_passive(ActiveSegment { memory, index: 0 }, InitTarget::NewFunction)
+        .unwrap();
+    assert_eq!(module.start, Some(init));
+    assert_ne!(other, init);
+}
+
+#[test]
+fn make_data_passive_rejects_overlaps_and_relative_offsets() {
+    let (mut module, memory) = segments();
+    let segment = |index| ActiveSegment { memory, index };
+
+    // Initializing "second segment" after "late" is applied would overwrite
+    // it.
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_absolute(8, b"late".to_vec());
+    assert!(module
+        .make_data_passive(segment(1), InitTarget::NewFunction)
+        .is_err());
+
+    let global =
+        module
+            .globals
+            .add_local(walrus::ValType::I32, false, InitExpr::Value(Value::I32(48)));
+    module
+        .memories
+        .get_mut(memory)
+        .data
+        .add_relative(global, b"relative".to_vec());
+    assert!(module
+        .make_data_passive(segment(4), InitTarget::NewFunction)
+        .is_err());
+    assert!(module
+        .make_data_passive(segment(2), InitTarget::NewFunction)
+        .is_err());
+    assert!(module
+        .make_data_passive(segment(5), InitTarget::NewFunction)
+        .is_err());
+
+    // Nothing was changed by the failed attempts.
+    assert_eq!(module.memories.get(memory).data.iter().count(), 5);
+    assert_eq!(module.data.iter().count(), 0);
+    assert_eq!(module.funcs.iter().count(), 0);
+}
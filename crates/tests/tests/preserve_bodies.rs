@@ -0,0 +1,138 @@
+//! Tests for re-emitting the original bytes of unmodified function bodies.
+
+use walrus::{FunctionId, FunctionKind, Module, ModuleConfig};
+
+/// A body with two separately declared `i32` locals and an over-long LEB
+/// encoding of `i32.const 0`, neither of which walrus would emit itself.
+const BODY: &[u8] = &[0x02, 0x01, 0x7f, 0x01, 0x7f, 0x41, 0x80, 0x00, 0x1a, 0x0b];
+
+/// A module defining three `(func)`s, all with `BODY` as their body.
+fn fixture() -> Vec<u8> {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x03, 0x04, 0x03, 0x00, 0x00, 0x00]); // function section
+
+    let mut code = vec![0x03];
+    for _ in 0..3 {
+        code.push(BODY.len() as u8);
+        code.extend(BODY);
+    }
+    wasm.push(0x0a);
+    wasm.push(code.len() as u8);
+    wasm.extend(code);
+    wasm
+}
+
+fn config(preserve: bool) -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false)
+        .preserve_original_bodies(preserve);
+    config
+}
+
+fn function_ids(module: &Module) -> Vec<FunctionId> {
+    module.funcs.iter().map(|f| f.id()).collect()
+}
+
+fn read_leb(wasm: &[u8], pos: &mut usize) -> usize {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        let byte = wasm[*pos];
+        *pos += 1;
+        ret |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return ret;
+        }
+    }
+}
+
+/// Extract the raw bodies from the code section of the given wasm.
+fn code_bodies(wasm: &[u8]) -> Vec<Vec<u8>> {
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let len = read_leb(wasm, &mut pos);
+        if id != 0x0a {
+            pos += len;
+            continue;
+        }
+        let count = read_leb(wasm, &mut pos);
+        let mut bodies = Vec::new();
+        for _ in 0..count {
+            let len = read_leb(wasm, &mut pos);
+            bodies.push(wasm[pos..pos + len].to_vec());
+            pos += len;
+        }
+        return bodies;
+    }
+    panic!("no code section")
+}
+
+fn modify(module: &mut Module, id: FunctionId) {
+    let local = match &mut module.funcs.get_mut(id).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    let entry = local.entry_block();
+    let value = local.builder_mut().i32_const(1);
+    let expr = local.builder_mut().drop(value);
+    local.block_mut(entry).exprs.push(expr);
+}
+
+#[test]
+fn untouched_bodies_are_preserved() {
+    let mut module = config(true).parse(&fixture()).unwrap();
+    let ids = function_ids(&module);
+    modify(&mut module, ids[1]);
+
+    let bodies = code_bodies(&module.emit_wasm().unwrap());
+    assert_eq!(bodies.len(), 3);
+    assert_eq!(bodies[0], BODY);
+    assert_ne!(bodies[1], BODY);
+    assert_eq!(bodies[2], BODY);
+}
+
+#[test]
+fn bodies_are_reencoded_by_default() {
+    let module = config(false).parse(&fixture()).unwrap();
+    let bodies = code_bodies(&module.emit_wasm().unwrap());
+    assert_eq!(bodies.len(), 3);
+    assert!(bodies.iter().all(|body| body != BODY));
+}
+
+#[test]
+fn changed_indices_fall_back_to_reencoding() {
+    let mut module = config(true).parse(&fixture()).unwrap();
+    let ty = module.types.add(&[], &[]);
+    module.add_import_func("env", "f", ty);
+
+    let bodies = code_bodies(&module.emit_wasm().unwrap());
+    assert_eq!(bodies.len(), 3);
+    assert!(bodies.iter().all(|body| body != BODY));
+}
+
+#[test]
+fn changed_tag_indices_fall_back_to_reencoding() {
+    // A module with one tag and one function, whose body declares an unused
+    // local and is `throw 0` with an over-long LEB encoding of the tag index.
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]); // type section
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]); // function section
+    wasm.extend(&[0x0d, 0x03, 0x01, 0x00, 0x00]); // tag section
+    wasm.extend(&[
+        0x0a, 0x09, 0x01, 0x07, 0x01, 0x01, 0x7f, 0x08, 0x80, 0x00, 0x0b,
+    ]); // code section
+
+    let mut module = config(true).parse(&wasm).unwrap();
+    let ty = module.types.add(&[], &[]);
+    module.add_import_tag("env", "t", ty);
+
+    // The local tag comes after the imported one now.
+    let bodies = code_bodies(&module.emit_wasm().unwrap());
+    assert_eq!(bodies, [[0x00, 0x08, 0x01, 0x0b]]);
+}
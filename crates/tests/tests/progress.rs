@@ -0,0 +1,88 @@
+//! Tests for progress reporting and timing of parsing and emitting.
+
+use std::sync::{Arc, Mutex};
+use walrus::{FunctionBuilder, Module, ModuleConfig, Phase, ValType};
+
+/// A module with enough functions for progress to be reported partway
+/// through its function bodies.
+fn fixture() -> Vec<u8> {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    for i in 0..600 {
+        let mut builder = FunctionBuilder::new();
+        let value = builder.i32_const(i);
+        let f = builder.finish(ty, vec![], vec![value], &mut module);
+        if i % 100 == 0 {
+            module.exports.add(&format!("f{}", i), f);
+        }
+    }
+    module.emit_wasm().unwrap()
+}
+
+#[test]
+fn progress_is_reported_for_every_phase() {
+    let wasm = fixture();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let mut config = ModuleConfig::new();
+    let r = reports.clone();
+    config.on_progress(move |phase, current, total| {
+        r.lock().unwrap().push((phase, current, total));
+    });
+
+    let module = config.parse(&wasm).unwrap();
+    let parsed = reports.lock().unwrap().drain(..).collect::<Vec<_>>();
+    module.emit_wasm().unwrap();
+    let emitted = reports.lock().unwrap().drain(..).collect::<Vec<_>>();
+
+    let of = |reports: &[(Phase, usize, usize)], phase| {
+        reports
+            .iter()
+            .filter(|r| r.0 == phase)
+            .map(|r| (r.1, r.2))
+            .collect::<Vec<_>>()
+    };
+
+    let sections = of(&parsed, Phase::ParseSections);
+    assert!(sections.len() > 1);
+    assert!(sections.windows(2).all(|w| w[0].0 <= w[1].0));
+    assert_eq!(*sections.last().unwrap(), (wasm.len(), wasm.len()));
+
+    let functions = of(&parsed, Phase::ParseFunctions);
+    assert_eq!(functions, [(256, 600), (512, 600), (600, 600)]);
+    assert_eq!(of(&parsed, Phase::Validate), [(0, 1), (1, 1)]);
+
+    // The callback is kept on the parsed module's configuration.
+    let sections = of(&emitted, Phase::EmitSections);
+    assert_eq!(sections.len(), 13);
+    assert_eq!(*sections.last().unwrap(), (13, 13));
+    let functions = of(&emitted, Phase::EmitFunctions);
+    assert_eq!(functions, [(256, 600), (512, 600), (600, 600)]);
+}
+
+#[test]
+fn stats_cover_every_phase() {
+    let wasm = fixture();
+    let module = Module::from_buffer(&wasm).unwrap();
+    let stats = module.parse_stats().unwrap();
+    let phases = stats.phases.iter().map(|p| p.phase).collect::<Vec<_>>();
+    assert_eq!(
+        phases,
+        [Phase::ParseSections, Phase::ParseFunctions, Phase::Validate]
+    );
+    assert_eq!(stats.phase(Phase::ParseFunctions).unwrap().items, 600);
+    assert_eq!(stats.phase(Phase::Validate).unwrap().items, 600);
+    // type, function, export and code
+    assert_eq!(stats.phase(Phase::ParseSections).unwrap().items, 4);
+    assert!(stats.total() >= stats.phase(Phase::ParseFunctions).unwrap().time);
+
+    let (emitted, stats) = module.emit_wasm_with_stats().unwrap();
+    assert_eq!(emitted, module.emit_wasm().unwrap());
+    let phases = stats.phases.iter().map(|p| p.phase).collect::<Vec<_>>();
+    assert_eq!(phases, [Phase::EmitSections, Phase::EmitFunctions]);
+    // ... and the producers section, which parsing added walrus to
+    assert_eq!(stats.phase(Phase::EmitSections).unwrap().items, 5);
+    assert_eq!(stats.phase(Phase::EmitFunctions).unwrap().items, 600);
+
+    // Modules built from scratch weren't parsed.
+    assert!(Module::default().parse_stats().is_none());
+}
@@ -0,0 +1,134 @@
+//! Tests for retaining the original bytes of custom sections that walrus
+//! parses into typed form.
+
+use walrus::{ExportItem, FunctionBuilder, Module, ModuleConfig, RawCustomSection};
+use walrus::{Reemission, ValType};
+
+/// The payload of each custom section called `name` in `wasm`.
+fn custom_payloads<'a>(wasm: &'a [u8], name: &str) -> Vec<&'a [u8]> {
+    let mut reader = wasmparser::ModuleReader::new(wasm).unwrap();
+    let mut payloads = Vec::new();
+    while !reader.eof() {
+        let section = reader.read().unwrap();
+        match section.code {
+            wasmparser::SectionCode::Custom { name: n, .. } if n == name => {
+                let mut reader = section.get_binary_reader();
+                let len = reader.bytes_remaining();
+                payloads.push(reader.read_bytes(len).unwrap());
+            }
+            _ => {}
+        }
+    }
+    payloads
+}
+
+/// A module with a named function and a producers section.
+fn wasm() -> Vec<u8> {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let one = builder.i32_const(1);
+    let func = builder.finish(ty, vec![], vec![one], &mut module);
+    module.funcs.get_mut(func).name = Some("one".to_string());
+    module.exports.add("one", ExportItem::Function(func));
+    module.producers.add_language("Rust", "");
+    module.emit_wasm().unwrap()
+}
+
+#[test]
+fn raw_bytes_match_the_input() {
+    let wasm = wasm();
+    let mut config = ModuleConfig::new();
+    config.retain_raw_custom_sections(true);
+    let module = config.parse(&wasm).unwrap();
+
+    for name in &["name", "producers"] {
+        let input = custom_payloads(&wasm, name);
+        assert_eq!(input.len(), 1);
+        assert_eq!(module.customs.raw_of(name), Some(input[0]));
+        assert_eq!(
+            module.customs.reemission(name),
+            Some(Reemission::Regenerated)
+        );
+    }
+
+    // The producers section is regenerated with walrus added to it.
+    let emitted = module.emit_wasm().unwrap();
+    assert_ne!(
+        custom_payloads(&emitted, "producers"),
+        custom_payloads(&wasm, "producers")
+    );
+    assert_eq!(
+        custom_payloads(&emitted, "name"),
+        custom_payloads(&wasm, "name")
+    );
+}
+
+#[test]
+fn raw_bytes_are_not_retained_by_default() {
+    let module = Module::from_buffer(&wasm()).unwrap();
+    assert_eq!(module.customs.raw_of("name"), None);
+    assert_eq!(
+        module.customs.reemission("name"),
+        Some(Reemission::Regenerated)
+    );
+    assert_eq!(module.customs.reemission("hello"), None);
+}
+
+#[test]
+fn unknown_sections_are_preserved() {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    module.customs.add(RawCustomSection {
+        name: "hello".to_string(),
+        data: vec![1, 2, 3],
+    });
+    let wasm = module.emit_wasm().unwrap();
+
+    let mut config = ModuleConfig::new();
+    config.retain_raw_custom_sections(true);
+    let module = config.parse(&wasm).unwrap();
+    assert_eq!(module.customs.raw_of("hello"), Some(&[1, 2, 3][..]));
+    assert_eq!(
+        module.customs.reemission("hello"),
+        Some(Reemission::Preserved)
+    );
+
+    let emitted = module.emit_wasm().unwrap();
+    assert_eq!(
+        custom_payloads(&emitted, "hello"),
+        custom_payloads(&wasm, "hello")
+    );
+
+    // Whether or not the payload is retained.
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.customs.raw_of("hello"), None);
+    assert_eq!(
+        module.customs.reemission("hello"),
+        Some(Reemission::Preserved)
+    );
+}
+
+#[test]
+fn unparseable_sections_are_dropped() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    module.customs.add(RawCustomSection {
+        name: "producers".to_string(),
+        data: vec![0xff],
+    });
+    let wasm = module.emit_wasm().unwrap();
+
+    let mut config = ModuleConfig::new();
+    config.retain_raw_custom_sections(true);
+    let module = config.parse(&wasm).unwrap();
+    assert_eq!(module.customs.raw_of("producers"), Some(&[0xff][..]));
+    assert_eq!(
+        module.customs.reemission("producers"),
+        Some(Reemission::Dropped)
+    );
+}
@@ -0,0 +1,131 @@
+//! Tests for declaring the targets of `ref.func` when emitting.
+
+use walrus::{
+    FunctionBuilder, FunctionId, GlobalKind, InitExpr, Module, ModuleConfig, TypeId, ValType,
+};
+use walrus_tests_utils::{section, sections};
+
+/// A module with a `(func)` and a function which takes a reference to it with
+/// `ref.func`.
+fn fixture(config: ModuleConfig) -> (Module, TypeId, FunctionId) {
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[], &[]);
+    let target = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let mut builder = FunctionBuilder::new();
+    let reference = builder.ref_func(target);
+    let drop = builder.drop(reference);
+    builder.finish(ty, vec![], vec![drop], &mut module);
+    (module, ty, target)
+}
+
+/// The element section of `wasm`, if it has one.
+fn element_section(wasm: &[u8]) -> Option<&[u8]> {
+    sections(wasm)
+        .into_iter()
+        .find(|(id, _)| *id == 0x09)
+        .map(|(_, payload)| payload)
+}
+
+#[test]
+fn undeclared_targets_are_declared() {
+    let (module, _, target) = fixture(ModuleConfig::new());
+    assert_eq!(module.undeclared_ref_func_targets(), [target]);
+    walrus::passes::validate::run(&module).unwrap();
+
+    // One declared segment of function indices, holding just `target`.
+    let index = module.function_index(target).unwrap() as u8;
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        element_section(&wasm).unwrap(),
+        [0x01, 0x03, 0x00, 0x01, index]
+    );
+}
+
+#[test]
+fn declared_targets_arent_declared_again() {
+    let (mut module, _, target) = fixture(ModuleConfig::new());
+    module.exports.add("target", target);
+    assert!(module.undeclared_ref_func_targets().is_empty());
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(element_section(&wasm), None);
+
+    let (mut module, _, target) = fixture(ModuleConfig::new());
+    module.elements.add_passive(&[target]);
+    assert!(module.undeclared_ref_func_targets().is_empty());
+    let index = module.function_index(target).unwrap() as u8;
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        element_section(&wasm).unwrap(),
+        [0x01, 0x01, 0x00, 0x01, index]
+    );
+}
+
+#[test]
+fn extra_segment_comes_last() {
+    let (mut module, ty, target) = fixture(ModuleConfig::new());
+    let other = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.elements.add_passive(&[other]);
+    let target = module.function_index(target).unwrap() as u8;
+    let other = module.function_index(other).unwrap() as u8;
+
+    // The existing segment keeps its index by staying first.
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        element_section(&wasm).unwrap(),
+        [0x02, 0x01, 0x00, 0x01, other, 0x03, 0x00, 0x01, target]
+    );
+}
+
+#[test]
+fn declarations_can_be_turned_off() {
+    let mut config = ModuleConfig::new();
+    config.declare_ref_func_targets(false);
+    let (mut module, _, target) = fixture(config);
+    module.funcs.get_mut(target).name = Some("target".to_string());
+
+    let err = walrus::passes::validate::run(&module).unwrap_err();
+    assert!(err.to_string().contains("`target`"));
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(element_section(&wasm), None);
+
+    module.exports.add("target", target);
+    walrus::passes::validate::run(&module).unwrap();
+}
+
+#[test]
+fn global_initializers_declare_targets() {
+    let (mut module, _, target) = fixture(ModuleConfig::new());
+    let global = module
+        .globals
+        .add_local(ValType::Funcref, false, InitExpr::RefFunc(target));
+    assert!(module.undeclared_ref_func_targets().is_empty());
+    walrus::passes::validate::run(&module).unwrap();
+
+    let index = module.function_index(target).unwrap() as u8;
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(element_section(&wasm), None);
+    assert_eq!(section(&wasm, 0x06), [0x01, 0x70, 0x00, 0xd2, index, 0x0b]);
+
+    // And parses back as a reference to the same function.
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    match &parsed.globals.iter().next().unwrap().kind {
+        GlobalKind::Local(InitExpr::RefFunc(f)) => {
+            assert_eq!(parsed.function_index(*f), Some(u32::from(index)));
+        }
+        kind => panic!("unexpected global: {:?}", kind),
+    }
+
+    // The function stays alive as long as the global does.
+    module.exports.add("global", global);
+    walrus::passes::gc::run(&mut module);
+    assert!(module.funcs.iter().any(|f| f.id() == target));
+}
+
+#[test]
+fn global_initializers_must_be_funcref() {
+    let (mut module, _, target) = fixture(ModuleConfig::new());
+    module
+        .globals
+        .add_local(ValType::Externref, false, InitExpr::RefFunc(target));
+    assert!(walrus::passes::validate::run(&module).is_err());
+}
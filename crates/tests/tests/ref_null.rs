@@ -0,0 +1,97 @@
+//! Tests for the heap type immediate of `ref.null`.
+
+use walrus::ir::{Expr, RefType};
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType};
+use walrus_tests_utils::function_bodies;
+
+fn emit_ref_null(ty: RefType) -> Vec<u8> {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let func_ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let null = builder.ref_null(ty);
+    let is_null = builder.ref_is_null(null);
+    builder.finish(func_ty, vec![], vec![is_null], &mut module);
+    module.emit_wasm().unwrap()
+}
+
+/// Parse `wasm` back, returning the type of the `ref.null` its only function
+/// tests.
+fn parse_ref_null(wasm: &[u8]) -> RefType {
+    let module = Module::from_buffer(wasm).unwrap();
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let block = func.block(func.entry_block());
+    let exprs = block.exprs.iter().map(|e| func.get(*e)).collect::<Vec<_>>();
+    match &exprs[..] {
+        [Expr::RefIsNull(is_null)] => match func.get(is_null.value) {
+            Expr::RefNull(null) => null.ty,
+            e => panic!("expected a ref.null, found {:?}", e),
+        },
+        exprs => panic!("expected a ref.is_null, found {:?}", exprs),
+    }
+}
+
+#[test]
+fn ref_null_func() {
+    let wasm = emit_ref_null(RefType::Funcref);
+    let bodies = function_bodies(&wasm);
+    assert_eq!(bodies, [&[0x00, 0xd0, 0x70, 0xd1, 0x0b][..]]);
+    assert_eq!(parse_ref_null(&wasm), RefType::Funcref);
+}
+
+#[test]
+fn ref_null_extern() {
+    let wasm = emit_ref_null(RefType::Externref);
+    let bodies = function_bodies(&wasm);
+    assert_eq!(bodies, [&[0x00, 0xd0, 0x6f, 0xd1, 0x0b][..]]);
+    assert_eq!(parse_ref_null(&wasm), RefType::Externref);
+}
+
+#[test]
+fn ref_null_round_trips() {
+    let wasm = emit_ref_null(RefType::Funcref);
+    let module = Module::from_buffer(&wasm).unwrap();
+    let again = module.emit_wasm().unwrap();
+    assert_eq!(function_bodies(&again), function_bodies(&wasm));
+}
+
+#[test]
+fn ref_null_defaults_to_func() {
+    assert_eq!(RefType::default(), RefType::Funcref);
+    assert_eq!(RefType::default().val_type(), ValType::Funcref);
+}
+
+#[test]
+fn ref_is_null_of_a_number_is_invalid() {
+    let mut module = Module::default();
+    let func_ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let zero = builder.i32_const(0);
+    let is_null = builder.ref_is_null(zero);
+    builder.finish(func_ty, vec![], vec![is_null], &mut module);
+    assert!(walrus::passes::validate::run(&module).is_err());
+}
+
+#[test]
+fn parsed_ref_is_null_of_ref_null_func_is_valid() {
+    let wasm = emit_ref_null(RefType::Funcref);
+    let module = Module::from_buffer(&wasm).unwrap();
+    walrus::passes::validate::run(&module).unwrap();
+    assert_eq!(
+        parse_ref_null(&module.emit_wasm().unwrap()),
+        RefType::Funcref
+    );
+}
+
+#[test]
+fn parsing_ref_is_null_of_a_number_fails() {
+    // (ref.is_null (i32.const 0))
+    let wasm = walrus_tests_utils::module(&[
+        (1, &[0x01, 0x60, 0x00, 0x01, 0x7f]),
+        (3, &[0x01, 0x00]),
+        (10, &[0x01, 0x05, 0x00, 0x41, 0x00, 0xd1, 0x0b]),
+    ]);
+    assert!(Module::from_buffer(&wasm).is_err());
+}
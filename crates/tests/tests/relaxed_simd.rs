@@ -0,0 +1,133 @@
+//! Tests for the instructions of the relaxed SIMD proposal.
+
+use walrus::ir::*;
+use walrus::passes::validate;
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType, WasmFeatures};
+use walrus_tests_utils::function_bodies;
+
+enum Op {
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    Ternary(TernaryOp),
+}
+
+/// Every relaxed SIMD instruction, with its opcode after the 0xfd prefix.
+fn ops() -> Vec<(Op, u32)> {
+    vec![
+        (Op::Binary(BinaryOp::I8x16RelaxedSwizzle), 0x100),
+        (Op::Unary(UnaryOp::I32x4RelaxedTruncSF32x4), 0x101),
+        (Op::Unary(UnaryOp::I32x4RelaxedTruncUF32x4), 0x102),
+        (Op::Unary(UnaryOp::I32x4RelaxedTruncSF64x2Zero), 0x103),
+        (Op::Unary(UnaryOp::I32x4RelaxedTruncUF64x2Zero), 0x104),
+        (Op::Ternary(TernaryOp::F32x4RelaxedMadd), 0x105),
+        (Op::Ternary(TernaryOp::F32x4RelaxedNmadd), 0x106),
+        (Op::Ternary(TernaryOp::F64x2RelaxedMadd), 0x107),
+        (Op::Ternary(TernaryOp::F64x2RelaxedNmadd), 0x108),
+        (Op::Binary(BinaryOp::F32x4RelaxedMin), 0x10d),
+        (Op::Binary(BinaryOp::F32x4RelaxedMax), 0x10e),
+        (Op::Binary(BinaryOp::F64x2RelaxedMin), 0x10f),
+        (Op::Binary(BinaryOp::F64x2RelaxedMax), 0x110),
+    ]
+}
+
+/// A module with a single function returning `op` applied to zeros.
+fn module(config: &ModuleConfig, op: &Op) -> Module {
+    let mut module = Module::with_config(config.clone());
+    let ty = module.types.add(&[], &[ValType::V128]);
+    let mut builder = FunctionBuilder::new();
+    let mut zero = || builder.const_(Value::V128(0));
+    let (a, b, c) = (zero(), zero(), zero());
+    let expr = match op {
+        Op::Unary(op) => builder.unop(*op, a),
+        Op::Binary(op) => builder.binop(*op, a, b),
+        Op::Ternary(op) => builder.ternop(*op, a, b, c),
+    };
+    builder.finish(ty, vec![], vec![expr], &mut module);
+    module
+}
+
+/// The body `module` emits for the instruction with the given opcode: one
+/// zero for each of its operands, then the opcode as a two byte LEB.
+fn body(op: &Op, opcode: u32) -> Vec<u8> {
+    let operands = match op {
+        Op::Unary(_) => 1,
+        Op::Binary(_) => 2,
+        Op::Ternary(_) => 3,
+    };
+    let mut body = vec![0x00]; // no locals
+    for _ in 0..operands {
+        body.extend(&[0xfd, 0x0c]); // v128.const
+        body.extend(&[0; 16]);
+    }
+    body.extend(&[0xfd, 0x80 | (opcode & 0x7f) as u8, (opcode >> 7) as u8]);
+    body.push(0x0b); // end
+    body
+}
+
+fn relaxed_config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .relaxed_simd(true)
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+#[test]
+fn opcodes_are_emitted() {
+    let config = relaxed_config();
+    for (op, opcode) in ops() {
+        let module = module(&config, &op);
+        validate::run(&module).unwrap();
+        assert_eq!(
+            module.used_features(),
+            WasmFeatures {
+                relaxed_simd: true,
+                ..WasmFeatures::default()
+            }
+        );
+
+        let wasm = module.emit_wasm().unwrap();
+        assert_eq!(
+            function_bodies(&wasm),
+            [&body(&op, opcode)[..]],
+            "opcode {:#x}",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn opcodes_round_trip() {
+    let config = relaxed_config();
+    for (op, opcode) in ops() {
+        let wasm = module(&config, &op).emit_wasm().unwrap();
+        let parsed = config.parse(&wasm).unwrap();
+        validate::run(&parsed).unwrap();
+        assert_eq!(parsed.emit_wasm().unwrap(), wasm, "opcode {:#x}", opcode);
+    }
+}
+
+#[test]
+fn rejected_unless_enabled() {
+    let config = ModuleConfig::new();
+    for (op, _) in ops() {
+        let err = validate::run(&module(&config, &op)).unwrap_err();
+        assert!(err.to_string().contains("relaxed SIMD"), "{}", err);
+    }
+}
+
+#[test]
+fn parsing_fails_unless_enabled() {
+    for (op, opcode) in ops() {
+        let wasm = module(&relaxed_config(), &op).emit_wasm().unwrap();
+        let err = ModuleConfig::new().parse(&wasm).unwrap_err();
+        let msg = format!("{}", err.find_root_cause());
+        assert!(
+            msg.contains("relaxed SIMD"),
+            "opcode {:#x}: {}",
+            opcode,
+            msg
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Tests for renaming functions, globals and memories with their exports.
+
+use walrus::ir::Value;
+use walrus::{FunctionBuilder, FunctionId, InitExpr, Module, NameTaken, RenamePolicy, ValType};
+
+fn fixture() -> (Module, FunctionId, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let a = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let b = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.funcs.get_mut(a).name = Some("a".to_string());
+    module.funcs.get_mut(b).name = Some("b".to_string());
+    module.exports.add("a", a);
+    module.exports.add("b", b);
+    (module, a, b)
+}
+
+#[test]
+fn exports_are_renamed_when_asked() {
+    let (mut module, a, _) = fixture();
+    module
+        .rename_function(a, "first", &RenamePolicy::default())
+        .unwrap();
+    assert_eq!(module.funcs.by_name("first"), Some(a));
+    assert!(module.exports.find("a").is_some());
+
+    let policy = RenamePolicy {
+        rename_exports: true,
+        ..Default::default()
+    };
+    module.rename_function(a, "alpha", &policy).unwrap();
+    assert_eq!(module.funcs.by_name("alpha"), Some(a));
+    assert!(module.exports.find("a").is_none());
+    assert_eq!(module.exports.get_func("alpha").unwrap(), a);
+}
+
+#[test]
+fn collisions_are_rejected_unless_allowed() {
+    let (mut module, a, b) = fixture();
+    let err = module
+        .rename_function(a, "b", &RenamePolicy::default())
+        .unwrap_err();
+    let taken = err.downcast::<NameTaken>().unwrap();
+    assert_eq!(taken.function, Some(b));
+    assert_eq!(module.funcs.get(a).name.as_ref().unwrap(), "a");
+
+    let policy = RenamePolicy {
+        allow_collisions: true,
+        ..Default::default()
+    };
+    module.rename_function(a, "b", &policy).unwrap();
+    assert_eq!(module.funcs.iter_by_name("b").count(), 2);
+
+    // Export names must stay unique regardless.
+    let policy = RenamePolicy {
+        rename_exports: true,
+        allow_collisions: true,
+    };
+    let err = module.rename_function(a, "b", &policy).unwrap_err();
+    let taken = err.downcast::<NameTaken>().unwrap();
+    assert_eq!(taken.export, module.exports.find("b"));
+    assert_eq!(module.exports.get_func("a").unwrap(), a);
+}
+
+#[test]
+fn globals_and_memories_rename_their_exports() {
+    let mut module = Module::default();
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    let memory = module.memories.add_local(false, 1, None);
+    module.exports.add("g", global);
+    module.exports.add("m", memory);
+    module.exports.add("memory", memory);
+
+    let policy = RenamePolicy {
+        rename_exports: true,
+        ..Default::default()
+    };
+    module.rename_global(global, "counter", &policy).unwrap();
+    assert!(module.exports.find("g").is_none());
+    assert!(module.exports.find("counter").is_some());
+
+    // A memory exported twice can't have both exports renamed to one name.
+    assert!(module.rename_memory(memory, "heap", &policy).is_err());
+    assert!(module.exports.find("m").is_some());
+    assert!(module.exports.find("heap").is_none());
+}
@@ -0,0 +1,130 @@
+//! Tests for replacing every use of one function with another.
+
+use walrus::ir::*;
+use walrus::passes::replace_function_uses;
+use walrus::{ElementKind, ExportItem, FunctionBuilder, FunctionId, FunctionKind, FunctionTable};
+use walrus::{LocalFunction, Module, TableKind, ValType};
+
+struct Fixture {
+    module: Module,
+    old: FunctionId,
+    new: FunctionId,
+    caller: FunctionId,
+}
+
+/// A module with a function `old`, a wrapper `new` which calls it, and a
+/// `caller` which calls it twice. `old` is also in a function table, in a
+/// passive element segment, exported, and the start function.
+fn fixture() -> Fixture {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+
+    let old = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+
+    let mut builder = FunctionBuilder::new();
+    let body = vec![builder.call(old, Box::new([]))];
+    let new = builder.finish(ty, vec![], body, &mut module);
+
+    let mut builder = FunctionBuilder::new();
+    let first = builder.call(old, Box::new([]));
+    let second = builder.call(old, Box::new([]));
+    let caller = builder.finish(ty, vec![], vec![first, second], &mut module);
+
+    let table = FunctionTable {
+        elements: vec![Some(old), None, Some(caller)],
+        ..FunctionTable::default()
+    };
+    module.tables.add_local(3, None, TableKind::Function(table));
+    module.elements.add(
+        ElementKind::Passive,
+        ValType::Funcref,
+        vec![Some(old), None],
+    );
+    module.exports.add("old", old);
+    module.start = Some(old);
+
+    Fixture {
+        module,
+        old,
+        new,
+        caller,
+    }
+}
+
+fn local(module: &Module, id: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(id).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn callees(module: &Module, id: FunctionId) -> Vec<FunctionId> {
+    let func = local(module, id);
+    func.block(func.entry_block())
+        .exprs
+        .iter()
+        .map(|e| match func.get(*e) {
+            Expr::Call(call) => call.func,
+            e => panic!("expected a call, found {:?}", e),
+        })
+        .collect()
+}
+
+#[test]
+fn replaces_every_use() {
+    let Fixture {
+        mut module,
+        old,
+        new,
+        caller,
+    } = fixture();
+    // Two calls, a table slot, a segment member, an export and the start
+    // function.
+    assert_eq!(replace_function_uses(&mut module, old, new).unwrap(), 6);
+
+    assert_eq!(callees(&module, caller), [new, new]);
+    // The wrapper still calls the original.
+    assert_eq!(callees(&module, new), [old]);
+
+    match &module.tables.iter().next().unwrap().kind {
+        TableKind::Function(table) => {
+            assert_eq!(table.elements, [Some(new), None, Some(caller)])
+        }
+        _ => panic!("expected a function table"),
+    }
+    let segment = module.elements.iter().next().unwrap();
+    assert_eq!(segment.members, [Some(new), None]);
+    let export = module.exports.iter().next().unwrap();
+    assert_eq!(export.item, ExportItem::Function(new));
+    assert_eq!(module.start, Some(new));
+
+    // Nothing uses `old` any more, so replacing it again does nothing.
+    assert_eq!(replace_function_uses(&mut module, old, new).unwrap(), 0);
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn replacing_a_function_with_itself_does_nothing() {
+    let Fixture {
+        mut module, old, ..
+    } = fixture();
+    assert_eq!(replace_function_uses(&mut module, old, old).unwrap(), 0);
+    assert_eq!(module.start, Some(old));
+}
+
+#[test]
+fn types_must_match() {
+    let Fixture {
+        mut module,
+        old,
+        caller,
+        ..
+    } = fixture();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let arg = module.locals.add(ValType::I32);
+    let other = FunctionBuilder::new().finish(ty, vec![arg], vec![], &mut module);
+
+    assert!(replace_function_uses(&mut module, old, other).is_err());
+    assert_eq!(callees(&module, caller), [old, old]);
+    assert_eq!(module.start, Some(old));
+}
@@ -12,13 +12,16 @@ fn run(wat_path: &Path) -> Result<(), failure::Error> {
         for (i, func) in module.functions().enumerate() {
             let mut file = String::new();
             func.dot(&mut file);
-            fs::write(wat_path.with_extension(&format!("{}.dot", i)), file)?;
+            fs::write(wat_path.with_extension(format!("{}.dot", i)), file)?;
         }
     }
 
     let out_wasm_file = wat_path.with_extension("out.wasm");
     walrus::passes::gc::run(&mut module);
-    module.emit_wasm_file(&out_wasm_file)?;
+    let (wasm, layout) = module.emit_wasm_with_layout()?;
+    let sections = layout.sections.iter().map(|s| s.range.len()).sum::<usize>();
+    assert_eq!(8 + sections, wasm.len());
+    fs::write(&out_wasm_file, wasm)?;
 
     let out_wat = wasm2wat(&out_wasm_file);
     let checker = walrus_tests::FileCheck::from_file(wat_path);
@@ -64,15 +64,15 @@
       i32.const 0
       table.get 1
       table.set 0
-      ref.null
+      ref.null extern
       i32.const 0
       table.grow 0
       drop
-      ref.null
+      ref.null extern
       i32.const 0
       table.grow 1
       drop
-      ref.null
+      ref.null extern
       ref.is_null
       i32.const 0
       i32.add
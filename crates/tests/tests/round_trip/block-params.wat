@@ -0,0 +1,25 @@
+;; Test blocks and ifs that take parameters.
+
+(module
+  (func (export "f") (param i32) (result i32)
+    i32.const 1
+    block (param i32) (result i32)
+      local.get 0
+      i32.add
+    end
+    local.get 0
+    if (param i32) (result i32)
+      i32.const 2
+      i32.mul
+    end))
+
+;; CHECK:   i32.const 1
+;; NEXT:    block
+;; NEXT:      local.get 0
+;; NEXT:      i32.add
+;; NEXT:    end
+;; NEXT:    local.get 0
+;; NEXT:    if
+;; NEXT:      i32.const 2
+;; NEXT:      i32.mul
+;; NEXT:    end)
@@ -0,0 +1,22 @@
+;; Test that an empty `else` arm isn't emitted.
+
+(module
+  (type (;0;) (func (param i32)))
+  (func $if_then (type 0)
+    local.get 0
+    if
+      i32.const 1
+      local.set 0
+    else
+    end)
+  (export "if_then" (func $if_then)))
+
+;; CHECK: (module
+;; NEXT:    (type (;0;) (func (param i32)))
+;; NEXT:    (func $if_then (type 0) (param i32)
+;; NEXT:      local.get 0
+;; NEXT:      if  ;; label = @1
+;; NEXT:        i32.const 1
+;; NEXT:        local.set 0
+;; NEXT:      end)
+;; NEXT:    (export "if_then" (func $if_then)))
@@ -0,0 +1,21 @@
+;; Test a loop passing a value from one iteration to the next as its parameter.
+
+(module
+  (func (export "count-down") (param i32) (result i32)
+    local.get 0
+    loop (param i32) (result i32)
+      i32.const 1
+      i32.sub
+      local.tee 0
+      local.get 0
+      br_if 0
+    end))
+
+;; CHECK:   local.get 0
+;; NEXT:    loop
+;; NEXT:      i32.const 1
+;; NEXT:      i32.sub
+;; NEXT:      local.tee 0
+;; NEXT:      local.get 0
+;; NEXT:      br_if 0 (;@1;)
+;; NEXT:    end)
@@ -19,7 +19,7 @@
   (func $v128.shuffle (export "v128.shuffle") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    v8x16.shuffle 16 1 18 3 20 5 22 7 24 9 26 11 28 13 30 15)
+    i8x16.shuffle 16 1 18 3 20 5 22 7 24 9 26 11 28 13 30 15)
 
    (func $i8x16.splat (export "i8x16.splat") (param i32) (result v128)
      local.get 0
@@ -265,9 +265,9 @@
    (func $i8x16.neg (export "i8x16.neg") (param v128) (result v128)
     local.get 0
     i8x16.neg)
-   (func $i8x16.any_true (export "i8x16.any_true") (param v128) (result i32)
+   (func $v128.any_true (export "v128.any_true") (param v128) (result i32)
     local.get 0
-    i8x16.any_true)
+    v128.any_true)
    (func $i8x16.all_true (export "i8x16.all_true") (param v128) (result i32)
     local.get 0
     i8x16.all_true)
@@ -287,37 +287,30 @@
     local.get 0
     local.get 1
     i8x16.add)
-   (func $i8x16.add_saturate_u (export "i8x16.add_saturate_u") (param v128 v128) (result v128)
+   (func $i8x16.add_sat_u (export "i8x16.add_sat_u") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i8x16.add_saturate_u)
-   (func $i8x16.add_saturate_s (export "i8x16.add_saturate_s") (param v128 v128) (result v128)
+    i8x16.add_sat_u)
+   (func $i8x16.add_sat_s (export "i8x16.add_sat_s") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i8x16.add_saturate_s)
+    i8x16.add_sat_s)
    (func $i8x16.sub (export "i8x16.sub") (param v128 v128) (result v128)
     local.get 0
     local.get 1
     i8x16.sub)
-   (func $i8x16.sub_saturate_u (export "i8x16.sub_saturate_u") (param v128 v128) (result v128)
+   (func $i8x16.sub_sat_u (export "i8x16.sub_sat_u") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i8x16.sub_saturate_u)
-   (func $i8x16.sub_saturate_s (export "i8x16.sub_saturate_s") (param v128 v128) (result v128)
+    i8x16.sub_sat_u)
+   (func $i8x16.sub_sat_s (export "i8x16.sub_sat_s") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i8x16.sub_saturate_s)
-   (func $i8x16.mul (export "i8x16.mul") (param v128 v128) (result v128)
-    local.get 0
-    local.get 1
-    i8x16.mul)
+    i8x16.sub_sat_s)
 
    (func $i16x8.neg (export "i16x8.neg") (param v128) (result v128)
     local.get 0
     i16x8.neg)
-   (func $i16x8.any_true (export "i16x8.any_true") (param v128) (result i32)
-    local.get 0
-    i16x8.any_true)
    (func $i16x8.all_true (export "i16x8.all_true") (param v128) (result i32)
     local.get 0
     i16x8.all_true)
@@ -337,26 +330,26 @@
     local.get 0
     local.get 1
     i16x8.add)
-   (func $i16x8.add_saturate_u (export "i16x8.add_saturate_u") (param v128 v128) (result v128)
+   (func $i16x8.add_sat_u (export "i16x8.add_sat_u") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i16x8.add_saturate_u)
-   (func $i16x8.add_saturate_s (export "i16x8.add_saturate_s") (param v128 v128) (result v128)
+    i16x8.add_sat_u)
+   (func $i16x8.add_sat_s (export "i16x8.add_sat_s") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i16x8.add_saturate_s)
+    i16x8.add_sat_s)
    (func $i16x8.sub (export "i16x8.sub") (param v128 v128) (result v128)
     local.get 0
     local.get 1
     i16x8.sub)
-   (func $i16x8.sub_saturate_u (export "i16x8.sub_saturate_u") (param v128 v128) (result v128)
+   (func $i16x8.sub_sat_u (export "i16x8.sub_sat_u") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i16x8.sub_saturate_u)
-   (func $i16x8.sub_saturate_s (export "i16x8.sub_saturate_s") (param v128 v128) (result v128)
+    i16x8.sub_sat_u)
+   (func $i16x8.sub_sat_s (export "i16x8.sub_sat_s") (param v128 v128) (result v128)
     local.get 0
     local.get 1
-    i16x8.sub_saturate_s)
+    i16x8.sub_sat_s)
    (func $i16x8.mul (export "i16x8.mul") (param v128 v128) (result v128)
     local.get 0
     local.get 1
@@ -365,9 +358,6 @@
    (func $i32x4.neg (export "i32x4.neg") (param v128) (result v128)
     local.get 0
     i32x4.neg)
-   (func $i32x4.any_true (export "i32x4.any_true") (param v128) (result i32)
-    local.get 0
-    i32x4.any_true)
    (func $i32x4.all_true (export "i32x4.all_true") (param v128) (result i32)
     local.get 0
     i32x4.all_true)
@@ -399,9 +389,6 @@
    (func $i64x2.neg (export "i64x2.neg") (param v128) (result v128)
     local.get 0
     i64x2.neg)
-   (func $i64x2.any_true (export "i64x2.any_true") (param v128) (result i32)
-    local.get 0
-    i64x2.any_true)
    (func $i64x2.all_true (export "i64x2.all_true") (param v128) (result i32)
     local.get 0
     i64x2.all_true)
@@ -500,12 +487,6 @@
    (func $i32x4_trunc_u_f32x4_sat (export "i32x4_trunc_u_f32x4_sat") (param v128) (result v128)
     local.get 0
     i32x4.trunc_sat_f32x4_u)
-   (func $i64x2_trunc_s_f64x2_sat (export "i64x2_trunc_s_f64x2_sat") (param v128) (result v128)
-    local.get 0
-    i64x2.trunc_sat_f64x2_s)
-   (func $i64x2_trunc_u_f64x2_sat (export "i64x2_trunc_u_f64x2_sat") (param v128) (result v128)
-    local.get 0
-    i64x2.trunc_sat_f64x2_u)
 
    (func $f32x4.convert_i32x4_s (export "f32x4.convert_i32x4_s") (param v128) (result v128)
     local.get 0
@@ -513,12 +494,6 @@
    (func $f32x4.convert_i32x4_u (export "f32x4.convert_i32x4_u") (param v128) (result v128)
     local.get 0
     f32x4.convert_i32x4_u)
-   (func $f64x2.convert_i64x2_s (export "f64x2.convert_i64x2_s") (param v128) (result v128)
-    local.get 0
-    f64x2.convert_i64x2_s)
-   (func $f64x2.convert_i64x2_u (export "f64x2.convert_i64x2_u") (param v128) (result v128)
-    local.get 0
-    f64x2.convert_i64x2_u)
 )
 
 (; CHECK-ALL:
@@ -552,7 +527,7 @@
     (func $v128.shuffle (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      v8x16.shuffle  16  1  18  3  20  5  22  7  24  9  26  11  28  13  30  15 )
+      i8x16.shuffle  16  1  18  3  20  5  22  7  24  9  26  11  28  13  30  15 )
     (func $i8x16.replace_lane (type 5) (param v128 i32) (result v128)
       local.get 0
       local.get 1
@@ -753,30 +728,26 @@
       local.get 0
       local.get 1
       i8x16.add)
-    (func $i8x16.add_saturate_u (type 3) (param v128 v128) (result v128)
+    (func $i8x16.add_sat_u (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i8x16.add_saturate_u)
-    (func $i8x16.add_saturate_s (type 3) (param v128 v128) (result v128)
+      i8x16.add_sat_u)
+    (func $i8x16.add_sat_s (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i8x16.add_saturate_s)
+      i8x16.add_sat_s)
     (func $i8x16.sub (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
       i8x16.sub)
-    (func $i8x16.sub_saturate_u (type 3) (param v128 v128) (result v128)
+    (func $i8x16.sub_sat_u (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i8x16.sub_saturate_u)
-    (func $i8x16.sub_saturate_s (type 3) (param v128 v128) (result v128)
+      i8x16.sub_sat_u)
+    (func $i8x16.sub_sat_s (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i8x16.sub_saturate_s)
-    (func $i8x16.mul (type 3) (param v128 v128) (result v128)
-      local.get 0
-      local.get 1
-      i8x16.mul)
+      i8x16.sub_sat_s)
     (func $i16x8.shl (type 5) (param v128 i32) (result v128)
       local.get 0
       local.get 1
@@ -793,26 +764,26 @@
       local.get 0
       local.get 1
       i16x8.add)
-    (func $i16x8.add_saturate_u (type 3) (param v128 v128) (result v128)
+    (func $i16x8.add_sat_u (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i16x8.add_saturate_u)
-    (func $i16x8.add_saturate_s (type 3) (param v128 v128) (result v128)
+      i16x8.add_sat_u)
+    (func $i16x8.add_sat_s (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i16x8.add_saturate_s)
+      i16x8.add_sat_s)
     (func $i16x8.sub (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
       i16x8.sub)
-    (func $i16x8.sub_saturate_u (type 3) (param v128 v128) (result v128)
+    (func $i16x8.sub_sat_u (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i16x8.sub_saturate_u)
-    (func $i16x8.sub_saturate_s (type 3) (param v128 v128) (result v128)
+      i16x8.sub_sat_u)
+    (func $i16x8.sub_sat_s (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
-      i16x8.sub_saturate_s)
+      i16x8.sub_sat_s)
     (func $i16x8.mul (type 3) (param v128 v128) (result v128)
       local.get 0
       local.get 1
@@ -960,36 +931,27 @@
     (func $i8x16.neg (type 15) (param v128) (result v128)
       local.get 0
       i8x16.neg)
-    (func $i8x16.any_true (type 4) (param v128) (result i32)
+    (func $v128.any_true (type 4) (param v128) (result i32)
       local.get 0
-      i8x16.any_true)
+      v128.any_true)
     (func $i8x16.all_true (type 4) (param v128) (result i32)
       local.get 0
       i8x16.all_true)
     (func $i16x8.neg (type 15) (param v128) (result v128)
       local.get 0
       i16x8.neg)
-    (func $i16x8.any_true (type 4) (param v128) (result i32)
-      local.get 0
-      i16x8.any_true)
     (func $i16x8.all_true (type 4) (param v128) (result i32)
       local.get 0
       i16x8.all_true)
     (func $i32x4.neg (type 15) (param v128) (result v128)
       local.get 0
       i32x4.neg)
-    (func $i32x4.any_true (type 4) (param v128) (result i32)
-      local.get 0
-      i32x4.any_true)
     (func $i32x4.all_true (type 4) (param v128) (result i32)
       local.get 0
       i32x4.all_true)
     (func $i64x2.neg (type 15) (param v128) (result v128)
       local.get 0
       i64x2.neg)
-    (func $i64x2.any_true (type 4) (param v128) (result i32)
-      local.get 0
-      i64x2.any_true)
     (func $i64x2.all_true (type 4) (param v128) (result i32)
       local.get 0
       i64x2.all_true)
@@ -1017,24 +979,12 @@
     (func $i32x4_trunc_u_f32x4_sat (type 15) (param v128) (result v128)
       local.get 0
       i32x4.trunc_sat_f32x4_u)
-    (func $i64x2_trunc_s_f64x2_sat (type 15) (param v128) (result v128)
-      local.get 0
-      i64x2.trunc_sat_f64x2_s)
-    (func $i64x2_trunc_u_f64x2_sat (type 15) (param v128) (result v128)
-      local.get 0
-      i64x2.trunc_sat_f64x2_u)
     (func $f32x4.convert_i32x4_s (type 15) (param v128) (result v128)
       local.get 0
       f32x4.convert_i32x4_s)
     (func $f32x4.convert_i32x4_u (type 15) (param v128) (result v128)
       local.get 0
       f32x4.convert_i32x4_u)
-    (func $f64x2.convert_i64x2_s (type 15) (param v128) (result v128)
-      local.get 0
-      f64x2.convert_i64x2_s)
-    (func $f64x2.convert_i64x2_u (type 15) (param v128) (result v128)
-      local.get 0
-      f64x2.convert_i64x2_u)
     (func $v128.const (type 0) (result v128)
       v128.const i32x4 0x00000001 0x00000002 0x00000003 0x00000004)
     (memory (;0;) 0)
@@ -1105,33 +1055,30 @@
     (export "v128.xor" (func $v128.xor))
     (export "v128.bitselect" (func $v128.bitselect))
     (export "i8x16.neg" (func $i8x16.neg))
-    (export "i8x16.any_true" (func $i8x16.any_true))
+    (export "v128.any_true" (func $v128.any_true))
     (export "i8x16.all_true" (func $i8x16.all_true))
     (export "i8x16.shl" (func $i8x16.shl))
     (export "i8x16.shr_s" (func $i8x16.shr_s))
     (export "i8x16.shr_u" (func $i8x16.shr_u))
     (export "i8x16.add" (func $i8x16.add))
-    (export "i8x16.add_saturate_u" (func $i8x16.add_saturate_u))
-    (export "i8x16.add_saturate_s" (func $i8x16.add_saturate_s))
+    (export "i8x16.add_sat_u" (func $i8x16.add_sat_u))
+    (export "i8x16.add_sat_s" (func $i8x16.add_sat_s))
     (export "i8x16.sub" (func $i8x16.sub))
-    (export "i8x16.sub_saturate_u" (func $i8x16.sub_saturate_u))
-    (export "i8x16.sub_saturate_s" (func $i8x16.sub_saturate_s))
-    (export "i8x16.mul" (func $i8x16.mul))
+    (export "i8x16.sub_sat_u" (func $i8x16.sub_sat_u))
+    (export "i8x16.sub_sat_s" (func $i8x16.sub_sat_s))
     (export "i16x8.neg" (func $i16x8.neg))
-    (export "i16x8.any_true" (func $i16x8.any_true))
     (export "i16x8.all_true" (func $i16x8.all_true))
     (export "i16x8.shl" (func $i16x8.shl))
     (export "i16x8.shr_s" (func $i16x8.shr_s))
     (export "i16x8.shr_u" (func $i16x8.shr_u))
     (export "i16x8.add" (func $i16x8.add))
-    (export "i16x8.add_saturate_u" (func $i16x8.add_saturate_u))
-    (export "i16x8.add_saturate_s" (func $i16x8.add_saturate_s))
+    (export "i16x8.add_sat_u" (func $i16x8.add_sat_u))
+    (export "i16x8.add_sat_s" (func $i16x8.add_sat_s))
     (export "i16x8.sub" (func $i16x8.sub))
-    (export "i16x8.sub_saturate_u" (func $i16x8.sub_saturate_u))
-    (export "i16x8.sub_saturate_s" (func $i16x8.sub_saturate_s))
+    (export "i16x8.sub_sat_u" (func $i16x8.sub_sat_u))
+    (export "i16x8.sub_sat_s" (func $i16x8.sub_sat_s))
     (export "i16x8.mul" (func $i16x8.mul))
     (export "i32x4.neg" (func $i32x4.neg))
-    (export "i32x4.any_true" (func $i32x4.any_true))
     (export "i32x4.all_true" (func $i32x4.all_true))
     (export "i32x4.shl" (func $i32x4.shl))
     (export "i32x4.shr_s" (func $i32x4.shr_s))
@@ -1140,7 +1087,6 @@
     (export "i32x4.sub" (func $i32x4.sub))
     (export "i32x4.mul" (func $i32x4.mul))
     (export "i64x2.neg" (func $i64x2.neg))
-    (export "i64x2.any_true" (func $i64x2.any_true))
     (export "i64x2.all_true" (func $i64x2.all_true))
     (export "i64x2.shl" (func $i64x2.shl))
     (export "i64x2.shr_s" (func $i64x2.shr_s))
@@ -1167,10 +1113,6 @@
     (export "f64x2.max" (func $f64x2.max))
     (export "i32x4_trunc_s_f32x4_sat" (func $i32x4_trunc_s_f32x4_sat))
     (export "i32x4_trunc_u_f32x4_sat" (func $i32x4_trunc_u_f32x4_sat))
-    (export "i64x2_trunc_s_f64x2_sat" (func $i64x2_trunc_s_f64x2_sat))
-    (export "i64x2_trunc_u_f64x2_sat" (func $i64x2_trunc_u_f64x2_sat))
     (export "f32x4.convert_i32x4_s" (func $f32x4.convert_i32x4_s))
-    (export "f32x4.convert_i32x4_u" (func $f32x4.convert_i32x4_u))
-    (export "f64x2.convert_i64x2_s" (func $f64x2.convert_i64x2_s))
-    (export "f64x2.convert_i64x2_u" (func $f64x2.convert_i64x2_u)))
+    (export "f32x4.convert_i32x4_u" (func $f32x4.convert_i32x4_u)))
 ;)
@@ -0,0 +1,54 @@
+//! Tests for reusing temporary locals across instrumentation sites.
+
+use walrus::{FunctionBuilder, Module, ScratchLocals, ValType};
+
+#[test]
+fn instrumentation_adds_bounded_locals() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let mut scratch = ScratchLocals::new();
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+
+    // Stash a value in a temporary at every site, with two temporaries live
+    // at once.
+    for i in 0..100 {
+        let a = scratch.get(&mut module.locals, ValType::I32);
+        let b = scratch.get(&mut module.locals, ValType::I32);
+        let value = builder.i32_const(i);
+        exprs.push(builder.local_set(a, value));
+        let value = builder.local_get(a);
+        exprs.push(builder.local_set(b, value));
+        let value = builder.local_get(b);
+        exprs.push(builder.drop(value));
+        scratch.release(&module.locals, a);
+        scratch.release(&module.locals, b);
+    }
+    builder.finish(ty, vec![], exprs, &mut module);
+
+    assert_eq!(scratch.added(), 2);
+    assert_eq!(module.locals.iter().count(), 2);
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn types_are_not_mixed() {
+    let mut module = Module::default();
+    let mut scratch = ScratchLocals::new();
+    let a = scratch.get(&mut module.locals, ValType::I32);
+    scratch.release(&module.locals, a);
+    let b = scratch.get(&mut module.locals, ValType::F64);
+    assert_ne!(a, b);
+    assert_eq!(module.locals.get(b).ty(), ValType::F64);
+    assert_eq!(scratch.get(&mut module.locals, ValType::I32), a);
+}
+
+#[test]
+#[should_panic]
+fn double_release_panics() {
+    let mut module = Module::default();
+    let mut scratch = ScratchLocals::new();
+    let a = scratch.get(&mut module.locals, ValType::I32);
+    scratch.release(&module.locals, a);
+    scratch.release(&module.locals, a);
+}
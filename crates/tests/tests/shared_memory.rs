@@ -0,0 +1,72 @@
+//! Tests for shared memories and the validation of atomics that use them.
+
+use walrus::ir::*;
+use walrus::passes::validate;
+use walrus::{FunctionBuilder, MemoryId, Module, ModuleConfig, ValType};
+use walrus_tests_utils::{function_bodies, section};
+
+fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config
+        .generate_name_section(false)
+        .generate_producers_section(false);
+    config
+}
+
+/// Add a function to `module` which atomically increments the `i32` at
+/// address 0 of `memory`.
+fn add_increment(module: &mut Module, memory: MemoryId) {
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let address = builder.i32_const(0);
+    let value = builder.i32_const(1);
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+    let rmw = builder.atomic_rmw(memory, AtomicOp::Add, AtomicWidth::I32, arg, address, value);
+    let func = builder.finish(ty, vec![], vec![rmw], module);
+    module.exports.add("increment", func);
+}
+
+#[test]
+fn threaded_module_round_trips() {
+    let mut module = Module::with_config(config());
+    let memory = module.memories.add_shared(1, 16);
+    module.exports.add("memory", memory);
+    add_increment(&mut module, memory);
+    validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm().unwrap();
+    // One shared memory of 1 to 16 pages.
+    assert_eq!(section(&wasm, 5), &[0x01, 0x03, 0x01, 0x10][..]);
+    // (i32.atomic.rmw.add (i32.const 0) (i32.const 1))
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x41, 0x00, 0x41, 0x01, 0xfe, 0x1e, 0x02, 0x00, 0x0b][..]]
+    );
+
+    let module = config().parse(&wasm).unwrap();
+    let memory = module.memories.iter().next().unwrap();
+    assert!(memory.shared);
+    assert_eq!(memory.initial, 1);
+    assert_eq!(memory.maximum, Some(16));
+    validate::run(&module).unwrap();
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn atomics_on_unshared_memory() {
+    let mut module = Module::with_config(config());
+    let memory = module.memories.add_local(false, 1, Some(16));
+    add_increment(&mut module, memory);
+    let err = validate::run(&module).unwrap_err();
+    assert!(err.to_string().contains("shared memory"), "{}", err);
+
+    let mut config = config();
+    config.allow_unshared_atomics(true);
+    let mut module = Module::with_config(config);
+    let memory = module.memories.add_local(false, 1, Some(16));
+    add_increment(&mut module, memory);
+    validate::run(&module).unwrap();
+}
@@ -0,0 +1,107 @@
+//! Tests for sharing interned data between parsed modules.
+
+use walrus::{ExportItem, FunctionBuilder, Module, ModuleConfig, SharedParseContext};
+use walrus::{SharedStr, ValType};
+
+/// A module built against a pretend SDK, with the same imports, exports and
+/// types as every other module built against it, but returning `n`.
+fn fixture(n: i32) -> Vec<u8> {
+    let mut module = Module::default();
+    let log_ty = module.types.add(&[ValType::I32, ValType::I32], &[]);
+    let now_ty = module.types.add(&[], &[ValType::I64]);
+    let main_ty = module.types.add(&[], &[ValType::I32]);
+    module.add_import_func("sdk_environment_v1", "log_message", log_ty);
+    module.add_import_func("sdk_environment_v1", "current_time", now_ty);
+
+    let mut builder = FunctionBuilder::new();
+    let n = builder.i32_const(n);
+    let main = builder.finish(main_ty, vec![], vec![n], &mut module);
+    module
+        .exports
+        .add("sdk_entry_point_main", ExportItem::Function(main));
+    module.emit_wasm().unwrap()
+}
+
+#[test]
+fn names_and_types_are_shared() {
+    let fixtures = (0..50).map(fixture).collect::<Vec<_>>();
+
+    let mut cx = SharedParseContext::new();
+    cx.intern_types(true);
+    let mut config = ModuleConfig::new();
+    config.shared_context(&cx);
+    let modules = fixtures
+        .iter()
+        .map(|wasm| config.parse(wasm).unwrap())
+        .collect::<Vec<_>>();
+
+    let names = |m: &Module| -> Vec<SharedStr> {
+        let imports = m
+            .imports
+            .iter()
+            .flat_map(|i| vec![i.module.clone(), i.name.clone()]);
+        imports
+            .chain(m.exports.iter().map(|e| e.name.clone()))
+            .collect()
+    };
+    let first = names(&modules[0]);
+    assert_eq!(first.len(), 5);
+    for module in &modules[1..] {
+        for (a, b) in first.iter().zip(names(module)) {
+            assert!(SharedStr::ptr_eq(a, &b));
+        }
+    }
+
+    // Each name is stored once, however many modules use it.
+    let total: usize = modules.iter().flat_map(names).map(|name| name.len()).sum();
+    assert_eq!(cx.strings(), 4);
+    assert_eq!(cx.string_bytes(), 61);
+    assert_eq!(total, 79 * 50);
+    // `[]`, `[i32, i32]`, `[i64]` and `[i32]`.
+    assert_eq!(cx.signatures(), 4);
+}
+
+#[test]
+fn shared_modules_emit_the_same_bytes() {
+    let mut cx = SharedParseContext::new();
+    cx.intern_types(true);
+    let mut config = ModuleConfig::new();
+    config.shared_context(&cx);
+
+    for n in 0..5 {
+        let wasm = fixture(n);
+        let shared = config.parse(&wasm).unwrap();
+        let alone = Module::from_buffer(&wasm).unwrap();
+        assert_eq!(shared.emit_wasm().unwrap(), alone.emit_wasm().unwrap());
+    }
+}
+
+#[test]
+fn shared_modules_are_independently_mutable() {
+    let cx = SharedParseContext::new();
+    let mut config = ModuleConfig::new();
+    config.shared_context(&cx);
+    let mut a = config.parse(&fixture(1)).unwrap();
+    let b = config.parse(&fixture(2)).unwrap();
+
+    let export = a.exports.iter().next().unwrap().id();
+    a.exports.get_mut(export).name = "renamed".into();
+    let import = a.imports.iter().next().unwrap().id();
+    a.imports.get_mut(import).module = "sdk_environment_v2".into();
+
+    assert_eq!(
+        b.exports.iter().next().unwrap().name,
+        "sdk_entry_point_main"
+    );
+    assert!(b.imports.iter().all(|i| i.module == "sdk_environment_v1"));
+
+    let a = Module::from_buffer(&a.emit_wasm().unwrap()).unwrap();
+    assert_eq!(a.exports.iter().next().unwrap().name, "renamed");
+    assert_eq!(
+        a.imports
+            .iter()
+            .map(|i| i.module.as_str())
+            .collect::<Vec<_>>(),
+        ["sdk_environment_v2", "sdk_environment_v1"]
+    );
+}
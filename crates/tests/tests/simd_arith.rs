@@ -0,0 +1,279 @@
+//! Tests for emitting and parsing SIMD arithmetic from the finalized
+//! proposal.
+
+use walrus::ir::*;
+use walrus::{ExportItem, FunctionBuilder, Module, ValType};
+use walrus_tests_utils::function_bodies;
+
+/// Emit a module whose only function applies `op` to its parameters.
+fn emit(op: impl FnOnce(&mut FunctionBuilder, ExprId, ExprId) -> ExprId) -> Vec<u8> {
+    let mut module = Module::default();
+    let ty = module
+        .types
+        .add(&[ValType::V128, ValType::V128], &[ValType::V128]);
+    let a = module.locals.add(ValType::V128);
+    let b = module.locals.add(ValType::V128);
+    let mut builder = FunctionBuilder::new();
+    let lhs = builder.local_get(a);
+    let rhs = builder.local_get(b);
+    let result = op(&mut builder, lhs, rhs);
+    let func = builder.finish(ty, vec![a, b], vec![result], &mut module);
+    module.exports.add("f", ExportItem::Function(func));
+    module.emit_wasm().unwrap()
+}
+
+/// Parse `wasm` back, check that it emits the same function body again, and
+/// return the last instruction of its function.
+fn parse(wasm: &[u8]) -> Expr {
+    let module = Module::from_buffer(wasm).unwrap();
+    assert_eq!(
+        function_bodies(&module.emit_wasm().unwrap()),
+        function_bodies(wasm)
+    );
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let last = *func.block(func.entry_block()).exprs.last().unwrap();
+    func.get(last).clone()
+}
+
+#[test]
+fn q15mulr_sat_s() {
+    let wasm = emit(|builder, lhs, rhs| builder.binop(BinaryOp::I16x8Q15MulrSatS, lhs, rhs));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x00, 0x20, 0x01, 0xfd, 0x82, 0x01, 0x0b][..]]
+    );
+    match parse(&wasm) {
+        Expr::Binop(Binop {
+            op: BinaryOp::I16x8Q15MulrSatS,
+            ..
+        }) => {}
+        e => panic!("unexpected expression: {:?}", e),
+    }
+    assert_eq!(BinaryOp::I16x8Q15MulrSatS.result_type(), ValType::V128);
+}
+
+#[test]
+fn i64x2_abs() {
+    let wasm = emit(|builder, lhs, _| builder.unop(UnaryOp::I64x2Abs, lhs));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x00, 0xfd, 0xc0, 0x01, 0x0b][..]]
+    );
+    match parse(&wasm) {
+        Expr::Unop(Unop {
+            op: UnaryOp::I64x2Abs,
+            ..
+        }) => {}
+        e => panic!("unexpected expression: {:?}", e),
+    }
+    assert_eq!(UnaryOp::I64x2Abs.result_type(), ValType::V128);
+}
+
+#[test]
+fn i8x16_popcnt() {
+    let wasm = emit(|builder, lhs, _| builder.unop(UnaryOp::I8x16Popcnt, lhs));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x00, 0xfd, 0x62, 0x0b][..]]
+    );
+    match parse(&wasm) {
+        Expr::Unop(Unop {
+            op: UnaryOp::I8x16Popcnt,
+            ..
+        }) => {}
+        e => panic!("unexpected expression: {:?}", e),
+    }
+    assert_eq!(UnaryOp::I8x16Popcnt.result_type(), ValType::V128);
+}
+
+#[test]
+fn swizzle() {
+    let wasm = emit(|builder, lhs, rhs| builder.v128_swizzle(lhs, rhs));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x00, 0x20, 0x01, 0xfd, 0x0e, 0x0b][..]]
+    );
+    match parse(&wasm) {
+        Expr::V128Swizzle(..) => {}
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
+
+#[test]
+fn narrowing() {
+    let ops: [(BinaryOp, &[u8]); 4] = [
+        (BinaryOp::I8x16NarrowI16x8S, &[0x65]),
+        (BinaryOp::I8x16NarrowI16x8U, &[0x66]),
+        (BinaryOp::I16x8NarrowI32x4S, &[0x85, 0x01]),
+        (BinaryOp::I16x8NarrowI32x4U, &[0x86, 0x01]),
+    ];
+    for &(op, opcode) in ops.iter() {
+        let wasm = emit(|builder, lhs, rhs| builder.binop(op, lhs, rhs));
+        let mut expected = vec![0x00, 0x20, 0x00, 0x20, 0x01, 0xfd];
+        expected.extend_from_slice(opcode);
+        expected.push(0x0b);
+        assert_eq!(function_bodies(&wasm), [&expected[..]], "{:?}", op);
+        match parse(&wasm) {
+            Expr::Binop(e) => assert_eq!(e.op, op),
+            e => panic!("unexpected expression: {:?}", e),
+        }
+        assert_eq!(op.result_type(), ValType::V128);
+    }
+}
+
+#[test]
+fn widening() {
+    let ops = [
+        (UnaryOp::I16x8WidenLowI8x16S, 0x87),
+        (UnaryOp::I16x8WidenHighI8x16S, 0x88),
+        (UnaryOp::I16x8WidenLowI8x16U, 0x89),
+        (UnaryOp::I16x8WidenHighI8x16U, 0x8a),
+        (UnaryOp::I32x4WidenLowI16x8S, 0xa7),
+        (UnaryOp::I32x4WidenHighI16x8S, 0xa8),
+        (UnaryOp::I32x4WidenLowI16x8U, 0xa9),
+        (UnaryOp::I32x4WidenHighI16x8U, 0xaa),
+    ];
+    for &(op, opcode) in ops.iter() {
+        let wasm = emit(|builder, lhs, _| builder.unop(op, lhs));
+        assert_eq!(
+            function_bodies(&wasm),
+            [&[0x00, 0x20, 0x00, 0xfd, opcode, 0x01, 0x0b][..]]
+        );
+        match parse(&wasm) {
+            Expr::Unop(e) => assert_eq!(e.op, op),
+            e => panic!("unexpected expression: {:?}", e),
+        }
+        assert_eq!(op.result_type(), ValType::V128);
+    }
+}
+
+#[test]
+fn andnot() {
+    let wasm = emit(|builder, lhs, rhs| builder.binop(BinaryOp::V128Andnot, lhs, rhs));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x20, 0x00, 0x20, 0x01, 0xfd, 0x4f, 0x0b][..]]
+    );
+    match parse(&wasm) {
+        Expr::Binop(Binop {
+            op: BinaryOp::V128Andnot,
+            ..
+        }) => {}
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
+
+#[test]
+fn v128_const() {
+    // `v128.const i32x4 1 2 3 0x80000000`, as encoded by `wat2wasm`.
+    let expected = [
+        0x00, 0xfd, 0x0c, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x0b,
+    ];
+    let bytes = [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0x80];
+    let wasm = emit(|builder, _, _| builder.v128_const(bytes));
+    assert_eq!(function_bodies(&wasm), [&expected[..]]);
+    let wasm = emit(|builder, _, _| builder.v128_const_u32_lanes([1, 2, 3, 0x8000_0000]));
+    assert_eq!(function_bodies(&wasm), [&expected[..]]);
+    match parse(&wasm) {
+        Expr::Const(Const {
+            value: Value::V128(n),
+        }) => assert_eq!(n.to_le_bytes(), bytes),
+        e => panic!("unexpected expression: {:?}", e),
+    }
+
+    match Value::v128_from_u32_lanes([1, 2, 3, 0x8000_0000]) {
+        Value::V128(n) => assert_eq!(n.to_le_bytes(), bytes),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn integer_min_max_avgr_and_dot() {
+    // Opcodes under 0x80 are encoded in a single byte.
+    let ops: [(BinaryOp, &[u8]); 15] = [
+        (BinaryOp::I8x16MinS, &[0x76]),
+        (BinaryOp::I8x16MinU, &[0x77]),
+        (BinaryOp::I8x16MaxS, &[0x78]),
+        (BinaryOp::I8x16MaxU, &[0x79]),
+        (BinaryOp::I8x16AvgrU, &[0x7b]),
+        (BinaryOp::I16x8MinS, &[0x96, 0x01]),
+        (BinaryOp::I16x8MinU, &[0x97, 0x01]),
+        (BinaryOp::I16x8MaxS, &[0x98, 0x01]),
+        (BinaryOp::I16x8MaxU, &[0x99, 0x01]),
+        (BinaryOp::I16x8AvgrU, &[0x9b, 0x01]),
+        (BinaryOp::I32x4MinS, &[0xb6, 0x01]),
+        (BinaryOp::I32x4MinU, &[0xb7, 0x01]),
+        (BinaryOp::I32x4MaxS, &[0xb8, 0x01]),
+        (BinaryOp::I32x4MaxU, &[0xb9, 0x01]),
+        (BinaryOp::I32x4DotI16x8S, &[0xba, 0x01]),
+    ];
+    for &(op, opcode) in ops.iter() {
+        let wasm = emit(|builder, lhs, rhs| builder.binop(op, lhs, rhs));
+        let mut expected = vec![0x00, 0x20, 0x00, 0x20, 0x01, 0xfd];
+        expected.extend_from_slice(opcode);
+        expected.push(0x0b);
+        assert_eq!(function_bodies(&wasm), [&expected[..]], "{:?}", op);
+        match parse(&wasm) {
+            Expr::Binop(e) => assert_eq!(e.op, op),
+            e => panic!("unexpected expression: {:?}", e),
+        }
+        assert_eq!(op.result_type(), ValType::V128);
+    }
+}
+
+#[test]
+fn integer_abs() {
+    let ops: [(UnaryOp, &[u8]); 3] = [
+        (UnaryOp::I8x16Abs, &[0x60]),
+        (UnaryOp::I16x8Abs, &[0x80, 0x01]),
+        (UnaryOp::I32x4Abs, &[0xa0, 0x01]),
+    ];
+    for &(op, opcode) in ops.iter() {
+        let wasm = emit(|builder, lhs, _| builder.unop(op, lhs));
+        let mut expected = vec![0x00, 0x20, 0x00, 0xfd];
+        expected.extend_from_slice(opcode);
+        expected.push(0x0b);
+        assert_eq!(function_bodies(&wasm), [&expected[..]], "{:?}", op);
+        match parse(&wasm) {
+            Expr::Unop(e) => assert_eq!(e.op, op),
+            e => panic!("unexpected expression: {:?}", e),
+        }
+        assert_eq!(op.result_type(), ValType::V128);
+    }
+}
+
+#[test]
+fn renumbered_for_wasmparser() {
+    // These are read by `wasmparser`, which only knows their pre-standard
+    // opcodes.
+    let ops: [(BinaryOp, &[u8]); 6] = [
+        (BinaryOp::I8x16Eq, &[0x23]),
+        (BinaryOp::F64x2Ge, &[0x4c]),
+        (BinaryOp::V128Xor, &[0x51]),
+        (BinaryOp::I8x16Add, &[0x6e]),
+        (BinaryOp::I32x4Mul, &[0xb5, 0x01]),
+        (BinaryOp::F64x2Max, &[0xf5, 0x01]),
+    ];
+    for &(op, opcode) in ops.iter() {
+        let wasm = emit(|builder, lhs, rhs| builder.binop(op, lhs, rhs));
+        let mut expected = vec![0x00, 0x20, 0x00, 0x20, 0x01, 0xfd];
+        expected.extend_from_slice(opcode);
+        expected.push(0x0b);
+        assert_eq!(function_bodies(&wasm), [&expected[..]], "{:?}", op);
+        match parse(&wasm) {
+            Expr::Binop(e) => assert_eq!(e.op, op),
+            e => panic!("unexpected expression: {:?}", e),
+        }
+    }
+
+    let wasm = emit(|builder, lhs, rhs| builder.v128_shuffle([3; 16], lhs, rhs));
+    let mut expected = vec![0x00, 0x20, 0x00, 0x20, 0x01, 0xfd, 0x0d];
+    expected.extend_from_slice(&[3; 16]);
+    expected.push(0x0b);
+    assert_eq!(function_bodies(&wasm), [&expected[..]]);
+    match parse(&wasm) {
+        Expr::V128Shuffle(e) => assert_eq!(e.indices, [3; 16]),
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
@@ -0,0 +1,272 @@
+//! Tests for simplifying branches with constant conditions.
+
+use walrus::ir::*;
+use walrus::passes::{simplify_branches, PassManager, SimplifyBranches};
+use walrus::ValType;
+use walrus::{BlockBuilder, FunctionBuilder, FunctionId, FunctionKind, LocalFunction, Module};
+
+fn local(module: &Module, func: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn entry(module: &Module, func: FunctionId) -> Vec<ExprId> {
+    let func = local(module, func);
+    func.block(func.entry_block()).exprs.clone()
+}
+
+fn exprs(module: &Module, func: FunctionId, block: BlockId) -> Vec<ExprId> {
+    local(module, func).block(block).exprs.clone()
+}
+
+#[test]
+fn br_if() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+
+    // (block $b (br_if $b (i32.const 0)) (br_if $b (i32.const 1)))
+    let mut builder = FunctionBuilder::new();
+    let (block, taken) = {
+        let mut block = builder.block(Box::new([]), Box::new([]));
+        let id = block.id();
+        let zero = block.i32_const(0);
+        let untaken = block.br_if(zero, id, Box::new([]));
+        block.expr(untaken);
+        let one = block.i32_const(1);
+        let taken = block.br_if(one, id, Box::new([]));
+        block.expr(taken);
+        (id, taken)
+    };
+    let func = builder.finish(ty, vec![], vec![block.into()], &mut module);
+
+    assert_eq!(simplify_branches(&mut module), 2);
+    assert_eq!(exprs(&module, func, block), [taken]);
+    match local(&module, func).get(taken) {
+        Expr::Br(br) => assert_eq!(br.block, block),
+        _ => panic!("expected a br"),
+    }
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn if_else() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+
+    let mut builder = FunctionBuilder::new();
+    let arm = |builder: &mut FunctionBuilder, n| {
+        let mut block = builder.if_else_block(Box::new([]), Box::new([ValType::I32]));
+        let value = block.i32_const(n);
+        block.expr(value);
+        (block.id(), value)
+    };
+
+    // (drop (if (result i32) (i32.const 1) (then (i32.const 1)) (else (i32.const 2))))
+    let (one, one_value) = arm(&mut builder, 1);
+    let (two, _) = arm(&mut builder, 2);
+    let condition = builder.i32_const(1);
+    let nested = builder.if_else(condition, one, two);
+    let dropped = builder.drop(nested);
+
+    // (if (result i32) (i32.const 0) (then (i32.const 3)) (else (i32.const 4)))
+    let (three, _) = arm(&mut builder, 3);
+    let (four, four_value) = arm(&mut builder, 4);
+    let condition = builder.i32_const(0);
+    let spliced = builder.if_else(condition, three, four);
+
+    let func = builder.finish(ty, vec![], vec![dropped, spliced], &mut module);
+
+    assert_eq!(simplify_branches(&mut module), 2);
+    assert_eq!(entry(&module, func), [dropped, four_value]);
+    match local(&module, func).get(nested) {
+        Expr::Block(block) => {
+            assert_eq!(block.kind, BlockKind::Block);
+            assert_eq!(block.exprs, [one_value]);
+        }
+        _ => panic!("expected a block"),
+    }
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn nested_in_if_arms_and_try_bodies() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let x = module.locals.add(ValType::I32);
+
+    // (if (local.get $x)
+    //   (then
+    //     (br_if 0 (i32.const 0))
+    //     (if (i32.const 1) (then (unreachable)) (else)))
+    //   (else
+    //     (try (do (br_if 0 (i32.const 0)) (unreachable)))))
+    let mut builder = FunctionBuilder::new();
+    let untaken = |block: &mut BlockBuilder, id| {
+        let zero = block.i32_const(0);
+        let br_if = block.br_if(zero, id, Box::new([]));
+        block.expr(br_if);
+    };
+    let (inner, trap) = {
+        let mut inner = builder.if_else_block(Box::new([]), Box::new([]));
+        let trap = inner.unreachable();
+        inner.expr(trap);
+        (inner.id(), trap)
+    };
+    let then = {
+        let empty = builder.if_else_block(Box::new([]), Box::new([])).id();
+        let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+        let id = block.id();
+        untaken(&mut block, id);
+        let one = block.i32_const(1);
+        let nested = block.if_else(one, inner, empty);
+        block.expr(nested);
+        id
+    };
+    let (body, try_trap) = {
+        let mut body = builder.try_block(Box::new([]), Box::new([]));
+        let id = body.id();
+        untaken(&mut body, id);
+        let trap = body.unreachable();
+        body.expr(trap);
+        (id, trap)
+    };
+    let try_ = builder.try_(body, Box::new([]), Box::new([]), None, None);
+    let otherwise = {
+        let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+        block.expr(try_);
+        block.id()
+    };
+    let condition = builder.local_get(x);
+    let if_else = builder.if_else(condition, then, otherwise);
+    let func = builder.finish(ty, vec![x], vec![if_else], &mut module);
+
+    assert_eq!(simplify_branches(&mut module), 3);
+    assert_eq!(exprs(&module, func, then), [trap]);
+    assert_eq!(exprs(&module, func, body), [try_trap]);
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn select() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let f = module.add_import_func("env", "f", ty);
+    let ty = module.types.add(&[], &[]);
+
+    // (drop (select (i32.const 5) (i32.const 5) (i32.const 1)))
+    // (drop (select (i32.const 5) (i32.const 5) (call $f)))
+    let mut builder = FunctionBuilder::new();
+    let mut body = Vec::new();
+    let mut selects = Vec::new();
+    for pure in &[true, false] {
+        let a = builder.i32_const(5);
+        let b = builder.i32_const(5);
+        let condition = if *pure {
+            builder.i32_const(1)
+        } else {
+            builder.call(f, Box::new([]))
+        };
+        let select = builder.select(condition, a, b, None);
+        selects.push((select, a, condition));
+        body.push(builder.drop(select));
+    }
+    let func = builder.finish(ty, vec![], body, &mut module);
+
+    assert_eq!(simplify_branches(&mut module), 2);
+    let func = local(&module, func);
+    match func.get(selects[0].0) {
+        Expr::Const(Const {
+            value: Value::I32(5),
+        }) => {}
+        _ => panic!("expected a constant"),
+    }
+    match func.get(selects[1].0) {
+        Expr::WithSideEffects(e) => {
+            assert!(e.before.is_empty());
+            assert_eq!(e.value, selects[1].1);
+            assert_eq!(e.after.len(), 1);
+            match func.get(e.after[0]) {
+                Expr::Drop(d) => assert_eq!(d.expr, selects[1].2),
+                _ => panic!("expected a drop"),
+            }
+        }
+        _ => panic!("expected side effects to be kept"),
+    }
+    module.emit_wasm().unwrap();
+}
+
+#[test]
+fn br_table() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+
+    // (block $outer
+    //   (block $inner (br_table $inner $outer (i32.const 0)))
+    //   (block $inner2 (br_table $inner2 $outer (i32.const 7))))
+    let mut builder = FunctionBuilder::new();
+    let mut outer = builder.block(Box::new([]), Box::new([]));
+    let outer_id = outer.id();
+    let mut tables = Vec::new();
+    for n in &[0, 7] {
+        let mut inner = outer.block(Box::new([]), Box::new([]));
+        let inner_id = inner.id();
+        let which = inner.i32_const(*n);
+        let table = inner.br_table(which, Box::new([inner_id]), outer_id, Box::new([]));
+        inner.expr(table);
+        drop(inner);
+        outer.expr(inner_id.into());
+        tables.push((table, inner_id));
+    }
+    drop(outer);
+    let func = builder.finish(ty, vec![], vec![outer_id.into()], &mut module);
+
+    assert_eq!(simplify_branches(&mut module), 2);
+    let func = local(&module, func);
+    for (table, target) in [(tables[0].0, tables[0].1), (tables[1].0, outer_id)] {
+        match func.get(table) {
+            Expr::Br(br) => assert_eq!(br.block, target),
+            _ => panic!("expected a br"),
+        }
+    }
+}
+
+#[test]
+fn fixpoint() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+
+    // (block $b
+    //   (if (select (i32.const 1) (i32.const 1) (i32.const 0))
+    //     (then (br_if $b (i32.const 0)))
+    //     (else)))
+    let mut builder = FunctionBuilder::new();
+    let mut block = builder.block(Box::new([]), Box::new([]));
+    let block_id = block.id();
+    let then = {
+        let mut then = block.if_else_block(Box::new([]), Box::new([]));
+        let zero = then.i32_const(0);
+        let br_if = then.br_if(zero, block_id, Box::new([]));
+        then.expr(br_if);
+        then.id()
+    };
+    let otherwise = block.if_else_block(Box::new([]), Box::new([])).id();
+    let a = block.i32_const(1);
+    let b = block.i32_const(1);
+    let condition = block.i32_const(0);
+    let select = block.select(condition, a, b, None);
+    let if_else = block.if_else(select, then, otherwise);
+    block.expr(if_else);
+    drop(block);
+    let func = builder.finish(ty, vec![], vec![block_id.into()], &mut module);
+
+    let mut passes = PassManager::new();
+    passes.add_fixpoint(vec![Box::new(SimplifyBranches)]);
+    let reports = passes.run(&mut module).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].stats.changed);
+    assert_eq!(reports[0].runs, 3);
+    assert!(exprs(&module, func, block_id).is_empty());
+    module.emit_wasm().unwrap();
+}
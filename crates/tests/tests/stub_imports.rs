@@ -0,0 +1,101 @@
+//! Tests for stubbing out imported functions.
+
+use std::fs;
+use walrus::passes::{stub_missing_imports, validate, StubKind};
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, Module};
+use walrus::{UnstubbableImport, ValType};
+use walrus_tests_utils::wasm_interp;
+
+const WASI: &str = "wasi_snapshot_preview1";
+
+/// A module importing `fd_write` and `proc_exit` from WASI and `print` from
+/// the host, with an exported `main` returning the result of `fd_write`.
+fn module() -> (Module, FunctionId) {
+    let mut module = Module::default();
+    let fd_write_ty = module.types.add(&[ValType::I32; 4], &[ValType::I32]);
+    let fd_write = module.add_import_func(WASI, "fd_write", fd_write_ty);
+    let proc_exit_ty = module.types.add(&[ValType::I32], &[]);
+    let proc_exit = module.add_import_func(WASI, "proc_exit", proc_exit_ty);
+    module.funcs.get_mut(proc_exit).name = Some("proc_exit".to_string());
+    module.add_import_func("host", "print", proc_exit_ty);
+
+    let main_ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let args = (0..4).map(|_| builder.i32_const(0)).collect();
+    let call = builder.call(fd_write, args);
+    let main = builder.finish(main_ty, vec![], vec![call], &mut module);
+    module.exports.add("main", ExportItem::Function(main));
+    (module, fd_write)
+}
+
+fn emit_and_run(module: &Module) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stubbed.wasm");
+    fs::write(&path, module.emit_wasm().unwrap()).unwrap();
+    wasm_interp(&path)
+}
+
+#[test]
+fn stubbed_imports_become_local_functions() {
+    let (mut module, fd_write) = module();
+    let stubbed = stub_missing_imports(&mut module, |m, _| m == WASI, StubKind::ReturnZero);
+    assert_eq!(
+        stubbed.unwrap(),
+        [
+            (WASI.to_string(), "fd_write".to_string()),
+            (WASI.to_string(), "proc_exit".to_string()),
+        ]
+    );
+    validate::run(&module).unwrap();
+
+    match &module.funcs.get(fd_write).kind {
+        FunctionKind::Local(_) => {}
+        _ => panic!("fd_write should be local"),
+    }
+    let proc_exit = module.funcs.by_name("proc_exit").unwrap();
+    match &module.funcs.get(proc_exit).kind {
+        FunctionKind::Local(_) => {}
+        _ => panic!("proc_exit should be local"),
+    }
+
+    // Only the host's import is left to satisfy when instantiating.
+    let wasm = module.emit_wasm().unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    let imports = module
+        .imports
+        .iter()
+        .map(|i| (i.module.as_str(), i.name.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(imports, [("host", "print")]);
+    assert_eq!(module.funcs.iter().count(), 4);
+    assert_eq!(emit_and_run(&module).trim(), "main() => i32:0");
+}
+
+#[test]
+fn trapping_stubs() {
+    let (mut module, _) = module();
+    stub_missing_imports(&mut module, |_, name| name == "fd_write", StubKind::Trap).unwrap();
+    validate::run(&module).unwrap();
+    assert_eq!(module.imports.iter().count(), 2);
+    assert!(emit_and_run(&module).contains("unreachable"));
+}
+
+#[test]
+fn only_functions_can_be_stubbed() {
+    let (mut module, fd_write) = module();
+    module.add_import_global(WASI, "errno", ValType::I32, true);
+    let err = stub_missing_imports(&mut module, |m, _| m == WASI, StubKind::Trap).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<UnstubbableImport>(),
+        Some(&UnstubbableImport {
+            module: WASI.to_string(),
+            name: "errno".to_string(),
+            kind: "global",
+        })
+    );
+    assert_eq!(module.imports.iter().count(), 4);
+    match &module.funcs.get(fd_write).kind {
+        FunctionKind::Import(_) => {}
+        _ => panic!("fd_write should still be imported"),
+    }
+}
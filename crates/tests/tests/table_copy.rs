@@ -0,0 +1,56 @@
+//! Tests for `table.copy`.
+
+use walrus::ir::{Expr, Value};
+use walrus::{FunctionBuilder, FunctionKind, FunctionTable, Module, ModuleConfig, TableKind};
+use walrus_tests_utils::function_bodies;
+
+#[test]
+fn table_copy_between_tables() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[], &[]);
+    let unused = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let src = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let dst = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+
+    let mut builder = FunctionBuilder::new();
+    let dst_offset = builder.const_(Value::I32(0));
+    let src_offset = builder.const_(Value::I32(1));
+    let len = builder.const_(Value::I32(2));
+    let copy = builder.table_copy(src, dst, dst_offset, src_offset, len);
+    let caller = builder.finish(ty, vec![], vec![copy], &mut module);
+    module.exports.add("caller", caller);
+
+    // Both tables stay alive, and keep their order.
+    walrus::passes::gc::run(&mut module);
+    let tables = module.tables.iter().map(|t| t.id()).collect::<Vec<_>>();
+    assert_eq!(tables, [src, dst]);
+    assert!(!tables.contains(&unused));
+
+    let wasm = module.emit_wasm().unwrap();
+    let body = [
+        0x00, 0x41, 0x00, 0x41, 0x01, 0x41, 0x02, 0xfc, 0x0e, 0x01, 0x00, 0x0b,
+    ];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+
+    // And it's parsed back between the same tables.
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(function_bodies(&parsed.emit_wasm().unwrap()), [&body[..]]);
+    let tables = parsed.tables.iter().map(|t| t.id()).collect::<Vec<_>>();
+    let caller = parsed.exports.get_func("caller").unwrap();
+    let caller = match &parsed.funcs.get(caller).kind {
+        FunctionKind::Local(l) => l,
+        _ => unreachable!(),
+    };
+    match caller.get(caller.block(caller.entry_block()).exprs[0]) {
+        Expr::TableCopy(copy) => assert_eq!([copy.src, copy.dst], tables[..]),
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
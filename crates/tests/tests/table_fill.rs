@@ -0,0 +1,54 @@
+//! Tests for `table.fill`.
+
+use walrus::ir::{Expr, RefType, Value};
+use walrus::{DisplayOptions, FunctionBuilder, FunctionKind, FunctionTable, Module};
+use walrus::{ModuleConfig, TableKind};
+use walrus_tests_utils::function_bodies;
+
+#[test]
+fn fill_with_null() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[], &[]);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+
+    let mut builder = FunctionBuilder::new();
+    let start = builder.const_(Value::I32(0));
+    let null = builder.ref_null(RefType::Funcref);
+    let len = builder.const_(Value::I32(2));
+    let fill = builder.table_fill(table, start, null, len);
+    let func = builder.finish(ty, vec![], vec![fill], &mut module);
+
+    let display = match &module.funcs.get(func).kind {
+        FunctionKind::Local(l) => l.display(&DisplayOptions::default()),
+        _ => unreachable!(),
+    };
+    assert!(display.contains("table.fill"));
+
+    let wasm = module.emit_wasm().unwrap();
+    let body = [
+        0x00, 0x41, 0x00, 0xd0, 0x70, 0x41, 0x02, 0xfc, 0x11, 0x00, 0x0b,
+    ];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+
+    // And it's parsed back the same way.
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(function_bodies(&parsed.emit_wasm().unwrap()), [&body[..]]);
+    let (_, func) = parsed.funcs.iter_local().next().unwrap();
+    match func.get(func.block(func.entry_block()).exprs[0]) {
+        Expr::TableFill(fill) => {
+            assert_eq!(
+                Some(fill.table),
+                parsed.tables.iter().map(|t| t.id()).next()
+            );
+            match func.get(fill.value) {
+                Expr::RefNull(null) => assert_eq!(null.ty, RefType::Funcref),
+                e => panic!("unexpected value: {:?}", e),
+            }
+        }
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
@@ -0,0 +1,44 @@
+//! Tests for `table.get` and `table.set`.
+
+use walrus::ir::{Expr, Value};
+use walrus::{FunctionBuilder, FunctionTable, Module, ModuleConfig};
+use walrus::{TableKind, ValType};
+use walrus_tests_utils::function_bodies;
+
+#[test]
+fn copy_within_function_table() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let dst = module.locals.add(ValType::I32);
+
+    let mut builder = FunctionBuilder::new();
+    let index = builder.local_get(dst);
+    let src = builder.const_(Value::I32(0));
+    let get = builder.table_get(table, src);
+    let set = builder.table_set(table, index, get);
+    builder.finish(ty, vec![dst], vec![set], &mut module);
+
+    let wasm = module.emit_wasm().unwrap();
+    let body = [0x00, 0x20, 0x00, 0x41, 0x00, 0x25, 0x00, 0x26, 0x00, 0x0b];
+    assert_eq!(function_bodies(&wasm), [&body[..]]);
+
+    // The funcref from `table.get` is accepted by `table.set`.
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(function_bodies(&parsed.emit_wasm().unwrap()), [&body[..]]);
+    let (_, func) = parsed.funcs.iter_local().next().unwrap();
+    match func.get(func.block(func.entry_block()).exprs[0]) {
+        Expr::TableSet(set) => {
+            assert!(matches!(func.get(set.value), Expr::TableGet(_)));
+            assert_eq!(
+                func.infer_block_results(&parsed, &[set.value]).unwrap(),
+                [ValType::Funcref]
+            );
+        }
+        e => panic!("unexpected expression: {:?}", e),
+    }
+}
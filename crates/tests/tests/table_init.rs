@@ -0,0 +1,89 @@
+//! Tests for `table.init` and `elem.drop`.
+
+use walrus::ir::{Expr, Value};
+use walrus::{ElementKind, FunctionBuilder, FunctionKind, FunctionTable, InitExpr, Module};
+use walrus::{ModuleConfig, TableKind, ValType};
+use walrus_tests_utils::function_bodies;
+
+#[test]
+fn gc_keeps_initialized_segments() {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+    let ty = module.types.add(&[], &[]);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let target = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let other = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.elements.add_passive(&[other]);
+    let elem = module.elements.add_passive(&[target]);
+
+    let mut builder = FunctionBuilder::new();
+    let dst = builder.const_(Value::I32(0));
+    let src = builder.const_(Value::I32(0));
+    let len = builder.const_(Value::I32(1));
+    let init = builder.table_init(table, elem, dst, src, len);
+    let drop = builder.elem_drop(elem);
+    let caller = builder.finish(ty, vec![], vec![init, drop], &mut module);
+    module.exports.add("caller", caller);
+    walrus::passes::validate::run(&module).unwrap();
+
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(
+        module.elements.iter().map(|e| e.id()).collect::<Vec<_>>(),
+        [elem]
+    );
+    assert!(module.funcs.iter().any(|f| f.id() == target));
+    assert!(!module.funcs.iter().any(|f| f.id() == other));
+
+    // The segment is the only one left, so it has index 0.
+    let wasm = module.emit_wasm().unwrap();
+    let body = [
+        0x00, 0x41, 0x00, 0x41, 0x00, 0x41, 0x01, 0xfc, 0x0c, 0x00, 0x00, 0xfc, 0x0d, 0x00, 0x0b,
+    ];
+    assert_eq!(function_bodies(&wasm), [&body[..], &[0x00, 0x0b]]);
+
+    // And it's parsed back the same way.
+    let parsed = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(
+        function_bodies(&parsed.emit_wasm().unwrap()),
+        [&body[..], &[0x00, 0x0b]]
+    );
+    let elem = parsed.elements.iter().next().unwrap().id();
+    let caller = parsed.exports.get_func("caller").unwrap();
+    let caller = match &parsed.funcs.get(caller).kind {
+        FunctionKind::Local(l) => l,
+        _ => unreachable!(),
+    };
+    let exprs = &caller.block(caller.entry_block()).exprs;
+    match (caller.get(exprs[0]), caller.get(exprs[1])) {
+        (Expr::TableInit(init), Expr::ElemDrop(drop)) => {
+            assert_eq!(init.elem, elem);
+            assert_eq!(drop.elem, elem);
+        }
+        e => panic!("unexpected expressions: {:?}", e),
+    }
+}
+
+#[test]
+fn active_segments_cant_be_referenced() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let target = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let offset = InitExpr::Value(Value::I32(0));
+    let elem = module.elements.add(
+        ElementKind::Active { table, offset },
+        ValType::Funcref,
+        vec![Some(target)],
+    );
+
+    let mut builder = FunctionBuilder::new();
+    let drop = builder.elem_drop(elem);
+    builder.finish(ty, vec![], vec![drop], &mut module);
+    let err = walrus::passes::validate::run(&module).unwrap_err();
+    assert!(err.to_string().contains("not passive"));
+}
@@ -0,0 +1,101 @@
+//! Tests for changing the limits of tables.
+
+use walrus::ir::Value;
+use walrus::{FunctionBuilder, FunctionId, FunctionTable, InitExpr, Module, TableId};
+use walrus::{TableKind, ValType};
+
+/// A module with an over-provisioned function table holding a `(func)` in
+/// slots 4 and 5.
+fn fixture() -> (Module, TableId, FunctionId) {
+    let mut module = Module::default();
+    let table = module.tables.add_local(
+        100,
+        Some(200),
+        TableKind::Function(FunctionTable::default()),
+    );
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let offset = InitExpr::Value(Value::I32(4));
+    module.elements.add_active(table, offset, &[f, f]);
+    module.exports.add("table", table);
+    (module, table, f)
+}
+
+fn limits(module: &Module, table: TableId) -> (u32, Option<u32>) {
+    let table = module.tables.get(table);
+    (table.initial, table.maximum)
+}
+
+#[test]
+fn shrink_to_fit() {
+    let (mut module, table, _) = fixture();
+    assert_eq!(module.required_table_min(table), 6);
+    module.set_table_limits(table, 6, Some(6)).unwrap();
+    assert_eq!(limits(&module, table), (6, Some(6)));
+    let wasm = module.emit_wasm().unwrap();
+    assert!(wasmparser::validate(&wasm, None));
+}
+
+#[test]
+fn too_small_for_segments() {
+    let (mut module, table, _) = fixture();
+    assert!(module.set_table_limits(table, 5, None).is_err());
+    assert_eq!(limits(&module, table), (100, Some(200)));
+
+    // Reserved slots count too.
+    module.tables.reserve_slots(table, 10).unwrap();
+    assert_eq!(module.required_table_min(table), 110);
+    assert!(module.set_table_limits(table, 109, None).is_err());
+    module.set_table_limits(table, 110, None).unwrap();
+}
+
+#[test]
+fn maximum_less_than_initial() {
+    let (mut module, table, _) = fixture();
+    assert!(module.set_table_limits(table, 10, Some(9)).is_err());
+    assert!(module.tables.set_limits(table, 10, Some(9)).is_err());
+    assert_eq!(limits(&module, table), (100, Some(200)));
+}
+
+#[test]
+fn too_big_for_engines() {
+    let (mut module, table, _) = fixture();
+    assert!(module.set_table_limits(table, 20_000_000, None).is_err());
+    module.set_table_limits(table, 10_000_000, None).unwrap();
+    // Only the initial size is capped.
+    module.set_table_limits(table, 10, Some(u32::MAX)).unwrap();
+    assert!(module.tables.reserve_slots(table, 10_000_000).is_err());
+}
+
+#[test]
+fn imported_tables_cant_get_stricter() {
+    let mut module = Module::default();
+    let kind = TableKind::Function(FunctionTable::default());
+    let table = module.add_import_table("env", "table", 10, Some(20), kind);
+
+    assert!(module.set_table_limits(table, 11, Some(20)).is_err());
+    assert!(module.set_table_limits(table, 10, Some(19)).is_err());
+    module.set_table_limits(table, 5, Some(30)).unwrap();
+    module.set_table_limits(table, 5, None).unwrap();
+    assert!(module.set_table_limits(table, 5, Some(30)).is_err());
+    assert_eq!(limits(&module, table), (5, None));
+}
+
+#[test]
+fn segments_at_global_offsets() {
+    let (mut module, table, f) = fixture();
+    let constant = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(10)));
+    module
+        .elements
+        .add_active(table, InitExpr::Global(constant), &[f]);
+    assert_eq!(module.required_table_min(table), 11);
+
+    // Where this ends isn't known until the module is instantiated.
+    let imported = module.add_import_global("env", "base", ValType::I32, false);
+    module
+        .elements
+        .add_active(table, InitExpr::Global(imported), &[f, f, f]);
+    assert_eq!(module.required_table_min(table), 11);
+}
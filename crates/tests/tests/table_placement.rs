@@ -0,0 +1,99 @@
+//! Tests for reserving table slots and placing functions in them.
+
+use walrus::ir::Value;
+use walrus::{ExportItem, FunctionBuilder, FunctionId, FunctionTable, InitExpr, Module};
+use walrus::{TableId, TableKind, ValType};
+
+/// A module with an empty function table and two `(func)`s.
+fn fixture() -> (Module, TableId, FunctionId, FunctionId) {
+    let mut module = Module::default();
+    let table = module
+        .tables
+        .add_local(0, None, TableKind::Function(FunctionTable::default()));
+    let ty = module.types.add(&[], &[]);
+    let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    let g = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    (module, table, f, g)
+}
+
+#[test]
+fn reserve_slots() {
+    let (mut module, table, _, _) = fixture();
+    assert_eq!(module.tables.reserve_slots(table, 4).unwrap(), 0..4);
+    assert_eq!(module.tables.reserve_slots(table, 2).unwrap(), 4..6);
+    assert_eq!(module.tables.get(table).initial, 6);
+    assert_eq!(module.tables.get(table).reserved, [0..4, 4..6]);
+
+    module.tables.get_mut(table).maximum = Some(8);
+    assert!(module.tables.reserve_slots(table, 3).is_err());
+    assert_eq!(module.tables.get(table).initial, 6);
+}
+
+#[test]
+fn place_non_overlapping_segments() {
+    let (mut module, table, f, g) = fixture();
+    let slots = module.tables.reserve_slots(table, 4).unwrap();
+    let elements = &mut module.elements;
+    let tables = &module.tables;
+
+    elements
+        .place_functions(tables, table, slots.start, &[f, g], false)
+        .unwrap();
+    let second = elements
+        .place_functions(tables, table, slots.start + 2, &[g], false)
+        .unwrap();
+    assert!(elements
+        .place_functions(tables, table, slots.start + 1, &[f], false)
+        .is_err());
+    // Doesn't fit in the table.
+    assert!(elements
+        .place_functions(tables, table, slots.start + 3, &[f, g], false)
+        .is_err());
+
+    elements.rebase_segment(tables, second, 3, false).unwrap();
+    assert!(elements.rebase_segment(tables, second, 1, false).is_err());
+
+    module.exports.add("table", ExportItem::Table(table));
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.elements.iter().count(), 2);
+    let wasm = module.emit_wasm().unwrap();
+    let segments = [
+        0x02, 0x00, 0x41, 0x00, 0x0b, 0x02, 0x00, 0x01, 0x00, 0x41, 0x03, 0x0b, 0x01, 0x01,
+    ];
+    assert_eq!(walrus_tests_utils::section(&wasm, 0x09), segments);
+}
+
+#[test]
+fn relative_segments_need_override() {
+    let (mut module, table, f, _) = fixture();
+    let global = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    module.tables.reserve_slots(table, 2).unwrap();
+    match &mut module.tables.get_mut(table).kind {
+        TableKind::Function(t) => t.relative_elements.push((global, vec![Some(f)])),
+        TableKind::Externref(_) => unreachable!(),
+    }
+
+    let elements = &mut module.elements;
+    assert!(elements
+        .place_functions(&module.tables, table, 0, &[f], false)
+        .is_err());
+    elements
+        .place_functions(&module.tables, table, 0, &[f], true)
+        .unwrap();
+}
+
+#[test]
+fn segments_past_the_end_of_the_index_space() {
+    let (mut module, table, f, g) = fixture();
+    module.tables.get_mut(table).initial = 4;
+    let offset = InitExpr::Value(Value::I32(-1));
+    module.elements.add_active(table, offset, &[f, g]);
+
+    // The segment starts at slot `u32::MAX`, so it can't overlap these.
+    let elements = &mut module.elements;
+    elements
+        .place_functions(&module.tables, table, 0, &[f, g], false)
+        .unwrap();
+}
@@ -0,0 +1,62 @@
+//! Tests for building traps which report a message before trapping.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, InitExpr, Module, ValType};
+
+fn address(offset: &InitExpr) -> i32 {
+    match offset {
+        InitExpr::Value(Value::I32(n)) => *n,
+        _ => panic!("not an absolute address"),
+    }
+}
+
+#[test]
+fn traps_with_the_same_message_share_data() {
+    let mut module = Module::default();
+    let abort_ty = module.types.add(&[ValType::I32, ValType::I32], &[]);
+    let abort = module.add_import_func("env", "abort", abort_ty);
+    let memory = module.memories.add_local(false, 1, None);
+    module.exports.add("memory", memory);
+
+    let ty = module.types.add(&[ValType::I32], &[]);
+    let arg = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new();
+    let mut body = Vec::new();
+    for message in ["out of bounds", "out of bounds", "null pointer"].iter() {
+        let value = builder.local_get(arg);
+        let condition = builder.unop(UnaryOp::I32Eqz, value);
+        let trap = builder
+            .trap_if(&mut module, condition, message, abort, memory)
+            .unwrap();
+        body.push(trap);
+    }
+    let func = builder.finish(ty, vec![arg], body, &mut module);
+    module.exports.add("check", func);
+
+    // The messages go in a page of their own after the existing one.
+    let mem = module.memories.get(memory);
+    assert_eq!(mem.initial, 2);
+    let data = mem.data.iter().collect::<Vec<_>>();
+    assert_eq!(data.len(), 2);
+    assert_eq!(address(&data[0].0), 65536);
+    assert_eq!(data[0].1, b"out of bounds");
+    assert_eq!(address(&data[1].0), 65536 + 16);
+    assert_eq!(data[1].1, b"null pointer");
+
+    let wasm = module.emit_wasm().unwrap();
+    assert!(wasmparser::validate(&wasm, None));
+}
+
+#[test]
+fn abort_must_take_a_message() {
+    let mut module = Module::default();
+    let abort_ty = module.types.add(&[], &[]);
+    let abort = module.add_import_func("env", "abort", abort_ty);
+    let memory = module.memories.add_local(false, 1, None);
+
+    let mut builder = FunctionBuilder::new();
+    let condition = builder.i32_const(1);
+    assert!(builder
+        .trap_if(&mut module, condition, "oops", abort, memory)
+        .is_err());
+}
@@ -0,0 +1,142 @@
+//! Tests for finding the expressions which might trap.
+
+use walrus::ir::*;
+use walrus::passes::{can_trap, trap_sites, TrapKind, TrapSite};
+use walrus::{FunctionBuilder, FunctionTable, Module, TableKind, ValType};
+
+#[test]
+fn one_of_each() {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    let unit = module.types.add(&[], &[]);
+    let x = module.locals.add(ValType::I32);
+    let f = module.locals.add(ValType::F32);
+
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    let mut expected = Vec::new();
+    let arg = MemArg {
+        align: 4,
+        offset: 0,
+    };
+
+    let unreachable = builder.unreachable();
+    exprs.push(unreachable);
+    expected.push((unreachable, TrapKind::Unreachable));
+
+    // A load of a variable address might trap, but not one of a constant
+    // address within the memory's first page.
+    let address = builder.local_get(x);
+    let load = builder.load(memory, LoadKind::I32 { atomic: false }, arg, address);
+    exprs.push(builder.drop(load));
+    expected.push((load, TrapKind::MemoryAccess));
+    let address = builder.i32_const(65532);
+    let load = builder.load(memory, LoadKind::I32 { atomic: false }, arg, address);
+    exprs.push(builder.drop(load));
+    let address = builder.i32_const(65533);
+    let value = builder.i32_const(0);
+    let store = builder.store(
+        memory,
+        StoreKind::I32 { atomic: false },
+        arg,
+        address,
+        value,
+    );
+    exprs.push(store);
+    expected.push((store, TrapKind::MemoryAccess));
+
+    // Dividing by zero, or signed division of the minimum by -1, traps.
+    let lhs = builder.local_get(x);
+    let rhs = builder.local_get(x);
+    let div = builder.binop(BinaryOp::I32DivU, lhs, rhs);
+    exprs.push(builder.drop(div));
+    expected.push((div, TrapKind::IntegerDivision));
+    let lhs = builder.local_get(x);
+    let rhs = builder.i32_const(-1);
+    let div = builder.binop(BinaryOp::I32DivS, lhs, rhs);
+    exprs.push(builder.drop(div));
+    expected.push((div, TrapKind::IntegerDivision));
+    let lhs = builder.local_get(x);
+    let rhs = builder.i32_const(-1);
+    let rem = builder.binop(BinaryOp::I32RemS, lhs, rhs);
+    exprs.push(builder.drop(rem));
+    let lhs = builder.local_get(x);
+    let rhs = builder.i32_const(7);
+    let div = builder.binop(BinaryOp::I32DivS, lhs, rhs);
+    exprs.push(builder.drop(div));
+
+    let value = builder.local_get(f);
+    let trunc = builder.unop(UnaryOp::I32TruncSF32, value);
+    exprs.push(builder.drop(trunc));
+    expected.push((trunc, TrapKind::FloatToInt));
+    let value = builder.local_get(f);
+    let trunc = builder.unop(UnaryOp::I32TruncSSatF32, value);
+    exprs.push(builder.drop(trunc));
+
+    let index = builder.i32_const(0);
+    let call = builder.call_indirect(unit, table, index, Box::new([]));
+    exprs.push(call);
+    expected.push((call, TrapKind::IndirectCall));
+
+    let index = builder.local_get(x);
+    let get = builder.table_get(table, index);
+    exprs.push(builder.drop(get));
+    expected.push((get, TrapKind::TableAccess));
+
+    // Growing memory returns -1 on failure rather than trapping.
+    let pages = builder.i32_const(1);
+    let grown = builder.memory_grow(memory, pages);
+    exprs.push(builder.drop(grown));
+
+    let func = builder.finish(unit, vec![], exprs, &mut module);
+
+    // `trap_sites` reports expressions in the order they're visited, which is
+    // the order they were built in here.
+    let sites = trap_sites(&module);
+    let expected = expected
+        .into_iter()
+        .map(|(expr, kind)| TrapSite { func, expr, kind })
+        .collect::<Vec<_>>();
+    assert_eq!(sites, expected);
+}
+
+#[test]
+fn can_trap_follows_direct_calls() {
+    let mut module = Module::default();
+    let unit = module.types.add(&[], &[]);
+    let imported = module.add_import_func("env", "f", unit);
+
+    let func = |module: &mut Module, body: &dyn Fn(&mut FunctionBuilder) -> Vec<ExprId>| {
+        let mut builder = FunctionBuilder::new();
+        let exprs = body(&mut builder);
+        builder.finish(unit, vec![], exprs, module)
+    };
+    let traps = func(&mut module, &|b| vec![b.unreachable()]);
+    let calls_traps = func(&mut module, &|b| vec![b.call(traps, Box::new([]))]);
+    let calls_calls_traps = func(&mut module, &|b| vec![b.call(calls_traps, Box::new([]))]);
+    let calls_import = func(&mut module, &|b| vec![b.call(imported, Box::new([]))]);
+    let safe = func(&mut module, &|b| {
+        let one = b.i32_const(1);
+        vec![b.drop(one)]
+    });
+    let calls_safe = func(&mut module, &|b| vec![b.call(safe, Box::new([]))]);
+
+    let trapping = can_trap(&module);
+    for f in [
+        traps,
+        calls_traps,
+        calls_calls_traps,
+        calls_import,
+        imported,
+    ]
+    .iter()
+    {
+        assert!(trapping.contains(f));
+    }
+    assert!(!trapping.contains(&safe));
+    assert!(!trapping.contains(&calls_safe));
+    assert_eq!(trapping.len(), 5);
+}
@@ -0,0 +1,70 @@
+//! Tests for the order of the emitted type section.
+
+use walrus::{ExportItem, FunctionBuilder, Module, ValType};
+
+/// The parameters and results of each entry in the type section of `wasm`.
+fn type_section(wasm: &[u8]) -> Vec<(Vec<wasmparser::Type>, Vec<wasmparser::Type>)> {
+    let mut reader = wasmparser::ModuleReader::new(wasm).unwrap();
+    let mut types = Vec::new();
+    while !reader.eof() {
+        let section = reader.read().unwrap();
+        if let wasmparser::SectionCode::Type = section.code {
+            for ty in section.get_type_section_reader().unwrap() {
+                let ty = ty.unwrap();
+                types.push((ty.params.to_vec(), ty.returns.to_vec()));
+            }
+        }
+    }
+    types
+}
+
+#[test]
+fn parsed_types_come_first_in_their_original_order() {
+    use wasmparser::Type::{F64, I32, I64};
+
+    let mut module = Module::default();
+    module.types.add(&[ValType::I64], &[]);
+    module.types.add(&[], &[ValType::I32]);
+    let wasm = module.emit_wasm().unwrap();
+
+    let mut module = Module::from_buffer(&wasm).unwrap();
+    module.types.add(&[ValType::F64], &[]);
+    module.types.add(&[], &[ValType::I32]);
+    module.types.add(&[ValType::I32], &[ValType::I32]);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(
+        type_section(&wasm),
+        [
+            (vec![I64], vec![]),
+            (vec![], vec![I32]),
+            (vec![F64], vec![]),
+            (vec![I32], vec![I32]),
+        ]
+    );
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
+
+#[test]
+fn identical_types_are_emitted_once() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let arg = module.locals.add(ValType::I32);
+    let get = builder.local_get(arg);
+    let func = builder.finish(ty, vec![arg], vec![get], &mut module);
+    module.exports.add("f", ExportItem::Function(func));
+    let wasm = module.emit_wasm().unwrap();
+
+    // The same signature, both parsed and added again afterwards.
+    let mut module = Module::from_buffer(&wasm).unwrap();
+    let parsed = module.types.iter().next().unwrap().id();
+    let added = module.types.add(&[ValType::I32], &[ValType::I32]);
+    assert_eq!(added, parsed);
+    module.add_import_func("env", "g", added);
+    assert_eq!(module.types.iter().count(), 1);
+
+    let wasm = module.emit_wasm().unwrap();
+    assert_eq!(type_section(&wasm).len(), 1);
+    assert_eq!(module.emit_wasm().unwrap(), wasm);
+}
@@ -0,0 +1,70 @@
+//! Tests for the typed `select` of the reference types proposal.
+
+use walrus::ir::{Expr, RefType, Value};
+use walrus::{FunctionBuilder, Module, ModuleConfig, ValType};
+use walrus_tests_utils::function_bodies;
+
+fn emit_select(result: ValType, ty: Option<ValType>) -> Vec<u8> {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    let mut module = Module::with_config(config);
+
+    let func_ty = module.types.add(&[], &[result]);
+    let mut builder = FunctionBuilder::new();
+    let (consequent, alternative) = match result {
+        ValType::Funcref => (
+            builder.ref_null(RefType::Funcref),
+            builder.ref_null(RefType::Funcref),
+        ),
+        _ => (builder.const_(Value::I32(1)), builder.const_(Value::I32(2))),
+    };
+    let condition = builder.const_(Value::I32(0));
+    let select = builder.select(condition, consequent, alternative, ty);
+    builder.finish(func_ty, vec![], vec![select], &mut module);
+    module.emit_wasm().unwrap()
+}
+
+/// Parse `wasm` back, check that it emits the same function body again, and
+/// return the type of the `select` its function is made of.
+fn parsed_type(wasm: &[u8]) -> Option<ValType> {
+    let module = Module::from_buffer(wasm).unwrap();
+    assert_eq!(
+        function_bodies(&module.emit_wasm().unwrap()),
+        function_bodies(wasm)
+    );
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    match func.get(func.block(func.entry_block()).exprs[0]) {
+        Expr::Select(e) => e.ty,
+        e => panic!("expected a select, found {:?}", e),
+    }
+}
+
+#[test]
+fn typed_select_of_references() {
+    let wasm = emit_select(ValType::Funcref, Some(ValType::Funcref));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0xd0, 0x70, 0xd0, 0x70, 0x41, 0x00, 0x1c, 0x01, 0x70, 0x0b][..]]
+    );
+    assert_eq!(parsed_type(&wasm), Some(ValType::Funcref));
+}
+
+#[test]
+fn typed_select_of_numbers() {
+    let wasm = emit_select(ValType::I32, Some(ValType::I32));
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x41, 0x01, 0x41, 0x02, 0x41, 0x00, 0x1c, 0x01, 0x7f, 0x0b][..]]
+    );
+    assert_eq!(parsed_type(&wasm), Some(ValType::I32));
+}
+
+#[test]
+fn untyped_select() {
+    let wasm = emit_select(ValType::I32, None);
+    assert_eq!(
+        function_bodies(&wasm),
+        [&[0x00, 0x41, 0x01, 0x41, 0x02, 0x41, 0x00, 0x1b, 0x0b][..]]
+    );
+    assert_eq!(parsed_type(&wasm), None);
+}
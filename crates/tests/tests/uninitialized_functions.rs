@@ -0,0 +1,20 @@
+//! Tests for modules with functions which were never given a body.
+
+use walrus::{FunctionBuilder, FunctionKind, Module};
+
+#[test]
+fn emitting_uninitialized_functions_fails() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let func = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+    module.exports.add("f", func);
+    let placeholder = module.funcs.get_mut(func);
+    placeholder.kind = FunctionKind::Uninitialized(ty);
+    placeholder.name = Some("placeholder".to_string());
+
+    let func = module.funcs.get(func);
+    assert_eq!(func.to_string(), "<uninitialized>\n");
+    let err = module.emit_wasm().unwrap_err().to_string();
+    assert!(err.contains(&func.id().index().to_string()), "{}", err);
+    assert!(err.contains("placeholder"), "{}", err);
+}
@@ -0,0 +1,51 @@
+//! Tests for finding everything which refers to a function.
+
+use walrus::analysis::uses_of_function;
+use walrus::{FunctionBuilder, FunctionKind, Module, SegmentSource};
+
+#[test]
+fn every_kind_of_use_is_found() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let target = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+
+    let mut builder = FunctionBuilder::new();
+    let first = builder.call(target, Box::new([]));
+    let second = builder.call(target, Box::new([]));
+    let reference = builder.ref_func(target);
+    let drop = builder.drop(reference);
+    let caller = builder.finish(ty, vec![], vec![first, second, drop], &mut module);
+
+    // `target` calls itself too.
+    let func = match &mut module.funcs.get_mut(target).kind {
+        FunctionKind::Local(f) => f,
+        _ => unreachable!(),
+    };
+    let recursive = func.builder_mut().call(target, Box::new([]));
+    let entry = func.entry_block();
+    func.block_mut(entry).exprs.push(recursive);
+
+    let unused = uses_of_function(&module, target);
+    assert_eq!(
+        unused.calls,
+        [(target, vec![recursive]), (caller, vec![first, second])]
+    );
+    assert_eq!(unused.ref_funcs, [(caller, vec![reference])]);
+    assert!(unused.exports.is_empty());
+    assert!(unused.elements.is_empty());
+    assert!(!unused.start);
+
+    let segment = module.elements.add_passive(&[caller, target]);
+    let export = module.exports.add("target", target);
+    module.start = Some(target);
+    let uses = uses_of_function(&module, target);
+    assert_eq!(uses.exports, [export]);
+    assert_eq!(uses.elements, [(SegmentSource::Element(segment), 1)]);
+    assert!(uses.start);
+
+    let uses = uses_of_function(&module, caller);
+    assert!(uses.calls.is_empty());
+    assert!(uses.ref_funcs.is_empty());
+    assert_eq!(uses.elements, [(SegmentSource::Element(segment), 0)]);
+    assert!(!uses.is_empty());
+}
@@ -0,0 +1,87 @@
+//! An example pass written purely against the public visitor API.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, FunctionId, FunctionKind, LocalFunction, Module, ValType};
+
+/// Collects the value of every `i32.const`, in evaluation order.
+struct I32Consts<'a> {
+    func: &'a LocalFunction,
+    values: Vec<i32>,
+}
+
+impl<'a> Visitor<'a> for I32Consts<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_const(&mut self, e: &Const) {
+        if let Value::I32(n) = e.value {
+            self.values.push(n);
+        }
+        e.visit(self);
+    }
+}
+
+/// Doubles every `i32.const`.
+struct DoubleI32Consts<'a> {
+    func: &'a mut LocalFunction,
+}
+
+impl VisitorMut for DoubleI32Consts<'_> {
+    fn local_function_mut(&mut self) -> &mut LocalFunction {
+        self.func
+    }
+
+    fn visit_const_mut(&mut self, e: &mut Const) {
+        if let Value::I32(n) = &mut e.value {
+            *n *= 2;
+        }
+        e.visit_mut(self);
+    }
+}
+
+/// `(func (result i32) (i32.add (i32.const 1) (i32.const 2)))`
+fn module() -> (Module, FunctionId) {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[ValType::I32]);
+    let mut builder = FunctionBuilder::new();
+    let lhs = builder.i32_const(1);
+    let rhs = builder.i32_const(2);
+    let add = builder.binop(BinaryOp::I32Add, lhs, rhs);
+    let func = builder.finish(ty, vec![], vec![add], &mut module);
+    (module, func)
+}
+
+fn local(module: &Module, id: FunctionId) -> &LocalFunction {
+    match &module.funcs.get(id).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    }
+}
+
+fn i32_consts(func: &LocalFunction) -> Vec<i32> {
+    let mut visitor = I32Consts {
+        func,
+        values: Vec::new(),
+    };
+    dfs_in_order(&mut visitor, func, func.entry_block().into());
+    visitor.values
+}
+
+#[test]
+fn visit_in_order() {
+    let (module, id) = module();
+    assert_eq!(i32_consts(local(&module, id)), [1, 2]);
+}
+
+#[test]
+fn visit_mut_rewrites() {
+    let (mut module, id) = module();
+    let func = match &mut module.funcs.get_mut(id).kind {
+        FunctionKind::Local(l) => l,
+        _ => panic!("not a local function"),
+    };
+    let entry = func.entry_block().into();
+    dfs_pre_order_mut(&mut DoubleI32Consts { func }, entry);
+    assert_eq!(i32_consts(local(&module, id)), [2, 4]);
+}
@@ -0,0 +1,162 @@
+//! Tests for detecting, rejecting and lowering WebAssembly features.
+
+use std::fs;
+use walrus::ir::*;
+use walrus::passes::lower_sign_ext;
+use walrus::{DisabledFeature, ExportItem, FunctionBuilder, Module, ModuleConfig};
+use walrus::{ValType, WasmFeatures};
+use walrus_tests_utils::wasm_interp;
+
+/// A module with one exported function per expression built by `body`.
+fn module(
+    n: usize,
+    mut body: impl FnMut(&mut FunctionBuilder, usize) -> (ExprId, ValType),
+) -> Module {
+    let mut module = Module::default();
+    for i in 0..n {
+        let mut builder = FunctionBuilder::new();
+        let (expr, ty) = body(&mut builder, i);
+        let ty = module.types.add(&[], &[ty]);
+        let func = builder.finish(ty, vec![], vec![expr], &mut module);
+        module
+            .exports
+            .add(&format!("f{}", i), ExportItem::Function(func));
+    }
+    module
+}
+
+#[test]
+fn detects_used_features() {
+    let ops = [UnaryOp::I32Extend8S, UnaryOp::I32TruncSSatF32];
+    let used = |ops: &[UnaryOp]| {
+        module(ops.len(), |builder, i| {
+            let (arg, ty) = match ops[i] {
+                UnaryOp::I32Extend8S => (builder.i32_const(0x80), ValType::I32),
+                _ => (builder.f32_const(1.5), ValType::I32),
+            };
+            (builder.unop(ops[i], arg), ty)
+        })
+        .used_features()
+    };
+
+    assert_eq!(used(&[]), WasmFeatures::default());
+    assert_eq!(
+        used(&ops[..1]),
+        WasmFeatures {
+            sign_extension: true,
+            ..WasmFeatures::default()
+        }
+    );
+    assert_eq!(
+        used(&ops[1..]),
+        WasmFeatures {
+            saturating_float_to_int: true,
+            ..WasmFeatures::default()
+        }
+    );
+    assert_eq!(
+        used(&ops),
+        WasmFeatures {
+            multi_value: false,
+            relaxed_simd: false,
+            ..WasmFeatures::all()
+        }
+    );
+}
+
+#[test]
+fn rejects_disabled_features() {
+    let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    wasm.extend(&[0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f]); // type section
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]); // function section
+    wasm.extend(&[0x0a, 0x08, 0x01, 0x06, 0x00]); // code section
+    wasm.extend(&[0x41, 0x80, 0x01, 0xc0, 0x0b]); // (i32.extend8_s (i32.const 128))
+
+    ModuleConfig::new().parse(&wasm).unwrap();
+
+    let mut config = ModuleConfig::new();
+    config.wasm_features(WasmFeatures {
+        sign_extension: false,
+        ..WasmFeatures::all()
+    });
+    let err = config.parse(&wasm).unwrap_err();
+    match err.find_root_cause().downcast_ref::<DisabledFeature>() {
+        Some(e) => {
+            assert_eq!(e.function, 0);
+            assert_eq!(e.instruction, "i32.extend8_s");
+        }
+        None => panic!("not a disabled feature error: {}", err),
+    }
+
+    let mut config = ModuleConfig::new();
+    config.wasm_features(WasmFeatures {
+        saturating_float_to_int: false,
+        ..WasmFeatures::all()
+    });
+    config.parse(&wasm).unwrap();
+}
+
+#[test]
+fn lowered_sign_extension_is_equivalent() {
+    // Each sign-extension operator, an input, and the expected result.
+    let mut cases = Vec::new();
+    for n in &[0, 0x7f, 0x80, 0xff, 0x100, -1, i32::MIN, i32::MAX] {
+        cases.push((
+            UnaryOp::I32Extend8S,
+            Value::I32(*n),
+            Value::I32(*n as i8 as i32),
+        ));
+    }
+    for n in &[0x7fff, 0x8000, 0xffff, 0x1_8000] {
+        cases.push((
+            UnaryOp::I32Extend16S,
+            Value::I32(*n),
+            Value::I32(*n as i16 as i32),
+        ));
+    }
+    for n in &[0x7f, 0x80, -0x81, i64::MAX] {
+        cases.push((
+            UnaryOp::I64Extend8S,
+            Value::I64(*n),
+            Value::I64(*n as i8 as i64),
+        ));
+    }
+    for n in &[0x7fff, 0x8000, i64::MIN] {
+        cases.push((
+            UnaryOp::I64Extend16S,
+            Value::I64(*n),
+            Value::I64(*n as i16 as i64),
+        ));
+    }
+    for n in &[0x7fff_ffff, 0x8000_0000, 0xffff_ffff, -1] {
+        cases.push((
+            UnaryOp::I64Extend32S,
+            Value::I64(*n),
+            Value::I64(*n as i32 as i64),
+        ));
+    }
+
+    let mut lowered = module(cases.len(), |builder, i| {
+        let (op, input, _) = cases[i];
+        let input = builder.const_(input);
+        (builder.unop(op, input), cases[i].1.ty())
+    });
+    assert!(lowered.used_features().sign_extension);
+    assert_eq!(lower_sign_ext(&mut lowered), cases.len());
+    assert_eq!(lowered.used_features(), WasmFeatures::default());
+
+    let expected = module(cases.len(), |builder, i| {
+        let (_, _, expected) = cases[i];
+        (builder.const_(expected), expected.ty())
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut outputs = Vec::new();
+    for (name, m) in &[("lowered.wasm", lowered), ("expected.wasm", expected)] {
+        let path = dir.path().join(name);
+        fs::write(&path, m.emit_wasm().unwrap()).unwrap();
+        outputs.push(wasm_interp(&path));
+    }
+    assert_eq!(outputs[0].lines().count(), cases.len());
+    assert_eq!(outputs[0], outputs[1]);
+}
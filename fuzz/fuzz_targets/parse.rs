@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Parsing arbitrary bytes may fail, but must never panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = walrus::Module::from_buffer(data);
+});
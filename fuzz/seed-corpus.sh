@@ -0,0 +1,16 @@
+#!/bin/sh
+
+# Seed the `parse` fuzz target's corpus with the test suite's fixtures.
+#
+# Requires `wat2wasm` from WABT on `$PATH`.
+
+set -e
+
+cd "$(dirname "$0")"
+mkdir -p corpus/parse
+
+for wat in ../crates/tests/tests/valid/*.wat ../crates/tests/tests/round_trip/*.wat; do
+    name=$(basename "$(dirname "$wat")")-$(basename "$wat" .wat)
+    wat2wasm --enable-all "$wat" -o "corpus/parse/$name.wasm" 2>/dev/null ||
+        echo "skipping $wat"
+done
@@ -0,0 +1,303 @@
+//! The graph of which functions call which.
+
+use crate::ir::*;
+use crate::map::IdHashMap;
+use crate::{ElementKind, Function, FunctionId, GlobalKind, LocalFunction};
+use crate::{Module, TableId, TypeId};
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+
+/// Options for building a `CallGraph`.
+#[derive(Debug, Clone)]
+pub struct CallGraphOptions {
+    /// Whether `call_indirect`s and `call_ref`s are included in the graph.
+    ///
+    /// Which function a `call_indirect` calls isn't known until it runs, so
+    /// it's approximated by an edge to every function of the right type that
+    /// could be in the table it calls through: those in active segments for
+    /// the table, and those in passive segments which could be copied into
+    /// it. Functions put into imported or exported tables from outside the
+    /// module aren't known, though. Likewise a `call_ref` gets an edge to
+    /// every function of the right type that's referenced, by `ref.func` or
+    /// an element segment. Defaults to `true`.
+    pub indirect_calls: bool,
+}
+
+impl Default for CallGraphOptions {
+    fn default() -> CallGraphOptions {
+        CallGraphOptions {
+            indirect_calls: true,
+        }
+    }
+}
+
+/// The graph of which functions in a module call which other functions.
+///
+/// This is a snapshot of the module when it was built, and isn't updated as
+/// the module changes. Everything it returns is in a deterministic order.
+///
+/// ```
+/// use walrus::analysis::CallGraph;
+/// use walrus::{FunctionBuilder, Module};
+///
+/// let mut module = Module::default();
+/// let ty = module.types.add(&[], &[]);
+/// let leaf = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+/// let mut builder = FunctionBuilder::new();
+/// let call = builder.call(leaf, Box::new([]));
+/// let root = builder.finish(ty, vec![], vec![call], &mut module);
+///
+/// let graph = CallGraph::new(&module);
+/// assert_eq!(graph.callees(root), [leaf]);
+/// assert_eq!(graph.callers(leaf), [root]);
+/// assert!(!graph.is_recursive(root));
+/// let order = graph.reverse_topological_order().collect::<Vec<_>>();
+/// assert_eq!(order, [leaf, root]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    callees: IdHashMap<Function, Vec<FunctionId>>,
+    callers: IdHashMap<Function, Vec<FunctionId>>,
+    sccs: Vec<Vec<FunctionId>>,
+    scc_of: IdHashMap<Function, usize>,
+}
+
+impl CallGraph {
+    /// Build the call graph of a module with the default options.
+    pub fn new(module: &Module) -> CallGraph {
+        CallGraph::with_options(module, &CallGraphOptions::default())
+    }
+
+    /// Build the call graph of a module.
+    pub fn with_options(module: &Module, options: &CallGraphOptions) -> CallGraph {
+        // The functions which could be called through each table.
+        let mut tables = HashMap::new();
+        let mut passive = BTreeSet::new();
+        // The functions which could be called through a typed reference.
+        let mut referenced = BTreeSet::new();
+        for segment in module.element_segments() {
+            let members = segment.members().iter().filter_map(|m| *m);
+            referenced.extend(members.clone());
+            match segment.kind() {
+                ElementKind::Active { table, .. } => tables
+                    .entry(table)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(members),
+                ElementKind::Passive => passive.extend(members),
+                ElementKind::Declared => {}
+            }
+        }
+        for global in module.globals.iter() {
+            if let GlobalKind::Local(init) = &global.kind {
+                referenced.extend(init.funcs());
+            }
+        }
+
+        let ids = module.funcs.iter().map(|f| f.id()).collect::<Vec<_>>();
+        let mut callees = IdHashMap::default();
+        let mut callers = IdHashMap::default();
+        for id in ids.iter() {
+            callees.insert(*id, Vec::new());
+            callers.insert(*id, Vec::new());
+        }
+
+        let mut all_calls = Vec::new();
+        for (id, func) in module.funcs.iter_local() {
+            let mut calls = Calls {
+                func,
+                direct: BTreeSet::new(),
+                indirect: BTreeSet::new(),
+                by_ref: BTreeSet::new(),
+                ref_funcs: BTreeSet::new(),
+            };
+            dfs_in_order(&mut calls, func, func.entry_block().into());
+            referenced.extend(calls.ref_funcs.iter().cloned());
+            all_calls.push((id, calls));
+        }
+
+        for (id, calls) in all_calls {
+            let mut targets = calls.direct;
+            if options.indirect_calls {
+                for (ty, table) in calls.indirect {
+                    let in_table = tables.get(&table).into_iter().flat_map(|t| t.iter());
+                    for f in in_table.chain(passive.iter()) {
+                        if module.funcs.get(*f).ty() == ty {
+                            targets.insert(*f);
+                        }
+                    }
+                }
+                for ty in calls.by_ref {
+                    for f in referenced.iter() {
+                        if module.funcs.get(*f).ty() == ty {
+                            targets.insert(*f);
+                        }
+                    }
+                }
+            }
+            targets.retain(|f| callees.contains_key(f));
+            callees.insert(id, targets.into_iter().collect());
+        }
+
+        for caller in ids.iter() {
+            for callee in callees[caller].iter() {
+                callers.get_mut(callee).unwrap().push(*caller);
+            }
+        }
+
+        let sccs = tarjan(&ids, &callees);
+        let mut scc_of = IdHashMap::default();
+        for (i, scc) in sccs.iter().enumerate() {
+            for id in scc.iter() {
+                scc_of.insert(*id, i);
+            }
+        }
+
+        CallGraph {
+            callees,
+            callers,
+            sccs,
+            scc_of,
+        }
+    }
+
+    /// Get the functions the function `id` calls, in order of their ids.
+    pub fn callees(&self, id: FunctionId) -> &[FunctionId] {
+        self.callees.get(&id).map_or(&[], |c| &c[..])
+    }
+
+    /// Get the functions which call the function `id`, in order of their ids.
+    pub fn callers(&self, id: FunctionId) -> &[FunctionId] {
+        self.callers.get(&id).map_or(&[], |c| &c[..])
+    }
+
+    /// Get the strongly connected components of the graph: the sets of
+    /// functions which can all call each other, directly or indirectly.
+    ///
+    /// Components come in reverse topological order, so every component
+    /// comes after the components it calls into. The functions within each
+    /// component are in order of their ids.
+    pub fn sccs(&self) -> &[Vec<FunctionId>] {
+        &self.sccs
+    }
+
+    /// Get the strongly connected component containing the function `id`.
+    pub fn scc(&self, id: FunctionId) -> &[FunctionId] {
+        self.scc_of.get(&id).map_or(&[], |i| &self.sccs[*i][..])
+    }
+
+    /// Can the function `id` end up calling itself, directly or through
+    /// other functions?
+    pub fn is_recursive(&self, id: FunctionId) -> bool {
+        self.scc(id).len() > 1 || self.callees(id).contains(&id)
+    }
+
+    /// Iterate over every function in reverse topological order, so that
+    /// each function comes after the functions it calls, except for calls
+    /// within the same strongly connected component.
+    pub fn reverse_topological_order(&self) -> impl Iterator<Item = FunctionId> + '_ {
+        self.sccs.iter().flat_map(|scc| scc.iter().cloned())
+    }
+}
+
+struct Calls<'a> {
+    func: &'a LocalFunction,
+    direct: BTreeSet<FunctionId>,
+    indirect: BTreeSet<(TypeId, TableId)>,
+    by_ref: BTreeSet<TypeId>,
+    ref_funcs: BTreeSet<FunctionId>,
+}
+
+impl<'a> Visitor<'a> for Calls<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        match self.func.get(id) {
+            Expr::Call(e) => {
+                self.direct.insert(e.func);
+            }
+            Expr::CallIndirect(e) => {
+                self.indirect.insert((e.ty, e.table));
+            }
+            Expr::CallRef(e) => {
+                self.by_ref.insert(e.ty);
+            }
+            Expr::RefFunc(e) => {
+                self.ref_funcs.insert(e.func);
+            }
+            _ => {}
+        }
+        id.visit(self);
+    }
+}
+
+/// Find the strongly connected components of the graph, in reverse
+/// topological order, with Tarjan's algorithm.
+///
+/// This uses an explicit stack rather than recursion, since call chains can
+/// be very deep.
+fn tarjan(
+    ids: &[FunctionId],
+    callees: &IdHashMap<Function, Vec<FunctionId>>,
+) -> Vec<Vec<FunctionId>> {
+    let position = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect::<IdHashMap<_, _>>();
+    let mut index = vec![None; ids.len()];
+    let mut lowlink = vec![0; ids.len()];
+    let mut on_stack = vec![false; ids.len()];
+    let mut stack = Vec::new();
+    let mut next = 0;
+    let mut sccs = Vec::new();
+
+    for root in 0..ids.len() {
+        if index[root].is_some() {
+            continue;
+        }
+        // Each entry is a function and the position of the next callee of
+        // it to visit.
+        let mut work = vec![(root, 0)];
+        while let Some((v, child)) = work.pop() {
+            if child == 0 {
+                index[v] = Some(next);
+                lowlink[v] = next;
+                next += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+
+            let succs = &callees[&ids[v]];
+            if child < succs.len() {
+                work.push((v, child + 1));
+                let w = position[&succs[child]];
+                match index[w] {
+                    None => work.push((w, 0)),
+                    Some(i) if on_stack[w] => lowlink[v] = cmp::min(lowlink[v], i),
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            if Some(lowlink[v]) == index[v] {
+                let mut scc = Vec::new();
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack[w] = false;
+                    scc.push(ids[w]);
+                    if w == v {
+                        break;
+                    }
+                }
+                scc.sort();
+                sccs.push(scc);
+            }
+            if let Some((parent, _)) = work.last() {
+                lowlink[*parent] = cmp::min(lowlink[*parent], lowlink[v]);
+            }
+        }
+    }
+    sccs
+}
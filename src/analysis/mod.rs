@@ -0,0 +1,7 @@
+//! Analyses of a whole module, which don't change it.
+
+mod call_graph;
+mod uses;
+
+pub use self::call_graph::{CallGraph, CallGraphOptions};
+pub use self::uses::{uses_of_function, FunctionUses};
@@ -0,0 +1,102 @@
+//! Finding everything which refers to a function.
+
+use crate::ir::*;
+use crate::{ExportId, ExportItem, FunctionId, LocalFunction, Module, SegmentSource};
+
+/// Everything in a module which refers to a function, as found by
+/// `uses_of_function`.
+///
+/// Everything is in a deterministic order: functions in order of their ids,
+/// expressions in the order they appear in their function, and exports and
+/// element segment slots in the order they're emitted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionUses {
+    /// The `call`s of the function, grouped by the local function containing
+    /// them. Calls in the function's own body are included.
+    pub calls: Vec<(FunctionId, Vec<ExprId>)>,
+    /// The `ref.func`s of the function, grouped by the local function
+    /// containing them.
+    pub ref_funcs: Vec<(FunctionId, Vec<ExprId>)>,
+    /// The exports of the function.
+    pub exports: Vec<ExportId>,
+    /// The element segment slots holding the function, as the segment and
+    /// the index of the slot within it.
+    pub elements: Vec<(SegmentSource, usize)>,
+    /// Whether the function is the module's start function.
+    pub start: bool,
+}
+
+impl FunctionUses {
+    /// Returns whether nothing refers to the function.
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+            && self.ref_funcs.is_empty()
+            && self.exports.is_empty()
+            && self.elements.is_empty()
+            && !self.start
+    }
+}
+
+/// Find everything in `module` which refers to the function `id`.
+///
+/// Function bodies are searched in parallel, in one pass over all of them.
+/// Unlike `Module::function_uses`, calls in the function's own body are
+/// included, since they're affected by changes to its signature too.
+pub fn uses_of_function(module: &Module, id: FunctionId) -> FunctionUses {
+    let found = module.funcs.par_map_local(|_, func| {
+        let mut refs = Refs {
+            func,
+            target: id,
+            calls: Vec::new(),
+            ref_funcs: Vec::new(),
+        };
+        dfs_in_order(&mut refs, func, func.entry_block().into());
+        (refs.calls, refs.ref_funcs)
+    });
+
+    let mut uses = FunctionUses::default();
+    for (func, (calls, ref_funcs)) in found {
+        if !calls.is_empty() {
+            uses.calls.push((func, calls));
+        }
+        if !ref_funcs.is_empty() {
+            uses.ref_funcs.push((func, ref_funcs));
+        }
+    }
+    for export in module.exports.iter() {
+        if export.item == ExportItem::Function(id) {
+            uses.exports.push(export.id());
+        }
+    }
+    for segment in module.element_segments() {
+        for (index, member) in segment.members().iter().enumerate() {
+            if *member == Some(id) {
+                uses.elements.push((segment.source(), index));
+            }
+        }
+    }
+    uses.start = module.start == Some(id);
+    uses
+}
+
+struct Refs<'a> {
+    func: &'a LocalFunction,
+    target: FunctionId,
+    calls: Vec<ExprId>,
+    ref_funcs: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Refs<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        match self.func.get(id) {
+            Expr::Call(e) if e.func == self.target => self.calls.push(id),
+            Expr::RefFunc(e) if e.func == self.target => self.ref_funcs.push(id),
+            _ => {}
+        }
+        id.visit(self);
+    }
+}
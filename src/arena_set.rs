@@ -1,5 +1,6 @@
 use crate::tombstone_arena::{Tombstone, TombstoneArena};
 use id_arena::Id;
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::ops;
@@ -36,6 +37,18 @@ impl<T: Clone + Eq + Hash> ArenaSet<T> {
         self.arena.next_id()
     }
 
+    /// The number of items in this set.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Reserve space for `additional` more items, see
+    /// `TombstoneArena::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+        self.already_in_arena.reserve(additional);
+    }
+
     /// Remove an item from this set
     pub fn remove(&mut self, id: Id<T>)
     where
@@ -49,6 +62,14 @@ impl<T: Clone + Eq + Hash> ArenaSet<T> {
     pub fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> {
         self.arena.iter()
     }
+
+    /// Iterate over the items in this arena and their ids, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (Id<T>, &T)>
+    where
+        T: Sync,
+    {
+        self.arena.par_iter()
+    }
 }
 
 impl<T: Clone + Eq + Hash> ops::Index<Id<T>> for ArenaSet<T> {
@@ -0,0 +1,602 @@
+//! Decoding the parts of the binary format our version of `wasmparser`
+//! predates.
+//!
+//! Encodings which were added or changed after it was released are decoded
+//! here, and everything else is still left to `wasmparser`.
+
+use crate::encode::{leb128_u32, read_leb128_i64, read_leb128_u32};
+use crate::ir::{BinaryOp, BlockKind, RefType, TernaryOp, UnaryOp};
+use crate::parse::IndicesToIds;
+use crate::{DataId, ElementId, FunctionId, MemoryId, Result, TableId, TagId, TypeId, ValType};
+use failure::bail;
+use wasmparser::{BinaryReader, Operator};
+
+/// A cursor over encoded bytes.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    /// How many bytes have been read so far.
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The bytes read since `start`.
+    pub(crate) fn since(&self, start: usize) -> &'a [u8] {
+        &self.bytes[start..self.pos]
+    }
+
+    /// The bytes which haven't been read yet.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    pub(crate) fn eof(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub(crate) fn peek(&self) -> Result<u8> {
+        match self.bytes.get(self.pos) {
+            Some(byte) => Ok(*byte),
+            None => bail!("unexpected end of input"),
+        }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let (value, len) = read_leb128_u32(&self.bytes[self.pos..])?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    pub(crate) fn s64(&mut self) -> Result<i64> {
+        let (value, len) = read_leb128_i64(&self.bytes[self.pos..])?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    /// Read a name, as used by imports and exports.
+    pub(crate) fn name(&mut self) -> Result<&'a str> {
+        let len = self.u32()? as usize;
+        let start = self.pos;
+        self.skip(len)?;
+        match std::str::from_utf8(self.since(start)) {
+            Ok(name) => Ok(name),
+            Err(_) => bail!("invalid UTF-8 in a name"),
+        }
+    }
+
+    /// Skip an unsigned LEB128 integer of up to 64 bits.
+    pub(crate) fn leb(&mut self) -> Result<()> {
+        for _ in 0..10 {
+            if self.byte()? & 0x80 == 0 {
+                return Ok(());
+            }
+        }
+        bail!("LEB128 encoding is longer than 10 bytes")
+    }
+
+    pub(crate) fn skip(&mut self, len: usize) -> Result<()> {
+        if self.bytes.len() - self.pos < len {
+            bail!("unexpected end of input");
+        }
+        self.pos += len;
+        Ok(())
+    }
+}
+
+/// An instruction, either as `wasmparser` reads it or decoded here.
+pub(crate) enum Instruction<'a> {
+    Operator(Operator<'a>),
+    Extended(Extended),
+    /// An instruction with a memory argument naming its memory, as allowed
+    /// by the multi-memory proposal, read by `wasmparser` with the memory
+    /// index left out.
+    Memory(MemoryId, Operator<'a>),
+}
+
+/// An instruction `wasmparser` doesn't know about, or knows only by an older
+/// encoding.
+#[derive(Debug)]
+pub(crate) enum Extended {
+    /// `ref.null`, with the heap type it has had since the reference types
+    /// proposal was finalized.
+    RefNull(RefType),
+    /// `ref.func`.
+    RefFunc(FunctionId),
+    /// `select` with its result type given explicitly, opcode 0x1c.
+    Select(ValType),
+    /// `table.init`, which `wasmparser` can only read for table 0.
+    TableInit { elem: ElementId, table: TableId },
+    /// `table.copy`, which `wasmparser` can only read for table 0.
+    TableCopy { dst: TableId, src: TableId },
+    /// `table.fill`.
+    TableFill(TableId),
+    /// `memory.init`, which `wasmparser` can only read for memory 0.
+    MemoryInit { data: DataId, memory: MemoryId },
+    /// `memory.copy`, which `wasmparser` can only read for memory 0.
+    MemoryCopy { dst: MemoryId, src: MemoryId },
+    /// `memory.fill`, which `wasmparser` can only read for memory 0.
+    MemoryFill(MemoryId),
+    /// `v8x16.swizzle`.
+    Swizzle,
+    /// A SIMD operation on a `v128` which `wasmparser` doesn't know about,
+    /// or whose opcode it reads as a single byte although it's longer.
+    SimdUnop(UnaryOp),
+    /// A SIMD operation on two `v128`s, like `SimdUnop`.
+    SimdBinop(BinaryOp),
+    /// A SIMD operation on three `v128`s, like `SimdUnop`.
+    SimdTernop(TernaryOp),
+    /// `block`, `loop` or `if` with a type `wasmparser` can't read: a type
+    /// index, as used by blocks with parameters or several results, or a
+    /// typed reference.
+    Block { kind: BlockKind, ty: BlockType },
+    /// `call_ref` of a function of the given type.
+    CallRef(TypeId),
+    /// `ref.as_non_null`.
+    RefAsNonNull,
+    /// `try`, from the exception handling proposal.
+    Try(BlockType),
+    /// `catch`, starting the handler for exceptions with the given tag.
+    Catch(TagId),
+    /// `catch_all`, starting the handler for any exception.
+    CatchAll,
+    /// `delegate`, ending a `try` by passing its exceptions on to the block
+    /// at the given depth.
+    Delegate(u32),
+    /// `throw`.
+    Throw(TagId),
+    /// `rethrow`, of the exception caught by the handler at the given depth.
+    Rethrow(u32),
+}
+
+/// The type of a block, as it's encoded.
+#[derive(Debug)]
+pub(crate) enum BlockType {
+    /// No parameters or results.
+    Empty,
+    /// No parameters, and a single result.
+    Value(ValType),
+    /// The type at the given index.
+    Index(u32),
+}
+
+/// Reads instructions, decoding those `wasmparser` can't read itself and
+/// leaving the rest to it.
+pub(crate) struct Instructions<'a> {
+    bytes: &'a [u8],
+    reader: BinaryReader<'a>,
+    /// The current instruction, rewritten for `wasmparser` to read.
+    scratch: Vec<u8>,
+}
+
+impl<'a> Instructions<'a> {
+    /// Read the instructions in `bytes`, which are at `offset` in the
+    /// original binary.
+    pub(crate) fn new(bytes: &'a [u8], offset: usize) -> Instructions<'a> {
+        Instructions {
+            bytes,
+            reader: BinaryReader::new_with_offset(bytes, offset),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Read the instructions of a constant expression.
+    pub(crate) fn from_init_expr(init: &wasmparser::InitExpr<'a>) -> Result<Instructions<'a>> {
+        let mut reader = init.get_binary_reader();
+        let offset = reader.original_position();
+        let bytes = reader.read_bytes(reader.bytes_remaining())?;
+        Ok(Instructions::new(bytes, offset))
+    }
+
+    pub(crate) fn eof(&self) -> bool {
+        self.reader.eof()
+    }
+
+    pub(crate) fn original_position(&self) -> usize {
+        self.reader.original_position()
+    }
+
+    pub(crate) fn read(&mut self, ids: &IndicesToIds) -> Result<Instruction<'_>> {
+        let pos = self.reader.current_position();
+        let mut r = Reader::new(&self.bytes[pos..]);
+        if let Some(inst) = extended(&mut r, ids)? {
+            self.reader.skip_bytes(r.position())?;
+            return Ok(Instruction::Extended(inst));
+        }
+        let mut r = Reader::new(&self.bytes[pos..]);
+        let memory = rewrite(&mut r, &mut self.scratch)?;
+        if self.scratch.is_empty() {
+            return Ok(Instruction::Operator(self.reader.read_operator()?));
+        }
+        let offset = self.reader.original_position();
+        self.reader.skip_bytes(r.position())?;
+        let op = BinaryReader::new_with_offset(&self.scratch, offset).read_operator()?;
+        Ok(match memory {
+            Some(memory) => Instruction::Memory(ids.get_memory(memory)?, op),
+            None => Instruction::Operator(op),
+        })
+    }
+}
+
+/// Decode the instruction at the start of `r`, if it's one `wasmparser`
+/// can't read.
+fn extended(r: &mut Reader, ids: &IndicesToIds) -> Result<Option<Extended>> {
+    if r.eof() {
+        return Ok(None);
+    }
+    let inst = match r.byte()? {
+        opcode @ 0x02..=0x04 => {
+            let kind = match opcode {
+                0x02 => BlockKind::Block,
+                0x03 => BlockKind::Loop,
+                _ => BlockKind::IfElse,
+            };
+            match block_type(r, ids)? {
+                ty @ BlockType::Index(_) | ty @ BlockType::Value(ValType::Ref { .. }) => {
+                    Extended::Block { kind, ty }
+                }
+                _ => return Ok(None),
+            }
+        }
+        0x06 => Extended::Try(block_type(r, ids)?),
+        0x07 => Extended::Catch(ids.get_tag(r.u32()?)?),
+        0x08 => Extended::Throw(ids.get_tag(r.u32()?)?),
+        0x09 => Extended::Rethrow(r.u32()?),
+        0x14 => Extended::CallRef(ids.get_type(r.u32()?)?),
+        0x18 => Extended::Delegate(r.u32()?),
+        0x19 => Extended::CatchAll,
+        0x1c => {
+            let count = r.u32()?;
+            if count != 1 {
+                bail!("invalid number of types for select: {}", count);
+            }
+            Extended::Select(val_type(r, ids)?)
+        }
+        0xd0 => Extended::RefNull(ref_type(r)?),
+        0xd2 => Extended::RefFunc(ids.get_func(r.u32()?)?),
+        0xd4 => Extended::RefAsNonNull,
+        0xfc => match bulk(r, ids)? {
+            Some(inst) => inst,
+            None => return Ok(None),
+        },
+        0xfd => match simd(r.u32()?) {
+            Some(inst) => inst,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(inst))
+}
+
+/// Rewrite the instruction at the start of `r` into `scratch` in a form
+/// `wasmparser` can read, if it can't read it as it is, and return the index
+/// of the memory it names, if any.
+///
+/// SIMD instructions get the opcodes `wasmparser` knows them by, see
+/// `prestandard_simd`. A memory argument with its memory index, which is
+/// there when bit 6 of the alignment flags is set, loses the index. Anything
+/// else is left as it is, with `scratch` empty.
+fn rewrite(r: &mut Reader, scratch: &mut Vec<u8>) -> Result<Option<u32>> {
+    scratch.clear();
+    if r.eof() {
+        return Ok(None);
+    }
+    let has_mem_arg = match r.byte()? {
+        0x28..=0x3e => true,
+        0xfd => {
+            let opcode = r.u32()?;
+            scratch.push(0xfd);
+            scratch.push(prestandard_simd(opcode)?);
+            match opcode {
+                0x00 | 0x0b => true, // v128.load, v128.store
+                // `v128.const` and `i8x16.shuffle`
+                0x0c | 0x0d => {
+                    let start = r.position();
+                    r.skip(16)?;
+                    scratch.extend_from_slice(r.since(start));
+                    false
+                }
+                // Lane accesses
+                0x15..=0x22 => {
+                    scratch.push(r.byte()?);
+                    false
+                }
+                _ => false,
+            }
+        }
+        0xfe => r.u32()? != 0x03, // atomic.fence
+        _ => false,
+    };
+    if !has_mem_arg {
+        return Ok(None);
+    }
+    let opcode = r.since(0);
+    let flags = r.u32()?;
+    if flags & 0x40 == 0 && scratch.is_empty() {
+        return Ok(None);
+    }
+    let memory = if flags & 0x40 == 0 {
+        None
+    } else {
+        Some(r.u32()?)
+    };
+    let offset = r.position();
+    r.leb()?;
+    if scratch.is_empty() {
+        scratch.extend_from_slice(opcode);
+    }
+    leb128_u32(scratch, flags & !0x40);
+    scratch.extend_from_slice(r.since(offset));
+    Ok(memory)
+}
+
+/// Decode the rest of a bulk memory or table instruction, after its 0xfc
+/// prefix, if it's one `wasmparser` can't read.
+fn bulk(r: &mut Reader, ids: &IndicesToIds) -> Result<Option<Extended>> {
+    let inst = match r.u32()? {
+        0x08 => {
+            let data = ids.get_data(r.u32()?)?;
+            let memory = ids.get_memory(r.u32()?)?;
+            Extended::MemoryInit { data, memory }
+        }
+        0x0a => {
+            let dst = ids.get_memory(r.u32()?)?;
+            let src = ids.get_memory(r.u32()?)?;
+            Extended::MemoryCopy { dst, src }
+        }
+        0x0b => Extended::MemoryFill(ids.get_memory(r.u32()?)?),
+        0x0c => {
+            let elem = ids.get_element(r.u32()?)?;
+            let table = ids.get_table(r.u32()?)?;
+            Extended::TableInit { elem, table }
+        }
+        0x0e => {
+            let dst = ids.get_table(r.u32()?)?;
+            let src = ids.get_table(r.u32()?)?;
+            Extended::TableCopy { dst, src }
+        }
+        0x11 => Extended::TableFill(ids.get_table(r.u32()?)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(inst))
+}
+
+/// Decode the SIMD instruction with the given opcode, if it's one
+/// `wasmparser` can't read.
+///
+/// SIMD opcodes are those of the finalized proposal, including the relaxed
+/// SIMD instructions, which are 0x100 and up.
+fn simd(opcode: u32) -> Option<Extended> {
+    use crate::ir::BinaryOp::*;
+    use crate::ir::TernaryOp::*;
+    use crate::ir::UnaryOp::*;
+
+    let inst = match opcode {
+        0x0e => Extended::Swizzle,
+        0x4f => Extended::SimdBinop(V128Andnot),
+        0x60 => Extended::SimdUnop(I8x16Abs),
+        0x62 => Extended::SimdUnop(I8x16Popcnt),
+        0x65 => Extended::SimdBinop(I8x16NarrowI16x8S),
+        0x66 => Extended::SimdBinop(I8x16NarrowI16x8U),
+        0x76 => Extended::SimdBinop(I8x16MinS),
+        0x77 => Extended::SimdBinop(I8x16MinU),
+        0x78 => Extended::SimdBinop(I8x16MaxS),
+        0x79 => Extended::SimdBinop(I8x16MaxU),
+        0x7b => Extended::SimdBinop(I8x16AvgrU),
+        0x80 => Extended::SimdUnop(I16x8Abs),
+        0x82 => Extended::SimdBinop(I16x8Q15MulrSatS),
+        0x85 => Extended::SimdBinop(I16x8NarrowI32x4S),
+        0x86 => Extended::SimdBinop(I16x8NarrowI32x4U),
+        0x87 => Extended::SimdUnop(I16x8WidenLowI8x16S),
+        0x88 => Extended::SimdUnop(I16x8WidenHighI8x16S),
+        0x89 => Extended::SimdUnop(I16x8WidenLowI8x16U),
+        0x8a => Extended::SimdUnop(I16x8WidenHighI8x16U),
+        0x96 => Extended::SimdBinop(I16x8MinS),
+        0x97 => Extended::SimdBinop(I16x8MinU),
+        0x98 => Extended::SimdBinop(I16x8MaxS),
+        0x99 => Extended::SimdBinop(I16x8MaxU),
+        0x9b => Extended::SimdBinop(I16x8AvgrU),
+        0xa0 => Extended::SimdUnop(I32x4Abs),
+        0xa7 => Extended::SimdUnop(I32x4WidenLowI16x8S),
+        0xa8 => Extended::SimdUnop(I32x4WidenHighI16x8S),
+        0xa9 => Extended::SimdUnop(I32x4WidenLowI16x8U),
+        0xaa => Extended::SimdUnop(I32x4WidenHighI16x8U),
+        0xb6 => Extended::SimdBinop(I32x4MinS),
+        0xb7 => Extended::SimdBinop(I32x4MinU),
+        0xb8 => Extended::SimdBinop(I32x4MaxS),
+        0xb9 => Extended::SimdBinop(I32x4MaxU),
+        0xba => Extended::SimdBinop(I32x4DotI16x8S),
+        0xc0 => Extended::SimdUnop(I64x2Abs),
+        0x100 => Extended::SimdBinop(I8x16RelaxedSwizzle),
+        0x101 => Extended::SimdUnop(I32x4RelaxedTruncSF32x4),
+        0x102 => Extended::SimdUnop(I32x4RelaxedTruncUF32x4),
+        0x103 => Extended::SimdUnop(I32x4RelaxedTruncSF64x2Zero),
+        0x104 => Extended::SimdUnop(I32x4RelaxedTruncUF64x2Zero),
+        0x105 => Extended::SimdTernop(F32x4RelaxedMadd),
+        0x106 => Extended::SimdTernop(F32x4RelaxedNmadd),
+        0x107 => Extended::SimdTernop(F64x2RelaxedMadd),
+        0x108 => Extended::SimdTernop(F64x2RelaxedNmadd),
+        0x10d => Extended::SimdBinop(F32x4RelaxedMin),
+        0x10e => Extended::SimdBinop(F32x4RelaxedMax),
+        0x10f => Extended::SimdBinop(F64x2RelaxedMin),
+        0x110 => Extended::SimdBinop(F64x2RelaxedMax),
+        _ => return None,
+    };
+    Some(inst)
+}
+
+/// The opcode our version of `wasmparser` knows the SIMD instruction with
+/// the given opcode by.
+///
+/// It predates the finalized SIMD proposal, and reads the opcodes of a
+/// pre-standard numbering as single bytes. Instructions it doesn't know at
+/// all are decoded by `simd` instead, and any others aren't supported.
+fn prestandard_simd(opcode: u32) -> Result<u8> {
+    let prestandard = match opcode {
+        0x00 => 0x00, // v128.load
+        0x0b => 0x01, // v128.store
+        0x0c => 0x02, // v128.const
+        0x0d => 0x03, // i8x16.shuffle
+
+        // Splats
+        0x0f => 0x04,
+        0x10 => 0x08,
+        0x11 => 0x0c,
+        0x12 => 0x0f,
+        0x13 => 0x12,
+        0x14 => 0x15,
+
+        // Lane accesses
+        0x15..=0x17 => opcode - 0x10,
+        0x18..=0x1a => opcode - 0x0f,
+        0x1b | 0x1c => opcode - 0x0e,
+        0x1d | 0x1e => opcode - 0x0d,
+        0x1f | 0x20 => opcode - 0x0c,
+        0x21 | 0x22 => opcode - 0x0b,
+
+        // Comparisons
+        0x23..=0x40 => opcode - 0x0b,
+        0x41..=0x4c => opcode - 0x01,
+
+        0x4d => 0x4c, // v128.not
+        0x4e => 0x4d, // v128.and
+        0x50 => 0x4e, // v128.or
+        0x51 => 0x4f, // v128.xor
+        0x52 => 0x50, // v128.bitselect
+        0x53 => 0x52, // v128.any_true
+
+        0x61 => 0x51, // i8x16.neg
+        0x63 => 0x53, // i8x16.all_true
+        0x6b..=0x73 => opcode - 0x17,
+
+        0x81 => 0x62, // i16x8.neg
+        0x83 => 0x64, // i16x8.all_true
+        0x8b..=0x93 => opcode - 0x26,
+        0x95 => 0x6e, // i16x8.mul
+
+        0xa1 => 0x73, // i32x4.neg
+        0xa3 => 0x75, // i32x4.all_true
+        0xab..=0xae => opcode - 0x35,
+        0xb1 => 0x7c, // i32x4.sub
+        0xb5 => 0x7f, // i32x4.mul
+
+        0xc1 => 0x84, // i64x2.neg
+        0xc3 => 0x86, // i64x2.all_true
+        0xcb..=0xce => opcode - 0x44,
+        0xd1 => 0x8d, // i64x2.sub
+
+        0xe0 => 0x95, // f32x4.abs
+        0xe1 => 0x96, // f32x4.neg
+        0xe3 => 0x97, // f32x4.sqrt
+        0xe4..=0xe9 => opcode - 0x4a,
+        0xec => 0xa0, // f64x2.abs
+        0xed => 0xa1, // f64x2.neg
+        0xef => 0xa2, // f64x2.sqrt
+        0xf0..=0xf5 => opcode - 0x4b,
+
+        0xf8 => 0xab, // i32x4.trunc_sat_f32x4_s
+        0xf9 => 0xac, // i32x4.trunc_sat_f32x4_u
+        0xfa => 0xaf, // f32x4.convert_i32x4_s
+        0xfb => 0xb0, // f32x4.convert_i32x4_u
+
+        _ => bail!("unknown opcode: 0xfd {}", opcode),
+    };
+    Ok(prestandard as u8)
+}
+
+/// Read a block type.
+fn block_type(r: &mut Reader, ids: &IndicesToIds) -> Result<BlockType> {
+    match r.peek()? {
+        0x40 => {
+            r.byte()?;
+            return Ok(BlockType::Empty);
+        }
+        // Value types are negative as a signed LEB, unlike type indices.
+        byte if byte & 0xc0 == 0x40 => return Ok(BlockType::Value(val_type(r, ids)?)),
+        _ => {}
+    }
+    let index = r.s64()?;
+    if index < 0 || index > i64::from(u32::MAX) {
+        bail!("invalid block type: {}", index);
+    }
+    Ok(BlockType::Index(index as u32))
+}
+
+/// Read a value type.
+pub(crate) fn val_type(r: &mut Reader, ids: &IndicesToIds) -> Result<ValType> {
+    let ty = match r.peek()? {
+        0x7f => ValType::I32,
+        0x7e => ValType::I64,
+        0x7d => ValType::F32,
+        0x7c => ValType::F64,
+        0x7b => ValType::V128,
+        byte @ 0x63 | byte @ 0x64 => {
+            r.byte()?;
+            // The heap type, which has to be a type index, as a signed LEB.
+            let index = r.s64()?;
+            if index < 0 || index > i64::from(u32::MAX) {
+                bail!("unsupported heap type: {}", index);
+            }
+            return Ok(ValType::Ref {
+                nullable: byte == 0x63,
+                ty: ids.get_type(index as u32)?,
+            });
+        }
+        _ => return Ok(ref_type(r)?.val_type()),
+    };
+    r.byte()?;
+    Ok(ty)
+}
+
+/// Read the heap type of a reference type walrus can represent.
+pub(crate) fn ref_type(r: &mut Reader) -> Result<RefType> {
+    match r.byte()? {
+        0x70 => Ok(RefType::Funcref),
+        0x6f => Ok(RefType::Externref),
+        byte => bail!("unsupported heap type: {:#x}", byte),
+    }
+}
+
+/// The limits of a table or memory.
+pub(crate) struct Limits {
+    pub(crate) shared: bool,
+    pub(crate) initial: u32,
+    pub(crate) maximum: Option<u32>,
+    /// The log2 of a custom page size, from the custom page sizes proposal.
+    pub(crate) page_size_log2: Option<u32>,
+}
+
+/// Read the limits of a table or memory.
+pub(crate) fn limits(r: &mut Reader) -> Result<Limits> {
+    let flags = r.byte()?;
+    if flags & !0x0b != 0 {
+        bail!("invalid limits flags: {:#x}", flags);
+    }
+    let initial = r.u32()?;
+    let maximum = match flags & 0x01 {
+        0 => None,
+        _ => Some(r.u32()?),
+    };
+    let page_size_log2 = match flags & 0x08 {
+        0 => None,
+        _ => Some(r.u32()?),
+    };
+    Ok(Limits {
+        shared: flags & 0x02 != 0,
+        initial,
+        maximum,
+        page_size_log2,
+    })
+}
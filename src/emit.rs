@@ -5,9 +5,11 @@
 use crate::encode::{Encoder, MAX_U32_LENGTH};
 use crate::ir::Local;
 use crate::map::{IdHashMap, IdHashSet};
+use crate::parse::IndicesToIds;
+use crate::tombstone_arena::Id;
 use crate::{Data, DataId, Element, ElementId, Function, FunctionId};
 use crate::{Global, GlobalId, Memory, MemoryId, Module, Table, TableId};
-use crate::{Type, TypeId};
+use crate::{Tag, TagId, Type, TypeId};
 use std::ops::{Deref, DerefMut};
 
 pub struct EmitContext<'a> {
@@ -28,7 +30,7 @@ pub trait Emit {
     fn emit(&self, cx: &mut EmitContext);
 }
 
-impl<'a, T: ?Sized + Emit> Emit for &'a T {
+impl<T: ?Sized + Emit> Emit for &T {
     fn emit(&self, cx: &mut EmitContext) {
         T::emit(self, cx)
     }
@@ -48,8 +50,13 @@ pub struct IdsToIndices {
     funcs: IdHashMap<Function, u32>,
     globals: IdHashMap<Global, u32>,
     memories: IdHashMap<Memory, u32>,
+    tags: IdHashMap<Tag, u32>,
     elements: IdHashMap<Element, u32>,
     data: IdHashMap<Data, u32>,
+    /// How many active element segments are emitted before the rest.
+    active_elements: u32,
+    /// How many active data segments are emitted before the rest.
+    active_data: u32,
     pub(crate) locals: IdHashMap<Function, IdHashMap<Local, u32>>,
 }
 
@@ -98,17 +105,84 @@ define_get_push_index! {
     get_func_index, push_func, FunctionId, funcs;
     get_global_index, push_global, GlobalId, globals;
     get_memory_index, push_memory, MemoryId, memories;
-    get_element_index, push_element, ElementId, elements;
+    get_tag_index, push_tag, TagId, tags;
 }
 define_get_index! {
+    get_element_index, ElementId, elements;
     get_data_index, DataId, data;
 }
 
 impl IdsToIndices {
+    /// Returns whether everything from the original wasm module was assigned
+    /// the same index that it had in the original module, meaning that
+    /// original function bodies can be re-emitted without re-encoding.
+    pub(crate) fn matches_original(&self, original: &IndicesToIds) -> bool {
+        fn same<T>(assigned: &IdHashMap<T, u32>, original: &[Id<T>]) -> bool {
+            original
+                .iter()
+                .enumerate()
+                .all(|(i, id)| assigned.get(id) == Some(&(i as u32)))
+        }
+
+        // Active segments don't get an index assigned, and can end up in a
+        // different order or merged together, but they're emitted before any
+        // others. `data.drop` and `elem.drop` can still refer to them, so each
+        // index which was an active segment has to stay one of the active
+        // segments, which can't be told apart by bodies since they've all
+        // been dropped already.
+        let data = original
+            .data
+            .iter()
+            .enumerate()
+            .all(|(i, id)| match self.data.get(id) {
+                Some(index) => *index == i as u32,
+                None => (i as u32) < self.active_data,
+            });
+        let elements = original.elements.iter().enumerate().all(|(i, id)| {
+            match id.and_then(|id| self.elements.get(&id)) {
+                Some(index) => *index == i as u32,
+                None => (i as u32) < self.active_elements,
+            }
+        });
+
+        same(&self.tables, &original.tables)
+            && same(&self.types, &original.types)
+            && same(&self.funcs, &original.funcs)
+            && same(&self.globals, &original.globals)
+            && same(&self.memories, &original.memories)
+            && same(&self.tags, &original.tags)
+            && data
+            && elements
+    }
+
+    /// Get the index of the given function, if it was assigned one.
+    ///
+    /// Imported functions whose import has been deleted aren't emitted, so
+    /// they never get an index.
+    pub(crate) fn try_get_func_index(&self, id: FunctionId) -> Option<u32> {
+        self.funcs.get(&id).cloned()
+    }
+
     /// Sets the data index to the specified value
     pub(crate) fn set_data_index(&mut self, id: DataId, idx: u32) {
         self.data.insert(id, idx);
     }
+
+    /// Sets the element index to the specified value
+    pub(crate) fn set_element_index(&mut self, id: ElementId, idx: u32) {
+        self.elements.insert(id, idx);
+    }
+
+    /// Sets how many active data segments are emitted before the passive
+    /// ones.
+    pub(crate) fn set_active_data(&mut self, count: u32) {
+        self.active_data = count;
+    }
+
+    /// Sets how many active element segments are emitted before the rest.
+    pub(crate) fn set_active_elements(&mut self, count: u32) {
+        self.active_elements = count;
+    }
 }
 
 impl<'a> EmitContext<'a> {
@@ -128,7 +202,7 @@ impl<'a> EmitContext<'a> {
     pub fn custom_section<'b>(&'b mut self, name: &str) -> SubContext<'a, 'b> {
         let mut cx = self.start_section(Section::Custom);
         cx.encoder.str(name);
-        return cx;
+        cx
     }
 
     pub fn list<T>(&mut self, list: T)
@@ -149,24 +223,27 @@ impl<'a> Deref for SubContext<'a, '_> {
     type Target = EmitContext<'a>;
 
     fn deref(&self) -> &EmitContext<'a> {
-        &self.cx
+        self.cx
     }
 }
 
 impl<'a> DerefMut for SubContext<'a, '_> {
     fn deref_mut(&mut self) -> &mut EmitContext<'a> {
-        &mut self.cx
+        self.cx
     }
 }
 
 impl Drop for SubContext<'_, '_> {
     fn drop(&mut self) {
         let amt = self.cx.encoder.pos() - self.write_size_to - MAX_U32_LENGTH;
-        assert!(amt <= u32::max_value() as usize);
+        assert!(amt <= u32::MAX as usize);
         self.cx.encoder.u32_at(self.write_size_to, amt as u32);
     }
 }
 
+/// The known sections of a wasm module, with their section ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
 pub enum Section {
     Custom = 0,
     Type = 1,
@@ -181,4 +258,5 @@ pub enum Section {
     Code = 10,
     Data = 11,
     DataCount = 12,
+    Tag = 13,
 }
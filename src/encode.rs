@@ -1,90 +1,154 @@
-pub const MAX_U32_LENGTH: usize = 5;
+//! LEB128 encoding and decoding, exactly as walrus's emitter uses it.
+//!
+//! Integer immediates, counts, indices and sizes are written as the shortest
+//! LEB128 encoding of their value, except for the sizes of sections, which
+//! are always padded to `MAX_U32_LENGTH` bytes so that they can be filled in
+//! once the section has been written. Offsets computed with these
+//! functions therefore line up with the bytes walrus emits.
 
-#[derive(Debug)]
-pub struct Encoder<'a> {
-    dst: &'a mut Vec<u8>,
-}
+use crate::error::Result;
+use failure::bail;
 
-impl<'data> Encoder<'data> {
-    pub fn new(dst: &'data mut Vec<u8>) -> Encoder<'data> {
-        Encoder { dst }
-    }
+mod encoder;
 
-    pub fn byte(&mut self, byte: u8) {
-        self.dst.push(byte);
-    }
+pub(crate) use self::encoder::Encoder;
 
-    pub fn bytes(&mut self, bytes: &[u8]) {
-        self.usize(bytes.len());
-        self.raw(bytes);
-    }
-
-    pub fn str(&mut self, data: &str) {
-        self.bytes(data.as_bytes())
-    }
+/// The most bytes a `u32` takes up as an unsigned LEB128, and the number of
+/// bytes that padded encodings always take up.
+pub const MAX_U32_LENGTH: usize = 5;
 
-    pub fn usize(&mut self, amt: usize) {
-        assert!(amt <= u32::max_value() as usize);
-        self.u32(amt as u32)
-    }
+/// Append `value` to `buf` as an unsigned LEB128.
+pub fn leb128_u32(buf: &mut Vec<u8>, value: u32) {
+    leb128::write::unsigned(buf, value.into()).unwrap();
+}
 
-    pub fn u32(&mut self, amt: u32) {
-        leb128::write::unsigned(&mut self.dst, amt.into()).unwrap();
-    }
+/// Append `value` to `buf` as a signed LEB128.
+pub fn leb128_i32(buf: &mut Vec<u8>, value: i32) {
+    leb128::write::signed(buf, value.into()).unwrap();
+}
 
-    pub fn i32(&mut self, val: i32) {
-        leb128::write::signed(&mut self.dst, val.into()).unwrap();
-    }
+/// Append `value` to `buf` as a signed LEB128.
+pub fn leb128_i64(buf: &mut Vec<u8>, value: i64) {
+    leb128::write::signed(buf, value).unwrap();
+}
 
-    pub fn i64(&mut self, val: i64) {
-        leb128::write::signed(&mut self.dst, val).unwrap();
+/// The number of bytes `leb128_u32` writes for `value`.
+pub fn leb128_len(value: u32) -> usize {
+    let mut len = 1;
+    let mut value = value >> 7;
+    while value != 0 {
+        len += 1;
+        value >>= 7;
     }
+    len
+}
 
-    pub fn f32(&mut self, val: f32) {
-        let bits = val.to_bits();
-        for i in 0..4 {
-            self.byte((bits >> (i * 8)) as u8);
-        }
+/// The number of bytes `leb128_i32` or `leb128_i64` writes for `value`.
+pub fn leb128_signed_len(value: i64) -> usize {
+    let mut len = 1;
+    let mut value = value >> 6;
+    while value != 0 && value != -1 {
+        len += 1;
+        value >>= 7;
     }
+    len
+}
 
-    pub fn f64(&mut self, val: f64) {
-        let bits = val.to_bits();
-        for i in 0..8 {
-            self.byte((bits >> (i * 8)) as u8);
-        }
-    }
+/// Append `value` to `buf` as an unsigned LEB128 padded to `MAX_U32_LENGTH`
+/// bytes, as section sizes are.
+pub fn leb128_u32_padded(buf: &mut Vec<u8>, value: u32) {
+    let pos = buf.len();
+    buf.resize(pos + MAX_U32_LENGTH, 0);
+    leb128_u32_padded_at(buf, pos, value);
+}
 
-    pub fn raw(&mut self, raw: &[u8]) {
-        self.dst.extend_from_slice(raw);
+/// Overwrite the `MAX_U32_LENGTH` bytes of `buf` at `pos` with `value` as a
+/// padded unsigned LEB128, as when patching a size in place.
+///
+/// # Panics
+///
+/// Panics if there are fewer than `MAX_U32_LENGTH` bytes at `pos`.
+pub fn leb128_u32_padded_at(buf: &mut [u8], pos: usize, mut value: u32) {
+    // TODO: don't write this code here, use upstream once
+    // gimli-rs/leb128#6 is implemented
+    for i in 0..MAX_U32_LENGTH {
+        let flag = if i == MAX_U32_LENGTH - 1 { 0 } else { 0x80 };
+        buf[pos + i] = (value as u8) & 0x7f | flag;
+        value >>= 7;
     }
+}
 
-    /// Reserves `bytes` bytes of space, returning the position at which the
-    /// reservation starts
-    pub fn reserve(&mut self, bytes: usize) -> usize {
-        let start = self.dst.len();
-        for _ in 0..bytes {
-            self.byte(0);
+/// Read an unsigned LEB128 `u32` from the start of `bytes`, returning it
+/// along with the number of bytes it took up.
+///
+/// Padded encodings are accepted, as long as they're no longer than
+/// `MAX_U32_LENGTH` bytes.
+pub fn read_leb128_u32(bytes: &[u8]) -> Result<(u32, usize)> {
+    let mut value = 0u32;
+    for (i, byte) in bytes.iter().enumerate() {
+        if i == MAX_U32_LENGTH {
+            bail!(
+                "LEB128 encoding of a u32 is longer than {} bytes",
+                MAX_U32_LENGTH
+            );
+        }
+        let low = u32::from(byte & 0x7f);
+        if i == MAX_U32_LENGTH - 1 && low >> 4 != 0 {
+            bail!("LEB128 value doesn't fit in a u32");
+        }
+        value |= low << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
         }
-        return start;
     }
+    bail!("unexpected end of LEB128 encoding")
+}
 
-    /// Reserves space to write a uleb128 `u32`, returning the postition at
-    /// hwich it can be written.
-    pub fn reserve_u32(&mut self) -> usize {
-        self.reserve(MAX_U32_LENGTH)
-    }
+/// Read a signed LEB128 `i32` from the start of `bytes`, returning it along
+/// with the number of bytes it took up.
+pub fn read_leb128_i32(bytes: &[u8]) -> Result<(i32, usize)> {
+    let (value, len) = read_signed(bytes, 32)?;
+    Ok((value as i32, len))
+}
 
-    pub fn pos(&self) -> usize {
-        self.dst.len()
-    }
+/// Read a signed LEB128 `i64` from the start of `bytes`, returning it along
+/// with the number of bytes it took up.
+pub fn read_leb128_i64(bytes: &[u8]) -> Result<(i64, usize)> {
+    read_signed(bytes, 64)
+}
 
-    // TODO: don't write this code here, use upstream once
-    // gimli-rs/leb128#6 is implemented
-    pub fn u32_at(&mut self, pos: usize, mut amt: u32) {
-        for i in 0..MAX_U32_LENGTH {
-            let flag = if i == MAX_U32_LENGTH - 1 { 0 } else { 0x80 };
-            self.dst[pos + i] = (amt as u8) & 0x7f | flag;
-            amt >>= 7;
+fn read_signed(bytes: &[u8], bits: u32) -> Result<(i64, usize)> {
+    let max_len = bits.div_ceil(7) as usize;
+    let mut value = 0i64;
+    let mut shift = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        if i == max_len {
+            bail!(
+                "LEB128 encoding of an i{} is longer than {} bytes",
+                bits,
+                max_len
+            );
+        }
+        value |= i64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 != 0 {
+            continue;
+        }
+        if shift < 64 && byte & 0x40 != 0 {
+            value |= -1 << shift;
+        }
+        let fits = if bits < 64 {
+            let bound = 1i64 << (bits - 1);
+            -bound <= value && value < bound
+        } else {
+            // The last byte only has one bit left, so the rest have to be
+            // copies of it.
+            i < max_len - 1 || *byte == 0x00 || *byte == 0x7f
+        };
+        if !fits {
+            bail!("LEB128 value doesn't fit in an i{}", bits);
         }
+        return Ok((value, i + 1));
     }
+    bail!("unexpected end of LEB128 encoding")
 }
@@ -0,0 +1,84 @@
+use super::{leb128_i32, leb128_i64, leb128_u32, leb128_u32_padded_at, MAX_U32_LENGTH};
+
+#[derive(Debug)]
+pub struct Encoder<'a> {
+    dst: &'a mut Vec<u8>,
+}
+
+impl<'data> Encoder<'data> {
+    pub fn new(dst: &'data mut Vec<u8>) -> Encoder<'data> {
+        Encoder { dst }
+    }
+
+    pub fn byte(&mut self, byte: u8) {
+        self.dst.push(byte);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.raw(bytes);
+    }
+
+    pub fn str(&mut self, data: &str) {
+        self.bytes(data.as_bytes())
+    }
+
+    pub fn usize(&mut self, amt: usize) {
+        assert!(amt <= u32::MAX as usize);
+        self.u32(amt as u32)
+    }
+
+    pub fn u32(&mut self, amt: u32) {
+        leb128_u32(self.dst, amt);
+    }
+
+    pub fn i32(&mut self, val: i32) {
+        leb128_i32(self.dst, val);
+    }
+
+    pub fn i64(&mut self, val: i64) {
+        leb128_i64(self.dst, val);
+    }
+
+    pub fn f32(&mut self, val: f32) {
+        let bits = val.to_bits();
+        for i in 0..4 {
+            self.byte((bits >> (i * 8)) as u8);
+        }
+    }
+
+    pub fn f64(&mut self, val: f64) {
+        let bits = val.to_bits();
+        for i in 0..8 {
+            self.byte((bits >> (i * 8)) as u8);
+        }
+    }
+
+    pub fn raw(&mut self, raw: &[u8]) {
+        self.dst.extend_from_slice(raw);
+    }
+
+    /// Reserves `bytes` bytes of space, returning the position at which the
+    /// reservation starts
+    pub fn reserve(&mut self, bytes: usize) -> usize {
+        let start = self.dst.len();
+        for _ in 0..bytes {
+            self.byte(0);
+        }
+        start
+    }
+
+    /// Reserves space to write a uleb128 `u32`, returning the postition at
+    /// hwich it can be written.
+    pub fn reserve_u32(&mut self) -> usize {
+        self.reserve(MAX_U32_LENGTH)
+    }
+
+    pub fn pos(&self) -> usize {
+        self.dst.len()
+    }
+
+    pub fn u32_at(&mut self, pos: usize, amt: u32) {
+        leb128_u32_padded_at(self.dst, pos, amt);
+    }
+}
@@ -1,7 +1,10 @@
 //! Error types and utilities.
 
-pub use failure::Error;
+// `failure_derive` puts the impls it generates inside constants.
+#![allow(non_local_definitions)]
+
 use failure::*;
+use std::fmt;
 
 /// Either `Ok(T)` or `Err(failure::Error)`.
 pub type Result<T> = ::std::result::Result<T, failure::Error>;
@@ -16,3 +19,161 @@ pub enum ErrorKind {
     #[fail(display = "The input WebAssembly is invalid")]
     InvalidWasm,
 }
+
+/// A malformed function body was found in the code section.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(
+    display = "malformed body for function {} at byte offset {}: {}",
+    function, offset, kind
+)]
+pub struct MalformedFunctionBody {
+    /// The index of the function, in the function index space, whose body is
+    /// malformed.
+    pub function: u32,
+    /// The byte offset, within the input wasm, where the problem was found.
+    pub offset: usize,
+    /// What exactly is malformed about the body.
+    pub kind: MalformedBodyKind,
+}
+
+/// The ways in which a function body can be malformed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
+pub enum MalformedBodyKind {
+    /// The body declares more than 2^32 locals. The offset is that of the
+    /// local declaration which goes over.
+    #[fail(display = "can't have more than 2^32 locals")]
+    TooManyLocals,
+    /// There are more bytes in the body after the function's final `end`.
+    #[fail(display = "trailing bytes after the function's final `end`")]
+    TrailingBytes,
+    /// The body's declared size doesn't match its contents: either it runs
+    /// past the end of the code section, or its bytes run out before the
+    /// function's final `end`.
+    #[fail(display = "body size doesn't match its declared size")]
+    BodySizeMismatch,
+}
+
+/// A function uses an instruction from a WebAssembly feature which wasn't
+/// enabled with `ModuleConfig::wasm_features`.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(
+    display = "function {} uses `{}`, but the {} feature isn't enabled",
+    function, instruction, feature
+)]
+pub struct DisabledFeature {
+    /// The index of the function, in the function index space, which uses the
+    /// instruction.
+    pub function: u32,
+    /// The instruction's name in the text format, such as `i32.extend8_s`.
+    pub instruction: &'static str,
+    /// The name of the feature the instruction belongs to.
+    pub feature: &'static str,
+}
+
+/// An import selected for stubbing by `passes::stub_missing_imports` isn't a
+/// function, and so can't be replaced by a local stub.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(
+    display = "can't stub the import `{}` `{}`, since it's a {} rather than a function",
+    module, name, kind
+)]
+pub struct UnstubbableImport {
+    /// The module the item is imported from.
+    pub module: String,
+    /// The name of the imported item.
+    pub name: String,
+    /// What kind of item it is: a table, memory or global.
+    pub kind: &'static str,
+}
+
+/// A `wasmparser` type which walrus can't represent where it was used, such
+/// as the block type `EmptyBlockType` used as a value type.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(display = "`{:?}` is not a valid {}", ty, expected)]
+pub struct UnsupportedType {
+    /// The type.
+    pub ty: wasmparser::Type,
+    /// What the type was used as, such as a "value type" or a "table element
+    /// type".
+    pub expected: &'static str,
+}
+
+/// More than one function has the name given to
+/// `ModuleFunctions::by_name_unique`.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(display = "more than one function is named `{}`", name)]
+pub struct AmbiguousName {
+    /// The name.
+    pub name: String,
+    /// Every function with the name, in order of their ids.
+    pub functions: Vec<crate::FunctionId>,
+}
+
+/// The name given to `Module::rename_function` and friends is already
+/// taken.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[fail(display = "the name `{}` is already taken", name)]
+pub struct NameTaken {
+    /// The name.
+    pub name: String,
+    /// The function which already has the name, if it's a function's name.
+    pub function: Option<crate::FunctionId>,
+    /// The export which already has the name, if it's an export's name.
+    pub export: Option<crate::ExportId>,
+}
+
+/// A function passed to `Module::delete_function_if_unused` is still used.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub struct FunctionInUse {
+    /// The function.
+    pub function: crate::FunctionId,
+    /// Every use of the function, as found by `Module::function_uses`.
+    pub uses: Vec<crate::FunctionUse>,
+}
+
+impl fmt::Display for FunctionInUse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} is still used by:", self.function)?;
+        for u in self.uses.iter() {
+            match u {
+                crate::FunctionUse::Call { caller, expr } => {
+                    write!(f, "\n  a call {:?} in {:?}", expr, caller)?
+                }
+                crate::FunctionUse::Export(id) => write!(f, "\n  export {:?}", id)?,
+                crate::FunctionUse::Element { source, index } => write!(
+                    f,
+                    "\n  member {} of the element segment {:?}",
+                    index, source
+                )?,
+                crate::FunctionUse::Start => write!(f, "\n  the start function")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The export asked for by `ModuleExports::get_func` isn't a function.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub struct WrongExportKind {
+    /// The name of the export.
+    pub name: String,
+    /// What the export actually is.
+    pub item: crate::ExportItem,
+}
+
+impl fmt::Display for WrongExportKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.item {
+            crate::ExportItem::Function(_) => "function",
+            crate::ExportItem::Table(_) => "table",
+            crate::ExportItem::Memory(_) => "memory",
+            crate::ExportItem::Global(_) => "global",
+            crate::ExportItem::Tag(_) => "tag",
+        };
+        write!(
+            f,
+            "the export `{}` is a {}, not a function",
+            self.name, kind
+        )
+    }
+}
@@ -1,14 +1,48 @@
 use crate::ir::*;
 use crate::tombstone_arena::TombstoneArena;
-use crate::{FunctionId, LocalFunction, Module, TypeId, ValType};
-use crate::{ModuleFunctions, ModuleTypes};
-use std::mem;
+use crate::{FunctionId, FunctionKind, GlobalId, ImportId, LocalFunction, MemoryId, Module};
+use crate::{ModuleFunctions, ModuleTypes, Result, TypeId, ValType};
+use failure::bail;
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut, Drop};
 
 /// A helpful struct used for building instances of `LocalFunction`
 #[derive(Default, Debug)]
 pub struct FunctionBuilder {
     pub(crate) arena: TombstoneArena<Expr>,
+    interned: Option<HashMap<Leaf, ExprId>>,
+}
+
+/// The identity of a pure leaf expression, which can be shared between all
+/// the places it's used when interning is enabled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Leaf {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    V128(u128),
+    LocalGet(LocalId),
+    GlobalGet(GlobalId),
+}
+
+impl Leaf {
+    fn of(expr: &Expr) -> Option<Leaf> {
+        Some(match expr {
+            // Floats are compared by their bits, so that `-0.0` and `0.0`, and
+            // NaNs with different payloads, aren't shared.
+            Expr::Const(Const { value }) => match *value {
+                Value::I32(n) => Leaf::I32(n),
+                Value::I64(n) => Leaf::I64(n),
+                Value::F32(n) => Leaf::F32(n.to_bits()),
+                Value::F64(n) => Leaf::F64(n.to_bits()),
+                Value::V128(n) => Leaf::V128(n),
+            },
+            Expr::LocalGet(LocalGet { local }) => Leaf::LocalGet(*local),
+            Expr::GlobalGet(GlobalGet { global }) => Leaf::GlobalGet(*global),
+            _ => return None,
+        })
+    }
 }
 
 impl FunctionBuilder {
@@ -21,10 +55,64 @@ impl FunctionBuilder {
     where
         T: Ast,
     {
-        let id = self.arena.alloc(val.into());
+        let expr = val.into();
+        let id = match (&mut self.interned, Leaf::of(&expr)) {
+            (Some(interned), Some(leaf)) => {
+                let arena = &mut self.arena;
+                *interned.entry(leaf).or_insert_with(|| arena.alloc(expr))
+            }
+            _ => self.arena.alloc(expr),
+        };
         T::new_id(id)
     }
 
+    /// Enable or disable interning of pure leaf expressions.
+    ///
+    /// While interning is enabled, creating a constant, a `local.get` or a
+    /// `global.get` which is identical to one created earlier while interning
+    /// was enabled returns the earlier expression's id rather than allocating
+    /// a new expression. This saves a lot of memory in large functions which
+    /// use the same constants over and over again, and doesn't change the
+    /// emitted code, since a shared expression is emitted everywhere it's
+    /// used.
+    ///
+    /// Because interned expressions are shared, mutating one in place, for
+    /// example through `LocalFunction::get_mut`, changes it everywhere it's
+    /// used. To change just one use, allocate a new expression and replace
+    /// the id at that use instead. Once an interned expression has been
+    /// mutated it's no longer handed out to new uses. Passes that identify
+    /// uses by `ExprId` see a single id for all of an interned expression's
+    /// uses.
+    ///
+    /// Disabling interning forgets which expressions were interned, but
+    /// expressions which are already shared stay shared.
+    ///
+    /// Interning is disabled by default.
+    pub fn set_interning(&mut self, enable: bool) {
+        if !enable {
+            self.interned = None;
+        } else if self.interned.is_none() {
+            self.interned = Some(HashMap::new());
+        }
+    }
+
+    /// Is `id` an interned expression, which may be shared between several
+    /// uses?
+    pub fn is_interned(&self, id: ExprId) -> bool {
+        match (&self.interned, Leaf::of(&self.arena[id])) {
+            (Some(interned), Some(leaf)) => interned.get(&leaf) == Some(&id),
+            _ => false,
+        }
+    }
+
+    /// Stop handing out `id` to new uses, since it's about to be mutated.
+    pub(crate) fn forget_interned(&mut self, id: ExprId) {
+        if self.is_interned(id) {
+            let leaf = Leaf::of(&self.arena[id]).unwrap();
+            self.interned.as_mut().unwrap().remove(&leaf);
+        }
+    }
+
     /// Create a `Block` node with the kind of `Block`.
     ///
     /// Note that instructions aren't passed here, but rather added to the
@@ -61,9 +149,21 @@ impl FunctionBuilder {
 
     /// Create a `Block` node with the kind of `Loop`
     pub fn loop_<'a>(&'a mut self, results: Box<[ValType]>) -> BlockBuilder<'a> {
+        self.loop_with_params(Vec::new().into_boxed_slice(), results)
+    }
+
+    /// Create a `Block` node with the kind of `Loop`, which takes `params`
+    /// from the operand stack
+    ///
+    /// Branches to a loop supply its `params`, rather than its `results`.
+    pub fn loop_with_params<'a>(
+        &'a mut self,
+        params: Box<[ValType]>,
+        results: Box<[ValType]>,
+    ) -> BlockBuilder<'a> {
         self.block_builder(Block {
-            kind: BlockKind::Block,
-            params: Vec::new().into_boxed_slice(),
+            kind: BlockKind::Loop,
+            params,
             results,
             exprs: Vec::new(),
         })
@@ -83,6 +183,21 @@ impl FunctionBuilder {
         })
     }
 
+    /// Create a `Block` node with the kind of `Try`, for the body or a
+    /// handler of a `Try` expression
+    pub fn try_block<'a>(
+        &'a mut self,
+        params: Box<[ValType]>,
+        results: Box<[ValType]>,
+    ) -> BlockBuilder<'a> {
+        self.block_builder(Block {
+            kind: BlockKind::Try,
+            params,
+            results,
+            exprs: Vec::new(),
+        })
+    }
+
     fn block_builder<'a>(&'a mut self, block: Block) -> BlockBuilder<'a> {
         let id = self.alloc(block);
         BlockBuilder {
@@ -112,6 +227,55 @@ impl FunctionBuilder {
         self.const_(Value::F64(val))
     }
 
+    /// Creates a `v128.const` instruction for the specified bytes, in the
+    /// order they're stored in memory
+    pub fn v128_const(&mut self, bytes: [u8; 16]) -> ExprId {
+        self.const_(Value::v128_from_bytes(bytes))
+    }
+
+    /// Creates a `v128.const` instruction for the specified `i32x4` lanes
+    pub fn v128_const_u32_lanes(&mut self, lanes: [u32; 4]) -> ExprId {
+        self.const_(Value::v128_from_u32_lanes(lanes))
+    }
+
+    /// Creates an expression which, if `condition` is true, calls `abort`
+    /// with the address and length of `message` in `memory` and then traps,
+    /// and otherwise does nothing.
+    ///
+    /// `abort` must take two `i32` parameters. The message is placed in
+    /// memory with `Module::add_static_str`, so every trap with the same
+    /// message shares one data segment.
+    pub fn trap_if(
+        &mut self,
+        module: &mut Module,
+        condition: ExprId,
+        message: &str,
+        abort: FunctionId,
+        memory: MemoryId,
+    ) -> Result<ExprId> {
+        let ty = module.types.get(module.funcs.get(abort).ty());
+        if ty.params() != [ValType::I32, ValType::I32] {
+            bail!(
+                "function {} must take an address and a length to abort with a message",
+                abort.index()
+            );
+        }
+        let address = module.add_static_str(memory, message)?;
+
+        let trap = {
+            let mut block = self.if_else_block(Box::new([]), Box::new([]));
+            let address = block.i32_const(address as i32);
+            let len = block.i32_const(message.len() as i32);
+            let call = block.call(abort, Box::new([address, len]));
+            block.expr(call);
+            let unreachable = block.unreachable();
+            block.expr(unreachable);
+            block.id()
+        };
+        let ok = self.if_else_block(Box::new([]), Box::new([])).id();
+        Ok(self.if_else(condition, trap, ok))
+    }
+
     /// Finishes this builder, wrapping it all up and inserting it into the
     /// specified `Module`.
     pub fn finish(
@@ -126,14 +290,66 @@ impl FunctionBuilder {
 
     /// Finishes this builder, wrapping it all up and inserting it into the
     /// specified `Module`.
+    ///
+    /// Any blocks that take parameters or produce multiple results have their
+    /// types added to `types`, since they're encoded as type indices.
     pub fn finish_parts(
-        mut self,
+        self,
         ty_id: TypeId,
         args: Vec<LocalId>,
         exprs: Vec<ExprId>,
         types: &mut ModuleTypes,
         funcs: &mut ModuleFunctions,
     ) -> FunctionId {
+        funcs.add_local(self.into_local(ty_id, args, exprs, types))
+    }
+
+    /// Finishes this builder as the body of the imported function `func`,
+    /// which becomes a local function with the same id and type.
+    ///
+    /// The function's import is deleted from the module, and its id returned.
+    /// Returns an error if `func` isn't an imported function, or `args`
+    /// doesn't have a local for each of its parameters.
+    pub fn finish_import(
+        self,
+        func: FunctionId,
+        args: Vec<LocalId>,
+        exprs: Vec<ExprId>,
+        module: &mut Module,
+    ) -> Result<ImportId> {
+        let (ty, import) = match &module.funcs.get(func).kind {
+            FunctionKind::Import(i) => (i.ty, i.import),
+            _ => bail!("function {} isn't imported", func.index()),
+        };
+        let params = module.types.get(ty).params().len();
+        if args.len() != params {
+            bail!(
+                "function {} takes {} parameters, but was given {} locals for them",
+                func.index(),
+                params,
+                args.len()
+            );
+        }
+        let local = self.into_local(ty, args, exprs, &mut module.types);
+        module.funcs.get_mut(func).kind = FunctionKind::Local(local);
+        module.imports.delete(import);
+        Ok(import)
+    }
+
+    fn into_local(
+        mut self,
+        ty_id: TypeId,
+        args: Vec<LocalId>,
+        exprs: Vec<ExprId>,
+        types: &mut ModuleTypes,
+    ) -> LocalFunction {
+        for (_, expr) in self.arena.iter() {
+            if let Expr::Block(block) = expr {
+                if block.needs_type_index() {
+                    types.add(&block.params, &block.results);
+                }
+            }
+        }
         let ty = types.get(ty_id);
         let entry = self.alloc(Block {
             kind: BlockKind::FunctionEntry,
@@ -141,8 +357,7 @@ impl FunctionBuilder {
             results: ty.results().to_vec().into_boxed_slice(),
             exprs,
         });
-        let func = LocalFunction::new(ty_id, args, self, entry);
-        funcs.add_local(func)
+        LocalFunction::new(ty_id, args, self, entry)
     }
 }
 
@@ -183,7 +398,7 @@ impl DerefMut for BlockBuilder<'_> {
 
 impl Drop for BlockBuilder<'_> {
     fn drop(&mut self) {
-        let exprs = mem::replace(&mut self.exprs, Vec::new());
+        let exprs = std::mem::take(&mut self.exprs);
         let block = match &mut self.builder.arena[self.id.into()] {
             Expr::Block(b) => b,
             _ => unreachable!(),
@@ -1,52 +1,193 @@
 //! Handling wasm constant values
 
+use crate::decode::{Extended, Instruction, Instructions, Reader};
 use crate::emit::{Emit, EmitContext};
-use crate::ir::Value;
+use crate::ir::{RefType, Value};
 use crate::parse::IndicesToIds;
-use crate::{GlobalId, Result};
+use crate::{FunctionId, GlobalId, Result};
 use failure::bail;
 
 /// A constant which is produced in WebAssembly, typically used in global
 /// initializers or element/data offsets.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum InitExpr {
     /// An immediate constant value
     Value(Value),
     /// A constant value referenced by the global specified
     Global(GlobalId),
+    /// A reference to the function specified, as with `ref.func`
+    RefFunc(FunctionId),
+    /// A null reference of the given type, as with `ref.null`
+    RefNull(RefType),
+    /// An extended constant expression, as allowed by the extended-const
+    /// proposal, made of the given instructions in order.
+    ///
+    /// Expressions of a single instruction are always represented by the
+    /// other variants when parsed.
+    Extended(Vec<InitOp>),
+}
+
+/// An instruction of an extended constant expression.
+#[derive(Debug, Copy, Clone)]
+pub enum InitOp {
+    /// Push an immediate constant value
+    Value(Value),
+    /// Push the value of the global specified
+    Global(GlobalId),
+    /// Push a reference to the function specified
+    RefFunc(FunctionId),
+    /// Push a null reference of the given type
+    RefNull(RefType),
+    /// `i32.add`
+    I32Add,
+    /// `i32.sub`
+    I32Sub,
+    /// `i32.mul`
+    I32Mul,
+    /// `i64.add`
+    I64Add,
+    /// `i64.sub`
+    I64Sub,
+    /// `i64.mul`
+    I64Mul,
 }
 
 impl InitExpr {
     pub(crate) fn eval(init: &wasmparser::InitExpr, ids: &IndicesToIds) -> Result<InitExpr> {
+        let mut reader = Instructions::from_init_expr(init)?;
+        let expr = InitExpr::read(&mut reader, ids)?;
+        if !reader.eof() {
+            bail!("unexpected data at the end of a constant expression");
+        }
+        Ok(expr)
+    }
+
+    /// Decode the constant expression at the start of `r`, for sections
+    /// which are decoded by walrus rather than `wasmparser`.
+    pub(crate) fn decode(r: &mut Reader, ids: &IndicesToIds) -> Result<InitExpr> {
+        let mut reader = Instructions::new(r.rest(), 0);
+        let expr = InitExpr::read(&mut reader, ids)?;
+        r.skip(reader.original_position())?;
+        Ok(expr)
+    }
+
+    /// Read a constant expression, up to and including its `end`.
+    fn read(reader: &mut Instructions, ids: &IndicesToIds) -> Result<InitExpr> {
         use wasmparser::Operator::*;
-        let mut reader = init.get_operators_reader();
-        let val = match reader.read()? {
-            I32Const { value } => InitExpr::Value(Value::I32(value)),
-            I64Const { value } => InitExpr::Value(Value::I64(value)),
-            F32Const { value } => InitExpr::Value(Value::F32(f32::from_bits(value.bits()))),
-            F64Const { value } => InitExpr::Value(Value::F64(f64::from_bits(value.bits()))),
-            GetGlobal { global_index } => InitExpr::Global(ids.get_global(global_index)?),
-            _ => bail!("invalid constant expression"),
-        };
-        match reader.read()? {
-            End => {}
-            _ => bail!("invalid constant expression"),
-        }
-        reader.ensure_end()?;
-        Ok(val)
+        let mut ops = Vec::new();
+        loop {
+            let op = match reader.read(ids)? {
+                Instruction::Extended(Extended::RefNull(ty)) => InitOp::RefNull(ty),
+                Instruction::Extended(Extended::RefFunc(func)) => InitOp::RefFunc(func),
+                Instruction::Extended(_) | Instruction::Memory(..) => {
+                    bail!("invalid constant expression")
+                }
+                Instruction::Operator(op) => match op {
+                    I32Const { value } => InitOp::Value(Value::I32(value)),
+                    I64Const { value } => InitOp::Value(Value::I64(value)),
+                    F32Const { value } => InitOp::Value(Value::F32(f32::from_bits(value.bits()))),
+                    F64Const { value } => InitOp::Value(Value::F64(f64::from_bits(value.bits()))),
+                    GetGlobal { global_index } => InitOp::Global(ids.get_global(global_index)?),
+                    I32Add => InitOp::I32Add,
+                    I32Sub => InitOp::I32Sub,
+                    I32Mul => InitOp::I32Mul,
+                    I64Add => InitOp::I64Add,
+                    I64Sub => InitOp::I64Sub,
+                    I64Mul => InitOp::I64Mul,
+                    End => break,
+                    _ => bail!("invalid constant expression"),
+                },
+            };
+            ops.push(op);
+        }
+
+        if let [op] = ops[..] {
+            match op {
+                InitOp::Value(value) => return Ok(InitExpr::Value(value)),
+                InitOp::Global(id) => return Ok(InitExpr::Global(id)),
+                InitOp::RefFunc(id) => return Ok(InitExpr::RefFunc(id)),
+                InitOp::RefNull(ty) => return Ok(InitExpr::RefNull(ty)),
+                _ => {}
+            }
+        }
+        if ops.is_empty() {
+            bail!("invalid constant expression");
+        }
+        Ok(InitExpr::Extended(ops))
+    }
+
+    /// The globals this expression reads, in order.
+    pub fn globals(&self) -> Vec<GlobalId> {
+        match self {
+            InitExpr::Global(id) => vec![*id],
+            InitExpr::Extended(ops) => ops
+                .iter()
+                .filter_map(|op| match op {
+                    InitOp::Global(id) => Some(*id),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The functions this expression references with `ref.func`, in order.
+    pub fn funcs(&self) -> Vec<FunctionId> {
+        match self {
+            InitExpr::RefFunc(id) => vec![*id],
+            InitExpr::Extended(ops) => ops
+                .iter()
+                .filter_map(|op| match op {
+                    InitOp::RefFunc(id) => Some(*id),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
     }
 }
 
 impl Emit for InitExpr {
+    fn emit(&self, cx: &mut EmitContext) {
+        match self {
+            InitExpr::Value(val) => InitOp::Value(*val).emit(cx),
+            InitExpr::Global(id) => InitOp::Global(*id).emit(cx),
+            InitExpr::RefFunc(id) => InitOp::RefFunc(*id).emit(cx),
+            InitExpr::RefNull(ty) => InitOp::RefNull(*ty).emit(cx),
+            InitExpr::Extended(ops) => {
+                for op in ops {
+                    op.emit(cx);
+                }
+            }
+        }
+        cx.encoder.byte(0x0b); // end
+    }
+}
+
+impl Emit for InitOp {
     fn emit(&self, cx: &mut EmitContext) {
         match *self {
-            InitExpr::Value(val) => val.emit(&mut cx.encoder),
-            InitExpr::Global(id) => {
+            InitOp::Value(val) => val.emit(&mut cx.encoder),
+            InitOp::Global(id) => {
                 let idx = cx.indices.get_global_index(id);
                 cx.encoder.byte(0x23); // global.get
                 cx.encoder.u32(idx);
             }
+            InitOp::RefFunc(id) => {
+                let idx = cx.indices.get_func_index(id);
+                cx.encoder.byte(0xd2); // ref.func
+                cx.encoder.u32(idx);
+            }
+            InitOp::RefNull(ty) => {
+                cx.encoder.byte(0xd0); // ref.null
+                ty.emit(&mut cx.encoder);
+            }
+            InitOp::I32Add => cx.encoder.byte(0x6a),
+            InitOp::I32Sub => cx.encoder.byte(0x6b),
+            InitOp::I32Mul => cx.encoder.byte(0x6c),
+            InitOp::I64Add => cx.encoder.byte(0x7c),
+            InitOp::I64Sub => cx.encoder.byte(0x7d),
+            InitOp::I64Mul => cx.encoder.byte(0x7e),
         }
-        cx.encoder.byte(0x0b); // end
     }
 }
@@ -1,7 +1,28 @@
 //! Matching expressions.
+//!
+//! Besides the generated `*Matcher` types, this module has a small pattern
+//! language for finding expressions by their shape. Patterns are built up
+//! from the functions in this module, and can capture the expressions they
+//! match by name:
+//!
+//! ```
+//! use walrus::ir::matcher::*;
+//! use walrus::ir::BinaryOp;
+//!
+//! // `(i32.add (local.get _) (i32.const _))`, capturing the constant.
+//! let pattern = binop(
+//!     BinaryOp::I32Add,
+//!     local_get(any()),
+//!     const_i32(any()).bind("n"),
+//! );
+//! ```
+//!
+//! `find_all` runs a pattern over a whole function, and `replace_all` rewrites
+//! each expression it finds.
 
-use super::Expr;
+use super::*;
 use crate::LocalFunction;
+use std::ops::Index;
 
 // Re-export the custom derive-generated impls here, where it makes more sense
 // to expose them.
@@ -12,3 +33,327 @@ pub trait Matcher {
     /// Does this expression match?
     fn is_match(&self, func: &LocalFunction, expr: &Expr) -> bool;
 }
+
+/// A structural pattern over expressions.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    kind: PatternKind,
+    name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum PatternKind {
+    Any,
+    Or(Box<Pattern>, Box<Pattern>),
+    Binop(BinaryOp, Box<Pattern>, Box<Pattern>),
+    Unop(UnaryOp, Box<Pattern>),
+    LocalGet(Imm<LocalId>),
+    LocalSet(Imm<LocalId>, Box<Pattern>),
+    LocalTee(Imm<LocalId>, Box<Pattern>),
+    GlobalGet(Imm<GlobalId>),
+    I32Const(Imm<i32>),
+    I64Const(Imm<i64>),
+    Drop(Box<Pattern>),
+    Select(Box<Pattern>, Box<Pattern>, Box<Pattern>),
+}
+
+/// A pattern over an immediate, such as a local or a constant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Imm<T> {
+    /// Matches any value.
+    Any,
+    /// Matches only this value.
+    Exactly(T),
+}
+
+impl<T: PartialEq> Imm<T> {
+    fn matches(&self, value: &T) -> bool {
+        match self {
+            Imm::Any => true,
+            Imm::Exactly(v) => v == value,
+        }
+    }
+}
+
+/// Patterns that have a wildcard, which is what `any` returns.
+pub trait Wildcard {
+    /// The pattern matching anything.
+    fn any() -> Self;
+}
+
+impl Wildcard for Pattern {
+    fn any() -> Pattern {
+        Pattern::new(PatternKind::Any)
+    }
+}
+
+impl<T> Wildcard for Imm<T> {
+    fn any() -> Imm<T> {
+        Imm::Any
+    }
+}
+
+/// Match any expression, or any immediate.
+pub fn any<T: Wildcard>() -> T {
+    T::any()
+}
+
+/// Match only the immediate `value`.
+pub fn exactly<T>(value: T) -> Imm<T> {
+    Imm::Exactly(value)
+}
+
+/// Match a binary operation `op` on operands matching `lhs` and `rhs`.
+pub fn binop(op: BinaryOp, lhs: Pattern, rhs: Pattern) -> Pattern {
+    Pattern::new(PatternKind::Binop(op, Box::new(lhs), Box::new(rhs)))
+}
+
+/// Match a unary operation `op` on an operand matching `expr`.
+pub fn unop(op: UnaryOp, expr: Pattern) -> Pattern {
+    Pattern::new(PatternKind::Unop(op, Box::new(expr)))
+}
+
+/// Match a `local.get`.
+pub fn local_get(local: Imm<LocalId>) -> Pattern {
+    Pattern::new(PatternKind::LocalGet(local))
+}
+
+/// Match a `local.set` of a value matching `value`.
+pub fn local_set(local: Imm<LocalId>, value: Pattern) -> Pattern {
+    Pattern::new(PatternKind::LocalSet(local, Box::new(value)))
+}
+
+/// Match a `local.tee` of a value matching `value`.
+pub fn local_tee(local: Imm<LocalId>, value: Pattern) -> Pattern {
+    Pattern::new(PatternKind::LocalTee(local, Box::new(value)))
+}
+
+/// Match a `global.get`.
+pub fn global_get(global: Imm<GlobalId>) -> Pattern {
+    Pattern::new(PatternKind::GlobalGet(global))
+}
+
+/// Match an `i32.const`.
+pub fn const_i32(value: Imm<i32>) -> Pattern {
+    Pattern::new(PatternKind::I32Const(value))
+}
+
+/// Match an `i64.const`.
+pub fn const_i64(value: Imm<i64>) -> Pattern {
+    Pattern::new(PatternKind::I64Const(value))
+}
+
+/// Match a `drop` of a value matching `expr`.
+pub fn drop(expr: Pattern) -> Pattern {
+    Pattern::new(PatternKind::Drop(Box::new(expr)))
+}
+
+/// Match a `select` with operands matching the given patterns.
+pub fn select(condition: Pattern, consequent: Pattern, alternative: Pattern) -> Pattern {
+    Pattern::new(PatternKind::Select(
+        Box::new(condition),
+        Box::new(consequent),
+        Box::new(alternative),
+    ))
+}
+
+impl Pattern {
+    fn new(kind: PatternKind) -> Pattern {
+        Pattern { kind, name: None }
+    }
+
+    /// Capture the expression this pattern matches as `name`.
+    ///
+    /// Names should be unique within a pattern.
+    pub fn bind(mut self, name: &str) -> Pattern {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Match either this pattern or, if it doesn't match, `other`.
+    pub fn or(self, other: Pattern) -> Pattern {
+        Pattern::new(PatternKind::Or(Box::new(self), Box::new(other)))
+    }
+
+    /// Match this pattern against the expression `id` in `func`, returning
+    /// the captured expressions if it matches.
+    pub fn matches(&self, func: &LocalFunction, id: ExprId) -> Option<Match> {
+        let mut captures = Vec::new();
+        if self.match_expr(func, id, &mut captures) {
+            Some(Match { expr: id, captures })
+        } else {
+            None
+        }
+    }
+
+    fn match_expr(
+        &self,
+        func: &LocalFunction,
+        id: ExprId,
+        captures: &mut Vec<(String, ExprId)>,
+    ) -> bool {
+        self.match_with(func, func.get(id), Some(id), captures)
+    }
+
+    /// Does `expr`, whose id is `id` if it's known, match this pattern?
+    fn match_with(
+        &self,
+        func: &LocalFunction,
+        expr: &Expr,
+        id: Option<ExprId>,
+        captures: &mut Vec<(String, ExprId)>,
+    ) -> bool {
+        let start = captures.len();
+        let mut sub = |p: &Pattern, id: ExprId| p.match_expr(func, id, captures);
+        let matched = match (&self.kind, expr) {
+            (PatternKind::Any, _) => true,
+            (PatternKind::Or(a, b), _) => {
+                a.match_with(func, expr, id, captures) || {
+                    captures.truncate(start);
+                    b.match_with(func, expr, id, captures)
+                }
+            }
+            (PatternKind::Binop(op, lhs, rhs), Expr::Binop(e)) => {
+                *op == e.op && sub(lhs, e.lhs) && sub(rhs, e.rhs)
+            }
+            (PatternKind::Unop(op, expr), Expr::Unop(e)) => *op == e.op && sub(expr, e.expr),
+            (PatternKind::LocalGet(local), Expr::LocalGet(e)) => local.matches(&e.local),
+            (PatternKind::LocalSet(local, value), Expr::LocalSet(e)) => {
+                local.matches(&e.local) && sub(value, e.value)
+            }
+            (PatternKind::LocalTee(local, value), Expr::LocalTee(e)) => {
+                local.matches(&e.local) && sub(value, e.value)
+            }
+            (PatternKind::GlobalGet(global), Expr::GlobalGet(e)) => global.matches(&e.global),
+            (
+                PatternKind::I32Const(n),
+                Expr::Const(Const {
+                    value: Value::I32(v),
+                }),
+            ) => n.matches(v),
+            (
+                PatternKind::I64Const(n),
+                Expr::Const(Const {
+                    value: Value::I64(v),
+                }),
+            ) => n.matches(v),
+            (PatternKind::Drop(expr), Expr::Drop(e)) => sub(expr, e.expr),
+            (PatternKind::Select(c, t, f), Expr::Select(e)) => {
+                sub(c, e.condition) && sub(t, e.consequent) && sub(f, e.alternative)
+            }
+            _ => false,
+        };
+        if !matched {
+            // Forget anything captured by a partial match, such as the first
+            // operand of a binop whose second operand didn't match.
+            captures.truncate(start);
+            return false;
+        }
+        if let (Some(name), Some(id)) = (&self.name, id) {
+            captures.insert(start, (name.clone(), id));
+        }
+        true
+    }
+}
+
+impl Matcher for Pattern {
+    fn is_match(&self, func: &LocalFunction, expr: &Expr) -> bool {
+        self.match_with(func, expr, None, &mut Vec::new())
+    }
+}
+
+/// An expression matched by a `Pattern`, along with the expressions its
+/// sub-patterns captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// The matched expression.
+    pub expr: ExprId,
+    captures: Vec<(String, ExprId)>,
+}
+
+impl Match {
+    /// The expression captured as `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<ExprId> {
+        self.captures
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, id)| *id)
+    }
+
+    /// Every captured expression and its name, in the order they appear in
+    /// the pattern.
+    pub fn captures(&self) -> impl Iterator<Item = (&str, ExprId)> {
+        self.captures.iter().map(|(n, id)| (n.as_str(), *id))
+    }
+}
+
+impl Index<&str> for Match {
+    type Output = ExprId;
+
+    fn index(&self, name: &str) -> &ExprId {
+        match self.captures.iter().find(|(n, _)| n == name) {
+            Some((_, id)) => id,
+            None => panic!("nothing was captured as `{}`", name),
+        }
+    }
+}
+
+/// Find every expression in `func` matching `pattern`, in the order they're
+/// visited by `dfs_in_order`.
+///
+/// Matches can be nested within each other, in which case the outer one comes
+/// first.
+pub fn find_all(func: &LocalFunction, pattern: &Pattern) -> Vec<Match> {
+    let mut find = FindAll {
+        func,
+        pattern,
+        matches: Vec::new(),
+    };
+    dfs_in_order(&mut find, func, func.entry_block().into());
+    find.matches
+}
+
+struct FindAll<'a, 'p> {
+    func: &'a LocalFunction,
+    pattern: &'p Pattern,
+    matches: Vec<Match>,
+}
+
+impl<'a> Visitor<'a> for FindAll<'a, '_> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, id: &ExprId) {
+        if let Some(m) = self.pattern.matches(self.func, *id) {
+            self.matches.push(m);
+        }
+        id.visit(self);
+    }
+}
+
+/// Replace every expression in `func` matching `pattern` with the expression
+/// `f` returns for it, returning how many were replaced.
+///
+/// Each match is replaced in place, so everything referring to the matched
+/// expression sees the replacement. Returning `None` from `f` leaves that
+/// match as it is. Matches are replaced in the order `find_all` returns them,
+/// and each is checked again just before `f` is called for it, so it's
+/// skipped if an earlier replacement means it no longer matches.
+pub fn replace_all<F>(func: &mut LocalFunction, pattern: &Pattern, mut f: F) -> usize
+where
+    F: FnMut(&mut LocalFunction, &Match) -> Option<Expr>,
+{
+    let mut replaced = 0;
+    for m in find_all(func, pattern) {
+        let m = match pattern.matches(func, m.expr) {
+            Some(m) => m,
+            None => continue,
+        };
+        if let Some(expr) = f(func, &m) {
+            *func.get_mut(m.expr) = expr;
+            replaced += 1;
+        }
+    }
+    replaced
+}
@@ -5,11 +5,14 @@
 //! are representd as `Block`s.
 
 pub mod matcher;
+mod traversals;
+
+pub use self::traversals::{dfs_in_order, dfs_pre_order_mut};
 
 use crate::dot::Dot;
 use crate::encode::Encoder;
 use crate::module::{DisplayExpr, DotExpr};
-use crate::{DataId, FunctionId, GlobalId, MemoryId, TableId, TypeId, ValType};
+use crate::{DataId, ElementId, FunctionId, GlobalId, MemoryId, TableId, TagId, TypeId, ValType};
 use id_arena::Id;
 use std::fmt;
 use std::mem;
@@ -77,6 +80,9 @@ pub enum BlockKind {
     /// An `if` or `else` block.
     IfElse,
 
+    /// The body of a `try`, or one of its `catch` or `catch_all` handlers.
+    Try,
+
     /// The entry to a function.
     FunctionEntry,
 }
@@ -116,6 +122,9 @@ pub enum Expr {
         kind: BlockKind,
         /// The types of the expected values on the stack when entering this
         /// block.
+        ///
+        /// Inside the block, these values are the `BlockParam` expressions
+        /// for it.
         #[walrus(skip_visit)] // nothing to recurse
         params: Box<[ValType]>,
         /// The types of the resulting values added to the stack after this
@@ -126,6 +135,21 @@ pub enum Expr {
         exprs: Vec<ExprId>,
     },
 
+    /// One of the parameters of a block, which is already on the stack when
+    /// the block starts, and so isn't emitted as an instruction of its own.
+    ///
+    /// A block's parameters must be used in the order they're on the stack,
+    /// before any other value in the block.
+    #[walrus(display_extra = display_block_param)]
+    BlockParam {
+        /// The block whose parameter this is.
+        #[walrus(skip_visit)] // should have already been visited
+        block: BlockId,
+        /// Which of the block's parameters this is.
+        #[walrus(skip_visit)] // nothing to recurse
+        index: u32,
+    },
+
     /// `call`
     Call {
         /// The function being invoked.
@@ -135,6 +159,7 @@ pub enum Expr {
     },
 
     /// `call_indirect`
+    #[walrus(operand_order(args, func))]
     CallIndirect {
         /// The type signature of the function we're calling
         ty: TypeId,
@@ -146,6 +171,17 @@ pub enum Expr {
         args: Box<[ExprId]>,
     },
 
+    /// `call_ref`
+    #[walrus(operand_order(args, func))]
+    CallRef {
+        /// The type signature of the function we're calling
+        ty: TypeId,
+        /// The typed reference to the function we're invoking
+        func: ExprId,
+        /// The arguments to the function.
+        args: Box<[ExprId]>,
+    },
+
     /// `local.get n`
     LocalGet {
         /// The local being got.
@@ -210,7 +246,23 @@ pub enum Expr {
         expr: ExprId,
     },
 
+    /// Ternary operations, those requiring three operands
+    #[walrus(display_name = display_ternop_name, dot_name = dot_ternop_name)]
+    Ternop {
+        /// The operation being performed
+        #[walrus(skip_visit)]
+        op: TernaryOp,
+        /// The first operand
+        a: ExprId,
+        /// The second operand
+        b: ExprId,
+        /// The third operand
+        c: ExprId,
+    },
+
     /// `select`
+    #[walrus(display_extra = display_select)]
+    #[walrus(operand_order(consequent, alternative, condition))]
     Select {
         /// The condition.
         condition: ExprId,
@@ -220,6 +272,11 @@ pub enum Expr {
         /// The value returned when the condition is false. Evaluated regardless
         /// if the condition is false.
         alternative: ExprId,
+        /// The type of the value returned, for the typed `select` of the
+        /// reference types proposal. This is required when selecting between
+        /// references, and optional otherwise.
+        #[walrus(skip_visit)]
+        ty: Option<ValType>,
     },
 
     /// `unreachable`
@@ -237,6 +294,7 @@ pub enum Expr {
 
     /// `br_if`
     #[walrus(display_extra = display_br_if)]
+    #[walrus(operand_order(args, condition))]
     BrIf {
         /// The condition for when to branch.
         condition: ExprId,
@@ -259,6 +317,7 @@ pub enum Expr {
 
     /// `br_table`
     #[walrus(display_extra = display_br_table)]
+    #[walrus(operand_order(args, which))]
     BrTable {
         /// The table index of which block to branch to.
         which: ExprId,
@@ -273,6 +332,48 @@ pub enum Expr {
         args: Box<[ExprId]>,
     },
 
+    /// `try ... catch ... catch_all ... end`, or `try ... delegate`, from
+    /// the exception handling proposal.
+    ///
+    /// Branching to the body or to a handler branches to the end of the whole
+    /// `try`, like the arms of an `if`.
+    #[walrus(display_extra = display_try)]
+    Try {
+        /// The block which may throw.
+        body: BlockId,
+        /// The tags caught by the `catch` handlers, in order, one per
+        /// handler in `catches`.
+        catch_tags: Box<[TagId]>,
+        /// The `catch` handlers, each of which takes the values thrown along
+        /// with its tag as parameters.
+        catches: Box<[BlockId]>,
+        /// The `catch_all` handler, which takes no parameters.
+        catch_all: Option<BlockId>,
+        /// Instead of handling exceptions, pass them on to the handlers of
+        /// the `try` ending with this block, or to the caller if this is the
+        /// function's entry block.
+        ///
+        /// A `try` which delegates mustn't have any handlers.
+        #[walrus(skip_visit)] // should have already been visited
+        delegate: Option<BlockId>,
+    },
+
+    /// `throw`
+    Throw {
+        /// The tag of the exception being thrown.
+        tag: TagId,
+        /// The values thrown along with the tag.
+        args: Box<[ExprId]>,
+    },
+
+    /// `rethrow`
+    #[walrus(display_extra = display_rethrow)]
+    Rethrow {
+        /// The `catch` or `catch_all` handler whose exception is rethrown.
+        #[walrus(skip_visit)] // should have already been visited
+        block: BlockId,
+    },
+
     /// `drop`
     Drop {
         /// The expression to be evaluated and results ignored.
@@ -483,7 +584,8 @@ pub enum Expr {
     ///   (call $f))
     /// ```
     WithSideEffects {
-        /// The stack-neutral, side-effecting operations before `value`
+        /// The stack-neutral, side-effecting operations before `value`, or
+        /// the operands it takes if it's a block with parameters
         before: Vec<ExprId>,
         /// The value.
         value: ExprId,
@@ -510,6 +612,7 @@ pub enum Expr {
     },
 
     /// table.grow
+    #[walrus(operand_order(value, amount))]
     TableGrow {
         /// The table we're growing
         table: TableId,
@@ -525,8 +628,59 @@ pub enum Expr {
         table: TableId,
     },
 
+    /// table.fill
+    TableFill {
+        /// The table we're filling
+        table: TableId,
+        /// The first slot to fill
+        start: ExprId,
+        /// The reference to store in every filled slot
+        value: ExprId,
+        /// The number of slots to fill
+        len: ExprId,
+    },
+
+    /// table.init
+    TableInit {
+        /// The table we're initializing
+        table: TableId,
+        /// The element segment to copy in
+        elem: ElementId,
+        /// The first slot of the table to write
+        dst: ExprId,
+        /// The first member of the segment to copy
+        src: ExprId,
+        /// The number of slots to copy
+        len: ExprId,
+    },
+
+    /// elem.drop
+    ElemDrop {
+        /// The element segment to drop
+        elem: ElementId,
+    },
+
+    /// table.copy
+    TableCopy {
+        /// The source table
+        src: TableId,
+        /// The destination table
+        dst: TableId,
+        /// The first slot to write in the destination table
+        dst_offset: ExprId,
+        /// The first slot to read in the source table
+        src_offset: ExprId,
+        /// The number of slots to copy
+        len: ExprId,
+    },
+
     /// ref.null
-    RefNull {},
+    #[walrus(display_extra = display_ref_null)]
+    RefNull {
+        /// The type of null reference produced
+        #[walrus(skip_visit)] // nothing to recurse
+        ty: RefType,
+    },
 
     /// ref.is_null
     RefIsNull {
@@ -534,7 +688,20 @@ pub enum Expr {
         value: ExprId,
     },
 
+    /// ref.as_non_null
+    RefAsNonNull {
+        /// The reference that traps if it's null
+        value: ExprId,
+    },
+
+    /// ref.func
+    RefFunc {
+        /// The function that we're referencing
+        func: FunctionId,
+    },
+
     /// `v128.bitselect`
+    #[walrus(operand_order(v1, v2, mask))]
     V128Bitselect {
         /// The bit mask selecting bits from the two operands.
         mask: ExprId,
@@ -555,6 +722,106 @@ pub enum Expr {
         /// The second 16 bytes to be indxed (with indices 16..31)
         hi: ExprId,
     },
+
+    /// `v8x16.swizzle`
+    V128Swizzle {
+        /// The 16 bytes to be indexed
+        lanes: ExprId,
+        /// The index into `lanes` of each byte of the result, where an index
+        /// out of range selects zero
+        indices: ExprId,
+    },
+
+    /// Instructions walrus doesn't understand, such as those of the GC
+    /// proposal, passed through as they were encoded.
+    ///
+    /// These are only parsed with `ModuleConfig::allow_gc_types`, and then
+    /// make up the whole body of their function, along with its locals
+    /// declarations. See `LocalFunction::is_raw`.
+    Raw {
+        /// The function's locals declarations.
+        #[walrus(skip_visit)] // only refers to the ids below
+        locals: RawCode,
+        /// The function's instructions, without the final `end`.
+        #[walrus(skip_visit)] // only refers to the ids below
+        code: RawCode,
+        /// The functions used.
+        funcs: Box<[FunctionId]>,
+        /// The globals used.
+        globals: Box<[GlobalId]>,
+        /// The tables used.
+        tables: Box<[TableId]>,
+        /// The memories used.
+        memories: Box<[MemoryId]>,
+        /// The types used.
+        types: Box<[TypeId]>,
+        /// The passive data segments used.
+        data: Box<[DataId]>,
+        /// The element segments used.
+        elements: Box<[ElementId]>,
+        /// The exception tags used.
+        tags: Box<[TagId]>,
+    },
+}
+
+/// Encoded instructions or declarations in a `Raw` expression.
+///
+/// The bytes are kept as they were, except for the indices of the module's
+/// items, which are kept as ids in the `Raw` expression so that they can be
+/// emitted with the items' new indices.
+#[derive(Clone, Debug, Default)]
+pub struct RawCode {
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) holes: Vec<RawHole>,
+}
+
+impl RawCode {
+    /// The number of bytes this code was encoded in.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Is this code empty?
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+/// An index in a `RawCode`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RawHole {
+    /// Where the index was encoded in the bytes, which are emitted unchanged
+    /// if the index is the same.
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    /// The index it was encoded with.
+    pub(crate) original: u32,
+    /// The kind of item, and how the index is encoded.
+    pub(crate) kind: RawIndex,
+    /// The position of the item's id in the `Raw` expression's list of
+    /// that kind of item.
+    pub(crate) item: usize,
+}
+
+/// The kinds of indices in a `RawCode`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum RawIndex {
+    Func,
+    Global,
+    Table,
+    Memory,
+    Type,
+    Data,
+    Element,
+    Tag,
+    /// A type index as a heap type or block type, encoded as a signed LEB.
+    HeapType,
+    /// A memory access's alignment flags and memory index, where the index
+    /// is left out for memory 0 unless it was `explicit` in the input.
+    MemArg {
+        align: u32,
+        explicit: bool,
+    },
 }
 
 /// Argument in `V128Shuffle` of lane indices to select
@@ -576,6 +843,32 @@ pub enum Value {
 }
 
 impl Value {
+    /// The type of this constant.
+    pub fn ty(&self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+            Value::V128(_) => ValType::V128,
+        }
+    }
+
+    /// A `v128` constant made of the given bytes, in the order they're stored
+    /// in memory, so that `bytes[0]` is lane 0 when viewed as an `i8x16`.
+    pub fn v128_from_bytes(bytes: [u8; 16]) -> Value {
+        Value::V128(u128::from_le_bytes(bytes))
+    }
+
+    /// A `v128` constant made of the given lanes, when viewed as an `i32x4`.
+    pub fn v128_from_u32_lanes(lanes: [u32; 4]) -> Value {
+        let mut n = 0;
+        for (i, lane) in lanes.iter().enumerate() {
+            n |= u128::from(*lane) << (i * 32);
+        }
+        Value::V128(n)
+    }
+
     pub(crate) fn emit(&self, encoder: &mut Encoder) {
         match *self {
             Value::I32(n) => {
@@ -595,7 +888,7 @@ impl Value {
                 encoder.f64(n);
             }
             Value::V128(n) => {
-                encoder.raw(&[0xfd, 0x02]); // v128.const
+                encoder.raw(&[0xfd, 0x0c]); // v128.const
                 for i in 0..16 {
                     encoder.byte((n >> (i * 8)) as u8);
                 }
@@ -618,7 +911,7 @@ impl fmt::Display for Value {
 
 /// Possible binary operations in wasm
 #[allow(missing_docs)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     I32Eq,
     I32Ne,
@@ -761,6 +1054,7 @@ pub enum BinaryOp {
     V128And,
     V128Or,
     V128Xor,
+    V128Andnot,
 
     I8x16Shl,
     I8x16ShrS,
@@ -771,7 +1065,11 @@ pub enum BinaryOp {
     I8x16Sub,
     I8x16SubSaturateS,
     I8x16SubSaturateU,
-    I8x16Mul,
+    I8x16MinS,
+    I8x16MinU,
+    I8x16MaxS,
+    I8x16MaxU,
+    I8x16AvgrU,
     I16x8Shl,
     I16x8ShrS,
     I16x8ShrU,
@@ -782,18 +1080,34 @@ pub enum BinaryOp {
     I16x8SubSaturateS,
     I16x8SubSaturateU,
     I16x8Mul,
+    I16x8Q15MulrSatS,
+    I16x8MinS,
+    I16x8MinU,
+    I16x8MaxS,
+    I16x8MaxU,
+    I16x8AvgrU,
     I32x4Shl,
     I32x4ShrS,
     I32x4ShrU,
     I32x4Add,
     I32x4Sub,
     I32x4Mul,
+    I32x4MinS,
+    I32x4MinU,
+    I32x4MaxS,
+    I32x4MaxU,
+    I32x4DotI16x8S,
     I64x2Shl,
     I64x2ShrS,
     I64x2ShrU,
     I64x2Add,
     I64x2Sub,
 
+    I8x16NarrowI16x8S,
+    I8x16NarrowI16x8U,
+    I16x8NarrowI32x4S,
+    I16x8NarrowI32x4U,
+
     F32x4Add,
     F32x4Sub,
     F32x4Mul,
@@ -806,11 +1120,17 @@ pub enum BinaryOp {
     F64x2Div,
     F64x2Min,
     F64x2Max,
+
+    I8x16RelaxedSwizzle,
+    F32x4RelaxedMin,
+    F32x4RelaxedMax,
+    F64x2RelaxedMin,
+    F64x2RelaxedMax,
 }
 
 /// Possible unary operations in wasm
 #[allow(missing_docs)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum UnaryOp {
     I32Eqz,
     I32Clz,
@@ -888,18 +1208,20 @@ pub enum UnaryOp {
     F64x2ExtractLane { idx: u8 },
 
     V128Not,
+    V128AnyTrue,
 
     I8x16Neg,
-    I8x16AnyTrue,
+    I8x16Abs,
+    I8x16Popcnt,
     I8x16AllTrue,
     I16x8Neg,
-    I16x8AnyTrue,
+    I16x8Abs,
     I16x8AllTrue,
     I32x4Neg,
-    I32x4AnyTrue,
+    I32x4Abs,
     I32x4AllTrue,
     I64x2Neg,
-    I64x2AnyTrue,
+    I64x2Abs,
     I64x2AllTrue,
 
     F32x4Abs,
@@ -911,12 +1233,22 @@ pub enum UnaryOp {
 
     I32x4TruncSF32x4Sat,
     I32x4TruncUF32x4Sat,
-    I64x2TruncSF64x2Sat,
-    I64x2TruncUF64x2Sat,
     F32x4ConvertSI32x4,
     F32x4ConvertUI32x4,
-    F64x2ConvertSI64x2,
-    F64x2ConvertUI64x2,
+
+    I16x8WidenLowI8x16S,
+    I16x8WidenHighI8x16S,
+    I16x8WidenLowI8x16U,
+    I16x8WidenHighI8x16U,
+    I32x4WidenLowI16x8S,
+    I32x4WidenHighI16x8S,
+    I32x4WidenLowI16x8U,
+    I32x4WidenHighI16x8U,
+
+    I32x4RelaxedTruncSF32x4,
+    I32x4RelaxedTruncUF32x4,
+    I32x4RelaxedTruncSF64x2Zero,
+    I32x4RelaxedTruncUF64x2Zero,
 
     I32TruncSSatF32,
     I32TruncUSatF32,
@@ -928,6 +1260,91 @@ pub enum UnaryOp {
     I64TruncUSatF64,
 }
 
+/// Possible ternary operations in wasm
+///
+/// These are all from the relaxed SIMD proposal, where `madd` computes
+/// `a * b + c` and `nmadd` computes `-(a * b) + c`, either with or without
+/// rounding the product.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TernaryOp {
+    F32x4RelaxedMadd,
+    F32x4RelaxedNmadd,
+    F64x2RelaxedMadd,
+    F64x2RelaxedNmadd,
+}
+
+impl BinaryOp {
+    /// The type of the value this operation produces.
+    pub fn result_type(&self) -> ValType {
+        use self::BinaryOp::*;
+        match self {
+            I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS
+            | I32GeU | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU
+            | I64GeS | I64GeU | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne
+            | F64Lt | F64Gt | F64Le | F64Ge => ValType::I32,
+
+            I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU | I32And | I32Or
+            | I32Xor | I32Shl | I32ShrS | I32ShrU | I32Rotl | I32Rotr => ValType::I32,
+
+            I64Add | I64Sub | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or
+            | I64Xor | I64Shl | I64ShrS | I64ShrU | I64Rotl | I64Rotr => ValType::I64,
+
+            F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => ValType::F32,
+
+            F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => ValType::F64,
+
+            // Everything else is a SIMD operation.
+            _ => ValType::V128,
+        }
+    }
+}
+
+impl UnaryOp {
+    /// The type of the value this operation produces.
+    pub fn result_type(&self) -> ValType {
+        use self::UnaryOp::*;
+        match self {
+            I32Eqz | I32Clz | I32Ctz | I32Popcnt | I64Eqz => ValType::I32,
+            I64Clz | I64Ctz | I64Popcnt => ValType::I64,
+
+            F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => ValType::F32,
+            F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => ValType::F64,
+
+            I32WrapI64 | I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64
+            | I32ReinterpretF32 | I32Extend8S | I32Extend16S | I32TruncSSatF32
+            | I32TruncUSatF32 | I32TruncSSatF64 | I32TruncUSatF64 => ValType::I32,
+
+            I64ExtendSI32 | I64ExtendUI32 | I64TruncSF32 | I64TruncUF32 | I64TruncSF64
+            | I64TruncUF64 | I64ReinterpretF64 | I64Extend8S | I64Extend16S | I64Extend32S
+            | I64TruncSSatF32 | I64TruncUSatF32 | I64TruncSSatF64 | I64TruncUSatF64 => ValType::I64,
+
+            F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 | F32DemoteF64
+            | F32ReinterpretI32 => ValType::F32,
+
+            F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 | F64PromoteF32
+            | F64ReinterpretI64 => ValType::F64,
+
+            I8x16ExtractLaneS { .. }
+            | I8x16ExtractLaneU { .. }
+            | I16x8ExtractLaneS { .. }
+            | I16x8ExtractLaneU { .. }
+            | I32x4ExtractLane { .. }
+            | V128AnyTrue
+            | I8x16AllTrue
+            | I16x8AllTrue
+            | I32x4AllTrue
+            | I64x2AllTrue => ValType::I32,
+            I64x2ExtractLane { .. } => ValType::I64,
+            F32x4ExtractLane { .. } => ValType::F32,
+            F64x2ExtractLane { .. } => ValType::F64,
+
+            // Everything else produces a vector.
+            _ => ValType::V128,
+        }
+    }
+}
+
 /// The different kinds of load instructions that are part of a `Load` IR node
 #[derive(Debug, Copy, Clone)]
 #[allow(missing_docs)]
@@ -970,6 +1387,18 @@ impl LoadKind {
         }
     }
 
+    /// Returns the type of the value loaded
+    pub fn result_type(&self) -> ValType {
+        use self::LoadKind::*;
+        match self {
+            I32 { .. } | I32_8 { .. } | I32_16 { .. } => ValType::I32,
+            I64 { .. } | I64_8 { .. } | I64_16 { .. } | I64_32 { .. } => ValType::I64,
+            F32 => ValType::F32,
+            F64 => ValType::F64,
+            V128 => ValType::V128,
+        }
+    }
+
     /// Returns if this is an atomic load
     pub fn atomic(&self) -> bool {
         use self::LoadKind::*;
@@ -1051,6 +1480,87 @@ pub struct MemArg {
     pub offset: u32,
 }
 
+/// The kind of memory operation a `MemArg` belongs to, as given to
+/// `LocalFunction::rewrite_memargs`.
+#[derive(Debug, Copy, Clone)]
+pub enum LoadStoreKind {
+    /// A `Load`.
+    Load(LoadKind),
+    /// A `Store`.
+    Store(StoreKind),
+    /// An `AtomicRmw`.
+    AtomicRmw {
+        /// The atomic operation being performed.
+        op: AtomicOp,
+        /// The width of the operation.
+        width: AtomicWidth,
+    },
+    /// A `Cmpxchg`.
+    Cmpxchg {
+        /// The width of the operation.
+        width: AtomicWidth,
+    },
+    /// An `AtomicNotify`.
+    AtomicNotify,
+    /// An `AtomicWait`.
+    AtomicWait {
+        /// Whether this is an `i64` wait, rather than an `i32` wait.
+        sixty_four: bool,
+    },
+}
+
+/// How `LocalFunction::rewrite_memargs` should change a memory operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rewrite {
+    /// Leave the operation as it is, apart from any changes already made to
+    /// its `MemArg`.
+    Keep,
+    /// Add a constant to the operation's offset.
+    ///
+    /// If the offset would overflow then the constant is added to the address
+    /// operand instead, as with `AddToAddress`.
+    AddToOffset(u32),
+    /// Add a constant to the operation's address operand, either by folding
+    /// it into the operand if that's a constant or with an `i32.add`.
+    AddToAddress(u32),
+}
+
+/// The type of a reference, as given to `ref.null`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RefType {
+    /// A reference to a function, `funcref`.
+    Funcref,
+    /// An opaque reference from the host, `externref` (previously `anyref`).
+    Externref,
+}
+
+impl RefType {
+    /// The value type of references of this type.
+    pub fn val_type(&self) -> ValType {
+        match self {
+            RefType::Funcref => ValType::Funcref,
+            RefType::Externref => ValType::Externref,
+        }
+    }
+
+    pub(crate) fn emit(&self, encoder: &mut Encoder) {
+        match self {
+            RefType::Funcref => encoder.byte(0x70),
+            RefType::Externref => encoder.byte(0x6f),
+        }
+    }
+}
+
+/// Defaults to `funcref`.
+///
+/// This exists as a migration path for code written before `ref.null` had a
+/// type, and new code should name the reference type explicitly.
+impl Default for RefType {
+    fn default() -> RefType {
+        RefType::Funcref
+    }
+}
+
 /// The different kinds of atomic rmw operations
 #[derive(Debug, Copy, Clone)]
 #[allow(missing_docs)]
@@ -1077,6 +1587,15 @@ pub enum AtomicWidth {
 }
 
 impl AtomicWidth {
+    /// Returns the type of the value this atomic operation works with
+    pub fn result_type(&self) -> ValType {
+        use self::AtomicWidth::*;
+        match self {
+            I32 | I32_8 | I32_16 => ValType::I32,
+            I64 | I64_8 | I64_16 | I64_32 => ValType::I64,
+        }
+    }
+
     /// Returns the size, in bytes, of this atomic operation
     pub fn bytes(&self) -> u32 {
         use self::AtomicWidth::*;
@@ -1098,11 +1617,18 @@ impl Expr {
     /// (`i32.add`, etc...).
     pub fn following_instructions_are_unreachable(&self) -> bool {
         match *self {
-            Expr::Unreachable(..) | Expr::Br(..) | Expr::BrTable(..) | Expr::Return(..) => true,
+            Expr::Unreachable(..)
+            | Expr::Br(..)
+            | Expr::BrTable(..)
+            | Expr::Return(..)
+            | Expr::Throw(..)
+            | Expr::Rethrow(..) => true,
 
             // No `_` arm to make sure that we properly update this function as
             // we add support for new instructions.
             Expr::Block(..)
+            | Expr::BlockParam(..)
+            | Expr::Try(..)
             | Expr::Call(..)
             | Expr::LocalGet(..)
             | Expr::LocalSet(..)
@@ -1112,6 +1638,7 @@ impl Expr {
             | Expr::Const(..)
             | Expr::Binop(..)
             | Expr::Unop(..)
+            | Expr::Ternop(..)
             | Expr::Select(..)
             | Expr::BrIf(..)
             | Expr::IfElse(..)
@@ -1122,6 +1649,7 @@ impl Expr {
             | Expr::MemoryCopy(..)
             | Expr::MemoryFill(..)
             | Expr::CallIndirect(..)
+            | Expr::CallRef(..)
             | Expr::Load(..)
             | Expr::Store(..)
             | Expr::AtomicRmw(..)
@@ -1133,10 +1661,18 @@ impl Expr {
             | Expr::TableSet(..)
             | Expr::TableGrow(..)
             | Expr::TableSize(..)
+            | Expr::TableFill(..)
+            | Expr::TableInit(..)
+            | Expr::ElemDrop(..)
+            | Expr::TableCopy(..)
             | Expr::RefNull(..)
             | Expr::RefIsNull(..)
+            | Expr::RefAsNonNull(..)
+            | Expr::RefFunc(..)
             | Expr::V128Bitselect(..)
             | Expr::V128Shuffle(..)
+            | Expr::V128Swizzle(..)
+            | Expr::Raw(..)
             | Expr::Drop(..) => false,
         }
     }
@@ -1153,6 +1689,15 @@ impl Block {
             exprs,
         }
     }
+
+    /// Does this block's type have to be encoded as an index into the type
+    /// section?
+    ///
+    /// This is the case for blocks that take parameters or produce more than
+    /// one result.
+    pub(crate) fn needs_type_index(&self) -> bool {
+        self.kind != BlockKind::FunctionEntry && (!self.params.is_empty() || self.results.len() > 1)
+    }
 }
 
 /// Anything that can be visited by a `Visitor`.
@@ -1176,7 +1721,7 @@ impl<'expr> Visit<'expr> for ExprId {
     where
         V: Visitor<'expr>,
     {
-        visitor.visit_expr(&visitor.local_function().get(*self))
+        visitor.visit_expr(visitor.local_function().get(*self))
     }
 }
 
@@ -1209,6 +1754,7 @@ fn dot_block_name(block: &Block, out: &mut DotExpr<'_, '_>) {
     match block.kind {
         BlockKind::Loop => out.out.push_str("loop"),
         BlockKind::IfElse => out.out.push_str("if_else"),
+        BlockKind::Try => out.out.push_str("try"),
         BlockKind::FunctionEntry => out.out.push_str("entry"),
         BlockKind::Block => out.out.push_str("block"),
     }
@@ -1219,6 +1765,26 @@ fn display_br(e: &Br, out: &mut DisplayExpr) {
         .push_str(&format!(" (;e{};)", ExprId::from(e.block).index()))
 }
 
+fn display_block_param(e: &BlockParam, out: &mut DisplayExpr) {
+    out.f.push_str(&format!(
+        " {} (;e{};)",
+        e.index,
+        ExprId::from(e.block).index()
+    ))
+}
+
+fn display_try(e: &Try, out: &mut DisplayExpr) {
+    if let Some(block) = e.delegate {
+        out.f
+            .push_str(&format!(" (delegate (;e{};))", ExprId::from(block).index()))
+    }
+}
+
+fn display_rethrow(e: &Rethrow, out: &mut DisplayExpr) {
+    out.f
+        .push_str(&format!(" (;e{};)", ExprId::from(e.block).index()))
+}
+
 fn display_br_if(e: &BrIf, out: &mut DisplayExpr) {
     out.f
         .push_str(&format!(" (;e{};)", ExprId::from(e.block).index()))
@@ -1238,6 +1804,19 @@ fn display_br_table(e: &BrTable, out: &mut DisplayExpr) {
     ))
 }
 
+fn display_select(e: &Select, out: &mut DisplayExpr) {
+    if let Some(ty) = e.ty {
+        out.f.push_str(&format!(" (result {})", ty));
+    }
+}
+
+fn display_ref_null(e: &RefNull, out: &mut DisplayExpr) {
+    match e.ty {
+        RefType::Funcref => out.f.push_str(" func"),
+        RefType::Externref => out.f.push_str(" extern"),
+    }
+}
+
 fn display_binop_name(e: &Binop, out: &mut DisplayExpr) {
     out.f.push_str(&format!("{:?}", e.op))
 }
@@ -1253,3 +1832,11 @@ fn display_unop_name(e: &Unop, out: &mut DisplayExpr) {
 fn dot_unop_name(e: &Unop, out: &mut DotExpr<'_, '_>) {
     out.out.push_str(&format!("{:?}", e.op))
 }
+
+fn display_ternop_name(e: &Ternop, out: &mut DisplayExpr) {
+    out.f.push_str(&format!("{:?}", e.op))
+}
+
+fn dot_ternop_name(e: &Ternop, out: &mut DotExpr<'_, '_>) {
+    out.out.push_str(&format!("{:?}", e.op))
+}
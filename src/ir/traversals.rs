@@ -0,0 +1,50 @@
+//! Drivers for walking a function's expression tree with a `Visitor` or
+//! `VisitorMut`.
+//!
+//! The visitor traits do the recursion themselves: the default `visit_*`
+//! method for every kind of expression visits that expression's operands, in
+//! the order they are evaluated. Overriding a method and calling
+//! `expr.visit(self)` (or `expr.visit_mut(self)`) continues on to the
+//! operands, while leaving that call out skips them.
+//!
+//! When new kinds of expressions are added to the IR, they come with a
+//! default `visit_*` method that only recurses into their operands, so
+//! visitors written against the public traits keep compiling and simply
+//! don't do anything special for the new expressions.
+
+use super::{ExprId, Visit, VisitMut, Visitor, VisitorMut};
+use crate::LocalFunction;
+
+/// Visit the expression tree of `func` rooted at `start`.
+///
+/// `start` itself is handed straight to `visitor.visit_expr`, without going
+/// through `visitor.visit_expr_id`, so a visitor which needs the id of the
+/// root has to handle `start` itself. Every expression reachable from it is
+/// then visited through `visit_expr_id`, with its operands in evaluation
+/// order, i.e. the order in which they would be emitted. A `visit_*` method
+/// that does its work after calling `expr.visit(self)` therefore sees
+/// expressions in the same order as a wasm engine executing them.
+///
+/// `func` must be the function returned by `visitor.local_function()`.
+pub fn dfs_in_order<'expr, V>(visitor: &mut V, func: &'expr LocalFunction, start: ExprId)
+where
+    V: Visitor<'expr>,
+{
+    debug_assert!(std::ptr::eq(visitor.local_function(), func));
+    start.visit(visitor);
+}
+
+/// Visit the expression tree rooted at `start` mutably.
+///
+/// Each expression is handed to its `visit_*_mut` method before its operands
+/// are visited, so a visitor can rewrite an expression and then have the
+/// traversal continue into the operands of the rewritten expression. The
+/// function being visited is the one returned by
+/// `visitor.local_function_mut()`.
+pub fn dfs_pre_order_mut<V>(visitor: &mut V, start: ExprId)
+where
+    V: VisitorMut,
+{
+    let mut start = start;
+    start.visit_mut(visitor);
+}
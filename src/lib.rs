@@ -3,10 +3,12 @@
 #![deny(missing_debug_implementations)]
 #![deny(missing_docs)]
 
+pub mod analysis;
 mod arena_set;
+mod decode;
 pub mod dot;
 mod emit;
-mod encode;
+pub mod encode;
 mod error;
 mod function_builder;
 mod init_expr;
@@ -18,10 +20,12 @@ pub mod passes;
 mod tombstone_arena;
 mod ty;
 
-pub use crate::emit::IdsToIndices;
-pub use crate::error::{ErrorKind, Result};
+pub use crate::emit::{IdsToIndices, Section};
+pub use crate::error::{AmbiguousName, DisabledFeature, ErrorKind, FunctionInUse, Result};
+pub use crate::error::{MalformedBodyKind, MalformedFunctionBody, NameTaken};
+pub use crate::error::{UnstubbableImport, UnsupportedType, WrongExportKind};
 pub use crate::function_builder::{BlockBuilder, FunctionBuilder};
-pub use crate::init_expr::InitExpr;
+pub use crate::init_expr::{InitExpr, InitOp};
 pub use crate::ir::{Local, LocalId};
 pub use crate::module::*;
 pub use crate::parse::IndicesToIds;
@@ -0,0 +1,135 @@
+//! Embedding an identifier for a build of a module in the `build_id` custom
+//! section.
+//!
+//! The payload of a `build_id` section is a byte saying where the id came
+//! from, followed by the id itself as a LEB128-encoded length and that many
+//! bytes:
+//!
+//! * `0x00`: the id was provided by whoever built the module.
+//! * `0x01`: the id is the 128-bit FNV-1a hash of the module without its
+//!   `build_id` section, in big-endian order.
+
+use crate::emit::IdsToIndices;
+use crate::encode::{read_leb128_u32, Encoder};
+use crate::error::Result;
+use crate::module::custom::{CustomSection, RawCustomSection};
+use crate::module::Module;
+use std::borrow::Cow;
+
+/// The name of the custom section holding a module's build id.
+const NAME: &str = "build_id";
+
+const PROVIDED: u8 = 0x00;
+const CONTENT_HASH: u8 = 0x01;
+
+/// Where the build id added with `Module::add_build_id` comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildIdStyle {
+    /// Use these bytes as the build id.
+    Bytes(Vec<u8>),
+    /// Use a 128-bit hash of the module's contents, not counting the
+    /// `build_id` section itself, as the build id.
+    ContentHash,
+}
+
+#[derive(Debug)]
+struct BuildId {
+    style: u8,
+    id: Vec<u8>,
+}
+
+impl CustomSection for BuildId {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.byte(self.style);
+        encoder.bytes(&self.id);
+        data.into()
+    }
+}
+
+impl Module {
+    /// Add a build id to this module, in a `build_id` custom section,
+    /// replacing any build id it already has.
+    ///
+    /// A content hash is computed by emitting this module without a build id,
+    /// so it's only accurate as long as the module isn't changed afterwards:
+    /// this should be the last thing done to a module before it's emitted.
+    /// The same module always gets the same hash, and any change to what it
+    /// emits changes the hash.
+    pub fn add_build_id(&mut self, style: BuildIdStyle) -> Result<()> {
+        self.remove_build_id();
+        let section = match style {
+            BuildIdStyle::Bytes(id) => BuildId {
+                style: PROVIDED,
+                id,
+            },
+            BuildIdStyle::ContentHash => BuildId {
+                style: CONTENT_HASH,
+                id: fnv1a_128(&self.emit_wasm()?).to_vec(),
+            },
+        };
+        self.customs.add(section);
+        Ok(())
+    }
+
+    /// Get this module's build id, if it has one.
+    ///
+    /// This is also found in the `build_id` sections of parsed modules, as
+    /// long as it's well-formed.
+    pub fn build_id(&self) -> Option<&[u8]> {
+        for (_, section) in self.customs.iter() {
+            if section.name() != NAME {
+                continue;
+            }
+            if let Some(section) = section.as_any().downcast_ref::<BuildId>() {
+                return Some(&section.id);
+            }
+            if let Some(raw) = section.as_any().downcast_ref::<RawCustomSection>() {
+                return parse(&raw.data);
+            }
+        }
+        None
+    }
+
+    /// Remove this module's build id, if it has one.
+    pub fn remove_build_id(&mut self) {
+        while self.customs.delete_typed::<BuildId>().is_some() {}
+        while self.customs.remove_raw(NAME).is_some() {}
+    }
+}
+
+/// Get the id out of the payload of a `build_id` section.
+fn parse(data: &[u8]) -> Option<&[u8]> {
+    match data.first() {
+        Some(&PROVIDED) | Some(&CONTENT_HASH) => {}
+        _ => return None,
+    }
+    let (len, size) = read_leb128_u32(&data[1..]).ok()?;
+    let start = 1 + size;
+    let end = start.checked_add(len as usize)?;
+    if end != data.len() {
+        return None;
+    }
+    Some(&data[start..end])
+}
+
+fn fnv1a_128(bytes: &[u8]) -> [u8; 16] {
+    const OFFSET_BASIS: u128 = 0x6c62272e_07bb0142_62b82175_6295c58d;
+    const PRIME: u128 = 0x00000000_01000000_00000000_0000013b;
+
+    let mut hash = OFFSET_BASIS;
+    for byte in bytes {
+        hash ^= u128::from(*byte);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    let mut ret = [0; 16];
+    for (i, byte) in ret.iter_mut().enumerate() {
+        *byte = (hash >> (8 * (15 - i))) as u8;
+    }
+    ret
+}
@@ -1,8 +1,10 @@
 use crate::error::Result;
-use crate::module::Module;
+use crate::module::progress::{Phase, ProgressFn};
+use crate::module::{Module, SharedParseContext, WasmFeatures};
 use crate::parse::IndicesToIds;
 use std::fmt;
 use std::path::Path;
+use std::sync::Arc;
 
 /// Configuration for a `Module` which currently affects parsing.
 #[derive(Default)]
@@ -13,10 +15,25 @@ pub struct ModuleConfig {
     pub(crate) skip_strict_validate: bool,
     pub(crate) skip_producers_section: bool,
     pub(crate) skip_name_section: bool,
-    pub(crate) on_parse:
-        Option<Box<dyn Fn(&mut Module, &IndicesToIds) -> Result<()> + Sync + Send + 'static>>,
+    pub(crate) skip_ref_func_declarations: bool,
+    pub(crate) preserve_original_bodies: bool,
+    pub(crate) preserve_declared_locals: bool,
+    pub(crate) preserve_function_order: bool,
+    pub(crate) intern_leaf_exprs: bool,
+    pub(crate) retain_index_mapping: bool,
+    pub(crate) retain_raw_custom_sections: bool,
+    pub(crate) wasm_features: Option<WasmFeatures>,
+    pub(crate) relaxed_simd: bool,
+    pub(crate) allow_unshared_atomics: bool,
+    pub(crate) force_data_count: Option<bool>,
+    pub(crate) allow_gc_types: bool,
+    pub(crate) shared_context: Option<SharedParseContext>,
+    pub(crate) on_progress: Option<ProgressFn>,
+    pub(crate) on_parse: Option<OnParseFn>,
 }
 
+type OnParseFn = Box<dyn Fn(&mut Module, &IndicesToIds) -> Result<()> + Sync + Send + 'static>;
+
 impl Clone for ModuleConfig {
     fn clone(&self) -> ModuleConfig {
         ModuleConfig {
@@ -28,6 +45,20 @@ impl Clone for ModuleConfig {
             skip_strict_validate: self.skip_strict_validate,
             skip_producers_section: self.skip_producers_section,
             skip_name_section: self.skip_name_section,
+            skip_ref_func_declarations: self.skip_ref_func_declarations,
+            preserve_original_bodies: self.preserve_original_bodies,
+            preserve_declared_locals: self.preserve_declared_locals,
+            preserve_function_order: self.preserve_function_order,
+            intern_leaf_exprs: self.intern_leaf_exprs,
+            retain_index_mapping: self.retain_index_mapping,
+            retain_raw_custom_sections: self.retain_raw_custom_sections,
+            wasm_features: self.wasm_features,
+            relaxed_simd: self.relaxed_simd,
+            allow_unshared_atomics: self.allow_unshared_atomics,
+            force_data_count: self.force_data_count,
+            allow_gc_types: self.allow_gc_types,
+            shared_context: self.shared_context.clone(),
+            on_progress: self.on_progress.clone(),
 
             // ... and this is left empty.
             on_parse: None,
@@ -46,6 +77,20 @@ impl fmt::Debug for ModuleConfig {
             ref skip_strict_validate,
             ref skip_producers_section,
             ref skip_name_section,
+            ref skip_ref_func_declarations,
+            ref preserve_original_bodies,
+            ref preserve_declared_locals,
+            ref preserve_function_order,
+            ref intern_leaf_exprs,
+            ref retain_index_mapping,
+            ref retain_raw_custom_sections,
+            ref wasm_features,
+            ref relaxed_simd,
+            ref allow_unshared_atomics,
+            ref force_data_count,
+            ref allow_gc_types,
+            ref shared_context,
+            ref on_progress,
             ref on_parse,
         } = self;
 
@@ -59,6 +104,20 @@ impl fmt::Debug for ModuleConfig {
             .field("skip_strict_validate", skip_strict_validate)
             .field("skip_producers_section", skip_producers_section)
             .field("skip_name_section", skip_name_section)
+            .field("skip_ref_func_declarations", skip_ref_func_declarations)
+            .field("preserve_original_bodies", preserve_original_bodies)
+            .field("preserve_declared_locals", preserve_declared_locals)
+            .field("preserve_function_order", preserve_function_order)
+            .field("intern_leaf_exprs", intern_leaf_exprs)
+            .field("retain_index_mapping", retain_index_mapping)
+            .field("retain_raw_custom_sections", retain_raw_custom_sections)
+            .field("wasm_features", wasm_features)
+            .field("relaxed_simd", relaxed_simd)
+            .field("allow_unshared_atomics", allow_unshared_atomics)
+            .field("force_data_count", force_data_count)
+            .field("allow_gc_types", allow_gc_types)
+            .field("shared_context", shared_context)
+            .field("on_progress", &on_progress.as_ref().map(|_| ".."))
             .field("on_parse", &on_parse.as_ref().map(|_| ".."))
             .finish()
     }
@@ -148,6 +207,224 @@ impl ModuleConfig {
         self
     }
 
+    /// Indicates whether the original encoded bodies of local functions are
+    /// kept around after parsing so that they can be re-emitted verbatim.
+    ///
+    /// When enabled, functions which haven't been mutably accessed since
+    /// parsing (via `ModuleFunctions::get_mut`, `iter_local_mut`,
+    /// `LocalFunction::builder_mut`, etc) have their original bytes spliced
+    /// into the emitted code section instead of being re-encoded, and local
+    /// functions are emitted in their original order. If any index assignment
+    /// differs from the original module (for example because imports were
+    /// added or removed, or types were renumbered) then every function is
+    /// re-encoded as usual.
+    ///
+    /// By default this flag is `false`.
+    pub fn preserve_original_bodies(&mut self, preserve: bool) -> &mut ModuleConfig {
+        self.preserve_original_bodies = preserve;
+        self
+    }
+
+    /// Indicates whether local functions re-declare all of the locals they
+    /// were originally declared with when emitted, even those which are no
+    /// longer used.
+    ///
+    /// When enabled, the locals declarations of each parsed function are
+    /// recorded and emitted again exactly as they appeared in the input, with
+    /// any new locals declared after them. This keeps the size of unmodified
+    /// locals declarations the same, for example for bodies that were
+    /// replaced with a bare `unreachable` by `wasm-snip`. This must be enabled
+    /// when the module is parsed.
+    ///
+    /// By default this flag is `false`, and unused locals are dropped.
+    pub fn preserve_declared_locals(&mut self, preserve: bool) -> &mut ModuleConfig {
+        self.preserve_declared_locals = preserve;
+        self
+    }
+
+    /// Indicates whether local functions are emitted in the order they were
+    /// parsed or added in, rather than from largest to smallest.
+    ///
+    /// Emitting larger functions first helps engines which compile functions
+    /// in parallel, but moves functions around whenever their sizes change.
+    /// When enabled, parsing and re-emitting a module keeps every function at
+    /// the same index, which makes emitted modules easier to diff, with new
+    /// functions placed after the existing ones.
+    ///
+    /// By default this flag is `false`.
+    pub fn preserve_function_order(&mut self, preserve: bool) -> &mut ModuleConfig {
+        self.preserve_function_order = preserve;
+        self
+    }
+
+    /// Sets a flag to whether functions referenced with `ref.func` are
+    /// declared automatically when emitting this module.
+    ///
+    /// Functions referenced by `ref.func` in function bodies must be declared
+    /// by appearing in an element segment or an export, or the module won't
+    /// validate. When enabled, every such function that isn't already
+    /// declared is put in an extra declared element segment at the end of the
+    /// element section, so passes can add `ref.func`s without having to
+    /// declare their targets themselves.
+    ///
+    /// By default this flag is `true`.
+    pub fn declare_ref_func_targets(&mut self, declare: bool) -> &mut ModuleConfig {
+        self.skip_ref_func_declarations = !declare;
+        self
+    }
+
+    /// Indicates whether identical constants, `local.get`s and `global.get`s
+    /// within each parsed function share a single expression.
+    ///
+    /// This makes the IR of large functions which repeat the same constants
+    /// much smaller, but mutating a shared expression in place changes all of
+    /// its uses. See `FunctionBuilder::set_interning` for details.
+    ///
+    /// By default this flag is `false`.
+    pub fn intern_leaf_exprs(&mut self, intern: bool) -> &mut ModuleConfig {
+        self.intern_leaf_exprs = intern;
+        self
+    }
+
+    /// Indicates whether the map from indices in the original wasm to walrus
+    /// IDs is kept on the parsed `Module`, available through
+    /// `Module::input_indices`.
+    ///
+    /// This is useful for translating raw indices found in external files,
+    /// such as symbol maps or profiles, after parsing has finished.
+    ///
+    /// By default this flag is `false`.
+    pub fn retain_index_mapping(&mut self, retain: bool) -> &mut ModuleConfig {
+        self.retain_index_mapping = retain;
+        self
+    }
+
+    /// Indicates whether the original payloads of custom sections are kept,
+    /// available through `ModuleCustomSections::raw_of`.
+    ///
+    /// Sections which walrus parses into typed form, such as the `name` and
+    /// `producers` sections, are regenerated from the module when it's
+    /// emitted, so the original bytes are useful for checking what walrus
+    /// understood of them, or for falling back to them.
+    ///
+    /// By default this flag is `false`.
+    pub fn retain_raw_custom_sections(&mut self, retain: bool) -> &mut ModuleConfig {
+        self.retain_raw_custom_sections = retain;
+        self
+    }
+
+    /// Restricts the WebAssembly features that parsed modules may use.
+    ///
+    /// Parsing a module with a function that uses an instruction from a
+    /// feature that isn't in `features` fails with a `DisabledFeature` error
+    /// naming the function and instruction. This is useful when targeting an
+    /// engine that doesn't support every feature walrus does.
+    ///
+    /// By default every feature is allowed.
+    pub fn wasm_features(&mut self, features: WasmFeatures) -> &mut ModuleConfig {
+        self.wasm_features = Some(features);
+        self
+    }
+
+    /// Sets a flag to whether the instructions of the relaxed SIMD proposal,
+    /// such as `f32x4.relaxed_madd`, are allowed.
+    ///
+    /// The results of these instructions can differ between engines, so
+    /// they're easy to use by accident and hard to notice. When disabled,
+    /// validating a module which uses any of them fails, including when it's
+    /// parsed with strict validation.
+    ///
+    /// By default this flag is `false`.
+    pub fn relaxed_simd(&mut self, enable: bool) -> &mut ModuleConfig {
+        self.relaxed_simd = enable;
+        self
+    }
+
+    /// Sets a flag to whether atomic instructions may access memories which
+    /// aren't shared.
+    ///
+    /// The threads proposal originally required a shared memory, but engines
+    /// now also allow atomics on unshared memories. When enabled, validation
+    /// only logs a warning for such an instruction instead of failing.
+    ///
+    /// By default this flag is `false`.
+    pub fn allow_unshared_atomics(&mut self, enable: bool) -> &mut ModuleConfig {
+        self.allow_unshared_atomics = enable;
+        self
+    }
+
+    /// Overrides whether emitted modules have a data count section.
+    ///
+    /// By default, which is `None`, a data count section is emitted when the
+    /// module has passive data segments, uses `memory.init` or `data.drop`,
+    /// or was parsed from a module which had one. `Some(true)` always emits
+    /// one, and `Some(false)` never does.
+    pub fn force_data_count(&mut self, force: Option<bool>) -> &mut ModuleConfig {
+        self.force_data_count = force;
+        self
+    }
+
+    /// Sets whether modules using the GC proposal's types and instructions
+    /// can be parsed, by passing them through without understanding them.
+    ///
+    /// A type section defining struct, array or recursive types, or subtypes,
+    /// is kept as it was, and its types are opaque to walrus unless they're
+    /// plain function types (see `Type::is_opaque`). Function bodies using
+    /// instructions or types walrus can't parse on its own are kept as raw
+    /// instructions, with the indices they use tracked so that the rest of
+    /// the module can still be changed (see `LocalFunction::is_raw`).
+    /// Deleting one of the passed through types, or adding expressions to a
+    /// raw body, makes emitting the module fail.
+    ///
+    /// Function bodies are decoded with the standard encoding of the
+    /// reference types proposal, rather than the pre-standard one walrus
+    /// otherwise parses, and those using it, or any SIMD instructions, are
+    /// kept raw too.
+    ///
+    /// Globals, tables and imports of GC types still can't be parsed.
+    ///
+    /// By default this flag is `false`.
+    pub fn allow_gc_types(&mut self, allow: bool) -> &mut ModuleConfig {
+        self.allow_gc_types = allow;
+        self
+    }
+
+    /// Shares immutable data, such as import and export names, between every
+    /// module parsed with a clone of `cx`.
+    ///
+    /// See `SharedParseContext` for what's shared. This doesn't change what
+    /// modules parse to or emit.
+    ///
+    /// By default modules don't share anything.
+    pub fn shared_context(&mut self, cx: &SharedParseContext) -> &mut ModuleConfig {
+        self.shared_context = Some(cx.clone());
+        self
+    }
+
+    /// Provide a function that is invoked with the progress of parsing and
+    /// emitting modules, such as to show a progress bar for large modules.
+    ///
+    /// The function is called with the current `Phase`, how far through the
+    /// phase parsing or emitting is, and the total amount of work in the
+    /// phase, at a coarse granularity: once per section, and once every few
+    /// hundred function bodies. It's never called concurrently, even when
+    /// function bodies are handled in parallel.
+    ///
+    /// Modules keep the configuration they were parsed with, so the function
+    /// is also called while emitting a module parsed with this configuration.
+    /// Unlike `on_parse`, it's kept when the configuration is cloned.
+    ///
+    /// By default no function is called. Timings of each phase are available
+    /// from `Module::parse_stats` and `Module::emit_wasm_with_stats` either
+    /// way.
+    pub fn on_progress<F>(&mut self, f: F) -> &mut ModuleConfig
+    where
+        F: Fn(Phase, usize, usize) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(f) as _);
+        self
+    }
+
     /// Provide a function that is invoked after successfully parsing a module,
     /// and gets access to data structures that only exist at parse time, such
     /// as the map from indices in the original Wasm to the new walrus IDs.
@@ -1,5 +1,7 @@
 //! Working with custom sections.
 
+use crate::emit::Section;
+use crate::map::IdHashMap;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
 use crate::IdsToIndices;
 use std::any::Any;
@@ -26,7 +28,7 @@ pub trait CustomSection: WalrusAny + Debug + Send + Sync {
     /// This should *not* include the section header with id=0, the custom
     /// section's name, or the count of how many bytes are in the
     /// payload. `walrus` will handle these for you.
-    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<[u8]>;
+    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<'_, [u8]>;
 }
 
 /// A wrapper trait around `any` but implemented for all types that already
@@ -85,7 +87,7 @@ impl CustomSection for RawCustomSection {
         &self.name
     }
 
-    fn data(&self, _: &IdsToIndices) -> Cow<[u8]> {
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
         self.data.as_slice().into()
     }
 }
@@ -163,10 +165,7 @@ where
     T: CustomSection,
 {
     fn clone(&self) -> Self {
-        TypedCustomSectionId {
-            id: self.id,
-            _phantom: PhantomData,
-        }
+        *self
     }
 }
 
@@ -231,6 +230,56 @@ impl Tombstone for Option<Box<dyn CustomSection>> {
     }
 }
 
+/// Where a custom section is placed in the emitted wasm module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum Placement {
+    /// Before every other section.
+    Start,
+    /// Directly after the given known section, or where that section would be
+    /// if it isn't emitted. `AfterSection(Section::Custom)` is the same as
+    /// `End`.
+    AfterSection(Section),
+    /// After every other section, including the "name" and "producers"
+    /// sections. This is the default.
+    #[default]
+    End,
+}
+
+impl Placement {
+    /// Is this placement after every other section?
+    pub(crate) fn end_of_module(self) -> bool {
+        matches!(
+            self,
+            Placement::AfterSection(Section::Custom) | Placement::End
+        )
+    }
+}
+
+/// What becomes of a custom section from the input wasm when the module is
+/// emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reemission {
+    /// The section wasn't parsed into typed form, and is kept as a
+    /// `RawCustomSection`, so it's emitted byte-identically unless that's
+    /// modified or removed.
+    Preserved,
+    /// The section was parsed, and is regenerated from the module when it's
+    /// emitted, so its bytes can differ from the original even if nothing was
+    /// changed.
+    Regenerated,
+    /// The section couldn't be parsed, so it was dropped and isn't emitted.
+    Dropped,
+}
+
+/// A custom section from the input wasm, and whether walrus parsed it into
+/// typed form.
+#[derive(Debug)]
+struct ParsedCustomSection {
+    name: String,
+    reemission: Reemission,
+    raw: Option<Vec<u8>>,
+}
+
 /// A collection of custom sections inside a Wasm module.
 ///
 /// To add parse and emit your own custom section:
@@ -253,9 +302,15 @@ impl Tombstone for Option<Box<dyn CustomSection>> {
 /// * Use `my_module.customs.add(my_custom_section)` to add the custom section
 ///   back into the module, so `walrus` can emit the processed/updated version
 ///   of the custom section.
+///
+/// Custom sections are emitted in the order they were added, at the position
+/// given by their `Placement`. Custom sections parsed from the input wasm
+/// keep their original position relative to the known sections.
 #[derive(Debug, Default)]
 pub struct ModuleCustomSections {
     arena: TombstoneArena<Option<Box<dyn CustomSection>>>,
+    placements: IdHashMap<Option<Box<dyn CustomSection>>, Placement>,
+    parsed: Vec<ParsedCustomSection>,
 }
 
 impl ModuleCustomSections {
@@ -281,9 +336,64 @@ impl ModuleCustomSections {
         let id = id.into_inner_id();
         let ret = self.arena.get_mut(id)?.take()?;
         self.arena.delete(id);
+        self.placements.remove(&id);
         I::section_box(ret)
     }
 
+    /// Set where the given custom section is placed in the emitted wasm.
+    pub fn set_placement<I>(&mut self, id: I, placement: Placement)
+    where
+        I: CustomSectionId,
+    {
+        self.placements.insert(id.into_inner_id(), placement);
+    }
+
+    /// Get where the given custom section is placed in the emitted wasm.
+    pub fn placement<I>(&self, id: I) -> Placement
+    where
+        I: CustomSectionId,
+    {
+        self.placements
+            .get(&id.into_inner_id())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get the original payload of the custom section `name` from the input
+    /// wasm, if the module was parsed with
+    /// `ModuleConfig::retain_raw_custom_sections` enabled.
+    ///
+    /// If the input had more than one section called `name`, this is the
+    /// first one's payload.
+    pub fn raw_of(&self, name: &str) -> Option<&[u8]> {
+        self.parsed
+            .iter()
+            .find(|s| s.name == name)
+            .and_then(|s| s.raw.as_ref())
+            .map(|raw| raw.as_slice())
+    }
+
+    /// Get what becomes of the custom section `name` from the input wasm when
+    /// the module is emitted, if there was one.
+    ///
+    /// Custom sections walrus doesn't parse are kept as `RawCustomSection`s,
+    /// and are `Reemission::Preserved`.
+    pub fn reemission(&self, name: &str) -> Option<Reemission> {
+        self.parsed
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.reemission)
+    }
+
+    /// Record what becomes of the custom section `name` from the input wasm.
+    pub(crate) fn add_parsed(&mut self, name: &str, reemission: Reemission, raw: Option<Vec<u8>>) {
+        self.parsed.push(ParsedCustomSection {
+            name: name.to_string(),
+            reemission,
+            raw,
+        });
+    }
+
     /// Take a raw, unparsed custom section out of this module.
     pub fn remove_raw(&mut self, name: &str) -> Option<RawCustomSection> {
         let id = self
@@ -300,6 +410,7 @@ impl ModuleCustomSections {
             .next()?;
         let section = self.arena[id].take().unwrap();
         self.arena.delete(id);
+        self.placements.remove(&id);
         let raw = section.into_any().downcast::<RawCustomSection>().unwrap();
         Some(*raw)
     }
@@ -334,28 +445,21 @@ impl ModuleCustomSections {
             .and_then(|s| T::section_mut(&mut **s.as_mut().unwrap()))
     }
 
-    /// Iterate over shared references to custom sections and their ids.
+    /// Iterate over shared references to custom sections and their ids, in
+    /// the order they were added.
     pub fn iter(&self) -> impl Iterator<Item = (UntypedCustomSectionId, &dyn CustomSection)> {
-        self.arena.iter().flat_map(|(id, s)| {
-            if let Some(s) = s.as_ref() {
-                Some((UntypedCustomSectionId(id), &**s))
-            } else {
-                None
-            }
-        })
+        self.arena
+            .iter()
+            .flat_map(|(id, s)| s.as_ref().map(|s| (UntypedCustomSectionId(id), &**s)))
     }
 
     /// Iterate over exclusive references to custom sections and their ids.
     pub fn iter_mut(
         &mut self,
     ) -> impl Iterator<Item = (UntypedCustomSectionId, &mut dyn CustomSection)> {
-        self.arena.iter_mut().flat_map(|(id, s)| {
-            if let Some(s) = s.as_mut() {
-                Some((UntypedCustomSectionId(id), &mut **s))
-            } else {
-                None
-            }
-        })
+        self.arena
+            .iter_mut()
+            .flat_map(|(id, s)| s.as_mut().map(|s| (UntypedCustomSectionId(id), &mut **s)))
     }
 
     /// Remove a custom section (by type) from the module.
@@ -1,11 +1,14 @@
 //! Data segments within a wasm module.
 
 use crate::emit::{Emit, EmitContext, Section};
-use crate::ir::Value;
-use crate::parse::IndicesToIds;
+use crate::ir::{dfs_in_order, Value, Visitor};
+use crate::parse::{reserve_hint, IndicesToIds};
+use crate::passes::ActiveSegment;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{InitExpr, Module, Result, ValType};
+use crate::{FunctionBuilder, FunctionId, FunctionKind, InitExpr, LocalFunction, Module};
+use crate::{Result, ValType};
 use failure::{bail, ResultExt};
+use rayon::prelude::*;
 
 /// A passive element segment identifier
 pub type DataId = Id<Data>;
@@ -46,9 +49,19 @@ impl Data {
 #[derive(Debug, Default)]
 pub struct ModuleData {
     arena: TombstoneArena<Data>,
+    parsed_data_count: bool,
 }
 
 impl ModuleData {
+    /// Returns whether the module this was parsed from had a data count
+    /// section.
+    ///
+    /// If it did, one is emitted again even when it isn't needed, unless
+    /// overridden with `ModuleConfig::force_data_count`.
+    pub fn parsed_data_count(&self) -> bool {
+        self.parsed_data_count
+    }
+
     /// Get an element associated with an ID
     pub fn get(&self, id: DataId) -> &Data {
         &self.arena[id]
@@ -68,12 +81,62 @@ impl ModuleData {
     }
 
     /// Get a shared reference to this module's passive elements.
+    ///
+    /// This leaves out the placeholders reserved for active segments by a
+    /// data count section.
     pub fn iter(&self) -> impl Iterator<Item = &Data> {
-        self.arena.iter().map(|(_, f)| f)
+        self.arena
+            .iter()
+            .map(|(_, f)| f)
+            .filter(|data| data.passive)
+    }
+
+    /// Get the number of data segments in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no data segments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more data segments, as long
+    /// as there aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a mutable reference to this module's data segments.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Data> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
+
+    /// Get a shared reference to this module's data segments, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (DataId, &Data)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's data segments.
+    pub fn ids(&self) -> impl Iterator<Item = DataId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's data segments, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Data> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's data segments, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Data> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
     }
 
     /// Adds a new passive data segment with the specified contents
-    pub fn add(&mut self, value: Vec<u8>) -> DataId {
+    ///
+    /// Passive segments are copied into a memory with `memory.init`, and can
+    /// be freed afterwards with `data.drop`.
+    pub fn add_passive(&mut self, value: Vec<u8>) -> DataId {
         self.arena.alloc_with_id(|id| Data {
             id,
             value,
@@ -81,6 +144,13 @@ impl ModuleData {
         })
     }
 
+    /// Adds a new passive data segment with the specified contents
+    ///
+    /// This is the same as `add_passive`.
+    pub fn add(&mut self, value: Vec<u8>) -> DataId {
+        self.add_passive(value)
+    }
+
     // Note that this is inaccordance with the upstream bulk memory proposal to
     // WebAssembly and isn't currently part of the WebAssembly standard.
     pub(crate) fn emit_data_count(&self, cx: &mut EmitContext) {
@@ -92,6 +162,7 @@ impl ModuleData {
         for mem in cx.module.memories.iter() {
             count += mem.emit_data().count();
         }
+        cx.indices.set_active_data(count as u32);
 
         // After the active data segments, assign indices to the passive data
         // segments.
@@ -102,13 +173,139 @@ impl ModuleData {
             any_passive = true;
         }
 
-        if any_passive {
+        // `memory.init` and `data.drop` can't be validated in a single pass
+        // without a data count section, but some older engines reject one
+        // in modules which don't use bulk memory.
+        let emit = match cx.module.config.force_data_count {
+            Some(force) => force,
+            None => any_passive || self.parsed_data_count || uses_data_segments(cx.module),
+        };
+        if emit {
             cx.start_section(Section::DataCount).encoder.usize(count);
         }
     }
 }
 
+/// Where `Module::make_data_passive` puts the code initializing the segment it
+/// makes passive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitTarget {
+    /// A new function, which it's up to you to call.
+    NewFunction,
+    /// A new function which becomes the module's start function, and calls
+    /// the previous start function, if there was one, once it's done.
+    Start,
+    /// The end of an existing local function, which mustn't have any results.
+    Append(FunctionId),
+}
+
 impl Module {
+    /// Turn an active data segment into a passive one, initialized by code
+    /// added as `init_into` says, returning the passive segment and the
+    /// function initializing it.
+    ///
+    /// The initializing code is a `memory.init` of the whole segment at its
+    /// original offset, followed by a `data.drop` of it. The memory's later
+    /// segments each move down one place.
+    ///
+    /// Active segments are all applied before any code runs, so once the
+    /// initializer has run the memory is exactly as it was before only if no
+    /// later segment writes to the same bytes. Returns an error, without
+    /// changing anything, if the segment's offset is relative to a global, if
+    /// a later segment of the memory overlaps it or has an offset relative to
+    /// a global, or if `init_into` appends to a function which isn't local or
+    /// has results.
+    pub fn make_data_passive(
+        &mut self,
+        segment: ActiveSegment,
+        init_into: InitTarget,
+    ) -> Result<(DataId, FunctionId)> {
+        let memory = segment.memory;
+        let mut segments = self.memories.get(memory).data.iter().skip(segment.index);
+        let (offset, len) = match segments.next() {
+            Some((InitExpr::Value(Value::I32(n)), data)) => (n as u32, data.len() as u32),
+            Some(_) => bail!(
+                "data segment {} of memory {} has an offset relative to a global",
+                segment.index,
+                memory.index()
+            ),
+            None => bail!(
+                "memory {} has no data segment {}",
+                memory.index(),
+                segment.index
+            ),
+        };
+        let range = u64::from(offset)..u64::from(offset) + u64::from(len);
+        for (later, data) in segments {
+            let start = match later {
+                InitExpr::Value(Value::I32(n)) => u64::from(n as u32),
+                _ => bail!(
+                    "a data segment of memory {} after segment {} has an offset \
+                     relative to a global",
+                    memory.index(),
+                    segment.index
+                ),
+            };
+            let end = start + data.len() as u64;
+            if start < range.end && range.start < end {
+                bail!(
+                    "a data segment of memory {} after segment {} overlaps it",
+                    memory.index(),
+                    segment.index
+                );
+            }
+        }
+        if let InitTarget::Append(func) = init_into {
+            let func = self.funcs.get(func);
+            let local = matches!(func.kind, FunctionKind::Local(_));
+            if !local || !self.types.get(func.ty()).results().is_empty() {
+                bail!(
+                    "can only append a data segment's initializer to a local \
+                     function without results"
+                );
+            }
+        }
+
+        let (_, value) = self.memories.get_mut(memory).data.remove(segment.index);
+        let data = self.data.add_passive(value);
+        let init = |builder: &mut FunctionBuilder| {
+            let dst = builder.i32_const(offset as i32);
+            let src = builder.i32_const(0);
+            let len = builder.i32_const(len as i32);
+            let init = builder.memory_init(memory, data, dst, src, len);
+            vec![init, builder.data_drop(data)]
+        };
+
+        let func = match init_into {
+            InitTarget::Append(func) => {
+                let local = match &mut self.funcs.get_mut(func).kind {
+                    FunctionKind::Local(local) => local,
+                    _ => unreachable!(),
+                };
+                let exprs = init(local.builder_mut());
+                let entry = local.entry_block();
+                local.block_mut(entry).exprs.extend(exprs);
+                func
+            }
+            InitTarget::NewFunction | InitTarget::Start => {
+                let mut builder = FunctionBuilder::new();
+                let mut exprs = init(&mut builder);
+                if init_into == InitTarget::Start {
+                    if let Some(start) = self.start {
+                        exprs.push(builder.call(start, Box::new([])));
+                    }
+                }
+                let ty = self.types.add(&[], &[]);
+                let func = builder.finish(ty, vec![], exprs, self);
+                if init_into == InitTarget::Start {
+                    self.start = Some(func);
+                }
+                func
+            }
+        };
+        Ok((data, func))
+    }
+
     /// Called when we see the data section section to create an id for all data
     /// indices
     ///
@@ -117,6 +314,8 @@ impl Module {
     /// they're actually passive or not, and that property is checked during
     /// validation.
     pub(crate) fn reserve_data(&mut self, count: u32, ids: &mut IndicesToIds) {
+        self.data.parsed_data_count = true;
+        self.data.reserve(reserve_hint(count));
         for _ in 0..count {
             ids.push_data(self.data.arena.alloc_with_id(|id| Data {
                 id,
@@ -166,6 +365,7 @@ impl Module {
                         InitExpr::Global(global) if self.globals.get(global).ty == ValType::I32 => {
                             memory.data.add_relative(global, value);
                         }
+                        InitExpr::Extended(ops) => memory.data.add_extended(ops, value),
                         _ => bail!("non-i32 constant in segment {}", i),
                     }
                 }
@@ -189,7 +389,7 @@ impl Emit for ModuleData {
         active.sort_by_key(|pair| pair.0);
         let passive = self.iter().count();
 
-        if active.len() == 0 && passive == 0 {
+        if active.is_empty() && passive == 0 {
             return;
         }
 
@@ -221,3 +421,28 @@ impl Emit for ModuleData {
         }
     }
 }
+
+/// Whether any function of `module` uses a data segment, with `memory.init`
+/// or `data.drop`.
+fn uses_data_segments(module: &Module) -> bool {
+    module.funcs.par_iter_local().any(|(_, func)| {
+        let mut visitor = DataUses { func, found: false };
+        dfs_in_order(&mut visitor, func, func.entry_block().into());
+        visitor.found
+    })
+}
+
+struct DataUses<'a> {
+    func: &'a LocalFunction,
+    found: bool,
+}
+
+impl<'a> Visitor<'a> for DataUses<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_data_id(&mut self, _: &DataId) {
+        self.found = true;
+    }
+}
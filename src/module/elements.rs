@@ -1,20 +1,58 @@
 //! Table elements within a wasm module.
 
+use crate::decode::{ref_type, Reader};
 use crate::emit::{Emit, EmitContext, Section};
-use crate::ir::Value;
-use crate::parse::IndicesToIds;
+use crate::ir::{dfs_in_order, RefFunc, Value, Visitor};
+use crate::map::IdHashSet;
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, InitExpr, Module, Result, TableKind, ValType};
+use crate::{ExportItem, FunctionId, FunctionTable, GlobalId, GlobalKind, InitExpr};
+use crate::{LocalFunction, Module};
+use crate::{ModuleTables, Result, TableId, TableKind, ValType};
 use failure::{bail, ResultExt};
+use rayon::prelude::*;
+use std::cmp;
+use std::ops::{Deref, DerefMut, Range};
 
-/// A passive element segment identifier
+/// The most slots of a table's layout that an active segment can fill in,
+/// which is the largest table the JS embedding allows.
+const MAX_TABLE_LAYOUT: usize = 10_000_000;
+
+/// An element segment identifier
 pub type ElementId = Id<Element>;
 
-/// A passive segment which contains a list of functions
+/// Whether an element segment is active, passive or declared.
+#[derive(Debug, Clone)]
+pub enum ElementKind {
+    /// A passive segment, which can be copied into a table at runtime.
+    Passive,
+    /// A declared segment, which is never copied into a table but declares
+    /// the functions that may be referenced with `ref.func`.
+    Declared,
+    /// An active segment, which is copied into a table when the module is
+    /// instantiated.
+    ///
+    /// Note that active segments parsed from a wasm module are instead
+    /// recorded in the layout of their `FunctionTable`.
+    Active {
+        /// The table this segment is copied into.
+        table: TableId,
+        /// The offset in the table this segment is copied to.
+        offset: InitExpr,
+    },
+}
+
+/// An element segment which contains a list of references
 #[derive(Debug)]
 pub struct Element {
     id: Id<Element>,
-    members: Vec<FunctionId>,
+    /// Whether this segment is active, passive or declared.
+    pub kind: ElementKind,
+    /// The type of the references in this segment, either `funcref` or
+    /// `externref`. Segments of `externref`s can only hold `ref.null`s.
+    pub ty: ValType,
+    /// The members of this segment, where `None` is a `ref.null`.
+    pub members: Vec<Option<FunctionId>>,
 }
 
 impl Element {
@@ -24,6 +62,98 @@ impl Element {
     }
 }
 
+/// Where the members of an element segment are stored.
+///
+/// Active segments parsed from a wasm module are recorded in the layout of
+/// their `FunctionTable` rather than in `ModuleElements`, so a segment can be
+/// stored in any of these places.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentSource {
+    /// A run of consecutive initialized slots of a function table's
+    /// `FunctionTable::elements`, starting at slot `start`.
+    Layout {
+        /// The table.
+        table: TableId,
+        /// The first slot of the run.
+        start: u32,
+    },
+    /// The entry at `index` in a function table's
+    /// `FunctionTable::relative_elements`.
+    Relative {
+        /// The table.
+        table: TableId,
+        /// The index of the entry.
+        index: usize,
+    },
+    /// A segment in `ModuleElements`.
+    Element(ElementId),
+}
+
+/// A view of an element segment, wherever it's stored, as returned by
+/// `Module::element_segments` and `Module::element_segments_mut`.
+#[derive(Debug)]
+pub struct ElementSegment<M> {
+    source: SegmentSource,
+    kind: ElementKind,
+    ty: ValType,
+    members: M,
+}
+
+impl<M> ElementSegment<M>
+where
+    M: Deref<Target = [Option<FunctionId>]>,
+{
+    /// Where this segment is stored.
+    pub fn source(&self) -> SegmentSource {
+        self.source
+    }
+
+    /// Whether this segment is active, passive or declared.
+    pub fn kind(&self) -> ElementKind {
+        self.kind.clone()
+    }
+
+    /// The table this segment is copied into, if it's active.
+    pub fn table(&self) -> Option<TableId> {
+        match self.kind {
+            ElementKind::Active { table, .. } => Some(table),
+            _ => None,
+        }
+    }
+
+    /// The offset in its table this segment is copied to, if it's active.
+    pub fn offset(&self) -> Option<InitExpr> {
+        match &self.kind {
+            ElementKind::Active { offset, .. } => Some(offset.clone()),
+            _ => None,
+        }
+    }
+
+    /// The type of the references in this segment.
+    pub fn ty(&self) -> ValType {
+        self.ty
+    }
+
+    /// The members of this segment, where `None` is a `ref.null`.
+    pub fn members(&self) -> &[Option<FunctionId>] {
+        &self.members
+    }
+}
+
+impl<M> ElementSegment<M>
+where
+    M: DerefMut<Target = [Option<FunctionId>]>,
+{
+    /// The members of this segment, where `None` is a `ref.null`.
+    ///
+    /// Replacing a member of a segment stored in a table's layout with `None`
+    /// leaves that slot of the table uninitialized, splitting the segment in
+    /// two when it's emitted.
+    pub fn members_mut(&mut self) -> &mut [Option<FunctionId>] {
+        &mut self.members
+    }
+}
+
 impl Tombstone for Element {
     fn on_delete(&mut self) {
         self.members = Vec::new();
@@ -38,6 +168,163 @@ pub struct ModuleElements {
 }
 
 impl ModuleElements {
+    /// Add a new element segment with the given members.
+    pub fn add(
+        &mut self,
+        kind: ElementKind,
+        ty: ValType,
+        members: Vec<Option<FunctionId>>,
+    ) -> ElementId {
+        self.arena.alloc_with_id(|id| Element {
+            id,
+            kind,
+            ty,
+            members,
+        })
+    }
+
+    /// Add a new active segment of functions, which is copied into `table`
+    /// at `offset` when the module is instantiated.
+    ///
+    /// Unlike `place_functions`, this doesn't check that the functions fit in
+    /// the table or don't overlap other segments.
+    pub fn add_active(
+        &mut self,
+        table: TableId,
+        offset: InitExpr,
+        funcs: &[FunctionId],
+    ) -> ElementId {
+        let kind = ElementKind::Active { table, offset };
+        let members = funcs.iter().cloned().map(Some).collect();
+        self.add(kind, ValType::Funcref, members)
+    }
+
+    /// Add a new passive segment of functions.
+    pub fn add_passive(&mut self, funcs: &[FunctionId]) -> ElementId {
+        let members = funcs.iter().cloned().map(Some).collect();
+        self.add(ElementKind::Passive, ValType::Funcref, members)
+    }
+
+    /// Add a new active segment which places the given functions in `table`,
+    /// starting at slot `base`.
+    ///
+    /// Returns an error if the functions don't fit in the table, or if they
+    /// would overlap any of the table's other active segments. Whether
+    /// segments at an offset relative to a global overlap isn't known, so if
+    /// the table has any then `allow_unknown_overlap` must be `true` to place
+    /// the functions anyway.
+    pub fn place_functions(
+        &mut self,
+        tables: &ModuleTables,
+        table: TableId,
+        base: u32,
+        funcs: &[FunctionId],
+        allow_unknown_overlap: bool,
+    ) -> Result<ElementId> {
+        self.check_overlap(
+            tables,
+            table,
+            base,
+            funcs.len(),
+            None,
+            allow_unknown_overlap,
+        )?;
+        let offset = InitExpr::Value(Value::I32(base as i32));
+        Ok(self.add_active(table, offset, funcs))
+    }
+
+    /// Move the given active segment so that it starts at slot `new_base` of
+    /// its table.
+    ///
+    /// Returns an error if the segment isn't active, or on overlap in the same
+    /// way as `place_functions`.
+    pub fn rebase_segment(
+        &mut self,
+        tables: &ModuleTables,
+        id: ElementId,
+        new_base: u32,
+        allow_unknown_overlap: bool,
+    ) -> Result<()> {
+        let segment = &self.arena[id];
+        let table = match segment.kind {
+            ElementKind::Active { table, .. } => table,
+            _ => bail!("only active element segments can be rebased"),
+        };
+        let len = segment.members.len();
+        self.check_overlap(
+            tables,
+            table,
+            new_base,
+            len,
+            Some(id),
+            allow_unknown_overlap,
+        )?;
+        self.arena[id].kind = ElementKind::Active {
+            table,
+            offset: InitExpr::Value(Value::I32(new_base as i32)),
+        };
+        Ok(())
+    }
+
+    /// Check that `len` slots starting at `base` in `table` are in bounds and
+    /// don't overlap any active segments, other than `skip`.
+    fn check_overlap(
+        &self,
+        tables: &ModuleTables,
+        table: TableId,
+        base: u32,
+        len: usize,
+        skip: Option<ElementId>,
+        allow_unknown_overlap: bool,
+    ) -> Result<()> {
+        let t = tables.get(table);
+        let end = match base.checked_add(len as u32) {
+            Some(end) if end <= t.initial => end,
+            _ => bail!(
+                "{} slots starting at {} don't fit in a table with {} slots",
+                len,
+                base,
+                t.initial
+            ),
+        };
+
+        let mut unknown = false;
+        for segment in segments(tables, self) {
+            let skipped = skip.map(SegmentSource::Element) == Some(segment.source());
+            if segment.table() != Some(table) || skipped {
+                continue;
+            }
+            match segment.offset() {
+                Some(InitExpr::Value(Value::I32(n))) => {
+                    // Segments added by hand can run past the end of the
+                    // `u32` range, so their ends are computed as `u64`s.
+                    let other_start = u64::from(n as u32);
+                    let other_end = other_start + segment.members().len() as u64;
+                    if u64::from(base) < other_end && other_start < u64::from(end) {
+                        bail!(
+                            "slots {}..{} overlap the segment at slots {}..{}",
+                            base,
+                            end,
+                            other_start,
+                            other_end
+                        );
+                    }
+                }
+                _ => unknown = true,
+            }
+        }
+
+        if unknown && !allow_unknown_overlap {
+            bail!(
+                "the table has segments at offsets relative to a global, \
+                 which might overlap slots {}..{}",
+                base,
+                end
+            );
+        }
+        Ok(())
+    }
+
     /// Get an element associated with an ID
     pub fn get(&self, id: ElementId) -> &Element {
         &self.arena[id]
@@ -60,175 +347,504 @@ impl ModuleElements {
     pub fn iter(&self) -> impl Iterator<Item = &Element> {
         self.arena.iter().map(|(_, f)| f)
     }
+
+    /// Get the number of element segments in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no element segments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more element segments, as long
+    /// as there aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a mutable reference to this module's element segments.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
+
+    /// Get a shared reference to this module's element segments, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (ElementId, &Element)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's element segments.
+    pub fn ids(&self) -> impl Iterator<Item = ElementId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's element segments, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Element> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's element segments, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Element> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
 }
 
 impl Module {
+    /// Get a view of every element segment in the module, in the order
+    /// they're emitted in.
+    ///
+    /// This covers the segments recorded in the layouts of function tables,
+    /// those at offsets relative to a global, and those in `ModuleElements`.
+    pub fn element_segments(&self) -> Vec<ElementSegment<&[Option<FunctionId>]>> {
+        segments(&self.tables, &self.elements)
+    }
+
+    /// Get a mutable view of every element segment in the module, in the
+    /// order they're emitted in, for editing their members in place.
+    pub fn element_segments_mut(&mut self) -> Vec<ElementSegment<&mut [Option<FunctionId>]>> {
+        let mut layout = Vec::new();
+        let mut relative = Vec::new();
+        for table in self.tables.iter_mut() {
+            let id = table.id();
+            let FunctionTable {
+                elements,
+                relative_elements,
+            } = match &mut table.kind {
+                TableKind::Function(list) => list,
+                TableKind::Externref(_) => continue,
+            };
+
+            let mut rest = &mut elements[..];
+            let mut consumed = 0;
+            for run in layout_runs(rest) {
+                let tail = std::mem::take(&mut rest);
+                let (members, tail) = tail[run.start - consumed..].split_at_mut(run.len());
+                rest = tail;
+                consumed = run.end;
+                layout.push(layout_segment(id, run.start, members));
+            }
+
+            for (index, (global, members)) in relative_elements.iter_mut().enumerate() {
+                relative.push(relative_segment(id, index, *global, &mut members[..]));
+            }
+        }
+
+        let (active, other): (Vec<_>, Vec<_>) = self
+            .elements
+            .arena
+            .iter_mut()
+            .partition(|(_, s)| is_active(s));
+        let elements = active
+            .into_iter()
+            .chain(other)
+            .map(|(id, s)| ElementSegment {
+                source: SegmentSource::Element(id),
+                kind: s.kind.clone(),
+                ty: s.ty,
+                members: &mut s.members[..],
+            });
+
+        layout.into_iter().chain(relative).chain(elements).collect()
+    }
+
+    /// Get every function that's the target of a `ref.func` expression but
+    /// isn't declared by an element segment, an export or a global's
+    /// initializer, sorted by id.
+    ///
+    /// Such a `ref.func` is invalid unless the function is declared, which
+    /// emitting the module does automatically unless
+    /// `ModuleConfig::declare_ref_func_targets` is turned off.
+    pub fn undeclared_ref_func_targets(&self) -> Vec<FunctionId> {
+        let mut declared = IdHashSet::default();
+        for segment in self.element_segments() {
+            declared.extend(segment.members().iter().filter_map(|m| *m));
+        }
+        for export in self.exports.iter() {
+            if let ExportItem::Function(f) = export.item {
+                declared.insert(f);
+            }
+        }
+        for global in self.globals.iter() {
+            if let GlobalKind::Local(init) = &global.kind {
+                declared.extend(init.funcs());
+            }
+        }
+
+        let mut targets = self
+            .funcs
+            .par_map_local(|_, func| {
+                let mut visitor = RefFuncTargets {
+                    func,
+                    targets: Vec::new(),
+                };
+                dfs_in_order(&mut visitor, func, func.entry_block().into());
+                visitor.targets
+            })
+            .into_iter()
+            .flat_map(|(_, targets)| targets)
+            .filter(|f| !declared.contains(f))
+            .collect::<Vec<_>>();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
     /// Parses a raw was section into a fully-formed `ModuleElements` instance.
-    pub(crate) fn parse_elements(
-        &mut self,
-        section: wasmparser::ElementSectionReader,
-        ids: &mut IndicesToIds,
-    ) -> Result<()> {
+    /// Parse the element section, given its contents.
+    ///
+    /// Our version of `wasmparser` only knows about the MVP encoding and
+    /// passive segments of function indices, so segments are decoded here.
+    pub(crate) fn parse_elements(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
         log::debug!("parse element section");
-        for (i, segment) in section.into_iter().enumerate() {
-            let segment = segment?;
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.elements.reserve(reserve_hint(count));
+        for i in 0..count {
+            self.parse_element(&mut r, ids)
+                .with_context(|_e| format!("in segment {}", i))?;
+        }
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
+        Ok(())
+    }
+
+    fn parse_element(&mut self, r: &mut Reader, ids: &mut IndicesToIds) -> Result<()> {
+        // Bit 0 is set for passive and declared segments, bit 1 for declared
+        // segments or an explicit table index, and bit 2 for members which
+        // are constant expressions rather than function indices.
+        let flags = r.u32()?;
+        if flags > 7 {
+            bail!("invalid flags for an element segment: {}", flags);
+        }
+        let active = flags & 0x01 == 0;
+        let expressions = flags & 0x04 != 0;
+        let table = if flags & 0x03 == 0x02 { r.u32()? } else { 0 };
+        let offset = if active {
+            Some(InitExpr::decode(r, ids)?)
+        } else {
+            None
+        };
+        let ty = if flags & 0x03 == 0 {
+            ValType::Funcref
+        } else if expressions {
+            ref_type(r)?.val_type()
+        } else {
+            match r.byte()? {
+                0x00 => ValType::Funcref,
+                kind => bail!("invalid element kind: {:#x}", kind),
+            }
+        };
+
+        let count = r.u32()?;
+        let mut members = Vec::with_capacity(reserve_hint(count));
+        for _ in 0..count {
+            let member = if expressions {
+                match InitExpr::decode(r, ids)? {
+                    InitExpr::RefFunc(func) => Some(func),
+                    InitExpr::RefNull(_) => None,
+                    _ => bail!("element expressions must be `ref.func` or `ref.null`"),
+                }
+            } else {
+                Some(ids.get_func(r.u32()?)?)
+            };
+            members.push(member);
+        }
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => {
+                let kind = if flags & 0x02 == 0 {
+                    ElementKind::Passive
+                } else {
+                    ElementKind::Declared
+                };
+                let id = self.elements.add(kind, ty, members);
+                ids.push_element(Some(id));
+                return Ok(());
+            }
+        };
 
-            match segment.kind {
-                wasmparser::ElementKind::Passive(ty) => {
-                    drop(ty);
-                    bail!("passive element segments not supported yet");
+        // Active segments of functions are recorded in their table's
+        // layout, as long as they have no `ref.null`s, which the layout
+        // can't tell apart from uninitialized slots.
+        let table_id = ids.get_table(table)?;
+        let maximum = self.tables.get(table_id).maximum;
+        let table = match &mut self.tables.get_mut(table_id).kind {
+            TableKind::Function(t) if members.iter().all(|m| m.is_some()) => Some(t),
+            TableKind::Function(_) => None,
+            TableKind::Externref(_) if ty == ValType::Funcref => {
+                bail!("active segments of functions can't initialize an externref table");
+            }
+            TableKind::Externref(_) => None,
+        };
+        match (table, offset) {
+            (Some(table), InitExpr::Value(Value::I32(n))) => {
+                if n < 0 {
+                    bail!("negative offset for an active element segment: {}", n);
+                }
+                let offset = n as usize;
+                let end = offset.checked_add(members.len());
+                let limit = maximum.map_or(MAX_TABLE_LAYOUT, |max| {
+                    cmp::min(max as usize, MAX_TABLE_LAYOUT)
+                });
+                match end {
+                    Some(end) if end <= limit => {}
+                    _ => bail!(
+                        "active element segment at offset {} with {} members \
+                         doesn't fit in its table",
+                        offset,
+                        members.len()
+                    ),
                 }
-                wasmparser::ElementKind::Active {
-                    table_index,
-                    init_expr,
-                } => {
-                    let table = ids.get_table(table_index)?;
-                    let table = match &mut self.tables.get_mut(table).kind {
-                        TableKind::Function(t) => t,
-                        TableKind::Anyref(_) => {
-                            bail!("active anyref segments not supported yet");
-                        }
-                    };
-
-                    let offset = InitExpr::eval(&init_expr, ids)
-                        .with_context(|_e| format!("in segment {}", i))?;
-                    let functions = segment.items.get_items_reader()?.into_iter().map(|func| {
-                        let func = func?;
-                        ids.get_func(func)
-                    });
-
-                    match offset {
-                        InitExpr::Value(Value::I32(n)) => {
-                            let offset = n as usize;
-                            for (i, id) in functions.enumerate() {
-                                while i + offset + 1 > table.elements.len() {
-                                    table.elements.push(None);
-                                }
-                                table.elements[i + offset] = Some(id?);
-                            }
-                        }
-                        InitExpr::Global(global) if self.globals.get(global).ty == ValType::I32 => {
-                            let list = functions.collect::<Result<_>>()?;
-                            table.relative_elements.push((global, list));
-                        }
-                        _ => bail!("non-i32 constant in segment {}", i),
+                for (i, id) in members.into_iter().enumerate() {
+                    while i + offset + 1 > table.elements.len() {
+                        table.elements.push(None);
                     }
+                    table.elements[i + offset] = id;
                 }
             }
+            (Some(table), InitExpr::Global(global))
+                if self.globals.get(global).ty == ValType::I32 =>
+            {
+                table.relative_elements.push((global, members));
+            }
+            (_, offset @ InitExpr::Extended(_))
+            | (None, offset @ InitExpr::Value(Value::I32(_)))
+            | (None, offset @ InitExpr::Global(_)) => {
+                let kind = ElementKind::Active {
+                    table: table_id,
+                    offset,
+                };
+                let id = self.elements.add(kind, ty, members);
+                ids.push_element(Some(id));
+                return Ok(());
+            }
+            _ => bail!("non-i32 constant offset"),
         }
+        ids.push_element(None);
         Ok(())
     }
 }
 
+struct RefFuncTargets<'a> {
+    func: &'a LocalFunction,
+    targets: Vec<FunctionId>,
+}
+
+impl<'a> Visitor<'a> for RefFuncTargets<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_ref_func(&mut self, e: &RefFunc) {
+        self.targets.push(e.func);
+    }
+}
+
 impl Emit for ModuleElements {
     fn emit(&self, cx: &mut EmitContext) {
         log::debug!("emit element section");
-        // Sort table ids for a deterministic emission for now, eventually we
-        // may want some sort of sorting heuristic here.
-        let mut active = cx
-            .module
-            .tables
-            .iter()
-            .filter_map(|t| match &t.kind {
-                TableKind::Function(list) => Some((t.id(), list)),
-                TableKind::Anyref(_) => None,
-            })
-            .collect::<Vec<_>>();
-        active.sort_by_key(|pair| pair.0);
-
-        // Append segments as we find them for all table initializers. We can
-        // skip initializers for unused tables, and otherwise we just want to
-        // create an initializer for each contiguous chunk of function indices.
-        let mut chunks = Vec::new();
-        for (table_id, table) in active.iter() {
-            let mut offset = 0;
-            let mut len = 0;
-            for (i, item) in table.elements.iter().enumerate() {
-                if item.is_some() {
-                    if len == 0 {
-                        offset = i;
-                    }
-                    len += 1;
-                } else {
-                    if len > 0 {
-                        chunks.push((table_id, table, offset, len));
-                    }
-                    len = 0;
-                }
-            }
-
-            if len > 0 {
-                chunks.push((table_id, table, offset, len));
-            }
-        }
+        // Segments making up each table's layout come first, followed by
+        // those relative to a global, then any other active segments, and
+        // finally passive and declared segments. We may want to sort this more
+        // intelligently in the future.
+        let module = cx.module;
+        let segments = segments(&module.tables, self);
 
-        let passive = self.iter().count();
-        let relative = active
-            .iter()
-            .map(|(_, table)| table.relative_elements.len())
-            .sum::<usize>();
-        let total = passive + relative + chunks.len();
+        // Functions referenced by `ref.func` which nothing else declares are
+        // declared by an extra segment at the end, so that the indices of the
+        // other segments don't change.
+        let undeclared = if module.config.skip_ref_func_declarations {
+            Vec::new()
+        } else {
+            module
+                .undeclared_ref_func_targets()
+                .into_iter()
+                .map(Some)
+                .collect()
+        };
 
-        if total == 0 {
+        if segments.is_empty() && undeclared.is_empty() {
             return;
         }
+        let active = segments.iter().take_while(|s| s.table().is_some()).count();
+        cx.indices.set_active_elements(active as u32);
         let mut cx = cx.start_section(Section::Element);
-        cx.encoder.usize(total);
-
-        // Emits the leading data for describing a table's index
-        //
-        // Note that much of this is in accordance with the
-        // currently-in-progress bulk-memory proposal for WebAssembly.
-        let active_table_header = |cx: &mut EmitContext, index: u32| {
-            if index == 0 {
-                cx.encoder.byte(0x00);
-            } else {
-                cx.encoder.byte(0x02);
-                cx.encoder.u32(index);
+        cx.encoder
+            .usize(segments.len() + (!undeclared.is_empty()) as usize);
+        for (i, segment) in segments.iter().enumerate() {
+            if let SegmentSource::Element(id) = segment.source() {
+                cx.indices.set_element_index(id, i as u32);
             }
-        };
+            emit_segment(segment.kind(), segment.ty(), segment.members(), &mut cx);
+        }
+        if !undeclared.is_empty() {
+            let kind = ElementKind::Declared;
+            emit_segment(kind, ValType::Funcref, &undeclared, &mut cx);
+        }
+    }
+}
 
-        // Emit all contiguous chunks of functions pointers that are located at
-        // constant offsets
-        for (&id, table, offset, len) in chunks {
-            let table_index = cx.indices.get_table_index(id);
-            active_table_header(&mut cx, table_index);
-            InitExpr::Value(Value::I32(offset as i32)).emit(&mut cx);
-            cx.encoder.usize(len);
-            for item in table.elements[offset..][..len].iter() {
-                let index = cx.indices.get_func_index(item.unwrap());
-                cx.encoder.u32(index);
+/// Views of all the segments of a module, in the order they're emitted in.
+fn segments<'a>(
+    tables: &'a ModuleTables,
+    elements: &'a ModuleElements,
+) -> Vec<ElementSegment<&'a [Option<FunctionId>]>> {
+    let lists = tables
+        .iter()
+        .filter_map(|t| match &t.kind {
+            TableKind::Function(list) => Some((t.id(), list)),
+            TableKind::Externref(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut segments = Vec::new();
+    for (id, list) in lists.iter() {
+        for run in layout_runs(&list.elements) {
+            let start = run.start;
+            segments.push(layout_segment(*id, start, &list.elements[run]));
+        }
+    }
+    for (id, list) in lists.iter() {
+        for (index, (global, members)) in list.relative_elements.iter().enumerate() {
+            segments.push(relative_segment(*id, index, *global, &members[..]));
+        }
+    }
+
+    let (active, other): (Vec<_>, Vec<_>) = elements.arena.iter().partition(|(_, s)| is_active(s));
+    for (id, segment) in active.into_iter().chain(other) {
+        segments.push(ElementSegment {
+            source: SegmentSource::Element(id),
+            kind: segment.kind.clone(),
+            ty: segment.ty,
+            members: &segment.members[..],
+        });
+    }
+    segments
+}
+
+/// The runs of consecutive initialized slots in a table's layout.
+fn layout_runs(slots: &[Option<FunctionId>]) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, slot) in slots.iter().enumerate() {
+        match (slot, start) {
+            (Some(_), None) => start = Some(i),
+            (None, Some(s)) => {
+                runs.push(s..i);
+                start = None;
             }
+            _ => {}
         }
+    }
+    if let Some(s) = start {
+        runs.push(s..slots.len());
+    }
+    runs
+}
 
-        // Emit all chunks of function pointers that are located at relative
-        // global offsets.
-        for (id, table) in active.iter() {
-            let table_index = cx.indices.get_table_index(*id);
-            for (global, list) in table.relative_elements.iter() {
-                active_table_header(&mut cx, table_index);
-                InitExpr::Global(*global).emit(&mut cx);
-                cx.encoder.usize(list.len());
-                for func in list {
-                    let index = cx.indices.get_func_index(*func);
-                    cx.encoder.u32(index);
-                }
+fn layout_segment<M>(table: TableId, start: usize, members: M) -> ElementSegment<M> {
+    ElementSegment {
+        source: SegmentSource::Layout {
+            table,
+            start: start as u32,
+        },
+        kind: ElementKind::Active {
+            table,
+            offset: InitExpr::Value(Value::I32(start as i32)),
+        },
+        ty: ValType::Funcref,
+        members,
+    }
+}
+
+fn relative_segment<M>(
+    table: TableId,
+    index: usize,
+    global: GlobalId,
+    members: M,
+) -> ElementSegment<M> {
+    ElementSegment {
+        source: SegmentSource::Relative { table, index },
+        kind: ElementKind::Active {
+            table,
+            offset: InitExpr::Global(global),
+        },
+        ty: ValType::Funcref,
+        members,
+    }
+}
+
+fn is_active(segment: &Element) -> bool {
+    matches!(segment.kind, ElementKind::Active { .. })
+}
+
+/// Emits a segment.
+///
+/// Segments of only functions use the compact encoding of function indices,
+/// and anything else, such as a segment containing a `ref.null`, is encoded
+/// as a vector of constant expressions. Active `funcref` segments for table 0
+/// use the MVP encoding, so that MVP modules round trip byte-for-byte.
+fn emit_segment(
+    kind: ElementKind,
+    ty: ValType,
+    members: &[Option<FunctionId>],
+    cx: &mut EmitContext,
+) {
+    let as_indices = ty == ValType::Funcref && members.iter().all(|m| m.is_some());
+    let expressions = if as_indices { 0x00 } else { 0x04 };
+    let explicit_type = match kind {
+        ElementKind::Passive => {
+            cx.encoder.byte(expressions | 0x01);
+            true
+        }
+        ElementKind::Declared => {
+            cx.encoder.byte(expressions | 0x03);
+            true
+        }
+        ElementKind::Active { table, offset } => {
+            // Only `funcref` segments for table 0 can leave both the table
+            // and the type of their members implicit.
+            let table = cx.indices.get_table_index(table);
+            let explicit = table != 0 || ty != ValType::Funcref;
+            if explicit {
+                cx.encoder.byte(expressions | 0x02);
+                cx.encoder.u32(table);
+            } else {
+                cx.encoder.byte(expressions);
             }
+            offset.emit(cx);
+            explicit
         }
+    };
+    if explicit_type {
+        if as_indices {
+            cx.encoder.byte(0x00); // elemkind == funcref
+        } else {
+            ty.emit(&mut cx.encoder, cx.indices);
+        }
+    }
 
-        // After all the active segments are added add passive segments next. We
-        // may want to sort this more intelligently in the future. Otherwise
-        // emitting a segment here is in general much simpler than above as we
-        // know there are no holes.
-        for (id, segment) in self.arena.iter() {
-            cx.indices.push_element(id);
-            drop((id, segment));
-            // TODO: sync this with the upstream spec
-            panic!(
-                "encoding a passive element segment requires either \
-                 `ref.null` or `ref.func` encodings, which aren't \
-                 currently implemented"
-            );
+    cx.encoder.usize(members.len());
+    for member in members.iter() {
+        match (member, as_indices) {
+            (Some(func), true) => {
+                let index = cx.indices.get_func_index(*func);
+                cx.encoder.u32(index);
+            }
+            (Some(func), false) => {
+                cx.encoder.byte(0xd2); // ref.func
+                let index = cx.indices.get_func_index(*func);
+                cx.encoder.u32(index);
+                cx.encoder.byte(0x0b); // end
+            }
+            (None, _) => {
+                cx.encoder.byte(0xd0); // ref.null
+                ty.emit(&mut cx.encoder, cx.indices);
+                cx.encoder.byte(0x0b); // end
+            }
         }
     }
 }
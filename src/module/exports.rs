@@ -1,9 +1,13 @@
 //! Exported items in a wasm module.
 
+use crate::decode::Reader;
 use crate::emit::{Emit, EmitContext, Section};
-use crate::parse::IndicesToIds;
+use crate::error::WrongExportKind;
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, GlobalId, MemoryId, Module, Result, TableId};
+use crate::{FunctionId, GlobalId, MemoryId, Module, Result, SharedStr, TableId, TagId};
+use failure::bail;
+use rayon::prelude::*;
 
 /// The id of an export.
 pub type ExportId = Id<Export>;
@@ -13,14 +17,14 @@ pub type ExportId = Id<Export>;
 pub struct Export {
     id: ExportId,
     /// The name of this export.
-    pub name: String,
+    pub name: SharedStr,
     /// The item being exported.
     pub item: ExportItem,
 }
 
 impl Tombstone for Export {
     fn on_delete(&mut self) {
-        self.name = String::new();
+        self.name = SharedStr::default();
     }
 }
 
@@ -32,7 +36,7 @@ impl Export {
 }
 
 /// An exported item.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ExportItem {
     /// An exported function.
     Function(FunctionId),
@@ -42,6 +46,8 @@ pub enum ExportItem {
     Memory(MemoryId),
     /// An exported global.
     Global(GlobalId),
+    /// An exported exception tag.
+    Tag(TagId),
 }
 
 /// The set of exports in a module.
@@ -72,20 +78,83 @@ impl ModuleExports {
         self.arena.iter().map(|(_, f)| f)
     }
 
+    /// Get the number of exports in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no exports.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more exports, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
     /// Get a mutable reference to this module's exports.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Export> {
         self.arena.iter_mut().map(|(_, f)| f)
     }
 
+    /// Get a shared reference to this module's exports, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (ExportId, &Export)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's exports.
+    pub fn ids(&self) -> impl Iterator<Item = ExportId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's exports, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Export> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's exports, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Export> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+
     /// Add a new export to this module
     pub fn add(&mut self, name: &str, item: impl Into<ExportItem>) -> ExportId {
         self.arena.alloc_with_id(|id| Export {
             id,
-            name: name.to_string(),
+            name: name.into(),
             item: item.into(),
         })
     }
 
+    /// Get the export with the given name.
+    pub fn find(&self, name: &str) -> Option<ExportId> {
+        let export = self.arena.iter().find(|(_, export)| export.name == name);
+        Some(export?.0)
+    }
+
+    /// Get the function exported with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no export with the name, or a
+    /// `WrongExportKind` error if there is one but it isn't a function.
+    pub fn get_func(&self, name: &str) -> Result<FunctionId> {
+        let id = match self.find(name) {
+            Some(id) => id,
+            None => bail!("there's no export named `{}`", name),
+        };
+        match self.arena[id].item {
+            ExportItem::Function(f) => Ok(f),
+            item => Err(WrongExportKind {
+                name: name.to_string(),
+                item,
+            }
+            .into()),
+        }
+    }
+
     #[doc(hidden)]
     #[deprecated(note = "Use `ModuleExports::delete` instead")]
     pub fn remove_root(&mut self, id: ExportId) {
@@ -95,27 +164,32 @@ impl ModuleExports {
 
 impl Module {
     /// Construct the export set for a wasm module.
-    pub(crate) fn parse_exports(
-        &mut self,
-        section: wasmparser::ExportSectionReader,
-        ids: &IndicesToIds,
-    ) -> Result<()> {
+    pub(crate) fn parse_exports(&mut self, section: &[u8], ids: &IndicesToIds) -> Result<()> {
         log::debug!("parse export section");
-        use wasmparser::ExternalKind::*;
-
-        for entry in section {
-            let entry = entry?;
-            let item = match entry.kind {
-                Function => ExportItem::Function(ids.get_func(entry.index)?),
-                Table => ExportItem::Table(ids.get_table(entry.index)?),
-                Memory => ExportItem::Memory(ids.get_memory(entry.index)?),
-                Global => ExportItem::Global(ids.get_global(entry.index)?),
+        // This is decoded here rather than by `wasmparser`, which doesn't
+        // know about exported tags.
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.exports.reserve(reserve_hint(count));
+        for _ in 0..count {
+            let name = r.name()?;
+            let kind = r.byte()?;
+            let index = r.u32()?;
+            let item = match kind {
+                0x00 => ExportItem::Function(ids.get_func(index)?),
+                0x01 => ExportItem::Table(ids.get_table(index)?),
+                0x02 => ExportItem::Memory(ids.get_memory(index)?),
+                0x03 => ExportItem::Global(ids.get_global(index)?),
+                0x04 => ExportItem::Tag(ids.get_tag(index)?),
+                kind => bail!("invalid export kind: {:#x}", kind),
             };
-            self.exports.arena.alloc_with_id(|id| Export {
-                id,
-                name: entry.field.to_string(),
-                item,
-            });
+            let name = self.shared_str(name);
+            self.exports
+                .arena
+                .alloc_with_id(|id| Export { id, name, item });
+        }
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
         }
         Ok(())
     }
@@ -158,6 +232,11 @@ impl Emit for ModuleExports {
                     cx.encoder.byte(0x03);
                     cx.encoder.u32(index);
                 }
+                ExportItem::Tag(id) => {
+                    let index = cx.indices.get_tag_index(id);
+                    cx.encoder.byte(0x04);
+                    cx.encoder.u32(index);
+                }
             }
         }
     }
@@ -186,3 +265,9 @@ impl From<TableId> for ExportItem {
         ExportItem::Table(id)
     }
 }
+
+impl From<TagId> for ExportItem {
+    fn from(id: TagId) -> ExportItem {
+        ExportItem::Tag(id)
+    }
+}
@@ -0,0 +1,245 @@
+//! Which WebAssembly proposals a module uses.
+
+use crate::error::DisabledFeature;
+use crate::ir::*;
+use crate::{LocalFunction, Module};
+
+/// A set of WebAssembly proposals beyond the MVP.
+///
+/// This is returned by `Module::used_features` to describe what a module
+/// requires of an engine, and can be given to `ModuleConfig::wasm_features`
+/// to reject modules which use anything else.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WasmFeatures {
+    /// The sign-extension operators, such as `i32.extend8_s`. These can be
+    /// removed with `passes::lower_sign_ext`.
+    pub sign_extension: bool,
+    /// The non-trapping float-to-int conversions, such as
+    /// `i32.trunc_sat_f32_s`. These can be removed with
+    /// `passes::lower_trunc_sat`.
+    pub saturating_float_to_int: bool,
+    /// Functions and blocks with multiple results, and blocks taking
+    /// parameters. Multiple results can be removed with
+    /// `passes::lower_multi_value`.
+    pub multi_value: bool,
+    /// The relaxed SIMD instructions, such as `f32x4.relaxed_madd`, whose
+    /// results can differ between engines. These are also rejected by
+    /// validation unless `ModuleConfig::relaxed_simd` is enabled.
+    pub relaxed_simd: bool,
+}
+
+impl WasmFeatures {
+    /// Every feature that can be described by `WasmFeatures`.
+    pub fn all() -> WasmFeatures {
+        WasmFeatures {
+            sign_extension: true,
+            saturating_float_to_int: true,
+            multi_value: true,
+            relaxed_simd: true,
+        }
+    }
+
+    /// The features needed by a unary operator, and its name in the text
+    /// format, if it isn't part of the MVP.
+    pub(crate) fn for_unop(op: UnaryOp) -> Option<(WasmFeatures, &'static str)> {
+        use UnaryOp::*;
+        let sign_extension = WasmFeatures {
+            sign_extension: true,
+            ..WasmFeatures::default()
+        };
+        let saturating_float_to_int = WasmFeatures {
+            saturating_float_to_int: true,
+            ..WasmFeatures::default()
+        };
+        let relaxed_simd = WasmFeatures::relaxed_simd();
+        Some(match op {
+            I32Extend8S => (sign_extension, "i32.extend8_s"),
+            I32Extend16S => (sign_extension, "i32.extend16_s"),
+            I64Extend8S => (sign_extension, "i64.extend8_s"),
+            I64Extend16S => (sign_extension, "i64.extend16_s"),
+            I64Extend32S => (sign_extension, "i64.extend32_s"),
+            I32TruncSSatF32 => (saturating_float_to_int, "i32.trunc_sat_f32_s"),
+            I32TruncUSatF32 => (saturating_float_to_int, "i32.trunc_sat_f32_u"),
+            I32TruncSSatF64 => (saturating_float_to_int, "i32.trunc_sat_f64_s"),
+            I32TruncUSatF64 => (saturating_float_to_int, "i32.trunc_sat_f64_u"),
+            I64TruncSSatF32 => (saturating_float_to_int, "i64.trunc_sat_f32_s"),
+            I64TruncUSatF32 => (saturating_float_to_int, "i64.trunc_sat_f32_u"),
+            I64TruncSSatF64 => (saturating_float_to_int, "i64.trunc_sat_f64_s"),
+            I64TruncUSatF64 => (saturating_float_to_int, "i64.trunc_sat_f64_u"),
+            I32x4RelaxedTruncSF32x4 => (relaxed_simd, "i32x4.relaxed_trunc_f32x4_s"),
+            I32x4RelaxedTruncUF32x4 => (relaxed_simd, "i32x4.relaxed_trunc_f32x4_u"),
+            I32x4RelaxedTruncSF64x2Zero => (relaxed_simd, "i32x4.relaxed_trunc_f64x2_s_zero"),
+            I32x4RelaxedTruncUF64x2Zero => (relaxed_simd, "i32x4.relaxed_trunc_f64x2_u_zero"),
+            _ => return None,
+        })
+    }
+
+    /// The features needed by a binary operator, and its name in the text
+    /// format, if it isn't part of the MVP.
+    pub(crate) fn for_binop(op: BinaryOp) -> Option<(WasmFeatures, &'static str)> {
+        use BinaryOp::*;
+        let relaxed_simd = WasmFeatures::relaxed_simd();
+        Some(match op {
+            I8x16RelaxedSwizzle => (relaxed_simd, "i8x16.relaxed_swizzle"),
+            F32x4RelaxedMin => (relaxed_simd, "f32x4.relaxed_min"),
+            F32x4RelaxedMax => (relaxed_simd, "f32x4.relaxed_max"),
+            F64x2RelaxedMin => (relaxed_simd, "f64x2.relaxed_min"),
+            F64x2RelaxedMax => (relaxed_simd, "f64x2.relaxed_max"),
+            _ => return None,
+        })
+    }
+
+    /// The features needed by a ternary operator, and its name in the text
+    /// format.
+    pub(crate) fn for_ternop(op: TernaryOp) -> (WasmFeatures, &'static str) {
+        use TernaryOp::*;
+        let relaxed_simd = WasmFeatures::relaxed_simd();
+        match op {
+            F32x4RelaxedMadd => (relaxed_simd, "f32x4.relaxed_madd"),
+            F32x4RelaxedNmadd => (relaxed_simd, "f32x4.relaxed_nmadd"),
+            F64x2RelaxedMadd => (relaxed_simd, "f64x2.relaxed_madd"),
+            F64x2RelaxedNmadd => (relaxed_simd, "f64x2.relaxed_nmadd"),
+        }
+    }
+
+    fn relaxed_simd() -> WasmFeatures {
+        WasmFeatures {
+            relaxed_simd: true,
+            ..WasmFeatures::default()
+        }
+    }
+
+    /// Does this set contain every feature in `other`?
+    fn contains(&self, other: &WasmFeatures) -> bool {
+        (self.sign_extension || !other.sign_extension)
+            && (self.saturating_float_to_int || !other.saturating_float_to_int)
+            && (self.multi_value || !other.multi_value)
+            && (self.relaxed_simd || !other.relaxed_simd)
+    }
+
+    fn union(&mut self, other: &WasmFeatures) {
+        self.sign_extension |= other.sign_extension;
+        self.saturating_float_to_int |= other.saturating_float_to_int;
+        self.multi_value |= other.multi_value;
+        self.relaxed_simd |= other.relaxed_simd;
+    }
+
+    /// The name of the first feature in this set.
+    fn name(&self) -> &'static str {
+        if self.sign_extension {
+            "sign-extension"
+        } else if self.saturating_float_to_int {
+            "saturating float-to-int"
+        } else if self.multi_value {
+            "multi-value"
+        } else {
+            "relaxed SIMD"
+        }
+    }
+}
+
+impl Module {
+    /// Find which WebAssembly proposals this module's code uses.
+    pub fn used_features(&self) -> WasmFeatures {
+        let mut used = WasmFeatures::default();
+        for (_, func) in self.funcs.iter_local() {
+            let mut scan = Scan {
+                func,
+                allowed: None,
+                used: &mut used,
+                disabled: None,
+            };
+            dfs_in_order(&mut scan, func, func.entry_block().into());
+        }
+        if self.types.iter().any(|ty| ty.results().len() > 1) {
+            used.multi_value = true;
+        }
+        used
+    }
+}
+
+/// Check that a function parsed as the function at `index` only uses the
+/// `allowed` features.
+pub(crate) fn check_function(
+    func: &LocalFunction,
+    index: u32,
+    allowed: &WasmFeatures,
+) -> Result<(), DisabledFeature> {
+    let mut used = WasmFeatures::default();
+    let mut scan = Scan {
+        func,
+        allowed: Some(allowed),
+        used: &mut used,
+        disabled: None,
+    };
+    dfs_in_order(&mut scan, func, func.entry_block().into());
+    match scan.disabled {
+        Some((features, instruction)) => Err(DisabledFeature {
+            function: index,
+            instruction,
+            feature: features.name(),
+        }),
+        None => Ok(()),
+    }
+}
+
+struct Scan<'a, 'b> {
+    func: &'a LocalFunction,
+    allowed: Option<&'b WasmFeatures>,
+    used: &'b mut WasmFeatures,
+    /// The first instruction found that needs a feature which isn't allowed.
+    disabled: Option<(WasmFeatures, &'static str)>,
+}
+
+impl<'a> Visitor<'a> for Scan<'a, '_> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_unop(&mut self, e: &Unop) {
+        if let Some((features, instruction)) = WasmFeatures::for_unop(e.op) {
+            self.found(features, instruction);
+        }
+        e.visit(self);
+    }
+
+    fn visit_binop(&mut self, e: &Binop) {
+        if let Some((features, instruction)) = WasmFeatures::for_binop(e.op) {
+            self.found(features, instruction);
+        }
+        e.visit(self);
+    }
+
+    fn visit_ternop(&mut self, e: &Ternop) {
+        let (features, instruction) = WasmFeatures::for_ternop(e.op);
+        self.found(features, instruction);
+        e.visit(self);
+    }
+
+    fn visit_block(&mut self, e: &Block) {
+        if !e.params.is_empty() || e.results.len() > 1 {
+            let multi_value = WasmFeatures {
+                multi_value: true,
+                ..WasmFeatures::default()
+            };
+            match e.kind {
+                BlockKind::Block => self.found(multi_value, "block"),
+                BlockKind::Loop => self.found(multi_value, "loop"),
+                BlockKind::IfElse => self.found(multi_value, "if"),
+                BlockKind::Try => self.found(multi_value, "try"),
+                BlockKind::FunctionEntry => {}
+            }
+        }
+        e.visit(self);
+    }
+}
+
+impl Scan<'_, '_> {
+    fn found(&mut self, features: WasmFeatures, instruction: &'static str) {
+        self.used.union(&features);
+        let allowed = self.allowed.is_none_or(|a| a.contains(&features));
+        if !allowed && self.disabled.is_none() {
+            self.disabled = Some((features, instruction));
+        }
+    }
+}
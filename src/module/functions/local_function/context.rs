@@ -3,7 +3,7 @@
 use crate::error::{ErrorKind, Result};
 use crate::ir::{Block, BlockId, BlockKind, Drop, Expr, ExprId, WithSideEffects};
 use crate::module::functions::{FunctionId, LocalFunction};
-use crate::module::Module;
+use crate::module::{MemoryId, Module, TagId};
 use crate::parse::IndicesToIds;
 use crate::ty::ValType;
 use failure::Fail;
@@ -27,6 +27,10 @@ pub struct ControlFrame {
 
     /// The id of this control frame's block.
     pub block: BlockId,
+
+    /// The operands a `block` or `loop` takes as its parameters, which are
+    /// evaluated just before it.
+    pub args: Vec<ExprId>,
 }
 
 /// The operand stack.
@@ -62,6 +66,13 @@ pub struct ValidationContext<'a> {
 
     /// If we're currently parsing an if/else expression, where we're at
     pub if_else: Vec<IfElseState>,
+
+    /// If we're currently parsing a try expression, where we're at
+    pub tries: Vec<TryState>,
+
+    /// The memory named by the current instruction's memory argument, if it
+    /// names one rather than using memory 0
+    pub memory: Option<MemoryId>,
 }
 
 #[derive(Debug)]
@@ -69,6 +80,21 @@ pub struct IfElseState {
     pub condition: ExprId,
     pub consequent: BlockId,
     pub alternative: Option<BlockId>,
+    /// The operands the `if` takes as its parameters, which are evaluated
+    /// just before it.
+    pub args: Vec<ExprId>,
+}
+
+#[derive(Debug)]
+pub struct TryState {
+    pub body: BlockId,
+    pub results: Box<[ValType]>,
+    pub catch_tags: Vec<TagId>,
+    pub catches: Vec<BlockId>,
+    pub catch_all: Option<BlockId>,
+    /// The operands the `try` takes as its parameters, which are evaluated
+    /// just before it.
+    pub args: Vec<ExprId>,
 }
 
 impl<'a> ValidationContext<'a> {
@@ -89,6 +115,8 @@ impl<'a> ValidationContext<'a> {
             operands,
             controls,
             if_else: Vec::new(),
+            tries: Vec::new(),
+            memory: None,
         }
     }
 
@@ -96,55 +124,60 @@ impl<'a> ValidationContext<'a> {
     where
         E: Copy + Into<ExprId>,
     {
-        impl_push_operand(&mut self.operands, op, expr);
+        impl_push_operand(self.operands, op, expr);
     }
 
     pub fn pop_operand(&mut self) -> Result<(Option<ValType>, ExprId)> {
-        impl_pop_operand(&mut self.operands, &mut self.controls)
+        impl_pop_operand(self.operands, self.controls)
     }
 
     pub fn pop_operand_expected(
         &mut self,
         expected: Option<ValType>,
     ) -> Result<(Option<ValType>, ExprId)> {
-        impl_pop_operand_expected(&mut self.operands, &mut self.controls, expected)
+        impl_pop_operand_expected(self.operands, self.controls, expected)
     }
 
     pub fn push_operands(&mut self, types: &[ValType], expr: ExprId) {
-        if types.is_empty() && self.controls.len() > 0 {
+        if types.is_empty() && !self.controls.is_empty() {
             self.add_to_current_frame_block(expr);
         } else {
-            impl_push_operands(&mut self.operands, types, expr)
+            impl_push_operands(self.operands, types, expr)
         }
     }
 
     pub fn pop_operands(&mut self, expected: &[ValType]) -> Result<Vec<ExprId>> {
-        impl_pop_operands(&mut self.operands, &self.controls, expected)
+        impl_pop_operands(self.operands, self.controls, expected)
     }
 
+    /// Push a new control frame for a block taking `params` and producing
+    /// `results`.
+    ///
+    /// Branches to a loop supply the loop's parameters, while branches to
+    /// any other kind of block supply its results.
     pub fn push_control(
         &mut self,
         kind: BlockKind,
-        label_types: Box<[ValType]>,
-        end_types: Box<[ValType]>,
+        params: Box<[ValType]>,
+        results: Box<[ValType]>,
     ) -> BlockId {
         impl_push_control(
             kind,
             self.func,
             self.controls,
             self.operands,
-            label_types,
-            end_types,
+            params,
+            results,
         )
     }
 
     pub fn pop_control(&mut self) -> Result<(Box<[ValType]>, BlockId)> {
-        let (frame, exprs) = impl_pop_control(&mut self.controls, &mut self.operands)?;
+        let (frame, exprs) = impl_pop_control(self.controls, self.operands)?;
         if frame.unreachable.is_none() {
             self.func
                 .block_mut(frame.block)
                 .exprs
-                .extend(exprs.iter().cloned());
+                .extend(exprs.iter().rev().cloned());
         }
         Ok((frame.end_types, frame.block))
     }
@@ -312,16 +345,21 @@ fn impl_push_control(
     func: &mut LocalFunction,
     controls: &mut ControlStack,
     operands: &OperandStack,
-    label_types: Box<[ValType]>,
-    end_types: Box<[ValType]>,
+    params: Box<[ValType]>,
+    results: Box<[ValType]>,
 ) -> BlockId {
-    let block = func.alloc(Block::new(kind, label_types.clone(), end_types.clone()));
+    let label_types = match kind {
+        BlockKind::Loop => params.clone(),
+        _ => results.clone(),
+    };
+    let block = func.alloc(Block::new(kind, params, results.clone()));
     let frame = ControlFrame {
         label_types,
-        end_types,
+        end_types: results,
         height: operands.len(),
         unreachable: None,
         block,
+        args: Vec::new(),
     };
     controls.push(frame);
     block
@@ -0,0 +1,366 @@
+//! Comparing the bodies of two functions.
+
+use super::display::{tree, Node};
+use crate::encode::leb128_signed_len;
+use crate::ir::*;
+use crate::map::IdHashMap;
+use crate::{FunctionId, FunctionKind, Local, LocalFunction, Module};
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// A difference between two functions' bodies, as found by `diff_functions`.
+///
+/// Each subtree is given with its text in the folded text format, on a
+/// single line, and a rough estimate of its encoded size in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubtreeChange {
+    /// A subtree of the old function was replaced by a different one.
+    Changed {
+        /// The root of the subtree in the old function.
+        old: ExprId,
+        /// The root of the subtree in the new function.
+        new: ExprId,
+        /// The old subtree's text.
+        old_text: String,
+        /// The new subtree's text.
+        new_text: String,
+        /// The old subtree's estimated size.
+        old_size: usize,
+        /// The new subtree's estimated size.
+        new_size: usize,
+    },
+    /// A subtree of the old function was removed.
+    Deleted {
+        /// The root of the subtree in the old function.
+        old: ExprId,
+        /// The subtree's text.
+        text: String,
+        /// The subtree's estimated size.
+        size: usize,
+    },
+    /// A subtree was added to the new function.
+    Inserted {
+        /// The root of the subtree in the new function.
+        new: ExprId,
+        /// The subtree's text.
+        text: String,
+        /// The subtree's estimated size.
+        size: usize,
+    },
+}
+
+/// The differences between two functions' bodies.
+///
+/// Displaying this prints both bodies in the folded text format, as a
+/// unified diff: unchanged subtrees are printed on a single line, starting
+/// with a space, and changed ones are printed once starting with `-` and
+/// again starting with `+`.
+#[derive(Debug, Clone)]
+pub struct FunctionDiff {
+    changes: Vec<SubtreeChange>,
+    old_size: usize,
+    new_size: usize,
+    lines: Vec<(char, String)>,
+}
+
+impl FunctionDiff {
+    /// The changed, deleted and inserted subtrees, in the order they appear
+    /// in the functions.
+    pub fn changes(&self) -> &[SubtreeChange] {
+        &self.changes
+    }
+
+    /// Are the two functions' bodies the same?
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// A rough estimate of the old function body's encoded size, in bytes.
+    pub fn old_size(&self) -> usize {
+        self.old_size
+    }
+
+    /// A rough estimate of the new function body's encoded size, in bytes.
+    pub fn new_size(&self) -> usize {
+        self.new_size
+    }
+
+    /// How much bigger the new function body is estimated to be than the old
+    /// one, in bytes.
+    pub fn size_delta(&self) -> i64 {
+        self.new_size as i64 - self.old_size as i64
+    }
+}
+
+impl fmt::Display for FunctionDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- old (~{} bytes)", self.old_size)?;
+        writeln!(f, "+++ new (~{} bytes)", self.new_size)?;
+        for (sign, line) in self.lines.iter() {
+            writeln!(f, "{}{}", sign, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the bodies of two local functions, which can be in different
+/// modules.
+///
+/// The bodies are compared as trees, so an expression is only reported as
+/// changed if its operator or immediates are, and otherwise the differences
+/// between its operands are reported instead. Operands are lined up by the
+/// longest sequence of identical subtrees they have in common.
+///
+/// Locals are compared by position rather than by id: the function's
+/// arguments first, in order, and then its other locals in the order they're
+/// first used. Other ids, such as those of functions and globals, are
+/// compared by their index, so they should refer to the same things in both
+/// modules.
+///
+/// # Panics
+///
+/// Panics if either function isn't a local function.
+pub fn diff_functions(a: (&Module, FunctionId), b: (&Module, FunctionId)) -> FunctionDiff {
+    let old = body(a);
+    let new = body(b);
+    let mut differ = Differ {
+        changes: Vec::new(),
+        lines: Vec::new(),
+    };
+    differ.subtree(&old, &new, 0);
+    FunctionDiff {
+        changes: differ.changes,
+        old_size: old.size,
+        new_size: new.size,
+        lines: differ.lines,
+    }
+}
+
+fn body((module, id): (&Module, FunctionId)) -> Subtree {
+    let func = match &module.funcs.get(id).kind {
+        FunctionKind::Local(func) => func,
+        _ => panic!("function {} isn't a local function", id.index()),
+    };
+
+    let mut locals = Locals {
+        func,
+        numbers: IdHashMap::default(),
+    };
+    for arg in func.args.iter() {
+        locals.visit_local_id(arg);
+    }
+    dfs_in_order(&mut locals, func, func.entry_block().into());
+
+    Subtree::new(func, tree(func, Some(&locals.numbers)))
+}
+
+/// Numbers a function's locals in the order they're first seen.
+struct Locals<'a> {
+    func: &'a LocalFunction,
+    numbers: IdHashMap<Local, usize>,
+}
+
+impl<'a> Visitor<'a> for Locals<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_local_id(&mut self, id: &LocalId) {
+        let next = self.numbers.len();
+        self.numbers.entry(*id).or_insert(next);
+    }
+}
+
+/// An expression and its operands, along with what's needed to compare it.
+struct Subtree {
+    id: ExprId,
+    head: String,
+    operands: Vec<Subtree>,
+    /// A hash of the whole subtree's structure.
+    hash: u64,
+    /// The estimated encoded size of the whole subtree.
+    size: usize,
+}
+
+impl Subtree {
+    fn new(func: &LocalFunction, node: Node) -> Subtree {
+        let operands = node
+            .operands
+            .into_iter()
+            .map(|n| Subtree::new(func, n))
+            .collect::<Vec<_>>();
+        let mut hasher = DefaultHasher::new();
+        node.head.hash(&mut hasher);
+        for operand in operands.iter() {
+            operand.hash.hash(&mut hasher);
+        }
+        let size = estimate(func.get(node.id)) + operands.iter().map(|o| o.size).sum::<usize>();
+        Subtree {
+            id: node.id,
+            head: node.head,
+            operands,
+            hash: hasher.finish(),
+            size,
+        }
+    }
+
+    /// This subtree on a single line.
+    fn text(&self) -> String {
+        let mut text = format!("({}", self.head);
+        for operand in self.operands.iter() {
+            text.push(' ');
+            text.push_str(&operand.text());
+        }
+        text.push(')');
+        text
+    }
+}
+
+/// A rough estimate of the encoded size of `expr`, not counting its operands.
+fn estimate(expr: &Expr) -> usize {
+    match expr {
+        Expr::Block(b) => match b.kind {
+            BlockKind::FunctionEntry | BlockKind::IfElse | BlockKind::Try => 1,
+            BlockKind::Block | BlockKind::Loop => 3,
+        },
+        Expr::IfElse(_) => 2,
+        Expr::Const(c) => match c.value {
+            Value::I32(n) => 1 + leb128_signed_len(i64::from(n)),
+            Value::I64(n) => 1 + leb128_signed_len(n),
+            Value::F32(_) => 5,
+            Value::F64(_) => 9,
+            Value::V128(_) => 18,
+        },
+        Expr::BrTable(e) => 3 + e.blocks.len(),
+        Expr::Load(e) => 2 + leb128_signed_len(i64::from(e.arg.offset)),
+        Expr::Store(e) => 2 + leb128_signed_len(i64::from(e.arg.offset)),
+        Expr::Binop(_)
+        | Expr::Unop(_)
+        | Expr::Drop(_)
+        | Expr::Select(_)
+        | Expr::Return(_)
+        | Expr::Unreachable(_)
+        | Expr::WithSideEffects(_) => 1,
+        _ => 2,
+    }
+}
+
+struct Differ {
+    changes: Vec<SubtreeChange>,
+    lines: Vec<(char, String)>,
+}
+
+impl Differ {
+    fn line(&mut self, sign: char, depth: usize, text: &str) {
+        self.lines
+            .push((sign, format!("{:1$}{2}", "", depth * 2, text)));
+    }
+
+    fn subtree(&mut self, old: &Subtree, new: &Subtree, depth: usize) {
+        if old.hash == new.hash {
+            self.line(' ', depth, &old.text());
+            return;
+        }
+        if old.head != new.head {
+            let (old_text, new_text) = (old.text(), new.text());
+            self.line('-', depth, &old_text);
+            self.line('+', depth, &new_text);
+            self.changes.push(SubtreeChange::Changed {
+                old: old.id,
+                new: new.id,
+                old_text,
+                new_text,
+                old_size: old.size,
+                new_size: new.size,
+            });
+            return;
+        }
+        self.line(' ', depth, &format!("({}", old.head));
+        self.operands(&old.operands, &new.operands, depth + 1);
+        self.line(' ', depth, ")");
+    }
+
+    fn operands(&mut self, old: &[Subtree], new: &[Subtree], depth: usize) {
+        let (mut i, mut j) = (0, 0);
+        let end = (old.len(), new.len());
+        for (a, b) in common(old, new).into_iter().chain(Some(end)) {
+            // Pair up whatever's between the common subtrees, and report
+            // anything left over as deleted or inserted.
+            let (old_gap, new_gap) = (&old[i..a], &new[j..b]);
+            let paired = cmp::min(old_gap.len(), new_gap.len());
+            for (o, n) in old_gap.iter().zip(new_gap) {
+                self.subtree(o, n, depth);
+            }
+            for o in old_gap[paired..].iter() {
+                let text = o.text();
+                self.line('-', depth, &text);
+                self.changes.push(SubtreeChange::Deleted {
+                    old: o.id,
+                    text,
+                    size: o.size,
+                });
+            }
+            for n in new_gap[paired..].iter() {
+                let text = n.text();
+                self.line('+', depth, &text);
+                self.changes.push(SubtreeChange::Inserted {
+                    new: n.id,
+                    text,
+                    size: n.size,
+                });
+            }
+
+            if (a, b) != end {
+                self.line(' ', depth, &old[a].text());
+            }
+            i = a + 1;
+            j = b + 1;
+        }
+    }
+}
+
+/// The indices of the longest sequence of identical subtrees common to `old`
+/// and `new`.
+fn common(old: &[Subtree], new: &[Subtree]) -> Vec<(usize, usize)> {
+    let same = |a: &Subtree, b: &Subtree| a.hash == b.hash;
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same(a, b))
+        .count();
+    let o = &old[prefix..old.len() - suffix];
+    let n = &new[prefix..new.len() - suffix];
+
+    // `lengths[i][j]` is the length of the longest common sequence of
+    // `o[i..]` and `n[j..]`.
+    let mut lengths = vec![vec![0; n.len() + 1]; o.len() + 1];
+    for i in (0..o.len()).rev() {
+        for j in (0..n.len()).rev() {
+            lengths[i][j] = if same(&o[i], &n[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lengths[i + 1][j], lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = (0..prefix).map(|i| (i, i)).collect::<Vec<_>>();
+    let (mut i, mut j) = (0, 0);
+    while i < o.len() && j < n.len() {
+        if same(&o[i], &n[j]) {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (old.len() - suffix + k, new.len() - suffix + k)));
+    pairs
+}
@@ -1,7 +1,9 @@
 //! Displaying IR.
 
 use crate::ir::*;
+use crate::map::IdHashMap;
 use crate::module::functions::{Function, FunctionKind, ImportedFunction, LocalFunction};
+use crate::{Local, LocalId};
 use id_arena::Id;
 use std::mem;
 
@@ -21,8 +23,8 @@ impl DisplayIr for Function {
         assert_eq!(indent, 0);
         match self.kind {
             FunctionKind::Import(ref i) => i.display_ir(f, &(), indent),
-            FunctionKind::Local(ref l) => l.display_ir(f, &(), indent),
-            FunctionKind::Uninitialized(_) => unreachable!(),
+            FunctionKind::Local(ref l) => l.display_ir(f, &DisplayOptions::default(), indent),
+            FunctionKind::Uninitialized(_) => f.push_str("<uninitialized>"),
         }
     }
 }
@@ -36,85 +38,252 @@ impl DisplayIr for ImportedFunction {
     }
 }
 
+/// Options for displaying a local function's IR with
+/// `LocalFunction::display`.
+///
+/// Each line starts with the id of the first expression on it, as a comment.
+/// An expression is printed on one line if it fits within the width, and
+/// otherwise its operands are each printed on their own lines, indented past
+/// the expression's opening parenthesis, with the closing parenthesis on a
+/// line of its own lined up with the opening one.
+#[derive(Clone, Debug)]
+pub struct DisplayOptions {
+    width: usize,
+    indent: usize,
+    max_depth: Option<usize>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> DisplayOptions {
+        DisplayOptions {
+            width: 0,
+            indent: 2,
+            max_depth: None,
+        }
+    }
+}
+
+impl DisplayOptions {
+    /// Create the default options, which print every expression on its own
+    /// line.
+    pub fn new() -> DisplayOptions {
+        DisplayOptions::default()
+    }
+
+    /// Sets the width lines are kept within, including the expression ids at
+    /// their start, wherever an expression can be broken over lines to fit.
+    ///
+    /// By default this is 0, so every expression is broken.
+    pub fn width(&mut self, width: usize) -> &mut DisplayOptions {
+        self.width = width;
+        self
+    }
+
+    /// Sets how many columns the operands of a broken expression are indented
+    /// past its opening parenthesis. With an indent of 1 they line up under
+    /// the operator's name.
+    ///
+    /// By default this is 2.
+    pub fn indent(&mut self, indent: usize) -> &mut DisplayOptions {
+        self.indent = indent;
+        self
+    }
+
+    /// Sets how many levels of expressions are printed, counting the
+    /// function's entry block as the first, or `None` to print them all.
+    ///
+    /// Anything deeper is replaced by `(... N exprs)`, where `N` is how many
+    /// expressions were left out.
+    ///
+    /// By default this is `None`.
+    pub fn max_depth(&mut self, depth: Option<usize>) -> &mut DisplayOptions {
+        self.max_depth = depth;
+        self
+    }
+}
+
 impl DisplayIr for LocalFunction {
-    type Context = ();
+    type Context = DisplayOptions;
 
-    fn display_ir(&self, f: &mut String, _: &(), indent: usize) {
+    fn display_ir(&self, f: &mut String, opts: &DisplayOptions, indent: usize) {
         assert_eq!(indent, 0);
 
-        let mut visitor = DisplayExpr {
-            func: self,
-            f,
-            indent,
-            first_arg: false,
-            line: 0,
-        };
+        let entry = tree(self, None);
+
         // leading spaces to leave room for leading expression ids
-        visitor.f.push_str("        (func\n");
-        self.entry_block().visit(&mut visitor);
-        visitor.f.push_str("        )");
+        f.push_str("        (func\n");
+        Layout { opts, out: f }.node(&entry, 1);
+        f.push_str("        )");
     }
 }
 
+/// Build the tree of `Node`s for `func`, starting at its entry block.
+///
+/// Locals are numbered as in `locals`, if it's given and has them, and
+/// otherwise by their ids.
+pub(crate) fn tree(func: &LocalFunction, locals: Option<&IdHashMap<Local, usize>>) -> Node {
+    let mut head = String::new();
+    let mut visitor = DisplayExpr {
+        func,
+        f: &mut head,
+        children: Vec::new(),
+        locals,
+    };
+    visitor.expr_id(func.entry_block().into());
+    visitor.children.pop().unwrap()
+}
+
+/// Builds the tree of `Node`s for a function.
 pub(crate) struct DisplayExpr<'a, 'b> {
     pub(crate) func: &'a LocalFunction,
+    /// The text of the expression currently being visited, apart from its
+    /// operands.
     pub(crate) f: &'b mut String,
-    indent: usize,
-    first_arg: bool,
-    line: usize,
+    /// The operands of the expression currently being visited, so far.
+    children: Vec<Node>,
+    locals: Option<&'b IdHashMap<Local, usize>>,
+}
+
+/// An expression to display.
+pub(crate) struct Node {
+    pub(crate) id: ExprId,
+    /// The expression's name and immediates, such as `local.set 1`.
+    pub(crate) head: String,
+    pub(crate) operands: Vec<Node>,
+}
+
+impl Node {
+    /// The number of expressions in this tree.
+    fn size(&self) -> usize {
+        1 + self.operands.iter().map(|n| n.size()).sum::<usize>()
+    }
 }
 
 impl DisplayExpr<'_, '_> {
     // Prints the index of ids such as memories, locals, globals, etc.
     pub(crate) fn id<T>(&mut self, id: Id<T>) {
-        self.f.push_str(" ");
+        self.f.push(' ');
         self.f.push_str(&id.index().to_string());
     }
 
-    fn line(&mut self) {
-        self.line += 1;
-        self.f.push_str("\n");
+    pub(crate) fn local(&mut self, id: LocalId) {
+        match self.locals.and_then(|locals| locals.get(&id)) {
+            Some(index) => {
+                self.f.push(' ');
+                self.f.push_str(&index.to_string());
+            }
+            None => self.id(id),
+        }
     }
 
     pub(crate) fn expr_id(&mut self, id: ExprId) {
-        // If we're the first argument of a previous expression, then we start
-        // ourselves on a new line. Otherwise we're already starting on a line.
-        let first_arg = mem::replace(&mut self.first_arg, true);
-        if first_arg {
-            self.line();
+        // Visit the expression with fresh buffers for its own text and
+        // operands, then restore the enclosing expression's and add this one
+        // to its operands.
+        let head = std::mem::take(self.f);
+        let siblings = std::mem::take(&mut self.children);
+        id.visit(self);
+        let node = Node {
+            id,
+            head: mem::replace(self.f, head),
+            operands: mem::replace(&mut self.children, siblings),
+        };
+        self.children.push(node);
+    }
+}
+
+// Note that the main body of `DisplayExpr` is generated by `#[walrus_expr]`
+
+/// The width of the expression id starting each line, and the space after it.
+const ID_WIDTH: usize = 8;
+
+struct Layout<'a> {
+    opts: &'a DisplayOptions,
+    out: &'a mut String,
+}
+
+impl Layout<'_> {
+    /// Print `node`, at nesting level `depth`, starting on a new line and
+    /// ending with a newline.
+    fn node(&mut self, node: &Node, depth: usize) {
+        self.out.push_str("(;");
+        self.out.push_str(&format!("{:3}", node.id.index()));
+        self.out.push_str(";)");
+        self.indent(depth);
+
+        let column = ID_WIDTH + depth * self.opts.indent;
+        let budget = self.opts.width.saturating_sub(column);
+        if node.operands.is_empty()
+            || self.elided(depth)
+            || self.flat_len(node, depth, budget).is_some()
+        {
+            self.flat(node, depth);
+            self.out.push('\n');
+            return;
         }
 
-        // Start all lines with the id of this expression, used as a handy
-        // debugging reference.
-        self.f.push_str("(;");
-        self.f.push_str(&format!("{:3}", id.index()));
-        self.f.push_str(";)");
-
-        // Start the s-expression of this expression with a properly indented
-        // parentheses. After recursing, which prints everything, we push a new
-        // line if we had any arguments. All instructions end with a newline
-        // afterwards too!
-        self.indent += 1;
-        self.indent();
-        self.f.push_str("(");
-        let start = self.line;
-        id.visit(self);
-        if start != self.line {
-            self.f.push_str("       ");
-            self.indent();
+        self.out.push('(');
+        self.out.push_str(&node.head);
+        self.out.push('\n');
+        for operand in node.operands.iter() {
+            self.node(operand, depth + 1);
         }
-        self.indent -= 1;
-        self.f.push_str(")");
-        self.line();
-        self.first_arg = false;
+        self.out.push_str("       ");
+        self.indent(depth);
+        self.out.push_str(")\n");
     }
 
-    fn indent(&mut self) {
-        self.f.push_str(" ");
-        for _ in 0..self.indent {
-            self.f.push_str("  ");
+    /// Print `node` on a single line.
+    fn flat(&mut self, node: &Node, depth: usize) {
+        if self.elided(depth) {
+            self.out.push_str(&elision(node));
+            return;
+        }
+        self.out.push('(');
+        self.out.push_str(&node.head);
+        for operand in node.operands.iter() {
+            self.out.push(' ');
+            self.flat(operand, depth + 1);
+        }
+        self.out.push(')');
+    }
+
+    /// The length of `node` printed on a single line, if it's at most
+    /// `budget`.
+    fn flat_len(&self, node: &Node, depth: usize, budget: usize) -> Option<usize> {
+        if self.elided(depth) {
+            let len = elision(node).len();
+            return if len <= budget { Some(len) } else { None };
+        }
+        let mut len = node.head.len() + 2;
+        for operand in node.operands.iter() {
+            if len >= budget {
+                return None;
+            }
+            len += 1 + self.flat_len(operand, depth + 1, budget - len - 1)?;
+        }
+        if len <= budget {
+            Some(len)
+        } else {
+            None
+        }
+    }
+
+    fn elided(&self, depth: usize) -> bool {
+        self.opts.max_depth.is_some_and(|max| depth > max)
+    }
+
+    fn indent(&mut self, depth: usize) {
+        self.out.push(' ');
+        for _ in 0..depth * self.opts.indent {
+            self.out.push(' ');
         }
     }
 }
 
-// Note that the main body of `DisplayExpr` is generated by `#[walrus_expr]`
+fn elision(node: &Node) -> String {
+    match node.size() {
+        1 => "(... 1 expr)".to_string(),
+        n => format!("(... {} exprs)", n),
+    }
+}
@@ -4,16 +4,19 @@ use crate::ir::*;
 use crate::map::IdHashMap;
 use crate::module::functions::LocalFunction;
 use crate::module::memories::MemoryId;
+use crate::module::types::ModuleTypes;
 use crate::ty::ValType;
 
 pub(crate) fn run(
     func: &LocalFunction,
+    types: &ModuleTypes,
     indices: &IdsToIndices,
     local_indices: &IdHashMap<Local, u32>,
     encoder: &mut Encoder,
 ) {
     let mut v = Emit {
         func,
+        types,
         indices,
         id: func.entry_block().into(),
         blocks: vec![],
@@ -30,6 +33,9 @@ struct Emit<'a, 'b> {
     // The id of the current expression.
     id: ExprId,
 
+    // Needed to find the type index of blocks with multi-value types.
+    types: &'a ModuleTypes,
+
     // Needed so we can map locals to their indices.
     indices: &'a IdsToIndices,
     local_indices: &'a IdHashMap<Local, u32>,
@@ -59,8 +65,11 @@ impl Emit<'_, '_> {
         match self.func.get(id) {
             Const(e) => e.value.emit(self.encoder),
             Block(e) => self.visit_block(e),
+            // The block's parameters are already on the stack when it starts.
+            BlockParam(_) => {}
             BrTable(e) => self.visit_br_table(e),
             IfElse(e) => self.visit_if_else(e),
+            Try(e) => self.visit_try(e),
 
             Drop(e) => {
                 self.visit(e.expr);
@@ -105,7 +114,6 @@ impl Emit<'_, '_> {
                 let idx = self.indices.get_data_index(e.data);
                 self.encoder.u32(idx);
                 let idx = self.indices.get_memory_index(e.memory);
-                assert_eq!(idx, 0);
                 self.encoder.u32(idx);
             }
 
@@ -115,16 +123,42 @@ impl Emit<'_, '_> {
                 self.encoder.u32(idx);
             }
 
+            TableInit(e) => {
+                self.visit(e.dst);
+                self.visit(e.src);
+                self.visit(e.len);
+                self.encoder.raw(&[0xfc, 0x0c]); // table.init
+                let idx = self.indices.get_element_index(e.elem);
+                self.encoder.u32(idx);
+                let idx = self.indices.get_table_index(e.table);
+                self.encoder.u32(idx);
+            }
+
+            ElemDrop(e) => {
+                self.encoder.raw(&[0xfc, 0x0d]); // elem.drop
+                let idx = self.indices.get_element_index(e.elem);
+                self.encoder.u32(idx);
+            }
+
+            TableCopy(e) => {
+                self.visit(e.dst_offset);
+                self.visit(e.src_offset);
+                self.visit(e.len);
+                self.encoder.raw(&[0xfc, 0x0e]); // table.copy
+                let idx = self.indices.get_table_index(e.dst);
+                self.encoder.u32(idx);
+                let idx = self.indices.get_table_index(e.src);
+                self.encoder.u32(idx);
+            }
+
             MemoryCopy(e) => {
                 self.visit(e.dst_offset);
                 self.visit(e.src_offset);
                 self.visit(e.len);
                 self.encoder.raw(&[0xfc, 0x0a]); // memory.copy
-                let idx = self.indices.get_memory_index(e.src);
-                assert_eq!(idx, 0);
-                self.encoder.u32(idx);
                 let idx = self.indices.get_memory_index(e.dst);
-                assert_eq!(idx, 0);
+                self.encoder.u32(idx);
+                let idx = self.indices.get_memory_index(e.src);
                 self.encoder.u32(idx);
             }
 
@@ -134,7 +168,6 @@ impl Emit<'_, '_> {
                 self.visit(e.len);
                 self.encoder.raw(&[0xfc, 0x0b]); // memory.fill
                 let idx = self.indices.get_memory_index(e.memory);
-                assert_eq!(idx, 0);
                 self.encoder.u32(idx);
             }
 
@@ -229,108 +262,135 @@ impl Emit<'_, '_> {
                     F64Max => self.encoder.byte(0xa5),
                     F64Copysign => self.encoder.byte(0xa6),
 
-                    I8x16ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x07, idx]),
-                    I16x8ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x0b, idx]),
-                    I32x4ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x0e, idx]),
-                    I64x2ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x11, idx]),
-                    F32x4ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x14, idx]),
-                    F64x2ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x17, idx]),
-
-                    I8x16Eq => self.simd(0x18),
-                    I8x16Ne => self.simd(0x19),
-                    I8x16LtS => self.simd(0x1a),
-                    I8x16LtU => self.simd(0x1b),
-                    I8x16GtS => self.simd(0x1c),
-                    I8x16GtU => self.simd(0x1d),
-                    I8x16LeS => self.simd(0x1e),
-                    I8x16LeU => self.simd(0x1f),
-                    I8x16GeS => self.simd(0x20),
-                    I8x16GeU => self.simd(0x21),
-
-                    I16x8Eq => self.simd(0x22),
-                    I16x8Ne => self.simd(0x23),
-                    I16x8LtS => self.simd(0x24),
-                    I16x8LtU => self.simd(0x25),
-                    I16x8GtS => self.simd(0x26),
-                    I16x8GtU => self.simd(0x27),
-                    I16x8LeS => self.simd(0x28),
-                    I16x8LeU => self.simd(0x29),
-                    I16x8GeS => self.simd(0x2a),
-                    I16x8GeU => self.simd(0x2b),
-
-                    I32x4Eq => self.simd(0x2c),
-                    I32x4Ne => self.simd(0x2d),
-                    I32x4LtS => self.simd(0x2e),
-                    I32x4LtU => self.simd(0x2f),
-                    I32x4GtS => self.simd(0x30),
-                    I32x4GtU => self.simd(0x31),
-                    I32x4LeS => self.simd(0x32),
-                    I32x4LeU => self.simd(0x33),
-                    I32x4GeS => self.simd(0x34),
-                    I32x4GeU => self.simd(0x35),
-
-                    F32x4Eq => self.simd(0x40),
-                    F32x4Ne => self.simd(0x41),
-                    F32x4Lt => self.simd(0x42),
-                    F32x4Gt => self.simd(0x43),
-                    F32x4Le => self.simd(0x44),
-                    F32x4Ge => self.simd(0x45),
-
-                    F64x2Eq => self.simd(0x46),
-                    F64x2Ne => self.simd(0x47),
-                    F64x2Lt => self.simd(0x48),
-                    F64x2Gt => self.simd(0x49),
-                    F64x2Le => self.simd(0x4a),
-                    F64x2Ge => self.simd(0x4b),
-
-                    V128And => self.simd(0x4d),
-                    V128Or => self.simd(0x4e),
-                    V128Xor => self.simd(0x4f),
-
-                    I8x16Shl => self.simd(0x54),
-                    I8x16ShrS => self.simd(0x55),
-                    I8x16ShrU => self.simd(0x56),
-                    I8x16Add => self.simd(0x57),
-                    I8x16AddSaturateS => self.simd(0x58),
-                    I8x16AddSaturateU => self.simd(0x59),
-                    I8x16Sub => self.simd(0x5a),
-                    I8x16SubSaturateS => self.simd(0x5b),
-                    I8x16SubSaturateU => self.simd(0x5c),
-                    I8x16Mul => self.simd(0x5d),
-                    I16x8Shl => self.simd(0x65),
-                    I16x8ShrS => self.simd(0x66),
-                    I16x8ShrU => self.simd(0x67),
-                    I16x8Add => self.simd(0x68),
-                    I16x8AddSaturateS => self.simd(0x69),
-                    I16x8AddSaturateU => self.simd(0x6a),
-                    I16x8Sub => self.simd(0x6b),
-                    I16x8SubSaturateS => self.simd(0x6c),
-                    I16x8SubSaturateU => self.simd(0x6d),
-                    I16x8Mul => self.simd(0x6e),
-                    I32x4Shl => self.simd(0x76),
-                    I32x4ShrS => self.simd(0x77),
-                    I32x4ShrU => self.simd(0x78),
-                    I32x4Add => self.simd(0x79),
-                    I32x4Sub => self.simd(0x7c),
-                    I32x4Mul => self.simd(0x7f),
-                    I64x2Shl => self.simd(0x87),
-                    I64x2ShrS => self.simd(0x88),
-                    I64x2ShrU => self.simd(0x89),
-                    I64x2Add => self.simd(0x8a),
-                    I64x2Sub => self.simd(0x8d),
-
-                    F32x4Add => self.simd(0x9a),
-                    F32x4Sub => self.simd(0x9b),
-                    F32x4Mul => self.simd(0x9c),
-                    F32x4Div => self.simd(0x9d),
-                    F32x4Min => self.simd(0x9e),
-                    F32x4Max => self.simd(0x9f),
-                    F64x2Add => self.simd(0xa5),
-                    F64x2Sub => self.simd(0xa6),
-                    F64x2Mul => self.simd(0xa7),
-                    F64x2Div => self.simd(0xa8),
-                    F64x2Min => self.simd(0xa9),
-                    F64x2Max => self.simd(0xaa),
+                    I8x16ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x17, idx]),
+                    I16x8ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x1a, idx]),
+                    I32x4ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x1c, idx]),
+                    I64x2ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x1e, idx]),
+                    F32x4ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x20, idx]),
+                    F64x2ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x22, idx]),
+
+                    I8x16Eq => self.simd(0x23),
+                    I8x16Ne => self.simd(0x24),
+                    I8x16LtS => self.simd(0x25),
+                    I8x16LtU => self.simd(0x26),
+                    I8x16GtS => self.simd(0x27),
+                    I8x16GtU => self.simd(0x28),
+                    I8x16LeS => self.simd(0x29),
+                    I8x16LeU => self.simd(0x2a),
+                    I8x16GeS => self.simd(0x2b),
+                    I8x16GeU => self.simd(0x2c),
+
+                    I16x8Eq => self.simd(0x2d),
+                    I16x8Ne => self.simd(0x2e),
+                    I16x8LtS => self.simd(0x2f),
+                    I16x8LtU => self.simd(0x30),
+                    I16x8GtS => self.simd(0x31),
+                    I16x8GtU => self.simd(0x32),
+                    I16x8LeS => self.simd(0x33),
+                    I16x8LeU => self.simd(0x34),
+                    I16x8GeS => self.simd(0x35),
+                    I16x8GeU => self.simd(0x36),
+
+                    I32x4Eq => self.simd(0x37),
+                    I32x4Ne => self.simd(0x38),
+                    I32x4LtS => self.simd(0x39),
+                    I32x4LtU => self.simd(0x3a),
+                    I32x4GtS => self.simd(0x3b),
+                    I32x4GtU => self.simd(0x3c),
+                    I32x4LeS => self.simd(0x3d),
+                    I32x4LeU => self.simd(0x3e),
+                    I32x4GeS => self.simd(0x3f),
+                    I32x4GeU => self.simd(0x40),
+
+                    F32x4Eq => self.simd(0x41),
+                    F32x4Ne => self.simd(0x42),
+                    F32x4Lt => self.simd(0x43),
+                    F32x4Gt => self.simd(0x44),
+                    F32x4Le => self.simd(0x45),
+                    F32x4Ge => self.simd(0x46),
+
+                    F64x2Eq => self.simd(0x47),
+                    F64x2Ne => self.simd(0x48),
+                    F64x2Lt => self.simd(0x49),
+                    F64x2Gt => self.simd(0x4a),
+                    F64x2Le => self.simd(0x4b),
+                    F64x2Ge => self.simd(0x4c),
+
+                    V128And => self.simd(0x4e),
+                    V128Or => self.simd(0x50),
+                    V128Xor => self.simd(0x51),
+                    V128Andnot => self.simd(0x4f),
+
+                    I8x16Shl => self.simd(0x6b),
+                    I8x16ShrS => self.simd(0x6c),
+                    I8x16ShrU => self.simd(0x6d),
+                    I8x16Add => self.simd(0x6e),
+                    I8x16AddSaturateS => self.simd(0x6f),
+                    I8x16AddSaturateU => self.simd(0x70),
+                    I8x16Sub => self.simd(0x71),
+                    I8x16SubSaturateS => self.simd(0x72),
+                    I8x16SubSaturateU => self.simd(0x73),
+                    I8x16MinS => self.simd(0x76),
+                    I8x16MinU => self.simd(0x77),
+                    I8x16MaxS => self.simd(0x78),
+                    I8x16MaxU => self.simd(0x79),
+                    I8x16AvgrU => self.simd(0x7b),
+                    I16x8Shl => self.simd(0x8b),
+                    I16x8ShrS => self.simd(0x8c),
+                    I16x8ShrU => self.simd(0x8d),
+                    I16x8Add => self.simd(0x8e),
+                    I16x8AddSaturateS => self.simd(0x8f),
+                    I16x8AddSaturateU => self.simd(0x90),
+                    I16x8Sub => self.simd(0x91),
+                    I16x8SubSaturateS => self.simd(0x92),
+                    I16x8SubSaturateU => self.simd(0x93),
+                    I16x8Mul => self.simd(0x95),
+                    I16x8Q15MulrSatS => self.simd(0x82),
+                    I16x8MinS => self.simd(0x96),
+                    I16x8MinU => self.simd(0x97),
+                    I16x8MaxS => self.simd(0x98),
+                    I16x8MaxU => self.simd(0x99),
+                    I16x8AvgrU => self.simd(0x9b),
+                    I32x4Shl => self.simd(0xab),
+                    I32x4ShrS => self.simd(0xac),
+                    I32x4ShrU => self.simd(0xad),
+                    I32x4Add => self.simd(0xae),
+                    I32x4Sub => self.simd(0xb1),
+                    I32x4Mul => self.simd(0xb5),
+                    I32x4MinS => self.simd(0xb6),
+                    I32x4MinU => self.simd(0xb7),
+                    I32x4MaxS => self.simd(0xb8),
+                    I32x4MaxU => self.simd(0xb9),
+                    I32x4DotI16x8S => self.simd(0xba),
+                    I64x2Shl => self.simd(0xcb),
+                    I64x2ShrS => self.simd(0xcc),
+                    I64x2ShrU => self.simd(0xcd),
+                    I64x2Add => self.simd(0xce),
+                    I64x2Sub => self.simd(0xd1),
+
+                    I8x16NarrowI16x8S => self.simd(0x65),
+                    I8x16NarrowI16x8U => self.simd(0x66),
+                    I16x8NarrowI32x4S => self.simd(0x85),
+                    I16x8NarrowI32x4U => self.simd(0x86),
+
+                    F32x4Add => self.simd(0xe4),
+                    F32x4Sub => self.simd(0xe5),
+                    F32x4Mul => self.simd(0xe6),
+                    F32x4Div => self.simd(0xe7),
+                    F32x4Min => self.simd(0xe8),
+                    F32x4Max => self.simd(0xe9),
+                    F64x2Add => self.simd(0xf0),
+                    F64x2Sub => self.simd(0xf1),
+                    F64x2Mul => self.simd(0xf2),
+                    F64x2Div => self.simd(0xf3),
+                    F64x2Min => self.simd(0xf4),
+                    F64x2Max => self.simd(0xf5),
+
+                    I8x16RelaxedSwizzle => self.simd(0x100),
+                    F32x4RelaxedMin => self.simd(0x10d),
+                    F32x4RelaxedMax => self.simd(0x10e),
+                    F64x2RelaxedMin => self.simd(0x10f),
+                    F64x2RelaxedMax => self.simd(0x110),
                 }
             }
 
@@ -399,75 +459,87 @@ impl Emit<'_, '_> {
                     I64Extend16S => self.encoder.byte(0xc3),
                     I64Extend32S => self.encoder.byte(0xc4),
 
-                    I8x16Splat => self.simd(0x04),
+                    I8x16Splat => self.simd(0x0f),
                     I8x16ExtractLaneS { idx } => {
-                        self.simd(0x05);
+                        self.simd(0x15);
                         self.encoder.byte(idx);
                     }
                     I8x16ExtractLaneU { idx } => {
-                        self.simd(0x06);
+                        self.simd(0x16);
                         self.encoder.byte(idx);
                     }
-                    I16x8Splat => self.simd(0x08),
+                    I16x8Splat => self.simd(0x10),
                     I16x8ExtractLaneS { idx } => {
-                        self.simd(0x09);
+                        self.simd(0x18);
                         self.encoder.byte(idx);
                     }
                     I16x8ExtractLaneU { idx } => {
-                        self.simd(0x0a);
+                        self.simd(0x19);
                         self.encoder.byte(idx);
                     }
-                    I32x4Splat => self.simd(0x0c),
+                    I32x4Splat => self.simd(0x11),
                     I32x4ExtractLane { idx } => {
-                        self.simd(0x0d);
+                        self.simd(0x1b);
                         self.encoder.byte(idx);
                     }
-                    I64x2Splat => self.simd(0x0f),
+                    I64x2Splat => self.simd(0x12),
                     I64x2ExtractLane { idx } => {
-                        self.simd(0x10);
+                        self.simd(0x1d);
                         self.encoder.byte(idx);
                     }
-                    F32x4Splat => self.simd(0x12),
+                    F32x4Splat => self.simd(0x13),
                     F32x4ExtractLane { idx } => {
-                        self.simd(0x13);
+                        self.simd(0x1f);
                         self.encoder.byte(idx);
                     }
-                    F64x2Splat => self.simd(0x15),
+                    F64x2Splat => self.simd(0x14),
                     F64x2ExtractLane { idx } => {
-                        self.simd(0x16);
+                        self.simd(0x21);
                         self.encoder.byte(idx);
                     }
 
-                    V128Not => self.simd(0x4c),
-
-                    I8x16Neg => self.simd(0x51),
-                    I8x16AnyTrue => self.simd(0x52),
-                    I8x16AllTrue => self.simd(0x53),
-                    I16x8Neg => self.simd(0x62),
-                    I16x8AnyTrue => self.simd(0x63),
-                    I16x8AllTrue => self.simd(0x64),
-                    I32x4Neg => self.simd(0x73),
-                    I32x4AnyTrue => self.simd(0x74),
-                    I32x4AllTrue => self.simd(0x75),
-                    I64x2Neg => self.simd(0x84),
-                    I64x2AnyTrue => self.simd(0x85),
-                    I64x2AllTrue => self.simd(0x86),
-
-                    F32x4Abs => self.simd(0x95),
-                    F32x4Neg => self.simd(0x96),
-                    F32x4Sqrt => self.simd(0x97),
-                    F64x2Abs => self.simd(0xa0),
-                    F64x2Neg => self.simd(0xa1),
-                    F64x2Sqrt => self.simd(0xa2),
-
-                    I32x4TruncSF32x4Sat => self.simd(0xab),
-                    I32x4TruncUF32x4Sat => self.simd(0xac),
-                    I64x2TruncSF64x2Sat => self.simd(0xad),
-                    I64x2TruncUF64x2Sat => self.simd(0xae),
-                    F32x4ConvertSI32x4 => self.simd(0xaf),
-                    F32x4ConvertUI32x4 => self.simd(0xb0),
-                    F64x2ConvertSI64x2 => self.simd(0xb1),
-                    F64x2ConvertUI64x2 => self.simd(0xb2),
+                    V128Not => self.simd(0x4d),
+                    V128AnyTrue => self.simd(0x53),
+
+                    I8x16Neg => self.simd(0x61),
+                    I8x16Abs => self.simd(0x60),
+                    I8x16Popcnt => self.simd(0x62),
+                    I8x16AllTrue => self.simd(0x63),
+                    I16x8Neg => self.simd(0x81),
+                    I16x8Abs => self.simd(0x80),
+                    I16x8AllTrue => self.simd(0x83),
+                    I32x4Neg => self.simd(0xa1),
+                    I32x4Abs => self.simd(0xa0),
+                    I32x4AllTrue => self.simd(0xa3),
+                    I64x2Neg => self.simd(0xc1),
+                    I64x2Abs => self.simd(0xc0),
+                    I64x2AllTrue => self.simd(0xc3),
+
+                    F32x4Abs => self.simd(0xe0),
+                    F32x4Neg => self.simd(0xe1),
+                    F32x4Sqrt => self.simd(0xe3),
+                    F64x2Abs => self.simd(0xec),
+                    F64x2Neg => self.simd(0xed),
+                    F64x2Sqrt => self.simd(0xef),
+
+                    I32x4TruncSF32x4Sat => self.simd(0xf8),
+                    I32x4TruncUF32x4Sat => self.simd(0xf9),
+                    F32x4ConvertSI32x4 => self.simd(0xfa),
+                    F32x4ConvertUI32x4 => self.simd(0xfb),
+
+                    I16x8WidenLowI8x16S => self.simd(0x87),
+                    I16x8WidenHighI8x16S => self.simd(0x88),
+                    I16x8WidenLowI8x16U => self.simd(0x89),
+                    I16x8WidenHighI8x16U => self.simd(0x8a),
+                    I32x4WidenLowI16x8S => self.simd(0xa7),
+                    I32x4WidenHighI16x8S => self.simd(0xa8),
+                    I32x4WidenLowI16x8U => self.simd(0xa9),
+                    I32x4WidenHighI16x8U => self.simd(0xaa),
+
+                    I32x4RelaxedTruncSF32x4 => self.simd(0x101),
+                    I32x4RelaxedTruncUF32x4 => self.simd(0x102),
+                    I32x4RelaxedTruncSF64x2Zero => self.simd(0x103),
+                    I32x4RelaxedTruncUF64x2Zero => self.simd(0x104),
 
                     I32TruncSSatF32 => self.encoder.raw(&[0xfc, 0x00]),
                     I32TruncUSatF32 => self.encoder.raw(&[0xfc, 0x01]),
@@ -480,11 +552,32 @@ impl Emit<'_, '_> {
                 }
             }
 
+            Ternop(e) => {
+                use crate::ir::TernaryOp::*;
+
+                self.visit(e.a);
+                self.visit(e.b);
+                self.visit(e.c);
+                match e.op {
+                    F32x4RelaxedMadd => self.simd(0x105),
+                    F32x4RelaxedNmadd => self.simd(0x106),
+                    F64x2RelaxedMadd => self.simd(0x107),
+                    F64x2RelaxedNmadd => self.simd(0x108),
+                }
+            }
+
             Select(e) => {
-                self.visit(e.alternative);
                 self.visit(e.consequent);
+                self.visit(e.alternative);
                 self.visit(e.condition);
-                self.encoder.byte(0x1b); // select
+                match e.ty {
+                    Some(ty) => {
+                        self.encoder.byte(0x1c); // select t*
+                        self.encoder.usize(1);
+                        ty.emit(self.encoder, self.indices);
+                    }
+                    None => self.encoder.byte(0x1b), // select
+                }
             }
 
             Unreachable(_) => {
@@ -510,6 +603,21 @@ impl Emit<'_, '_> {
                 self.encoder.u32(target);
             }
 
+            Throw(e) => {
+                for x in e.args.iter() {
+                    self.visit(*x);
+                }
+                let idx = self.indices.get_tag_index(e.tag);
+                self.encoder.byte(0x08); // throw
+                self.encoder.u32(idx);
+            }
+
+            Rethrow(e) => {
+                let target = self.branch_target(e.block);
+                self.encoder.byte(0x09); // rethrow
+                self.encoder.u32(target);
+            }
+
             Call(e) => {
                 for x in e.args.iter() {
                     self.visit(*x);
@@ -531,6 +639,16 @@ impl Emit<'_, '_> {
                 self.encoder.u32(table);
             }
 
+            CallRef(e) => {
+                for x in e.args.iter() {
+                    self.visit(*x);
+                }
+                self.visit(e.func);
+                let idx = self.indices.get_type_index(e.ty);
+                self.encoder.byte(0x14); // call_ref
+                self.encoder.u32(idx);
+            }
+
             LocalGet(e) => {
                 let idx = self.local_indices[&e.local];
                 self.encoder.byte(0x20); // local.get
@@ -575,7 +693,7 @@ impl Emit<'_, '_> {
                     I64 { atomic: true } => self.encoder.raw(&[0xfe, 0x11]), // i64.atomic.load
                     F32 => self.encoder.byte(0x2a),                   // f32.load
                     F64 => self.encoder.byte(0x2b),                   // f64.load
-                    V128 => self.simd(0x00),
+                    V128 => self.simd(0x00),                          // v128.load
                     I32_8 { kind: SignExtend } => self.encoder.byte(0x2c),
                     I32_8 { kind: ZeroExtend } => self.encoder.byte(0x2d),
                     I32_8 {
@@ -616,7 +734,7 @@ impl Emit<'_, '_> {
                     I64 { atomic: true } => self.encoder.raw(&[0xfe, 0x18]), // i64.atomic.store
                     F32 => self.encoder.byte(0x38),                   // f32.store
                     F64 => self.encoder.byte(0x39),                   // f64.store
-                    V128 => self.simd(0x01),                          // v128.store
+                    V128 => self.simd(0x0b),                          // v128.store
                     I32_8 { atomic: false } => self.encoder.byte(0x3a), // i32.store8
                     I32_8 { atomic: true } => self.encoder.raw(&[0xfe, 0x19]), // i32.atomic.store8
                     I32_16 { atomic: false } => self.encoder.byte(0x3b), // i32.store16
@@ -757,26 +875,50 @@ impl Emit<'_, '_> {
                 let idx = self.indices.get_table_index(e.table);
                 self.encoder.u32(idx);
             }
-            RefNull(_e) => {
+            TableFill(e) => {
+                self.visit(e.start);
+                self.visit(e.value);
+                self.visit(e.len);
+                self.encoder.raw(&[0xfc, 0x11]);
+                let idx = self.indices.get_table_index(e.table);
+                self.encoder.u32(idx);
+            }
+            RefNull(e) => {
                 self.encoder.byte(0xd0);
+                e.ty.emit(self.encoder);
             }
             RefIsNull(e) => {
                 self.visit(e.value);
                 self.encoder.byte(0xd1);
             }
+            RefAsNonNull(e) => {
+                self.visit(e.value);
+                self.encoder.byte(0xd4);
+            }
+            RefFunc(e) => {
+                self.encoder.byte(0xd2);
+                let idx = self.indices.get_func_index(e.func);
+                self.encoder.u32(idx);
+            }
 
             V128Bitselect(e) => {
                 self.visit(e.v1);
                 self.visit(e.v2);
                 self.visit(e.mask);
-                self.simd(0x50);
+                self.simd(0x52);
             }
             V128Shuffle(e) => {
                 self.visit(e.lo);
                 self.visit(e.hi);
-                self.simd(0x03);
+                self.simd(0x0d);
                 self.encoder.raw(&e.indices);
             }
+            V128Swizzle(e) => {
+                self.visit(e.lanes);
+                self.visit(e.indices);
+                self.simd(0x0e);
+            }
+            Raw(e) => emit_raw(e, &e.code, self.indices, self.encoder),
         }
 
         self.id = old;
@@ -794,13 +936,13 @@ impl Emit<'_, '_> {
         match e.kind {
             BlockKind::Block => {
                 self.encoder.byte(0x02); // block
-                self.block_type(&e.results);
+                self.block_type(&e.params, &e.results);
             }
             BlockKind::Loop => {
                 self.encoder.byte(0x03); // loop
-                self.block_type(&e.results);
+                self.block_type(&e.params, &e.results);
             }
-            BlockKind::FunctionEntry | BlockKind::IfElse => {}
+            BlockKind::FunctionEntry | BlockKind::IfElse | BlockKind::Try => {}
         }
 
         for x in &e.exprs {
@@ -811,7 +953,7 @@ impl Emit<'_, '_> {
             BlockKind::Block | BlockKind::Loop | BlockKind::FunctionEntry => {
                 self.encoder.byte(0x0b); // end
             }
-            BlockKind::IfElse => {}
+            BlockKind::IfElse | BlockKind::Try => {}
         }
 
         self.blocks.pop();
@@ -822,17 +964,52 @@ impl Emit<'_, '_> {
 
         self.encoder.byte(0x04); // if
         let consequent = self.func.block(e.consequent);
-        self.block_type(&consequent.results);
+        self.block_type(&consequent.params, &consequent.results);
 
         self.visit(e.consequent);
 
-        // TODO: don't emit `else` for empty else blocks
-        self.encoder.byte(0x05); // else
-        self.visit(e.alternative);
+        // An `if` without an `else` behaves as if the `else` arm were empty,
+        // which is only valid when the arm passes its parameters through
+        // unchanged as its results.
+        let alternative = self.func.block(e.alternative);
+        if !alternative.exprs.is_empty() || alternative.params != alternative.results {
+            self.encoder.byte(0x05); // else
+            self.visit(e.alternative);
+        }
 
         self.encoder.byte(0x0b); // end
     }
 
+    fn visit_try(&mut self, e: &Try) {
+        self.encoder.byte(0x06); // try
+        let body = self.func.block(e.body);
+        self.block_type(&body.params, &body.results);
+
+        self.visit(e.body);
+
+        for (tag, handler) in e.catch_tags.iter().zip(e.catches.iter()) {
+            let idx = self.indices.get_tag_index(*tag);
+            self.encoder.byte(0x07); // catch
+            self.encoder.u32(idx);
+            self.visit(*handler);
+        }
+        if let Some(handler) = e.catch_all {
+            self.encoder.byte(0x19); // catch_all
+            self.visit(handler);
+        }
+
+        match e.delegate {
+            // The `try`'s own label is no longer in scope here, so the depth
+            // is relative to the blocks around it.
+            Some(block) => {
+                let target = self.branch_target(block);
+                self.encoder.byte(0x18); // delegate
+                self.encoder.u32(target);
+            }
+            None => self.encoder.byte(0x0b), // end
+        }
+    }
+
     fn visit_br_table(&mut self, e: &BrTable) {
         for x in e.args.iter() {
             self.visit(*x);
@@ -849,20 +1026,35 @@ impl Emit<'_, '_> {
         self.encoder.u32(default);
     }
 
-    fn block_type(&mut self, ty: &[ValType]) {
-        match ty.len() {
-            0 => self.encoder.byte(0x40),
-            1 => ty[0].emit(self.encoder),
-            _ => panic!(
-                "multiple return values not yet supported; write a transformation to \
-                 rewrite them into single value returns"
-            ),
+    fn block_type(&mut self, params: &[ValType], results: &[ValType]) {
+        match (params.len(), results.len()) {
+            (0, 0) => self.encoder.byte(0x40),
+            (0, 1) => results[0].emit(self.encoder, self.indices),
+            _ => {
+                // Blocks with parameters or multiple results are encoded as an
+                // index into the type section, as a signed LEB.
+                let ty = self
+                    .types
+                    .find(params, results)
+                    .expect("block types are checked before emitting");
+                let index = self.indices.get_type_index(ty);
+                self.encoder.i64(i64::from(index));
+            }
         }
     }
 
     fn memarg(&mut self, id: MemoryId, arg: &MemArg) {
-        assert_eq!(self.indices.get_memory_index(id), 0);
-        self.encoder.u32(arg.align.trailing_zeros());
+        // With multiple memories, bit 6 of the alignment flags says that a
+        // memory index follows. It's left out for memory 0, so that modules
+        // with a single memory are encoded as before.
+        let idx = self.indices.get_memory_index(id);
+        let align = arg.align.trailing_zeros();
+        if idx == 0 {
+            self.encoder.u32(align);
+        } else {
+            self.encoder.u32(align | 0x40);
+            self.encoder.u32(idx);
+        }
         self.encoder.u32(arg.offset);
     }
 
@@ -871,3 +1063,43 @@ impl Emit<'_, '_> {
         self.encoder.u32(opcode);
     }
 }
+
+/// Emit `code`, which is part of `raw`, with the current indices of the items
+/// it uses.
+pub(crate) fn emit_raw(raw: &Raw, code: &RawCode, indices: &IdsToIndices, encoder: &mut Encoder) {
+    let mut pos = 0;
+    for hole in code.holes.iter() {
+        encoder.raw(&code.bytes[pos..hole.start]);
+        pos = hole.end;
+
+        let index = match hole.kind {
+            RawIndex::Func => indices.get_func_index(raw.funcs[hole.item]),
+            RawIndex::Global => indices.get_global_index(raw.globals[hole.item]),
+            RawIndex::Table => indices.get_table_index(raw.tables[hole.item]),
+            RawIndex::Memory | RawIndex::MemArg { .. } => {
+                indices.get_memory_index(raw.memories[hole.item])
+            }
+            RawIndex::Type | RawIndex::HeapType => indices.get_type_index(raw.types[hole.item]),
+            RawIndex::Data => indices.get_data_index(raw.data[hole.item]),
+            RawIndex::Element => indices.get_element_index(raw.elements[hole.item]),
+            RawIndex::Tag => indices.get_tag_index(raw.tags[hole.item]),
+        };
+        if index == hole.original {
+            encoder.raw(&code.bytes[hole.start..hole.end]);
+            continue;
+        }
+        match hole.kind {
+            RawIndex::HeapType => encoder.i64(i64::from(index)),
+            RawIndex::MemArg { align, explicit } => {
+                if explicit || index != 0 {
+                    encoder.u32(align | 0x40);
+                    encoder.u32(index);
+                } else {
+                    encoder.u32(align);
+                }
+            }
+            _ => encoder.u32(index),
+        }
+    }
+    encoder.raw(&code.bytes[pos..]);
+}
@@ -0,0 +1,186 @@
+//! Inferring the types of the values that expressions leave on the stack.
+
+use super::LocalFunction;
+use crate::ir::*;
+use crate::{Module, Result, TableKind, ValType};
+use failure::bail;
+
+impl LocalFunction {
+    /// Infer the `results` of a block whose body is the given sequence of
+    /// expressions.
+    ///
+    /// The sequence is typed as the validator would type the body of a block:
+    /// the results are the values left on the stack after the last
+    /// expression. If control never reaches the end of the sequence, for
+    /// example because it ends in a `br` or `return`, then the block could
+    /// have any results, and only the values pushed after the last such
+    /// expression are returned.
+    ///
+    /// Returns an error if the sequence doesn't type check, for example if a
+    /// nested block takes parameters which aren't on the stack.
+    pub fn infer_block_results(&self, module: &Module, exprs: &[ExprId]) -> Result<Vec<ValType>> {
+        let mut stack = Vec::new();
+        let mut unreachable = false;
+        for expr in exprs {
+            let (params, results) = match self.expr_type(module, *expr)? {
+                Some(ty) => ty,
+                None => {
+                    stack.clear();
+                    unreachable = true;
+                    continue;
+                }
+            };
+            for expected in params.iter().rev() {
+                match stack.pop() {
+                    Some(actual) if actual == *expected => {}
+                    Some(actual) => bail!(
+                        "expected a {} block parameter on the stack, found {}",
+                        expected,
+                        actual
+                    ),
+                    None if unreachable => {}
+                    None => bail!("missing a {} block parameter on the stack", expected),
+                }
+            }
+            stack.extend(results);
+        }
+        Ok(stack)
+    }
+
+    /// The types of the values an expression pops off of and pushes onto the
+    /// stack, besides its own operands, or `None` if the expression never
+    /// finishes executing.
+    pub(crate) fn expr_type(
+        &self,
+        module: &Module,
+        id: ExprId,
+    ) -> Result<Option<(Vec<ValType>, Vec<ValType>)>> {
+        let results = match self.get(id) {
+            Expr::Block(e) => return Ok(Some((e.params.to_vec(), e.results.to_vec()))),
+            Expr::IfElse(e) => {
+                let consequent = self.block(e.consequent);
+                let params = consequent.params.to_vec();
+                return Ok(Some((params, consequent.results.to_vec())));
+            }
+            Expr::Try(e) => {
+                let body = self.block(e.body);
+                return Ok(Some((body.params.to_vec(), body.results.to_vec())));
+            }
+
+            Expr::Unreachable(_)
+            | Expr::Br(_)
+            | Expr::BrTable(_)
+            | Expr::Return(_)
+            | Expr::Throw(_)
+            | Expr::Rethrow(_) => return Ok(None),
+
+            // Walrus doesn't know what these do to the stack, but they're
+            // only ever a whole function body, so nothing depends on it.
+            Expr::Raw(_) => return Ok(None),
+            Expr::BrIf(e) => {
+                let block = self.block(e.block);
+                match block.kind {
+                    BlockKind::Loop => block.params.to_vec(),
+                    _ => block.results.to_vec(),
+                }
+            }
+
+            Expr::Call(e) => {
+                let ty = module.funcs.get(e.func).ty();
+                module.types.get(ty).results().to_vec()
+            }
+            Expr::CallIndirect(e) => module.types.get(e.ty).results().to_vec(),
+            Expr::CallRef(e) => module.types.get(e.ty).results().to_vec(),
+
+            Expr::Select(Select { ty: Some(ty), .. }) => vec![*ty],
+            Expr::Select(e) => match self.value_types(module, e.consequent)? {
+                Some(tys) => tys,
+                None => match self.value_types(module, e.alternative)? {
+                    Some(tys) => tys,
+                    None => return Ok(None),
+                },
+            },
+            Expr::RefAsNonNull(e) => match self.value_types(module, e.value)? {
+                Some(tys) => tys
+                    .into_iter()
+                    .map(|ty| match ty {
+                        ValType::Ref { ty, .. } => ValType::Ref {
+                            nullable: false,
+                            ty,
+                        },
+                        ty => ty,
+                    })
+                    .collect(),
+                None => return Ok(None),
+            },
+            // A block's parameters are the values of the expressions before
+            // it.
+            Expr::WithSideEffects(e) => match self.expr_type(module, e.value)? {
+                Some((_, results)) => results,
+                None => return Ok(None),
+            },
+
+            Expr::BlockParam(e) => {
+                let block = self.block(e.block);
+                vec![block.params[e.index as usize]]
+            }
+            Expr::LocalGet(e) => vec![module.locals.get(e.local).ty()],
+            Expr::LocalTee(e) => vec![module.locals.get(e.local).ty()],
+            Expr::GlobalGet(e) => vec![module.globals.get(e.global).ty],
+            Expr::Const(e) => vec![e.value.ty()],
+            Expr::Binop(e) => vec![e.op.result_type()],
+            Expr::Unop(e) => vec![e.op.result_type()],
+            Expr::Load(e) => vec![e.kind.result_type()],
+            Expr::AtomicRmw(e) => vec![e.width.result_type()],
+            Expr::Cmpxchg(e) => vec![e.width.result_type()],
+            Expr::RefNull(e) => vec![e.ty.val_type()],
+            Expr::RefFunc(_) => vec![ValType::Funcref],
+            Expr::TableGet(e) => match module.tables.get(e.table).kind {
+                TableKind::Externref(_) => vec![ValType::Externref],
+                TableKind::Function(_) => vec![ValType::Funcref],
+            },
+
+            Expr::MemorySize(_)
+            | Expr::MemoryGrow(_)
+            | Expr::TableSize(_)
+            | Expr::TableGrow(_)
+            | Expr::AtomicNotify(_)
+            | Expr::AtomicWait(_)
+            | Expr::RefIsNull(_) => vec![ValType::I32],
+
+            Expr::Ternop(_)
+            | Expr::V128Bitselect(_)
+            | Expr::V128Shuffle(_)
+            | Expr::V128Swizzle(_) => {
+                vec![ValType::V128]
+            }
+
+            Expr::Drop(_)
+            | Expr::LocalSet(_)
+            | Expr::GlobalSet(_)
+            | Expr::Store(_)
+            | Expr::MemoryInit(_)
+            | Expr::DataDrop(_)
+            | Expr::MemoryCopy(_)
+            | Expr::MemoryFill(_)
+            | Expr::TableSet(_)
+            | Expr::TableInit(_)
+            | Expr::ElemDrop(_)
+            | Expr::TableCopy(_)
+            | Expr::TableFill(_) => Vec::new(),
+        };
+        Ok(Some((Vec::new(), results)))
+    }
+
+    /// The types of the values an operand expression produces, or `None` if
+    /// it never finishes executing.
+    fn value_types(&self, module: &Module, id: ExprId) -> Result<Option<Vec<ValType>>> {
+        match self.expr_type(module, id)? {
+            Some((ref params, _)) if !params.is_empty() => {
+                bail!("blocks taking parameters cannot be used as operands")
+            }
+            Some((_, results)) => Ok(Some(results)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Measuring a function's size and shape.
+
+use super::LocalFunction;
+use crate::encode::Encoder;
+use crate::ir::*;
+
+/// Measurements of a local function, as reported by `LocalFunction::metrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionMetrics {
+    /// The number of expressions in the function's body, including the entry
+    /// block.
+    pub exprs: u64,
+
+    /// The deepest nesting of `block`, `loop` and `if` expressions in the
+    /// function's body. A body without any is at depth zero.
+    pub max_nesting_depth: u32,
+
+    /// The number of `call` and `call_indirect` expressions.
+    pub calls: u64,
+
+    /// The number of loads and stores, including atomic ones.
+    pub memory_accesses: u64,
+
+    /// The number of constants.
+    pub consts: u64,
+
+    /// The number of `block`, `loop`, `if`, branch, `return` and
+    /// `unreachable` expressions.
+    pub control_flow: u64,
+
+    /// The number of locals the function uses, including its parameters.
+    pub locals: u32,
+
+    /// The locals actually referenced by the function's body, sorted by id.
+    ///
+    /// Unlike `locals`, this doesn't include unused parameters.
+    pub used_locals: Vec<LocalId>,
+
+    /// An estimate of the size of the function's encoded body in bytes.
+    ///
+    /// Constants are measured exactly, but other immediates are assumed to be
+    /// as long as the LEB128 encoding of their id's index, and each local
+    /// which isn't a parameter is assumed to be declared on its own.
+    pub estimated_size: u64,
+}
+
+impl LocalFunction {
+    /// Measure this function's body.
+    pub fn metrics(&self) -> FunctionMetrics {
+        let used = self.used_locals();
+        let mut used_locals = used.iter().cloned().collect::<Vec<_>>();
+        used_locals.sort();
+        let mut locals = used;
+        locals.extend(self.args.iter().cloned());
+        if let Some(declared) = &self.declared_locals {
+            for (_, group) in declared {
+                locals.extend(group.iter().cloned());
+            }
+        }
+        let locals = locals.len() as u32;
+        let declared = u64::from(locals) - self.args.len() as u64;
+
+        let mut v = Measure {
+            func: self,
+            local_index_size: leb_size(u64::from(locals.saturating_sub(1))),
+            depth: 0,
+            metrics: FunctionMetrics {
+                locals,
+                used_locals,
+                estimated_size: leb_size(declared) + 2 * declared,
+                ..FunctionMetrics::default()
+            },
+        };
+        self.entry_block().visit(&mut v);
+        v.metrics
+    }
+}
+
+struct Measure<'a> {
+    func: &'a LocalFunction,
+    local_index_size: u64,
+    depth: u32,
+    metrics: FunctionMetrics,
+}
+
+impl<'expr> Visitor<'expr> for Measure<'expr> {
+    fn local_function(&self) -> &'expr LocalFunction {
+        self.func
+    }
+
+    fn visit_expr(&mut self, e: &'expr Expr) {
+        self.metrics.exprs += 1;
+        self.metrics.estimated_size += self.estimate(e);
+        self.count(e);
+        e.visit(self);
+    }
+
+    fn visit_block(&mut self, e: &Block) {
+        if e.kind == BlockKind::FunctionEntry {
+            e.visit(self);
+            return;
+        }
+        self.depth += 1;
+        if self.depth > self.metrics.max_nesting_depth {
+            self.metrics.max_nesting_depth = self.depth;
+        }
+        e.visit(self);
+        self.depth -= 1;
+    }
+}
+
+impl Measure<'_> {
+    /// Count `e` towards the category it belongs to, if any.
+    fn count(&mut self, e: &Expr) {
+        let m = &mut self.metrics;
+        match e {
+            Expr::Call(_) | Expr::CallIndirect(_) | Expr::CallRef(_) => m.calls += 1,
+            Expr::Load(_) | Expr::Store(_) | Expr::AtomicRmw(_) | Expr::Cmpxchg(_) => {
+                m.memory_accesses += 1
+            }
+            Expr::Const(_) => m.consts += 1,
+            Expr::Block(b) => match b.kind {
+                BlockKind::Block | BlockKind::Loop => m.control_flow += 1,
+                // These are counted as part of their `if` or `try`, or not at
+                // all.
+                BlockKind::IfElse | BlockKind::Try | BlockKind::FunctionEntry => {}
+            },
+            Expr::IfElse(_)
+            | Expr::Try(_)
+            | Expr::Throw(_)
+            | Expr::Rethrow(_)
+            | Expr::Br(_)
+            | Expr::BrIf(_)
+            | Expr::BrTable(_)
+            | Expr::Return(_)
+            | Expr::Unreachable(_) => m.control_flow += 1,
+            _ => {}
+        }
+    }
+
+    /// The estimated number of bytes `e` itself encodes to, not counting its
+    /// operands.
+    fn estimate(&self, e: &Expr) -> u64 {
+        let index = |i: usize| leb_size(i as u64);
+        let memarg = 2;
+        match e {
+            // The opcode and block type, and the `end`. The arms of an `if`
+            // and the handlers of a `try` each have an `end`, `else` or
+            // `catch` instead.
+            Expr::Block(b) => match b.kind {
+                BlockKind::Block | BlockKind::Loop => 3,
+                BlockKind::IfElse | BlockKind::Try | BlockKind::FunctionEntry => 1,
+            },
+            Expr::IfElse(_) => 2,
+            // The opcode and block type, the `end` or `delegate`, and the
+            // opcodes starting the handlers.
+            Expr::Try(t) => {
+                let catches: u64 = t.catch_tags.iter().map(|tag| 1 + index(tag.index())).sum();
+                3 + catches + t.catch_all.map_or(0, |_| 1) + t.delegate.map_or(0, |_| 1)
+            }
+            Expr::Throw(t) => 1 + index(t.tag.index()),
+            Expr::Rethrow(_) => 2,
+            Expr::Const(c) => {
+                let mut dst = Vec::new();
+                c.value.emit(&mut Encoder::new(&mut dst));
+                dst.len() as u64
+            }
+            Expr::Call(c) => 1 + index(c.func.index()),
+            Expr::CallIndirect(c) => 1 + index(c.ty.index()) + index(c.table.index()),
+            Expr::CallRef(c) => 1 + index(c.ty.index()),
+            Expr::LocalGet(_) | Expr::LocalSet(_) | Expr::LocalTee(_) => 1 + self.local_index_size,
+            Expr::GlobalGet(g) => 1 + index(g.global.index()),
+            Expr::GlobalSet(g) => 1 + index(g.global.index()),
+            Expr::Br(_) | Expr::BrIf(_) => 2,
+            Expr::BrTable(b) => 2 + index(b.blocks.len()) + b.blocks.len() as u64,
+            Expr::MemorySize(m) => 1 + index(m.memory.index()),
+            Expr::MemoryGrow(m) => 1 + index(m.memory.index()),
+            Expr::MemoryInit(m) => 2 + index(m.data.index()) + index(m.memory.index()),
+            Expr::DataDrop(d) => 2 + index(d.data.index()),
+            Expr::MemoryCopy(_) => 4,
+            Expr::MemoryFill(_) => 3,
+            Expr::Load(_) | Expr::Store(_) => 1 + memarg,
+            Expr::AtomicRmw(_) | Expr::Cmpxchg(_) | Expr::AtomicNotify(_) | Expr::AtomicWait(_) => {
+                2 + memarg
+            }
+            Expr::TableGet(t) => 1 + index(t.table.index()),
+            Expr::TableSet(t) => 1 + index(t.table.index()),
+            Expr::TableGrow(t) => 2 + index(t.table.index()),
+            Expr::TableSize(t) => 2 + index(t.table.index()),
+            Expr::TableFill(t) => 2 + index(t.table.index()),
+            Expr::TableInit(t) => 2 + index(t.elem.index()) + index(t.table.index()),
+            Expr::ElemDrop(e) => 2 + index(e.elem.index()),
+            Expr::TableCopy(t) => 2 + index(t.dst.index()) + index(t.src.index()),
+            Expr::RefNull(_) => 2,
+            Expr::RefFunc(r) => 1 + index(r.func.index()),
+            Expr::V128Bitselect(_) => 2,
+            Expr::V128Shuffle(_) => 18,
+            Expr::V128Swizzle(_) => 3,
+            // All of these are relaxed SIMD instructions, with two byte
+            // opcodes.
+            Expr::Ternop(_) => 3,
+            // Only its parts are emitted.
+            Expr::WithSideEffects(_) => 0,
+            // Already on the stack.
+            Expr::BlockParam(_) => 0,
+            // Exactly as it was encoded, along with the locals declarations,
+            // which aren't counted otherwise.
+            Expr::Raw(r) => (r.locals.len() + r.code.len()) as u64,
+            Expr::Binop(_)
+            | Expr::Unop(_)
+            | Expr::Select(_)
+            | Expr::Unreachable(_)
+            | Expr::Drop(_)
+            | Expr::Return(_)
+            | Expr::RefIsNull(_)
+            | Expr::RefAsNonNull(_) => 1,
+        }
+    }
+}
+
+/// The length of the unsigned LEB128 encoding of `n`.
+fn leb_size(mut n: u64) -> u64 {
+    let mut size = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        size += 1;
+    }
+    size
+}
@@ -1,18 +1,29 @@
 //! Functions defined locally within a wasm module.
 
 mod context;
+mod diff;
 pub mod display;
 mod emit;
+mod infer;
+mod metrics;
 
 use self::context::ValidationContext;
+pub use self::diff::{diff_functions, FunctionDiff, SubtreeChange};
+pub use self::display::DisplayOptions;
+pub use self::metrics::FunctionMetrics;
+use crate::decode::{BlockType, Extended, Instruction, Instructions};
 use crate::dot::Dot;
 use crate::emit::IdsToIndices;
 use crate::encode::Encoder;
+use crate::error::{MalformedBodyKind, MalformedFunctionBody};
 use crate::ir::matcher::{ConstMatcher, Matcher};
 use crate::ir::*;
 use crate::map::{IdHashMap, IdHashSet};
+use crate::module::features;
 use crate::parse::IndicesToIds;
-use crate::{FunctionBuilder, FunctionId, Module, Result, TableKind, TypeId, ValType};
+use crate::ty::Signature;
+use crate::{FunctionBuilder, FunctionId, MemoryId, Module, ModuleTypes, Result};
+use crate::{TableKind, TypeId, ValType, WasmFeatures};
 use failure::{bail, ResultExt};
 use id_arena::Id;
 use std::collections::BTreeMap;
@@ -35,6 +46,21 @@ pub struct LocalFunction {
     /// The entry block for this function. Always `Some` after the constructor
     /// returns.
     entry: Option<BlockId>,
+
+    /// The original encoded body (locals and instructions) from the input
+    /// wasm, if `ModuleConfig::preserve_original_bodies` was enabled.
+    original_body: Option<Vec<u8>>,
+
+    /// The groups of locals this function was originally declared with, if
+    /// `ModuleConfig::preserve_declared_locals` was enabled.
+    declared_locals: Option<Vec<(ValType, Vec<LocalId>)>>,
+
+    /// Whether this function may have been modified since it was parsed, in
+    /// which case `original_body` is stale.
+    dirty: bool,
+
+    /// Whether this function's body is a `Raw` expression, see `is_raw`.
+    raw: bool,
     //
     // TODO: provenance: ExprId -> offset in code section of the original
     // instruction. This will be necessary for preserving debug info.
@@ -53,6 +79,10 @@ impl LocalFunction {
             args,
             entry: Some(entry),
             exprs,
+            original_body: None,
+            declared_locals: None,
+            dirty: true,
+            raw: false,
         }
     }
 
@@ -64,18 +94,25 @@ impl LocalFunction {
         module: &Module,
         indices: &IndicesToIds,
         id: FunctionId,
+        index: u32,
         ty: TypeId,
         args: Vec<LocalId>,
-        body: wasmparser::OperatorsReader,
+        mut body: Instructions,
     ) -> Result<LocalFunction> {
         let mut func = LocalFunction {
             ty,
             exprs: FunctionBuilder::new(),
             args,
             entry: None,
+            original_body: None,
+            declared_locals: None,
+            dirty: true,
+            raw: false,
         };
+        func.exprs.set_interning(module.config.intern_leaf_exprs);
 
-        let result: Vec<_> = module.types.get(ty).results().iter().cloned().collect();
+        let params = module.types.get(ty).params().to_vec().into_boxed_slice();
+        let result: Vec<_> = module.types.get(ty).results().to_vec();
         let result = result.into_boxed_slice();
         let result_len = result.len();
 
@@ -84,26 +121,67 @@ impl LocalFunction {
 
         let mut ctx = ValidationContext::new(module, indices, id, &mut func, operands, controls);
 
-        let entry = ctx.push_control(BlockKind::FunctionEntry, result.clone(), result);
+        let entry = ctx.push_control(BlockKind::FunctionEntry, params, result);
         ctx.func.entry = Some(entry);
-        for inst in body {
-            let inst = inst?;
-            validate_instruction(&mut ctx, inst)?;
+        let malformed = |offset, kind| MalformedFunctionBody {
+            function: index,
+            offset,
+            kind,
+        };
+        while !ctx.controls.is_empty() {
+            if body.eof() {
+                let kind = MalformedBodyKind::BodySizeMismatch;
+                return Err(malformed(body.original_position(), kind).into());
+            }
+            match body.read(indices)? {
+                Instruction::Operator(inst) => validate_instruction(&mut ctx, inst)?,
+                Instruction::Extended(inst) => validate_extended(&mut ctx, inst)?,
+                Instruction::Memory(memory, inst) => {
+                    ctx.memory = Some(memory);
+                    validate_instruction(&mut ctx, inst)?;
+                }
+            }
         }
-        if !ctx.controls.is_empty() {
-            bail!("function failed to end with `end`");
+        if !body.eof() {
+            let kind = MalformedBodyKind::TrailingBytes;
+            return Err(malformed(body.original_position(), kind).into());
         }
 
         debug_assert_eq!(ctx.operands.len(), result_len);
         debug_assert!(ctx.controls.is_empty());
 
+        if let Some(allowed) = &module.config.wasm_features {
+            features::check_function(&func, index, allowed)?;
+        }
+
         Ok(func)
     }
 
+    /// Construct a function whose body is kept as the given `Raw` expression.
+    pub(crate) fn from_raw(
+        types: &ModuleTypes,
+        ty: TypeId,
+        args: Vec<LocalId>,
+        raw: Raw,
+    ) -> LocalFunction {
+        let mut exprs = FunctionBuilder::new();
+        let body = exprs.alloc(raw).into();
+        let entry = exprs.alloc(Block {
+            kind: BlockKind::FunctionEntry,
+            params: types.get(ty).params().to_vec().into_boxed_slice(),
+            results: types.get(ty).results().to_vec().into_boxed_slice(),
+            exprs: vec![body],
+        });
+        let mut func = LocalFunction::new(ty, args, exprs, entry);
+        func.raw = true;
+        func
+    }
+
     pub(crate) fn alloc<T>(&mut self, val: T) -> T::Id
     where
         T: Ast,
     {
+        self.dirty = true;
         self.exprs.alloc(val)
     }
 
@@ -128,10 +206,38 @@ impl LocalFunction {
     }
 
     /// Get the expression associated with the given id
+    ///
+    /// If the expression is interned (see `FunctionBuilder::set_interning`),
+    /// mutating it changes every use of it, and it's no longer handed out to
+    /// new uses.
     pub fn get_mut(&mut self, id: ExprId) -> &mut Expr {
+        self.dirty = true;
+        self.exprs.forget_interned(id);
         &mut self.exprs.arena[id]
     }
 
+    /// Enable or disable interning of constants, `local.get`s and
+    /// `global.get`s allocated for this function from now on.
+    ///
+    /// See `FunctionBuilder::set_interning` for details.
+    pub fn set_interning(&mut self, enable: bool) {
+        self.exprs.set_interning(enable);
+    }
+
+    /// Is `id` an interned expression, which may be shared between several
+    /// uses?
+    ///
+    /// See `FunctionBuilder::set_interning` for details.
+    pub fn is_interned(&self, id: ExprId) -> bool {
+        self.exprs.is_interned(id)
+    }
+
+    /// The number of expressions allocated for this function, including any
+    /// which are no longer used by its body.
+    pub fn allocated_exprs(&self) -> usize {
+        self.exprs.arena.len()
+    }
+
     /// Get access to a `FunctionBuilder` to continue adding expressions to
     /// this function.
     pub fn builder(&self) -> &FunctionBuilder {
@@ -140,10 +246,75 @@ impl LocalFunction {
 
     /// Get access to a `FunctionBuilder` to continue adding expressions to
     /// this function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this function's body is raw, see `is_raw`, since raw bodies
+    /// can't be extended.
     pub fn builder_mut(&mut self) -> &mut FunctionBuilder {
+        assert!(!self.raw, "raw function bodies can't be changed");
+        self.dirty = true;
         &mut self.exprs
     }
 
+    /// Flag this function as possibly modified, so that its original body (if
+    /// any) is no longer used when emitting.
+    pub(crate) fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Flag this function as unmodified with respect to its original body.
+    pub(crate) fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+
+    pub(crate) fn set_original_body(&mut self, body: Vec<u8>) {
+        self.original_body = Some(body);
+    }
+
+    pub(crate) fn set_declared_locals(&mut self, declared: Vec<(ValType, Vec<LocalId>)>) {
+        self.declared_locals = Some(declared);
+    }
+
+    /// Is this function's body kept as raw instructions?
+    ///
+    /// With `ModuleConfig::allow_gc_types`, functions which use instructions
+    /// or types walrus doesn't understand, such as those of the GC proposal,
+    /// are parsed into a single `Raw` expression in their entry block. It's
+    /// emitted exactly as it was parsed, except for the indices it uses,
+    /// which are updated as items are added and removed.
+    ///
+    /// A raw body's ids can be visited and replaced like those of any other
+    /// expression, so `get_mut` still works on it, but nothing else can be
+    /// changed: `builder_mut` panics, and emitting the module fails if the
+    /// entry block no longer consists of just the `Raw` expression.
+    pub fn is_raw(&self) -> bool {
+        self.raw
+    }
+
+    /// Get this function's `Raw` body, if it's raw and still consists of just
+    /// that expression.
+    pub(crate) fn raw_body(&self) -> Option<&Raw> {
+        if !self.raw {
+            return None;
+        }
+        match self.block(self.entry_block()).exprs[..] {
+            [id] => match self.get(id) {
+                Expr::Raw(raw) => Some(raw),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Get this function's original encoded body if it's still up to date.
+    pub(crate) fn original_body(&self) -> Option<&[u8]> {
+        if self.dirty {
+            return None;
+        }
+        self.original_body.as_ref().map(|b| &b[..])
+    }
+
     /// Get the size of this function, in number of expressions.
     pub fn size(&self) -> u64 {
         struct SizeVisitor<'a> {
@@ -178,7 +349,152 @@ impl LocalFunction {
         entry
             .exprs
             .iter()
-            .all(|e| matcher.is_match(self, &self.get(*e)))
+            .all(|e| matcher.is_match(self, self.get(*e)))
+    }
+
+    /// Rewrite the `MemArg` of every load, store and atomic operation on
+    /// `memory` in this function.
+    ///
+    /// `f` is given each operation's `MemArg`, which it can change directly,
+    /// along with the kind of operation. It can also ask for a constant to be
+    /// added to the operation's offset or address by returning the
+    /// appropriate `Rewrite`. Adding to an offset which would overflow adds to
+    /// the address instead.
+    ///
+    /// Note that adding to the address is done with a wrapping `i32.add`, so
+    /// an operation which would have trapped because its effective address
+    /// didn't fit in 32 bits might no longer trap.
+    pub fn rewrite_memargs<F>(&mut self, memory: MemoryId, mut f: F)
+    where
+        F: FnMut(&mut MemArg, &LoadStoreKind) -> Rewrite,
+    {
+        struct Operations<'a> {
+            func: &'a LocalFunction,
+            memory: MemoryId,
+            operations: Vec<ExprId>,
+        }
+
+        impl<'a> Visitor<'a> for Operations<'a> {
+            fn local_function(&self) -> &'a LocalFunction {
+                self.func
+            }
+
+            fn visit_expr_id(&mut self, &id: &ExprId) {
+                let memory = match self.func.get(id) {
+                    Expr::Load(e) => Some(e.memory),
+                    Expr::Store(e) => Some(e.memory),
+                    Expr::AtomicRmw(e) => Some(e.memory),
+                    Expr::Cmpxchg(e) => Some(e.memory),
+                    Expr::AtomicNotify(e) => Some(e.memory),
+                    Expr::AtomicWait(e) => Some(e.memory),
+                    _ => None,
+                };
+                if memory == Some(self.memory) {
+                    self.operations.push(id);
+                }
+                id.visit(self);
+            }
+        }
+
+        let mut operations = Operations {
+            func: self,
+            memory,
+            operations: Vec::new(),
+        };
+        dfs_in_order(&mut operations, self, self.entry_block().into());
+        let operations = operations.operations;
+
+        for id in operations {
+            let (address, n) = {
+                let (arg, kind, address) = match memarg_mut(self.get_mut(id)) {
+                    Some(operation) => operation,
+                    None => continue,
+                };
+                let n = match f(arg, &kind) {
+                    Rewrite::Keep => continue,
+                    Rewrite::AddToOffset(n) => match arg.offset.checked_add(n) {
+                        Some(offset) => {
+                            arg.offset = offset;
+                            continue;
+                        }
+                        None => n,
+                    },
+                    Rewrite::AddToAddress(n) => n,
+                };
+                (*address, n)
+            };
+            let new_address = self.add_to_address(address, n);
+            if let Some((_, _, address)) = memarg_mut(self.get_mut(id)) {
+                *address = new_address;
+            }
+        }
+    }
+
+    /// Add `n` to the `i32` address computed by `address`, returning the
+    /// expression computing the new address.
+    fn add_to_address(&mut self, address: ExprId, n: u32) -> ExprId {
+        // Folding into an interned constant would change its other uses.
+        if !self.is_interned(address) {
+            if let Expr::Const(Const {
+                value: Value::I32(a),
+            }) = self.get_mut(address)
+            {
+                *a = a.wrapping_add(n as i32);
+                return address;
+            }
+        }
+        let n = self.alloc(Const {
+            value: Value::I32(n as i32),
+        });
+        self.alloc(Binop {
+            op: BinaryOp::I32Add,
+            lhs: address,
+            rhs: n.into(),
+        })
+        .into()
+    }
+
+    /// The parameters and results of the first block which is encoded as a
+    /// type index, but whose type isn't in `types`.
+    pub(crate) fn missing_block_type(&self, types: &ModuleTypes) -> Option<Signature> {
+        struct Missing<'a> {
+            func: &'a LocalFunction,
+            types: &'a ModuleTypes,
+            missing: Option<Signature>,
+        }
+        if self.is_raw() {
+            return None;
+        }
+        let mut missing = Missing {
+            func: self,
+            types,
+            missing: None,
+        };
+        missing.visit_block_id(&self.entry_block());
+        return missing.missing;
+
+        impl<'a> Visitor<'a> for Missing<'a> {
+            fn local_function(&self) -> &'a LocalFunction {
+                self.func
+            }
+
+            fn visit_block(&mut self, block: &Block) {
+                let found = || self.types.find(&block.params, &block.results).is_some();
+                if self.missing.is_none() && block.needs_type_index() && !found() {
+                    self.missing = Some((block.params.clone(), block.results.clone()));
+                }
+                block.visit(self);
+            }
+
+            fn visit_try(&mut self, e: &Try) {
+                // Only the type of the body is encoded, the handlers' types
+                // are implied by their tags.
+                self.visit_block_id(&e.body);
+                for handler in e.catches.iter().chain(&e.catch_all) {
+                    self.func.block(*handler).visit(self);
+                }
+            }
+        }
     }
 
     fn used_locals(&self) -> IdHashSet<Local> {
@@ -208,9 +524,44 @@ impl LocalFunction {
     pub(crate) fn emit_locals(
         &self,
         module: &Module,
+        indices: &IdsToIndices,
         encoder: &mut Encoder,
     ) -> (IdHashSet<Local>, IdHashMap<Local, u32>) {
-        let used_set = self.used_locals();
+        // Raw bodies refer to locals by index, so their locals are declared
+        // exactly as they were, and only the arguments have ids.
+        if let Some(raw) = self.raw_body() {
+            emit::emit_raw(raw, &raw.locals, indices, encoder);
+            let used_set = self.args.iter().cloned().collect();
+            let local_map = self
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| (*arg, i as u32))
+                .collect();
+            return (used_set, local_map);
+        }
+
+        let mut used_set = self.used_locals();
+
+        // If we're preserving the original locals declarations then those
+        // come first, exactly as they were, and everything else is declared
+        // after them.
+        let declared = self
+            .declared_locals
+            .as_ref()
+            .filter(|_| module.config.preserve_declared_locals);
+        let declared: &[(ValType, Vec<LocalId>)] = match declared {
+            Some(declared) => declared,
+            None => &[],
+        };
+        for (_, group) in declared {
+            used_set.extend(group.iter().cloned());
+        }
+        let declared_set = declared
+            .iter()
+            .flat_map(|(_, group)| group.iter().cloned())
+            .collect::<IdHashSet<_>>();
+
         let mut used_locals = used_set.iter().cloned().collect::<Vec<_>>();
         // Sort to ensure we assign local indexes deterministically, and
         // everything is distinct so we can use a faster unstable sort.
@@ -223,9 +574,9 @@ impl LocalFunction {
 
         // Partition all locals by their type as we'll create at most one entry
         // for each type. Skip all arguments to the function because they're
-        // handled separately.
+        // handled separately, as well as locals which were declared already.
         for local in used_locals.iter() {
-            if !args.contains(local) {
+            if !args.contains(local) && !declared_set.contains(local) {
                 let ty = module.locals.get(*local).ty();
                 ty_to_locals.entry(ty).or_insert_with(Vec::new).push(*local);
             }
@@ -243,7 +594,11 @@ impl LocalFunction {
         }
 
         // Assign an index to all remaining locals
-        for (_, locals) in ty_to_locals.iter() {
+        let groups = declared
+            .iter()
+            .map(|(ty, locals)| (ty, locals))
+            .chain(ty_to_locals.iter());
+        for (_, locals) in groups.clone() {
             for l in locals {
                 local_map.insert(*l, idx);
                 idx += 1;
@@ -251,10 +606,10 @@ impl LocalFunction {
         }
 
         // Use our type map to emit a compact representation of all locals now
-        encoder.usize(ty_to_locals.len());
-        for (ty, locals) in ty_to_locals.iter() {
+        encoder.usize(declared.len() + ty_to_locals.len());
+        for (ty, locals) in groups {
             encoder.usize(locals.len());
-            ty.emit(encoder);
+            ty.emit(encoder, indices);
         }
 
         (used_set, local_map)
@@ -263,20 +618,33 @@ impl LocalFunction {
     /// Emit this function's instruction sequence.
     pub(crate) fn emit_instructions(
         &self,
+        types: &ModuleTypes,
         indices: &IdsToIndices,
         local_indices: &IdHashMap<Local, u32>,
         dst: &mut Encoder,
     ) {
-        emit::run(self, indices, local_indices, dst)
+        emit::run(self, types, indices, local_indices, dst)
+    }
+
+    /// Display this function's IR with the given options.
+    ///
+    /// This is what `Display` prints with the default options, or with a width
+    /// given as in `format!("{:100}", func)`.
+    pub fn display(&self, opts: &DisplayOptions) -> String {
+        use self::display::DisplayIr;
+        let mut dst = String::new();
+        self.display_ir(&mut dst, opts, 0);
+        dst
     }
 }
 
 impl fmt::Display for LocalFunction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use self::display::DisplayIr;
-        let mut dst = String::new();
-        self.display_ir(&mut dst, &(), 0);
-        dst.fmt(f)
+        let mut opts = DisplayOptions::new();
+        if let Some(width) = f.width() {
+            opts.width(width);
+        }
+        f.write_str(&self.display(&opts))
     }
 }
 
@@ -321,7 +689,7 @@ impl DotExpr<'_, '_> {
     }
 
     pub(crate) fn id<T>(&mut self, id: Id<T>) {
-        self.out.push_str(" ");
+        self.out.push(' ');
         self.out.push_str(&id.index().to_string());
     }
 
@@ -349,6 +717,394 @@ impl DotExpr<'_, '_> {
     }
 }
 
+/// Get the `MemArg`, kind and address operand of a memory operation.
+fn memarg_mut(expr: &mut Expr) -> Option<(&mut MemArg, LoadStoreKind, &mut ExprId)> {
+    Some(match expr {
+        Expr::Load(e) => (&mut e.arg, LoadStoreKind::Load(e.kind), &mut e.address),
+        Expr::Store(e) => (&mut e.arg, LoadStoreKind::Store(e.kind), &mut e.address),
+        Expr::AtomicRmw(e) => {
+            let kind = LoadStoreKind::AtomicRmw {
+                op: e.op,
+                width: e.width,
+            };
+            (&mut e.arg, kind, &mut e.address)
+        }
+        Expr::Cmpxchg(e) => {
+            let kind = LoadStoreKind::Cmpxchg { width: e.width };
+            (&mut e.arg, kind, &mut e.address)
+        }
+        Expr::AtomicNotify(e) => (&mut e.arg, LoadStoreKind::AtomicNotify, &mut e.address),
+        Expr::AtomicWait(e) => {
+            let kind = LoadStoreKind::AtomicWait {
+                sixty_four: e.sixty_four,
+            };
+            (&mut e.arg, kind, &mut e.address)
+        }
+        _ => return None,
+    })
+}
+
+/// Start a `block`, `loop` or `if` taking `params` and producing `results`.
+///
+/// The operands it takes are evaluated just before it, see `with_args`.
+fn push_block(
+    ctx: &mut ValidationContext,
+    kind: BlockKind,
+    params: Box<[ValType]>,
+    results: Box<[ValType]>,
+) -> Result<()> {
+    let condition = if kind == BlockKind::IfElse {
+        Some(ctx.pop_operand_expected(Some(ValType::I32))?.1)
+    } else {
+        None
+    };
+    let mut args = ctx.pop_operands(&params)?;
+    args.reverse();
+    let block = ctx.push_control(kind, params, results);
+    push_params(ctx, block);
+    match condition {
+        Some(condition) => ctx.if_else.push(context::IfElseState {
+            condition,
+            consequent: block,
+            alternative: None,
+            args,
+        }),
+        None => ctx.controls.last_mut().unwrap().args = args,
+    }
+    Ok(())
+}
+
+/// Push the parameters of `block`, which are already on the stack when it
+/// starts, as the first operands inside it.
+fn push_params(ctx: &mut ValidationContext, block: BlockId) {
+    let params = ctx.func.block(block).params.clone();
+    for (index, ty) in params.iter().enumerate() {
+        let index = index as u32;
+        let expr = ctx.func.alloc(BlockParam { block, index });
+        ctx.push_operand(Some(*ty), expr);
+    }
+}
+
+/// Evaluate `args`, the operands taken by the block `expr`, just before it.
+fn with_args(ctx: &mut ValidationContext, args: Vec<ExprId>, expr: ExprId) -> ExprId {
+    if args.is_empty() {
+        return expr;
+    }
+    ctx.func
+        .alloc(WithSideEffects {
+            before: args,
+            value: expr,
+            after: Vec::new(),
+        })
+        .into()
+}
+
+/// The parameters and results of a block of the given type.
+fn block_type(ctx: &ValidationContext, ty: BlockType) -> Result<Signature> {
+    Ok(match ty {
+        BlockType::Empty => (Box::new([]), Box::new([])),
+        BlockType::Value(ty) => (Box::new([]), Box::new([ty])),
+        BlockType::Index(ty) => {
+            let ty = ctx.module.types.get(ctx.indices.get_type(ty)?);
+            (ty.params().into(), ty.results().into())
+        }
+    })
+}
+
+/// End the body or current handler of the innermost `try`, at the given
+/// instruction.
+fn end_try_arm(ctx: &mut ValidationContext, inst: &str) -> Result<()> {
+    let state = match ctx.tries.last() {
+        Some(state) => state,
+        None => bail!("`{}` without a leading `try`", inst),
+    };
+    let current = state
+        .catch_all
+        .or_else(|| state.catches.last().cloned())
+        .unwrap_or(state.body);
+    if ctx.controls.last().map(|c| c.block) != Some(current) {
+        bail!("`{}` without a leading `try`", inst);
+    }
+    ctx.pop_control()?;
+    Ok(())
+}
+
+/// Start a handler of the innermost `try` for exceptions carrying values of
+/// the types `params`, returning its block.
+///
+/// The caught values are the handler's parameters.
+fn start_handler(
+    ctx: &mut ValidationContext,
+    inst: &str,
+    params: Box<[ValType]>,
+) -> Result<BlockId> {
+    if let Some(state) = ctx.tries.last() {
+        if state.catch_all.is_some() {
+            bail!("`{}` after `catch_all`", inst);
+        }
+    }
+    end_try_arm(ctx, inst)?;
+    let results = ctx.tries.last().unwrap().results.clone();
+    let handler = ctx.push_control(BlockKind::Try, params, results);
+    push_params(ctx, handler);
+    Ok(handler)
+}
+
+/// Finish parsing the innermost `try`, whose last arm has already ended.
+fn finish_try(ctx: &mut ValidationContext, delegate: Option<BlockId>) {
+    let state = ctx.tries.pop().unwrap();
+    let expr = ctx.func.alloc(Try {
+        body: state.body,
+        catch_tags: state.catch_tags.into_boxed_slice(),
+        catches: state.catches.into_boxed_slice(),
+        catch_all: state.catch_all,
+        delegate,
+    });
+    let expr = with_args(ctx, state.args, expr.into());
+    ctx.push_operands(&state.results, expr);
+}
+
+/// Validate an instruction decoded by walrus itself, see `crate::decode`.
+fn validate_extended(ctx: &mut ValidationContext, inst: Extended) -> Result<()> {
+    use crate::ValType::*;
+
+    match inst {
+        Extended::Block { kind, ty } => {
+            let (params, results) = block_type(ctx, ty)?;
+            push_block(ctx, kind, params, results)?;
+        }
+        Extended::RefNull(ty) => {
+            let expr = ctx.func.alloc(RefNull { ty });
+            ctx.push_operand(Some(ty.val_type()), expr);
+        }
+        Extended::RefFunc(func) => {
+            let expr = ctx.func.alloc(RefFunc { func });
+            ctx.push_operand(Some(Funcref), expr);
+        }
+        Extended::CallRef(ty) => {
+            let (callee_ty, func) = ctx.pop_operand()?;
+            match callee_ty {
+                Some(Ref { ty: callee, .. }) if callee == ty => {}
+                None => {}
+                _ => bail!("call_ref requires a typed reference to a function of its type"),
+            }
+            let fun_ty = ctx.module.types.get(ty);
+            let mut args = ctx.pop_operands(fun_ty.params())?.into_boxed_slice();
+            args.reverse();
+            let expr = ctx.func.alloc(CallRef { ty, func, args });
+            ctx.push_operands(fun_ty.results(), expr.into());
+        }
+        Extended::RefAsNonNull => {
+            let (ty, value) = ctx.pop_operand()?;
+            let ty = match ty {
+                Some(Ref { ty, .. }) => Some(Ref {
+                    nullable: false,
+                    ty,
+                }),
+                Some(Funcref) | Some(Externref) | None => ty,
+                Some(_) => bail!("ref.as_non_null requires a reference"),
+            };
+            let expr = ctx.func.alloc(RefAsNonNull { value });
+            ctx.push_operand(ty, expr);
+        }
+        Extended::Select(ty) => {
+            let (_, condition) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, alternative) = ctx.pop_operand_expected(Some(ty))?;
+            let (_, consequent) = ctx.pop_operand_expected(Some(ty))?;
+            let expr = ctx.func.alloc(Select {
+                condition,
+                consequent,
+                alternative,
+                ty: Some(ty),
+            });
+            ctx.push_operand(Some(ty), expr);
+        }
+        Extended::TableInit { elem, table } => {
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, src) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, dst) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(TableInit {
+                table,
+                elem,
+                dst,
+                src,
+                len,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::TableCopy { dst, src } => {
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, src_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, dst_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(TableCopy {
+                src,
+                dst,
+                dst_offset,
+                src_offset,
+                len,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::TableFill(table) => {
+            let expected_ty = match ctx.module.tables.get(table).kind {
+                TableKind::Externref(_) => Externref,
+                TableKind::Function(_) => Funcref,
+            };
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, value) = ctx.pop_operand_expected(Some(expected_ty))?;
+            let (_, start) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(TableFill {
+                table,
+                start,
+                value,
+                len,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::MemoryInit { data, memory } => {
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, data_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, memory_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(MemoryInit {
+                len,
+                data_offset,
+                memory_offset,
+                memory,
+                data,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::MemoryCopy { dst, src } => {
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, src_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, dst_offset) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(MemoryCopy {
+                len,
+                src_offset,
+                dst_offset,
+                src,
+                dst,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::MemoryFill(memory) => {
+            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, value) = ctx.pop_operand_expected(Some(I32))?;
+            let (_, offset) = ctx.pop_operand_expected(Some(I32))?;
+            let expr = ctx.func.alloc(MemoryFill {
+                len,
+                offset,
+                value,
+                memory,
+            });
+            ctx.add_to_current_frame_block(expr);
+        }
+        Extended::Swizzle => {
+            let (_, indices) = ctx.pop_operand_expected(Some(V128))?;
+            let (_, lanes) = ctx.pop_operand_expected(Some(V128))?;
+            let expr = ctx.func.alloc(V128Swizzle { lanes, indices });
+            ctx.push_operand(Some(V128), expr);
+        }
+        Extended::Try(ty) => {
+            let (params, results) = block_type(ctx, ty)?;
+            let mut args = ctx.pop_operands(&params)?;
+            args.reverse();
+            let body = ctx.push_control(BlockKind::Try, params, results.clone());
+            push_params(ctx, body);
+            ctx.tries.push(context::TryState {
+                body,
+                results,
+                catch_tags: Vec::new(),
+                catches: Vec::new(),
+                catch_all: None,
+                args,
+            });
+        }
+        Extended::Catch(tag) => {
+            let ty = ctx.module.tags.get(tag).ty;
+            let params = ctx.module.types.get(ty).params().into();
+            let handler = start_handler(ctx, "catch", params)?;
+            let state = ctx.tries.last_mut().unwrap();
+            state.catch_tags.push(tag);
+            state.catches.push(handler);
+        }
+        Extended::CatchAll => {
+            let handler = start_handler(ctx, "catch_all", Box::new([]))?;
+            ctx.tries.last_mut().unwrap().catch_all = Some(handler);
+        }
+        Extended::Delegate(depth) => {
+            if let Some(state) = ctx.tries.last() {
+                if !state.catches.is_empty() || state.catch_all.is_some() {
+                    bail!("`delegate` after a handler");
+                }
+            }
+            end_try_arm(ctx, "delegate")?;
+            // The `try`'s own label is no longer in scope.
+            let target = ctx.control(depth as usize)?.block;
+            finish_try(ctx, Some(target));
+        }
+        Extended::Throw(tag) => {
+            let ty = ctx.module.tags.get(tag).ty;
+            let params = ctx.module.types.get(ty).params();
+            let args = ctx.pop_operands(params)?.into_boxed_slice();
+            let expr = ctx.func.alloc(Throw { tag, args });
+            ctx.unreachable(expr);
+        }
+        Extended::Rethrow(depth) => {
+            let block = ctx.control(depth as usize)?.block;
+            let handler = ctx
+                .tries
+                .iter()
+                .any(|t| t.catches.contains(&block) || t.catch_all == Some(block));
+            if !handler {
+                bail!("`rethrow` must target a `catch` or `catch_all`");
+            }
+            let expr = ctx.func.alloc(Rethrow { block });
+            ctx.unreachable(expr);
+        }
+        Extended::SimdUnop(op) => {
+            if let Some((features, name)) = WasmFeatures::for_unop(op) {
+                require_relaxed_simd(ctx, features, name)?;
+            }
+            let (_, expr) = ctx.pop_operand_expected(Some(V128))?;
+            let expr = ctx.func.alloc(Unop { op, expr });
+            ctx.push_operand(Some(V128), expr);
+        }
+        Extended::SimdBinop(op) => {
+            if let Some((features, name)) = WasmFeatures::for_binop(op) {
+                require_relaxed_simd(ctx, features, name)?;
+            }
+            let (_, rhs) = ctx.pop_operand_expected(Some(V128))?;
+            let (_, lhs) = ctx.pop_operand_expected(Some(V128))?;
+            let expr = ctx.func.alloc(Binop { op, lhs, rhs });
+            ctx.push_operand(Some(V128), expr);
+        }
+        Extended::SimdTernop(op) => {
+            let (features, name) = WasmFeatures::for_ternop(op);
+            require_relaxed_simd(ctx, features, name)?;
+            let (_, c) = ctx.pop_operand_expected(Some(V128))?;
+            let (_, b) = ctx.pop_operand_expected(Some(V128))?;
+            let (_, a) = ctx.pop_operand_expected(Some(V128))?;
+            let expr = ctx.func.alloc(Ternop { op, a, b, c });
+            ctx.push_operand(Some(V128), expr);
+        }
+    }
+    Ok(())
+}
+
+/// Relaxed SIMD instructions are only parsed if `ModuleConfig::relaxed_simd`
+/// is enabled, since their results can differ between engines.
+fn require_relaxed_simd(ctx: &ValidationContext, features: WasmFeatures, name: &str) -> Result<()> {
+    if features.relaxed_simd && !ctx.module.config.relaxed_simd {
+        bail!(
+            "relaxed SIMD instruction `{}` used without enabling \
+             `ModuleConfig::relaxed_simd`",
+            name
+        );
+    }
+    Ok(())
+}
+
 fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<()> {
     use crate::ir::ExtendedLoad::*;
     use crate::ValType::*;
@@ -377,20 +1133,28 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
     let testop = |ctx, ty, op| one_op(ctx, ty, I32, op);
     let relop = |ctx, ty, op| two_ops(ctx, ty, ty, I32, op);
 
-    let mem_arg = |arg: &wasmparser::MemoryImmediate| -> Result<MemArg> {
+    // The memory of an instruction's memory argument, along with the
+    // argument itself.
+    let mem_arg = |ctx: &mut ValidationContext,
+                   arg: &wasmparser::MemoryImmediate|
+     -> Result<(MemoryId, MemArg)> {
+        let memory = match ctx.memory.take() {
+            Some(memory) => memory,
+            None => ctx.indices.get_memory(0)?,
+        };
         if arg.flags >= 32 {
             failure::bail!("invalid alignment");
         }
-        Ok(MemArg {
+        let arg = MemArg {
             align: 1 << (arg.flags as i32),
             offset: arg.offset,
-        })
+        };
+        Ok((memory, arg))
     };
 
     let load = |ctx: &mut ValidationContext, arg, ty, kind| -> Result<()> {
         let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-        let memory = ctx.indices.get_memory(0)?;
-        let arg = mem_arg(&arg)?;
+        let (memory, arg) = mem_arg(ctx, &arg)?;
         let expr = ctx.func.alloc(Load {
             arg,
             kind,
@@ -404,8 +1168,7 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
     let store = |ctx: &mut ValidationContext, arg, ty, kind| -> Result<()> {
         let (_, value) = ctx.pop_operand_expected(Some(ty))?;
         let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-        let memory = ctx.indices.get_memory(0)?;
-        let arg = mem_arg(&arg)?;
+        let (memory, arg) = mem_arg(ctx, &arg)?;
         let expr = ctx.func.alloc(Store {
             arg,
             kind,
@@ -420,8 +1183,7 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
     let atomicrmw = |ctx: &mut ValidationContext, arg, ty, op, width| -> Result<()> {
         let (_, value) = ctx.pop_operand_expected(Some(ty))?;
         let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-        let memory = ctx.indices.get_memory(0)?;
-        let arg = mem_arg(&arg)?;
+        let (memory, arg) = mem_arg(ctx, &arg)?;
         let expr = ctx.func.alloc(AtomicRmw {
             arg,
             address,
@@ -438,8 +1200,7 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         let (_, replacement) = ctx.pop_operand_expected(Some(ty))?;
         let (_, expected) = ctx.pop_operand_expected(Some(ty))?;
         let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-        let memory = ctx.indices.get_memory(0)?;
-        let arg = mem_arg(&arg)?;
+        let (memory, arg) = mem_arg(ctx, &arg)?;
         let expr = ctx.func.alloc(Cmpxchg {
             arg,
             address,
@@ -535,7 +1296,7 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         }
         Operator::V128Const { value } => {
             let n = value.bytes();
-            let val = ((n[0] as u128) << 0)
+            let val = (n[0] as u128)
                 | ((n[1] as u128) << 8)
                 | ((n[2] as u128) << 16)
                 | ((n[3] as u128) << 24)
@@ -700,14 +1461,22 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             let expr = ctx.func.alloc(Drop { expr });
             ctx.add_to_current_frame_block(expr);
         }
+        // Selects between references are given their type explicitly, so
+        // that they're emitted in the typed form. That form, opcode 0x1c, is
+        // decoded by `validate_extended`.
         Operator::Select => {
             let (_, condition) = ctx.pop_operand_expected(Some(I32))?;
-            let (t1, consequent) = ctx.pop_operand()?;
-            let (t2, alternative) = ctx.pop_operand_expected(t1)?;
+            let (t1, alternative) = ctx.pop_operand()?;
+            let (t2, consequent) = ctx.pop_operand_expected(t1)?;
+            let ty = match t2 {
+                Some(Externref) | Some(Funcref) => t2,
+                _ => None,
+            };
             let expr = ctx.func.alloc(Select {
                 condition,
                 consequent,
                 alternative,
+                ty,
             });
             ctx.push_operand(t2, expr);
         }
@@ -722,26 +1491,33 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             let expr = ctx.func.alloc(Unreachable {});
             ctx.unreachable(expr);
         }
+        // Block types which refer to the type section are decoded by walrus
+        // itself, see `validate_extended`.
         Operator::Block { ty } => {
             let results = ValType::from_block_ty(ty)?;
-            ctx.push_control(BlockKind::Block, results.clone(), results);
+            push_block(ctx, BlockKind::Block, Box::new([]), results)?;
         }
         Operator::Loop { ty } => {
-            let t = ValType::from_block_ty(ty)?;
-            ctx.push_control(BlockKind::Loop, vec![].into_boxed_slice(), t);
+            let results = ValType::from_block_ty(ty)?;
+            push_block(ctx, BlockKind::Loop, Box::new([]), results)?;
         }
         Operator::If { ty } => {
-            let ty = ValType::from_block_ty(ty)?;
-            let (_, condition) = ctx.pop_operand_expected(Some(I32))?;
-
-            let consequent = ctx.push_control(BlockKind::IfElse, ty.clone(), ty.clone());
-            ctx.if_else.push(context::IfElseState {
-                condition,
-                consequent,
-                alternative: None,
-            });
+            let results = ValType::from_block_ty(ty)?;
+            push_block(ctx, BlockKind::IfElse, Box::new([]), results)?;
         }
         Operator::End => {
+            // A `try` ends with its last arm, which is its own block.
+            if let Some(frame) = ctx.controls.last() {
+                if ctx.func.block(frame.block).kind == BlockKind::Try {
+                    end_try_arm(ctx, "end")?;
+                    finish_try(ctx, None);
+                    return Ok(());
+                }
+            }
+            let args = match ctx.controls.last_mut() {
+                Some(frame) => mem::take(&mut frame.args),
+                None => Vec::new(),
+            };
             let (results, block) = ctx.pop_control()?;
 
             let id: ExprId = match ctx.func.block(block).kind {
@@ -754,32 +1530,34 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
                         condition,
                         consequent,
                         alternative,
+                        args,
                     } = ctx.if_else.pop().unwrap();
 
                     let alternative = match alternative {
                         Some(alt) => alt,
                         None => {
-                            let alternative = ctx.push_control(
-                                BlockKind::IfElse,
-                                results.clone(),
-                                results.clone(),
-                            );
+                            // A missing `else` arm passes its parameters
+                            // through unchanged, which an empty arm does too.
+                            let params = ctx.func.block(consequent).params.clone();
+                            let alternative =
+                                ctx.push_control(BlockKind::IfElse, params, results.clone());
+                            push_params(ctx, alternative);
                             ctx.pop_control()?;
+                            ctx.func.block_mut(alternative).exprs.clear();
                             alternative
                         }
                     };
 
-                    ctx.func
-                        .alloc(IfElse {
-                            condition,
-                            consequent,
-                            alternative,
-                        })
-                        .into()
+                    let expr = ctx.func.alloc(IfElse {
+                        condition,
+                        consequent,
+                        alternative,
+                    });
+                    with_args(ctx, args, expr.into())
                 }
 
                 // Otherwise the expression is the block itself.
-                _ => block.into(),
+                _ => with_args(ctx, args, block.into()),
             };
             ctx.push_operands(&results, id);
         }
@@ -793,7 +1571,10 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
                 BlockKind::IfElse => {}
                 _ => bail!("`else` without a leading `if`"),
             }
-            let alternative = ctx.push_control(BlockKind::IfElse, results.clone(), results);
+            // Both arms of an `if` take the same parameters.
+            let params = ctx.func.block(consequent).params.clone();
+            let alternative = ctx.push_control(BlockKind::IfElse, params, results);
+            push_params(ctx, alternative);
             let last = ctx.if_else.last_mut().unwrap();
             if last.alternative.is_some() {
                 bail!("`else` without a leading `if`")
@@ -871,69 +1652,41 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             ctx.unreachable(expr);
         }
 
+        // The reserved byte of `memory.size` and `memory.grow` is the index of
+        // the memory with multiple memories.
         Operator::MemorySize { reserved } => {
-            if reserved != 0 {
-                bail!("reserved byte isn't zero");
-            }
-            let memory = ctx.indices.get_memory(0)?;
+            let memory = ctx.indices.get_memory(reserved)?;
             let expr = ctx.func.alloc(MemorySize { memory });
             ctx.push_operand(Some(I32), expr);
         }
         Operator::MemoryGrow { reserved } => {
-            if reserved != 0 {
-                bail!("reserved byte isn't zero");
-            }
             let (_, pages) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
+            let memory = ctx.indices.get_memory(reserved)?;
             let expr = ctx.func.alloc(MemoryGrow { memory, pages });
             ctx.push_operand(Some(I32), expr);
         }
-        Operator::MemoryInit { segment } => {
-            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, data_offset) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, memory_offset) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
-            let data = ctx.indices.get_data(segment)?;
-            let expr = ctx.func.alloc(MemoryInit {
-                len,
-                data_offset,
-                memory_offset,
-                memory,
-                data,
-            });
-            ctx.add_to_current_frame_block(expr);
-        }
         Operator::DataDrop { segment } => {
             let data = ctx.indices.get_data(segment)?;
             let expr = ctx.func.alloc(DataDrop { data });
             ctx.add_to_current_frame_block(expr);
         }
-        Operator::MemoryCopy => {
-            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, src_offset) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, dst_offset) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
-            let expr = ctx.func.alloc(MemoryCopy {
-                len,
-                src_offset,
-                dst_offset,
-                src: memory,
-                dst: memory,
-            });
+        // Our version of `wasmparser` can only read `table.init` for table
+        // 0, so it's always decoded by walrus instead.
+        Operator::TableInit { .. } => unreachable!(),
+        Operator::ElemDrop { segment } => {
+            let elem = ctx.indices.get_element(segment)?;
+            let expr = ctx.func.alloc(ElemDrop { elem });
             ctx.add_to_current_frame_block(expr);
         }
-        Operator::MemoryFill => {
-            let (_, len) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, value) = ctx.pop_operand_expected(Some(I32))?;
-            let (_, offset) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
-            let expr = ctx.func.alloc(MemoryFill {
-                len,
-                offset,
-                value,
-                memory,
-            });
-            ctx.add_to_current_frame_block(expr);
+        // Our version of `wasmparser` only knows the form of `table.copy`
+        // from before multiple tables, so it's always decoded by walrus
+        // instead.
+        Operator::TableCopy => unreachable!(),
+        // Like `table.copy`, our version of `wasmparser` only knows the forms
+        // of `memory.init`, `memory.copy` and `memory.fill` from before
+        // multiple memories, so they're always decoded by walrus instead.
+        Operator::MemoryInit { .. } | Operator::MemoryCopy | Operator::MemoryFill => {
+            unreachable!()
         }
 
         Operator::Nop => {}
@@ -1220,12 +1973,12 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::Wake { ref memarg } => {
             let (_, count) = ctx.pop_operand_expected(Some(I32))?;
             let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
+            let (memory, arg) = mem_arg(ctx, memarg)?;
             let expr = ctx.func.alloc(AtomicNotify {
                 count,
                 address,
                 memory,
-                arg: mem_arg(memarg)?,
+                arg,
             });
             ctx.push_operand(Some(I32), expr);
         }
@@ -1237,29 +1990,33 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             let (_, timeout) = ctx.pop_operand_expected(Some(I64))?;
             let (_, expected) = ctx.pop_operand_expected(Some(ty))?;
             let (_, address) = ctx.pop_operand_expected(Some(I32))?;
-            let memory = ctx.indices.get_memory(0)?;
+            let (memory, arg) = mem_arg(ctx, memarg)?;
             let expr = ctx.func.alloc(AtomicWait {
                 timeout,
                 expected,
                 sixty_four,
                 address,
                 memory,
-                arg: mem_arg(memarg)?,
+                arg,
             });
             ctx.push_operand(Some(I32), expr);
         }
 
         Operator::TableGet { table } => {
             let table = ctx.indices.get_table(table)?;
+            let ty = match ctx.module.tables.get(table).kind {
+                TableKind::Externref(_) => Externref,
+                TableKind::Function(_) => Funcref,
+            };
             let (_, index) = ctx.pop_operand_expected(Some(I32))?;
             let expr = ctx.func.alloc(TableGet { table, index });
-            ctx.push_operand(Some(Anyref), expr);
+            ctx.push_operand(Some(ty), expr);
         }
         Operator::TableSet { table } => {
             let table = ctx.indices.get_table(table)?;
             let expected_ty = match ctx.module.tables.get(table).kind {
-                TableKind::Anyref(_) => Anyref,
-                TableKind::Function(_) => bail!("cannot set function table yet"),
+                TableKind::Externref(_) => Externref,
+                TableKind::Function(_) => Funcref,
             };
             let (_, value) = ctx.pop_operand_expected(Some(expected_ty))?;
             let (_, index) = ctx.pop_operand_expected(Some(I32))?;
@@ -1273,7 +2030,7 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::TableGrow { table } => {
             let table = ctx.indices.get_table(table)?;
             let expected_ty = match ctx.module.tables.get(table).kind {
-                TableKind::Anyref(_) => Anyref,
+                TableKind::Externref(_) => Externref,
                 TableKind::Function(_) => bail!("cannot grow function table yet"),
             };
             let (_, amount) = ctx.pop_operand_expected(Some(I32))?;
@@ -1290,12 +2047,15 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             let expr = ctx.func.alloc(TableSize { table });
             ctx.push_operand(Some(I32), expr);
         }
-        Operator::RefNull => {
-            let expr = ctx.func.alloc(RefNull {});
-            ctx.push_operand(Some(Anyref), expr);
-        }
+        // Our version of `wasmparser` reads `ref.null` without the heap type
+        // it has now, so it's always decoded by walrus instead.
+        Operator::RefNull => unreachable!(),
         Operator::RefIsNull => {
-            let (_, value) = ctx.pop_operand_expected(Some(Anyref))?;
+            let (ty, value) = ctx.pop_operand()?;
+            match ty {
+                None | Some(Externref) | Some(Funcref) => {}
+                Some(ty) => bail!("expected a reference type, found {}", ty),
+            }
             let expr = ctx.func.alloc(RefIsNull { value });
             ctx.push_operand(Some(I32), expr);
         }
@@ -1306,7 +2066,6 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
             let expr = ctx.func.alloc(V128Shuffle { indices: lines, lo, hi });
             ctx.push_operand(Some(V128), expr);
         }
-
         Operator::I8x16Splat => one_op(ctx, I32, V128, UnaryOp::I8x16Splat)?,
         Operator::I8x16ExtractLaneS { line: idx } => {
             one_op(ctx, V128, I32, UnaryOp::I8x16ExtractLaneS { idx })?
@@ -1403,7 +2162,6 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::V128And => binop(ctx, V128, BinaryOp::V128And)?,
         Operator::V128Or => binop(ctx, V128, BinaryOp::V128Or)?,
         Operator::V128Xor => binop(ctx, V128, BinaryOp::V128Xor)?,
-
         Operator::V128Bitselect => {
             let (_, mask) = ctx.pop_operand_expected(Some(V128))?;
             let (_, v2) = ctx.pop_operand_expected(Some(V128))?;
@@ -1413,7 +2171,9 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         }
 
         Operator::I8x16Neg => unop(ctx, V128, UnaryOp::I8x16Neg)?,
-        Operator::I8x16AnyTrue => one_op(ctx, V128, I32, UnaryOp::I8x16AnyTrue)?,
+        // `v128.any_true` is rewritten to `i8x16.any_true` for `wasmparser`,
+        // see `crate::decode::prestandard_simd`.
+        Operator::I8x16AnyTrue => one_op(ctx, V128, I32, UnaryOp::V128AnyTrue)?,
         Operator::I8x16AllTrue => one_op(ctx, V128, I32, UnaryOp::I8x16AllTrue)?,
         Operator::I8x16Shl => two_ops(ctx, V128, I32, V128, BinaryOp::I8x16Shl)?,
         Operator::I8x16ShrS => two_ops(ctx, V128, I32, V128, BinaryOp::I8x16ShrS)?,
@@ -1424,10 +2184,8 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::I8x16Sub => binop(ctx, V128, BinaryOp::I8x16Sub)?,
         Operator::I8x16SubSaturateS => binop(ctx, V128, BinaryOp::I8x16SubSaturateS)?,
         Operator::I8x16SubSaturateU => binop(ctx, V128, BinaryOp::I8x16SubSaturateU)?,
-        Operator::I8x16Mul => binop(ctx, V128, BinaryOp::I8x16Mul)?,
 
         Operator::I16x8Neg => unop(ctx, V128, UnaryOp::I16x8Neg)?,
-        Operator::I16x8AnyTrue => one_op(ctx, V128, I32, UnaryOp::I16x8AnyTrue)?,
         Operator::I16x8AllTrue => one_op(ctx, V128, I32, UnaryOp::I16x8AllTrue)?,
         Operator::I16x8Shl => two_ops(ctx, V128, I32, V128, BinaryOp::I16x8Shl)?,
         Operator::I16x8ShrS => two_ops(ctx, V128, I32, V128, BinaryOp::I16x8ShrS)?,
@@ -1441,7 +2199,6 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::I16x8Mul => binop(ctx, V128, BinaryOp::I16x8Mul)?,
 
         Operator::I32x4Neg => unop(ctx, V128, UnaryOp::I32x4Neg)?,
-        Operator::I32x4AnyTrue => one_op(ctx, V128, I32, UnaryOp::I32x4AnyTrue)?,
         Operator::I32x4AllTrue => one_op(ctx, V128, I32, UnaryOp::I32x4AllTrue)?,
         Operator::I32x4Shl => two_ops(ctx, V128, I32, V128, BinaryOp::I32x4Shl)?,
         Operator::I32x4ShrS => two_ops(ctx, V128, I32, V128, BinaryOp::I32x4ShrS)?,
@@ -1451,7 +2208,6 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::I32x4Mul => binop(ctx, V128, BinaryOp::I32x4Mul)?,
 
         Operator::I64x2Neg => unop(ctx, V128, UnaryOp::I64x2Neg)?,
-        Operator::I64x2AnyTrue => one_op(ctx, V128, I32, UnaryOp::I64x2AnyTrue)?,
         Operator::I64x2AllTrue => one_op(ctx, V128, I32, UnaryOp::I64x2AllTrue)?,
         Operator::I64x2Shl => two_ops(ctx, V128, I32, V128, BinaryOp::I64x2Shl)?,
         Operator::I64x2ShrS => two_ops(ctx, V128, I32, V128, BinaryOp::I64x2ShrS)?,
@@ -1481,12 +2237,19 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
 
         Operator::I32x4TruncSF32x4Sat => unop(ctx, V128, UnaryOp::I32x4TruncSF32x4Sat)?,
         Operator::I32x4TruncUF32x4Sat => unop(ctx, V128, UnaryOp::I32x4TruncUF32x4Sat)?,
-        Operator::I64x2TruncSF64x2Sat => unop(ctx, V128, UnaryOp::I64x2TruncSF64x2Sat)?,
-        Operator::I64x2TruncUF64x2Sat => unop(ctx, V128, UnaryOp::I64x2TruncUF64x2Sat)?,
         Operator::F32x4ConvertSI32x4 => unop(ctx, V128, UnaryOp::F32x4ConvertSI32x4)?,
         Operator::F32x4ConvertUI32x4 => unop(ctx, V128, UnaryOp::F32x4ConvertUI32x4)?,
-        Operator::F64x2ConvertSI64x2 => unop(ctx, V128, UnaryOp::F64x2ConvertSI64x2)?,
-        Operator::F64x2ConvertUI64x2 => unop(ctx, V128, UnaryOp::F64x2ConvertUI64x2)?,
+
+        // These didn't make it into the finalized SIMD proposal, so nothing
+        // is rewritten to them for `wasmparser`.
+        Operator::I8x16Mul
+        | Operator::I16x8AnyTrue
+        | Operator::I32x4AnyTrue
+        | Operator::I64x2AnyTrue
+        | Operator::I64x2TruncSF64x2Sat
+        | Operator::I64x2TruncUF64x2Sat
+        | Operator::F64x2ConvertSI64x2
+        | Operator::F64x2ConvertUI64x2 => unreachable!(),
 
         Operator::I32TruncSSatF32 => one_op(ctx, F32, I32, UnaryOp::I32TruncSSatF32)?,
         Operator::I32TruncUSatF32 => one_op(ctx, F32, I32, UnaryOp::I32TruncUSatF32)?,
@@ -1496,12 +2259,6 @@ fn validate_instruction(ctx: &mut ValidationContext, inst: Operator) -> Result<(
         Operator::I64TruncUSatF32 => one_op(ctx, F32, I64, UnaryOp::I64TruncUSatF32)?,
         Operator::I64TruncSSatF64 => one_op(ctx, F64, I64, UnaryOp::I64TruncSSatF64)?,
         Operator::I64TruncUSatF64 => one_op(ctx, F64, I64, UnaryOp::I64TruncUSatF64)?,
-
-        op @ Operator::TableInit { .. }
-        | op @ Operator::ElemDrop { .. }
-        | op @ Operator::TableCopy => {
-            bail!("Have not implemented support for opcode yet: {:?}", op)
-        }
     }
     Ok(())
 }
@@ -0,0 +1,110 @@
+//! Arbitrary user data attached to functions.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A bag of user data attached to a `Function`, holding at most one value of
+/// each type.
+///
+/// Walrus never looks at this data itself. It's dropped when its function is
+/// deleted, so it can't outlive the function the way a map keyed by
+/// `FunctionId` can. It isn't `Clone`, so it's never shared between
+/// functions.
+///
+/// ```
+/// use walrus::{FunctionBuilder, Module};
+///
+/// struct Cost(u32);
+///
+/// let mut module = Module::default();
+/// let ty = module.types.add(&[], &[]);
+/// let f = FunctionBuilder::new().finish(ty, vec![], vec![], &mut module);
+///
+/// module.funcs.get_mut(f).metadata.insert(Cost(3));
+/// for func in module.funcs.iter_mut() {
+///     if let Some(cost) = func.metadata.get_mut::<Cost>() {
+///         cost.0 += 1;
+///     }
+/// }
+/// assert_eq!(module.funcs.get(f).metadata.get::<Cost>().unwrap().0, 4);
+/// ```
+#[derive(Default)]
+pub struct Metadata {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Metadata {
+    /// Set the value of type `T`, returning the previous one, if any.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .map(unbox::<T>)
+    }
+
+    /// Get the value of type `T`, if there is one.
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .map(|value| value.downcast_ref::<T>().unwrap())
+    }
+
+    /// Get a mutable reference to the value of type `T`, if there is one.
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .map(|value| value.downcast_mut::<T>().unwrap())
+    }
+
+    /// Get a mutable reference to the value of type `T`, inserting the result
+    /// of `f` first if there isn't one.
+    pub fn get_or_insert_with<T, F>(&mut self, f: F) -> &mut T
+    where
+        T: Any + Send + Sync,
+        F: FnOnce() -> T,
+    {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(f()))
+            .downcast_mut::<T>()
+            .unwrap()
+    }
+
+    /// Remove the value of type `T`, returning it if there was one.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.values.remove(&TypeId::of::<T>()).map(unbox::<T>)
+    }
+
+    /// Is there a value of type `T`?
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    /// How many values there are.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Is this bag empty?
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Remove every value.
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+fn unbox<T: Any>(value: Box<dyn Any + Send + Sync>) -> T {
+    let value: Box<dyn Any> = value;
+    *value.downcast::<T>().unwrap()
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metadata")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
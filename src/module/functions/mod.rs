@@ -1,28 +1,42 @@
 //! Functions within a wasm module.
 
 mod local_function;
+mod metadata;
+mod topological;
+mod uses;
 
+use crate::decode::{val_type, Instructions, Reader};
 use crate::dot::Dot;
-use crate::emit::{Emit, EmitContext, Section};
+use crate::emit::{EmitContext, Section};
 use crate::encode::Encoder;
-use crate::error::Result;
-use crate::module::imports::ImportId;
-use crate::module::Module;
-use crate::parse::IndicesToIds;
+use crate::error::{AmbiguousName, MalformedBodyKind, MalformedFunctionBody, Result};
+use crate::ir::{Block, BlockId, BlockKind, ExprId};
+use crate::map::{IdHashMap, IdHashSet};
+use crate::module::gc::decode_body;
+use crate::module::imports::{ImportId, ImportKind};
+use crate::module::progress::{Phase, Progress};
+use crate::module::{EmittedIndices, Module};
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
 use crate::ty::TypeId;
-use crate::ty::ValType;
-use failure::bail;
+use failure::{bail, ResultExt};
 use rayon::prelude::*;
 use std::cmp;
 use std::fmt;
 
-pub use self::local_function::LocalFunction;
+pub use self::local_function::{diff_functions, FunctionDiff, SubtreeChange};
+pub use self::local_function::{DisplayOptions, FunctionMetrics, LocalFunction};
+pub use self::metadata::Metadata;
+pub use self::uses::FunctionUse;
 
 // have generated impls from the `#[walrus_expr]` macro
 pub(crate) use self::local_function::display::DisplayExpr;
 pub(crate) use self::local_function::DotExpr;
 
+/// The message `wasmparser` reports when a function body's declared size runs
+/// past the end of the code section.
+const BODY_PAST_END_OF_SECTION: &str = "Function body extends past end of the code section";
+
 /// A function identifier.
 pub type FunctionId = Id<Function>;
 
@@ -40,6 +54,9 @@ pub struct Function {
 
     /// An optional name associated with this function
     pub name: Option<String>,
+
+    /// Arbitrary data attached to this function by users of walrus.
+    pub metadata: Metadata,
 }
 
 impl Tombstone for Function {
@@ -47,6 +64,7 @@ impl Tombstone for Function {
         let ty = self.ty();
         self.kind = FunctionKind::Uninitialized(ty);
         self.name = None;
+        self.metadata.clear();
     }
 }
 
@@ -56,6 +74,7 @@ impl Function {
             id,
             kind: FunctionKind::Uninitialized(ty),
             name: None,
+            metadata: Metadata::default(),
         }
     }
 
@@ -72,6 +91,22 @@ impl Function {
             FunctionKind::Uninitialized(t) => *t,
         }
     }
+
+    /// Format this function's signature, like `foo: [i32, i32] -> [i64]`,
+    /// leaving out the name if it doesn't have one.
+    pub fn display_signature(&self, module: &Module) -> String {
+        let ty = module.types.get(self.ty());
+        match &self.name {
+            Some(name) => format!("{}: {}", name, ty),
+            None => ty.to_string(),
+        }
+    }
+
+    fn mark_dirty(&mut self) {
+        if let FunctionKind::Local(l) = &mut self.kind {
+            l.mark_dirty();
+        }
+    }
 }
 
 impl Dot for Function {
@@ -79,7 +114,7 @@ impl Dot for Function {
         match &self.kind {
             FunctionKind::Import(i) => i.dot(out),
             FunctionKind::Local(l) => l.dot(out),
-            FunctionKind::Uninitialized(_) => unreachable!(),
+            FunctionKind::Uninitialized(_) => out.push_str("digraph { uninitialized_function; }\n"),
         }
     }
 }
@@ -89,7 +124,7 @@ impl fmt::Display for Function {
         match &self.kind {
             FunctionKind::Import(i) => fmt::Display::fmt(i, f),
             FunctionKind::Local(l) => fmt::Display::fmt(l, f),
-            FunctionKind::Uninitialized(_) => unreachable!(),
+            FunctionKind::Uninitialized(_) => writeln!(f, "<uninitialized>"),
         }
     }
 }
@@ -132,7 +167,7 @@ pub struct ImportedFunction {
 
 impl Dot for ImportedFunction {
     fn dot(&self, out: &mut String) {
-        out.push_str("digraph {{ imported_function; }}");
+        out.push_str("digraph { imported_function; }\n");
     }
 }
 
@@ -161,6 +196,7 @@ impl ModuleFunctions {
             id,
             kind: FunctionKind::Import(ImportedFunction { import, ty }),
             name: None,
+            metadata: Metadata::default(),
         })
     }
 
@@ -170,6 +206,7 @@ impl ModuleFunctions {
             id,
             kind: FunctionKind::Local(func),
             name: None,
+            metadata: Metadata::default(),
         })
     }
 
@@ -180,7 +217,9 @@ impl ModuleFunctions {
 
     /// Gets a reference to a function given its id
     pub fn get_mut(&mut self, id: FunctionId) -> &mut Function {
-        &mut self.arena[id]
+        let func = &mut self.arena[id];
+        func.mark_dirty();
+        func
     }
 
     /// Get a function ID by its name.
@@ -189,10 +228,20 @@ impl ModuleFunctions {
     /// name, if a function happens to be exported.
     ///
     /// Note that function names are *not* guaranteed to be unique. This will
-    /// return the first function in the module with the given name.
+    /// return the first function in the module with the given name; use
+    /// `iter_by_name` to find all of them, or `by_name_unique` to find out
+    /// whether there's more than one.
     pub fn by_name(&self, name: &str) -> Option<FunctionId> {
-        self.arena.iter().find_map(|(id, f)| {
-            if f.name.as_ref().map(|s| s.as_str()) == Some(name) {
+        self.iter_by_name(name).next()
+    }
+
+    /// Get the IDs of every function with the given name, in order of their
+    /// IDs.
+    ///
+    /// As with `by_name`, the name used is the "name" custom section name.
+    pub fn iter_by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = FunctionId> + 'a {
+        self.arena.iter().filter_map(move |(id, f)| {
+            if f.name.as_deref() == Some(name) {
                 Some(id)
             } else {
                 None
@@ -200,11 +249,31 @@ impl ModuleFunctions {
         })
     }
 
+    /// Get a function ID by its name, making sure no other function has the
+    /// same name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `AmbiguousName` error if more than one function has the
+    /// name.
+    pub fn by_name_unique(&self, name: &str) -> Result<Option<FunctionId>> {
+        let functions = self.iter_by_name(name).collect::<Vec<_>>();
+        if functions.len() > 1 {
+            return Err(AmbiguousName {
+                name: name.to_string(),
+                functions,
+            }
+            .into());
+        }
+        Ok(functions.first().cloned())
+    }
+
     /// Removes a function from this module.
     ///
     /// It is up to you to ensure that any potential references to the deleted
     /// function are also removed, eg `call` expressions, exports, table
-    /// elements, etc.
+    /// elements, etc. `Module::delete_function_cascade` and
+    /// `Module::delete_function_if_unused` take care of this.
     pub fn delete(&mut self, id: FunctionId) {
         self.arena.delete(id);
     }
@@ -214,6 +283,37 @@ impl ModuleFunctions {
         self.arena.iter().map(|(_, f)| f)
     }
 
+    /// Get the number of functions in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no functions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more functions.
+    ///
+    /// The ids of functions are handed out by an arena which can only be
+    /// given a capacity while it's empty, so this does nothing once any
+    /// function, including an imported one, has been added. The same goes
+    /// for the `reserve` methods of the other collections of a module.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a shared reference to this module's functions, along with their
+    /// ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (FunctionId, &Function)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's functions.
+    pub fn ids(&self) -> impl Iterator<Item = FunctionId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
     /// Get a shared reference to this module's functions.
     pub fn par_iter(&self) -> impl ParallelIterator<Item = &Function> {
         self.arena.par_iter().map(|(_, f)| f)
@@ -235,14 +335,60 @@ impl ModuleFunctions {
         })
     }
 
+    /// Apply `f` to every local function in parallel, returning the results
+    /// sorted by function id.
+    ///
+    /// The order doesn't depend on how the work was scheduled, so analyses
+    /// built on this produce the same output on every run.
+    pub fn par_map_local<T, F>(&self, f: F) -> Vec<(FunctionId, T)>
+    where
+        T: Send,
+        F: Fn(FunctionId, &LocalFunction) -> T + Sync,
+    {
+        let mut results = self
+            .par_iter_local()
+            .map(|(id, func)| (id, f(id, func)))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(id, _)| *id);
+        results
+    }
+
+    /// Like `par_map_local`, but for a fallible `f`.
+    ///
+    /// If `f` fails for any function, returns the error for the one with the
+    /// smallest id, along with that id.
+    pub fn try_par_map_local<T, E, F>(
+        &self,
+        f: F,
+    ) -> std::result::Result<Vec<(FunctionId, T)>, (FunctionId, E)>
+    where
+        T: Send,
+        E: Send,
+        F: Fn(FunctionId, &LocalFunction) -> std::result::Result<T, E> + Sync,
+    {
+        self.par_map_local(f)
+            .into_iter()
+            .map(|(id, result)| match result {
+                Ok(value) => Ok((id, value)),
+                Err(e) => Err((id, e)),
+            })
+            .collect()
+    }
+
     /// Get a mutable reference to this module's functions.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Function> {
-        self.arena.iter_mut().map(|(_, f)| f)
+        self.arena.iter_mut().map(|(_, f)| {
+            f.mark_dirty();
+            f
+        })
     }
 
     /// Get a mutable reference to this module's functions.
     pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Function> {
-        self.arena.par_iter_mut().map(|(_, f)| f)
+        self.arena.par_iter_mut().map(|(_, f)| {
+            f.mark_dirty();
+            f
+        })
     }
 
     /// Get an iterator of this module's local functions
@@ -269,29 +415,67 @@ impl ModuleFunctions {
         })
     }
 
-    pub(crate) fn emit_func_section(&self, cx: &mut EmitContext) {
+    /// Flag every local function as unmodified, called once parsing has
+    /// finished.
+    pub(crate) fn mark_all_clean(&mut self) {
+        for (_, f) in self.arena.iter_mut() {
+            if let FunctionKind::Local(l) = &mut f.kind {
+                l.mark_clean();
+            }
+        }
+    }
+
+    /// Emit the function section, declaring the given local functions in
+    /// order. Their indices have already been assigned, see
+    /// `Module::function_index_order`.
+    pub(crate) fn emit_func_section(&self, cx: &mut EmitContext, functions: &[FunctionId]) {
         log::debug!("emit function section");
-        let functions = used_local_functions(cx);
-        if functions.len() == 0 {
+        if functions.is_empty() {
             return;
         }
         let mut cx = cx.start_section(Section::Function);
         cx.encoder.usize(functions.len());
-        for (id, function, _size) in functions {
-            let index = cx.indices.get_type_index(function.ty);
+        for id in functions {
+            let index = cx.indices.get_type_index(self.get(*id).ty());
             cx.encoder.u32(index);
-
-            // Assign an index to all local defined functions before we start
-            // translating them. While translating they may refer to future
-            // functions, so we'll need to have an index for it by that point.
-            // We're guaranteed the function section is emitted before the code
-            // section so we should be covered here.
-            cx.indices.push_func(id);
         }
     }
 }
 
 impl Module {
+    /// Wrap a sequence of expressions of the local function `func` in a new
+    /// block of the given kind, returning the id of the new block.
+    ///
+    /// The block's results are inferred with
+    /// `LocalFunction::infer_block_results`. The expressions aren't removed
+    /// from wherever they currently are, that's up to the caller.
+    pub fn wrap_in_block(
+        &mut self,
+        func: FunctionId,
+        exprs: Vec<ExprId>,
+        kind: BlockKind,
+    ) -> Result<(BlockId, ExprId)> {
+        match kind {
+            BlockKind::Block | BlockKind::Loop => {}
+            _ => bail!("can only wrap expressions in a `block` or a `loop`"),
+        }
+        let results = match &self.funcs.get(func).kind {
+            FunctionKind::Local(local) => local.infer_block_results(self, &exprs)?,
+            _ => bail!("can only wrap expressions of a local function in a block"),
+        };
+        let local = match &mut self.funcs.get_mut(func).kind {
+            FunctionKind::Local(local) => local,
+            _ => unreachable!(),
+        };
+        let block = local.alloc(Block {
+            kind,
+            params: Box::new([]),
+            results: results.into_boxed_slice(),
+            exprs,
+        });
+        Ok((block, block.into()))
+    }
+
     /// Declare local functions after seeing the `function` section of a wasm
     /// executable.
     pub(crate) fn declare_local_functions(
@@ -300,6 +484,8 @@ impl Module {
         ids: &mut IndicesToIds,
     ) -> Result<()> {
         log::debug!("parse function section");
+        self.funcs.reserve(reserve_hint(section.get_count()));
+        ids.funcs.reserve(reserve_hint(section.get_count()));
         for func in section {
             let ty = ids.get_type(func?)?;
             let id = self
@@ -318,7 +504,7 @@ impl Module {
     /// Add the locally defined functions in the wasm module to this instance.
     pub(crate) fn parse_local_functions(
         &mut self,
-        section: wasmparser::CodeSectionReader,
+        mut section: wasmparser::CodeSectionReader,
         function_section_count: u32,
         indices: &mut IndicesToIds,
     ) -> Result<()> {
@@ -328,77 +514,158 @@ impl Module {
             bail!("code and function sections must have same number of entries")
         }
         let num_imports = self.funcs.arena.len() - (amt as usize);
+        indices.locals.reserve(reserve_hint(amt));
 
         // First up serially create corresponding `LocalId` instances for all
         // functions as well as extract the operators parser for each function.
         // This is pretty tough to parallelize, but we can look into it later if
         // necessary and it's a bottleneck!
-        let mut bodies = Vec::with_capacity(amt as usize);
-        for (i, body) in section.into_iter().enumerate() {
-            let body = body?;
-            let index = (num_imports + i) as u32;
+        let mut bodies = Vec::with_capacity(reserve_hint(amt));
+        let mut raw = Vec::new();
+        for i in 0..amt {
+            let index = num_imports as u32 + i;
+            let offset = section.original_position();
+            let body = match section.read() {
+                Ok(body) => body,
+                Err(e) if e.message == BODY_PAST_END_OF_SECTION => {
+                    return Err(MalformedFunctionBody {
+                        function: index,
+                        offset,
+                        kind: MalformedBodyKind::BodySizeMismatch,
+                    }
+                    .into())
+                }
+                Err(e) => return Err(e.into()),
+            };
             let id = indices.get_func(index)?;
             let ty = match self.funcs.arena[id].kind {
                 FunctionKind::Uninitialized(ty) => ty,
-                _ => unreachable!(),
+                _ => bail!("code for function {} which isn't declared", index),
             };
 
             // First up, implicitly add locals for all function arguments. We also
             // record these in the function itself for later processing.
-            let mut args = Vec::new();
-            for ty in self.types.get(ty).params().iter() {
-                let local_id = self.locals.add(*ty);
-                let idx = indices.push_local(id, local_id);
-                args.push(local_id);
-                if self.config.generate_synthetic_names_for_anonymous_items {
+            let params = self.types.get(ty).params();
+            let mut args = Vec::with_capacity(params.len());
+            for ty in params.iter() {
+                args.push(self.locals.add(*ty));
+            }
+            if let Some(first) = args.first() {
+                indices.push_locals(id, args.len() as u32, *first);
+            }
+            if self.config.generate_synthetic_names_for_anonymous_items {
+                for (idx, local_id) in args.iter().enumerate() {
                     let name = format!("arg{}", idx);
-                    self.locals.get_mut(local_id).name = Some(name);
+                    self.locals.get_mut(*local_id).name = Some(name);
                 }
             }
 
+            // Bodies using instructions or types our version of `wasmparser`
+            // can't parse are kept as they are, see `LocalFunction::is_raw`.
+            if self.config.allow_gc_types {
+                let mut reader = body.get_binary_reader();
+                let len = reader.bytes_remaining();
+                let bytes = reader.read_bytes(len)?;
+                let decoded = decode_body(self, indices, ty, bytes)
+                    .with_context(|_| format!("failed to parse function {}", index))?;
+                if let Some(body) = decoded {
+                    raw.push((id, LocalFunction::from_raw(&self.types, ty, args, body)));
+                    continue;
+                }
+            }
+
+            let mut reader = body.get_binary_reader();
+            let offset = reader.original_position();
+            let bytes = reader.read_bytes(reader.bytes_remaining())?;
+
+            // The locals are decoded here rather than by `wasmparser`, which
+            // doesn't know about typed references.
+            //
             // WebAssembly local indices are 32 bits, so it's a validation error to
             // have more than 2^32 locals. Sure enough there's a spec test for this!
-            let mut total = 0u32;
-            for local in body.get_locals_reader()? {
-                let (count, _) = local?;
+            let mut r = Reader::new(bytes);
+            let mut locals = Vec::new();
+            let mut total = args.len() as u32;
+            for _ in 0..r.u32()? {
+                let entry = offset + r.position();
+                let count = r.u32()?;
                 total = match total.checked_add(count) {
                     Some(n) => n,
-                    None => bail!("can't have more than 2^32 locals"),
+                    None => {
+                        return Err(MalformedFunctionBody {
+                            function: index,
+                            offset: entry,
+                            kind: MalformedBodyKind::TooManyLocals,
+                        }
+                        .into())
+                    }
                 };
+                locals.push((count, val_type(&mut r, indices)?));
             }
 
             // Now that we know we have a reasonable amount of locals, put them in
             // our map.
-            for local in body.get_locals_reader()? {
-                let (count, ty) = local?;
-                let ty = ValType::parse(&ty)?;
+            let mut declared = Vec::new();
+            for (count, ty) in locals {
+                let mut group = Vec::with_capacity(reserve_hint(count));
                 for _ in 0..count {
-                    let local_id = self.locals.add(ty);
-                    let idx = indices.push_local(id, local_id);
+                    group.push(self.locals.add(ty));
+                }
+                if let Some(first) = group.first() {
+                    let idx = indices.push_locals(id, count, *first);
                     if self.config.generate_synthetic_names_for_anonymous_items {
-                        let name = format!("l{}", idx);
-                        self.locals.get_mut(local_id).name = Some(name);
+                        for (i, local_id) in group.iter().enumerate() {
+                            let name = format!("l{}", idx as usize + i);
+                            self.locals.get_mut(*local_id).name = Some(name);
+                        }
                     }
                 }
+                declared.push((ty, group));
             }
+            let declared = if self.config.preserve_declared_locals {
+                Some(declared)
+            } else {
+                None
+            };
 
-            let body = body.get_operators_reader()?;
-            bodies.push((id, body, args, ty));
+            let original = if self.config.preserve_original_bodies {
+                Some(bytes.to_vec())
+            } else {
+                None
+            };
+
+            // Instructions are read from the bytes after the locals, since
+            // some of them are decoded by walrus rather than `wasmparser`.
+            let start = r.position();
+            let body = Instructions::new(&bytes[start..], offset + start);
+            bodies.push((id, index, body, args, ty, original, declared));
         }
 
         // Wasm modules can often have a lot of functions and this operation can
         // take some time, so parse all function bodies in parallel.
+        let progress = Progress::new(&self.config, Phase::ParseFunctions, bodies.len());
         let results = bodies
             .into_par_iter()
-            .map(|(id, body, args, ty)| {
-                (id, LocalFunction::parse(self, indices, id, ty, args, body))
+            .map(|(id, index, body, args, ty, original, declared)| {
+                let func = LocalFunction::parse(self, indices, id, index, ty, args, body);
+                progress.function_done();
+                (id, func, original, declared)
             })
             .collect::<Vec<_>>();
 
         // After all the function bodies are collected and finished push them
         // into our function arena.
-        for (id, func) in results {
-            let func = func?;
+        for (id, func, original, declared) in results {
+            let mut func = func?;
+            if let Some(original) = original {
+                func.set_original_body(original);
+            }
+            if let Some(declared) = declared {
+                func.set_declared_locals(declared);
+            }
+            self.funcs.arena[id].kind = FunctionKind::Local(func);
+        }
+        for (id, func) in raw {
             self.funcs.arena[id].kind = FunctionKind::Local(func);
         }
 
@@ -406,61 +673,210 @@ impl Module {
     }
 }
 
-fn used_local_functions<'a>(cx: &mut EmitContext<'a>) -> Vec<(FunctionId, &'a LocalFunction, u64)> {
-    // Extract all local functions because imported ones were already
-    // emitted as part of the import sectin. Find the size of each local
-    // function. Sort imported functions in order so that we can get their
-    // index in the function index space.
-    let mut functions = Vec::new();
-    for f in cx.module.funcs.iter() {
-        match &f.kind {
-            FunctionKind::Local(l) => functions.push((f.id(), l, l.size())),
-            FunctionKind::Import(_) => {}
-            FunctionKind::Uninitialized(_) => unreachable!(),
+impl Module {
+    /// Iterate over every function in the order of the function index space
+    /// of the emitted module, along with its index in it.
+    ///
+    /// Imported functions come first, in the order of the import section,
+    /// followed by local functions in the order they're emitted in, which
+    /// depends on this module's configuration (see
+    /// `ModuleConfig::preserve_function_order`) unless the order was frozen
+    /// with `Module::assign_indices`. The emitter assigns indices with this
+    /// same iterator, so these indices are the ones functions will have in
+    /// the module as long as it isn't changed before being emitted.
+    pub fn function_index_order(&self) -> impl Iterator<Item = (u32, &Function)> + '_ {
+        let imported = self
+            .imports
+            .iter()
+            .filter_map(move |import| match import.kind {
+                ImportKind::Function(id) => Some(self.funcs.get(id)),
+                _ => None,
+            });
+        let local = self
+            .local_functions_in_emit_order()
+            .into_iter()
+            .map(move |id| self.funcs.get(id));
+        imported
+            .chain(local)
+            .enumerate()
+            .map(|(i, func)| (i as u32, func))
+    }
+
+    /// Get the index every function will have in the function index space
+    /// of the emitted module, as given by `function_index_order`.
+    ///
+    /// Unlike `assign_indices`, this doesn't freeze them.
+    pub fn function_indices(&self) -> EmittedIndices {
+        let mut indices = EmittedIndices::default();
+        for (index, func) in self.function_index_order() {
+            indices.push(func.id(), index);
         }
+        indices
     }
 
-    // Sort local functions from largest to smallest; we will emit them in
-    // this order. This helps load times, since wasm engines generally use
-    // the function as their level of granularity for parallelism. We want
-    // larger functions compiled before smaller ones because they will take
-    // longer to compile.
-    functions.sort_by_key(|(id, _, size)| (cmp::Reverse(*size), *id));
+    /// Get the index the given function will have in the function index
+    /// space of the emitted module, as given by `function_index_order`.
+    ///
+    /// Returns `None` if it won't be emitted, such as an imported function
+    /// whose import was deleted. This works out the order of every function,
+    /// so use `function_indices` to look up more than one.
+    pub fn function_index(&self, id: FunctionId) -> Option<u32> {
+        self.function_indices().func(id)
+    }
 
-    functions
+    /// Check that every function is either imported or has a body, so that
+    /// the module can be emitted.
+    pub(crate) fn check_initialized_functions(&self) -> Result<()> {
+        for func in self.funcs.iter() {
+            if let FunctionKind::Uninitialized(_) = func.kind {
+                bail!(
+                    "function {} ({}) has no body, it was declared but never defined",
+                    func.id().index(),
+                    func.name.as_ref().map_or("<unnamed>", |n| n.as_str())
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every block encoded as a type index has its type in the
+    /// type section, since types can't be added while emitting.
+    pub(crate) fn check_block_types(&self) -> Result<()> {
+        for (id, func) in self.funcs.iter_local() {
+            if let Some((params, results)) = func.missing_block_type(&self.types) {
+                bail!(
+                    "function {} has a block of type {:?} -> {:?}, which isn't in the \
+                     module's types; add it with `ModuleTypes::add`",
+                    id.index(),
+                    params,
+                    results
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Get every local function in the order they're emitted in.
+    fn local_functions_in_emit_order(&self) -> Vec<FunctionId> {
+        // Extract all local functions because imported ones come first in the
+        // function index space, in the order of the import section.
+        let mut functions = Vec::new();
+        for f in self.funcs.iter() {
+            match &f.kind {
+                FunctionKind::Local(_) => functions.push(f.id()),
+                // Emitting checks that there are none of these before it
+                // gets here, see `check_initialized_functions`.
+                FunctionKind::Import(_) | FunctionKind::Uninitialized(_) => {}
+            }
+        }
+
+        // Indices frozen by `assign_indices` win over everything else.
+        if let Some(frozen) = &self.frozen_indices {
+            functions.sort_by_key(|id| (frozen.position(*id), *id));
+            return functions;
+        }
+
+        // If we're trying to preserve original function bodies then keep
+        // functions in their original order, with any new functions at the
+        // end, so that function indices stay the same as in the input.
+        let original = match &self.input_indices {
+            Some(original) if self.config.preserve_original_bodies => Some(original),
+            _ => None,
+        };
+        if let Some(original) = original {
+            let order = original
+                .funcs
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (*id, i))
+                .collect::<IdHashMap<_, _>>();
+            functions.sort_by_key(|id| (order.get(id).cloned().unwrap_or(usize::MAX), *id));
+            return functions;
+        }
+
+        // Functions are allocated ids in the order they're parsed or added,
+        // which is the order `funcs.iter()` yields them in.
+        if self.config.preserve_function_order {
+            return functions;
+        }
+
+        // Sort local functions from largest to smallest; we will emit them in
+        // this order. This helps load times, since wasm engines generally use
+        // the function as their level of granularity for parallelism. We want
+        // larger functions compiled before smaller ones because they will take
+        // longer to compile. Sizes are only worked out here, since finding
+        // them walks every function body.
+        functions.sort_by_cached_key(|id| match &self.funcs.get(*id).kind {
+            FunctionKind::Local(l) => (cmp::Reverse(l.size()), *id),
+            _ => unreachable!(),
+        });
+
+        functions
+    }
 }
 
-impl Emit for ModuleFunctions {
-    fn emit(&self, cx: &mut EmitContext) {
+impl ModuleFunctions {
+    /// Emit the code section, with the bodies of the given local functions
+    /// in the same order as in the function section.
+    pub(crate) fn emit_code_section(&self, cx: &mut EmitContext, functions: &[FunctionId]) {
         log::debug!("emit code section");
-        let functions = used_local_functions(cx);
-        if functions.len() == 0 {
+        if functions.is_empty() {
             return;
         }
 
+        // Original function bodies encode raw indices, so they can only be
+        // reused if every index is the same as it was in the input.
+        let original = cx
+            .module
+            .input_indices
+            .as_ref()
+            .filter(|_| cx.module.config.preserve_original_bodies)
+            .filter(|original| cx.indices.matches_original(original));
+
+        let progress = Progress::new(&cx.module.config, Phase::EmitFunctions, functions.len());
         let mut cx = cx.start_section(Section::Code);
         cx.encoder.usize(functions.len());
 
         // Functions can typically take awhile to serialize, so serialize
         // everything in parallel. Afterwards we'll actually place all the
         // functions together.
-        let bytes = functions
-            .into_par_iter()
-            .map(|(id, func, _size)| {
+        let mut bodies = cx
+            .module
+            .funcs
+            .par_map_local(|id, func| {
                 log::debug!("emit function {:?} {:?}", id, cx.module.funcs.get(id).name);
+                let body = original.and_then(|original| {
+                    let locals = original.locals.get(&id)?;
+                    Some((func.original_body()?, locals))
+                });
+                if let Some((body, locals)) = body {
+                    let used_locals = locals.iter().collect::<IdHashSet<_>>();
+                    let local_indices = locals
+                        .iter()
+                        .enumerate()
+                        .map(|(i, local)| (local, i as u32))
+                        .collect::<IdHashMap<_, _>>();
+                    progress.function_done();
+                    return (body.to_vec(), used_locals, local_indices);
+                }
+
                 let mut wasm = Vec::new();
                 let mut encoder = Encoder::new(&mut wasm);
-                let (used_locals, local_indices) = func.emit_locals(cx.module, &mut encoder);
-                func.emit_instructions(cx.indices, &local_indices, &mut encoder);
-                (wasm, id, used_locals, local_indices)
+                let (used_locals, local_indices) =
+                    func.emit_locals(cx.module, cx.indices, &mut encoder);
+                func.emit_instructions(&cx.module.types, cx.indices, &local_indices, &mut encoder);
+                progress.function_done();
+                (wasm, used_locals, local_indices)
             })
-            .collect::<Vec<_>>();
+            .into_iter()
+            .collect::<IdHashMap<_, _>>();
 
-        cx.indices.locals.reserve(bytes.len());
-        for (wasm, id, used_locals, local_indices) in bytes {
+        cx.indices.locals.reserve(functions.len());
+        for id in functions {
+            let (wasm, used_locals, local_indices) = bodies.remove(id).unwrap();
             cx.encoder.bytes(&wasm);
-            cx.indices.locals.insert(id, local_indices);
-            cx.locals.insert(id, used_locals);
+            cx.indices.locals.insert(*id, local_indices);
+            cx.locals.insert(*id, used_locals);
         }
     }
 }
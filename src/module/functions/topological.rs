@@ -0,0 +1,92 @@
+//! Iterating over local functions with callees before their callers.
+
+use crate::analysis::{CallGraph, CallGraphOptions};
+use crate::map::IdHashMap;
+use crate::{FunctionId, FunctionKind, LocalFunction, Module, ModuleFunctions};
+use std::ptr;
+
+impl ModuleFunctions {
+    /// Iterate over the local functions of `module`, whose functions these
+    /// are, so that functions come after the functions they call.
+    ///
+    /// Functions which call each other, directly or through other functions,
+    /// can't be ordered, so they're yielded together as one strongly
+    /// connected component, in order of their ids. Every other group has a
+    /// single function in it. Only direct calls are taken into account:
+    /// which function a `call_indirect` calls isn't known, so it doesn't
+    /// constrain the order at all.
+    pub fn iter_local_topological<'a>(
+        &'a self,
+        module: &Module,
+    ) -> impl Iterator<Item = Vec<(FunctionId, &'a LocalFunction)>> + 'a {
+        debug_assert!(ptr::eq(self, &module.funcs));
+        let graph = direct_calls(module);
+        let sccs = graph.sccs().to_vec();
+        sccs.into_iter().filter_map(move |scc| {
+            let locals = scc
+                .into_iter()
+                .filter_map(|id| match &self.get(id).kind {
+                    FunctionKind::Local(local) => Some((id, local)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if locals.is_empty() {
+                None
+            } else {
+                Some(locals)
+            }
+        })
+    }
+
+    /// Split the local functions of `module`, whose functions these are, into
+    /// layers which can each be processed in parallel, bottom-up.
+    ///
+    /// Every function calls only functions in earlier layers, or in its own
+    /// strongly connected component, which is always in the same layer as
+    /// it. As with `iter_local_topological`, only direct calls are taken into
+    /// account. The functions in each layer are in order of their ids, so a
+    /// layer can be processed with `par_iter_local_mut` by skipping the
+    /// functions which aren't in it.
+    pub fn local_topological_layers(&self, module: &Module) -> Vec<Vec<FunctionId>> {
+        debug_assert!(ptr::eq(self, &module.funcs));
+        let graph = direct_calls(module);
+
+        // Components come with their callees before them, so the layer of
+        // every callee is known by the time it's needed.
+        let mut layer_of = IdHashMap::default();
+        let mut layers: Vec<Vec<FunctionId>> = Vec::new();
+        for scc in graph.sccs() {
+            // Imported functions don't call anything, so they're always in a
+            // component of their own.
+            match self.get(scc[0]).kind {
+                FunctionKind::Local(_) => {}
+                _ => continue,
+            }
+            let layer = scc
+                .iter()
+                .flat_map(|id| graph.callees(*id))
+                .filter(|callee| !scc.contains(callee))
+                .filter_map(|callee| layer_of.get(callee).map(|l: &usize| l + 1))
+                .max()
+                .unwrap_or(0);
+            if layer == layers.len() {
+                layers.push(Vec::new());
+            }
+            for id in scc {
+                layer_of.insert(*id, layer);
+                layers[layer].push(*id);
+            }
+        }
+        for layer in layers.iter_mut() {
+            layer.sort();
+        }
+        layers
+    }
+}
+
+fn direct_calls(module: &Module) -> CallGraph {
+    let options = CallGraphOptions {
+        indirect_calls: false,
+    };
+    CallGraph::with_options(module, &options)
+}
@@ -0,0 +1,156 @@
+//! Finding the uses of a function, and deleting it along with them.
+
+use crate::error::{FunctionInUse, Result};
+use crate::ir::*;
+use crate::module::functions::{FunctionId, FunctionKind, LocalFunction};
+use crate::module::Module;
+use crate::{ExportId, ExportItem, SegmentSource};
+
+/// A use of a function elsewhere in its module, as returned by
+/// `Module::function_uses`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FunctionUse {
+    /// A `call` in the body of another function.
+    Call {
+        /// The function containing the call.
+        caller: FunctionId,
+        /// The call expression.
+        expr: ExprId,
+    },
+    /// An export of the function.
+    Export(ExportId),
+    /// A member of an element segment, or an initialized slot of a function
+    /// table.
+    Element {
+        /// The segment.
+        source: SegmentSource,
+        /// The index of the member within the segment.
+        index: usize,
+    },
+    /// The module's start function.
+    Start,
+}
+
+impl Module {
+    /// Find every use of the function `id` elsewhere in this module.
+    ///
+    /// Calls in the function's own body aren't included, since they go away
+    /// along with it.
+    pub fn function_uses(&self, id: FunctionId) -> Vec<FunctionUse> {
+        let mut uses = Vec::new();
+        for (caller, func) in self.funcs.iter_local() {
+            if caller == id {
+                continue;
+            }
+            let mut calls = Calls {
+                func,
+                callee: id,
+                calls: Vec::new(),
+            };
+            dfs_in_order(&mut calls, func, func.entry_block().into());
+            uses.extend(
+                calls
+                    .calls
+                    .into_iter()
+                    .map(|expr| FunctionUse::Call { caller, expr }),
+            );
+        }
+
+        for export in self.exports.iter() {
+            if export.item == ExportItem::Function(id) {
+                uses.push(FunctionUse::Export(export.id()));
+            }
+        }
+        for segment in self.element_segments() {
+            for (index, member) in segment.members().iter().enumerate() {
+                if *member == Some(id) {
+                    uses.push(FunctionUse::Element {
+                        source: segment.source(),
+                        index,
+                    });
+                }
+            }
+        }
+        if self.start == Some(id) {
+            uses.push(FunctionUse::Start);
+        }
+        uses
+    }
+
+    /// Delete the function `id`, along with every use of it.
+    ///
+    /// Calls to it are replaced with `unreachable`, so its arguments are no
+    /// longer evaluated. Its exports are deleted, its slots in function tables
+    /// are left uninitialized, its entries in other element segments become
+    /// `ref.null`, and it stops being the start function. If it's imported,
+    /// its import is deleted too.
+    pub fn delete_function_cascade(&mut self, id: FunctionId) {
+        for u in self.function_uses(id) {
+            match u {
+                FunctionUse::Call { caller, expr } => {
+                    let func = match &mut self.funcs.get_mut(caller).kind {
+                        FunctionKind::Local(func) => func,
+                        _ => unreachable!(),
+                    };
+                    *func.get_mut(expr) = Expr::Unreachable(Unreachable {});
+                }
+                FunctionUse::Export(export) => self.exports.delete(export),
+                // Cleared below, since views of segments can't be looked up
+                // individually.
+                FunctionUse::Element { .. } => {}
+                FunctionUse::Start => self.start = None,
+            }
+        }
+        for mut segment in self.element_segments_mut() {
+            for member in segment.members_mut().iter_mut() {
+                if *member == Some(id) {
+                    *member = None;
+                }
+            }
+        }
+        self.delete_function_and_import(id);
+    }
+
+    /// Delete the function `id` if nothing else in the module uses it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `FunctionInUse` error listing every use of the function,
+    /// leaving the module unchanged, if anything still uses it.
+    pub fn delete_function_if_unused(&mut self, id: FunctionId) -> Result<()> {
+        let uses = self.function_uses(id);
+        if !uses.is_empty() {
+            return Err(FunctionInUse { function: id, uses }.into());
+        }
+        self.delete_function_and_import(id);
+        Ok(())
+    }
+
+    fn delete_function_and_import(&mut self, id: FunctionId) {
+        if let FunctionKind::Import(import) = &self.funcs.get(id).kind {
+            self.imports.delete(import.import);
+        }
+        self.funcs.delete(id);
+    }
+}
+
+struct Calls<'a> {
+    func: &'a LocalFunction,
+    callee: FunctionId,
+    calls: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Calls<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Expr::Call(e) = self.func.get(id) {
+            if e.func == self.callee {
+                self.calls.push(id);
+            }
+        }
+        id.visit(self);
+    }
+}
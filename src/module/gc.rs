@@ -0,0 +1,601 @@
+//! Passing through types and function bodies walrus doesn't understand, such
+//! as those of the GC proposal, with `ModuleConfig::allow_gc_types`.
+//!
+//! Our version of `wasmparser` predates the GC proposal, so the type section
+//! and function bodies are decoded here instead, assuming the standard
+//! encodings of the GC, typed function references, tail call, exception
+//! handling and SIMD proposals. They're only decoded far enough to find the
+//! indices of the module's items they use, and are otherwise kept as they
+//! were. Function bodies walrus can parse itself are left to `wasmparser`.
+
+use crate::decode::Reader;
+use crate::ir::{Raw, RawCode, RawHole, RawIndex};
+use crate::parse::IndicesToIds;
+use crate::ty::Signature;
+use crate::{DataId, ElementId, FunctionId, GlobalId, MemoryId, TableId, TagId};
+use crate::{Module, Result, TypeId, ValType};
+use failure::bail;
+
+/// The value type with the given encoding, if walrus can represent it.
+fn known_val_type(byte: u8) -> Option<ValType> {
+    match byte {
+        0x7f => Some(ValType::I32),
+        0x7e => Some(ValType::I64),
+        0x7d => Some(ValType::F32),
+        0x7c => Some(ValType::F64),
+        0x7b => Some(ValType::V128),
+        0x70 => Some(ValType::Funcref),
+        0x6f => Some(ValType::Externref),
+        _ => None,
+    }
+}
+
+/// Is this the encoding of an abstract heap type, which is also the
+/// shorthand for a nullable reference to it?
+fn is_abstract_heap_type(byte: u8) -> bool {
+    (0x69..=0x74).contains(&byte)
+}
+
+/// Read a heap type, returning its type index if it isn't an abstract one.
+fn heap_type(r: &mut Reader) -> Result<Option<u32>> {
+    if is_abstract_heap_type(r.peek()?) {
+        r.byte()?;
+        return Ok(None);
+    }
+    let index = r.s64()?;
+    if index < 0 || index > i64::from(u32::MAX) {
+        bail!("invalid heap type: {}", index);
+    }
+    Ok(Some(index as u32))
+}
+
+/// Read a value type, returning it if walrus can represent it.
+fn val_type(r: &mut Reader) -> Result<Option<ValType>> {
+    let byte = r.byte()?;
+    if let Some(ty) = known_val_type(byte) {
+        return Ok(Some(ty));
+    }
+    match byte {
+        0x63 | 0x64 => {
+            heap_type(r)?;
+        }
+        _ if is_abstract_heap_type(byte) => {}
+        _ => bail!("invalid value type: {:#x}", byte),
+    }
+    Ok(None)
+}
+
+/// Read a vector of value types, returning them if walrus can represent them
+/// all.
+fn val_types(r: &mut Reader) -> Result<Option<Box<[ValType]>>> {
+    let mut tys = Vec::new();
+    let mut known = true;
+    for _ in 0..r.u32()? {
+        match val_type(r)? {
+            Some(ty) => tys.push(ty),
+            None => known = false,
+        }
+    }
+    Ok(if known {
+        Some(tys.into_boxed_slice())
+    } else {
+        None
+    })
+}
+
+/// Read the type of a struct field or array element.
+fn field_type(r: &mut Reader) -> Result<()> {
+    match r.peek()? {
+        // Packed `i8` and `i16`.
+        0x78 | 0x77 => {
+            r.byte()?;
+        }
+        _ => {
+            val_type(r)?;
+        }
+    }
+    match r.byte()? {
+        0x00 | 0x01 => Ok(()),
+        byte => bail!("invalid mutability: {:#x}", byte),
+    }
+}
+
+/// Read a subtype, returning its parameters and results if it's a function
+/// type walrus can represent.
+fn sub_type(r: &mut Reader) -> Result<Option<Signature>> {
+    // Without `sub` or `sub final`, this is a final type without supertypes.
+    let byte = r.peek()?;
+    if byte == 0x50 || byte == 0x4f {
+        r.byte()?;
+        for _ in 0..r.u32()? {
+            r.u32()?;
+        }
+    }
+    match r.byte()? {
+        0x60 => {
+            let params = val_types(r)?;
+            let results = val_types(r)?;
+            match (params, results) {
+                (Some(params), Some(results)) => Ok(Some((params, results))),
+                _ => Ok(None),
+            }
+        }
+        0x5f => {
+            for _ in 0..r.u32()? {
+                field_type(r)?;
+            }
+            Ok(None)
+        }
+        0x5e => {
+            field_type(r)?;
+            Ok(None)
+        }
+        byte => bail!("invalid type form: {:#x}", byte),
+    }
+}
+
+impl Module {
+    /// Parse a type section which may use the GC proposal, returning whether
+    /// it does. Sections which don't are left to be parsed as usual.
+    pub(crate) fn parse_gc_types(
+        &mut self,
+        section: &[u8],
+        ids: &mut IndicesToIds,
+    ) -> Result<bool> {
+        log::debug!("parse type section for GC types");
+        let mut r = Reader::new(section);
+        let mut entries = Vec::new();
+        let mut plain = true;
+        for _ in 0..r.u32()? {
+            let start = r.position();
+            let mut types = Vec::new();
+            if r.peek()? == 0x4e {
+                r.byte()?;
+                for _ in 0..r.u32()? {
+                    types.push(sub_type(&mut r)?);
+                }
+                plain = false;
+            } else {
+                plain = plain && r.peek()? == 0x60;
+                let ty = sub_type(&mut r)?;
+                plain = plain && ty.is_some();
+                types.push(ty);
+            }
+            entries.push((r.since(start).to_vec(), types));
+        }
+        if !r.eof() {
+            bail!("unexpected data at the end of the type section");
+        }
+        if plain {
+            return Ok(false);
+        }
+
+        let mut index = 0;
+        for (bytes, types) in entries {
+            let count = types.len() as u32;
+            for id in self.types.add_raw(bytes, index, types) {
+                ids.push_type(id);
+            }
+            index += count;
+        }
+        Ok(true)
+    }
+
+    /// Check that everything parsed with `ModuleConfig::allow_gc_types` can
+    /// still be emitted.
+    pub(crate) fn check_raw(&self) -> Result<()> {
+        self.types.check_raw()?;
+        for (id, func) in self.funcs.iter_local() {
+            if func.is_raw() && func.raw_body().is_none() {
+                bail!(
+                    "function {} ({}) uses instructions walrus doesn't understand, \
+                     so its body can't be changed other than by replacing the ids \
+                     its `Raw` expression uses",
+                    id.index(),
+                    self.funcs
+                        .get(id)
+                        .name
+                        .as_ref()
+                        .map_or("<unnamed>", |n| n.as_str())
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode the body of a function of type `ty`, returning it as a `Raw`
+/// expression if walrus can't parse it itself.
+pub(crate) fn decode_body(
+    module: &Module,
+    ids: &IndicesToIds,
+    ty: TypeId,
+    body: &[u8],
+) -> Result<Option<Raw>> {
+    let mut decoder = BodyDecoder {
+        r: Reader::new(body),
+        ids,
+        unknown: module.types.get(ty).is_opaque(),
+        holes: Vec::new(),
+        funcs: Vec::new(),
+        globals: Vec::new(),
+        tables: Vec::new(),
+        memories: Vec::new(),
+        types: Vec::new(),
+        data: Vec::new(),
+        elements: Vec::new(),
+        tags: Vec::new(),
+    };
+    for _ in 0..decoder.r.u32()? {
+        decoder.r.u32()?;
+        decoder.val_type()?;
+    }
+    let locals = decoder.r.position();
+    decoder.instructions()?;
+    if !decoder.r.eof() {
+        bail!("unexpected data after the end of the function body");
+    }
+    if !decoder.unknown {
+        return Ok(None);
+    }
+
+    // Leave out the final `end`, which is emitted with the entry block.
+    let end = decoder.r.position() - 1;
+    let holes = decoder.holes;
+    let code = |start: usize, end: usize| RawCode {
+        bytes: body[start..end].to_vec(),
+        holes: holes
+            .iter()
+            .filter(|hole| hole.start >= start && hole.end <= end)
+            .map(|hole| RawHole {
+                start: hole.start - start,
+                end: hole.end - start,
+                ..*hole
+            })
+            .collect(),
+    };
+    Ok(Some(Raw {
+        locals: code(0, locals),
+        code: code(locals, end),
+        funcs: decoder.funcs.into_boxed_slice(),
+        globals: decoder.globals.into_boxed_slice(),
+        tables: decoder.tables.into_boxed_slice(),
+        memories: decoder.memories.into_boxed_slice(),
+        types: decoder.types.into_boxed_slice(),
+        data: decoder.data.into_boxed_slice(),
+        elements: decoder.elements.into_boxed_slice(),
+        tags: decoder.tags.into_boxed_slice(),
+    }))
+}
+
+/// Decodes a function body, recording the indices it uses and whether it
+/// uses anything walrus doesn't understand.
+struct BodyDecoder<'a> {
+    r: Reader<'a>,
+    ids: &'a IndicesToIds,
+    unknown: bool,
+    holes: Vec<RawHole>,
+    funcs: Vec<FunctionId>,
+    globals: Vec<GlobalId>,
+    tables: Vec<TableId>,
+    memories: Vec<MemoryId>,
+    types: Vec<TypeId>,
+    data: Vec<DataId>,
+    elements: Vec<ElementId>,
+    tags: Vec<TagId>,
+}
+
+fn push<T>(items: &mut Vec<T>, id: T) -> usize {
+    items.push(id);
+    items.len() - 1
+}
+
+impl BodyDecoder<'_> {
+    /// Record the index of the given kind which was just read from `start`.
+    fn hole(&mut self, start: usize, original: u32, kind: RawIndex) -> Result<()> {
+        let ids = self.ids;
+        let item = match kind {
+            RawIndex::Func => push(&mut self.funcs, ids.get_func(original)?),
+            RawIndex::Global => push(&mut self.globals, ids.get_global(original)?),
+            RawIndex::Table => push(&mut self.tables, ids.get_table(original)?),
+            RawIndex::Memory | RawIndex::MemArg { .. } => {
+                push(&mut self.memories, ids.get_memory(original)?)
+            }
+            RawIndex::Type | RawIndex::HeapType => push(&mut self.types, ids.get_type(original)?),
+            RawIndex::Data => push(&mut self.data, ids.get_data(original)?),
+            RawIndex::Element => push(&mut self.elements, ids.get_element(original)?),
+            RawIndex::Tag => push(&mut self.tags, ids.get_tag(original)?),
+        };
+        self.holes.push(RawHole {
+            start,
+            end: self.r.position(),
+            original,
+            kind,
+            item,
+        });
+        Ok(())
+    }
+
+    fn index(&mut self, kind: RawIndex) -> Result<()> {
+        let start = self.r.position();
+        let index = self.r.u32()?;
+        self.hole(start, index, kind)
+    }
+
+    fn label(&mut self) -> Result<()> {
+        self.r.u32()?;
+        Ok(())
+    }
+
+    fn heap_type(&mut self) -> Result<()> {
+        let start = self.r.position();
+        match heap_type(&mut self.r)? {
+            Some(index) => self.hole(start, index, RawIndex::HeapType),
+            None => Ok(()),
+        }
+    }
+
+    fn val_type(&mut self) -> Result<()> {
+        if known_val_type(self.r.peek()?).is_some() {
+            self.r.byte()?;
+            return Ok(());
+        }
+        self.unknown = true;
+        match self.r.byte()? {
+            0x63 | 0x64 => self.heap_type(),
+            byte if is_abstract_heap_type(byte) => Ok(()),
+            byte => bail!("invalid value type: {:#x}", byte),
+        }
+    }
+
+    fn block_type(&mut self) -> Result<()> {
+        match self.r.peek()? {
+            0x40 => {
+                self.r.byte()?;
+                Ok(())
+            }
+            byte if byte > 0x40 && byte < 0x80 => self.val_type(),
+            // Otherwise it's a type index, as a signed LEB.
+            _ => {
+                self.unknown = true;
+                self.heap_type()
+            }
+        }
+    }
+
+    fn mem_arg(&mut self) -> Result<()> {
+        let start = self.r.position();
+        let flags = self.r.u32()?;
+        let explicit = flags & 0x40 != 0;
+        let index = if explicit { self.r.u32()? } else { 0 };
+        let align = flags & !0x40;
+        self.hole(start, index, RawIndex::MemArg { align, explicit })?;
+        // The offset, which is 64 bits for 64-bit memories.
+        self.r.leb()
+    }
+
+    fn tag(&mut self) -> Result<()> {
+        self.unknown = true;
+        self.index(RawIndex::Tag)
+    }
+
+    /// Decode instructions up to and including the `end` of the body.
+    fn instructions(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.r.byte()? {
+                0x00 | 0x01 | 0x05 | 0x0f | 0x1a | 0x1b | 0xd1 | 0x45..=0xc4 => {}
+                0x02..=0x04 => {
+                    self.block_type()?;
+                    depth += 1;
+                }
+                0x0b => {
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    depth -= 1;
+                }
+                0x0c | 0x0d => self.label()?,
+                0x0e => {
+                    for _ in 0..self.r.u32()? {
+                        self.label()?;
+                    }
+                    self.label()?;
+                }
+                0x10 => self.index(RawIndex::Func)?,
+                0x11 => {
+                    self.index(RawIndex::Type)?;
+                    self.index(RawIndex::Table)?;
+                }
+                0x20..=0x22 => {
+                    self.r.u32()?;
+                }
+                0x23 | 0x24 => self.index(RawIndex::Global)?,
+                0x25 | 0x26 => self.index(RawIndex::Table)?,
+                0x28..=0x3e => self.mem_arg()?,
+                0x3f | 0x40 => self.index(RawIndex::Memory)?,
+                0x41 | 0x42 => {
+                    self.r.s64()?;
+                }
+                0x43 => self.r.skip(4)?,
+                0x44 => self.r.skip(8)?,
+                0xfc => self.misc()?,
+                0xfe => self.atomic()?,
+
+                // Everything from here on can't be parsed by walrus.
+                0x06 => {
+                    // `try`
+                    self.unknown = true;
+                    self.block_type()?;
+                    depth += 1;
+                }
+                0x07 | 0x08 => self.tag()?,
+                0x09 | 0xd5 | 0xd6 => {
+                    // `rethrow`, `br_on_null` and `br_on_non_null`
+                    self.unknown = true;
+                    self.label()?;
+                }
+                0x18 => {
+                    // `delegate`, which ends a `try`
+                    self.unknown = true;
+                    self.label()?;
+                    if depth == 0 {
+                        bail!("`delegate` outside of a `try`");
+                    }
+                    depth -= 1;
+                }
+                0x0a | 0x19 | 0xd3 | 0xd4 => {
+                    // `throw_ref`, `catch_all`, `ref.eq` and `ref.as_non_null`
+                    self.unknown = true;
+                }
+                0x12 | 0xd2 => {
+                    // `return_call` and `ref.func`
+                    self.unknown = true;
+                    self.index(RawIndex::Func)?;
+                }
+                0x13 => {
+                    // `return_call_indirect`
+                    self.unknown = true;
+                    self.index(RawIndex::Type)?;
+                    self.index(RawIndex::Table)?;
+                }
+                0x14 | 0x15 => {
+                    // `call_ref` and `return_call_ref`
+                    self.unknown = true;
+                    self.index(RawIndex::Type)?;
+                }
+                0x1c => {
+                    // `select` with types
+                    self.unknown = true;
+                    for _ in 0..self.r.u32()? {
+                        self.val_type()?;
+                    }
+                }
+                0x1f => {
+                    // `try_table`
+                    self.unknown = true;
+                    self.block_type()?;
+                    depth += 1;
+                    for _ in 0..self.r.u32()? {
+                        match self.r.byte()? {
+                            0x00 | 0x01 => self.tag()?,
+                            0x02 | 0x03 => self.label()?,
+                            kind => bail!("invalid catch kind: {:#x}", kind),
+                        }
+                    }
+                }
+                0xd0 => {
+                    // `ref.null`, with a heap type
+                    self.unknown = true;
+                    self.heap_type()?;
+                }
+                0xfb => {
+                    self.unknown = true;
+                    self.gc()?;
+                }
+                0xfd => {
+                    self.unknown = true;
+                    self.simd()?;
+                }
+                opcode => bail!("unknown opcode: {:#x}", opcode),
+            }
+        }
+    }
+
+    fn misc(&mut self) -> Result<()> {
+        match self.r.u32()? {
+            0..=7 => {}
+            8 => {
+                self.index(RawIndex::Data)?;
+                self.index(RawIndex::Memory)?;
+            }
+            9 => self.index(RawIndex::Data)?,
+            10 => {
+                self.index(RawIndex::Memory)?;
+                self.index(RawIndex::Memory)?;
+            }
+            11 => self.index(RawIndex::Memory)?,
+            12 => {
+                self.index(RawIndex::Element)?;
+                self.index(RawIndex::Table)?;
+            }
+            13 => self.index(RawIndex::Element)?,
+            14 => {
+                self.index(RawIndex::Table)?;
+                self.index(RawIndex::Table)?;
+            }
+            15 | 16 => self.index(RawIndex::Table)?,
+            17 => {
+                // `table.fill`
+                self.unknown = true;
+                self.index(RawIndex::Table)?;
+            }
+            opcode => bail!("unknown opcode: 0xfc {}", opcode),
+        }
+        Ok(())
+    }
+
+    fn atomic(&mut self) -> Result<()> {
+        match self.r.u32()? {
+            0x03 => {
+                // `atomic.fence`
+                self.r.byte()?;
+            }
+            0x00..=0x02 | 0x10..=0x4e => self.mem_arg()?,
+            opcode => bail!("unknown opcode: 0xfe {}", opcode),
+        }
+        Ok(())
+    }
+
+    fn gc(&mut self) -> Result<()> {
+        match self.r.u32()? {
+            0 | 1 | 6 | 7 | 11..=14 | 16 => self.index(RawIndex::Type)?,
+            2..=5 | 8 => {
+                // A field index or the number of elements.
+                self.index(RawIndex::Type)?;
+                self.r.u32()?;
+            }
+            9 | 18 => {
+                self.index(RawIndex::Type)?;
+                self.index(RawIndex::Data)?;
+            }
+            10 | 19 => {
+                self.index(RawIndex::Type)?;
+                self.index(RawIndex::Element)?;
+            }
+            17 => {
+                self.index(RawIndex::Type)?;
+                self.index(RawIndex::Type)?;
+            }
+            15 | 26..=30 => {}
+            20..=23 => self.heap_type()?,
+            24 | 25 => {
+                // `br_on_cast` and `br_on_cast_fail`
+                self.r.byte()?;
+                self.label()?;
+                self.heap_type()?;
+                self.heap_type()?;
+            }
+            opcode => bail!("unknown opcode: 0xfb {}", opcode),
+        }
+        Ok(())
+    }
+
+    fn simd(&mut self) -> Result<()> {
+        match self.r.u32()? {
+            0..=11 | 92 | 93 => self.mem_arg()?,
+            // `v128.const` and `i8x16.shuffle`
+            12 | 13 => self.r.skip(16)?,
+            // Lane accesses
+            21..=34 => self.r.skip(1)?,
+            84..=91 => {
+                self.mem_arg()?;
+                self.r.skip(1)?;
+            }
+            // Including the relaxed SIMD proposal.
+            opcode if opcode <= 0x113 => {}
+            opcode => bail!("unknown opcode: 0xfd {}", opcode),
+        }
+        Ok(())
+    }
+}
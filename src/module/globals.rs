@@ -1,8 +1,12 @@
 //! Globals within a wasm module.
+use crate::decode::{val_type, Reader};
 use crate::emit::{Emit, EmitContext, Section};
-use crate::parse::IndicesToIds;
+use crate::ir::{RefType, Value};
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{ImportId, InitExpr, Module, Result, ValType};
+use crate::{FunctionId, ImportId, InitExpr, InitOp, Module, Result, ValType};
+use failure::bail;
+use rayon::prelude::*;
 
 /// The id of a global.
 pub type GlobalId = Id<Global>;
@@ -35,6 +39,24 @@ pub enum GlobalKind {
     Local(InitExpr),
 }
 
+/// The value of a constant expression, as far as it's known before the
+/// module is instantiated, as returned by `ModuleGlobals::eval`.
+#[derive(Debug, Copy, Clone)]
+pub enum ConstValue {
+    /// A known constant.
+    Known(Value),
+    /// The value of the given imported global, which isn't known until the
+    /// module is instantiated.
+    Imported(GlobalId),
+    /// A reference to the given function.
+    RefFunc(FunctionId),
+    /// A null reference of the given type.
+    RefNull(RefType),
+    /// A number computed from the values of imported globals, which isn't
+    /// known until the module is instantiated.
+    Unknown,
+}
+
 impl Global {
     /// Get this global's id.
     pub fn id(&self) -> GlobalId {
@@ -42,6 +64,15 @@ impl Global {
     }
 }
 
+impl From<&Global> for wasmparser::GlobalType {
+    fn from(global: &Global) -> wasmparser::GlobalType {
+        wasmparser::GlobalType {
+            content_type: global.ty.into(),
+            mutable: global.mutable,
+        }
+    }
+}
+
 impl Emit for Global {
     fn emit(&self, cx: &mut EmitContext) {
         Emit::emit(&self.ty, cx);
@@ -78,6 +109,123 @@ impl ModuleGlobals {
         })
     }
 
+    /// Construct a new global which is initialized with the value of the
+    /// global `source`, with a `global.get` initializer.
+    ///
+    /// This is how globals relative to an imported base, such as
+    /// `__memory_base`, are defined.
+    ///
+    /// # Errors
+    ///
+    /// Constant expressions can only read imported, immutable globals, so
+    /// this returns an error if `source` is a local or mutable global, or if
+    /// its type isn't `ty`.
+    pub fn add_derived(
+        &mut self,
+        ty: ValType,
+        mutable: bool,
+        source: GlobalId,
+    ) -> Result<GlobalId> {
+        let global = self.get(source);
+        match global.kind {
+            GlobalKind::Import(_) => {}
+            GlobalKind::Local(_) => bail!(
+                "can't initialize a global with the value of the local global {:?}",
+                source
+            ),
+        }
+        if global.mutable {
+            bail!(
+                "can't initialize a global with the value of the mutable global {:?}",
+                source
+            );
+        }
+        if global.ty != ty {
+            bail!(
+                "can't initialize a global of type {} with the value of the global {:?} of type {}",
+                ty,
+                source,
+                global.ty
+            );
+        }
+        Ok(self.add_local(ty, mutable, InitExpr::Global(source)))
+    }
+
+    /// Evaluate a constant expression, such as a global's initializer or a
+    /// segment's offset.
+    ///
+    /// Reading an imported global gives a symbolic `ConstValue::Imported`,
+    /// since its value isn't known until instantiation. Reading a local
+    /// global evaluates its initializer in turn. Arithmetic in an extended
+    /// expression wraps, and gives `ConstValue::Unknown` if any of its
+    /// operands read an imported global.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the globals' initializers read each other in a
+    /// cycle, or if an extended expression is ill-typed.
+    pub fn eval(&self, init: InitExpr) -> Result<ConstValue> {
+        self.eval_at_depth(&init, 0)
+    }
+
+    /// Compute the value of a constant expression, if it's a number known
+    /// before the module is instantiated.
+    ///
+    /// Returns `None` if the expression reads an imported global, produces a
+    /// reference, or fails to evaluate; see `eval` for details.
+    pub fn const_eval(&self, init: &InitExpr) -> Option<Value> {
+        match self.eval_at_depth(init, 0) {
+            Ok(ConstValue::Known(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn eval_at_depth(&self, init: &InitExpr, depth: usize) -> Result<ConstValue> {
+        if depth > self.arena.len() {
+            bail!("the initializers of globals read each other in a cycle")
+        }
+        let ops = match init {
+            InitExpr::Value(value) => return Ok(ConstValue::Known(*value)),
+            InitExpr::Global(id) => match &self.get(*id).kind {
+                GlobalKind::Import(_) => return Ok(ConstValue::Imported(*id)),
+                GlobalKind::Local(next) => return self.eval_at_depth(next, depth + 1),
+            },
+            InitExpr::RefFunc(func) => return Ok(ConstValue::RefFunc(*func)),
+            InitExpr::RefNull(ty) => return Ok(ConstValue::RefNull(*ty)),
+            InitExpr::Extended(ops) => ops,
+        };
+
+        let mut stack = Vec::new();
+        for op in ops {
+            let value = match *op {
+                InitOp::Value(value) => ConstValue::Known(value),
+                InitOp::Global(id) => self.eval_at_depth(&InitExpr::Global(id), depth)?,
+                InitOp::RefFunc(func) => ConstValue::RefFunc(func),
+                InitOp::RefNull(ty) => ConstValue::RefNull(ty),
+                op => {
+                    let (rhs, lhs) = match (stack.pop(), stack.pop()) {
+                        (Some(rhs), Some(lhs)) => (rhs, lhs),
+                        _ => bail!("missing operands in constant expression"),
+                    };
+                    binop(op, lhs, rhs)?
+                }
+            };
+            stack.push(value);
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(value), true) => Ok(value),
+            _ => bail!("constant expression must produce exactly one value"),
+        }
+    }
+
+    /// Get the value a global is initialized with, as far as it's known
+    /// before the module is instantiated.
+    ///
+    /// See `eval` for details.
+    pub fn initial_value(&self, id: GlobalId) -> Result<ConstValue> {
+        self.eval(InitExpr::Global(id))
+    }
+
     /// Gets a reference to a memory given its id
     pub fn get(&self, id: GlobalId) -> &Global {
         &self.arena[id]
@@ -100,25 +248,99 @@ impl ModuleGlobals {
     pub fn iter(&self) -> impl Iterator<Item = &Global> {
         self.arena.iter().map(|(_, f)| f)
     }
+
+    /// Get the number of globals in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no globals.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more globals, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a mutable reference to this module's globals.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Global> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
+
+    /// Get a shared reference to this module's globals, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (GlobalId, &Global)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's globals.
+    pub fn ids(&self) -> impl Iterator<Item = GlobalId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's globals, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Global> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's globals, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Global> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+}
+
+/// Apply an arithmetic instruction of an extended constant expression.
+fn binop(op: InitOp, lhs: ConstValue, rhs: ConstValue) -> Result<ConstValue> {
+    use self::ConstValue::{Imported, Known, Unknown};
+    use crate::ir::Value::{I32, I64};
+
+    fn is_number(value: ConstValue) -> bool {
+        match value {
+            Known(_) | Imported(_) | Unknown => true,
+            ConstValue::RefFunc(_) | ConstValue::RefNull(_) => false,
+        }
+    }
+
+    let value = match (op, lhs, rhs) {
+        (InitOp::I32Add, Known(I32(a)), Known(I32(b))) => I32(a.wrapping_add(b)),
+        (InitOp::I32Sub, Known(I32(a)), Known(I32(b))) => I32(a.wrapping_sub(b)),
+        (InitOp::I32Mul, Known(I32(a)), Known(I32(b))) => I32(a.wrapping_mul(b)),
+        (InitOp::I64Add, Known(I64(a)), Known(I64(b))) => I64(a.wrapping_add(b)),
+        (InitOp::I64Sub, Known(I64(a)), Known(I64(b))) => I64(a.wrapping_sub(b)),
+        (InitOp::I64Mul, Known(I64(a)), Known(I64(b))) => I64(a.wrapping_mul(b)),
+        (_, Known(_), Known(_)) => bail!("mismatched types in constant expression"),
+        (_, lhs, rhs) if is_number(lhs) && is_number(rhs) => return Ok(Unknown),
+        _ => bail!("arithmetic on references in constant expression"),
+    };
+    Ok(Known(value))
 }
 
 impl Module {
     /// Construct a new, empty set of globals for a module.
-    pub(crate) fn parse_globals(
-        &mut self,
-        section: wasmparser::GlobalSectionReader,
-        ids: &mut IndicesToIds,
-    ) -> Result<()> {
+    pub(crate) fn parse_globals(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
         log::debug!("parse global section");
-        for g in section {
-            let g = g?;
-            let id = self.globals.add_local(
-                ValType::parse(&g.ty.content_type)?,
-                g.ty.mutable,
-                InitExpr::eval(&g.init_expr, ids)?,
-            );
+        // This is decoded here rather than by `wasmparser`, which can't read
+        // initializers using `ref.func`.
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.globals.reserve(reserve_hint(count));
+        for _ in 0..count {
+            let ty = val_type(&mut r, ids)?;
+            let mutable = match r.byte()? {
+                0x00 => false,
+                0x01 => true,
+                byte => bail!("invalid mutability: {:#x}", byte),
+            };
+            let id = self
+                .globals
+                .add_local(ty, mutable, InitExpr::decode(&mut r, ids)?);
             ids.push_global(id);
         }
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
         Ok(())
     }
 }
@@ -1,10 +1,16 @@
 //! A wasm module's imports.
 
+use crate::decode::{limits, ref_type, val_type, Reader};
 use crate::emit::{Emit, EmitContext, Section};
-use crate::parse::IndicesToIds;
+use crate::ir::RefType;
+use crate::module::functions::ImportedFunction;
+use crate::module::tags::tag_type;
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, FunctionTable, GlobalId, MemoryId, Result, TableId};
-use crate::{Module, TableKind, TypeId, ValType};
+use crate::{ExternrefTable, FunctionId, FunctionKind, FunctionTable, GlobalId, MemoryId};
+use crate::{Module, Result, SharedStr, TableId, TableKind, TagId, TypeId, ValType};
+use failure::bail;
+use rayon::prelude::*;
 
 /// The id of an import.
 pub type ImportId = Id<Import>;
@@ -14,17 +20,17 @@ pub type ImportId = Id<Import>;
 pub struct Import {
     id: ImportId,
     /// The module name of this import.
-    pub module: String,
+    pub module: SharedStr,
     /// The name of this import.
-    pub name: String,
+    pub name: SharedStr,
     /// The kind of item being imported.
     pub kind: ImportKind,
 }
 
 impl Tombstone for Import {
     fn on_delete(&mut self) {
-        self.module = String::new();
-        self.name = String::new();
+        self.module = SharedStr::default();
+        self.name = SharedStr::default();
     }
 }
 
@@ -46,6 +52,8 @@ pub enum ImportKind {
     Memory(MemoryId),
     /// An imported global.
     Global(GlobalId),
+    /// An imported exception tag.
+    Tag(TagId),
 }
 
 /// The set of imports in a module.
@@ -78,24 +86,65 @@ impl ModuleImports {
         self.arena.iter().map(|(_, f)| f)
     }
 
+    /// Get the number of imports in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no imports.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more imports, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
     /// Get mutable references to this module's imports.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Import> {
         self.arena.iter_mut().map(|(_, f)| f)
     }
 
+    /// Get a shared reference to this module's imports, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (ImportId, &Import)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's imports.
+    pub fn ids(&self) -> impl Iterator<Item = ImportId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's imports, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Import> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's imports, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Import> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+
     /// Adds a new import to this module
     pub fn add(&mut self, module: &str, name: &str, kind: impl Into<ImportKind>) -> ImportId {
+        self.add_shared(module.into(), name.into(), kind.into())
+    }
+
+    fn add_shared(&mut self, module: SharedStr, name: SharedStr, kind: ImportKind) -> ImportId {
         self.arena.alloc_with_id(|id| Import {
             id,
-            module: module.to_string(),
-            name: name.to_string(),
-            kind: kind.into(),
+            module,
+            name,
+            kind,
         })
     }
 
     /// Get the import with the given module and name
     pub fn find(&self, module: &str, name: &str) -> Option<ImportId> {
-        let import = self.arena
+        let import = self
+            .arena
             .iter()
             .find(|(_, import)| import.name == name && import.module == module);
 
@@ -105,56 +154,68 @@ impl ModuleImports {
 
 impl Module {
     /// Construct the import set for a wasm module.
-    pub(crate) fn parse_imports(
-        &mut self,
-        section: wasmparser::ImportSectionReader,
-        ids: &mut IndicesToIds,
-    ) -> Result<()> {
+    pub(crate) fn parse_imports(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
         log::debug!("parse import section");
-        for entry in section {
-            let entry = entry?;
-            match entry.ty {
-                wasmparser::ImportSectionEntryType::Function(idx) => {
-                    let ty = ids.get_type(idx)?;
-                    let id = self.add_import_func(entry.module, entry.field, ty);
+        // This is decoded here rather than by `wasmparser`, which doesn't
+        // know about imported tags.
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.imports.reserve(reserve_hint(count));
+        for _ in 0..count {
+            let module = r.name()?;
+            let field = r.name()?;
+            match r.byte()? {
+                0x00 => {
+                    let ty = ids.get_type(r.u32()?)?;
+                    let id = self.add_import_func(module, field, ty);
                     ids.push_func(id);
                 }
-                wasmparser::ImportSectionEntryType::Table(t) => {
-                    let kind = match t.element_type {
-                        wasmparser::Type::AnyFunc => TableKind::Function(FunctionTable::default()),
-                        _ => failure::bail!("invalid table type"),
+                0x01 => {
+                    let kind = match ref_type(&mut r)? {
+                        RefType::Funcref => TableKind::Function(FunctionTable::default()),
+                        RefType::Externref => TableKind::Externref(ExternrefTable::default()),
                     };
-                    let id = self.add_import_table(
-                        entry.module,
-                        entry.field,
-                        t.limits.initial,
-                        t.limits.maximum,
-                        kind,
-                    );
+                    let limits = limits(&mut r)?;
+                    if limits.page_size_log2.is_some() {
+                        bail!("tables can't have a custom page size");
+                    }
+                    let id =
+                        self.add_import_table(module, field, limits.initial, limits.maximum, kind);
                     ids.push_table(id);
                 }
-                wasmparser::ImportSectionEntryType::Memory(m) => {
+                0x02 => {
+                    let limits = limits(&mut r)?;
                     let id = self.add_import_memory(
-                        entry.module,
-                        entry.field,
-                        m.shared,
-                        m.limits.initial,
-                        m.limits.maximum,
+                        module,
+                        field,
+                        limits.shared,
+                        limits.initial,
+                        limits.maximum,
                     );
+                    self.memories.get_mut(id).page_size_log2 = limits.page_size_log2;
                     ids.push_memory(id);
                 }
-                wasmparser::ImportSectionEntryType::Global(g) => {
-                    let id = self.add_import_global(
-                        entry.module,
-                        entry.field,
-                        ValType::parse(&g.content_type)?,
-                        g.mutable,
-                    );
+                0x03 => {
+                    let ty = val_type(&mut r, ids)?;
+                    let mutable = match r.byte()? {
+                        0x00 => false,
+                        0x01 => true,
+                        byte => bail!("invalid mutability: {:#x}", byte),
+                    };
+                    let id = self.add_import_global(module, field, ty, mutable);
                     ids.push_global(id);
                 }
+                0x04 => {
+                    let ty = tag_type(&mut r, ids)?;
+                    let id = self.add_import_tag(module, field, ty);
+                    ids.push_tag(id);
+                }
+                kind => bail!("invalid import kind: {:#x}", kind),
             }
         }
-
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
         Ok(())
     }
 
@@ -162,10 +223,35 @@ impl Module {
     pub fn add_import_func(&mut self, module: &str, name: &str, ty: TypeId) -> FunctionId {
         let import = self.imports.arena.next_id();
         let func = self.funcs.add_import(ty, import);
-        self.imports.add(module, name, func);
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        self.imports.add_shared(module, name, func.into());
         func
     }
 
+    /// Turn the local function `id` into a function imported as
+    /// `module`.`name`, returning the new import's id.
+    ///
+    /// The function keeps its id, type and name, so calls, exports and table
+    /// elements referring to it are unchanged. Its body is dropped, along
+    /// with any locals only it used, which are no longer emitted.
+    ///
+    /// Returns an error if `id` isn't a local function.
+    pub fn externalize_function(
+        &mut self,
+        id: FunctionId,
+        module: &str,
+        name: &str,
+    ) -> Result<ImportId> {
+        let ty = match &self.funcs.get(id).kind {
+            FunctionKind::Local(local) => local.ty,
+            _ => bail!("function {} isn't a local function", id.index()),
+        };
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        let import = self.imports.add_shared(module, name, id.into());
+        self.funcs.get_mut(id).kind = FunctionKind::Import(ImportedFunction { import, ty });
+        Ok(import)
+    }
+
     /// Add an imported memory to this module
     pub fn add_import_memory(
         &mut self,
@@ -177,7 +263,8 @@ impl Module {
     ) -> MemoryId {
         let import = self.imports.arena.next_id();
         let mem = self.memories.add_import(shared, initial, maximum, import);
-        self.imports.add(module, name, mem);
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        self.imports.add_shared(module, name, mem.into());
         mem
     }
 
@@ -192,7 +279,8 @@ impl Module {
     ) -> TableId {
         let import = self.imports.arena.next_id();
         let table = self.tables.add_import(initial, max, kind, import);
-        self.imports.add(module, name, table);
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        self.imports.add_shared(module, name, table.into());
         table
     }
 
@@ -206,9 +294,19 @@ impl Module {
     ) -> GlobalId {
         let import = self.imports.arena.next_id();
         let global = self.globals.add_import(ty, mutable, import);
-        self.imports.add(module, name, global);
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        self.imports.add_shared(module, name, global.into());
         global
     }
+
+    /// Add an imported exception tag to this module
+    pub fn add_import_tag(&mut self, module: &str, name: &str, ty: TypeId) -> TagId {
+        let import = self.imports.arena.next_id();
+        let tag = self.tags.add_import(ty, import);
+        let (module, name) = (self.shared_str(module), self.shared_str(name));
+        self.imports.add_shared(module, name, tag.into());
+        tag
+    }
 }
 
 impl Emit for ModuleImports {
@@ -228,7 +326,6 @@ impl Emit for ModuleImports {
             match import.kind {
                 ImportKind::Function(id) => {
                     cx.encoder.byte(0x00);
-                    cx.indices.push_func(id);
                     let ty = cx.module.funcs.get(id).ty();
                     let idx = cx.indices.get_type_index(ty);
                     cx.encoder.u32(idx);
@@ -248,6 +345,11 @@ impl Emit for ModuleImports {
                     cx.indices.push_global(id);
                     cx.module.globals.get(id).emit(&mut cx);
                 }
+                ImportKind::Tag(id) => {
+                    cx.encoder.byte(0x04);
+                    cx.indices.push_tag(id);
+                    cx.module.tags.get(id).emit(&mut cx);
+                }
             }
         }
     }
@@ -276,3 +378,9 @@ impl From<TableId> for ImportKind {
         ImportKind::Table(id)
     }
 }
+
+impl From<TagId> for ImportKind {
+    fn from(id: TagId) -> ImportKind {
+        ImportKind::Tag(id)
+    }
+}
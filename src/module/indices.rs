@@ -0,0 +1,103 @@
+//! Freezing the function index space of a module before it's emitted.
+
+use crate::map::IdHashMap;
+use crate::{Function, FunctionId, Module, Result};
+use failure::bail;
+
+/// The index every function will have in the emitted module, as frozen by
+/// `Module::assign_indices`.
+#[derive(Debug, Clone, Default)]
+pub struct EmittedIndices {
+    funcs: Vec<FunctionId>,
+    indices: IdHashMap<Function, u32>,
+}
+
+impl EmittedIndices {
+    /// Get the index the given function will be emitted at, or `None` if it
+    /// won't be emitted.
+    pub fn func(&self, id: FunctionId) -> Option<u32> {
+        self.indices.get(&id).cloned()
+    }
+
+    /// Get every function that will be emitted, in order of index.
+    pub fn funcs(&self) -> &[FunctionId] {
+        &self.funcs
+    }
+
+    /// The position of the given local function among the local functions,
+    /// used to emit them in their frozen order.
+    pub(crate) fn position(&self, id: FunctionId) -> usize {
+        self.func(id).map_or(usize::MAX, |i| i as usize)
+    }
+
+    /// Record that the given function is emitted at `index`, the next one.
+    pub(crate) fn push(&mut self, id: FunctionId, index: u32) {
+        debug_assert_eq!(index as usize, self.funcs.len());
+        self.funcs.push(id);
+        self.indices.insert(id, index);
+    }
+}
+
+impl Module {
+    /// Decide the index every function will be emitted at, and freeze them.
+    ///
+    /// This makes the same decisions emitting the module would, following
+    /// its configuration, but once frozen the indices stay the same even if
+    /// functions change size, so they can be baked into data segments or
+    /// function bodies. Emitting the module fails if functions or function
+    /// imports are added or removed after their indices are frozen, unless
+    /// they're frozen again or unfrozen with `unfreeze_indices`.
+    pub fn assign_indices(&mut self) -> EmittedIndices {
+        self.frozen_indices = None;
+        let frozen = self.function_indices();
+        self.frozen_indices = Some(frozen.clone());
+        frozen
+    }
+
+    /// Get the indices frozen by `assign_indices`, if they're still frozen.
+    pub fn frozen_indices(&self) -> Option<&EmittedIndices> {
+        self.frozen_indices.as_ref()
+    }
+
+    /// Let emitting this module decide indices for itself again.
+    pub fn unfreeze_indices(&mut self) {
+        self.frozen_indices = None;
+    }
+
+    /// Check that the frozen indices, if any, still cover exactly the
+    /// functions that will be emitted.
+    pub(crate) fn check_frozen_indices(&self) -> Result<()> {
+        let frozen = match &self.frozen_indices {
+            Some(frozen) => frozen,
+            None => return Ok(()),
+        };
+        let mut count = 0;
+        for (index, func) in self.function_index_order() {
+            match frozen.func(func.id()) {
+                Some(expected) if expected == index => {}
+                Some(expected) => bail!(
+                    "function {:?} would be emitted at index {} rather than its \
+                     frozen index {}, since functions were removed after indices \
+                     were assigned",
+                    func.id(),
+                    index,
+                    expected
+                ),
+                None => bail!(
+                    "function {:?} was added after indices were assigned",
+                    func.id()
+                ),
+            }
+            count += 1;
+        }
+        if count != frozen.funcs.len() {
+            bail!(
+                "{} functions were frozen but {} will be emitted; functions \
+                 were removed after indices were assigned",
+                frozen.funcs.len(),
+                count
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Reading the rest of a module while its functions are being changed.
+
+use crate::{FunctionId, LocalFunction, Module, ModuleCustomSections, ModuleData};
+use crate::{ModuleElements, ModuleExports, ModuleFunctions, ModuleGlobals, ModuleImports};
+use crate::{ModuleLocals, ModuleMemories, ModuleTables, ModuleTypes};
+use rayon::prelude::*;
+
+/// Shared references to every part of a `Module` except its functions, as
+/// split off by `Module::split_funcs_mut`.
+///
+/// This can be captured by closures which modify functions in parallel,
+/// where borrowing the whole module isn't possible.
+#[derive(Debug, Copy, Clone)]
+#[allow(missing_docs)]
+pub struct ModuleInfo<'a> {
+    pub imports: &'a ModuleImports,
+    pub tables: &'a ModuleTables,
+    pub types: &'a ModuleTypes,
+    pub globals: &'a ModuleGlobals,
+    pub locals: &'a ModuleLocals,
+    pub exports: &'a ModuleExports,
+    pub memories: &'a ModuleMemories,
+    pub data: &'a ModuleData,
+    pub elements: &'a ModuleElements,
+    pub start: Option<FunctionId>,
+    pub customs: &'a ModuleCustomSections,
+}
+
+impl Module {
+    /// Borrow this module's functions mutably, along with everything else in
+    /// it immutably.
+    pub fn split_funcs_mut(&mut self) -> (&mut ModuleFunctions, ModuleInfo<'_>) {
+        let info = ModuleInfo {
+            imports: &self.imports,
+            tables: &self.tables,
+            types: &self.types,
+            globals: &self.globals,
+            locals: &self.locals,
+            exports: &self.exports,
+            memories: &self.memories,
+            data: &self.data,
+            elements: &self.elements,
+            start: self.start,
+            customs: &self.customs,
+        };
+        (&mut self.funcs, info)
+    }
+
+    /// Run `f` on every local function in parallel, with read access to the
+    /// rest of the module, and collect the results sorted by function id.
+    ///
+    /// This is the mutable counterpart of `ModuleFunctions::par_map_local`.
+    pub fn par_map_local_mut<T, F>(&mut self, f: F) -> Vec<(FunctionId, T)>
+    where
+        T: Send,
+        F: Fn(&ModuleInfo, FunctionId, &mut LocalFunction) -> T + Sync,
+    {
+        let (funcs, info) = self.split_funcs_mut();
+        let info = &info;
+        let mut results = funcs
+            .par_iter_local_mut()
+            .map(|(id, func)| (id, f(info, id, func)))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(id, _)| *id);
+        results
+    }
+}
@@ -0,0 +1,292 @@
+//! The byte layout of an emitted wasm module.
+
+use crate::emit::IdsToIndices;
+use crate::{DataId, ExportId, FunctionId, ImportId, ImportKind, Module, Result};
+use failure::bail;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Where everything ended up in a wasm module emitted by
+/// `Module::emit_wasm_with_layout`.
+///
+/// All ranges are byte ranges into the emitted buffer.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleLayout {
+    /// Every section, known and custom, in the order they appear.
+    ///
+    /// These cover the whole module after its 8-byte header.
+    pub sections: Vec<SectionLayout>,
+    /// The body of every local function, in the order they appear in the code
+    /// section, not including the size of the body.
+    pub functions: Vec<(FunctionId, Range<usize>)>,
+    /// The payload of every data segment, in the order they appear in the
+    /// data section, along with the id of passive segments.
+    pub data: Vec<(Option<DataId>, Range<usize>)>,
+    /// Every entry of the import section.
+    pub imports: Vec<(ImportId, Range<usize>)>,
+    /// Every entry of the export section.
+    pub exports: Vec<(ExportId, Range<usize>)>,
+}
+
+/// Where a section ended up in an emitted wasm module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionLayout {
+    /// The id of this section.
+    pub id: u8,
+    /// The name of this section, if it's a custom section.
+    pub name: Option<String>,
+    /// The whole section, including its id and size.
+    pub range: Range<usize>,
+    /// The contents of this section, after its id and size.
+    pub payload: Range<usize>,
+}
+
+impl ModuleLayout {
+    /// Find the layout of a module that was just emitted into `wasm`, using
+    /// the indices that were assigned while emitting it.
+    pub(crate) fn scan(module: &Module, indices: &IdsToIndices, wasm: &[u8]) -> Result<Self> {
+        let mut funcs = HashMap::new();
+        for func in module.funcs.iter() {
+            if let Some(index) = indices.try_get_func_index(func.id()) {
+                funcs.insert(index, func.id());
+            }
+        }
+        let mut passive = HashMap::new();
+        for data in module.data.iter() {
+            passive.insert(indices.get_data_index(data.id()), data.id());
+        }
+        let num_imported_funcs = module
+            .imports
+            .iter()
+            .filter(|i| matches!(i.kind, ImportKind::Function(_)))
+            .count();
+
+        let mut layout = ModuleLayout::default();
+        let mut reader = Reader { wasm, pos: 8 };
+        while !reader.eof() {
+            let start = reader.pos;
+            let id = reader.byte()?;
+            let len = reader.u32()? as usize;
+            let payload = reader.pos..reader.pos + len;
+            reader.skip(len)?;
+            let mut section = Reader {
+                wasm: &wasm[..payload.end],
+                pos: payload.start,
+            };
+
+            let mut name = None;
+            match id {
+                0 => {
+                    let len = section.u32()? as usize;
+                    let bytes = section.skip(len)?;
+                    name = Some(String::from_utf8_lossy(bytes).into_owned());
+                }
+                2 => {
+                    let imports = module.imports.iter().map(|i| i.id());
+                    layout.imports = section.entries(imports, Reader::import)?;
+                }
+                7 => {
+                    let exports = module.exports.iter().map(|e| e.id());
+                    layout.exports = section.entries(exports, Reader::export)?;
+                }
+                10 => {
+                    let count = section.u32()?;
+                    for i in 0..count {
+                        let len = section.u32()? as usize;
+                        let body = section.pos..section.pos + len;
+                        section.skip(len)?;
+                        let index = (num_imported_funcs + i as usize) as u32;
+                        match funcs.get(&index) {
+                            Some(id) => layout.functions.push((*id, body)),
+                            None => bail!("no function with index {}", index),
+                        }
+                    }
+                }
+                11 => {
+                    let count = section.u32()?;
+                    for i in 0..count {
+                        let payload = section.data_segment()?;
+                        layout.data.push((passive.get(&i).cloned(), payload));
+                    }
+                }
+                _ => {}
+            }
+
+            layout.sections.push(SectionLayout {
+                id,
+                name,
+                range: start..payload.end,
+                payload,
+            });
+        }
+        Ok(layout)
+    }
+}
+
+/// A minimal reader of the encodings walrus emits.
+struct Reader<'a> {
+    wasm: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn eof(&self) -> bool {
+        self.pos >= self.wasm.len()
+    }
+
+    fn skip(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.wasm.len() - self.pos < len {
+            bail!("unexpected end of module at {}", self.pos);
+        }
+        let bytes = &self.wasm[self.pos..][..len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.skip(1)?[0])
+    }
+
+    /// Read any LEB128-encoded integer.
+    fn leb(&mut self) -> Result<u64> {
+        let mut ret = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 64 {
+                ret |= u64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(ret);
+            }
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.leb()? as u32)
+    }
+
+    fn val_type(&mut self) -> Result<()> {
+        // Typed references are followed by the index of their type.
+        if let 0x63 | 0x64 = self.byte()? {
+            self.leb()?;
+        }
+        Ok(())
+    }
+
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 0x01 != 0 {
+            self.leb()?;
+        }
+        if flags & 0x08 != 0 {
+            self.leb()?; // the page size
+        }
+        Ok(())
+    }
+
+    /// Skip over a constant expression, including its `end`.
+    fn const_expr(&mut self) -> Result<()> {
+        loop {
+            match self.byte()? {
+                0x0b => return Ok(()),
+                0x41 | 0x42 | 0x23 | 0xd2 => {
+                    self.leb()?;
+                }
+                0x43 => {
+                    self.skip(4)?;
+                }
+                0x44 => {
+                    self.skip(8)?;
+                }
+                0xd0 => {
+                    self.byte()?;
+                }
+                0xfd => {
+                    self.leb()?; // v128.const
+                    self.skip(16)?;
+                }
+                op => bail!("unexpected opcode {:#x} in a constant expression", op),
+            }
+        }
+    }
+
+    /// Read a vector of entries, pairing each with the id it was emitted for.
+    fn entries<T, I>(
+        &mut self,
+        ids: I,
+        mut entry: impl FnMut(&mut Self) -> Result<()>,
+    ) -> Result<Vec<(T, Range<usize>)>>
+    where
+        I: Iterator<Item = T>,
+    {
+        let count = self.u32()? as usize;
+        let mut ret = Vec::with_capacity(count);
+        for id in ids.take(count) {
+            let start = self.pos;
+            entry(self)?;
+            ret.push((id, start..self.pos));
+        }
+        if ret.len() != count {
+            bail!("section has more entries than the module");
+        }
+        Ok(ret)
+    }
+
+    fn name(&mut self) -> Result<()> {
+        let len = self.u32()? as usize;
+        self.skip(len)?;
+        Ok(())
+    }
+
+    fn import(&mut self) -> Result<()> {
+        self.name()?;
+        self.name()?;
+        match self.byte()? {
+            0x00 => {
+                self.u32()?;
+            }
+            0x01 => {
+                self.byte()?;
+                self.limits()?;
+            }
+            0x02 => self.limits()?,
+            0x03 => {
+                self.val_type()?;
+                self.byte()?;
+            }
+            0x04 => {
+                self.byte()?; // the tag's attribute
+                self.u32()?;
+            }
+            kind => bail!("unknown import kind {:#x}", kind),
+        }
+        Ok(())
+    }
+
+    fn export(&mut self) -> Result<()> {
+        self.name()?;
+        self.byte()?;
+        self.u32()?;
+        Ok(())
+    }
+
+    /// Skip over a data segment, returning the range of its payload.
+    fn data_segment(&mut self) -> Result<Range<usize>> {
+        match self.u32()? {
+            0 => self.const_expr()?,
+            1 => {}
+            2 => {
+                self.u32()?;
+                self.const_expr()?;
+            }
+            flags => bail!("unknown data segment flags {:#x}", flags),
+        }
+        let len = self.u32()? as usize;
+        let start = self.pos;
+        self.skip(len)?;
+        Ok(start..self.pos)
+    }
+}
@@ -0,0 +1,267 @@
+//! Checking a module against the implementation limits of an engine.
+
+use crate::{FunctionId, FunctionKind, Module};
+use std::fmt;
+
+/// The largest modules and functions an engine accepts.
+///
+/// The defaults are the limits that the [JS embedding] requires of engines,
+/// which all of the browser engines implement.
+///
+/// [JS embedding]: https://webassembly.github.io/spec/js-api/#limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineLimits {
+    /// The most types a module can define.
+    pub max_types: u64,
+    /// The most functions a module can define or import.
+    pub max_functions: u64,
+    /// The most imports a module can have.
+    pub max_imports: u64,
+    /// The most exports a module can have.
+    pub max_exports: u64,
+    /// The most globals a module can define or import.
+    pub max_globals: u64,
+    /// The most data segments a module can have.
+    pub max_data_segments: u64,
+    /// The largest initial size of a table.
+    pub max_table_size: u64,
+    /// The largest initial size of a memory, in pages.
+    pub max_memory_pages: u64,
+    /// The most parameters a function can take.
+    pub max_function_params: u64,
+    /// The most results a function can return.
+    pub max_function_results: u64,
+    /// The largest estimated size of a function's body in bytes, as
+    /// measured by `LocalFunction::metrics`.
+    pub max_function_size: u64,
+    /// The most locals a function can have, including its parameters.
+    pub max_function_locals: u64,
+    /// The deepest nesting of blocks in a function.
+    ///
+    /// The JS embedding doesn't limit this, but engines compile nested
+    /// blocks recursively, so the default keeps well clear of their stacks.
+    pub max_nesting_depth: u64,
+}
+
+impl Default for EngineLimits {
+    fn default() -> EngineLimits {
+        EngineLimits {
+            max_types: 1_000_000,
+            max_functions: 1_000_000,
+            max_imports: 100_000,
+            max_exports: 100_000,
+            max_globals: 1_000_000,
+            max_data_segments: 100_000,
+            max_table_size: 10_000_000,
+            max_memory_pages: 65_536,
+            max_function_params: 1_000,
+            max_function_results: 1_000,
+            max_function_size: 7_654_321,
+            max_function_locals: 50_000,
+            max_nesting_depth: 1_000,
+        }
+    }
+}
+
+/// One of the limits in `EngineLimits`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// `EngineLimits::max_types`
+    Types,
+    /// `EngineLimits::max_functions`
+    Functions,
+    /// `EngineLimits::max_imports`
+    Imports,
+    /// `EngineLimits::max_exports`
+    Exports,
+    /// `EngineLimits::max_globals`
+    Globals,
+    /// `EngineLimits::max_data_segments`
+    DataSegments,
+    /// `EngineLimits::max_table_size`
+    TableSize,
+    /// `EngineLimits::max_memory_pages`
+    MemoryPages,
+    /// `EngineLimits::max_function_params`
+    FunctionParams,
+    /// `EngineLimits::max_function_results`
+    FunctionResults,
+    /// `EngineLimits::max_function_size`
+    FunctionSize,
+    /// `EngineLimits::max_function_locals`
+    FunctionLocals,
+    /// `EngineLimits::max_nesting_depth`
+    NestingDepth,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Types => "types",
+            Limit::Functions => "functions",
+            Limit::Imports => "imports",
+            Limit::Exports => "exports",
+            Limit::Globals => "globals",
+            Limit::DataSegments => "data segments",
+            Limit::TableSize => "table size",
+            Limit::MemoryPages => "memory pages",
+            Limit::FunctionParams => "parameters",
+            Limit::FunctionResults => "results",
+            Limit::FunctionSize => "body size",
+            Limit::FunctionLocals => "locals",
+            Limit::NestingDepth => "block nesting depth",
+        })
+    }
+}
+
+/// A limit that a module exceeds, as reported by `Module::check_limits`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitViolation {
+    /// The function exceeding the limit, if it's a limit on functions.
+    pub function: Option<FunctionId>,
+    /// The function's name, if it has one.
+    pub name: Option<String>,
+    /// The limit exceeded.
+    pub limit: Limit,
+    /// The module's or function's value for the limit.
+    pub value: u64,
+    /// The limit's maximum.
+    pub max: u64,
+}
+
+impl fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.function, &self.name) {
+            (Some(_), Some(name)) => write!(f, "function `{}`", name)?,
+            (Some(id), None) => write!(f, "function {}", id.index())?,
+            (None, _) => f.write_str("module")?,
+        }
+        write!(
+            f,
+            " exceeds the limit on {}: {} > {}",
+            self.limit, self.value, self.max
+        )
+    }
+}
+
+impl Module {
+    /// Find every way in which this module exceeds `limits`.
+    ///
+    /// Limits on the whole module are reported first, followed by the limits
+    /// on each function in order.
+    pub fn check_limits(&self, limits: &EngineLimits) -> Vec<LimitViolation> {
+        let mut violations = Vec::new();
+        let mut check = |function: Option<FunctionId>, limit, value: u64, max| {
+            if value > max {
+                violations.push(LimitViolation {
+                    function,
+                    name: function.and_then(|id| self.funcs.get(id).name.clone()),
+                    limit,
+                    value,
+                    max,
+                });
+            }
+        };
+
+        let count = |n: usize| n as u64;
+        let data_segments = self.data.iter().count()
+            + self
+                .memories
+                .iter()
+                .map(|m| m.data.iter().count())
+                .sum::<usize>();
+        check(
+            None,
+            Limit::Types,
+            count(self.types.iter().count()),
+            limits.max_types,
+        );
+        check(
+            None,
+            Limit::Functions,
+            count(self.funcs.iter().count()),
+            limits.max_functions,
+        );
+        check(
+            None,
+            Limit::Imports,
+            count(self.imports.iter().count()),
+            limits.max_imports,
+        );
+        check(
+            None,
+            Limit::Exports,
+            count(self.exports.iter().count()),
+            limits.max_exports,
+        );
+        check(
+            None,
+            Limit::Globals,
+            count(self.globals.iter().count()),
+            limits.max_globals,
+        );
+        check(
+            None,
+            Limit::DataSegments,
+            count(data_segments),
+            limits.max_data_segments,
+        );
+        for table in self.tables.iter() {
+            check(
+                None,
+                Limit::TableSize,
+                table.initial.into(),
+                limits.max_table_size,
+            );
+        }
+        for memory in self.memories.iter() {
+            check(
+                None,
+                Limit::MemoryPages,
+                memory.initial.into(),
+                limits.max_memory_pages,
+            );
+        }
+
+        for func in self.funcs.iter() {
+            let id = Some(func.id());
+            let ty = self.types.get(func.ty());
+            check(
+                id,
+                Limit::FunctionParams,
+                count(ty.params().len()),
+                limits.max_function_params,
+            );
+            check(
+                id,
+                Limit::FunctionResults,
+                count(ty.results().len()),
+                limits.max_function_results,
+            );
+            let local = match &func.kind {
+                FunctionKind::Local(local) => local,
+                _ => continue,
+            };
+            let metrics = local.metrics();
+            check(
+                id,
+                Limit::FunctionSize,
+                metrics.estimated_size,
+                limits.max_function_size,
+            );
+            check(
+                id,
+                Limit::FunctionLocals,
+                metrics.locals.into(),
+                limits.max_function_locals,
+            );
+            check(
+                id,
+                Limit::NestingDepth,
+                metrics.max_nesting_depth.into(),
+                limits.max_nesting_depth,
+            );
+        }
+        violations
+    }
+}
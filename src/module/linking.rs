@@ -0,0 +1,395 @@
+//! Sharing a memory and function table between modules, as dynamic linking
+//! does.
+//!
+//! The main module defines a memory and a function table and exports them,
+//! and each side module imports them rather than defining its own.
+
+use crate::{ExportItem, FunctionTable, ImportKind, MemoryId, Module, Result, TableId, TableKind};
+use failure::bail;
+use std::fmt;
+
+/// The initial and maximum sizes of a memory, in pages, or of a table, in
+/// elements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResizableLimits {
+    /// The initial size.
+    pub initial: u32,
+    /// The maximum size, if there is one.
+    pub maximum: Option<u32>,
+}
+
+impl ResizableLimits {
+    /// Can something with these limits be imported as something declaring
+    /// `import`'s limits?
+    ///
+    /// That's the case when it's at least as big initially, and, if `import`
+    /// has a maximum, never grows beyond it.
+    pub fn satisfies(&self, import: &ResizableLimits) -> bool {
+        if self.initial < import.initial {
+            return false;
+        }
+        match (self.maximum, import.maximum) {
+            (_, None) => true,
+            (Some(max), Some(import_max)) => max <= import_max,
+            (None, Some(_)) => false,
+        }
+    }
+}
+
+impl fmt::Display for ResizableLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.maximum {
+            Some(max) => write!(f, "{}..={}", self.initial, max),
+            None => write!(f, "{}..", self.initial),
+        }
+    }
+}
+
+/// Whether a `LinkIssue` is about the memory or the table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LinkItem {
+    /// The memory.
+    Memory,
+    /// The function table.
+    Table,
+}
+
+impl fmt::Display for LinkItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LinkItem::Memory => "memory",
+            LinkItem::Table => "table",
+        })
+    }
+}
+
+/// A reason a side module can't be linked against a main module, as
+/// reported by `check_link_compat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkIssue {
+    /// The side module imports a memory or table which the main module
+    /// doesn't export.
+    MissingExport {
+        /// What's imported.
+        item: LinkItem,
+        /// The name it's imported with.
+        name: String,
+    },
+    /// The main module exports something of a different kind with the name
+    /// the side module imports a memory or table with.
+    WrongKind {
+        /// What's imported.
+        item: LinkItem,
+        /// The name it's imported with.
+        name: String,
+    },
+    /// The main module's memory or table is too small, or can grow too big,
+    /// for the side module's import of it.
+    Limits {
+        /// What's imported.
+        item: LinkItem,
+        /// The name it's imported with.
+        name: String,
+        /// The limits of the main module's memory or table.
+        exported: ResizableLimits,
+        /// The limits the side module imports it with.
+        imported: ResizableLimits,
+    },
+    /// The main module's memory is shared and the side module's import of it
+    /// isn't, or the other way around.
+    Shared {
+        /// The name it's imported with.
+        name: String,
+        /// Whether the main module's memory is shared.
+        exported: bool,
+    },
+    /// The main module's table holds different kinds of elements than the
+    /// side module imports it with.
+    TableKind {
+        /// The name it's imported with.
+        name: String,
+    },
+    /// The side module defines its own memory or table, rather than importing
+    /// the main module's.
+    NotImported {
+        /// What's defined.
+        item: LinkItem,
+    },
+}
+
+impl fmt::Display for LinkIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkIssue::MissingExport { item, name } => {
+                write!(f, "imported {} `{}` isn't exported", item, name)
+            }
+            LinkIssue::WrongKind { item, name } => {
+                write!(
+                    f,
+                    "imported {} `{}` is exported as something else",
+                    item, name
+                )
+            }
+            LinkIssue::Limits {
+                item,
+                name,
+                exported,
+                imported,
+            } => write!(
+                f,
+                "imported {} `{}` has limits {}, which {} doesn't satisfy",
+                item, name, imported, exported
+            ),
+            LinkIssue::Shared {
+                name,
+                exported: true,
+            } => write!(f, "memory `{}` is shared, but its import isn't", name),
+            LinkIssue::Shared {
+                name,
+                exported: false,
+            } => write!(
+                f,
+                "imported memory `{}` is shared, but the export isn't",
+                name
+            ),
+            LinkIssue::TableKind { name } => write!(
+                f,
+                "imported table `{}` has a different element type from the exported one",
+                name
+            ),
+            LinkIssue::NotImported { item } => {
+                write!(f, "{} is defined rather than imported", item)
+            }
+        }
+    }
+}
+
+/// Check that `side` can be linked against `main`, by importing the memories
+/// and tables that `main` exports.
+///
+/// Every memory and table `side` imports must be exported by `main` with the
+/// same name, whatever the import's module name, and with limits, shared
+/// flags and element types that line up. `side` mustn't define any memories
+/// or tables of its own.
+pub fn check_link_compat(main: &Module, side: &Module) -> Vec<LinkIssue> {
+    let mut issues = Vec::new();
+
+    for memory in side.memories.iter() {
+        if memory.import.is_none() {
+            issues.push(LinkIssue::NotImported {
+                item: LinkItem::Memory,
+            });
+        }
+    }
+    for table in side.tables.iter() {
+        if table.import.is_none() {
+            issues.push(LinkIssue::NotImported {
+                item: LinkItem::Table,
+            });
+        }
+    }
+
+    for import in side.imports.iter() {
+        let (item, name) = match import.kind {
+            ImportKind::Memory(_) => (LinkItem::Memory, import.name.to_string()),
+            ImportKind::Table(_) => (LinkItem::Table, import.name.to_string()),
+            _ => continue,
+        };
+        let export = match main.exports.iter().find(|e| *e.name == *name) {
+            Some(export) => export,
+            None => {
+                issues.push(LinkIssue::MissingExport { item, name });
+                continue;
+            }
+        };
+
+        match (import.kind.clone(), export.item) {
+            (ImportKind::Memory(imported), ExportItem::Memory(exported)) => {
+                let imported = side.memories.get(imported);
+                let exported = main.memories.get(exported);
+                let limits = |m: &crate::Memory| ResizableLimits {
+                    initial: m.initial,
+                    maximum: m.maximum,
+                };
+                if !limits(exported).satisfies(&limits(imported)) {
+                    issues.push(LinkIssue::Limits {
+                        item,
+                        name: name.clone(),
+                        exported: limits(exported),
+                        imported: limits(imported),
+                    });
+                }
+                if imported.shared != exported.shared {
+                    issues.push(LinkIssue::Shared {
+                        name,
+                        exported: exported.shared,
+                    });
+                }
+            }
+            (ImportKind::Table(imported), ExportItem::Table(exported)) => {
+                let imported = side.tables.get(imported);
+                let exported = main.tables.get(exported);
+                let limits = |t: &crate::Table| ResizableLimits {
+                    initial: t.initial,
+                    maximum: t.maximum,
+                };
+                if !limits(exported).satisfies(&limits(imported)) {
+                    issues.push(LinkIssue::Limits {
+                        item,
+                        name: name.clone(),
+                        exported: limits(exported),
+                        imported: limits(imported),
+                    });
+                }
+                match (&imported.kind, &exported.kind) {
+                    (TableKind::Function(_), TableKind::Function(_))
+                    | (TableKind::Externref(_), TableKind::Externref(_)) => {}
+                    _ => issues.push(LinkIssue::TableKind { name }),
+                }
+            }
+            _ => issues.push(LinkIssue::WrongKind { item, name }),
+        }
+    }
+
+    issues
+}
+
+impl Module {
+    /// Export this module's memory and function table with the given names,
+    /// as the main module of a dynamically linked program does.
+    ///
+    /// If the module doesn't have a memory or a function table, an empty one
+    /// is added. Exporting a memory or table which is already exported with
+    /// the same name does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module has more than one memory or function
+    /// table, or if something else is already exported with either name.
+    pub fn export_memory_and_table(
+        &mut self,
+        mem_name: &str,
+        table_name: &str,
+    ) -> Result<(MemoryId, TableId)> {
+        let memory = match self.only_memory()? {
+            Some(id) => id,
+            None => self.memories.add_local(false, 0, None),
+        };
+        let table = match self.tables.main_function_table()? {
+            Some(id) => id,
+            None => self
+                .tables
+                .add_local(0, None, TableKind::Function(FunctionTable::default())),
+        };
+        self.export_once(mem_name, ExportItem::Memory(memory))?;
+        self.export_once(table_name, ExportItem::Table(table))?;
+        Ok((memory, table))
+    }
+
+    /// Import this module's memory and function table from `module`, with the
+    /// given names and limits, as a side module of a dynamically linked
+    /// program does.
+    ///
+    /// If the module already has a memory or function table, it's converted
+    /// to an import, keeping its id, so nothing referring to it needs to
+    /// change. Its active data and element segments are kept, and are
+    /// written into the imported memory and table when the module is
+    /// instantiated. Otherwise a new imported memory or table is added.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module has more than one memory or function
+    /// table, or if either maximum is less than its initial size.
+    pub fn import_memory_and_table(
+        &mut self,
+        module: &str,
+        mem_name: &str,
+        table_name: &str,
+        mem_limits: ResizableLimits,
+        table_limits: ResizableLimits,
+    ) -> Result<(MemoryId, TableId)> {
+        for (item, limits) in [
+            (LinkItem::Memory, mem_limits),
+            (LinkItem::Table, table_limits),
+        ]
+        .iter()
+        {
+            if limits.maximum.is_some_and(|max| max < limits.initial) {
+                bail!(
+                    "{} limits {} have a maximum less than the initial size",
+                    item,
+                    limits
+                );
+            }
+        }
+        let memory = self.only_memory()?;
+        let table = self.tables.main_function_table()?;
+
+        let memory = match memory {
+            Some(id) => {
+                if let Some(import) = self.memories.get(id).import {
+                    self.imports.delete(import);
+                }
+                let import = self.imports.add(module, mem_name, id);
+                let memory = self.memories.get_mut(id);
+                memory.initial = mem_limits.initial;
+                memory.maximum = mem_limits.maximum;
+                memory.import = Some(import);
+                id
+            }
+            None => self.add_import_memory(
+                module,
+                mem_name,
+                false,
+                mem_limits.initial,
+                mem_limits.maximum,
+            ),
+        };
+        let table = match table {
+            Some(id) => {
+                if let Some(import) = self.tables.get(id).import {
+                    self.imports.delete(import);
+                }
+                let import = self.imports.add(module, table_name, id);
+                let table = self.tables.get_mut(id);
+                table.initial = table_limits.initial;
+                table.maximum = table_limits.maximum;
+                table.import = Some(import);
+                id
+            }
+            None => self.add_import_table(
+                module,
+                table_name,
+                table_limits.initial,
+                table_limits.maximum,
+                TableKind::Function(FunctionTable::default()),
+            ),
+        };
+        Ok((memory, table))
+    }
+
+    fn only_memory(&self) -> Result<Option<MemoryId>> {
+        let mut memories = self.memories.ids();
+        let id = memories.next();
+        if memories.next().is_some() {
+            bail!("module contains more than one memory");
+        }
+        Ok(id)
+    }
+
+    fn export_once(&mut self, name: &str, item: ExportItem) -> Result<()> {
+        let existing = self
+            .exports
+            .iter()
+            .find(|e| *e.name == *name)
+            .map(|e| e.item);
+        match existing {
+            Some(existing) if existing == item => Ok(()),
+            Some(_) => bail!("something else is already exported as `{}`", name),
+            None => {
+                self.exports.add(name, item);
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
 //! All the locals used by functions in a wasm module.
 
 use crate::ir::{Local, LocalId};
+use crate::map::IdHashSet;
 use crate::ty::ValType;
 use id_arena::Arena;
+use rayon::prelude::*;
+use std::collections::HashMap;
 
 /// The set of locals in each function in this module.
 #[derive(Debug, Default)]
@@ -34,4 +37,112 @@ impl ModuleLocals {
     pub fn iter(&self) -> impl Iterator<Item = &Local> {
         self.arena.iter().map(|(_, f)| f)
     }
+
+    /// Get the number of locals in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no locals.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more locals, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        if self.arena.len() == 0 {
+            self.arena = Arena::with_capacity(additional);
+        }
+    }
+
+    /// Get a mutable reference to this module's locals.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Local> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
+
+    /// Get a shared reference to this module's locals, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (LocalId, &Local)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's locals.
+    pub fn ids(&self) -> impl Iterator<Item = LocalId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's locals, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Local> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's locals, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Local> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+}
+
+/// An allocator of temporary locals for passes that rewrite functions.
+///
+/// Rather than adding a fresh local with `ModuleLocals::add` every time a
+/// temporary is needed, passes can `get` a scratch local and `release` it once
+/// it is no longer live. Released locals are handed out again by later calls
+/// to `get` for the same type, which keeps the number of locals that a pass
+/// adds bounded by the number of temporaries live at once.
+///
+/// Locals are shared by all functions in a module, so the same
+/// `ScratchLocals` can be used while rewriting many functions. It doesn't
+/// borrow the module, so it can be used alongside a `&mut LocalFunction`.
+#[derive(Debug, Default)]
+pub struct ScratchLocals {
+    free: HashMap<ValType, Vec<LocalId>>,
+    live: IdHashSet<Local>,
+    added: usize,
+}
+
+impl ScratchLocals {
+    /// Construct a new allocator which hasn't handed out any locals yet.
+    pub fn new() -> ScratchLocals {
+        ScratchLocals::default()
+    }
+
+    /// Get a scratch local of the given type.
+    ///
+    /// A previously released local of this type is reused if there is one,
+    /// otherwise a new local is added to `locals`.
+    pub fn get(&mut self, locals: &mut ModuleLocals, ty: ValType) -> LocalId {
+        let id = match self.free.get_mut(&ty).and_then(|free| free.pop()) {
+            Some(id) => id,
+            None => {
+                self.added += 1;
+                locals.add(ty)
+            }
+        };
+        self.live.insert(id);
+        id
+    }
+
+    /// Release a scratch local previously returned by `get`, allowing it to
+    /// be handed out again.
+    ///
+    /// Only release a local once nothing reads its current value anymore.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` wasn't handed out by this allocator, or has already
+    /// been released.
+    pub fn release(&mut self, locals: &ModuleLocals, id: LocalId) {
+        assert!(
+            self.live.remove(&id),
+            "local {:?} is not a live scratch local",
+            id
+        );
+        let ty = locals.get(id).ty();
+        self.free.entry(ty).or_default().push(id);
+    }
+
+    /// The number of locals this allocator has added to the module.
+    pub fn added(&self) -> usize {
+        self.added
+    }
 }
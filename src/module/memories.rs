@@ -1,10 +1,13 @@
 //! Memories used in a wasm module.
 
+use crate::decode::{limits, Reader};
 use crate::emit::{Emit, EmitContext, Section};
 use crate::ir::Value;
-use crate::parse::IndicesToIds;
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{GlobalId, ImportId, InitExpr, Module, Result};
+use crate::{GlobalId, ImportId, InitExpr, InitOp, Module, Result};
+use failure::bail;
+use rayon::prelude::*;
 
 /// The id of a memory.
 pub type MemoryId = Id<Memory>;
@@ -19,6 +22,9 @@ pub struct Memory {
     pub initial: u32,
     /// The maximum page size for this memory
     pub maximum: Option<u32>,
+    /// The log2 of the size of this memory's pages in bytes, as declared with
+    /// the custom-page-sizes proposal, or `None` for the default of 64KiB
+    pub page_size_log2: Option<u32>,
     /// Whether or not this memory is imported, and if so from where
     pub import: Option<ImportId>,
     /// Data that will be used to initialize this memory chunk, with known
@@ -40,6 +46,7 @@ impl Tombstone for Memory {
 pub struct MemoryData {
     absolute: Vec<(u32, Vec<u8>)>,
     relative: Vec<(GlobalId, Vec<u8>)>,
+    extended: Vec<(Vec<InitOp>, Vec<u8>)>,
 }
 
 impl Memory {
@@ -48,30 +55,47 @@ impl Memory {
         self.id
     }
 
+    /// The size of this memory's pages in bytes.
+    pub fn page_size(&self) -> u64 {
+        1 << self.page_size_log2.unwrap_or(16)
+    }
+
     pub(crate) fn emit_data(&self) -> impl Iterator<Item = (InitExpr, &[u8])> {
-        let absolute = self
-            .data
-            .absolute
-            .iter()
-            .map(move |(pos, data)| (InitExpr::Value(Value::I32(*pos as i32)), &data[..]));
-        let relative = self
-            .data
-            .relative
-            .iter()
-            .map(move |(id, data)| (InitExpr::Global(*id), &data[..]));
-        absolute.chain(relative)
+        self.data.iter()
+    }
+}
+
+impl From<&Memory> for wasmparser::MemoryType {
+    fn from(memory: &Memory) -> wasmparser::MemoryType {
+        wasmparser::MemoryType {
+            limits: wasmparser::ResizableLimits {
+                initial: memory.initial,
+                maximum: memory.maximum,
+            },
+            shared: memory.shared,
+        }
     }
 }
 
 impl Emit for Memory {
     fn emit(&self, cx: &mut EmitContext) {
+        let mut flags = 0x00;
+        if self.maximum.is_some() {
+            flags |= 0x01;
+        }
+        if self.shared {
+            flags |= 0x02;
+        }
+        if self.page_size_log2.is_some() {
+            flags |= 0x08;
+        }
+        cx.encoder.byte(flags);
+        cx.encoder.u32(self.initial);
         if let Some(max) = self.maximum {
-            cx.encoder.byte(if self.shared { 0x03 } else { 0x01 });
-            cx.encoder.u32(self.initial);
             cx.encoder.u32(max);
-        } else {
-            cx.encoder.byte(0x00);
-            cx.encoder.u32(self.initial);
+        }
+        if let Some(page_size_log2) = self.page_size_log2 {
+            cx.encoder.u32(page_size_log2);
         }
     }
 }
@@ -97,6 +121,7 @@ impl ModuleMemories {
             shared,
             initial,
             maximum,
+            page_size_log2: None,
             import: Some(import),
             data: MemoryData::default(),
         });
@@ -104,6 +129,14 @@ impl ModuleMemories {
         id
     }
 
+    /// Construct a new shared memory, as used by threads, that does not
+    /// originate from any of the input wasm memories.
+    ///
+    /// Shared memories always have a maximum size.
+    pub fn add_shared(&mut self, initial: u32, maximum: u32) -> MemoryId {
+        self.add_local(true, initial, Some(maximum))
+    }
+
     /// Construct a new memory, that does not originate from any of the input
     /// wasm memories.
     pub fn add_local(&mut self, shared: bool, initial: u32, maximum: Option<u32>) -> MemoryId {
@@ -113,6 +146,7 @@ impl ModuleMemories {
             shared,
             initial,
             maximum,
+            page_size_log2: None,
             import: None,
             data: MemoryData::default(),
         });
@@ -143,27 +177,68 @@ impl ModuleMemories {
         self.arena.iter().map(|(_, f)| f)
     }
 
+    /// Get the number of memories in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no memories.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more memories, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
     /// Get a mutable reference to this module's memories.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Memory> {
         self.arena.iter_mut().map(|(_, f)| f)
     }
+
+    /// Get a shared reference to this module's memories, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (MemoryId, &Memory)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's memories.
+    pub fn ids(&self) -> impl Iterator<Item = MemoryId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's memories, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Memory> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's memories, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Memory> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
 }
 
 impl Module {
     /// Construct a new, empty set of memories for a module.
-    pub(crate) fn parse_memories(
-        &mut self,
-        section: wasmparser::MemorySectionReader,
-        ids: &mut IndicesToIds,
-    ) -> Result<()> {
+    pub(crate) fn parse_memories(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
         log::debug!("parse memory section");
-        for m in section {
-            let m = m?;
+        // This is decoded here rather than by `wasmparser`, which rejects the
+        // limits flag that carries a custom page size.
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.memories.reserve(reserve_hint(count));
+        for _ in 0..count {
+            let limits = limits(&mut r)?;
             let id = self
                 .memories
-                .add_local(m.shared, m.limits.initial, m.limits.maximum);
+                .add_local(limits.shared, limits.initial, limits.maximum);
+            self.memories.get_mut(id).page_size_log2 = limits.page_size_log2;
             ids.push_memory(id);
         }
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
         Ok(())
     }
 }
@@ -197,17 +272,75 @@ impl MemoryData {
         self.relative.push((id, data));
     }
 
+    /// Adds a new chunk of data in this `ModuleData` at an address computed
+    /// by an extended constant expression
+    pub fn add_extended(&mut self, offset: Vec<InitOp>, data: Vec<u8>) {
+        self.extended.push((offset, data));
+    }
+
+    /// Removes the segment at `index`, in the order they're emitted, returning
+    /// its offset and contents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> (InitExpr, Vec<u8>) {
+        if index < self.absolute.len() {
+            let (pos, data) = self.absolute.remove(index);
+            return (InitExpr::Value(Value::I32(pos as i32)), data);
+        }
+        let index = index - self.absolute.len();
+        if index < self.relative.len() {
+            let (id, data) = self.relative.remove(index);
+            return (InitExpr::Global(id), data);
+        }
+        let (ops, data) = self.extended.remove(index - self.relative.len());
+        (InitExpr::Extended(ops), data)
+    }
+
     /// Returns an iterator of all globals used as relative bases
     pub fn globals<'a>(&'a self) -> impl Iterator<Item = GlobalId> + 'a {
-        self.relative.iter().map(|p| p.0)
+        let extended = self
+            .extended
+            .iter()
+            .flat_map(|(ops, _)| ops.iter())
+            .filter_map(|op| match op {
+                InitOp::Global(id) => Some(*id),
+                _ => None,
+            });
+        self.relative.iter().map(|p| p.0).chain(extended)
+    }
+
+    /// Returns an iterator of each segment's offset and contents, in the order
+    /// they're emitted
+    pub fn iter(&self) -> impl Iterator<Item = (InitExpr, &[u8])> {
+        let absolute = self
+            .absolute
+            .iter()
+            .map(move |(pos, data)| (InitExpr::Value(Value::I32(*pos as i32)), &data[..]));
+        let relative = self
+            .relative
+            .iter()
+            .map(move |(id, data)| (InitExpr::Global(*id), &data[..]));
+        let extended = self
+            .extended
+            .iter()
+            .map(move |(ops, data)| (InitExpr::Extended(ops.clone()), &data[..]));
+        absolute.chain(relative).chain(extended)
+    }
+
+    /// Returns the number of segments in this data
+    pub fn len(&self) -> usize {
+        self.absolute.len() + self.relative.len() + self.extended.len()
     }
 
     /// Returns whether this data has no initialization sections
     pub fn is_empty(&self) -> bool {
-        self.absolute.is_empty() && self.relative.is_empty()
+        self.absolute.is_empty() && self.relative.is_empty() && self.extended.is_empty()
     }
 
     /// Consumes this data and returns a by-value iterator of each segment
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> impl Iterator<Item = (InitExpr, Vec<u8>)> {
         let absolute = self
             .absolute
@@ -217,6 +350,10 @@ impl MemoryData {
             .relative
             .into_iter()
             .map(move |(id, data)| (InitExpr::Global(id), data));
-        absolute.chain(relative)
+        let extended = self
+            .extended
+            .into_iter()
+            .map(move |(ops, data)| (InitExpr::Extended(ops), data));
+        absolute.chain(relative).chain(extended)
     }
 }
@@ -1,49 +1,119 @@
 //! A high-level API for manipulating wasm modules.
 
+mod build_id;
 mod config;
 mod custom;
 mod data;
 mod elements;
 mod exports;
+mod features;
 mod functions;
+mod gc;
 mod globals;
 mod imports;
+mod indices;
+mod info;
+mod layout;
+mod limits;
+mod linking;
 mod locals;
 mod memories;
 mod producers;
+mod progress;
+mod rename;
+mod shared;
+mod static_data;
 mod tables;
+mod tags;
 mod types;
 
+use crate::decode::Reader;
 use crate::emit::{Emit, EmitContext, IdsToIndices, Section};
 use crate::encode::Encoder;
 use crate::error::Result;
+pub use crate::module::build_id::BuildIdStyle;
 pub use crate::module::custom::{
-    CustomSection, CustomSectionId, ModuleCustomSections, RawCustomSection, TypedCustomSectionId,
-    UntypedCustomSectionId,
+    CustomSection, CustomSectionId, ModuleCustomSections, Placement, RawCustomSection, Reemission,
+    TypedCustomSectionId, UntypedCustomSectionId,
 };
-pub use crate::module::data::{Data, DataId, ModuleData};
-pub use crate::module::elements::{Element, ElementId, ModuleElements};
+pub use crate::module::data::{Data, DataId, InitTarget, ModuleData};
+pub use crate::module::elements::{Element, ElementId, ElementKind, ModuleElements};
+pub use crate::module::elements::{ElementSegment, SegmentSource};
 pub use crate::module::exports::{Export, ExportId, ExportItem, ModuleExports};
-pub use crate::module::functions::{Function, FunctionId, ModuleFunctions};
-pub use crate::module::functions::{FunctionKind, LocalFunction};
-pub use crate::module::globals::{Global, GlobalId, GlobalKind, ModuleGlobals};
+pub use crate::module::features::WasmFeatures;
+pub use crate::module::functions::{diff_functions, FunctionDiff, SubtreeChange};
+pub use crate::module::functions::{DisplayOptions, FunctionKind, FunctionMetrics, LocalFunction};
+pub use crate::module::functions::{Function, FunctionId, FunctionUse, Metadata, ModuleFunctions};
+pub use crate::module::globals::{ConstValue, Global, GlobalId, GlobalKind, ModuleGlobals};
 pub use crate::module::imports::{Import, ImportId, ImportKind, ModuleImports};
-pub use crate::module::locals::ModuleLocals;
+pub use crate::module::indices::EmittedIndices;
+pub use crate::module::info::ModuleInfo;
+pub use crate::module::layout::{ModuleLayout, SectionLayout};
+pub use crate::module::limits::{EngineLimits, Limit, LimitViolation};
+pub use crate::module::linking::{check_link_compat, LinkIssue, LinkItem, ResizableLimits};
+pub use crate::module::locals::{ModuleLocals, ScratchLocals};
 pub use crate::module::memories::{Memory, MemoryData, MemoryId, ModuleMemories};
 pub use crate::module::producers::ModuleProducers;
-pub use crate::module::tables::FunctionTable;
+pub use crate::module::progress::{EmitStats, ParseStats, Phase, PhaseStats};
+use crate::module::progress::{Progress, Timer};
+pub use crate::module::rename::RenamePolicy;
+pub use crate::module::shared::{SharedParseContext, SharedStr};
+use crate::module::static_data::StaticData;
+pub use crate::module::tables::{ExternrefTable, FunctionTable};
 pub use crate::module::tables::{ModuleTables, Table, TableId, TableKind};
+pub use crate::module::tags::{ModuleTags, Tag, TagId, TagKind};
 pub use crate::module::types::ModuleTypes;
 use crate::parse::IndicesToIds;
 use failure::{bail, ResultExt};
+use std::collections::HashSet;
 use std::fs;
-use std::mem;
+use std::ops::Range;
 use std::path::Path;
 
 pub use self::config::ModuleConfig;
 pub(crate) use self::functions::{DisplayExpr, DotExpr};
 
 /// A wasm module.
+///
+/// # Iterating over items
+///
+/// Every collection of items in a module supports the same ways of iterating
+/// over its items:
+///
+/// | Method          | Yields               | Available on                |
+/// |-----------------|----------------------|-----------------------------|
+/// | `iter`          | `&Item`              | all                         |
+/// | `iter_with_ids` | `(Id, &Item)`        | all                         |
+/// | `ids`           | `Id`                 | all                         |
+/// | `iter_mut`      | `&mut Item`          | all but `types`             |
+/// | `par_iter`      | `&Item`, in parallel | all                         |
+/// | `par_iter_mut`  | `&mut Item`, ditto   | all but `types`             |
+///
+/// Types are de-duplicated, so they can only be modified one at a time with
+/// `ModuleTypes::get_mut`.
+///
+/// ```
+/// use rayon::prelude::*;
+/// use walrus::ir::Value;
+/// use walrus::{GlobalId, InitExpr, Module, ValType};
+///
+/// let mut module = Module::default();
+/// let init = InitExpr::Value(Value::I32(0));
+/// let global = module.globals.add_local(ValType::I32, false, init);
+///
+/// let ids: Vec<GlobalId> = module.globals.ids().collect();
+/// assert_eq!(ids, [global]);
+/// for (id, g) in module.globals.iter_with_ids() {
+///     assert_eq!(id, g.id());
+/// }
+/// module.globals.iter_mut().for_each(|g| g.mutable = true);
+/// assert!(module.globals.par_iter().all(|g| g.mutable));
+///
+/// assert_eq!(module.funcs.ids().count(), 0);
+/// assert_eq!(module.types.iter_with_ids().count(), 0);
+/// assert_eq!(module.exports.par_iter().count(), 0);
+/// assert_eq!(module.imports.ids().count(), 0);
+/// ```
 #[derive(Debug, Default)]
 #[allow(missing_docs)]
 pub struct Module {
@@ -55,6 +125,8 @@ pub struct Module {
     pub locals: ModuleLocals,
     pub exports: ModuleExports,
     pub memories: ModuleMemories,
+    /// Exception tags, from the exception handling proposal
+    pub tags: ModuleTags,
     /// Registration of passive data segments, if any
     pub data: ModuleData,
     /// Registration of passive element segments, if any
@@ -68,7 +140,22 @@ pub struct Module {
     /// The name of this module, used for debugging purposes in the `name`
     /// custom section.
     pub name: Option<String>,
+    /// Subsections of the `name` custom section with ids that walrus doesn't
+    /// understand, stored as `(id, payload)` pairs. These are re-emitted
+    /// as-is after the subsections that walrus does understand, as long as
+    /// everything in the module still has the index it was parsed with, since
+    /// the indices inside them can't be updated. Otherwise they're dropped.
+    pub unknown_name_subsections: Vec<(u8, Vec<u8>)>,
     pub(crate) config: ModuleConfig,
+    /// The index spaces of the original wasm module, kept around when
+    /// requested by the configuration.
+    pub(crate) input_indices: Option<IndicesToIds>,
+    /// How long parsing this module took, if it was parsed.
+    pub(crate) parse_stats: Option<ParseStats>,
+    /// The function indices frozen by `assign_indices`, if any.
+    pub(crate) frozen_indices: Option<EmittedIndices>,
+    /// The space handed out by `add_static_data`.
+    pub(crate) static_data: StaticData,
 }
 
 impl Module {
@@ -104,33 +191,86 @@ impl Module {
     }
 
     fn parse(wasm: &[u8], config: &ModuleConfig) -> Result<Module> {
-        let mut parser = wasmparser::ModuleReader::new(wasm)?;
+        let mut tag_sections = Vec::new();
+        let disguised = disguise_tag_sections(wasm, &mut tag_sections)?;
+        let mut parser = wasmparser::ModuleReader::new(disguised.as_ref().map_or(wasm, |w| w))?;
         if parser.get_version() != 1 {
             bail!("only support version 1 of wasm");
         }
 
-        let mut ret = Module::default();
-        ret.config = config.clone();
+        let mut ret = Module {
+            config: config.clone(),
+            ..Module::default()
+        };
         let mut indices = IndicesToIds::default();
         let mut function_section_size = None;
         let mut data_count = None;
+        let mut last_section = None;
+        let mut pending_customs = Vec::new();
+
+        let mut stats = ParseStats::default();
+        let timer = Timer::start(Phase::ParseSections);
+        let progress = Progress::new(config, Phase::ParseSections, wasm.len());
+        let mut sections = 0;
+        let mut functions = None;
 
         while !parser.eof() {
             let section = parser.read()?;
+            sections += 1;
+            let reader = section.get_binary_reader();
+            let end = reader.original_position() + reader.bytes_remaining();
+            progress.report(end);
+            let tag_section = tag_sections.iter().find(|range| range.end == end);
+
+            // Custom sections that appear before some known section keep
+            // their position relative to the known sections; trailing custom
+            // sections are left at the end.
+            let known = match tag_section {
+                Some(_) => Some(Section::Tag),
+                None => known_section(&section.code),
+            };
+            if let Some(known) = known {
+                let placement = last_section.map_or(Placement::Start, Placement::AfterSection);
+                for id in pending_customs.drain(..) {
+                    ret.customs.set_placement(id, placement);
+                }
+                last_section = Some(known);
+            }
+
+            if let Some(range) = tag_section {
+                ret.parse_tags(&wasm[range.clone()], &mut indices)
+                    .context("failed to parse tag section")?;
+                continue;
+            }
+
             match section.code {
                 wasmparser::SectionCode::Data => {
                     let reader = section.get_data_section_reader()?;
-                    ret.parse_data(reader, &mut indices, data_count)
+                    ret.parse_data(reader, &indices, data_count)
                         .context("failed to parse data section")?;
                 }
                 wasmparser::SectionCode::Type => {
-                    let reader = section.get_type_section_reader()?;
-                    ret.parse_types(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    // The GC proposal's types can't be represented, so those
+                    // are decoded and kept as they are.
+                    if ret.config.allow_gc_types {
+                        let parsed = ret
+                            .parse_gc_types(bytes, &mut indices)
+                            .context("failed to parse type section")?;
+                        if parsed {
+                            continue;
+                        }
+                    }
+                    ret.parse_types(bytes, &mut indices)
                         .context("failed to parse type section")?;
                 }
                 wasmparser::SectionCode::Import => {
-                    let reader = section.get_import_section_reader()?;
-                    ret.parse_imports(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    ret.parse_imports(bytes, &mut indices)
                         .context("failed to parse import section")?;
                 }
                 wasmparser::SectionCode::Table => {
@@ -139,23 +279,31 @@ impl Module {
                         .context("failed to parse table section")?;
                 }
                 wasmparser::SectionCode::Memory => {
-                    let reader = section.get_memory_section_reader()?;
-                    ret.parse_memories(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    ret.parse_memories(bytes, &mut indices)
                         .context("failed to parse memory section")?;
                 }
                 wasmparser::SectionCode::Global => {
-                    let reader = section.get_global_section_reader()?;
-                    ret.parse_globals(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    ret.parse_globals(bytes, &mut indices)
                         .context("failed to parse global section")?;
                 }
                 wasmparser::SectionCode::Export => {
-                    let reader = section.get_export_section_reader()?;
-                    ret.parse_exports(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    ret.parse_exports(bytes, &indices)
                         .context("failed to parse export section")?;
                 }
                 wasmparser::SectionCode::Element => {
-                    let reader = section.get_element_section_reader()?;
-                    ret.parse_elements(reader, &mut indices)
+                    let mut reader = section.get_binary_reader();
+                    let len = reader.bytes_remaining();
+                    let bytes = reader.read_bytes(len)?;
+                    ret.parse_elements(bytes, &mut indices)
                         .context("failed to parse element section")?;
                 }
                 wasmparser::SectionCode::Start => {
@@ -169,13 +317,14 @@ impl Module {
                         .context("failed to parse function section")?;
                 }
                 wasmparser::SectionCode::Code => {
-                    let function_section_size = match function_section_size.take() {
-                        Some(i) => i,
-                        None => bail!("cannot have a code section without function section"),
-                    };
+                    // A missing function section declares no functions, so
+                    // only an empty code section can go without one.
+                    let function_section_size = function_section_size.take().unwrap_or(0);
                     let reader = section.get_code_section_reader()?;
+                    let timer = Timer::start(Phase::ParseFunctions);
                     ret.parse_local_functions(reader, function_section_size, &mut indices)
                         .context("failed to parse code section")?;
+                    functions = Some(timer.stop(function_section_size as usize));
                 }
                 wasmparser::SectionCode::DataCount => {
                     let count = section.get_data_count_section_content()?;
@@ -184,49 +333,114 @@ impl Module {
                 }
                 wasmparser::SectionCode::Custom { name, kind: _ } => {
                     let result = match name {
-                        "producers" => {
-                            let reader = section.get_producers_section_reader()?;
-                            ret.parse_producers_section(reader)
+                        // A section which can't even be read is dropped
+                        // like any other which fails to parse.
+                        "producers" => match section.get_producers_section_reader() {
+                            Ok(reader) => ret.parse_producers_section(reader),
+                            Err(e) => Err(e.into()),
+                        },
+                        "name" => {
+                            let reader = section.get_binary_reader();
+                            ret.parse_name_section(reader, &indices)
                         }
-                        "name" => section
-                            .get_name_section_reader()
-                            .map_err(failure::Error::from)
-                            .and_then(|r| ret.parse_name_section(r, &indices)),
                         _ => {
                             log::debug!("parsing custom section `{}`", name);
                             let mut reader = section.get_binary_reader();
                             let len = reader.bytes_remaining();
                             let payload = reader.read_bytes(len)?;
-                            ret.customs.add(RawCustomSection {
+                            let id = ret.customs.add(RawCustomSection {
                                 name: name.to_string(),
                                 data: payload.to_vec(),
                             });
+                            pending_customs.push(id);
+                            let raw = if ret.config.retain_raw_custom_sections {
+                                Some(payload.to_vec())
+                            } else {
+                                None
+                            };
+                            ret.customs.add_parsed(name, Reemission::Preserved, raw);
                             continue;
                         }
                     };
-                    if let Err(e) = result {
-                        log::warn!("failed to parse `{}` custom section {}", name, e);
-                    }
+                    let reemission = match result {
+                        Ok(()) => Reemission::Regenerated,
+                        Err(e) => {
+                            log::warn!("failed to parse `{}` custom section {}", name, e);
+                            Reemission::Dropped
+                        }
+                    };
+                    let raw = if ret.config.retain_raw_custom_sections {
+                        let mut reader = section.get_binary_reader();
+                        let len = reader.bytes_remaining();
+                        Some(reader.read_bytes(len)?.to_vec())
+                    } else {
+                        None
+                    };
+                    ret.customs.add_parsed(name, reemission, raw);
                 }
             }
         }
 
-        if function_section_size.is_some() {
+        if function_section_size.is_some_and(|n| n > 0) {
             bail!("cannot define a function section without a code section");
         }
 
+        // The time spent parsing function bodies is counted separately.
+        let mut section_stats = timer.stop(sections);
+        if let Some(functions) = functions {
+            section_stats.time = section_stats
+                .time
+                .checked_sub(functions.time)
+                .unwrap_or_default();
+            stats.phases.push(section_stats);
+            stats.phases.push(functions);
+        } else {
+            stats.phases.push(section_stats);
+        }
+        progress.report(wasm.len());
+
+        // A second function section, for example, declares functions which the
+        // code section never gets to.
+        for func in ret.funcs.iter() {
+            if let FunctionKind::Uninitialized(_) = func.kind {
+                bail!(
+                    "function {} was declared but never defined",
+                    func.id().index()
+                );
+            }
+        }
+
         ret.producers
             .add_processed_by("walrus", env!("CARGO_PKG_VERSION"));
 
+        // Parsing itself mutates function bodies and names, so everything
+        // starts out as unmodified once we're done here.
+        ret.funcs.mark_all_clean();
+
         // TODO: probably run this in a different location
         if !ret.config.skip_strict_validate {
+            let progress = Progress::new(config, Phase::Validate, 1);
+            progress.report(0);
+            let timer = Timer::start(Phase::Validate);
             crate::passes::validate::run(&ret)?;
+            stats
+                .phases
+                .push(timer.stop(ret.funcs.iter_local().count()));
+            progress.report(1);
         }
+        ret.parse_stats = Some(stats);
 
         if let Some(ref on_parse) = config.on_parse {
             on_parse(&mut ret, &indices)?;
         }
 
+        if ret.config.preserve_original_bodies
+            || ret.config.retain_index_mapping
+            || !ret.unknown_name_subsections.is_empty()
+        {
+            ret.input_indices = Some(indices);
+        }
+
         log::debug!("parse complete");
         Ok(ret)
     }
@@ -243,58 +457,203 @@ impl Module {
 
     /// Emit this module into an in-memory wasm buffer.
     pub fn emit_wasm(&self) -> Result<Vec<u8>> {
-        log::debug!("start emit");
+        Ok(self.emit_with_indices()?.0)
+    }
 
-        let indices = &mut IdsToIndices::default();
-        let mut wasm = Vec::new();
-        wasm.extend(&[0x00, 0x61, 0x73, 0x6d]); // magic
-        wasm.extend(&[0x01, 0x00, 0x00, 0x00]); // version
+    /// Emit this module into an in-memory wasm buffer, along with how long
+    /// each phase of emitting it took.
+    ///
+    /// See `ModuleConfig::on_progress` for reporting progress while emitting
+    /// large modules.
+    pub fn emit_wasm_with_stats(&self) -> Result<(Vec<u8>, EmitStats)> {
+        let (wasm, _, stats) = self.emit_with_indices()?;
+        Ok((wasm, stats))
+    }
 
-        let mut cx = EmitContext {
-            module: self,
-            indices,
-            encoder: Encoder::new(&mut wasm),
-            locals: Default::default(),
-        };
-        self.types.emit(&mut cx);
-        self.imports.emit(&mut cx);
-        self.funcs.emit_func_section(&mut cx);
-        self.tables.emit(&mut cx);
-        self.memories.emit(&mut cx);
-        self.globals.emit(&mut cx);
-        self.exports.emit(&mut cx);
-        if let Some(start) = self.start {
-            let idx = cx.indices.get_func_index(start);
-            cx.start_section(Section::Start).encoder.u32(idx);
-        }
-        self.elements.emit(&mut cx);
-        self.data.emit_data_count(&mut cx);
-        self.funcs.emit(&mut cx);
-        self.data.emit(&mut cx);
+    /// Get how long each phase of parsing this module took, if it was parsed
+    /// from a wasm binary.
+    pub fn parse_stats(&self) -> Option<&ParseStats> {
+        self.parse_stats.as_ref()
+    }
+
+    /// Emit this module into an in-memory wasm buffer, along with where every
+    /// section, function body, data segment, import and export ended up in
+    /// it.
+    ///
+    /// This is useful for building external index files which refer to parts
+    /// of the emitted module by byte offset.
+    pub fn emit_wasm_with_layout(&self) -> Result<(Vec<u8>, ModuleLayout)> {
+        let (wasm, indices, _) = self.emit_with_indices()?;
+        let layout = ModuleLayout::scan(self, &indices, &wasm)?;
+        Ok((wasm, layout))
+    }
 
-        if !self.config.skip_name_section {
-            emit_name_section(&mut cx);
+    fn emit_with_indices(&self) -> Result<(Vec<u8>, IdsToIndices, EmitStats)> {
+        log::debug!("start emit");
+        self.check_initialized_functions()?;
+        self.check_frozen_indices()?;
+        self.check_raw()?;
+        self.check_block_types()?;
+        let timer = Timer::start(Phase::EmitSections);
+
+        // Every function's index is assigned up front, in the order given by
+        // `function_index_order`, and the function and code sections declare
+        // the local functions in that same order.
+        let mut indices = IdsToIndices::default();
+        let mut local_funcs = Vec::new();
+        for (_, func) in self.function_index_order() {
+            indices.push_func(func.id());
+            if let FunctionKind::Local(_) = func.kind {
+                local_funcs.push(func.id());
+            }
         }
-        if !self.config.skip_producers_section {
-            self.producers.emit(&mut cx);
+        let mut wasm = Vec::new();
+        wasm.extend(&[0x00, 0x61, 0x73, 0x6d]); // magic
+        wasm.extend(&[0x01, 0x00, 0x00, 0x00]); // version
+        let header_len = wasm.len();
+
+        // Record where each known section ends, so that custom sections can
+        // be placed after them once everything has an index.
+        let mut ends = Vec::new();
+        let mut sections = 0;
+        let functions;
+        {
+            let mut cx = EmitContext {
+                module: self,
+                indices: &mut indices,
+                encoder: Encoder::new(&mut wasm),
+                locals: Default::default(),
+            };
+            let progress = Progress::new(&self.config, Phase::EmitSections, KNOWN_SECTIONS);
+            let mut end = |section: Section, pos: usize| {
+                if ends.last().map_or(header_len, |(_, prev)| *prev) != pos {
+                    sections += 1;
+                }
+                ends.push((section, pos));
+                progress.report(ends.len());
+            };
+            self.types.emit(&mut cx);
+            end(Section::Type, cx.encoder.pos());
+            self.imports.emit(&mut cx);
+            end(Section::Import, cx.encoder.pos());
+            self.funcs.emit_func_section(&mut cx, &local_funcs);
+            end(Section::Function, cx.encoder.pos());
+            self.tables.emit(&mut cx);
+            end(Section::Table, cx.encoder.pos());
+            self.memories.emit(&mut cx);
+            end(Section::Memory, cx.encoder.pos());
+            self.tags.emit(&mut cx);
+            end(Section::Tag, cx.encoder.pos());
+            self.globals.emit(&mut cx);
+            end(Section::Global, cx.encoder.pos());
+            self.exports.emit(&mut cx);
+            end(Section::Export, cx.encoder.pos());
+            if let Some(start) = self.start {
+                let idx = cx.indices.get_func_index(start);
+                cx.start_section(Section::Start).encoder.u32(idx);
+            }
+            end(Section::Start, cx.encoder.pos());
+            self.elements.emit(&mut cx);
+            end(Section::Element, cx.encoder.pos());
+            self.data.emit_data_count(&mut cx);
+            end(Section::DataCount, cx.encoder.pos());
+            let timer = Timer::start(Phase::EmitFunctions);
+            self.funcs.emit_code_section(&mut cx, &local_funcs);
+            functions = timer.stop(self.funcs.iter_local().count());
+            end(Section::Code, cx.encoder.pos());
+            self.data.emit(&mut cx);
+            end(Section::Data, cx.encoder.pos());
+
+            let pos = cx.encoder.pos();
+            if !self.config.skip_name_section {
+                emit_name_section(&mut cx);
+            }
+            if cx.encoder.pos() != pos {
+                sections += 1;
+            }
+            let pos = cx.encoder.pos();
+            if !self.config.skip_producers_section {
+                self.producers.emit(&mut cx);
+            }
+            if cx.encoder.pos() != pos {
+                sections += 1;
+            }
         }
 
-        let indices = mem::replace(cx.indices, Default::default());
-
-        for (_id, section) in self.customs.iter() {
+        let mut customs = Vec::new();
+        for (id, section) in self.customs.iter() {
             if !self.config.generate_dwarf && section.name().starts_with(".debug") {
                 log::debug!("skipping DWARF custom section {}", section.name());
                 continue;
             }
 
             log::debug!("emitting custom section {}", section.name());
-            cx.custom_section(&section.name())
-                .encoder
-                .raw(&section.data(&indices));
+            let placement = self.customs.placement(id);
+            let pos = match placement {
+                Placement::Start => header_len,
+                Placement::AfterSection(Section::Custom) | Placement::End => wasm.len(),
+                Placement::AfterSection(known) => ends
+                    .iter()
+                    .find(|(s, _)| *s == known)
+                    .map(|(_, pos)| *pos)
+                    .unwrap(),
+            };
+
+            let mut payload = Vec::new();
+            let mut encoder = Encoder::new(&mut payload);
+            encoder.str(section.name());
+            encoder.raw(&section.data(&indices));
+            let mut bytes = vec![Section::Custom as u8];
+            Encoder::new(&mut bytes).bytes(&payload);
+            let last = placement.end_of_module();
+            customs.push((pos, last, bytes));
+        }
+
+        // Splice all the custom sections in. Sections placed at the end go
+        // after any placed after the last known section, and otherwise this is
+        // a stable sort so custom sections at the same position stay in the
+        // order they were added.
+        customs.sort_by_key(|(pos, last, _)| (*pos, *last));
+        let customs_len = customs.len();
+        let len = customs
+            .iter()
+            .map(|(_, _, bytes)| bytes.len())
+            .sum::<usize>();
+        let mut ret = Vec::with_capacity(wasm.len() + len);
+        let mut prev = 0;
+        for (pos, _, bytes) in customs {
+            ret.extend_from_slice(&wasm[prev..pos]);
+            ret.extend(bytes);
+            prev = pos;
         }
+        ret.extend_from_slice(&wasm[prev..]);
+
+        // The time spent encoding function bodies is counted separately.
+        let mut section_stats = timer.stop(sections + customs_len);
+        section_stats.time = section_stats
+            .time
+            .checked_sub(functions.time)
+            .unwrap_or_default();
+        let stats = EmitStats {
+            phases: vec![section_stats, functions],
+        };
 
         log::debug!("emission finished");
-        Ok(wasm)
+        Ok((ret, indices, stats))
+    }
+
+    /// Get the map from indices in the original wasm to walrus IDs, if this
+    /// module was parsed with `ModuleConfig::retain_index_mapping` enabled.
+    ///
+    /// The map describes the input module as it was parsed: it isn't updated
+    /// as items are added to or removed from this module, so an ID found
+    /// through it may refer to an item that has since been deleted.
+    pub fn input_indices(&self) -> Option<&IndicesToIds> {
+        if self.config.retain_index_mapping {
+            self.input_indices.as_ref()
+        } else {
+            None
+        }
     }
 
     /// Returns an iterator over all functions in this module
@@ -304,44 +663,96 @@ impl Module {
 
     fn parse_name_section(
         &mut self,
-        names: wasmparser::NameSectionReader,
+        mut reader: wasmparser::BinaryReader,
         indices: &IndicesToIds,
     ) -> Result<()> {
         log::debug!("parse name section");
-        for name in names {
-            match name? {
-                wasmparser::Name::Module(m) => {
-                    self.name = Some(m.get_name()?.to_string());
+
+        // Parse the subsections by hand rather than through
+        // `wasmparser::NameSectionReader` so that we can tolerate subsections
+        // that appear out of order, more than once, or with ids we don't know
+        // about. Each subsection is length-prefixed, so a malformed one can be
+        // skipped without losing the rest of the section.
+        let mut seen = HashSet::new();
+        while !reader.eof() {
+            let id = reader.read_bytes(1)?[0];
+            let len = reader.read_var_u32()? as usize;
+            let payload = reader.read_bytes(len)?;
+            if !seen.insert(id) {
+                log::warn!("ignoring duplicate name subsection with id {}", id);
+                continue;
+            }
+
+            let mut subsection = wasmparser::BinaryReader::new(payload);
+            let result = match id {
+                0 => self.parse_module_name(&mut subsection),
+                1 => self.parse_function_names(&mut subsection, indices),
+                2 => self.parse_local_names(&mut subsection, indices),
+                _ => {
+                    log::debug!("preserving unknown name subsection with id {}", id);
+                    self.unknown_name_subsections.push((id, payload.to_vec()));
+                    continue;
                 }
-                wasmparser::Name::Function(f) => {
-                    let mut map = f.get_map()?;
-                    for _ in 0..map.get_count() {
-                        let naming = map.read()?;
-                        let id = indices.get_func(naming.index)?;
-                        self.funcs.get_mut(id).name = Some(naming.name.to_string());
-                    }
+            };
+            if let Err(e) = result {
+                log::warn!("failed to parse name subsection with id {}: {}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_module_name(&mut self, reader: &mut wasmparser::BinaryReader) -> Result<()> {
+        self.name = Some(reader.read_string()?.to_string());
+        Ok(())
+    }
+
+    fn parse_function_names(
+        &mut self,
+        reader: &mut wasmparser::BinaryReader,
+        indices: &IndicesToIds,
+    ) -> Result<()> {
+        let count = reader.read_var_u32()?;
+        for _ in 0..count {
+            let index = reader.read_var_u32()?;
+            let name = reader.read_string()?;
+            match indices.get_func(index) {
+                Ok(id) => self.funcs.get_mut(id).name = Some(name.to_string()),
+                Err(e) => log::warn!("skipping name for function {}: {}", index, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_local_names(
+        &mut self,
+        reader: &mut wasmparser::BinaryReader,
+        indices: &IndicesToIds,
+    ) -> Result<()> {
+        let count = reader.read_var_u32()?;
+        for _ in 0..count {
+            let func_index = reader.read_var_u32()?;
+            let func_id = indices.get_func(func_index);
+            if let Err(e) = &func_id {
+                log::warn!("skipping local names for function {}: {}", func_index, e);
+            }
+            let locals = reader.read_var_u32()?;
+            for _ in 0..locals {
+                let index = reader.read_var_u32()?;
+                let name = reader.read_string()?;
+                let func_id = match &func_id {
+                    Ok(id) => *id,
+                    Err(_) => continue,
+                };
+                // Looks like tools like `wat2wasm` generate empty
+                // names for locals if they aren't specified, so
+                // just ignore empty names which would in theory
+                // make debugging a bit harder.
+                if self.config.generate_synthetic_names_for_anonymous_items && name.is_empty() {
+                    continue;
                 }
-                wasmparser::Name::Local(l) => {
-                    let mut reader = l.get_function_local_reader()?;
-                    for _ in 0..reader.get_count() {
-                        let name = reader.read()?;
-                        let func_id = indices.get_func(name.func_index)?;
-                        let mut map = name.get_map()?;
-                        for _ in 0..map.get_count() {
-                            let naming = map.read()?;
-                            // Looks like tools like `wat2wasm` generate empty
-                            // names for locals if they aren't specified, so
-                            // just ignore empty names which would in theory
-                            // make debugging a bit harder.
-                            if self.config.generate_synthetic_names_for_anonymous_items
-                                && naming.name.is_empty()
-                            {
-                                continue;
-                            }
-                            let id = indices.get_local(func_id, naming.index)?;
-                            self.locals.get_mut(id).name = Some(naming.name.to_string());
-                        }
-                    }
+                match indices.get_local(func_id, index) {
+                    Ok(id) => self.locals.get_mut(id).name = Some(name.to_string()),
+                    Err(e) => log::warn!("skipping name for local {}: {}", index, e),
                 }
             }
         }
@@ -349,14 +760,72 @@ impl Module {
     }
 }
 
+/// Our version of `wasmparser` rejects the tag section of the exception
+/// handling proposal, so if there are any, return a copy of `wasm` in which
+/// they look like custom sections with an empty name, and push the ranges of
+/// their contents onto `ranges`, to be parsed from the original.
+fn disguise_tag_sections(wasm: &[u8], ranges: &mut Vec<Range<usize>>) -> Result<Option<Vec<u8>>> {
+    let mut disguised = None;
+    if wasm.len() < 8 {
+        // Left for `wasmparser` to complain about.
+        return Ok(disguised);
+    }
+    let mut r = Reader::new(&wasm[8..]);
+    while !r.eof() {
+        let id = r.position();
+        let code = r.byte()?;
+        let size = r.u32()? as usize;
+        let start = r.position();
+        r.skip(size)?;
+        if code != 13 {
+            continue;
+        }
+        if size == 0 {
+            bail!("failed to parse tag section: unexpected end of input");
+        }
+        let copy = disguised.get_or_insert_with(|| wasm.to_vec());
+        copy[8 + id] = 0;
+        // An empty name, in place of the first byte of the tag count.
+        copy[8 + start] = 0;
+        ranges.push(8 + start..8 + start + size);
+    }
+    Ok(disguised)
+}
+
+/// The number of known sections, including empty ones, reported as the total
+/// progress of emitting sections.
+const KNOWN_SECTIONS: usize = 13;
+
+/// The known section that the given section code corresponds to, or `None`
+/// for custom sections.
+fn known_section(code: &wasmparser::SectionCode) -> Option<Section> {
+    Some(match code {
+        wasmparser::SectionCode::Custom { .. } => return None,
+        wasmparser::SectionCode::Type => Section::Type,
+        wasmparser::SectionCode::Import => Section::Import,
+        wasmparser::SectionCode::Function => Section::Function,
+        wasmparser::SectionCode::Table => Section::Table,
+        wasmparser::SectionCode::Memory => Section::Memory,
+        wasmparser::SectionCode::Global => Section::Global,
+        wasmparser::SectionCode::Export => Section::Export,
+        wasmparser::SectionCode::Start => Section::Start,
+        wasmparser::SectionCode::Element => Section::Element,
+        wasmparser::SectionCode::Code => Section::Code,
+        wasmparser::SectionCode::Data => Section::Data,
+        wasmparser::SectionCode::DataCount => Section::DataCount,
+    })
+}
+
 fn emit_name_section(cx: &mut EmitContext) {
     log::debug!("emit name section");
     let mut funcs = cx
         .module
         .funcs
         .iter()
-        .filter_map(|func| func.name.as_ref().map(|name| (func, name)))
-        .map(|(func, name)| (cx.indices.get_func_index(func.id()), name))
+        .filter_map(|func| {
+            let name = func.name.as_ref()?;
+            Some((cx.indices.try_get_func_index(func.id())?, name))
+        })
         .collect::<Vec<_>>();
     funcs.sort_by_key(|p| p.0); // sort by index
 
@@ -374,7 +843,7 @@ fn emit_name_section(cx: &mut EmitContext) {
                     Some((*index, name))
                 })
                 .collect::<Vec<_>>();
-            if local_names.len() == 0 {
+            if local_names.is_empty() {
                 None
             } else {
                 Some((cx.indices.get_func_index(func.id()), local_names))
@@ -383,7 +852,21 @@ fn emit_name_section(cx: &mut EmitContext) {
         .collect::<Vec<_>>();
     locals.sort_by_key(|p| p.0); // sort by index
 
-    if cx.module.name.is_none() && funcs.len() == 0 && locals.len() == 0 {
+    // Unknown subsections may well contain indices, which would go stale if
+    // anything has moved since the module was parsed.
+    let unknown = match &cx.module.input_indices {
+        Some(original) if cx.indices.matches_original(original) => {
+            &cx.module.unknown_name_subsections[..]
+        }
+        _ => {
+            if !cx.module.unknown_name_subsections.is_empty() {
+                log::warn!("dropping unknown name subsections, since indices have changed");
+            }
+            &[]
+        }
+    };
+
+    if cx.module.name.is_none() && funcs.is_empty() && locals.is_empty() && unknown.is_empty() {
         return;
     }
 
@@ -392,7 +875,7 @@ fn emit_name_section(cx: &mut EmitContext) {
         cx.subsection(0).encoder.str(name);
     }
 
-    if funcs.len() > 0 {
+    if !funcs.is_empty() {
         let mut cx = cx.subsection(1);
         cx.encoder.usize(funcs.len());
         for (index, name) in funcs {
@@ -401,7 +884,7 @@ fn emit_name_section(cx: &mut EmitContext) {
         }
     }
 
-    if locals.len() > 0 {
+    if !locals.is_empty() {
         let mut cx = cx.subsection(2);
         cx.encoder.usize(locals.len());
         for (index, mut map) in locals {
@@ -414,4 +897,8 @@ fn emit_name_section(cx: &mut EmitContext) {
             }
         }
     }
+
+    for (id, payload) in unknown {
+        cx.subsection(*id).encoder.raw(payload);
+    }
 }
@@ -0,0 +1,148 @@
+//! Progress reporting and timing of parsing and emitting modules.
+
+use crate::module::ModuleConfig;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many function bodies are parsed or emitted between reports of
+/// progress.
+const FUNCTIONS_PER_REPORT: usize = 256;
+
+pub(crate) type ProgressFn = Arc<dyn Fn(Phase, usize, usize) + Send + Sync + 'static>;
+
+/// A phase of parsing or emitting a module, as reported to the callback
+/// registered with `ModuleConfig::on_progress`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Reading the sections of the input, other than function bodies.
+    /// Progress is in bytes of the input.
+    ParseSections,
+    /// Parsing function bodies. Progress is in functions.
+    ParseFunctions,
+    /// Validating the parsed module. Progress is reported once at the start
+    /// and once at the end.
+    Validate,
+    /// Emitting sections, other than function bodies. Progress is in
+    /// sections.
+    EmitSections,
+    /// Encoding function bodies. Progress is in functions.
+    EmitFunctions,
+}
+
+/// How long a phase of parsing or emitting took, and how many items it
+/// processed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PhaseStats {
+    /// The phase.
+    pub phase: Phase,
+    /// The wall time spent in this phase.
+    pub time: Duration,
+    /// How many items this phase processed: sections for `ParseSections` and
+    /// `EmitSections`, and functions otherwise.
+    pub items: usize,
+}
+
+/// Timings of parsing a module, available afterwards from
+/// `Module::parse_stats`.
+#[derive(Debug, Clone, Default)]
+pub struct ParseStats {
+    /// Every phase of parsing, in the order they started.
+    pub phases: Vec<PhaseStats>,
+}
+
+/// Timings of emitting a module, as returned by `Module::emit_wasm_with_stats`.
+#[derive(Debug, Clone, Default)]
+pub struct EmitStats {
+    /// Every phase of emitting, in the order they started.
+    pub phases: Vec<PhaseStats>,
+}
+
+impl ParseStats {
+    /// Get the stats of the given phase, if it happened.
+    pub fn phase(&self, phase: Phase) -> Option<&PhaseStats> {
+        self.phases.iter().find(|p| p.phase == phase)
+    }
+
+    /// The total time spent parsing.
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|p| p.time).sum()
+    }
+}
+
+impl EmitStats {
+    /// Get the stats of the given phase, if it happened.
+    pub fn phase(&self, phase: Phase) -> Option<&PhaseStats> {
+        self.phases.iter().find(|p| p.phase == phase)
+    }
+
+    /// The total time spent emitting.
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|p| p.time).sum()
+    }
+}
+
+/// Reports progress through a phase to the configured callback, if any.
+///
+/// Items processed in parallel are counted behind a lock, so the callback is
+/// never invoked concurrently and sees progress in increasing order.
+pub(crate) struct Progress<'a> {
+    callback: Option<&'a ProgressFn>,
+    phase: Phase,
+    total: usize,
+    done: Mutex<usize>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(config: &'a ModuleConfig, phase: Phase, total: usize) -> Progress<'a> {
+        Progress {
+            callback: config.on_progress.as_ref(),
+            phase,
+            total,
+            done: Mutex::new(0),
+        }
+    }
+
+    /// Report that `current` out of the total are done.
+    pub(crate) fn report(&self, current: usize) {
+        if let Some(f) = self.callback {
+            f(self.phase, current, self.total);
+        }
+    }
+
+    /// Count one more function as done, reporting every so often and once
+    /// they're all done.
+    pub(crate) fn function_done(&self) {
+        let f = match self.callback {
+            Some(f) => f,
+            None => return,
+        };
+        let mut done = self.done.lock().unwrap();
+        *done += 1;
+        if (*done).is_multiple_of(FUNCTIONS_PER_REPORT) || *done == self.total {
+            f(self.phase, *done, self.total);
+        }
+    }
+}
+
+/// Measures the wall time of a phase.
+pub(crate) struct Timer {
+    phase: Phase,
+    start: Instant,
+}
+
+impl Timer {
+    pub(crate) fn start(phase: Phase) -> Timer {
+        Timer {
+            phase,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn stop(self, items: usize) -> PhaseStats {
+        PhaseStats {
+            phase: self.phase,
+            time: self.start.elapsed(),
+            items,
+        }
+    }
+}
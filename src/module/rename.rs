@@ -0,0 +1,120 @@
+//! Renaming functions, globals and memories along with their exports.
+
+use crate::error::NameTaken;
+use crate::{ExportItem, FunctionId, GlobalId, MemoryId, Module, Result};
+use failure::bail;
+
+/// How `Module::rename_function` and friends treat exports and names which
+/// are already taken.
+#[derive(Debug, Clone, Default)]
+pub struct RenamePolicy {
+    /// Whether the exports of the item are renamed too.
+    pub rename_exports: bool,
+    /// Whether giving a function the same name as another function is
+    /// allowed.
+    ///
+    /// Two exports can never have the same name, so renaming an export to
+    /// the name of another export is always an error.
+    pub allow_collisions: bool,
+}
+
+impl Module {
+    /// Give the function `id` the name `new_name`, in the "name" section, and
+    /// also rename its exports if the policy says so.
+    ///
+    /// Nothing is renamed if an error is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NameTaken` error if another function already has the name
+    /// and the policy doesn't allow collisions, or if exports are renamed and
+    /// another export already has the name. Returns an error if exports are
+    /// renamed and the function is exported more than once, since its exports
+    /// can't all have the same name.
+    pub fn rename_function(
+        &mut self,
+        id: FunctionId,
+        new_name: &str,
+        policy: &RenamePolicy,
+    ) -> Result<()> {
+        if !policy.allow_collisions {
+            if let Some(other) = self.funcs.iter_by_name(new_name).find(|f| *f != id) {
+                return Err(NameTaken {
+                    name: new_name.to_string(),
+                    function: Some(other),
+                    export: None,
+                }
+                .into());
+            }
+        }
+        if policy.rename_exports {
+            self.rename_exports(ExportItem::Function(id), new_name)?;
+        }
+        self.funcs.get_mut(id).name = Some(new_name.to_string());
+        Ok(())
+    }
+
+    /// Rename the exports of the global `id` to `new_name`, if the policy
+    /// says so.
+    ///
+    /// Globals don't have names of their own, so this does nothing unless
+    /// `rename_exports` is set. Returns the same errors as `rename_function`
+    /// does for exports.
+    pub fn rename_global(
+        &mut self,
+        id: GlobalId,
+        new_name: &str,
+        policy: &RenamePolicy,
+    ) -> Result<()> {
+        if policy.rename_exports {
+            self.rename_exports(ExportItem::Global(id), new_name)?;
+        }
+        Ok(())
+    }
+
+    /// Rename the exports of the memory `id` to `new_name`, if the policy
+    /// says so.
+    ///
+    /// Memories don't have names of their own, so this does nothing unless
+    /// `rename_exports` is set. Returns the same errors as `rename_function`
+    /// does for exports.
+    pub fn rename_memory(
+        &mut self,
+        id: MemoryId,
+        new_name: &str,
+        policy: &RenamePolicy,
+    ) -> Result<()> {
+        if policy.rename_exports {
+            self.rename_exports(ExportItem::Memory(id), new_name)?;
+        }
+        Ok(())
+    }
+
+    fn rename_exports(&mut self, item: ExportItem, new_name: &str) -> Result<()> {
+        let mut exports = Vec::new();
+        for (export_id, export) in self.exports.iter_with_ids() {
+            if export.item == item {
+                exports.push(export_id);
+            } else if export.name == new_name {
+                return Err(NameTaken {
+                    name: new_name.to_string(),
+                    function: None,
+                    export: Some(export_id),
+                }
+                .into());
+            }
+        }
+        if exports.len() > 1 {
+            bail!(
+                "{:?} is exported {} times, so its exports can't all be named `{}`",
+                item,
+                exports.len(),
+                new_name
+            );
+        }
+        for export in exports {
+            self.exports.get_mut(export).name = new_name.into();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! Interning data shared between many parsed modules.
+
+use crate::module::Module;
+use crate::ty::ValType;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/// An immutable, reference-counted string, such as the name of an import or
+/// export.
+///
+/// Modules parsed with a `SharedParseContext` share the strings for names
+/// they have in common, rather than each having their own copy. A
+/// `SharedStr` derefs to `str`, and compares equal to strings with the same
+/// contents.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SharedStr(Arc<str>);
+
+impl SharedStr {
+    /// Get this string as a `&str`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Are `a` and `b` the same shared string, rather than just equal ones?
+    pub fn ptr_eq(a: &SharedStr, b: &SharedStr) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl Default for SharedStr {
+    fn default() -> SharedStr {
+        SharedStr("".into())
+    }
+}
+
+impl Deref for SharedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for SharedStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for SharedStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl From<&str> for SharedStr {
+    fn from(s: &str) -> SharedStr {
+        SharedStr(s.into())
+    }
+}
+
+impl From<String> for SharedStr {
+    fn from(s: String) -> SharedStr {
+        SharedStr(s.into())
+    }
+}
+
+impl From<SharedStr> for String {
+    fn from(s: SharedStr) -> String {
+        s.0.to_string()
+    }
+}
+
+impl PartialEq<str> for SharedStr {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for SharedStr {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for SharedStr {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialEq<SharedStr> for str {
+    fn eq(&self, other: &SharedStr) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<SharedStr> for &str {
+    fn eq(&self, other: &SharedStr) -> bool {
+        *self == &*other.0
+    }
+}
+
+impl PartialEq<SharedStr> for String {
+    fn eq(&self, other: &SharedStr) -> bool {
+        **self == *other.0
+    }
+}
+
+/// Data interned across every module parsed with the same context.
+///
+/// Tools parsing many related modules, such as ones built against the same
+/// SDK, can give each module's `ModuleConfig` a clone of one context with
+/// `ModuleConfig::shared_context`. The modules then share a single copy of
+/// each import and export name and, if `intern_types` is enabled, of each
+/// function type's parameters and results.
+///
+/// Only immutable data is shared, so each module can still be changed and
+/// emitted independently, and emits exactly the same bytes as it would have
+/// without the context. Clones of a context share its interned data.
+#[derive(Clone, Default)]
+pub struct SharedParseContext {
+    intern_types: bool,
+    tables: Arc<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    strings: Mutex<HashSet<Arc<str>>>,
+    signatures: Mutex<HashSet<Arc<[ValType]>>>,
+}
+
+impl SharedParseContext {
+    /// Create a new context, which only interns names.
+    pub fn new() -> SharedParseContext {
+        SharedParseContext::default()
+    }
+
+    /// Sets whether the parameters and results of function types are
+    /// interned too.
+    ///
+    /// By default this flag is `false`.
+    pub fn intern_types(&mut self, intern: bool) -> &mut SharedParseContext {
+        self.intern_types = intern;
+        self
+    }
+
+    /// The number of distinct strings interned so far.
+    pub fn strings(&self) -> usize {
+        self.tables.strings.lock().unwrap().len()
+    }
+
+    /// The total length in bytes of the distinct strings interned so far.
+    pub fn string_bytes(&self) -> usize {
+        let strings = self.tables.strings.lock().unwrap();
+        strings.iter().map(|s| s.len()).sum()
+    }
+
+    /// The number of distinct lists of parameters or results interned so far.
+    pub fn signatures(&self) -> usize {
+        self.tables.signatures.lock().unwrap().len()
+    }
+
+    pub(crate) fn str(&self, s: &str) -> SharedStr {
+        let mut strings = self.tables.strings.lock().unwrap();
+        if let Some(shared) = strings.get(s) {
+            return SharedStr(shared.clone());
+        }
+        let shared: Arc<str> = s.into();
+        strings.insert(shared.clone());
+        SharedStr(shared)
+    }
+
+    /// Intern a list of parameters or results, if this context interns types.
+    pub(crate) fn signature(&self, tys: Vec<ValType>) -> Arc<[ValType]> {
+        if !self.intern_types {
+            return tys.into();
+        }
+        let mut signatures = self.tables.signatures.lock().unwrap();
+        if let Some(shared) = signatures.get(&tys[..]) {
+            return shared.clone();
+        }
+        let shared: Arc<[ValType]> = tys.into();
+        signatures.insert(shared.clone());
+        shared
+    }
+}
+
+impl fmt::Debug for SharedParseContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedParseContext")
+            .field("intern_types", &self.intern_types)
+            .field("strings", &self.strings())
+            .field("signatures", &self.signatures())
+            .finish()
+    }
+}
+
+impl Module {
+    /// Intern `s` in this module's shared context, if it has one.
+    pub(crate) fn shared_str(&self, s: &str) -> SharedStr {
+        match &self.config.shared_context {
+            Some(cx) => cx.str(s),
+            None => s.into(),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Placing constant data, such as messages, in new pages of a memory.
+
+use crate::map::IdHashMap;
+use crate::{Memory, MemoryId, Module, Result};
+use failure::bail;
+use std::cmp;
+use std::collections::HashMap;
+
+/// The largest a 32-bit memory can be, in bytes.
+const MAX_SIZE: u64 = 1 << 32;
+
+/// The space handed out by `Module::add_static_data` so far.
+#[derive(Debug, Default)]
+pub(crate) struct StaticData {
+    /// The unused part of the pages added to each memory, as the address it
+    /// starts at and the address it ends at.
+    free: IdHashMap<Memory, (u64, u64)>,
+    /// The address of every string added with `add_static_str`.
+    strings: HashMap<(MemoryId, String), u32>,
+}
+
+impl Module {
+    /// Place `data` in `memory` with a new data segment, returning its
+    /// address.
+    ///
+    /// The data goes in pages added to the end of the memory for this
+    /// purpose, by raising its initial size, so it never overlaps the
+    /// memory's existing contents, its stack or its heap. Further data is
+    /// placed in the same pages while there's room left in them. Each
+    /// address is aligned to 8 bytes.
+    ///
+    /// Returns an error if `memory` is imported, since its actual size isn't
+    /// known, or if it can't grow enough.
+    pub fn add_static_data(&mut self, memory: MemoryId, data: Vec<u8>) -> Result<u32> {
+        let len = data.len() as u64;
+        let free = self.static_data.free.get(&memory).cloned();
+        let address = match free {
+            Some((start, end)) if align(start) + len <= end => align(start),
+            _ => {
+                let mem = self.memories.get_mut(memory);
+                if mem.import.is_some() {
+                    bail!(
+                        "can't place static data in imported memory {}",
+                        memory.index()
+                    );
+                }
+                let page_size = mem.page_size();
+                let start = u64::from(mem.initial) * page_size;
+                let pages = len.div_ceil(page_size).max(1);
+                let initial = u64::from(mem.initial) + pages;
+                let max_pages = cmp::min(MAX_SIZE / page_size, u64::from(u32::MAX));
+                if initial > max_pages || mem.maximum.is_some_and(|max| u64::from(max) < initial) {
+                    bail!(
+                        "memory {} can't grow by {} pages to fit {} bytes of static data",
+                        memory.index(),
+                        pages,
+                        len
+                    );
+                }
+                mem.initial = initial as u32;
+                self.static_data
+                    .free
+                    .insert(memory, (start, initial * page_size));
+                start
+            }
+        };
+
+        let end = self.static_data.free[&memory].1;
+        self.static_data.free.insert(memory, (address + len, end));
+        if !data.is_empty() {
+            self.memories
+                .get_mut(memory)
+                .data
+                .add_absolute(address as u32, data);
+        }
+        Ok(address as u32)
+    }
+
+    /// Place the bytes of `s` in `memory` like `add_static_data`, unless the
+    /// same string was already placed there, returning its address.
+    ///
+    /// Identical strings share one data segment, so this is handy for
+    /// messages which are used in many places, such as by
+    /// `FunctionBuilder::trap_if`.
+    pub fn add_static_str(&mut self, memory: MemoryId, s: &str) -> Result<u32> {
+        let key = (memory, s.to_string());
+        if let Some(address) = self.static_data.strings.get(&key) {
+            return Ok(*address);
+        }
+        let address = self.add_static_data(memory, s.as_bytes().to_vec())?;
+        self.static_data.strings.insert(key, address);
+        Ok(address)
+    }
+}
+
+fn align(address: u64) -> u64 {
+    (address + 7) & !7
+}
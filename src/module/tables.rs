@@ -1,9 +1,16 @@
 //! Tables within a wasm module.
 
 use crate::emit::{Emit, EmitContext, Section};
-use crate::parse::IndicesToIds;
+use crate::error::UnsupportedType;
+use crate::ir::Value;
+use crate::parse::{reserve_hint, IndicesToIds};
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, GlobalId, ImportId, Module, Result, ValType};
+use crate::{ConstValue, EngineLimits, FunctionId, GlobalId, ImportId, Module, Result, ValType};
+use failure::{bail, ResultExt};
+use rayon::prelude::*;
+use std::cmp;
+use std::convert::TryFrom;
+use std::ops::Range;
 
 /// The id of a table.
 pub type TableId = Id<Table>;
@@ -20,6 +27,8 @@ pub struct Table {
     pub kind: TableKind,
     /// Whether or not this table is imported, and if so what imports it.
     pub import: Option<ImportId>,
+    /// The ranges of slots reserved with `ModuleTables::reserve_slots`.
+    pub reserved: Vec<Range<u32>>,
 }
 
 impl Tombstone for Table {}
@@ -32,8 +41,9 @@ pub enum TableKind {
     /// Contains the initialization list for this table, if any.
     Function(FunctionTable),
 
-    /// A table of type `anyref` values
-    Anyref(AnyrefTable),
+    /// A table of `externref` values, which were called `anyref` before the
+    /// reference types proposal was finalized.
+    Externref(ExternrefTable),
 }
 
 impl TableKind {
@@ -44,11 +54,27 @@ impl TableKind {
             _ => panic!("not a Function"),
         }
     }
-    /// Unwrap `TableKind` to get inner `Anyref`. Panics if `TableKind` is anything other than `Anyref`
-    pub fn unwrap_anyref(&self) -> &AnyrefTable {
+    /// Unwrap `TableKind` to get inner `Externref`. Panics if `TableKind` is anything other than `Externref`
+    pub fn unwrap_externref(&self) -> &ExternrefTable {
         match *self {
-            TableKind::Anyref(ref anyref) => anyref,
-            _ => panic!("not an Anyref"),
+            TableKind::Externref(ref externref) => externref,
+            _ => panic!("not an Externref"),
+        }
+    }
+}
+
+impl TryFrom<wasmparser::Type> for TableKind {
+    type Error = UnsupportedType;
+
+    /// Create an empty table of the given element type.
+    fn try_from(ty: wasmparser::Type) -> std::result::Result<TableKind, UnsupportedType> {
+        match ty {
+            wasmparser::Type::AnyFunc => Ok(TableKind::Function(FunctionTable::default())),
+            wasmparser::Type::AnyRef => Ok(TableKind::Externref(ExternrefTable::default())),
+            _ => Err(UnsupportedType {
+                ty,
+                expected: "table element type",
+            }),
         }
     }
 }
@@ -61,13 +87,13 @@ pub struct FunctionTable {
     pub elements: Vec<Option<FunctionId>>,
 
     /// Elements of this table which are relative to a global, typically
-    /// imported.
-    pub relative_elements: Vec<(GlobalId, Vec<FunctionId>)>,
+    /// imported. A `None` is a `ref.null`.
+    pub relative_elements: Vec<(GlobalId, Vec<Option<FunctionId>>)>,
 }
 
-/// Components of a table of `anyref`
+/// Components of a table of `externref`
 #[derive(Debug, Default)]
-pub struct AnyrefTable {
+pub struct ExternrefTable {
     // currently intentionally empty
 }
 
@@ -78,13 +104,28 @@ impl Table {
     }
 }
 
+impl From<&Table> for wasmparser::TableType {
+    fn from(table: &Table) -> wasmparser::TableType {
+        wasmparser::TableType {
+            element_type: match table.kind {
+                TableKind::Function(_) => wasmparser::Type::AnyFunc,
+                TableKind::Externref(_) => wasmparser::Type::AnyRef,
+            },
+            limits: wasmparser::ResizableLimits {
+                initial: table.initial,
+                maximum: table.maximum,
+            },
+        }
+    }
+}
+
 impl Emit for Table {
     fn emit(&self, cx: &mut EmitContext) {
         match self.kind {
             TableKind::Function(_) => {
                 cx.encoder.byte(0x70); // the `anyfunc` type
             }
-            TableKind::Anyref(_) => ValType::Anyref.emit(&mut cx.encoder),
+            TableKind::Externref(_) => ValType::Externref.emit(&mut cx.encoder, cx.indices),
         }
         cx.encoder.byte(self.maximum.is_some() as u8);
         cx.encoder.u32(self.initial);
@@ -117,6 +158,7 @@ impl ModuleTables {
             maximum: max,
             kind,
             import: Some(import),
+            reserved: Vec::new(),
         })
     }
 
@@ -130,6 +172,7 @@ impl ModuleTables {
             maximum: max,
             kind,
             import: None,
+            reserved: Vec::new(),
         });
         debug_assert_eq!(id, id2);
         id
@@ -145,6 +188,55 @@ impl ModuleTables {
         &mut self.arena[table]
     }
 
+    /// Reserve `count` contiguous slots at the end of the given table,
+    /// returning their indices.
+    ///
+    /// The table's initial size is grown to make room for the new slots, and
+    /// the reservation is recorded in `Table::reserved`. Returns an error if
+    /// the table can't grow that large.
+    pub fn reserve_slots(&mut self, table: TableId, count: u32) -> Result<Range<u32>> {
+        let start = self.arena[table].initial;
+        let end = match start.checked_add(count) {
+            Some(end) => end,
+            None => bail!("cannot reserve {} more slots in a table", count),
+        };
+        let maximum = self.arena[table].maximum;
+        self.set_limits(table, end, maximum)
+            .with_context(|_| format!("cannot reserve {} more slots in the table", count))?;
+        self.arena[table].reserved.push(start..end);
+        Ok(start..end)
+    }
+
+    /// Set the initial and maximum sizes of a table.
+    ///
+    /// Returns an error if the maximum is less than the initial size, or if
+    /// the initial size is more than the `EngineLimits::max_table_size` which
+    /// engines accept. This can't see the module's element segments, so
+    /// `Module::set_table_limits` is usually what you want instead.
+    pub fn set_limits(&mut self, table: TableId, initial: u32, maximum: Option<u32>) -> Result<()> {
+        if let Some(max) = maximum {
+            if max < initial {
+                bail!(
+                    "a table's maximum size of {} is less than its initial size of {}",
+                    max,
+                    initial
+                );
+            }
+        }
+        let cap = EngineLimits::default().max_table_size;
+        if u64::from(initial) > cap {
+            bail!(
+                "a table's initial size of {} is more than engines accept, which is {}",
+                initial,
+                cap
+            );
+        }
+        let table = &mut self.arena[table];
+        table.initial = initial;
+        table.maximum = maximum;
+        Ok(())
+    }
+
     /// Removes a table from this module.
     ///
     /// It is up to you to ensure that any potential references to the deleted
@@ -158,6 +250,42 @@ impl ModuleTables {
         self.arena.iter().map(|p| p.1)
     }
 
+    /// Get the number of tables in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no tables.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more tables, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a shared reference to this module's tables, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (TableId, &Table)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's tables.
+    pub fn ids(&self) -> impl Iterator<Item = TableId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's tables, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Table> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's tables, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Table> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+
     /// Finds a unique function table in a module.
     ///
     /// Modules produced by compilers like LLVM typically have one function
@@ -178,7 +306,7 @@ impl ModuleTables {
             None => return Ok(None),
         };
         if tables.next().is_some() {
-            failure::bail!("module contains more than one function table");
+            bail!("module contains more than one function table");
         }
         Ok(Some(id))
     }
@@ -190,6 +318,75 @@ impl ModuleTables {
 }
 
 impl Module {
+    /// Set the initial and maximum sizes of a table, checking that the
+    /// module stays valid.
+    ///
+    /// On top of the checks of `ModuleTables::set_limits`, this returns an
+    /// error if the initial size is less than `required_table_min`, so that
+    /// some element segment or reserved slots wouldn't fit, or if the table
+    /// is imported and the new limits are stricter than the old ones. An
+    /// imported table's limits say which tables the host can provide, so
+    /// making them stricter could stop the module from instantiating with a
+    /// table it used to accept.
+    pub fn set_table_limits(
+        &mut self,
+        table: TableId,
+        initial: u32,
+        maximum: Option<u32>,
+    ) -> Result<()> {
+        let t = self.tables.get(table);
+        if t.import.is_some() {
+            let stricter_max = match (t.maximum, maximum) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(old), Some(new)) => new < old,
+            };
+            if initial > t.initial || stricter_max {
+                bail!(
+                    "the limits of an imported table can't be made stricter, from {} to {}",
+                    display_limits(t.initial, t.maximum),
+                    display_limits(initial, maximum)
+                );
+            }
+        }
+
+        let required = self.required_table_min(table);
+        if initial < required {
+            bail!(
+                "a table's initial size can't be {}, since its element segments \
+                 and reserved slots need {} slots",
+                initial,
+                required
+            );
+        }
+        self.tables.set_limits(table, initial, maximum)
+    }
+
+    /// Get the smallest initial size `table` can have while still holding all
+    /// of its active element segments and reserved slots.
+    ///
+    /// Segments at offsets which aren't constant, such as those relative to
+    /// an imported global, aren't counted, since where they end isn't known
+    /// until the module is instantiated.
+    pub fn required_table_min(&self, table: TableId) -> u32 {
+        let mut min = 0;
+        for segment in self.element_segments() {
+            if segment.table() != Some(table) {
+                continue;
+            }
+            let offset = match segment.offset().map(|o| self.globals.eval(o)) {
+                Some(Ok(ConstValue::Known(Value::I32(n)))) => n as u32,
+                _ => continue,
+            };
+            let end = u64::from(offset) + segment.members().len() as u64;
+            min = cmp::max(min, end);
+        }
+        for reserved in self.tables.get(table).reserved.iter() {
+            min = cmp::max(min, u64::from(reserved.end));
+        }
+        cmp::min(min, u64::from(u32::MAX)) as u32
+    }
+
     /// Construct a new, empty set of tables for a module.
     pub(crate) fn parse_tables(
         &mut self,
@@ -197,17 +394,13 @@ impl Module {
         ids: &mut IndicesToIds,
     ) -> Result<()> {
         log::debug!("parse table section");
+        self.tables.reserve(reserve_hint(section.get_count()));
         for t in section {
             let t = t?;
-            let id = self.tables.add_local(
-                t.limits.initial,
-                t.limits.maximum,
-                match t.element_type {
-                    wasmparser::Type::AnyFunc => TableKind::Function(FunctionTable::default()),
-                    wasmparser::Type::AnyRef => TableKind::Anyref(AnyrefTable::default()),
-                    _ => failure::bail!("invalid table type"),
-                },
-            );
+            let kind = TableKind::try_from(t.element_type)?;
+            let id = self
+                .tables
+                .add_local(t.limits.initial, t.limits.maximum, kind);
             ids.push_table(id);
         }
         Ok(())
@@ -231,3 +424,10 @@ impl Emit for ModuleTables {
         }
     }
 }
+
+fn display_limits(initial: u32, maximum: Option<u32>) -> String {
+    match maximum {
+        Some(max) => format!("{}..={}", initial, max),
+        None => format!("{}..", initial),
+    }
+}
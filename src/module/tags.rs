@@ -0,0 +1,202 @@
+//! Exception tags within a wasm module.
+
+use crate::decode::Reader;
+use crate::emit::{Emit, EmitContext, Section};
+use crate::parse::{reserve_hint, IndicesToIds};
+use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
+use crate::{ImportId, Module, Result, TypeId};
+use failure::bail;
+use rayon::prelude::*;
+
+/// The id of a tag.
+pub type TagId = Id<Tag>;
+
+/// An exception tag, as defined by the exception handling proposal.
+///
+/// Exceptions are thrown with a tag, and carry the values described by the
+/// parameters of the tag's type, which must not have any results.
+#[derive(Debug)]
+pub struct Tag {
+    // NB: Not public so that it can't get out of sync with the arena this is
+    // contained within.
+    id: TagId,
+
+    /// The type of the values thrown along with this tag.
+    pub ty: TypeId,
+
+    /// The kind of tag this is.
+    pub kind: TagKind,
+}
+
+impl Tombstone for Tag {}
+
+/// The different kinds of tags a wasm module can have.
+#[derive(Debug)]
+pub enum TagKind {
+    /// An imported tag.
+    Import(ImportId),
+    /// A tag defined in this module.
+    Local,
+}
+
+impl Tag {
+    /// Get this tag's id.
+    pub fn id(&self) -> TagId {
+        self.id
+    }
+}
+
+impl Emit for Tag {
+    fn emit(&self, cx: &mut EmitContext) {
+        // The attribute, of which only 0, an exception, is defined.
+        cx.encoder.byte(0x00);
+        let idx = cx.indices.get_type_index(self.ty);
+        cx.encoder.u32(idx);
+    }
+}
+
+/// The set of tags in this module.
+#[derive(Debug, Default)]
+pub struct ModuleTags {
+    /// The arena where the tags are stored.
+    arena: TombstoneArena<Tag>,
+}
+
+impl ModuleTags {
+    /// Adds a new imported tag to this list.
+    pub fn add_import(&mut self, ty: TypeId, import_id: ImportId) -> TagId {
+        self.arena.alloc_with_id(|id| Tag {
+            id,
+            ty,
+            kind: TagKind::Import(import_id),
+        })
+    }
+
+    /// Construct a new tag, that does not originate from any of the input
+    /// wasm tags.
+    pub fn add_local(&mut self, ty: TypeId) -> TagId {
+        self.arena.alloc_with_id(|id| Tag {
+            id,
+            ty,
+            kind: TagKind::Local,
+        })
+    }
+
+    /// Gets a reference to a tag given its id
+    pub fn get(&self, id: TagId) -> &Tag {
+        &self.arena[id]
+    }
+
+    /// Gets a reference to a tag given its id
+    pub fn get_mut(&mut self, id: TagId) -> &mut Tag {
+        &mut self.arena[id]
+    }
+
+    /// Removes a tag from this module.
+    ///
+    /// It is up to you to ensure that any potential references to the deleted
+    /// tag are also removed, eg `throw` expressions.
+    pub fn delete(&mut self, id: TagId) {
+        self.arena.delete(id);
+    }
+
+    /// Get a shared reference to this module's tags.
+    pub fn iter(&self) -> impl Iterator<Item = &Tag> {
+        self.arena.iter().map(|(_, f)| f)
+    }
+
+    /// Get the number of tags in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no tags.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more tags, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a mutable reference to this module's tags.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Tag> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
+
+    /// Get a shared reference to this module's tags, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (TagId, &Tag)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's tags.
+    pub fn ids(&self) -> impl Iterator<Item = TagId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's tags, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Tag> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
+    /// Get a mutable reference to this module's tags, in parallel.
+    pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Tag> {
+        self.arena.par_iter_mut().map(|(_, f)| f)
+    }
+}
+
+/// Read the type of a tag, after its attribute.
+pub(crate) fn tag_type(r: &mut Reader, ids: &IndicesToIds) -> Result<TypeId> {
+    match r.byte()? {
+        0x00 => {}
+        byte => bail!("invalid tag attribute: {:#x}", byte),
+    }
+    ids.get_type(r.u32()?)
+}
+
+impl Module {
+    /// Parse the tag section, which `wasmparser` doesn't know about.
+    pub(crate) fn parse_tags(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
+        log::debug!("parse tag section");
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.tags.reserve(reserve_hint(count));
+        for _ in 0..count {
+            let ty = tag_type(&mut r, ids)?;
+            if !self.types.get(ty).results().is_empty() {
+                bail!("tag types can't have results");
+            }
+            let id = self.tags.add_local(ty);
+            ids.push_tag(id);
+        }
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
+        Ok(())
+    }
+}
+
+impl Emit for ModuleTags {
+    fn emit(&self, cx: &mut EmitContext) {
+        log::debug!("emit tag section");
+        // All imported tags emitted earlier during the import section, so
+        // filter those out.
+        let local = |tag: &&Tag| match tag.kind {
+            TagKind::Import(_) => false,
+            TagKind::Local => true,
+        };
+        let tags = self.iter().filter(local).count();
+        if tags == 0 {
+            return;
+        }
+
+        let mut cx = cx.start_section(Section::Tag);
+        cx.encoder.usize(tags);
+        for tag in self.iter().filter(local) {
+            cx.indices.push_tag(tag.id());
+            tag.emit(&mut cx);
+        }
+    }
+}
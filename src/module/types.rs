@@ -1,16 +1,36 @@
 //! Types in a wasm module.
 
 use crate::arena_set::ArenaSet;
+use crate::decode::{val_type, Reader};
 use crate::emit::{Emit, EmitContext, Section};
 use crate::error::Result;
 use crate::module::Module;
-use crate::parse::IndicesToIds;
-use crate::ty::{Type, TypeId, ValType};
+use crate::parse::{reserve_hint, IndicesToIds};
+use crate::ty::{Signature, Type, TypeId, ValType};
+use failure::bail;
+use rayon::prelude::*;
 
 /// The set of de-duplicated types within a module.
+///
+/// Types are interned, so adding a type with the same parameters and results
+/// as an existing one gives back the existing type's id, and each signature
+/// appears only once in the emitted type section.
+///
+/// The type section lists types in the order they were first added. For a
+/// parsed module, that's the input's order, with duplicates dropped, followed
+/// by any types added afterwards, in the order they were added. Type indices
+/// therefore only change when types are deleted or added, and not between
+/// emits of the same module.
+///
+/// A type section using the GC proposal, parsed with
+/// `ModuleConfig::allow_gc_types`, is emitted exactly as it was, followed by
+/// any types added afterwards. Its types are never interned with others.
 #[derive(Debug, Default)]
 pub struct ModuleTypes {
     arena: ArenaSet<Type>,
+    /// The entries of a type section using the GC proposal, as they were
+    /// encoded, along with the types each of them defines.
+    raw: Vec<(Vec<u8>, Vec<TypeId>)>,
 }
 
 impl ModuleTypes {
@@ -32,7 +52,19 @@ impl ModuleTypes {
     /// preserve type names from the WAT.
     pub fn by_name(&self, name: &str) -> Option<TypeId> {
         self.arena.iter().find_map(|(id, ty)| {
-            if ty.name.as_ref().map(|s| s.as_str()) == Some(name) {
+            if ty.name.as_deref() == Some(name) {
+                Some(id)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Find the type with the given parameters and results, if it exists
+    /// in this module.
+    pub fn find(&self, params: &[ValType], results: &[ValType]) -> Option<TypeId> {
+        self.arena.iter().find_map(|(id, ty)| {
+            if ty.params() == params && ty.results() == results {
                 Some(id)
             } else {
                 None
@@ -45,6 +77,37 @@ impl ModuleTypes {
         self.arena.iter().map(|(_, f)| f)
     }
 
+    /// Get the number of types in this module.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns whether this module has no types.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reserve space for at least `additional` more types, as long as there
+    /// aren't any yet; see `ModuleFunctions::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
+    /// Get a shared reference to this module's types, along with their ids.
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (TypeId, &Type)> {
+        self.arena.iter()
+    }
+
+    /// Get the ids of this module's types.
+    pub fn ids(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.arena.iter().map(|(id, _)| id)
+    }
+
+    /// Get a shared reference to this module's types, in parallel.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = &Type> {
+        self.arena.par_iter().map(|(_, f)| f)
+    }
+
     /// Removes a type from this module.
     ///
     /// It is up to you to ensure that any potential references to the deleted
@@ -55,6 +118,9 @@ impl ModuleTypes {
     }
 
     /// Add a new type to this module, and return its `Id`
+    ///
+    /// If the module already has a type with these parameters and results,
+    /// its `Id` is returned instead.
     pub fn add(&mut self, params: &[ValType], results: &[ValType]) -> TypeId {
         let id = self.arena.next_id();
         self.arena.insert(Type::new(
@@ -63,35 +129,78 @@ impl ModuleTypes {
             results.to_vec().into_boxed_slice(),
         ))
     }
+
+    /// Add an entry of a type section using the GC proposal, a single type or
+    /// a recursion group, given as its encoding and the types it defines,
+    /// which start at `index`. See `Type::new_raw`.
+    pub(crate) fn add_raw(
+        &mut self,
+        bytes: Vec<u8>,
+        index: u32,
+        types: Vec<Option<Signature>>,
+    ) -> Vec<TypeId> {
+        let mut ids = Vec::with_capacity(types.len());
+        for (i, func) in types.into_iter().enumerate() {
+            let id = self.arena.next_id();
+            let ty = Type::new_raw(id, index + i as u32, func);
+            ids.push(self.arena.insert(ty));
+        }
+        self.raw.push((bytes, ids.clone()));
+        ids
+    }
+
+    /// Check that none of the types of a type section using the GC proposal
+    /// were deleted, since the section is emitted exactly as it was.
+    pub(crate) fn check_raw(&self) -> Result<()> {
+        let expected = self.raw.iter().map(|(_, ids)| ids.len()).sum::<usize>();
+        let found = self.iter().filter(|ty| ty.raw_index().is_some()).count();
+        if found != expected {
+            bail!(
+                "{} types were deleted from a type section using the GC proposal, \
+                 which can only be emitted unchanged",
+                expected - found
+            );
+        }
+        Ok(())
+    }
 }
 
 impl Module {
     /// Construct the set of types within a module.
-    pub(crate) fn parse_types(
-        &mut self,
-        section: wasmparser::TypeSectionReader,
-        ids: &mut IndicesToIds,
-    ) -> Result<()> {
+    pub(crate) fn parse_types(&mut self, section: &[u8], ids: &mut IndicesToIds) -> Result<()> {
         log::debug!("parsing type section");
-        for ty in section {
-            let fun_ty = ty?;
+        // This is decoded here rather than by `wasmparser`, which doesn't
+        // know about typed references.
+        let mut r = Reader::new(section);
+        let count = r.u32()?;
+        self.types.reserve(reserve_hint(count));
+        ids.types.reserve(reserve_hint(count));
+        for _ in 0..count {
+            match r.byte()? {
+                0x60 => {}
+                byte => bail!("invalid type form: {:#x}", byte),
+            }
+            let mut val_types = || -> Result<Vec<ValType>> {
+                let count = r.u32()?;
+                let mut tys = Vec::with_capacity(reserve_hint(count));
+                for _ in 0..count {
+                    tys.push(val_type(&mut r, ids)?);
+                }
+                Ok(tys)
+            };
+            let params = val_types()?;
+            let results = val_types()?;
             let id = self.types.arena.next_id();
-            let params = fun_ty
-                .params
-                .iter()
-                .map(ValType::parse)
-                .collect::<Result<Vec<_>>>()?
-                .into_boxed_slice();
-            let results = fun_ty
-                .returns
-                .iter()
-                .map(ValType::parse)
-                .collect::<Result<Vec<_>>>()?
-                .into_boxed_slice();
-            let id = self.types.arena.insert(Type::new(id, params, results));
+            let ty = match &self.config.shared_context {
+                Some(cx) => Type::new_shared(id, cx.signature(params), cx.signature(results)),
+                None => Type::new_shared(id, params.into(), results.into()),
+            };
+            let id = self.types.arena.insert(ty);
             ids.push_type(id);
         }
-
+        if !r.eof() {
+            bail!("unexpected data at the end of the section");
+        }
         Ok(())
     }
 }
@@ -99,16 +208,30 @@ impl Module {
 impl Emit for ModuleTypes {
     fn emit(&self, cx: &mut EmitContext) {
         log::debug!("emitting type section");
+        let nraw = self.raw.iter().map(|(_, ids)| ids.len()).sum::<usize>();
         let ntypes = self.iter().count();
         if ntypes == 0 {
             return;
         }
         let mut cx = cx.start_section(Section::Type);
-        cx.encoder.usize(ntypes);
+        cx.encoder.usize(self.raw.len() + ntypes - nraw);
 
-        for (id, ty) in self.arena.iter() {
+        // Typed references in a type can refer to any type, including later
+        // ones, so assign all the indices up front.
+        for (id, _) in self.arena.iter() {
             cx.indices.push_type(id);
-            ty.emit(&mut cx);
+        }
+
+        // The types of a type section using the GC proposal were parsed
+        // before any others were added, so they come first, at the indices
+        // they were encoded with.
+        for (bytes, _) in self.raw.iter() {
+            cx.encoder.raw(bytes);
+        }
+        for (_, ty) in self.arena.iter() {
+            if ty.raw_index().is_none() {
+                ty.emit(&mut cx);
+            }
         }
     }
 }
@@ -1,7 +1,19 @@
 use crate::map::IdHashMap;
 use crate::{DataId, ElementId, Function, FunctionId, GlobalId, Result};
-use crate::{LocalId, MemoryId, TableId, TypeId};
+use crate::{Local, LocalId, MemoryId, TableId, TagId, TypeId};
 use failure::bail;
+use id_arena::{ArenaBehavior, DefaultArenaBehavior};
+use std::cmp;
+
+/// How many entries to reserve space for up front, given the count a section
+/// says it has.
+///
+/// Counts come straight from the input, so a few bytes can claim billions of
+/// entries. Reserving space for all of them could exhaust memory before the
+/// entries turn out not to be there.
+pub(crate) fn reserve_hint(count: u32) -> usize {
+    cmp::min(count, 1 << 20) as usize
+}
 
 /// Maps from old indices in the original Wasm binary to `walrus` IDs.
 ///
@@ -16,16 +28,41 @@ use failure::bail;
 /// Any newly built or added things (functions, tables, types, etc) are not
 /// associated with an old index (since they were not present in the original
 /// Wasm binary).
+///
+/// An `IndicesToIds` is handed to `ModuleConfig::on_parse` callbacks, and can
+/// also be kept around on the parsed module with
+/// `ModuleConfig::retain_index_mapping`.
 #[derive(Debug, Default)]
 pub struct IndicesToIds {
-    tables: Vec<TableId>,
-    types: Vec<TypeId>,
-    funcs: Vec<FunctionId>,
-    globals: Vec<GlobalId>,
-    memories: Vec<MemoryId>,
-    elements: Vec<ElementId>,
-    data: Vec<DataId>,
-    locals: IdHashMap<Function, Vec<LocalId>>,
+    pub(crate) tables: Vec<TableId>,
+    pub(crate) types: Vec<TypeId>,
+    pub(crate) funcs: Vec<FunctionId>,
+    pub(crate) globals: Vec<GlobalId>,
+    pub(crate) memories: Vec<MemoryId>,
+    /// Active element segments are stored in their table's layout rather
+    /// than as an `Element`, so they have no id.
+    pub(crate) elements: Vec<Option<ElementId>>,
+    pub(crate) data: Vec<DataId>,
+    pub(crate) tags: Vec<TagId>,
+    pub(crate) locals: IdHashMap<Function, LocalRange>,
+}
+
+/// The locals of a parsed function, which are always allocated one after the
+/// other, so only the first one and how many there are need to be stored.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct LocalRange {
+    first: LocalId,
+    len: u32,
+}
+
+impl LocalRange {
+    /// The locals in this range, in index order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = LocalId> {
+        let arena = DefaultArenaBehavior::<Local>::arena_id(self.first);
+        let first = self.first.index();
+        (first..first + self.len as usize)
+            .map(move |index| DefaultArenaBehavior::<Local>::new_id(arena, index))
+    }
 }
 
 macro_rules! define_push_get {
@@ -60,26 +97,60 @@ define_push_get!(push_type, get_type, TypeId, types);
 define_push_get!(push_func, get_func, FunctionId, funcs);
 define_push_get!(push_global, get_global, GlobalId, globals);
 define_push_get!(push_memory, get_memory, MemoryId, memories);
-//define_push_get!(push_element, get_element, ElementId, elements);
 define_push_get!(push_data, get_data, DataId, data);
+define_push_get!(push_tag, get_tag, TagId, tags);
 
 impl IndicesToIds {
-    /// Pushes a new local ID to map it to the next index internally
-    pub(crate) fn push_local(&mut self, function: FunctionId, id: LocalId) -> u32 {
-        let list = self.locals.entry(function).or_insert(Vec::new());
-        list.push(id);
-        (list.len() as u32) - 1
+    /// Pushes `count` new local IDs, starting at `first` and allocated
+    /// contiguously, mapping them to the next indices of `function`'s locals.
+    ///
+    /// Returns the index of the first local pushed.
+    pub(crate) fn push_locals(&mut self, function: FunctionId, count: u32, first: LocalId) -> u32 {
+        let range = self
+            .locals
+            .entry(function)
+            .or_insert(LocalRange { first, len: 0 });
+        debug_assert_eq!(
+            range.first.index() + range.len as usize,
+            first.index(),
+            "locals of a function must be allocated contiguously"
+        );
+        let index = range.len;
+        range.len += count;
+        index
+    }
+
+    /// Pushes a new element segment, or `None` for an active segment which
+    /// has no id, returning its index.
+    pub(crate) fn push_element(&mut self, id: Option<ElementId>) -> u32 {
+        self.elements.push(id);
+        (self.elements.len() - 1) as u32
+    }
+
+    /// Gets the ID for a particular element segment index.
+    ///
+    /// If the index did not exist in the original Wasm binary, or refers to
+    /// an active segment, an `Err` is returned.
+    pub fn get_element(&self, index: u32) -> Result<ElementId> {
+        match self.elements.get(index as usize) {
+            Some(Some(x)) => Ok(*x),
+            Some(None) => bail!(
+                "element segment `{}` is active and can't be referenced",
+                index
+            ),
+            None => bail!("index `{}` is out of bounds for elements", index),
+        }
     }
 
     /// Gets the ID for a particular index
     pub fn get_local(&self, function: FunctionId, index: u32) -> Result<LocalId> {
-        let ret = self
-            .locals
-            .get(&function)
-            .and_then(|list| list.get(index as usize));
-        match ret {
-            Some(x) => Ok(*x),
-            None => bail!("index `{}` is out of bounds for local", index,),
+        match self.locals.get(&function) {
+            Some(range) if index < range.len => {
+                let arena = DefaultArenaBehavior::<Local>::arena_id(range.first);
+                let index = range.first.index() + index as usize;
+                Ok(DefaultArenaBehavior::<Local>::new_id(arena, index))
+            }
+            _ => bail!("index `{}` is out of bounds for local", index,),
         }
     }
 }
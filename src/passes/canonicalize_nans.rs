@@ -0,0 +1,372 @@
+//! Collapsing the payloads of NaN constants to the canonical NaN.
+
+use crate::ir::*;
+use crate::map::IdHashMap;
+use crate::{FunctionKind, GlobalKind, InitExpr, LocalFunction, Module};
+
+/// The canonical quiet NaN payload of an `f32`, without its sign.
+const F32_CANONICAL: u32 = 0x7fc0_0000;
+const F32_SIGN: u32 = 0x8000_0000;
+/// The canonical quiet NaN payload of an `f64`, without its sign.
+const F64_CANONICAL: u64 = 0x7ff8_0000_0000_0000;
+const F64_SIGN: u64 = 0x8000_0000_0000_0000;
+
+/// Which NaN constants `canonicalize_nans` rewrites.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NanMode {
+    /// Only rewrite constants whose bits can't be observed: those which only
+    /// flow, possibly through `abs`, `neg`, `copysign` or `select`, into
+    /// float arithmetic, comparisons, conversions or `drop`. Constants which
+    /// could reach a reinterpret, a local, a global, memory, a call or a
+    /// branch are left alone, as are global initializers.
+    Conservative,
+    /// Rewrite every scalar NaN constant in function bodies and global
+    /// initializers, even when its bits could be observed.
+    Aggressive,
+}
+
+/// Collapse the payload of NaN constants to the canonical quiet NaN,
+/// returning how many constants were rewritten.
+///
+/// The sign of a NaN is kept, and other values, including negative zero,
+/// are never changed. `v128` constants are only rewritten when they're used
+/// by `f32x4` or `f64x2` operations, which decide the shape of their lanes:
+/// each NaN lane is canonicalized and the other lanes are kept as they are.
+///
+/// Constants which already hold a canonical NaN aren't counted, so running
+/// this twice rewrites nothing the second time.
+pub fn canonicalize_nans(module: &mut Module, mode: NanMode) -> usize {
+    let rewrites = module.funcs.par_map_local(|_, func| rewrites(func, mode));
+
+    let mut rewritten = 0;
+    for (id, rewrites) in rewrites {
+        if rewrites.is_empty() {
+            continue;
+        }
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        rewritten += rewrites.len();
+        for (expr, value) in rewrites {
+            *func.get_mut(expr) = Expr::Const(Const { value });
+        }
+    }
+
+    if mode == NanMode::Aggressive {
+        for global in module.globals.iter_mut() {
+            if let GlobalKind::Local(InitExpr::Value(value)) = &mut global.kind {
+                if let Some(canonical) = canonical_scalar(*value) {
+                    *value = canonical;
+                    rewritten += 1;
+                }
+            }
+        }
+    }
+    rewritten
+}
+
+/// The constants of `func` to rewrite, along with their new values.
+fn rewrites(func: &LocalFunction, mode: NanMode) -> Vec<(ExprId, Value)> {
+    let mut parents = Parents {
+        func,
+        stack: Vec::new(),
+        parents: IdHashMap::default(),
+        consts: Vec::new(),
+    };
+    dfs_in_order(&mut parents, func, func.entry_block().into());
+
+    // Expressions can be shared, so the same constant may have been visited
+    // more than once.
+    parents.consts.sort();
+    parents.consts.dedup();
+
+    let mut rewrites = Vec::new();
+    for id in parents.consts.iter().cloned() {
+        let value = match func.get(id) {
+            Expr::Const(c) => c.value,
+            _ => unreachable!(),
+        };
+        let canonical = match value {
+            Value::F32(_) | Value::F64(_) => {
+                if mode == NanMode::Conservative && !unobservable(func, &parents.parents, id) {
+                    continue;
+                }
+                canonical_scalar(value)
+            }
+            Value::V128(bits) => match lane_bits(func, &parents.parents, id, mode) {
+                Some(32) => canonical_f32x4(bits),
+                Some(64) => canonical_f64x2(bits),
+                _ => None,
+            },
+            Value::I32(_) | Value::I64(_) => None,
+        };
+        if let Some(canonical) = canonical {
+            rewrites.push((id, canonical));
+        }
+    }
+    rewrites
+}
+
+/// Records the expressions using each expression, and finds the float and
+/// vector constants.
+struct Parents<'a> {
+    func: &'a LocalFunction,
+    stack: Vec<ExprId>,
+    parents: IdHashMap<Expr, Vec<ExprId>>,
+    consts: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Parents<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        // Expressions directly inside the entry block have no parent, which
+        // is treated the same as a use that could observe them.
+        if let Some(parent) = self.stack.last() {
+            self.parents.entry(id).or_default().push(*parent);
+        }
+        if let Expr::Const(Const {
+            value: Value::F32(_),
+        })
+        | Expr::Const(Const {
+            value: Value::F64(_),
+        })
+        | Expr::Const(Const {
+            value: Value::V128(_),
+        }) = self.func.get(id)
+        {
+            self.consts.push(id);
+        }
+        self.stack.push(id);
+        id.visit(self);
+        self.stack.pop();
+    }
+}
+
+/// Whether every use of the scalar float `id` ignores the bits of a NaN
+/// payload.
+fn unobservable(func: &LocalFunction, parents: &IdHashMap<Expr, Vec<ExprId>>, id: ExprId) -> bool {
+    let users = match parents.get(&id) {
+        Some(users) => users,
+        None => return false,
+    };
+    users.iter().all(|parent| match func.get(*parent) {
+        // These keep the payload of their operand, so it's the uses of their
+        // result that matter.
+        Expr::Unop(Unop {
+            op: UnaryOp::F32Abs,
+            ..
+        })
+        | Expr::Unop(Unop {
+            op: UnaryOp::F32Neg,
+            ..
+        })
+        | Expr::Unop(Unop {
+            op: UnaryOp::F64Abs,
+            ..
+        })
+        | Expr::Unop(Unop {
+            op: UnaryOp::F64Neg,
+            ..
+        })
+        | Expr::Select(_) => unobservable(func, parents, *parent),
+        // Only the sign of the right-hand operand is used.
+        Expr::Binop(Binop {
+            op: BinaryOp::F32Copysign,
+            lhs,
+            ..
+        })
+        | Expr::Binop(Binop {
+            op: BinaryOp::F64Copysign,
+            lhs,
+            ..
+        }) => *lhs != id || unobservable(func, parents, *parent),
+        Expr::Unop(Unop { op, .. }) => ignores_payload_unop(*op),
+        Expr::Binop(Binop { op, .. }) => ignores_payload_binop(*op),
+        Expr::Drop(_) => true,
+        _ => false,
+    })
+}
+
+fn ignores_payload_unop(op: UnaryOp) -> bool {
+    use self::UnaryOp::*;
+    matches!(
+        op,
+        F32Ceil
+            | F32Floor
+            | F32Trunc
+            | F32Nearest
+            | F32Sqrt
+            | F64Ceil
+            | F64Floor
+            | F64Trunc
+            | F64Nearest
+            | F64Sqrt
+            | I32TruncSF32
+            | I32TruncUF32
+            | I32TruncSF64
+            | I32TruncUF64
+            | I64TruncSF32
+            | I64TruncUF32
+            | I64TruncSF64
+            | I64TruncUF64
+            | I32TruncSSatF32
+            | I32TruncUSatF32
+            | I32TruncSSatF64
+            | I32TruncUSatF64
+            | I64TruncSSatF32
+            | I64TruncUSatF32
+            | I64TruncSSatF64
+            | I64TruncUSatF64
+            | F32DemoteF64
+            | F64PromoteF32
+    )
+}
+
+fn ignores_payload_binop(op: BinaryOp) -> bool {
+    use self::BinaryOp::*;
+    matches!(
+        op,
+        F32Add
+            | F32Sub
+            | F32Mul
+            | F32Div
+            | F32Min
+            | F32Max
+            | F64Add
+            | F64Sub
+            | F64Mul
+            | F64Div
+            | F64Min
+            | F64Max
+            | F32Eq
+            | F32Ne
+            | F32Lt
+            | F32Gt
+            | F32Le
+            | F32Ge
+            | F64Eq
+            | F64Ne
+            | F64Lt
+            | F64Gt
+            | F64Le
+            | F64Ge
+    )
+}
+
+/// The width of the lanes of the `v128` constant `id`, if every use of it
+/// treats it as `f32x4` or `f64x2` and none of them could observe a NaN
+/// payload, as far as `mode` cares.
+fn lane_bits(
+    func: &LocalFunction,
+    parents: &IdHashMap<Expr, Vec<ExprId>>,
+    id: ExprId,
+    mode: NanMode,
+) -> Option<u32> {
+    let users = parents.get(&id)?;
+    let mut bits = None;
+    for user in users {
+        let shape = match func.get(*user) {
+            Expr::Unop(Unop { op, .. }) => unop_lane_bits(*op, mode),
+            Expr::Binop(Binop { op, lhs, .. }) => match op {
+                // The right-hand operand of a `replace_lane` is a scalar.
+                BinaryOp::F32x4ReplaceLane { .. } if mode == NanMode::Aggressive && *lhs == id => {
+                    Some(32)
+                }
+                BinaryOp::F64x2ReplaceLane { .. } if mode == NanMode::Aggressive && *lhs == id => {
+                    Some(64)
+                }
+                _ => binop_lane_bits(*op),
+            },
+            _ => None,
+        };
+        match (bits, shape) {
+            (_, None) => return None,
+            (Some(a), Some(b)) if a != b => return None,
+            _ => bits = shape,
+        }
+    }
+    bits
+}
+
+fn unop_lane_bits(op: UnaryOp, mode: NanMode) -> Option<u32> {
+    use self::UnaryOp::*;
+    match op {
+        F32x4Sqrt | I32x4TruncSF32x4Sat | I32x4TruncUF32x4Sat => Some(32),
+        F64x2Sqrt => Some(64),
+        // These keep or expose the payloads of their operand.
+        F32x4Abs | F32x4Neg | F32x4ExtractLane { .. } if mode == NanMode::Aggressive => Some(32),
+        F64x2Abs | F64x2Neg | F64x2ExtractLane { .. } if mode == NanMode::Aggressive => Some(64),
+        _ => None,
+    }
+}
+
+fn binop_lane_bits(op: BinaryOp) -> Option<u32> {
+    use self::BinaryOp::*;
+    match op {
+        F32x4Add | F32x4Sub | F32x4Mul | F32x4Div | F32x4Min | F32x4Max | F32x4Eq | F32x4Ne
+        | F32x4Lt | F32x4Gt | F32x4Le | F32x4Ge => Some(32),
+        F64x2Add | F64x2Sub | F64x2Mul | F64x2Div | F64x2Min | F64x2Max | F64x2Eq | F64x2Ne
+        | F64x2Lt | F64x2Gt | F64x2Le | F64x2Ge => Some(64),
+        _ => None,
+    }
+}
+
+/// The canonical NaN with the sign of `value`, if `value` is a NaN which
+/// isn't canonical already.
+fn canonical_scalar(value: Value) -> Option<Value> {
+    match value {
+        Value::F32(f) => canonical_f32(f.to_bits()).map(|bits| Value::F32(f32::from_bits(bits))),
+        Value::F64(f) => canonical_f64(f.to_bits()).map(|bits| Value::F64(f64::from_bits(bits))),
+        _ => None,
+    }
+}
+
+fn canonical_f32(bits: u32) -> Option<u32> {
+    let canonical = (bits & F32_SIGN) | F32_CANONICAL;
+    if f32::from_bits(bits).is_nan() && bits != canonical {
+        Some(canonical)
+    } else {
+        None
+    }
+}
+
+fn canonical_f64(bits: u64) -> Option<u64> {
+    let canonical = (bits & F64_SIGN) | F64_CANONICAL;
+    if f64::from_bits(bits).is_nan() && bits != canonical {
+        Some(canonical)
+    } else {
+        None
+    }
+}
+
+fn canonical_f32x4(bits: u128) -> Option<Value> {
+    let mut result = 0;
+    for i in 0..4 {
+        let lane = (bits >> (32 * i)) as u32;
+        let lane = canonical_f32(lane).unwrap_or(lane);
+        result |= u128::from(lane) << (32 * i);
+    }
+    if result == bits {
+        None
+    } else {
+        Some(Value::V128(result))
+    }
+}
+
+fn canonical_f64x2(bits: u128) -> Option<Value> {
+    let mut result = 0;
+    for i in 0..2 {
+        let lane = (bits >> (64 * i)) as u64;
+        let lane = canonical_f64(lane).unwrap_or(lane);
+        result |= u128::from(lane) << (64 * i);
+    }
+    if result == bits {
+        None
+    } else {
+        Some(Value::V128(result))
+    }
+}
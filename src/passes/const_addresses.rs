@@ -0,0 +1,237 @@
+//! Finding and rewriting constant memory addresses in code.
+//!
+//! This is useful when relocating static data, for example after splitting or
+//! rebasing data segments, where every constant that points into the moved
+//! data needs to move along with it.
+
+use crate::ir::*;
+use crate::{FunctionId, FunctionKind, InitExpr, LocalFunction, Module, Result};
+use failure::bail;
+use std::ops::Range;
+
+/// How a constant that looks like a memory address is used.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConstAddrKind {
+    /// The address operand of a load or store.
+    ///
+    /// This is always an address, so rewriting it is safe.
+    MemoryOperand,
+
+    /// An argument to a call of an imported function, which points into
+    /// static data.
+    ///
+    /// The argument might not actually be a pointer, so this is a heuristic.
+    CallArgument {
+        /// The position of the argument.
+        index: usize,
+    },
+
+    /// A value stored into memory with `i32.store`, which points into static
+    /// data.
+    ///
+    /// The value might not actually be a pointer, so this is a heuristic.
+    StoredValue,
+}
+
+/// A use of a constant memory address, found by `const_addresses`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstAddrUse {
+    /// The function containing the constant.
+    pub func: FunctionId,
+    /// The `i32.const` expression.
+    pub expr: ExprId,
+    /// The expression using the constant, such as a load, store or call.
+    pub user: ExprId,
+    /// The address the constant refers to, including the offset of a load or
+    /// store.
+    pub address: u64,
+    /// How the constant is used.
+    pub kind: ConstAddrKind,
+}
+
+/// Which uses of constant addresses `rebase_const_addresses` rewrites, besides
+/// the address operands of loads and stores which are always rewritten.
+#[derive(Debug, Clone, Default)]
+pub struct RebaseOptions {
+    /// Whether to rewrite `ConstAddrKind::CallArgument` uses.
+    pub call_arguments: bool,
+    /// Whether to rewrite `ConstAddrKind::StoredValue` uses.
+    pub stored_values: bool,
+}
+
+/// Find every `i32.const` in the module's local functions which is used as a
+/// memory address.
+///
+/// The address operands of loads and stores are always reported. Constants
+/// passed to imported functions or stored into memory are only reported if
+/// they point into one of the module's active data segments, and even then
+/// they may just happen to be integers that look like pointers.
+pub fn const_addresses(module: &Module) -> Vec<ConstAddrUse> {
+    let data = static_data(module);
+    let mut uses = Vec::new();
+    for func in module.funcs.iter() {
+        let local = match &func.kind {
+            FunctionKind::Local(local) => local,
+            _ => continue,
+        };
+        let mut finder = Finder {
+            module,
+            data: &data,
+            func: func.id(),
+            local,
+            uses: &mut uses,
+        };
+        dfs_in_order(&mut finder, local, local.entry_block().into());
+    }
+    uses
+}
+
+/// Add `delta` to every constant address found by `const_addresses` which
+/// refers to an address in `old_range`, returning how many were rewritten.
+///
+/// Only the address operands of loads and stores are rewritten unless the
+/// riskier heuristic uses are enabled in `options`. Returns an error, without
+/// changing anything, if a rewritten constant would no longer fit in an
+/// `i32`.
+pub fn rebase_const_addresses(
+    module: &mut Module,
+    old_range: Range<u64>,
+    delta: i64,
+    options: &RebaseOptions,
+) -> Result<usize> {
+    let mut rewrites = Vec::new();
+    for addr in const_addresses(module) {
+        if addr.address < old_range.start || addr.address >= old_range.end {
+            continue;
+        }
+        match addr.kind {
+            ConstAddrKind::MemoryOperand => {}
+            ConstAddrKind::CallArgument { .. } if options.call_arguments => {}
+            ConstAddrKind::StoredValue if options.stored_values => {}
+            _ => continue,
+        }
+        let func = match &module.funcs.get(addr.func).kind {
+            FunctionKind::Local(local) => local,
+            _ => unreachable!(),
+        };
+        let value = match func.get(addr.expr) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => i64::from(*n as u32) + delta,
+            _ => unreachable!(),
+        };
+        if value < 0 || value > i64::from(u32::MAX) {
+            bail!(
+                "rebasing the constant address {} by {} doesn't fit in an i32",
+                addr.address,
+                delta
+            );
+        }
+        rewrites.push((addr.func, addr.expr, value as u32 as i32));
+    }
+
+    for (func, expr, value) in rewrites.iter() {
+        match &mut module.funcs.get_mut(*func).kind {
+            FunctionKind::Local(local) => {
+                *local.get_mut(*expr) = Expr::Const(Const {
+                    value: Value::I32(*value),
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(rewrites.len())
+}
+
+/// The ranges of memory initialized by active data segments at constant
+/// offsets.
+fn static_data(module: &Module) -> Vec<Range<u64>> {
+    let mut ranges = Vec::new();
+    for memory in module.memories.iter() {
+        for (offset, data) in memory.data.iter() {
+            if let InitExpr::Value(Value::I32(n)) = offset {
+                let start = u64::from(n as u32);
+                ranges.push(start..start + data.len() as u64);
+            }
+        }
+    }
+    ranges
+}
+
+struct Finder<'a> {
+    module: &'a Module,
+    data: &'a [Range<u64>],
+    func: FunctionId,
+    local: &'a LocalFunction,
+    uses: &'a mut Vec<ConstAddrUse>,
+}
+
+impl Finder<'_> {
+    fn constant(&self, expr: ExprId) -> Option<u64> {
+        match self.local.get(expr) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => Some(u64::from(*n as u32)),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, expr: ExprId, user: ExprId, address: u64, kind: ConstAddrKind) {
+        self.uses.push(ConstAddrUse {
+            func: self.func,
+            expr,
+            user,
+            address,
+            kind,
+        });
+    }
+
+    /// Record `expr` if it's a constant pointing into static data.
+    fn maybe_pointer(&mut self, expr: ExprId, user: ExprId, kind: ConstAddrKind) {
+        if let Some(address) = self.constant(expr) {
+            if self
+                .data
+                .iter()
+                .any(|r| r.start <= address && address < r.end)
+            {
+                self.push(expr, user, address, kind);
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Finder<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.local
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        match self.local.get(id) {
+            Expr::Load(e) => {
+                if let Some(address) = self.constant(e.address) {
+                    let address = address + u64::from(e.arg.offset);
+                    self.push(e.address, id, address, ConstAddrKind::MemoryOperand);
+                }
+            }
+            Expr::Store(e) => {
+                if let Some(address) = self.constant(e.address) {
+                    let address = address + u64::from(e.arg.offset);
+                    self.push(e.address, id, address, ConstAddrKind::MemoryOperand);
+                }
+                if let StoreKind::I32 { .. } = e.kind {
+                    self.maybe_pointer(e.value, id, ConstAddrKind::StoredValue);
+                }
+            }
+            Expr::Call(e) => {
+                if let FunctionKind::Import(_) = self.module.funcs.get(e.func).kind {
+                    for (index, arg) in e.args.iter().enumerate() {
+                        let kind = ConstAddrKind::CallArgument { index };
+                        self.maybe_pointer(*arg, id, kind);
+                    }
+                }
+            }
+            _ => {}
+        }
+        id.visit(self);
+    }
+}
@@ -0,0 +1,136 @@
+//! Checking active data segments for overlap.
+//!
+//! Engines apply active data segments in order, so when two segments write to
+//! the same bytes the result silently depends on which was emitted last. This
+//! is almost always a bug, typically from merging modules or editing segments
+//! by hand.
+
+use crate::ir::Value;
+use crate::{GlobalId, InitExpr, MemoryId, Module};
+use std::ops::Range;
+
+/// Identifies an active data segment of a memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ActiveSegment {
+    /// The memory this segment initializes.
+    pub memory: MemoryId,
+    /// The position of this segment among the memory's segments, in the order
+    /// they're emitted.
+    pub index: usize,
+}
+
+/// A problem with the placement of an active data segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overlap {
+    /// Two segments initialize some of the same bytes.
+    ///
+    /// `first` is emitted before `second`, so its bytes are overwritten.
+    Overlapping {
+        /// The segment emitted first.
+        first: ActiveSegment,
+        /// The bytes initialized by the first segment.
+        first_range: Range<u64>,
+        /// The segment emitted second.
+        second: ActiveSegment,
+        /// The bytes initialized by the second segment.
+        second_range: Range<u64>,
+    },
+
+    /// A segment extends beyond the initial size of its memory.
+    OutOfBounds {
+        /// The segment that's out of bounds.
+        segment: ActiveSegment,
+        /// The bytes initialized by the segment.
+        range: Range<u64>,
+        /// The initial size of the memory, in bytes.
+        memory_size: u64,
+    },
+
+    /// A segment's offset is relative to a global, so which bytes it
+    /// initializes isn't known.
+    UnknownExtent {
+        /// The segment whose extent is unknown.
+        segment: ActiveSegment,
+        /// The global the segment's offset is relative to.
+        global: GlobalId,
+    },
+}
+
+/// Check every memory's active data segments for segments that overlap, that
+/// extend beyond the memory's initial size, or whose extent isn't known.
+///
+/// Problems are reported per memory. Segments which are out of bounds or whose
+/// extent is unknown come first, in the order they're emitted, followed by
+/// each overlapping pair of segments.
+pub fn check_data_overlap(module: &Module) -> Vec<Overlap> {
+    let mut problems = Vec::new();
+    for memory in module.memories.iter() {
+        let memory_size = u64::from(memory.initial) * memory.page_size();
+        let mut known = Vec::new();
+        for (index, (offset, data)) in memory.data.iter().enumerate() {
+            let segment = ActiveSegment {
+                memory: memory.id(),
+                index,
+            };
+            let start = match offset {
+                InitExpr::Value(Value::I32(n)) => u64::from(n as u32),
+                InitExpr::Global(global) => {
+                    problems.push(Overlap::UnknownExtent { segment, global });
+                    continue;
+                }
+                InitExpr::Extended(_) => match module.globals.const_eval(&offset) {
+                    Some(Value::I32(n)) => u64::from(n as u32),
+                    _ => {
+                        if let Some(&global) = offset.globals().first() {
+                            problems.push(Overlap::UnknownExtent { segment, global });
+                        }
+                        continue;
+                    }
+                },
+                InitExpr::Value(_) | InitExpr::RefFunc(_) | InitExpr::RefNull(_) => continue,
+            };
+            let range = start..start + data.len() as u64;
+            if range.end > memory_size {
+                problems.push(Overlap::OutOfBounds {
+                    segment,
+                    range: range.clone(),
+                    memory_size,
+                });
+            }
+            if !data.is_empty() {
+                known.push((segment, range));
+            }
+        }
+
+        // Sweep over the segments in order of their start, comparing each to
+        // the following segments which start before it ends.
+        let mut sorted = known.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(segment, range)| (range.start, segment.index));
+        let mut overlaps = Vec::new();
+        for (i, (a, a_range)) in sorted.iter().enumerate() {
+            for (b, b_range) in sorted[i + 1..].iter() {
+                if b_range.start >= a_range.end {
+                    break;
+                }
+                if a.index < b.index {
+                    overlaps.push((*a, a_range.clone(), *b, b_range.clone()));
+                } else {
+                    overlaps.push((*b, b_range.clone(), *a, a_range.clone()));
+                }
+            }
+        }
+        overlaps.sort_by_key(|(first, _, second, _)| (first.index, second.index));
+        let overlaps = overlaps
+            .into_iter()
+            .map(
+                |(first, first_range, second, second_range)| Overlap::Overlapping {
+                    first,
+                    first_range,
+                    second,
+                    second_range,
+                },
+            );
+        problems.extend(overlaps);
+    }
+    problems
+}
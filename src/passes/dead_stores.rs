@@ -0,0 +1,320 @@
+//! Removing stores to memory which are overwritten before they can be
+//! observed.
+//!
+//! This is deliberately narrow: a store is only removed when a later store in
+//! the same block writes the same number of bytes to the same constant
+//! address of the same memory, and nothing in between could read memory,
+//! leave the block, or trap. If the removed store could itself trap, nothing
+//! in between may have any side effect other than setting a local either,
+//! since the trap would then happen after it.
+
+use crate::ir::*;
+use crate::map::IdHashSet;
+use crate::{FunctionKind, LocalFunction, MemoryId, Module};
+
+/// Remove every store which is overwritten by a later store before anything
+/// could observe it, returning how many were removed.
+///
+/// A store is dead when it's followed, in the same block, by a store of the
+/// same width to the same constant address in the same memory, and none of
+/// the expressions in between (including the operands of the later store)
+/// load from memory, call a function, perform an atomic operation, grow
+/// memory, branch, or could otherwise trap. Stores which could trap are only
+/// removed when nothing in between sets a global or stores to memory either,
+/// so that the trap doesn't move past any side effect. Stores to shared
+/// memories are never removed, since other threads can observe them at any
+/// time.
+///
+/// The value operand of a removed store is still evaluated, unless it's a
+/// constant or a read of a local or global.
+pub fn dead_store_elimination(module: &mut Module) -> usize {
+    let mut memories = Memories::default();
+    for memory in module.memories.iter() {
+        if memory.shared {
+            memories.shared.insert(memory.id());
+        }
+        memories
+            .bounds
+            .push((memory.id(), u64::from(memory.initial) * memory.page_size()));
+    }
+
+    let mut dead = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        // The traversal starts inside the entry block, so it's never visited
+        // by id.
+        let mut blocks = Blocks {
+            func,
+            blocks: vec![func.entry_block().into()],
+        };
+        dfs_in_order(&mut blocks, func, func.entry_block().into());
+        for block in blocks.blocks {
+            let stores = dead_stores(func, &memories, block);
+            if !stores.is_empty() {
+                dead.push((id, block, stores));
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for (id, block, stores) in dead {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        removed += stores.len();
+        remove_stores(func, block, &stores);
+    }
+    removed
+}
+
+/// What's known about the module's memories.
+#[derive(Default)]
+struct Memories {
+    shared: IdHashSet<crate::Memory>,
+    /// The minimum size of each memory, in bytes. Memories never shrink, so
+    /// accesses within these bounds can't trap.
+    bounds: Vec<(MemoryId, u64)>,
+}
+
+impl Memories {
+    fn in_bounds(&self, memory: MemoryId, end: u64) -> bool {
+        self.bounds
+            .iter()
+            .any(|(id, size)| *id == memory && end <= *size)
+    }
+}
+
+/// A store of a constant address: its memory, address and width.
+type StoreKey = (MemoryId, u64, u32);
+
+fn const_store(func: &LocalFunction, memories: &Memories, expr: ExprId) -> Option<StoreKey> {
+    let store = match func.get(expr) {
+        Expr::Store(store) => store,
+        _ => return None,
+    };
+    if store.kind.atomic() || memories.shared.contains(&store.memory) {
+        return None;
+    }
+    match func.get(store.address) {
+        Expr::Const(Const {
+            value: Value::I32(n),
+        }) => {
+            let address = u64::from(*n as u32) + u64::from(store.arg.offset);
+            Some((store.memory, address, store.kind.width()))
+        }
+        _ => None,
+    }
+}
+
+/// Find the indices of the dead stores among the expressions of `block`.
+fn dead_stores(func: &LocalFunction, memories: &Memories, block: ExprId) -> Vec<usize> {
+    let exprs = match func.get(block) {
+        Expr::Block(b) => &b.exprs,
+        _ => unreachable!(),
+    };
+
+    let mut dead = Vec::new();
+    // Stores which nothing has observed yet, along with their index and
+    // whether they could trap.
+    let mut pending: Vec<(StoreKey, usize, bool)> = Vec::new();
+    for (i, expr) in exprs.iter().enumerate() {
+        let key = match const_store(func, memories, *expr) {
+            Some(key) => key,
+            None => {
+                barrier(&mut pending, observe(func, memories, *expr));
+                continue;
+            }
+        };
+
+        // The value is evaluated before the store happens, so it may observe
+        // earlier stores. The address is a constant.
+        let value = match func.get(*expr) {
+            Expr::Store(store) => store.value,
+            _ => unreachable!(),
+        };
+        barrier(&mut pending, observe(func, memories, value));
+        let in_bounds = memories.in_bounds(key.0, key.1 + u64::from(key.2));
+        if let Some(pos) = pending.iter().position(|(k, _, _)| *k == key) {
+            dead.push(pending.remove(pos).1);
+        } else if !in_bounds {
+            // This store might trap, unless an earlier store already wrote
+            // the same bytes.
+            pending.clear();
+        }
+        // The store itself is a side effect for any other pending store.
+        barrier(&mut pending, Observed::Effects);
+        pending.push((key, i, !in_bounds));
+    }
+    dead.sort();
+    dead
+}
+
+/// What evaluating an expression could do which matters to earlier stores.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Observed {
+    /// Nothing at all, beyond setting locals.
+    Nothing,
+    /// Side effects, like setting a global, which a trap mustn't be moved
+    /// past, although they don't observe memory.
+    Effects,
+    /// The contents of memory, either by reading it, by running code we
+    /// can't see, by leaving the current block, or by trapping.
+    Memory,
+}
+
+/// Forget the pending stores which can't be removed any more after an
+/// expression which observes `observed`.
+fn barrier(pending: &mut Vec<(StoreKey, usize, bool)>, observed: Observed) {
+    match observed {
+        Observed::Nothing => {}
+        Observed::Effects => pending.retain(|(_, _, can_trap)| !can_trap),
+        Observed::Memory => pending.clear(),
+    }
+}
+
+/// What could evaluating `expr` observe before it finishes?
+fn observe(func: &LocalFunction, memories: &Memories, expr: ExprId) -> Observed {
+    let mut observer = Observer {
+        func,
+        memories,
+        observed: Observed::Nothing,
+    };
+    observer.visit_expr_id(&expr);
+    observer.observed
+}
+
+struct Observer<'a> {
+    func: &'a LocalFunction,
+    memories: &'a Memories,
+    observed: Observed,
+}
+
+impl<'a> Visitor<'a> for Observer<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if self.observed == Observed::Memory {
+            return;
+        }
+        let harmless = match self.func.get(id) {
+            Expr::GlobalSet(_) => {
+                self.observed = Observed::Effects;
+                true
+            }
+            Expr::Const(_)
+            | Expr::BlockParam(_)
+            | Expr::LocalGet(_)
+            | Expr::LocalSet(_)
+            | Expr::LocalTee(_)
+            | Expr::GlobalGet(_)
+            | Expr::Select(_)
+            | Expr::Drop(_)
+            | Expr::MemorySize(_)
+            | Expr::WithSideEffects(_)
+            | Expr::TableSize(_)
+            | Expr::RefNull(_)
+            | Expr::RefIsNull(_)
+            | Expr::RefFunc(_)
+            | Expr::Ternop(_)
+            | Expr::V128Bitselect(_)
+            | Expr::V128Shuffle(_)
+            | Expr::V128Swizzle(_) => true,
+            Expr::Binop(e) => !binop_can_trap(e.op),
+            Expr::Unop(e) => !unop_can_trap(e.op),
+            // Writing memory doesn't observe it, as long as the write can't
+            // trap.
+            Expr::Store(e) => match self.func.get(e.address) {
+                Expr::Const(Const {
+                    value: Value::I32(n),
+                }) if !e.kind.atomic() => {
+                    let end =
+                        u64::from(*n as u32) + u64::from(e.arg.offset) + u64::from(e.kind.width());
+                    self.observed = Observed::Effects;
+                    self.memories.in_bounds(e.memory, end)
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if harmless {
+            id.visit(self);
+        } else {
+            self.observed = Observed::Memory;
+        }
+    }
+}
+
+fn binop_can_trap(op: BinaryOp) -> bool {
+    use BinaryOp::*;
+    matches!(
+        op,
+        I32DivS | I32DivU | I32RemS | I32RemU | I64DivS | I64DivU | I64RemS | I64RemU
+    )
+}
+
+fn unop_can_trap(op: UnaryOp) -> bool {
+    use UnaryOp::*;
+    matches!(
+        op,
+        I32TruncSF32
+            | I32TruncUF32
+            | I32TruncSF64
+            | I32TruncUF64
+            | I64TruncSF32
+            | I64TruncUF32
+            | I64TruncSF64
+            | I64TruncUF64
+    )
+}
+
+/// Collects every block in a function.
+struct Blocks<'a> {
+    func: &'a LocalFunction,
+    blocks: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Blocks<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Expr::Block(_) = self.func.get(id) {
+            self.blocks.push(id);
+        }
+        id.visit(self);
+    }
+}
+
+/// Remove the stores at the given indices of `block`, keeping the evaluation
+/// of any value that might have side effects.
+fn remove_stores(func: &mut LocalFunction, block: ExprId, stores: &[usize]) {
+    let exprs = match func.get(block) {
+        Expr::Block(b) => b.exprs.clone(),
+        _ => unreachable!(),
+    };
+    let mut keep = Vec::with_capacity(exprs.len());
+    for (i, expr) in exprs.into_iter().enumerate() {
+        if stores.binary_search(&i).is_err() {
+            keep.push(expr);
+            continue;
+        }
+        let value = match func.get(expr) {
+            Expr::Store(store) => store.value,
+            _ => unreachable!(),
+        };
+        match func.get(value) {
+            Expr::Const(_) | Expr::LocalGet(_) | Expr::GlobalGet(_) => {}
+            _ => {
+                *func.get_mut(expr) = Expr::Drop(Drop { expr: value });
+                keep.push(expr);
+            }
+        }
+    }
+    match func.get_mut(block) {
+        Expr::Block(b) => b.exprs = keep,
+        _ => unreachable!(),
+    }
+}
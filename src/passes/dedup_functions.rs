@@ -0,0 +1,195 @@
+//! Merging local functions with identical bodies.
+
+use crate::emit::IdsToIndices;
+use crate::encode::Encoder;
+use crate::ir::*;
+use crate::map::{IdHashMap, IdHashSet};
+use crate::passes::replace_function_uses::replace_uses;
+use crate::{FunctionId, LocalFunction, Module, TypeId};
+use std::collections::hash_map::{Entry, HashMap};
+
+/// Options for `dedup_functions_with`.
+#[derive(Debug, Clone, Default)]
+pub struct DedupOptions {
+    /// Whether functions whose address can be observed, because they're in
+    /// an element segment or a function table or are referenced by
+    /// `ref.func`, can be merged.
+    ///
+    /// Merging them makes distinct table entries refer to the same function,
+    /// which changes the behavior of code comparing function pointers, so
+    /// this is off by default.
+    pub merge_address_taken: bool,
+}
+
+/// What `dedup_functions` did.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// The number of functions which were removed in favor of an identical
+    /// one.
+    pub removed: usize,
+    /// The total encoded size, in bytes, of the bodies of the removed
+    /// functions.
+    pub bytes_saved: u64,
+}
+
+/// Merge local functions with identical bodies, keeping the one with the
+/// smallest id out of each group.
+///
+/// This is the same as `dedup_functions_with` with the default options.
+pub fn dedup_functions(module: &mut Module) -> DedupStats {
+    dedup_functions_with(module, &DedupOptions::default())
+}
+
+/// Merge local functions with identical bodies, keeping the one with the
+/// smallest id out of each group.
+///
+/// Two functions are identical when they have the same type, the same types
+/// of locals and the same expressions, regardless of the ids of their
+/// locals or their names. Every call, export, element segment member and
+/// `start` function referring to a removed function is pointed at the one
+/// that's kept instead.
+///
+/// Functions calling functions which were merged can themselves become
+/// identical, so this can be run until it stops removing functions to merge
+/// as much as possible.
+pub fn dedup_functions_with(module: &mut Module, options: &DedupOptions) -> DedupStats {
+    let indices = canonical_indices(module);
+    let shapes = module
+        .funcs
+        .par_map_local(|_, func| shape(module, &indices, func));
+
+    let mut address_taken = IdHashSet::default();
+    if !options.merge_address_taken {
+        for segment in module.element_segments() {
+            address_taken.extend(segment.members().iter().filter_map(|m| *m));
+        }
+        for (_, shape) in shapes.iter() {
+            address_taken.extend(shape.ref_funcs.iter().cloned());
+        }
+    }
+
+    // `par_map_local` sorts by id, so the first function with each shape is
+    // the one that's kept.
+    let mut kept = HashMap::new();
+    let mut merges = Vec::new();
+    for (id, shape) in shapes {
+        if address_taken.contains(&id) {
+            continue;
+        }
+        let size = shape.body.len() as u64;
+        match kept.entry((shape.ty, shape.body)) {
+            Entry::Occupied(existing) => merges.push((id, *existing.get(), size)),
+            Entry::Vacant(entry) => {
+                entry.insert(id);
+            }
+        }
+    }
+
+    // Uses inside the functions that are kept are replaced too, since a
+    // function calling a duplicate of itself is identical to the duplicate.
+    let mut map = IdHashMap::default();
+    let mut stats = DedupStats::default();
+    for (id, representative, size) in merges {
+        map.insert(id, representative);
+        stats.removed += 1;
+        stats.bytes_saved += size;
+    }
+    replace_uses(module, &map, None);
+    for id in map.keys() {
+        module.funcs.delete(*id);
+    }
+    stats
+}
+
+/// Indices for everything in `module`, in the order of their ids, which are
+/// only used to compare function bodies.
+fn canonical_indices(module: &Module) -> IdsToIndices {
+    let mut indices = IdsToIndices::default();
+    for ty in module.types.iter() {
+        indices.push_type(ty.id());
+    }
+    for func in module.funcs.iter() {
+        indices.push_func(func.id());
+    }
+    for global in module.globals.iter() {
+        indices.push_global(global.id());
+    }
+    for memory in module.memories.iter() {
+        indices.push_memory(memory.id());
+    }
+    for table in module.tables.iter() {
+        indices.push_table(table.id());
+    }
+    for tag in module.tags.iter() {
+        indices.push_tag(tag.id());
+    }
+    for (i, data) in module.data.iter().enumerate() {
+        indices.set_data_index(data.id(), i as u32);
+    }
+    for (i, element) in module.elements.iter().enumerate() {
+        indices.set_element_index(element.id(), i as u32);
+    }
+    indices
+}
+
+/// What makes a function the same as another.
+struct Shape {
+    ty: TypeId,
+    /// The function's locals and instructions, encoded with its locals
+    /// numbered in the order they're first used.
+    body: Vec<u8>,
+    ref_funcs: Vec<FunctionId>,
+}
+
+fn shape(module: &Module, indices: &IdsToIndices, func: &LocalFunction) -> Shape {
+    let mut locals = Locals {
+        func,
+        numbers: IdHashMap::default(),
+        order: Vec::new(),
+        ref_funcs: Vec::new(),
+    };
+    for arg in func.args.iter() {
+        locals.visit_local_id(arg);
+    }
+    dfs_in_order(&mut locals, func, func.entry_block().into());
+
+    let mut body = Vec::new();
+    let mut encoder = Encoder::new(&mut body);
+    let declared = &locals.order[func.args.len()..];
+    encoder.usize(declared.len());
+    for local in declared {
+        module.locals.get(*local).ty().emit(&mut encoder, indices);
+    }
+    func.emit_instructions(&module.types, indices, &locals.numbers, &mut encoder);
+    Shape {
+        ty: func.ty,
+        body,
+        ref_funcs: locals.ref_funcs,
+    }
+}
+
+/// Numbers a function's locals in the order they're first seen, and finds
+/// the targets of its `ref.func`s.
+struct Locals<'a> {
+    func: &'a LocalFunction,
+    numbers: IdHashMap<Local, u32>,
+    order: Vec<LocalId>,
+    ref_funcs: Vec<FunctionId>,
+}
+
+impl<'a> Visitor<'a> for Locals<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_local_id(&mut self, id: &LocalId) {
+        if !self.numbers.contains_key(id) {
+            self.numbers.insert(*id, self.order.len() as u32);
+            self.order.push(*id);
+        }
+    }
+
+    fn visit_ref_func(&mut self, e: &RefFunc) {
+        self.ref_funcs.push(e.func);
+    }
+}
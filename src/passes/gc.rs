@@ -4,11 +4,48 @@
 //! internally and can be safely removed.
 
 use crate::map::IdHashSet;
-use crate::passes::Used;
-use crate::{ImportKind, Module};
+use crate::passes::{Pass, PassStats, Used};
+use crate::{ImportKind, Module, Result};
 use id_arena::Id;
 
+/// The GC pass, for running as part of a `PassManager` pipeline.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gc;
+
+impl Pass for Gc {
+    fn name(&self) -> &str {
+        "gc"
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        let before = items(module);
+        run(module);
+        Ok(PassStats {
+            changed: items(module) != before,
+        })
+    }
+}
+
+/// The number of items in the module that GC can remove.
+fn items(m: &Module) -> usize {
+    m.imports.iter().count()
+        + m.tables.iter().count()
+        + m.globals.iter().count()
+        + m.memories.iter().count()
+        + m.data.iter().count()
+        + m.elements.iter().count()
+        + m.tags.iter().count()
+        + m.types.iter().count()
+        + m.funcs.iter().count()
+}
+
 /// Run GC passes over the module specified.
+///
+/// This is deterministic: what's kept depends only on the module's contents,
+/// and unused items are deleted in the order they appear in their arenas, so
+/// GC'ing equal modules leaves them equal, and emitting them gives the same
+/// bytes. Nothing here iterates over a randomly seeded hash map; the used sets
+/// are only queried, and use the fixed `IdHasher`.
 pub fn run(m: &mut Module) {
     let used = Used::new(m, m.exports.iter().map(|e| e.id()));
 
@@ -19,6 +56,7 @@ pub fn run(m: &mut Module) {
             ImportKind::Table(t) => used.tables.contains(t),
             ImportKind::Global(g) => used.globals.contains(g),
             ImportKind::Memory(m) => used.memories.contains(m),
+            ImportKind::Tag(t) => used.tags.contains(t),
         };
         if !used {
             unused_imports.push(import.id());
@@ -43,6 +81,9 @@ pub fn run(m: &mut Module) {
     for id in unused(&used.elements, m.elements.iter().map(|t| t.id())) {
         m.elements.delete(id);
     }
+    for id in unused(&used.tags, m.tags.iter().map(|t| t.id())) {
+        m.tags.delete(id);
+    }
     for id in unused(&used.types, m.types.iter().map(|t| t.id())) {
         m.types.delete(id);
     }
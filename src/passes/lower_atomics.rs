@@ -0,0 +1,521 @@
+//! Lowering atomic memory operations into plain ones for single-threaded
+//! engines.
+
+use crate::ir::*;
+use crate::{FunctionKind, LocalFunction, MemoryId, Module, ModuleLocals, ScratchLocals, ValType};
+
+/// What a lowered `memory.atomic.wait` evaluates to.
+///
+/// With only one thread nothing can ever notify a waiter, so a wait either
+/// returns immediately because the value in memory isn't the expected one, or
+/// blocks until it times out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum WaitResult {
+    /// Load the value and compare it against the expected one, evaluating to
+    /// 1 ("not-equal") if they differ and 2 ("timed-out") otherwise, as if
+    /// the timeout had expired immediately.
+    #[default]
+    Compare,
+    /// Always evaluate to 1 ("not-equal") without touching memory.
+    NotEqual,
+    /// Always evaluate to 2 ("timed-out") without touching memory.
+    TimedOut,
+}
+
+/// Options for `lower_atomics_with`.
+#[derive(Debug, Clone, Default)]
+pub struct LowerAtomicsOptions {
+    /// What lowered `memory.atomic.wait`s evaluate to.
+    pub wait: WaitResult,
+}
+
+/// Rewrite every atomic memory operation into plain loads and stores, and
+/// make every memory unshared, returning how many operations were rewritten.
+///
+/// This is the same as `lower_atomics_with` with the default options.
+pub fn lower_atomics(module: &mut Module) -> usize {
+    lower_atomics_with(module, &LowerAtomicsOptions::default())
+}
+
+/// Rewrite every atomic memory operation into plain loads and stores, and
+/// make every memory unshared, returning how many operations were rewritten.
+///
+/// **The result is only equivalent to the original module if it is run by a
+/// single thread.** Nothing else may access the memories concurrently, since
+/// read-modify-write operations become separate loads and stores, and
+/// misaligned accesses no longer trap.
+///
+/// * Atomic loads and stores become their plain counterparts.
+/// * `*.atomic.rmw*` becomes a load of the old value into a scratch local,
+///   followed by a store of the new one.
+/// * `*.atomic.rmw*.cmpxchg` becomes a load of the old value followed by an
+///   `if` storing the replacement when the old value is the expected one.
+/// * `memory.atomic.notify` evaluates to 0, since there are no waiters.
+/// * `memory.atomic.wait*` evaluates to what `options.wait` chooses.
+///
+/// Operands are still evaluated in their original order. Memories keep their
+/// maximum size even when it's no longer required. The IR has no
+/// representation of `atomic.fence`, so there are never any fences to remove.
+pub fn lower_atomics_with(module: &mut Module, options: &LowerAtomicsOptions) -> usize {
+    let mut found = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut atomics = Atomics {
+            func,
+            events: Vec::new(),
+        };
+        dfs_in_order(&mut atomics, func, func.entry_block().into());
+        if !atomics.events.is_empty() {
+            found.push((id, atomics.events));
+        }
+    }
+
+    let mut lower = Lower {
+        locals: &mut module.locals,
+        scratch: ScratchLocals::new(),
+        options,
+        live: Vec::new(),
+    };
+    let mut lowered = 0;
+    for (id, events) in found {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        for event in events {
+            match event {
+                // Scratch locals stay live while the operands of the
+                // operation which uses them are evaluated, so nested
+                // operations get locals of their own.
+                Event::Enter(expr) => {
+                    let used = lower.expr(func, expr);
+                    lower.live.push(used);
+                    lowered += 1;
+                }
+                Event::Exit => {
+                    for local in lower.live.pop().unwrap() {
+                        lower.scratch.release(lower.locals, local);
+                    }
+                }
+            }
+        }
+    }
+
+    for memory in module.memories.iter_mut() {
+        memory.shared = false;
+    }
+    lowered
+}
+
+struct Lower<'a> {
+    locals: &'a mut ModuleLocals,
+    scratch: ScratchLocals,
+    options: &'a LowerAtomicsOptions,
+    /// The scratch locals used by each operation being lowered.
+    live: Vec<Vec<LocalId>>,
+}
+
+impl Lower<'_> {
+    /// Lower one atomic operation in place, returning the scratch locals it
+    /// now uses.
+    fn expr(&mut self, func: &mut LocalFunction, id: ExprId) -> Vec<LocalId> {
+        match func.get_mut(id) {
+            Expr::Load(e) => {
+                e.kind = plain_load(e.kind);
+                return Vec::new();
+            }
+            Expr::Store(e) => {
+                e.kind = plain_store(e.kind);
+                return Vec::new();
+            }
+            _ => {}
+        }
+
+        let (expr, used) = match func.get(id).clone() {
+            Expr::AtomicRmw(e) => self.rmw(func, e),
+            Expr::Cmpxchg(e) => self.cmpxchg(func, e),
+            Expr::AtomicNotify(e) => {
+                let address = func.alloc(Drop { expr: e.address });
+                let count = func.alloc(Drop { expr: e.count });
+                let zero = func.alloc(Const {
+                    value: Value::I32(0),
+                });
+                let expr = WithSideEffects {
+                    before: vec![address.into(), count.into()],
+                    value: zero.into(),
+                    after: Vec::new(),
+                };
+                (expr, Vec::new())
+            }
+            Expr::AtomicWait(e) => self.wait(func, e),
+            _ => unreachable!(),
+        };
+        *func.get_mut(id) = Expr::WithSideEffects(expr);
+        used
+    }
+
+    /// `(local.set $a address) (local.set $v value) (local.tee $old (load
+    /// $a))`, followed by `(store $a (op $old $v))`.
+    fn rmw(&mut self, func: &mut LocalFunction, e: AtomicRmw) -> (WithSideEffects, Vec<LocalId>) {
+        let ty = e.width.result_type();
+        let a = self.scratch.get(self.locals, ValType::I32);
+        let v = self.scratch.get(self.locals, ty);
+        let old = self.scratch.get(self.locals, ty);
+
+        let set_a = func.alloc(LocalSet {
+            local: a,
+            value: e.address,
+        });
+        let set_v = func.alloc(LocalSet {
+            local: v,
+            value: e.value,
+        });
+        let load = load(func, e.memory, load_kind(e.width), e.arg, a);
+        let tee = func.alloc(LocalTee {
+            local: old,
+            value: load,
+        });
+
+        let value = func.alloc(LocalGet { local: v });
+        let new: ExprId = match rmw_binop(e.op, ty) {
+            Some(op) => {
+                let lhs = func.alloc(LocalGet { local: old });
+                func.alloc(Binop {
+                    op,
+                    lhs: lhs.into(),
+                    rhs: value.into(),
+                })
+                .into()
+            }
+            None => value.into(),
+        };
+        let store = store(func, e.memory, store_kind(e.width), e.arg, a, new);
+
+        let expr = WithSideEffects {
+            before: vec![set_a.into(), set_v.into()],
+            value: tee.into(),
+            after: vec![store],
+        };
+        (expr, vec![a, v, old])
+    }
+
+    /// `(local.set $a address) (local.set $e expected) (local.set $r
+    /// replacement) (local.tee $old (load $a))`, followed by `(if (eq $old
+    /// (wrap $e)) (then (store $a $r)))`.
+    fn cmpxchg(&mut self, func: &mut LocalFunction, e: Cmpxchg) -> (WithSideEffects, Vec<LocalId>) {
+        let ty = e.width.result_type();
+        let a = self.scratch.get(self.locals, ValType::I32);
+        let expected = self.scratch.get(self.locals, ty);
+        let replacement = self.scratch.get(self.locals, ty);
+        let old = self.scratch.get(self.locals, ty);
+
+        let set_a = func.alloc(LocalSet {
+            local: a,
+            value: e.address,
+        });
+        let set_expected = func.alloc(LocalSet {
+            local: expected,
+            value: e.expected,
+        });
+        let set_replacement = func.alloc(LocalSet {
+            local: replacement,
+            value: e.replacement,
+        });
+        let load = load(func, e.memory, load_kind(e.width), e.arg, a);
+        let tee = func.alloc(LocalTee {
+            local: old,
+            value: load,
+        });
+
+        // The loaded value is zero-extended, so only the low bits of the
+        // expected value take part in the comparison.
+        let mut wrapped: ExprId = func.alloc(LocalGet { local: expected }).into();
+        if let Some(mask) = mask(e.width) {
+            let mask = func.alloc(Const { value: mask });
+            let op = match ty {
+                ValType::I32 => BinaryOp::I32And,
+                _ => BinaryOp::I64And,
+            };
+            wrapped = func
+                .alloc(Binop {
+                    op,
+                    lhs: wrapped,
+                    rhs: mask.into(),
+                })
+                .into();
+        }
+        let lhs = func.alloc(LocalGet { local: old });
+        let op = match ty {
+            ValType::I32 => BinaryOp::I32Eq,
+            _ => BinaryOp::I64Eq,
+        };
+        let equal = func.alloc(Binop {
+            op,
+            lhs: lhs.into(),
+            rhs: wrapped,
+        });
+        let value = func.alloc(LocalGet { local: replacement });
+        let store = store(func, e.memory, store_kind(e.width), e.arg, a, value.into());
+        let mut consequent = Block::new(BlockKind::IfElse, Box::new([]), Box::new([]));
+        consequent.exprs.push(store);
+        let consequent = func.alloc(consequent);
+        let alternative = func.alloc(Block::new(BlockKind::IfElse, Box::new([]), Box::new([])));
+        let swap = func.alloc(IfElse {
+            condition: equal.into(),
+            consequent,
+            alternative,
+        });
+
+        let expr = WithSideEffects {
+            before: vec![set_a.into(), set_expected.into(), set_replacement.into()],
+            value: tee.into(),
+            after: vec![swap.into()],
+        };
+        (expr, vec![a, expected, replacement, old])
+    }
+
+    fn wait(&mut self, func: &mut LocalFunction, e: AtomicWait) -> (WithSideEffects, Vec<LocalId>) {
+        let timeout = func.alloc(Drop { expr: e.timeout });
+        let constant = |func: &mut LocalFunction, n| {
+            ExprId::from(func.alloc(Const {
+                value: Value::I32(n),
+            }))
+        };
+        let result = match self.options.wait {
+            WaitResult::NotEqual => 1,
+            WaitResult::TimedOut => 2,
+            WaitResult::Compare => {
+                let (ty, kind, op) = if e.sixty_four {
+                    (
+                        ValType::I64,
+                        LoadKind::I64 { atomic: false },
+                        BinaryOp::I64Ne,
+                    )
+                } else {
+                    (
+                        ValType::I32,
+                        LoadKind::I32 { atomic: false },
+                        BinaryOp::I32Ne,
+                    )
+                };
+                let a = self.scratch.get(self.locals, ValType::I32);
+                let expected = self.scratch.get(self.locals, ty);
+                let set_a = func.alloc(LocalSet {
+                    local: a,
+                    value: e.address,
+                });
+                let set_expected = func.alloc(LocalSet {
+                    local: expected,
+                    value: e.expected,
+                });
+
+                let load = load(func, e.memory, kind, e.arg, a);
+                let rhs = func.alloc(LocalGet { local: expected });
+                let differ = func.alloc(Binop {
+                    op,
+                    lhs: load,
+                    rhs: rhs.into(),
+                });
+                let not_equal = constant(func, 1);
+                let timed_out = constant(func, 2);
+                let mut arm = |expr| {
+                    let mut block =
+                        Block::new(BlockKind::IfElse, Box::new([]), Box::new([ValType::I32]));
+                    block.exprs.push(expr);
+                    func.alloc(block)
+                };
+                let consequent = arm(not_equal);
+                let alternative = arm(timed_out);
+                let value = func.alloc(IfElse {
+                    condition: differ.into(),
+                    consequent,
+                    alternative,
+                });
+                let expr = WithSideEffects {
+                    before: vec![set_a.into(), set_expected.into(), timeout.into()],
+                    value: value.into(),
+                    after: Vec::new(),
+                };
+                return (expr, vec![a, expected]);
+            }
+        };
+        let address = func.alloc(Drop { expr: e.address });
+        let expected = func.alloc(Drop { expr: e.expected });
+        let expr = WithSideEffects {
+            before: vec![address.into(), expected.into(), timeout.into()],
+            value: constant(func, result),
+            after: Vec::new(),
+        };
+        (expr, Vec::new())
+    }
+}
+
+fn load(
+    func: &mut LocalFunction,
+    memory: MemoryId,
+    kind: LoadKind,
+    arg: MemArg,
+    a: LocalId,
+) -> ExprId {
+    let address = func.alloc(LocalGet { local: a });
+    func.alloc(Load {
+        memory,
+        kind,
+        arg,
+        address: address.into(),
+    })
+    .into()
+}
+
+fn store(
+    func: &mut LocalFunction,
+    memory: MemoryId,
+    kind: StoreKind,
+    arg: MemArg,
+    a: LocalId,
+    value: ExprId,
+) -> ExprId {
+    let address = func.alloc(LocalGet { local: a });
+    func.alloc(Store {
+        memory,
+        kind,
+        arg,
+        address: address.into(),
+        value,
+    })
+    .into()
+}
+
+fn plain_load(kind: LoadKind) -> LoadKind {
+    use crate::ir::ExtendedLoad::*;
+    use crate::ir::LoadKind::*;
+    match kind {
+        I32 { .. } => I32 { atomic: false },
+        I64 { .. } => I64 { atomic: false },
+        I32_8 {
+            kind: ZeroExtendAtomic,
+        } => I32_8 { kind: ZeroExtend },
+        I32_16 {
+            kind: ZeroExtendAtomic,
+        } => I32_16 { kind: ZeroExtend },
+        I64_8 {
+            kind: ZeroExtendAtomic,
+        } => I64_8 { kind: ZeroExtend },
+        I64_16 {
+            kind: ZeroExtendAtomic,
+        } => I64_16 { kind: ZeroExtend },
+        I64_32 {
+            kind: ZeroExtendAtomic,
+        } => I64_32 { kind: ZeroExtend },
+        other => other,
+    }
+}
+
+fn plain_store(kind: StoreKind) -> StoreKind {
+    use crate::ir::StoreKind::*;
+    match kind {
+        I32 { .. } => I32 { atomic: false },
+        I64 { .. } => I64 { atomic: false },
+        I32_8 { .. } => I32_8 { atomic: false },
+        I32_16 { .. } => I32_16 { atomic: false },
+        I64_8 { .. } => I64_8 { atomic: false },
+        I64_16 { .. } => I64_16 { atomic: false },
+        I64_32 { .. } => I64_32 { atomic: false },
+        other => other,
+    }
+}
+
+/// The load which reads the operand of an atomic operation of this width,
+/// zero-extended.
+fn load_kind(width: AtomicWidth) -> LoadKind {
+    let kind = ExtendedLoad::ZeroExtend;
+    match width {
+        AtomicWidth::I32 => LoadKind::I32 { atomic: false },
+        AtomicWidth::I32_8 => LoadKind::I32_8 { kind },
+        AtomicWidth::I32_16 => LoadKind::I32_16 { kind },
+        AtomicWidth::I64 => LoadKind::I64 { atomic: false },
+        AtomicWidth::I64_8 => LoadKind::I64_8 { kind },
+        AtomicWidth::I64_16 => LoadKind::I64_16 { kind },
+        AtomicWidth::I64_32 => LoadKind::I64_32 { kind },
+    }
+}
+
+fn store_kind(width: AtomicWidth) -> StoreKind {
+    let atomic = false;
+    match width {
+        AtomicWidth::I32 => StoreKind::I32 { atomic },
+        AtomicWidth::I32_8 => StoreKind::I32_8 { atomic },
+        AtomicWidth::I32_16 => StoreKind::I32_16 { atomic },
+        AtomicWidth::I64 => StoreKind::I64 { atomic },
+        AtomicWidth::I64_8 => StoreKind::I64_8 { atomic },
+        AtomicWidth::I64_16 => StoreKind::I64_16 { atomic },
+        AtomicWidth::I64_32 => StoreKind::I64_32 { atomic },
+    }
+}
+
+/// The mask keeping the bits of a value that an atomic operation of this
+/// width stores, if it's narrower than the value.
+fn mask(width: AtomicWidth) -> Option<Value> {
+    Some(match width {
+        AtomicWidth::I32_8 => Value::I32(0xff),
+        AtomicWidth::I32_16 => Value::I32(0xffff),
+        AtomicWidth::I64_8 => Value::I64(0xff),
+        AtomicWidth::I64_16 => Value::I64(0xffff),
+        AtomicWidth::I64_32 => Value::I64(0xffff_ffff),
+        AtomicWidth::I32 | AtomicWidth::I64 => return None,
+    })
+}
+
+/// The operator computing the new value of a read-modify-write, or `None` for
+/// an exchange which stores its operand as is.
+fn rmw_binop(op: AtomicOp, ty: ValType) -> Option<BinaryOp> {
+    let is_i32 = ty == ValType::I32;
+    Some(match op {
+        AtomicOp::Add if is_i32 => BinaryOp::I32Add,
+        AtomicOp::Add => BinaryOp::I64Add,
+        AtomicOp::Sub if is_i32 => BinaryOp::I32Sub,
+        AtomicOp::Sub => BinaryOp::I64Sub,
+        AtomicOp::And if is_i32 => BinaryOp::I32And,
+        AtomicOp::And => BinaryOp::I64And,
+        AtomicOp::Or if is_i32 => BinaryOp::I32Or,
+        AtomicOp::Or => BinaryOp::I64Or,
+        AtomicOp::Xor if is_i32 => BinaryOp::I32Xor,
+        AtomicOp::Xor => BinaryOp::I64Xor,
+        AtomicOp::Xchg => return None,
+    })
+}
+
+enum Event {
+    /// An atomic operation, before any atomic operations in its operands.
+    Enter(ExprId),
+    /// After the operands of the most recently entered operation.
+    Exit,
+}
+
+struct Atomics<'a> {
+    func: &'a LocalFunction,
+    events: Vec<Event>,
+}
+
+impl<'a> Visitor<'a> for Atomics<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        let atomic = match self.func.get(id) {
+            Expr::Load(e) => e.kind.atomic(),
+            Expr::Store(e) => e.kind.atomic(),
+            Expr::AtomicRmw(_) | Expr::Cmpxchg(_) | Expr::AtomicNotify(_) | Expr::AtomicWait(_) => {
+                true
+            }
+            _ => false,
+        };
+        if atomic {
+            self.events.push(Event::Enter(id));
+        }
+        id.visit(self);
+        if atomic {
+            self.events.push(Event::Exit);
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! Lowering the bulk memory operations for engines which don't support them.
+
+use crate::ir::*;
+use crate::ValType::I32;
+use crate::{DataId, FunctionBuilder, FunctionId, FunctionKind, LocalFunction, MemoryId};
+use crate::{Module, Result};
+use failure::bail;
+use std::collections::HashMap;
+
+/// How `lower_bulk_memory` replaces `memory.init` of passive data segments.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoweringStyle {
+    /// Put the contents of each passive segment in an active segment, in new
+    /// pages added to the end of the memory it's copied into, and copy from
+    /// there.
+    ///
+    /// This keeps the module small, but the memory starts out bigger, so
+    /// `memory.size` and `memory.grow` return different sizes, and the
+    /// program can see and overwrite the segments' contents.
+    ActiveSegments,
+    /// Write the contents of each passive segment with a sequence of
+    /// generated stores, each one guarded by whether its byte is in the range
+    /// being copied.
+    ///
+    /// This leaves the memory as it was, but the code grows with the size of
+    /// the segments, so it's best suited to small segments.
+    Stores,
+}
+
+/// Rewrite every `memory.copy`, `memory.fill`, `memory.init` and `data.drop`
+/// into code using only MVP instructions, and remove every passive data
+/// segment, returning how many instructions were rewritten.
+///
+/// Copies, fills and initializations become calls to generated helper
+/// functions, one for each memory (or memory and segment) that's used, which
+/// check their bounds up front and then loop a byte at a time. Copies go
+/// backwards when the destination is after the source, so overlapping ranges
+/// are copied as if through a temporary buffer. `data.drop` becomes a no-op,
+/// so a `memory.init` of a dropped segment no longer traps. Passive segments
+/// are materialized as chosen by `style`.
+///
+/// Returns an error, without changing anything, if
+/// `LoweringStyle::ActiveSegments` would need to grow an imported memory, or
+/// grow a memory beyond its maximum size.
+pub fn lower_bulk_memory(module: &mut Module, style: LoweringStyle) -> Result<usize> {
+    let mut found = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut ops = BulkOps {
+            func,
+            ops: Vec::new(),
+        };
+        dfs_in_order(&mut ops, func, func.entry_block().into());
+        if !ops.ops.is_empty() {
+            found.push((id, ops.ops));
+        }
+    }
+
+    // Decide where every segment that's copied into a memory will live before
+    // changing anything, so that running out of space can fail cleanly.
+    let mut placements = HashMap::new();
+    if style == LoweringStyle::ActiveSegments {
+        let mut order = Vec::new();
+        let mut ends = HashMap::new();
+        for (_, ops) in found.iter() {
+            for (_, op) in ops {
+                let (memory, data) = match *op {
+                    Op::Init(memory, data) => (memory, data),
+                    _ => continue,
+                };
+                if placements.contains_key(&(memory, data)) {
+                    continue;
+                }
+                let end = ends.entry(memory).or_insert_with(|| {
+                    order.push(memory);
+                    u64::from(module.memories.get(memory).initial) * PAGE
+                });
+                placements.insert((memory, data), *end);
+                *end += module.data.get(data).value.len() as u64;
+            }
+        }
+        for id in order.iter() {
+            let memory = module.memories.get(*id);
+            let pages = ends[id].div_ceil(PAGE);
+            if memory.import.is_some() {
+                bail!("can't place passive data segments in an imported memory");
+            }
+            if pages > u64::from(memory.maximum.unwrap_or(MAX_PAGES)) {
+                bail!(
+                    "placing passive data segments needs {} pages of memory, \
+                     which is more than the maximum",
+                    pages
+                );
+            }
+        }
+        let mut placed = placements.iter().collect::<Vec<_>>();
+        placed.sort_by_key(|(_, base)| **base);
+        for (&(memory, data), &base) in placed {
+            let value = module.data.get(data).value.clone();
+            module
+                .memories
+                .get_mut(memory)
+                .data
+                .add_absolute(base as u32, value);
+        }
+        for id in order {
+            let pages = ends[&id].div_ceil(PAGE);
+            module.memories.get_mut(id).initial = pages as u32;
+        }
+    }
+
+    let mut helpers = HashMap::new();
+    let mut lowered = 0;
+    for (id, ops) in found {
+        let mut calls = Vec::new();
+        for (expr, op) in ops {
+            let helper = match op {
+                Op::Drop => None,
+                _ => Some(helper(module, &mut helpers, &placements, op)),
+            };
+            calls.push((expr, helper));
+        }
+
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        for (expr, helper) in calls {
+            let args = match func.get(expr) {
+                Expr::MemoryCopy(e) => vec![e.dst_offset, e.src_offset, e.len],
+                Expr::MemoryFill(e) => vec![e.offset, e.value, e.len],
+                Expr::MemoryInit(e) => vec![e.memory_offset, e.data_offset, e.len],
+                _ => Vec::new(),
+            };
+            *func.get_mut(expr) = match helper {
+                Some(func) => Expr::Call(Call {
+                    func,
+                    args: args.into_boxed_slice(),
+                }),
+                None => Expr::Block(Block::new(BlockKind::Block, Box::new([]), Box::new([]))),
+            };
+            lowered += 1;
+        }
+    }
+
+    let passive = module.data.ids().collect::<Vec<_>>();
+    for data in passive {
+        module.data.delete(data);
+    }
+    Ok(lowered)
+}
+
+const PAGE: u64 = 65536;
+const MAX_PAGES: u32 = 65536;
+
+/// A bulk memory operation, and what its helper function works with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Op {
+    /// A copy into the first memory from the second.
+    Copy(MemoryId, MemoryId),
+    Fill(MemoryId),
+    Init(MemoryId, DataId),
+    Drop,
+}
+
+/// Get the helper function implementing `op`, generating it if this is the
+/// first time it's needed.
+fn helper(
+    module: &mut Module,
+    helpers: &mut HashMap<Op, FunctionId>,
+    placements: &HashMap<(MemoryId, DataId), u64>,
+    op: Op,
+) -> FunctionId {
+    if let Some(id) = helpers.get(&op) {
+        return *id;
+    }
+    let id = match op {
+        Op::Copy(dst, src) => copy(module, dst, src),
+        Op::Fill(memory) => fill(module, memory),
+        Op::Init(memory, data) => match placements.get(&(memory, data)) {
+            Some(&base) => {
+                let copy = helper(module, helpers, placements, Op::Copy(memory, memory));
+                init_by_copy(module, copy, base as u32, data)
+            }
+            None => init_by_stores(module, memory, data),
+        },
+        Op::Drop => unreachable!(),
+    };
+    helpers.insert(op, id);
+    id
+}
+
+/// `(func (param $dst i32) (param $src i32) (param $n i32))`, copying `$n`
+/// bytes from `$src` in memory `src` to `$dst` in memory `dst`.
+fn copy(module: &mut Module, dst: MemoryId, src: MemoryId) -> FunctionId {
+    let params = params(module);
+    let (dst_offset, src_offset, n) = (params[0], params[1], params[2]);
+    let i = module.locals.add(I32);
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    let limit = memory_limit(&mut builder, dst);
+    exprs.push(trap_if_out_of_bounds(&mut builder, dst_offset, n, limit));
+    let limit = memory_limit(&mut builder, src);
+    exprs.push(trap_if_out_of_bounds(&mut builder, src_offset, n, limit));
+
+    let copy_byte = |builder: &mut FunctionBuilder| {
+        let address = offset_by(builder, src_offset, i);
+        let byte = builder.load(src, BYTE_LOAD, BYTE, address);
+        let address = offset_by(builder, dst_offset, i);
+        builder.store(dst, BYTE_STORE, BYTE, address, byte)
+    };
+    let forward = {
+        let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+        for expr in forward_loop(&mut block, i, n, copy_byte) {
+            block.expr(expr);
+        }
+        block.id()
+    };
+    let backward = {
+        let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+        for expr in backward_loop(&mut block, i, n, copy_byte) {
+            block.expr(expr);
+        }
+        block.id()
+    };
+    let a = builder.local_get(dst_offset);
+    let b = builder.local_get(src_offset);
+    let before = builder.binop(BinaryOp::I32LeU, a, b);
+    exprs.push(builder.if_else(before, forward, backward));
+
+    finish(module, builder, params, exprs)
+}
+
+/// `(func (param $dst i32) (param $value i32) (param $n i32))`, writing the
+/// low byte of `$value` to `$n` bytes at `$dst`.
+fn fill(module: &mut Module, memory: MemoryId) -> FunctionId {
+    let params = params(module);
+    let (dst, value, n) = (params[0], params[1], params[2]);
+    let i = module.locals.add(I32);
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    let limit = memory_limit(&mut builder, memory);
+    exprs.push(trap_if_out_of_bounds(&mut builder, dst, n, limit));
+    exprs.extend(forward_loop(&mut builder, i, n, |builder| {
+        let address = offset_by(builder, dst, i);
+        let value = builder.local_get(value);
+        builder.store(memory, BYTE_STORE, BYTE, address, value)
+    }));
+    finish(module, builder, params, exprs)
+}
+
+/// `(func (param $dst i32) (param $offset i32) (param $n i32))`, copying `$n`
+/// bytes from `$offset` in a segment which has been placed at `base` with
+/// the `copy` helper.
+fn init_by_copy(module: &mut Module, copy: FunctionId, base: u32, data: DataId) -> FunctionId {
+    let params = params(module);
+    let (dst, offset, n) = (params[0], params[1], params[2]);
+    let len = module.data.get(data).value.len();
+    let mut builder = FunctionBuilder::new();
+    let limit = builder.i64_const(len as i64);
+    let check = trap_if_out_of_bounds(&mut builder, offset, n, limit);
+
+    let dst = builder.local_get(dst);
+    let base = builder.i32_const(base as i32);
+    let offset = builder.local_get(offset);
+    let src = builder.binop(BinaryOp::I32Add, base, offset);
+    let n = builder.local_get(n);
+    let call = builder.call(copy, Box::new([dst, src, n]));
+    finish(module, builder, params, vec![check, call])
+}
+
+/// `(func (param $dst i32) (param $offset i32) (param $n i32))`, writing `$n`
+/// bytes from `$offset` in a segment to `$dst` with one store for each byte
+/// of the segment.
+fn init_by_stores(module: &mut Module, memory: MemoryId, data: DataId) -> FunctionId {
+    let params = params(module);
+    let (dst, offset, n) = (params[0], params[1], params[2]);
+    let relative = module.locals.add(I32);
+    let mut builder = FunctionBuilder::new();
+    let mut exprs = Vec::new();
+    let value = &module.data.get(data).value;
+    let limit = builder.i64_const(value.len() as i64);
+    exprs.push(trap_if_out_of_bounds(&mut builder, offset, n, limit));
+    let limit = memory_limit(&mut builder, memory);
+    exprs.push(trap_if_out_of_bounds(&mut builder, dst, n, limit));
+
+    // (if (i32.lt_u (local.tee $relative (i32.sub (i32.const k) $offset)) $n)
+    //   (then (i32.store8 (i32.add $dst $relative) (i32.const byte))))
+    for (k, byte) in value.iter().enumerate() {
+        let k = builder.i32_const(k as i32);
+        let start = builder.local_get(offset);
+        let position = builder.binop(BinaryOp::I32Sub, k, start);
+        let position = builder.local_tee(relative, position);
+        let len = builder.local_get(n);
+        let in_range = builder.binop(BinaryOp::I32LtU, position, len);
+        let store = {
+            let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+            let address = offset_by(&mut block, dst, relative);
+            let byte = block.i32_const(i32::from(*byte));
+            let store = block.store(memory, BYTE_STORE, BYTE, address, byte);
+            block.expr(store);
+            block.id()
+        };
+        let skip = builder.if_else_block(Box::new([]), Box::new([])).id();
+        exprs.push(builder.if_else(in_range, store, skip));
+    }
+    finish(module, builder, params, exprs)
+}
+
+const BYTE: MemArg = MemArg {
+    align: 1,
+    offset: 0,
+};
+const BYTE_LOAD: LoadKind = LoadKind::I32_8 {
+    kind: ExtendedLoad::ZeroExtend,
+};
+const BYTE_STORE: StoreKind = StoreKind::I32_8 { atomic: false };
+
+/// The three `i32` parameters of a helper.
+fn params(module: &mut Module) -> Vec<LocalId> {
+    (0..3).map(|_| module.locals.add(I32)).collect()
+}
+
+fn finish(
+    module: &mut Module,
+    builder: FunctionBuilder,
+    params: Vec<LocalId>,
+    exprs: Vec<ExprId>,
+) -> FunctionId {
+    let ty = module.types.add(&[I32, I32, I32], &[]);
+    builder.finish(ty, params, exprs, module)
+}
+
+/// `(i32.add $base $i)`
+fn offset_by(builder: &mut FunctionBuilder, base: LocalId, i: LocalId) -> ExprId {
+    let base = builder.local_get(base);
+    let i = builder.local_get(i);
+    builder.binop(BinaryOp::I32Add, base, i)
+}
+
+/// The size of `memory` in bytes, as an `i64`.
+fn memory_limit(builder: &mut FunctionBuilder, memory: MemoryId) -> ExprId {
+    let pages = builder.memory_size(memory);
+    let pages = builder.unop(UnaryOp::I64ExtendUI32, pages);
+    let page = builder.i64_const(PAGE as i64);
+    builder.binop(BinaryOp::I64Mul, pages, page)
+}
+
+/// `(if (i64.gt_u (i64.add $offset $len) limit) (then unreachable))`, with
+/// the addition done in 64 bits so that it can't overflow.
+fn trap_if_out_of_bounds(
+    builder: &mut FunctionBuilder,
+    offset: LocalId,
+    len: LocalId,
+    limit: ExprId,
+) -> ExprId {
+    let offset = builder.local_get(offset);
+    let offset = builder.unop(UnaryOp::I64ExtendUI32, offset);
+    let len = builder.local_get(len);
+    let len = builder.unop(UnaryOp::I64ExtendUI32, len);
+    let end = builder.binop(BinaryOp::I64Add, offset, len);
+    let out_of_bounds = builder.binop(BinaryOp::I64GtU, end, limit);
+    let trap = {
+        let mut block = builder.if_else_block(Box::new([]), Box::new([]));
+        let unreachable = block.unreachable();
+        block.expr(unreachable);
+        block.id()
+    };
+    let ok = builder.if_else_block(Box::new([]), Box::new([])).id();
+    builder.if_else(out_of_bounds, trap, ok)
+}
+
+/// Run `body` for each `$i` counting up from 0 to `$n`.
+fn forward_loop(
+    builder: &mut FunctionBuilder,
+    i: LocalId,
+    n: LocalId,
+    body: impl FnOnce(&mut FunctionBuilder) -> ExprId,
+) -> Vec<ExprId> {
+    let zero = builder.i32_const(0);
+    let init = builder.local_set(i, zero);
+    let done = {
+        let mut done = builder.block(Box::new([]), Box::new([]));
+        let done_id = done.id();
+        let repeat = {
+            let mut repeat = done.loop_(Box::new([]));
+            let repeat_id = repeat.id();
+            let a = repeat.local_get(i);
+            let b = repeat.local_get(n);
+            let finished = repeat.binop(BinaryOp::I32Eq, a, b);
+            let exit = repeat.br_if(finished, done_id, Box::new([]));
+            repeat.expr(exit);
+            let expr = body(&mut repeat);
+            repeat.expr(expr);
+            let a = repeat.local_get(i);
+            let b = repeat.i32_const(1);
+            let next = repeat.binop(BinaryOp::I32Add, a, b);
+            let next = repeat.local_set(i, next);
+            repeat.expr(next);
+            let again = repeat.br(repeat_id, Box::new([]));
+            repeat.expr(again);
+            repeat_id
+        };
+        done.expr(repeat.into());
+        done_id
+    };
+    vec![init, done.into()]
+}
+
+/// Run `body` for each `$i` counting down from `$n - 1` to 0.
+fn backward_loop(
+    builder: &mut FunctionBuilder,
+    i: LocalId,
+    n: LocalId,
+    body: impl FnOnce(&mut FunctionBuilder) -> ExprId,
+) -> Vec<ExprId> {
+    let n = builder.local_get(n);
+    let init = builder.local_set(i, n);
+    let done = {
+        let mut done = builder.block(Box::new([]), Box::new([]));
+        let done_id = done.id();
+        let repeat = {
+            let mut repeat = done.loop_(Box::new([]));
+            let repeat_id = repeat.id();
+            let a = repeat.local_get(i);
+            let finished = repeat.unop(UnaryOp::I32Eqz, a);
+            let exit = repeat.br_if(finished, done_id, Box::new([]));
+            repeat.expr(exit);
+            let a = repeat.local_get(i);
+            let b = repeat.i32_const(1);
+            let next = repeat.binop(BinaryOp::I32Sub, a, b);
+            let next = repeat.local_set(i, next);
+            repeat.expr(next);
+            let expr = body(&mut repeat);
+            repeat.expr(expr);
+            let again = repeat.br(repeat_id, Box::new([]));
+            repeat.expr(again);
+            repeat_id
+        };
+        done.expr(repeat.into());
+        done_id
+    };
+    vec![init, done.into()]
+}
+
+struct BulkOps<'a> {
+    func: &'a LocalFunction,
+    ops: Vec<(ExprId, Op)>,
+}
+
+impl<'a> Visitor<'a> for BulkOps<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        let op = match self.func.get(id) {
+            Expr::MemoryCopy(e) => Some(Op::Copy(e.dst, e.src)),
+            Expr::MemoryFill(e) => Some(Op::Fill(e.memory)),
+            Expr::MemoryInit(e) => Some(Op::Init(e.memory, e.data)),
+            Expr::DataDrop(_) => Some(Op::Drop),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.ops.push((id, op));
+        }
+        id.visit(self);
+    }
+}
@@ -0,0 +1,775 @@
+//! Lowering functions and blocks with multiple results for engines without
+//! the multi-value proposal.
+
+use crate::ir::*;
+use crate::{ExportItem, FunctionId, FunctionKind, GlobalId, InitExpr, LocalFunction, MemoryId};
+use crate::{Module, ModuleGlobals, ModuleLocals, Result, TypeId, ValType};
+use failure::bail;
+use std::collections::{HashMap, HashSet};
+
+/// Where lowered functions keep the results after their first one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ExtraResults {
+    /// In mutable globals, one for each type and position among the extra
+    /// results of that type, created as they're needed. Globals of
+    /// references start out as `ref.null`.
+    #[default]
+    Globals,
+    /// In a scratch area of `memory` starting at `address`, with each extra
+    /// result naturally aligned after the previous one. Nothing else may use
+    /// the area.
+    Memory {
+        /// The memory containing the scratch area.
+        memory: MemoryId,
+        /// The address of the scratch area.
+        address: u32,
+    },
+}
+
+/// What to do with imported and exported functions which return multiple
+/// values, whose signatures are seen by the embedder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Boundary {
+    /// Fail without changing the module.
+    #[default]
+    Error,
+    /// Lower them like any other function. The embedder must then pass the
+    /// extra results through the scratch storage itself, which is only
+    /// practical with `ExtraResults::Memory`.
+    ChangeSignature,
+}
+
+/// Options for `lower_multi_value_with`.
+#[derive(Debug, Clone, Default)]
+pub struct LowerMultiValueOptions {
+    /// Where lowered functions keep their extra results.
+    pub extra_results: ExtraResults,
+    /// What to do with imported and exported functions.
+    pub boundary: Boundary,
+}
+
+/// Rewrite every function and block with multiple results into ones with at
+/// most one, returning how many were rewritten.
+///
+/// This is the same as `lower_multi_value_with` with the default options.
+pub fn lower_multi_value(module: &mut Module) -> Result<usize> {
+    lower_multi_value_with(module, &LowerMultiValueOptions::default())
+}
+
+/// Rewrite every function and block with multiple results into ones with at
+/// most one, returning how many were rewritten.
+///
+/// * A function returning `[t0, t1, ...]` returns just `t0`, writing the
+///   other results to the storage chosen by `options.extra_results` right
+///   before it returns. Every call reads them back right after it returns,
+///   so nested and recursive calls don't overwrite each other's results.
+///   The same goes for `call_indirect`.
+/// * A block, loop or `if` with multiple results produces just the first,
+///   with the others written to fresh locals at its end, or by the branches
+///   to it, and read back right after it.
+///
+/// Afterwards the module has no types with multiple results left.
+///
+/// Returns an error, without changing anything, if an imported or exported
+/// function returns multiple values and `options.boundary` is
+/// `Boundary::Error`, if the extra results of a function are references
+/// which can't be kept in the chosen storage, or if any block takes
+/// parameters, since no engine without multi-value support could run the
+/// result anyway. Expressions producing several values must be used as
+/// operands once, not once per value.
+pub fn lower_multi_value_with(
+    module: &mut Module,
+    options: &LowerMultiValueOptions,
+) -> Result<usize> {
+    let exported: HashSet<FunctionId> = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Function(f) => Some(f),
+            _ => None,
+        })
+        .collect();
+    let mut retyped = Vec::new();
+    for func in module.funcs.iter() {
+        let results = module.types.get(func.ty()).results();
+        if results.len() < 2 {
+            continue;
+        }
+        let boundary = match func.kind {
+            FunctionKind::Import(_) => Some("imported"),
+            _ if exported.contains(&func.id()) => Some("exported"),
+            _ => None,
+        };
+        if let (Some(what), Boundary::Error) = (boundary, options.boundary) {
+            bail!(
+                "the {} function `{}` returns multiple values",
+                what,
+                func.name.as_ref().map_or("<unnamed>", |n| n.as_str())
+            );
+        }
+        check_storable(results, options.extra_results)?;
+        retyped.push(func.id());
+    }
+
+    let mut plans = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let plan = Plan::new(module, func, options.extra_results)?;
+        if !plan.is_empty() {
+            plans.push((id, plan));
+        }
+    }
+
+    // Every function, `call_indirect` and block ends up with at most one
+    // result, so the types with more end up unused.
+    let multi: Vec<TypeId> = module
+        .types
+        .iter_with_ids()
+        .filter(|(_, ty)| ty.results().len() > 1)
+        .map(|(id, _)| id)
+        .collect();
+    let mut lowered_types = HashMap::new();
+    for &ty in &multi {
+        let (params, results) = {
+            let ty = module.types.get(ty);
+            (ty.params().to_vec(), ty.results()[..1].to_vec())
+        };
+        let new = match module.types.find(&params, &results) {
+            Some(new) => new,
+            None => module.types.add(&params, &results),
+        };
+        lowered_types.insert(ty, new);
+    }
+
+    let mut storage = Storage {
+        globals: &mut module.globals,
+        locals: &mut module.locals,
+        extra_results: options.extra_results,
+        by_type: HashMap::new(),
+        by_results: HashMap::new(),
+    };
+    let mut lowered = retyped.len();
+    for (id, plan) in plans {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        lowered += plan.lowered;
+        let results = module.types.get(func.ty).results();
+        let rewrite = Rewrite::new(plan, &mut storage, results, &lowered_types);
+        rewrite.function(func);
+    }
+
+    for id in retyped {
+        match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func.ty = lowered_types[&func.ty],
+            FunctionKind::Import(import) => import.ty = lowered_types[&import.ty],
+            FunctionKind::Uninitialized(ty) => *ty = lowered_types[&*ty],
+        }
+    }
+    for ty in multi {
+        module.types.delete(ty);
+    }
+    Ok(lowered)
+}
+
+/// The extra results of functions are kept in globals or memory. Globals of
+/// `funcref` and `externref` can start out as `ref.null`, but references
+/// can't be stored in memory, and typed references have no null to start
+/// out as.
+fn check_storable(results: &[ValType], extra_results: ExtraResults) -> Result<()> {
+    for ty in &results[1..] {
+        let storable = match ty {
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 | ValType::V128 => true,
+            ValType::Funcref | ValType::Externref => extra_results == ExtraResults::Globals,
+            _ => false,
+        };
+        if !storable {
+            bail!(
+                "cannot lower a function returning multiple values including a {}",
+                ty
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Where the extra results of some multi-value expressions are found.
+enum Extras {
+    /// The slots of one of the function's groups of blocks.
+    Group(usize),
+    /// The slots of functions returning these results.
+    Results(Vec<ValType>),
+}
+
+/// What needs rewriting in one function.
+struct Plan {
+    /// Every expression which might need rewriting.
+    sites: Vec<ExprId>,
+    /// The blocks with multiple results, and the group of blocks whose extra
+    /// results share slots because a `br_table` or an `if` might leave them
+    /// in either block.
+    groups: HashMap<BlockId, usize>,
+    /// The results of each group's blocks.
+    group_results: Vec<Vec<ValType>>,
+    /// The group of the function's entry block, whose extra results are the
+    /// function's own.
+    entry_group: Option<usize>,
+    /// The expressions producing multiple values.
+    producers: HashMap<ExprId, Extras>,
+    /// How many values each expression in the body of a block with multiple
+    /// results leaves on the stack, or `None` if it never finishes executing.
+    arities: HashMap<BlockId, Vec<Option<usize>>>,
+    /// How many blocks, loops and `if`s have multiple results.
+    lowered: usize,
+}
+
+impl Plan {
+    fn new(module: &Module, func: &LocalFunction, extra_results: ExtraResults) -> Result<Plan> {
+        let mut sites = Sites {
+            func,
+            sites: Vec::new(),
+            params: false,
+        };
+        dfs_in_order(&mut sites, func, func.entry_block().into());
+        if sites.params {
+            bail!("cannot lower multiple results in a function with blocks taking parameters");
+        }
+        let sites = sites.sites;
+
+        let multi = |block: BlockId| func.block(block).results.len() > 1;
+        let branched = |block: BlockId| multi(block) && func.block(block).kind != BlockKind::Loop;
+        let mut blocks = Vec::new();
+        if multi(func.entry_block()) {
+            blocks.push(func.entry_block());
+        }
+        let mut links = Vec::new();
+        for &id in &sites {
+            match func.get(id) {
+                Expr::Block(_) => {
+                    let block = Block::new_id(id);
+                    if multi(block) {
+                        blocks.push(block);
+                    }
+                }
+                Expr::IfElse(e) if multi(e.consequent) => {
+                    blocks.push(e.consequent);
+                    blocks.push(e.alternative);
+                    links.push((e.consequent, e.alternative));
+                }
+                Expr::BrTable(e) if branched(e.default) => {
+                    for block in e.blocks.iter() {
+                        links.push((e.default, *block));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Union the linked blocks, then number the groups.
+        let mut parents: HashMap<BlockId, BlockId> = blocks.iter().map(|b| (*b, *b)).collect();
+        fn root(parents: &HashMap<BlockId, BlockId>, mut block: BlockId) -> BlockId {
+            while parents[&block] != block {
+                block = parents[&block];
+            }
+            block
+        }
+        for (a, b) in links {
+            let (a, b) = (root(&parents, a), root(&parents, b));
+            parents.insert(b, a);
+        }
+        let mut groups = HashMap::new();
+        let mut group_results = Vec::new();
+        let mut numbers = HashMap::new();
+        for &block in &blocks {
+            let n = *numbers.entry(root(&parents, block)).or_insert_with(|| {
+                group_results.push(func.block(block).results.to_vec());
+                group_results.len() - 1
+            });
+            groups.insert(block, n);
+        }
+        let entry_group = groups.get(&func.entry_block()).cloned();
+
+        let mut producers = HashMap::new();
+        let mut lowered = 0;
+        for &id in &sites {
+            let extras = match func.get(id) {
+                Expr::Call(e) => {
+                    let results = module.types.get(module.funcs.get(e.func).ty()).results();
+                    if results.len() < 2 {
+                        continue;
+                    }
+                    Extras::Results(results.to_vec())
+                }
+                Expr::CallIndirect(e) => {
+                    let results = module.types.get(e.ty).results();
+                    if results.len() < 2 {
+                        continue;
+                    }
+                    check_storable(results, extra_results)?;
+                    Extras::Results(results.to_vec())
+                }
+                Expr::CallRef(e) => {
+                    // The type of the reference would have to be lowered
+                    // along with the function it refers to.
+                    if module.types.get(e.ty).results().len() < 2 {
+                        continue;
+                    }
+                    bail!("cannot lower a `call_ref` returning multiple values");
+                }
+                Expr::Block(e) => match groups.get(&Block::new_id(id)) {
+                    Some(group) => {
+                        // The arms of an `if` are counted along with it.
+                        if e.kind != BlockKind::IfElse {
+                            lowered += 1;
+                        }
+                        Extras::Group(*group)
+                    }
+                    None => continue,
+                },
+                Expr::IfElse(e) => match groups.get(&e.consequent) {
+                    Some(group) => {
+                        lowered += 1;
+                        Extras::Group(*group)
+                    }
+                    None => continue,
+                },
+                Expr::BrIf(e) if branched(e.block) => Extras::Group(groups[&e.block]),
+                _ => continue,
+            };
+            producers.insert(id, extras);
+        }
+        for &id in &sites {
+            if let Expr::WithSideEffects(e) = func.get(id) {
+                if producers.contains_key(&e.value) {
+                    bail!("cannot lower multiple values produced with side effects");
+                }
+            }
+        }
+
+        let mut arities = HashMap::new();
+        for &block in groups.keys() {
+            let mut arity = Vec::new();
+            for expr in &func.block(block).exprs {
+                arity.push(match producers.get(expr) {
+                    Some(Extras::Group(group)) => Some(group_results[*group].len()),
+                    Some(Extras::Results(results)) => Some(results.len()),
+                    None => func
+                        .expr_type(module, *expr)?
+                        .map(|(_, results)| results.len()),
+                });
+            }
+            arities.insert(block, arity);
+        }
+
+        Ok(Plan {
+            sites,
+            groups,
+            group_results,
+            entry_group,
+            producers,
+            arities,
+            lowered,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.groups.is_empty() && self.producers.is_empty()
+    }
+}
+
+/// Creates the slots holding extra results.
+struct Storage<'a> {
+    globals: &'a mut ModuleGlobals,
+    locals: &'a mut ModuleLocals,
+    extra_results: ExtraResults,
+    /// The global for each type and position among the extra results of
+    /// that type.
+    by_type: HashMap<(ValType, usize), GlobalId>,
+    /// The slots of functions returning these results.
+    by_results: HashMap<Vec<ValType>, Vec<Slot>>,
+}
+
+impl Storage<'_> {
+    /// The slots of functions returning `results`.
+    fn results(&mut self, results: &[ValType]) -> Vec<Slot> {
+        if let Some(slots) = self.by_results.get(results) {
+            return slots.clone();
+        }
+        let mut slots = Vec::new();
+        let mut offset: u32 = 0;
+        for (i, ty) in results.iter().enumerate().skip(1) {
+            let slot = match self.extra_results {
+                ExtraResults::Globals => {
+                    let position = results[1..i].iter().filter(|t| *t == ty).count();
+                    let globals = &mut self.globals;
+                    let global = *self
+                        .by_type
+                        .entry((*ty, position))
+                        .or_insert_with(|| globals.add_local(*ty, true, initial(*ty)));
+                    Slot::Global(global)
+                }
+                ExtraResults::Memory { memory, address } => {
+                    let size = size(*ty);
+                    offset = offset.div_ceil(size) * size;
+                    let slot = Slot::Memory(memory, address + offset, *ty);
+                    offset += size;
+                    slot
+                }
+            };
+            slots.push(slot);
+        }
+        self.by_results.insert(results.to_vec(), slots.clone());
+        slots
+    }
+
+    /// Fresh locals for the extra results of a group of blocks.
+    fn locals(&mut self, results: &[ValType]) -> Vec<Slot> {
+        results[1..]
+            .iter()
+            .map(|ty| Slot::Local(self.locals.add(*ty)))
+            .collect()
+    }
+}
+
+/// Where one extra result is kept between being produced and read back.
+#[derive(Debug, Copy, Clone)]
+enum Slot {
+    Local(LocalId),
+    Global(GlobalId),
+    Memory(MemoryId, u32, ValType),
+}
+
+impl Slot {
+    fn read(self, func: &mut LocalFunction) -> ExprId {
+        match self {
+            Slot::Local(local) => func.alloc(LocalGet { local }).into(),
+            Slot::Global(global) => func.alloc(GlobalGet { global }).into(),
+            Slot::Memory(memory, offset, ty) => {
+                let address = func.alloc(Const {
+                    value: Value::I32(0),
+                });
+                let kind = match ty {
+                    ValType::I32 => LoadKind::I32 { atomic: false },
+                    ValType::I64 => LoadKind::I64 { atomic: false },
+                    ValType::F32 => LoadKind::F32,
+                    ValType::F64 => LoadKind::F64,
+                    _ => LoadKind::V128,
+                };
+                func.alloc(Load {
+                    memory,
+                    kind,
+                    arg: mem_arg(ty, offset),
+                    address: address.into(),
+                })
+                .into()
+            }
+        }
+    }
+
+    fn write(self, func: &mut LocalFunction, value: ExprId) -> ExprId {
+        match self {
+            Slot::Local(local) => func.alloc(LocalSet { local, value }).into(),
+            Slot::Global(global) => func.alloc(GlobalSet { global, value }).into(),
+            Slot::Memory(memory, offset, ty) => {
+                let address = func.alloc(Const {
+                    value: Value::I32(0),
+                });
+                let kind = match ty {
+                    ValType::I32 => StoreKind::I32 { atomic: false },
+                    ValType::I64 => StoreKind::I64 { atomic: false },
+                    ValType::F32 => StoreKind::F32,
+                    ValType::F64 => StoreKind::F64,
+                    _ => StoreKind::V128,
+                };
+                func.alloc(Store {
+                    memory,
+                    kind,
+                    arg: mem_arg(ty, offset),
+                    address: address.into(),
+                    value,
+                })
+                .into()
+            }
+        }
+    }
+}
+
+fn size(ty: ValType) -> u32 {
+    match ty {
+        ValType::I32 | ValType::F32 => 4,
+        ValType::I64 | ValType::F64 => 8,
+        _ => 16,
+    }
+}
+
+fn mem_arg(ty: ValType, offset: u32) -> MemArg {
+    MemArg {
+        align: size(ty),
+        offset,
+    }
+}
+
+/// The initial value of a global keeping extra results of the type `ty`.
+fn initial(ty: ValType) -> InitExpr {
+    InitExpr::Value(match ty {
+        ValType::I32 => Value::I32(0),
+        ValType::I64 => Value::I64(0),
+        ValType::F32 => Value::F32(0.0),
+        ValType::F64 => Value::F64(0.0),
+        ValType::Funcref => return InitExpr::RefNull(RefType::Funcref),
+        ValType::Externref => return InitExpr::RefNull(RefType::Externref),
+        _ => Value::V128(0),
+    })
+}
+
+/// Rewrites one function according to its plan.
+struct Rewrite<'a> {
+    plan: Plan,
+    /// The type with just the first result of each type with multiple.
+    lowered_types: &'a HashMap<TypeId, TypeId>,
+    /// The slots of the function's own extra results, if it has any.
+    results: Vec<Slot>,
+    /// The slots of each group of blocks.
+    groups: Vec<Vec<Slot>>,
+    /// The slots to read back after each expression producing multiple
+    /// values.
+    reads: HashMap<ExprId, Vec<Slot>>,
+}
+
+impl<'a> Rewrite<'a> {
+    fn new(
+        plan: Plan,
+        storage: &mut Storage,
+        results: &[ValType],
+        lowered_types: &'a HashMap<TypeId, TypeId>,
+    ) -> Rewrite<'a> {
+        let results = if results.len() > 1 {
+            storage.results(results)
+        } else {
+            Vec::new()
+        };
+        let groups: Vec<_> = plan
+            .group_results
+            .iter()
+            .enumerate()
+            .map(|(i, group)| {
+                if Some(i) == plan.entry_group {
+                    results.clone()
+                } else {
+                    storage.locals(group)
+                }
+            })
+            .collect();
+        // Go through the sites in order, so that globals are created in a
+        // deterministic order.
+        let mut reads = HashMap::new();
+        for id in &plan.sites {
+            let slots = match plan.producers.get(id) {
+                Some(Extras::Group(group)) => groups[*group].clone(),
+                Some(Extras::Results(results)) => storage.results(results),
+                None => continue,
+            };
+            reads.insert(*id, slots);
+        }
+        Rewrite {
+            plan,
+            lowered_types,
+            results,
+            groups,
+            reads,
+        }
+    }
+
+    fn function(&self, func: &mut LocalFunction) {
+        self.block(func, func.entry_block());
+        for &id in &self.plan.sites {
+            match func.get(id).clone() {
+                // This includes the arms of an `if`, which are visited as
+                // sites of their own.
+                Expr::Block(_) => self.block(func, Block::new_id(id)),
+                Expr::Call(e) => {
+                    let args = self.operands(func, &e.args);
+                    if let Expr::Call(e) = func.get_mut(id) {
+                        e.args = args.into_boxed_slice();
+                    }
+                }
+                Expr::CallIndirect(e) => {
+                    let args = self.operands(func, &e.args);
+                    if let Expr::CallIndirect(e) = func.get_mut(id) {
+                        e.args = args.into_boxed_slice();
+                        if let Some(ty) = self.lowered_types.get(&e.ty) {
+                            e.ty = *ty;
+                        }
+                    }
+                }
+                Expr::Return(e) => {
+                    let values = self.operands(func, &e.values);
+                    let values = self.branch(func, values, &self.results);
+                    if let Expr::Return(e) = func.get_mut(id) {
+                        e.values = values.into_boxed_slice();
+                    }
+                }
+                Expr::Br(e) => {
+                    let args = self.operands(func, &e.args);
+                    let args = self.branch_to(func, args, e.block);
+                    if let Expr::Br(e) = func.get_mut(id) {
+                        e.args = args.into_boxed_slice();
+                    }
+                }
+                Expr::BrIf(e) => {
+                    let args = self.operands(func, &e.args);
+                    let args = self.branch_to(func, args, e.block);
+                    if let Expr::BrIf(e) = func.get_mut(id) {
+                        e.args = args.into_boxed_slice();
+                    }
+                }
+                Expr::BrTable(e) => {
+                    let args = self.operands(func, &e.args);
+                    let args = self.branch_to(func, args, e.default);
+                    if let Expr::BrTable(e) = func.get_mut(id) {
+                        e.args = args.into_boxed_slice();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read back the extra values after each operand producing multiple
+    /// values.
+    fn operands(&self, func: &mut LocalFunction, operands: &[ExprId]) -> Vec<ExprId> {
+        let mut spliced = Vec::with_capacity(operands.len());
+        for &operand in operands {
+            spliced.push(operand);
+            if let Some(slots) = self.reads.get(&operand) {
+                spliced.extend(slots.iter().map(|slot| slot.read(func)));
+            }
+        }
+        spliced
+    }
+
+    fn branch_to(
+        &self,
+        func: &mut LocalFunction,
+        args: Vec<ExprId>,
+        block: BlockId,
+    ) -> Vec<ExprId> {
+        if func.block(block).kind == BlockKind::Loop {
+            return args;
+        }
+        match self.plan.groups.get(&block) {
+            Some(group) => self.branch(func, args, &self.groups[*group]),
+            None => args,
+        }
+    }
+
+    /// Keep the first value on the stack and write the others to `slots`,
+    /// evaluating them in their original order.
+    fn branch(
+        &self,
+        func: &mut LocalFunction,
+        mut values: Vec<ExprId>,
+        slots: &[Slot],
+    ) -> Vec<ExprId> {
+        if slots.is_empty() || values.len() < 2 {
+            return values;
+        }
+        let first = values.remove(0);
+        let after = values
+            .into_iter()
+            .zip(slots)
+            .map(|(value, slot)| slot.write(func, value))
+            .collect();
+        let expr = func.alloc(WithSideEffects {
+            before: Vec::new(),
+            value: first,
+            after,
+        });
+        vec![expr.into()]
+    }
+
+    /// Read back extra values in the block's body, and if it has multiple
+    /// results itself, write all but the first to its slots.
+    fn block(&self, func: &mut LocalFunction, block: BlockId) {
+        let exprs = std::mem::take(&mut func.block_mut(block).exprs);
+        let (slots, arities): (&[Slot], _) = match self.plan.groups.get(&block) {
+            Some(group) => (&self.groups[*group][..], &self.plan.arities[&block]),
+            None => {
+                let exprs = self.operands(func, &exprs);
+                func.block_mut(block).exprs = exprs;
+                return;
+            }
+        };
+
+        // Find which of the block's results each expression at the end of
+        // its body produces first.
+        let mut first = vec![None; exprs.len()];
+        let mut next = slots.len() + 1;
+        for (i, arity) in arities.iter().enumerate().rev() {
+            match arity {
+                Some(n) if *n <= next => {
+                    next -= n;
+                    if *n > 0 {
+                        first[i] = Some(next);
+                    }
+                }
+                _ => break,
+            }
+            if next == 0 {
+                break;
+            }
+        }
+
+        let mut body = Vec::with_capacity(exprs.len());
+        for (expr, first) in exprs.into_iter().zip(first) {
+            let values = self.operands(func, &[expr]);
+            match first {
+                Some(first) => {
+                    for (i, value) in values.into_iter().enumerate() {
+                        body.push(match first + i {
+                            0 => value,
+                            n => slots[n - 1].write(func, value),
+                        });
+                    }
+                }
+                None => body.extend(values),
+            }
+        }
+
+        let block = func.block_mut(block);
+        block.exprs = body;
+        block.results = block.results[..1].to_vec().into_boxed_slice();
+    }
+}
+
+/// Collects the expressions which might need rewriting.
+struct Sites<'a> {
+    func: &'a LocalFunction,
+    sites: Vec<ExprId>,
+    /// Whether any block takes parameters.
+    params: bool,
+}
+
+impl<'a> Visitor<'a> for Sites<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        match self.func.get(id) {
+            Expr::Block(e) => self.params |= !e.params.is_empty(),
+            Expr::IfElse(e) => self.params |= !self.func.block(e.consequent).params.is_empty(),
+            Expr::Call(_)
+            | Expr::CallIndirect(_)
+            | Expr::CallRef(_)
+            | Expr::Return(_)
+            | Expr::Br(_)
+            | Expr::BrIf(_)
+            | Expr::BrTable(_)
+            | Expr::WithSideEffects(_) => {}
+            _ => return id.visit(self),
+        }
+        self.sites.push(id);
+        id.visit(self);
+    }
+}
@@ -0,0 +1,88 @@
+//! Lowering the sign-extension operators for engines which don't support
+//! them.
+
+use crate::ir::*;
+use crate::{FunctionKind, LocalFunction, Module};
+
+/// Rewrite every sign-extension operator, such as `i32.extend8_s`, into an
+/// equivalent pair of shifts, returning how many were rewritten.
+///
+/// For example `(i32.extend8_s x)` becomes
+/// `(i32.shr_s (i32.shl x (i32.const 24)) (i32.const 24))`.
+pub fn lower_sign_ext(module: &mut Module) -> usize {
+    let mut found = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut extends = Extends {
+            func,
+            extends: Vec::new(),
+        };
+        dfs_in_order(&mut extends, func, func.entry_block().into());
+        if !extends.extends.is_empty() {
+            found.push((id, extends.extends));
+        }
+    }
+
+    let mut lowered = 0;
+    for (id, extends) in found {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        for extend in extends {
+            let (op, expr) = match func.get(extend) {
+                Expr::Unop(e) => (e.op, e.expr),
+                _ => unreachable!(),
+            };
+            let (shl, shr_s, by) = shift(op).unwrap();
+            let amount = func.alloc(Const { value: by });
+            let shifted = func.alloc(Binop {
+                op: shl,
+                lhs: expr,
+                rhs: amount.into(),
+            });
+            let amount = func.alloc(Const { value: by });
+            *func.get_mut(extend) = Expr::Binop(Binop {
+                op: shr_s,
+                lhs: shifted.into(),
+                rhs: amount.into(),
+            });
+            lowered += 1;
+        }
+    }
+    lowered
+}
+
+/// The shifts, and the amount to shift by, which implement a sign-extension
+/// operator.
+fn shift(op: UnaryOp) -> Option<(BinaryOp, BinaryOp, Value)> {
+    use BinaryOp::*;
+    let (shl, shr_s, shift) = match op {
+        UnaryOp::I32Extend8S => (I32Shl, I32ShrS, Value::I32(24)),
+        UnaryOp::I32Extend16S => (I32Shl, I32ShrS, Value::I32(16)),
+        UnaryOp::I64Extend8S => (I64Shl, I64ShrS, Value::I64(56)),
+        UnaryOp::I64Extend16S => (I64Shl, I64ShrS, Value::I64(48)),
+        UnaryOp::I64Extend32S => (I64Shl, I64ShrS, Value::I64(32)),
+        _ => return None,
+    };
+    Some((shl, shr_s, shift))
+}
+
+struct Extends<'a> {
+    func: &'a LocalFunction,
+    extends: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Extends<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Expr::Unop(e) = self.func.get(id) {
+            if shift(e.op).is_some() {
+                self.extends.push(id);
+            }
+        }
+        id.visit(self);
+    }
+}
@@ -0,0 +1,241 @@
+//! Lowering the non-trapping float-to-int conversions for engines which don't
+//! support them.
+
+use crate::ir::*;
+use crate::{FunctionKind, LocalFunction, Module, ScratchLocals, ValType};
+
+/// Rewrite every saturating float-to-int conversion, such as
+/// `i32.trunc_sat_f32_s`, into comparisons guarding the equivalent trapping
+/// conversion, returning how many were rewritten.
+///
+/// For example `(i32.trunc_sat_f32_s x)` becomes
+///
+/// ```wat
+/// (if (result i32) (f32.ne (local.tee $t x) (local.get $t))
+///   (then (i32.const 0))
+///   (else
+///     (if (result i32) (f32.ge (local.get $t) (f32.const 0x1p31))
+///       (then (i32.const 0x7fffffff))
+///       (else
+///         (if (result i32) (f32.lt (local.get $t) (f32.const -0x1p31))
+///           (then (i32.const 0x80000000))
+///           (else (i32.trunc_f32_s (local.get $t))))))))
+/// ```
+///
+/// so that the trapping conversion only runs on values it can represent. One
+/// scratch local per float type is added to the module, and only if something
+/// is rewritten.
+pub fn lower_trunc_sat(module: &mut Module) -> usize {
+    let mut found = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut truncs = Truncs {
+            func,
+            truncs: Vec::new(),
+        };
+        dfs_in_order(&mut truncs, func, func.entry_block().into());
+        if !truncs.truncs.is_empty() {
+            found.push((id, truncs.truncs));
+        }
+    }
+
+    let mut scratch = ScratchLocals::new();
+    let mut lowered = 0;
+    for (id, truncs) in found {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        for trunc in truncs {
+            let (op, value) = match func.get(trunc) {
+                Expr::Unop(e) => (e.op, e.expr),
+                _ => unreachable!(),
+            };
+            let l = Lowering::new(op).unwrap();
+
+            // The operand is fully evaluated before it is stored in the
+            // scratch local, and the local is dead once this expression is
+            // done, so nested conversions can share it.
+            let tmp = scratch.get(&mut module.locals, l.float);
+
+            let x = func.alloc(LocalGet { local: tmp });
+            let bound = func.alloc(Const { value: l.lower });
+            let below = func.alloc(Binop {
+                op: l.below,
+                lhs: x.into(),
+                rhs: bound.into(),
+            });
+            let min = func.alloc(Const { value: l.min });
+            let x = func.alloc(LocalGet { local: tmp });
+            let in_range = func.alloc(Unop {
+                op: l.trunc,
+                expr: x.into(),
+            });
+            let low = if_else(func, l.result, below.into(), min.into(), in_range.into());
+            let low = func.alloc(low);
+
+            let x = func.alloc(LocalGet { local: tmp });
+            let bound = func.alloc(Const { value: l.upper });
+            let above = func.alloc(Binop {
+                op: l.ge,
+                lhs: x.into(),
+                rhs: bound.into(),
+            });
+            let max = func.alloc(Const { value: l.max });
+            let high = if_else(func, l.result, above.into(), max.into(), low.into());
+            let high = func.alloc(high);
+
+            let tee = func.alloc(LocalTee { local: tmp, value });
+            let x = func.alloc(LocalGet { local: tmp });
+            let nan = func.alloc(Binop {
+                op: l.ne,
+                lhs: tee.into(),
+                rhs: x.into(),
+            });
+            let zero = func.alloc(Const { value: l.zero });
+            let expr = if_else(func, l.result, nan.into(), zero.into(), high.into());
+            *func.get_mut(trunc) = Expr::IfElse(expr);
+
+            scratch.release(&module.locals, tmp);
+            lowered += 1;
+        }
+    }
+    lowered
+}
+
+/// An `if` producing a `ty`, choosing between two single expressions.
+fn if_else(
+    func: &mut LocalFunction,
+    ty: ValType,
+    condition: ExprId,
+    consequent: ExprId,
+    alternative: ExprId,
+) -> IfElse {
+    let mut arm = |expr| {
+        let mut block = Block::new(BlockKind::IfElse, Box::new([]), Box::new([ty]));
+        block.exprs.push(expr);
+        func.alloc(block)
+    };
+    let consequent = arm(consequent);
+    let alternative = arm(alternative);
+    IfElse {
+        condition,
+        consequent,
+        alternative,
+    }
+}
+
+/// Everything needed to lower one saturating conversion.
+struct Lowering {
+    /// The type being converted from.
+    float: ValType,
+    /// The type being converted to.
+    result: ValType,
+    /// The trapping conversion.
+    trunc: UnaryOp,
+    ne: BinaryOp,
+    ge: BinaryOp,
+    /// The comparison with `lower` which is true for inputs that saturate to
+    /// `min`.
+    below: BinaryOp,
+    /// The smallest input which saturates to `max`.
+    upper: Value,
+    lower: Value,
+    max: Value,
+    min: Value,
+    zero: Value,
+}
+
+impl Lowering {
+    fn new(op: UnaryOp) -> Option<Lowering> {
+        use crate::ty::ValType::*;
+        use UnaryOp::*;
+        let (float, result, trunc, signed) = match op {
+            I32TruncSSatF32 => (F32, I32, I32TruncSF32, true),
+            I32TruncUSatF32 => (F32, I32, I32TruncUF32, false),
+            I32TruncSSatF64 => (F64, I32, I32TruncSF64, true),
+            I32TruncUSatF64 => (F64, I32, I32TruncUF64, false),
+            I64TruncSSatF32 => (F32, I64, I64TruncSF32, true),
+            I64TruncUSatF32 => (F32, I64, I64TruncUF32, false),
+            I64TruncSSatF64 => (F64, I64, I64TruncSF64, true),
+            I64TruncUSatF64 => (F64, I64, I64TruncUF64, false),
+            _ => return None,
+        };
+        let (ne, ge, lt, le) = match float {
+            F32 => (
+                BinaryOp::F32Ne,
+                BinaryOp::F32Ge,
+                BinaryOp::F32Lt,
+                BinaryOp::F32Le,
+            ),
+            _ => (
+                BinaryOp::F64Ne,
+                BinaryOp::F64Ge,
+                BinaryOp::F64Lt,
+                BinaryOp::F64Le,
+            ),
+        };
+        let (bits, max, min, zero) = match (result, signed) {
+            (I32, true) => (
+                32,
+                Value::I32(i32::MAX),
+                Value::I32(i32::MIN),
+                Value::I32(0),
+            ),
+            (I32, false) => (32, Value::I32(-1), Value::I32(0), Value::I32(0)),
+            (_, true) => (
+                64,
+                Value::I64(i64::MAX),
+                Value::I64(i64::MIN),
+                Value::I64(0),
+            ),
+            (_, false) => (64, Value::I64(-1), Value::I64(0), Value::I64(0)),
+        };
+        // Powers of two are exactly representable in both float types.
+        let constant = |x: f64| match float {
+            F32 => Value::F32(x as f32),
+            _ => Value::F64(x),
+        };
+        // Inputs in `(-1, 0]` truncate to zero, so unsigned conversions only
+        // saturate at or below -1. Signed conversions saturate below the
+        // minimum, but anything in `(min - 1, min]` truncates to it anyway.
+        let (below, upper, lower) = if signed {
+            let half = 2f64.powi(bits - 1);
+            (lt, constant(half), constant(-half))
+        } else {
+            (le, constant(2f64.powi(bits)), constant(-1.0))
+        };
+        Some(Lowering {
+            float,
+            result,
+            trunc,
+            ne,
+            ge,
+            below,
+            upper,
+            lower,
+            max,
+            min,
+            zero,
+        })
+    }
+}
+
+struct Truncs<'a> {
+    func: &'a LocalFunction,
+    truncs: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Truncs<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Expr::Unop(e) = self.func.get(id) {
+            if Lowering::new(e.op).is_some() {
+                self.truncs.push(id);
+            }
+        }
+        id.visit(self);
+    }
+}
@@ -0,0 +1,238 @@
+//! Running a pipeline of passes over a module.
+
+use crate::passes::{gc, validate, SimplifyBranches};
+use crate::{Module, Result};
+use failure::ResultExt;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A transformation or analysis that can be run by a `PassManager`.
+pub trait Pass {
+    /// The name of this pass, used in diagnostics.
+    fn name(&self) -> &str;
+
+    /// Run this pass over the given module.
+    fn run(&mut self, module: &mut Module) -> Result<PassStats>;
+}
+
+impl<P: Pass + ?Sized> Pass for Box<P> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        (**self).run(module)
+    }
+}
+
+/// What a single run of a pass did to a module.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PassStats {
+    /// Whether the pass changed the module at all.
+    ///
+    /// Passes which iterate to a fixpoint stop once none of them report a
+    /// change.
+    pub changed: bool,
+}
+
+/// What running a pass in a `PassManager` pipeline did.
+#[derive(Clone, Debug)]
+pub struct PassReport {
+    /// The name of the pass.
+    pub name: String,
+    /// The combined stats of every time the pass was run.
+    pub stats: PassStats,
+    /// How many times the pass was run.
+    pub runs: usize,
+    /// How long the pass took, across all of its runs, if timing is enabled.
+    pub duration: Option<Duration>,
+}
+
+/// Get one of walrus's built-in passes by name.
+///
+/// The available passes are:
+///
+/// * `gc`: see `walrus::passes::gc`.
+/// * `simplify-branches`: see `walrus::passes::simplify_branches`.
+pub fn builtin(name: &str) -> Option<Box<dyn Pass>> {
+    match name {
+        "gc" => Some(Box::new(gc::Gc)),
+        "simplify-branches" => Some(Box::new(SimplifyBranches)),
+        _ => None,
+    }
+}
+
+enum Step {
+    Single(Box<dyn Pass>),
+    Fixpoint(Vec<Box<dyn Pass>>),
+}
+
+/// Runs a sequence of passes over a module.
+///
+/// Each pass is run in the order it was added. Groups of cleanup passes that
+/// enable each other can be added with `add_fixpoint`, in which case the
+/// whole group is run repeatedly until none of its passes change the module.
+#[derive(Default)]
+pub struct PassManager {
+    steps: Vec<Step>,
+    time_passes: bool,
+    validate: bool,
+    max_iterations: Option<usize>,
+}
+
+impl fmt::Debug for PassManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self
+            .steps
+            .iter()
+            .map(|step| match step {
+                Step::Single(pass) => pass.name().to_string(),
+                Step::Fixpoint(passes) => {
+                    let names = passes.iter().map(|p| p.name()).collect::<Vec<_>>();
+                    format!("fixpoint({})", names.join(", "))
+                }
+            })
+            .collect::<Vec<_>>();
+        f.debug_struct("PassManager")
+            .field("steps", &names)
+            .field("time_passes", &self.time_passes)
+            .field("validate", &self.validate)
+            .field("max_iterations", &self.max_iterations)
+            .finish()
+    }
+}
+
+impl PassManager {
+    /// Construct a new, empty pipeline.
+    pub fn new() -> PassManager {
+        PassManager::default()
+    }
+
+    /// Add a pass to the end of this pipeline.
+    pub fn add(&mut self, pass: Box<dyn Pass>) -> &mut PassManager {
+        self.steps.push(Step::Single(pass));
+        self
+    }
+
+    /// Add a group of passes to the end of this pipeline, which are run in
+    /// order over and over until none of them change the module.
+    pub fn add_fixpoint(&mut self, passes: Vec<Box<dyn Pass>>) -> &mut PassManager {
+        self.steps.push(Step::Fixpoint(passes));
+        self
+    }
+
+    /// Sets whether the time taken by each pass is measured and reported.
+    ///
+    /// This is `false` by default.
+    pub fn time_passes(&mut self, time: bool) -> &mut PassManager {
+        self.time_passes = time;
+        self
+    }
+
+    /// Sets whether the module is validated after every pass, so that an
+    /// invalid module is blamed on the pass that produced it.
+    ///
+    /// This is `false` by default.
+    pub fn validate_between_passes(&mut self, validate: bool) -> &mut PassManager {
+        self.validate = validate;
+        self
+    }
+
+    /// Sets the maximum number of times a fixpoint group is run.
+    ///
+    /// By default there is no limit.
+    pub fn max_iterations(&mut self, max: Option<usize>) -> &mut PassManager {
+        self.max_iterations = max;
+        self
+    }
+
+    /// Run every pass in this pipeline over the given module, returning a
+    /// report for each pass in the order they were added.
+    pub fn run(&mut self, module: &mut Module) -> Result<Vec<PassReport>> {
+        let mut reports = Vec::new();
+        let mut steps = std::mem::take(&mut self.steps);
+        let result = self.run_steps(&mut steps, module, &mut reports);
+        self.steps = steps;
+        result?;
+        Ok(reports)
+    }
+
+    fn run_steps(
+        &self,
+        steps: &mut [Step],
+        module: &mut Module,
+        reports: &mut Vec<PassReport>,
+    ) -> Result<()> {
+        for step in steps {
+            match step {
+                Step::Single(pass) => {
+                    let mut report = self.report(&**pass);
+                    self.run_pass(&mut **pass, module, &mut report)?;
+                    reports.push(report);
+                }
+                Step::Fixpoint(passes) => {
+                    let mut group = passes.iter().map(|p| self.report(&**p)).collect::<Vec<_>>();
+                    let mut iterations = 0;
+                    loop {
+                        if self.max_iterations.is_some_and(|max| iterations >= max) {
+                            log::debug!("fixpoint group hit the iteration limit");
+                            break;
+                        }
+                        iterations += 1;
+                        let mut changed = false;
+                        for (pass, report) in passes.iter_mut().zip(&mut group) {
+                            changed |= self.run_pass(&mut **pass, module, report)?;
+                        }
+                        if !changed {
+                            break;
+                        }
+                    }
+                    reports.extend(group);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&self, pass: &dyn Pass) -> PassReport {
+        PassReport {
+            name: pass.name().to_string(),
+            stats: PassStats::default(),
+            runs: 0,
+            duration: if self.time_passes {
+                Some(Duration::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Run a single pass, recording what it did in `report` and returning
+    /// whether it changed the module.
+    fn run_pass(
+        &self,
+        pass: &mut dyn Pass,
+        module: &mut Module,
+        report: &mut PassReport,
+    ) -> Result<bool> {
+        log::debug!("running pass `{}`", pass.name());
+        let start = Instant::now();
+        let stats = pass
+            .run(module)
+            .with_context(|_| format!("pass `{}` failed", pass.name()))?;
+        if let Some(duration) = &mut report.duration {
+            let elapsed = start.elapsed();
+            log::debug!("pass `{}` took {:?}", pass.name(), elapsed);
+            *duration += elapsed;
+        }
+        report.runs += 1;
+        report.stats.changed |= stats.changed;
+
+        if self.validate {
+            validate::run(module).with_context(|_| {
+                format!("module failed to validate after pass `{}`", pass.name())
+            })?;
+        }
+        Ok(stats.changed)
+    }
+}
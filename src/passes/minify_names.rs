@@ -0,0 +1,272 @@
+//! Replacing the names of functions, locals and exports with short generated
+//! ones, and restoring them again.
+
+use crate::{Module, Result};
+use failure::bail;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+/// Options for `minify_names`.
+#[derive(Debug, Clone)]
+pub struct MinifyOptions {
+    /// Whether exports are renamed too.
+    ///
+    /// This changes the module's interface, so whatever instantiates it has
+    /// to use the renamed exports, or have them restored with
+    /// `apply_name_map` first. Defaults to `false`.
+    pub rename_exports: bool,
+
+    /// Whether the `name` section is emitted, with the minified names.
+    ///
+    /// When it's dropped, the minified names of functions and locals only
+    /// exist in the returned `NameMap`, and can't be restored after the
+    /// emitted module is parsed again. Defaults to `true`.
+    pub keep_name_section: bool,
+
+    /// The characters generated names are made of. Defaults to the lowercase
+    /// ASCII letters.
+    pub alphabet: String,
+}
+
+impl Default for MinifyOptions {
+    fn default() -> MinifyOptions {
+        MinifyOptions {
+            rename_exports: false,
+            keep_name_section: true,
+            alphabet: "abcdefghijklmnopqrstuvwxyz".to_string(),
+        }
+    }
+}
+
+/// The original names of items renamed by `minify_names`, keyed by the names
+/// they were given.
+///
+/// A map is written out, one name per line, with its `Display`
+/// implementation, and read back in with its `FromStr` implementation. Each
+/// line is the kind of item (`func`, `local` or `export`), the minified name
+/// and the original name, separated by tabs. Tabs, newlines and backslashes
+/// in names are escaped with backslashes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NameMap {
+    /// The original names of functions.
+    pub functions: BTreeMap<String, String>,
+    /// The original names of locals.
+    pub locals: BTreeMap<String, String>,
+    /// The original names of exports.
+    pub exports: BTreeMap<String, String>,
+}
+
+impl NameMap {
+    /// Is this map empty?
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.locals.is_empty() && self.exports.is_empty()
+    }
+
+    fn kinds(&self) -> [(&'static str, &BTreeMap<String, String>); 3] {
+        [
+            ("func", &self.functions),
+            ("local", &self.locals),
+            ("export", &self.exports),
+        ]
+    }
+}
+
+impl fmt::Display for NameMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (kind, names) in self.kinds().iter() {
+            for (minified, original) in names.iter() {
+                writeln!(f, "{}\t{}\t{}", kind, escape(minified), escape(original))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for NameMap {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<NameMap> {
+        let mut map = NameMap::default();
+        for (i, line) in s.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let (kind, minified, original) = match fields[..] {
+                [kind, minified, original] => (kind, unescape(minified)?, unescape(original)?),
+                _ => bail!("line {} of name map doesn't have three fields", i + 1),
+            };
+            let names = match kind {
+                "func" => &mut map.functions,
+                "local" => &mut map.locals,
+                "export" => &mut map.exports,
+                _ => bail!(
+                    "unknown kind of name `{}` on line {} of name map",
+                    kind,
+                    i + 1
+                ),
+            };
+            if names.insert(minified, original).is_some() {
+                bail!("duplicate {} name on line {} of name map", kind, i + 1);
+            }
+        }
+        Ok(map)
+    }
+}
+
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(name: &str) -> Result<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some(c) => bail!("unknown escape `\\{}` in name map", c),
+            None => bail!("name in name map ends with a backslash"),
+        });
+    }
+    Ok(unescaped)
+}
+
+/// Generates distinct names from an alphabet: all the one-character names in
+/// order, then all the two-character ones, and so on.
+struct Names {
+    alphabet: Vec<char>,
+    next: u64,
+}
+
+impl Names {
+    fn new(alphabet: &str) -> Result<Names> {
+        let alphabet = alphabet.chars().collect::<Vec<_>>();
+        if alphabet.is_empty() {
+            bail!("the alphabet for minified names is empty");
+        }
+        let mut seen = HashSet::new();
+        if let Some(c) = alphabet.iter().find(|c| !seen.insert(**c)) {
+            bail!(
+                "`{}` appears more than once in the alphabet for minified names",
+                c
+            );
+        }
+        Ok(Names { alphabet, next: 0 })
+    }
+
+    fn next(&mut self) -> String {
+        // Bijective base-n numbering, so every name is generated exactly
+        // once.
+        let base = self.alphabet.len() as u64;
+        let mut n = self.next;
+        self.next += 1;
+        let mut name = Vec::new();
+        loop {
+            name.push(self.alphabet[(n % base) as usize]);
+            if n < base {
+                break;
+            }
+            n = n / base - 1;
+        }
+        name.iter().rev().collect()
+    }
+}
+
+/// Replace the name of every named function and local, and optionally the
+/// name of every export, with a short generated name, returning a map from
+/// the generated names back to the original ones.
+///
+/// Generated names are distinct from each other for each kind of item, even
+/// across different functions' locals, so the returned map can be used to
+/// restore the original names with `apply_name_map`, either to this module or
+/// to one parsed from its emitted wasm as long as the `name` section was
+/// kept. Unnamed functions and locals stay unnamed. Globals don't have names
+/// in walrus, so they're left alone.
+///
+/// # Errors
+///
+/// Returns an error, leaving the module unchanged, if `options.alphabet` is
+/// empty or contains a character more than once.
+pub fn minify_names(module: &mut Module, options: &MinifyOptions) -> Result<NameMap> {
+    let mut map = NameMap::default();
+
+    let mut names = Names::new(&options.alphabet)?;
+    for func in module.funcs.iter_mut() {
+        if let Some(name) = &mut func.name {
+            let minified = names.next();
+            map.functions
+                .insert(minified.clone(), mem::replace(name, minified));
+        }
+    }
+
+    let mut names = Names::new(&options.alphabet)?;
+    for local in module.locals.iter_mut() {
+        if let Some(name) = &mut local.name {
+            let minified = names.next();
+            map.locals
+                .insert(minified.clone(), mem::replace(name, minified));
+        }
+    }
+
+    if options.rename_exports {
+        let mut names = Names::new(&options.alphabet)?;
+        for export in module.exports.iter_mut() {
+            let minified = names.next();
+            map.exports
+                .insert(minified.clone(), export.name.to_string());
+            export.name = minified.into();
+        }
+    }
+
+    if !options.keep_name_section {
+        module.config.generate_name_section(false);
+    }
+    Ok(map)
+}
+
+/// Restore the original names recorded in `map`, returning how many names
+/// were restored.
+///
+/// Every function, local and export whose name is a minified name in `map`
+/// gets its original name back. Anything else is left alone.
+pub fn apply_name_map(module: &mut Module, map: &NameMap) -> usize {
+    let mut restored = 0;
+    for func in module.funcs.iter_mut() {
+        if let Some(original) = func.name.as_ref().and_then(|n| map.functions.get(n)) {
+            func.name = Some(original.clone());
+            restored += 1;
+        }
+    }
+    for local in module.locals.iter_mut() {
+        if let Some(original) = local.name.as_ref().and_then(|n| map.locals.get(n)) {
+            local.name = Some(original.clone());
+            restored += 1;
+        }
+    }
+    for export in module.exports.iter_mut() {
+        if let Some(original) = map.exports.get(&*export.name) {
+            export.name = original.as_str().into();
+            restored += 1;
+        }
+    }
+    restored
+}
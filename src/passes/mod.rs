@@ -1,6 +1,42 @@
 //! Passes over whole modules or individual functions.
 
+mod canonicalize_nans;
+mod const_addresses;
+mod data_overlap;
+mod dead_stores;
+mod dedup_functions;
 pub mod gc;
+mod lower_atomics;
+mod lower_bulk_memory;
+mod lower_multi_value;
+mod lower_sign_ext;
+mod lower_trunc_sat;
+mod manager;
+mod minify_names;
+mod replace_function_uses;
+mod simplify_branches;
+mod stub_imports;
+mod trap_sites;
 mod used;
 pub mod validate;
+pub use self::canonicalize_nans::{canonicalize_nans, NanMode};
+pub use self::const_addresses::{const_addresses, rebase_const_addresses};
+pub use self::const_addresses::{ConstAddrKind, ConstAddrUse, RebaseOptions};
+pub use self::data_overlap::{check_data_overlap, ActiveSegment, Overlap};
+pub use self::dead_stores::dead_store_elimination;
+pub use self::dedup_functions::{dedup_functions, dedup_functions_with};
+pub use self::dedup_functions::{DedupOptions, DedupStats};
+pub use self::lower_atomics::{lower_atomics, lower_atomics_with};
+pub use self::lower_atomics::{LowerAtomicsOptions, WaitResult};
+pub use self::lower_bulk_memory::{lower_bulk_memory, LoweringStyle};
+pub use self::lower_multi_value::{lower_multi_value, lower_multi_value_with};
+pub use self::lower_multi_value::{Boundary, ExtraResults, LowerMultiValueOptions};
+pub use self::lower_sign_ext::lower_sign_ext;
+pub use self::lower_trunc_sat::lower_trunc_sat;
+pub use self::manager::{builtin, Pass, PassManager, PassReport, PassStats};
+pub use self::minify_names::{apply_name_map, minify_names, MinifyOptions, NameMap};
+pub use self::replace_function_uses::replace_function_uses;
+pub use self::simplify_branches::{simplify_branches, SimplifyBranches};
+pub use self::stub_imports::{stub_missing_imports, StubKind};
+pub use self::trap_sites::{can_trap, trap_sites, TrapKind, TrapSite};
 pub use self::used::Used;
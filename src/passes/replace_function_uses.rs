@@ -0,0 +1,134 @@
+//! Pointing every use of a function at another function.
+
+use crate::ir::*;
+use crate::map::IdHashMap;
+use crate::{ExportItem, Function, FunctionId, FunctionKind, LocalFunction, Module, Result};
+use failure::bail;
+
+/// Replace every use of the function `old` with the function `new`,
+/// returning how many uses were replaced.
+///
+/// Calls and `ref.func`s in local functions, members of element segments and
+/// of function tables, exports and the start function are all rewritten.
+/// Uses in the body of `new` itself are left alone, so that `new` can be a
+/// wrapper which calls `old`.
+///
+/// `old` itself isn't removed, and is left to be garbage collected if
+/// nothing else uses it.
+///
+/// # Errors
+///
+/// Returns an error, leaving the module unchanged, if `old` and `new` have
+/// different types.
+pub fn replace_function_uses(
+    module: &mut Module,
+    old: FunctionId,
+    new: FunctionId,
+) -> Result<usize> {
+    let old_ty = module.funcs.get(old).ty();
+    let new_ty = module.funcs.get(new).ty();
+    if old_ty != new_ty {
+        bail!(
+            "can't replace uses of {:?} with {:?}, which has a different type",
+            old,
+            new
+        );
+    }
+    if old == new {
+        return Ok(0);
+    }
+
+    let mut map = IdHashMap::default();
+    map.insert(old, new);
+    Ok(replace_uses(module, &map, Some(new)))
+}
+
+/// Replace every use of each function in `map` with the function it maps to,
+/// returning how many uses were replaced.
+///
+/// Uses inside the function `skip` are left alone.
+pub(crate) fn replace_uses(
+    module: &mut Module,
+    map: &IdHashMap<Function, FunctionId>,
+    skip: Option<FunctionId>,
+) -> usize {
+    let mut found = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        if Some(id) == skip {
+            continue;
+        }
+        let mut uses = Uses {
+            func,
+            map,
+            uses: Vec::new(),
+        };
+        dfs_in_order(&mut uses, func, func.entry_block().into());
+        if !uses.uses.is_empty() {
+            found.push((id, uses.uses));
+        }
+    }
+
+    let mut replaced = 0;
+    for (id, uses) in found {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        for expr in uses {
+            let target = match func.get_mut(expr) {
+                Expr::Call(e) => &mut e.func,
+                Expr::RefFunc(e) => &mut e.func,
+                _ => continue,
+            };
+            *target = map[&*target];
+            replaced += 1;
+        }
+    }
+
+    let mut replace = |id: &mut FunctionId| {
+        if let Some(new) = map.get(id) {
+            *id = *new;
+            replaced += 1;
+        }
+    };
+    for mut segment in module.element_segments_mut() {
+        for id in segment.members_mut().iter_mut().filter_map(|e| e.as_mut()) {
+            replace(id);
+        }
+    }
+    for export in module.exports.iter_mut() {
+        if let ExportItem::Function(id) = &mut export.item {
+            replace(id);
+        }
+    }
+    if let Some(id) = &mut module.start {
+        replace(id);
+    }
+
+    replaced
+}
+
+/// Finds the calls and `ref.func`s referring to functions in a map.
+struct Uses<'a> {
+    func: &'a LocalFunction,
+    map: &'a IdHashMap<Function, FunctionId>,
+    uses: Vec<ExprId>,
+}
+
+impl<'a> Visitor<'a> for Uses<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        let func = match self.func.get(id) {
+            Expr::Call(e) => Some(e.func),
+            Expr::RefFunc(e) => Some(e.func),
+            _ => None,
+        };
+        if func.is_some_and(|f| self.map.contains_key(&f)) {
+            self.uses.push(id);
+        }
+        id.visit(self);
+    }
+}
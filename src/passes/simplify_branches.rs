@@ -0,0 +1,325 @@
+//! Simplifying branches whose outcome is already known.
+//!
+//! After constants have been propagated, function bodies tend to contain
+//! branches with constant conditions and selects between identical values.
+//! This pass rewrites those into the code that would actually run.
+
+use crate::ir::*;
+use crate::passes::{Pass, PassStats};
+use crate::{FunctionKind, LocalFunction, Module, Result};
+use std::collections::{HashMap, HashSet};
+
+/// The branch simplification pass, for running as part of a `PassManager`
+/// pipeline.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SimplifyBranches;
+
+impl Pass for SimplifyBranches {
+    fn name(&self) -> &str {
+        "simplify-branches"
+    }
+
+    fn run(&mut self, module: &mut Module) -> Result<PassStats> {
+        Ok(PassStats {
+            changed: simplify_branches(module) > 0,
+        })
+    }
+}
+
+/// Simplify branches with constant conditions, returning how many
+/// expressions were rewritten.
+///
+/// * A `br_if` with a constant condition becomes a `br` if it's taken, or is
+///   removed if it isn't.
+/// * An `if` with a constant condition is replaced with the arm that's
+///   taken, spliced into the enclosing block when nothing branches to the
+///   arm.
+/// * A `select` between two identical constants, locals or globals becomes
+///   that value, with its condition dropped afterwards if it has side
+///   effects.
+/// * A `br_table` with a constant index becomes a `br` to the block it
+///   selects.
+///
+/// Rewrites can enable each other, so this is best run to a fixpoint, for
+/// example with `PassManager::add_fixpoint`.
+pub fn simplify_branches(module: &mut Module) -> usize {
+    let mut plans = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut plan = Plan {
+            func,
+            rewrites: Rewrites::default(),
+        };
+        plan.rewrites.lists.push(func.entry_block().into());
+        dfs_in_order(&mut plan, func, func.entry_block().into());
+        if !plan.rewrites.is_empty() {
+            plans.push((id, plan.rewrites));
+        }
+    }
+
+    let mut simplified = 0;
+    for (id, rewrites) in plans {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        simplified += rewrites.apply(func);
+    }
+    simplified
+}
+
+/// Finds the rewrites to make in a function.
+struct Plan<'a> {
+    func: &'a LocalFunction,
+    rewrites: Rewrites,
+}
+
+/// The rewrites to make in a function.
+#[derive(Default)]
+struct Rewrites {
+    /// Expressions with lists of stack-neutral expressions, such as blocks.
+    lists: Vec<ExprId>,
+    /// Blocks which are the target of a branch.
+    targets: HashSet<ExprId>,
+    /// Every branch.
+    branches: Vec<ExprId>,
+    /// Expressions to overwrite in place.
+    replace: Vec<(ExprId, Expr)>,
+    /// `select`s to replace with their value, followed by dropping their
+    /// condition: `(select, value, condition)`.
+    selects: Vec<(ExprId, ExprId, ExprId)>,
+    /// Expressions to remove from the lists they're in.
+    remove: HashSet<ExprId>,
+    /// `if`s with constant conditions, and the arm that's taken.
+    ifs: HashMap<ExprId, BlockId>,
+}
+
+impl Plan<'_> {
+    fn constant(&self, expr: ExprId) -> Option<i32> {
+        match self.func.get(expr) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Plan<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        match self.func.get(id) {
+            Expr::Block(_) | Expr::WithSideEffects(_) => self.rewrites.lists.push(id),
+            Expr::Br(e) => {
+                self.rewrites.targets.insert(e.block.into());
+                self.rewrites.branches.push(id);
+            }
+            Expr::BrIf(e) => {
+                self.rewrites.targets.insert(e.block.into());
+                self.rewrites.branches.push(id);
+                match self.constant(e.condition) {
+                    Some(0) if e.args.is_empty() => {
+                        self.rewrites.remove.insert(id);
+                    }
+                    // When it isn't taken, a `br_if` leaves its argument on
+                    // the stack. Blocks can't be copied, since branches refer
+                    // to them.
+                    Some(0) if e.args.len() == 1 => match self.func.get(e.args[0]) {
+                        Expr::Block(_) => {}
+                        arg => self.rewrites.replace.push((id, arg.clone())),
+                    },
+                    Some(0) => {}
+                    Some(_) => {
+                        let br = Br {
+                            block: e.block,
+                            args: e.args.clone(),
+                        };
+                        self.rewrites.replace.push((id, br.into()));
+                    }
+                    None => {}
+                }
+            }
+            Expr::BrTable(e) => {
+                for block in e.blocks.iter().chain(Some(&e.default)) {
+                    self.rewrites.targets.insert((*block).into());
+                }
+                self.rewrites.branches.push(id);
+                if let Some(n) = self.constant(e.which) {
+                    let block = e.blocks.get(n as u32 as usize).unwrap_or(&e.default);
+                    let br = Br {
+                        block: *block,
+                        args: e.args.clone(),
+                    };
+                    self.rewrites.replace.push((id, br.into()));
+                }
+            }
+            // Delegating to a block branches to it, as far as exceptions are
+            // concerned.
+            Expr::Try(Try {
+                delegate: Some(block),
+                ..
+            }) => {
+                self.rewrites.targets.insert((*block).into());
+                self.rewrites.branches.push(id);
+            }
+            Expr::IfElse(e) => {
+                if let Some(n) = self.constant(e.condition) {
+                    let arm = if n != 0 { e.consequent } else { e.alternative };
+                    self.rewrites.ifs.insert(id, arm);
+                }
+            }
+            Expr::Select(e) => {
+                let consequent = self.func.get(e.consequent);
+                if same_pure_value(consequent, self.func.get(e.alternative)) {
+                    if is_pure(self.func.get(e.condition)) {
+                        self.rewrites.replace.push((id, consequent.clone()));
+                    } else {
+                        self.rewrites.selects.push((id, e.consequent, e.condition));
+                    }
+                }
+            }
+            _ => {}
+        }
+        id.visit(self);
+    }
+
+    // The arms of `if`s and the bodies and handlers of `try`s are lists too.
+    // Their contents are visited directly, so they're only recorded once.
+    fn visit_block_id(&mut self, &id: &BlockId) {
+        self.rewrites.lists.push(id.into());
+        self.func.block(id).visit(self);
+    }
+}
+
+impl Rewrites {
+    fn is_empty(&self) -> bool {
+        self.replace.is_empty()
+            && self.selects.is_empty()
+            && self.remove.is_empty()
+            && self.ifs.is_empty()
+    }
+
+    fn apply(self, func: &mut LocalFunction) -> usize {
+        let mut simplified = self.replace.len() + self.selects.len();
+
+        for (id, expr) in self.replace.iter() {
+            *func.get_mut(*id) = expr.clone();
+        }
+        for (id, value, condition) in self.selects.iter() {
+            let drop = func.alloc(Drop { expr: *condition });
+            *func.get_mut(*id) = Expr::WithSideEffects(WithSideEffects {
+                before: Vec::new(),
+                value: *value,
+                after: vec![drop.into()],
+            });
+        }
+
+        // Remove untaken `br_if`s and splice in the arms of `if`s, wherever
+        // they appear in a list.
+        let mut removed = HashSet::new();
+        let mut spliced = HashSet::new();
+        for list in self.lists.iter() {
+            match func.get(*list).clone() {
+                Expr::Block(mut block) => {
+                    block.exprs = self.flatten(func, &block.exprs, &mut removed, &mut spliced);
+                    *func.get_mut(*list) = Expr::Block(block);
+                }
+                Expr::WithSideEffects(mut e) => {
+                    e.before = self.flatten(func, &e.before, &mut removed, &mut spliced);
+                    e.after = self.flatten(func, &e.after, &mut removed, &mut spliced);
+                    *func.get_mut(*list) = Expr::WithSideEffects(e);
+                }
+                _ => {}
+            }
+        }
+        simplified += removed.len() + spliced.len();
+
+        // Any other `if` becomes a block in its own right, and branches to
+        // its arm now go to the block.
+        for (id, arm) in self.ifs.iter() {
+            if spliced.contains(id) {
+                continue;
+            }
+            let mut block = func.block(*arm).clone();
+            block.kind = BlockKind::Block;
+            *func.get_mut(*id) = Expr::Block(block);
+            let new_target = Block::new_id(*id);
+            for branch in self.branches.iter() {
+                retarget(func.get_mut(*branch), *arm, new_target);
+            }
+            simplified += 1;
+        }
+        simplified
+    }
+
+    /// Build a new list out of `exprs`, without removed expressions and with
+    /// the arms of `if`s inlined.
+    fn flatten(
+        &self,
+        func: &LocalFunction,
+        exprs: &[ExprId],
+        removed: &mut HashSet<ExprId>,
+        spliced: &mut HashSet<ExprId>,
+    ) -> Vec<ExprId> {
+        let mut ret = Vec::with_capacity(exprs.len());
+        for id in exprs {
+            if self.remove.contains(id) {
+                removed.insert(*id);
+                continue;
+            }
+            if let Some(arm) = self.ifs.get(id) {
+                let block = func.block(*arm);
+                if block.params.is_empty() && !self.targets.contains(&ExprId::from(*arm)) {
+                    spliced.insert(*id);
+                    ret.extend(self.flatten(func, &block.exprs, removed, spliced));
+                    continue;
+                }
+            }
+            ret.push(*id);
+        }
+        ret
+    }
+}
+
+/// Point a branch to `from` at `to` instead.
+fn retarget(branch: &mut Expr, from: BlockId, to: BlockId) {
+    let blocks = match branch {
+        Expr::Br(e) => vec![&mut e.block],
+        Expr::BrIf(e) => vec![&mut e.block],
+        Expr::BrTable(e) => e.blocks.iter_mut().chain(Some(&mut e.default)).collect(),
+        Expr::Try(e) => e.delegate.iter_mut().collect(),
+        _ => return,
+    };
+    for block in blocks {
+        if *block == from {
+            *block = to;
+        }
+    }
+}
+
+/// Is evaluating this expression free of side effects?
+fn is_pure(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Const(_) | Expr::LocalGet(_) | Expr::GlobalGet(_)
+    )
+}
+
+/// Are these the same constant, local or global?
+fn same_pure_value(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Const(a), Expr::Const(b)) => match (a.value, b.value) {
+            (Value::I32(a), Value::I32(b)) => a == b,
+            (Value::I64(a), Value::I64(b)) => a == b,
+            (Value::F32(a), Value::F32(b)) => a.to_bits() == b.to_bits(),
+            (Value::F64(a), Value::F64(b)) => a.to_bits() == b.to_bits(),
+            (Value::V128(a), Value::V128(b)) => a == b,
+            _ => false,
+        },
+        (Expr::LocalGet(a), Expr::LocalGet(b)) => a.local == b.local,
+        (Expr::GlobalGet(a), Expr::GlobalGet(b)) => a.global == b.global,
+        _ => false,
+    }
+}
@@ -0,0 +1,90 @@
+//! Replacing imported functions with local stubs, for hosts which don't
+//! provide them.
+
+use crate::error::UnstubbableImport;
+use crate::ir::*;
+use crate::{FunctionBuilder, ImportKind, Module, Result, ValType};
+
+/// What a stub does when it's called.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StubKind {
+    /// Trap with `unreachable`.
+    Trap,
+    /// Return zero, or null, for each of the function's results.
+    ReturnZero,
+}
+
+impl StubKind {
+    /// Build the body of a stub returning `results`.
+    pub fn body(&self, builder: &mut FunctionBuilder, results: &[ValType]) -> Vec<ExprId> {
+        match self {
+            StubKind::Trap => vec![builder.unreachable()],
+            StubKind::ReturnZero => results
+                .iter()
+                .map(|ty| match ty {
+                    ValType::I32 => builder.const_(Value::I32(0)),
+                    ValType::I64 => builder.const_(Value::I64(0)),
+                    ValType::F32 => builder.const_(Value::F32(0.0)),
+                    ValType::F64 => builder.const_(Value::F64(0.0)),
+                    ValType::V128 => builder.const_(Value::V128(0)),
+                    ValType::Externref => builder.ref_null(RefType::Externref),
+                    ValType::Funcref => builder.ref_null(RefType::Funcref),
+                    // There's no `ref.null` of a typed reference yet, and a
+                    // non-nullable one has no zero value at all.
+                    ValType::Ref { .. } => builder.unreachable(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Replace every imported function that `select` picks, given the module and
+/// name it's imported from, with a local stub, returning the module and name
+/// of each import that was stubbed.
+///
+/// The stubs keep the ids, types and names of the functions they replace, so
+/// calls, exports and element segments referring to them are unchanged.
+///
+/// Returns an `UnstubbableImport` error, leaving the module unchanged, if
+/// `select` picks an imported table, memory or global.
+pub fn stub_missing_imports(
+    module: &mut Module,
+    select: impl Fn(&str, &str) -> bool,
+    kind: StubKind,
+) -> Result<Vec<(String, String)>> {
+    let mut funcs = Vec::new();
+    for import in module.imports.iter() {
+        if !select(&import.module, &import.name) {
+            continue;
+        }
+        let item = match import.kind {
+            ImportKind::Function(func) => {
+                funcs.push((func, import.module.to_string(), import.name.to_string()));
+                continue;
+            }
+            ImportKind::Table(_) => "table",
+            ImportKind::Memory(_) => "memory",
+            ImportKind::Global(_) => "global",
+            ImportKind::Tag(_) => "tag",
+        };
+        return Err(UnstubbableImport {
+            module: import.module.to_string(),
+            name: import.name.to_string(),
+            kind: item,
+        }
+        .into());
+    }
+
+    let mut stubbed = Vec::with_capacity(funcs.len());
+    for (func, import_module, name) in funcs {
+        let ty = module.types.get(module.funcs.get(func).ty());
+        let params = ty.params().to_vec();
+        let results = ty.results().to_vec();
+        let args = params.iter().map(|ty| module.locals.add(*ty)).collect();
+        let mut builder = FunctionBuilder::new();
+        let body = kind.body(&mut builder, &results);
+        builder.finish_import(func, args, body, module)?;
+        stubbed.push((import_module, name));
+    }
+    Ok(stubbed)
+}
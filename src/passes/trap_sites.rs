@@ -0,0 +1,250 @@
+//! Finding the expressions which might trap.
+//!
+//! This is for auditing modules which are expected never to trap. It's
+//! conservative: every expression which might trap is reported, apart from a
+//! few cases where the operands are constants which show it can't.
+
+use crate::ir::*;
+use crate::map::IdHashSet;
+use crate::{Function, FunctionId, FunctionKind, LocalFunction, MemoryId, Module};
+use std::collections::HashMap;
+
+/// Why an expression might trap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    /// `unreachable`.
+    Unreachable,
+    /// An access to memory which might be out of bounds, or for atomic
+    /// accesses misaligned, including `memory.init`, `memory.copy` and
+    /// `memory.fill`.
+    MemoryAccess,
+    /// An integer division or remainder which might divide by zero or
+    /// overflow.
+    IntegerDivision,
+    /// A float-to-int conversion of a value which might be NaN or out of
+    /// range.
+    FloatToInt,
+    /// A `call_indirect`, whose table index might be out of bounds or refer to
+    /// a null entry or a function of the wrong type.
+    IndirectCall,
+    /// A `table.get`, `table.set`, `table.fill`, `table.init` or `table.copy`
+    /// which might be out of bounds.
+    TableAccess,
+    /// A `call_ref` or `ref.as_non_null` of a reference which might be null.
+    NullReference,
+}
+
+/// An expression which might trap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrapSite {
+    /// The function the expression is in.
+    pub func: FunctionId,
+    /// The expression.
+    pub expr: ExprId,
+    /// Why it might trap.
+    pub kind: TrapKind,
+}
+
+/// Find every expression in the module's local functions which might trap,
+/// in order of their function's id and then the order they're visited by
+/// `dfs_in_order`.
+///
+/// Direct calls aren't reported, even if the function they call might trap;
+/// `can_trap` accounts for those. Divisions and remainders by a constant
+/// which can't trap, and loads and stores of a constant address which are
+/// within the initial size of a memory, are left out.
+pub fn trap_sites(module: &Module) -> Vec<TrapSite> {
+    let memories = module
+        .memories
+        .iter()
+        .map(|m| (m.id(), u64::from(m.initial) * m.page_size()))
+        .collect::<HashMap<_, _>>();
+    let mut sites = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut finder = TrapFinder {
+            id,
+            func,
+            memories: &memories,
+            sites: &mut sites,
+        };
+        dfs_in_order(&mut finder, func, func.entry_block().into());
+    }
+    sites
+}
+
+/// Find every function which might trap when it's called.
+///
+/// That's each local function with a trap site, each function that's
+/// imported (since what it does isn't known), and each function which
+/// directly calls any of these.
+pub fn can_trap(module: &Module) -> IdHashSet<Function> {
+    let mut trapping = IdHashSet::default();
+    let mut stack = Vec::new();
+    for site in trap_sites(module) {
+        if trapping.insert(site.func) {
+            stack.push(site.func);
+        }
+    }
+    for func in module.funcs.iter() {
+        if let FunctionKind::Local(_) = func.kind {
+            continue;
+        }
+        if trapping.insert(func.id()) {
+            stack.push(func.id());
+        }
+    }
+
+    let mut callers = HashMap::new();
+    for (id, func) in module.funcs.iter_local() {
+        let mut calls = DirectCalls {
+            func,
+            callees: Vec::new(),
+        };
+        dfs_in_order(&mut calls, func, func.entry_block().into());
+        for callee in calls.callees {
+            callers.entry(callee).or_insert_with(Vec::new).push(id);
+        }
+    }
+    while let Some(callee) = stack.pop() {
+        for caller in callers.get(&callee).into_iter().flatten() {
+            if trapping.insert(*caller) {
+                stack.push(*caller);
+            }
+        }
+    }
+    trapping
+}
+
+struct TrapFinder<'a, 'b> {
+    id: FunctionId,
+    func: &'a LocalFunction,
+    /// The initial size of each memory, in bytes. Memories never shrink, so
+    /// accesses within these bounds can't trap.
+    memories: &'b HashMap<MemoryId, u64>,
+    sites: &'b mut Vec<TrapSite>,
+}
+
+impl TrapFinder<'_, '_> {
+    fn const_i64(&self, expr: ExprId) -> Option<i64> {
+        match self.func.get(expr) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => Some(i64::from(*n)),
+            Expr::Const(Const {
+                value: Value::I64(n),
+            }) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Is an access of `width` bytes at `address` plus `arg`'s offset within
+    /// the initial size of `memory`?
+    fn in_bounds(&self, memory: MemoryId, address: ExprId, arg: &MemArg, width: u32) -> bool {
+        let address = match self.func.get(address) {
+            Expr::Const(Const {
+                value: Value::I32(n),
+            }) => u64::from(*n as u32),
+            _ => return false,
+        };
+        let end = address + u64::from(arg.offset) + u64::from(width);
+        self.memories.get(&memory).is_some_and(|size| end <= *size)
+    }
+
+    fn trap_kind(&self, expr: &Expr) -> Option<TrapKind> {
+        match expr {
+            Expr::Unreachable(_) => Some(TrapKind::Unreachable),
+            Expr::CallIndirect(_) => Some(TrapKind::IndirectCall),
+            Expr::CallRef(_) | Expr::RefAsNonNull(_) => Some(TrapKind::NullReference),
+            Expr::TableGet(_)
+            | Expr::TableSet(_)
+            | Expr::TableFill(_)
+            | Expr::TableInit(_)
+            | Expr::TableCopy(_) => Some(TrapKind::TableAccess),
+
+            Expr::Binop(e) => {
+                use BinaryOp::*;
+                let divisor = self.const_i64(e.rhs);
+                let safe = match e.op {
+                    I32DivU | I32RemU | I64DivU | I64RemU | I32RemS | I64RemS => {
+                        divisor.is_some_and(|n| n != 0)
+                    }
+                    I32DivS | I64DivS => divisor.is_some_and(|n| n != 0 && n != -1),
+                    _ => return None,
+                };
+                if safe {
+                    None
+                } else {
+                    Some(TrapKind::IntegerDivision)
+                }
+            }
+            Expr::Unop(e) => {
+                use UnaryOp::*;
+                match e.op {
+                    I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 | I64TruncSF32
+                    | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 => Some(TrapKind::FloatToInt),
+                    _ => None,
+                }
+            }
+
+            Expr::Load(e) => {
+                if !e.kind.atomic() && self.in_bounds(e.memory, e.address, &e.arg, e.kind.width()) {
+                    None
+                } else {
+                    Some(TrapKind::MemoryAccess)
+                }
+            }
+            Expr::Store(e) => {
+                if !e.kind.atomic() && self.in_bounds(e.memory, e.address, &e.arg, e.kind.width()) {
+                    None
+                } else {
+                    Some(TrapKind::MemoryAccess)
+                }
+            }
+            Expr::AtomicRmw(_)
+            | Expr::Cmpxchg(_)
+            | Expr::AtomicNotify(_)
+            | Expr::AtomicWait(_)
+            | Expr::MemoryInit(_)
+            | Expr::MemoryCopy(_)
+            | Expr::MemoryFill(_) => Some(TrapKind::MemoryAccess),
+
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for TrapFinder<'a, '_> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Some(kind) = self.trap_kind(self.func.get(id)) {
+            self.sites.push(TrapSite {
+                func: self.id,
+                expr: id,
+                kind,
+            });
+        }
+        id.visit(self);
+    }
+}
+
+/// Collects the functions a function calls directly.
+struct DirectCalls<'a> {
+    func: &'a LocalFunction,
+    callees: Vec<FunctionId>,
+}
+
+impl<'a> Visitor<'a> for DirectCalls<'a> {
+    fn local_function(&self) -> &'a LocalFunction {
+        self.func
+    }
+
+    fn visit_expr_id(&mut self, &id: &ExprId) {
+        if let Expr::Call(call) = self.func.get(id) {
+            self.callees.push(call.func);
+        }
+        id.visit(self);
+    }
+}
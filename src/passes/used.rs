@@ -1,9 +1,9 @@
 use crate::ir::*;
 use crate::map::IdHashSet;
-use crate::{Data, DataId, Element, ExportId, ExportItem, Function, InitExpr};
+use crate::{Data, DataId, Element, ElementId, ElementKind, ExportId, ExportItem, Function};
 use crate::{FunctionId, FunctionKind, Global, GlobalId, LocalFunction};
-use crate::{GlobalKind, ImportKind, Memory, MemoryId, Table, TableId};
-use crate::{Module, TableKind, Type, TypeId};
+use crate::{GlobalKind, ImportKind, Memory, MemoryId, SegmentSource, Table, TableId};
+use crate::{Module, ModuleLocals, ModuleTypes, Tag, TagId, Type, TypeId, ValType};
 
 /// Finds the things within a module that are used.
 ///
@@ -26,6 +26,8 @@ pub struct Used {
     pub elements: IdHashSet<Element>,
     /// The module's used passive data segments.
     pub data: IdHashSet<Data>,
+    /// The module's used exception tags.
+    pub tags: IdHashSet<Tag>,
 }
 
 impl Used {
@@ -42,6 +44,7 @@ impl Used {
             tables: Vec::new(),
             globals: Vec::new(),
             memories: Vec::new(),
+            elements: Vec::new(),
         };
 
         for r in roots {
@@ -50,6 +53,9 @@ impl Used {
                 ExportItem::Table(t) => stack.push_table(t),
                 ExportItem::Memory(m) => stack.push_memory(m),
                 ExportItem::Global(g) => stack.push_global(g),
+                ExportItem::Tag(t) => {
+                    stack.used.tags.insert(t);
+                }
             }
         }
         if let Some(f) = module.start {
@@ -59,6 +65,7 @@ impl Used {
         // Initialization of imported memories or imported tables is a
         // side-effectful operation, so be sure to retain any tables/memories
         // that are imported and initialized, even if they aren't used.
+        let segments = module.element_segments();
         for import in module.imports.iter() {
             match import.kind {
                 ImportKind::Memory(m) => {
@@ -67,26 +74,33 @@ impl Used {
                         stack.push_memory(m);
                     }
                 }
-                ImportKind::Table(t) => {
-                    let table = module.tables.get(t);
-                    match &table.kind {
-                        TableKind::Function(init) => {
-                            if !init.elements.is_empty() || !init.relative_elements.is_empty() {
-                                stack.push_table(t);
-                            }
-                        }
-                        TableKind::Anyref(_) => {}
-                    }
+                ImportKind::Table(t) if segments.iter().any(|s| s.table() == Some(t)) => {
+                    stack.push_table(t);
                 }
                 _ => {}
             }
         }
 
+        // Declared element segments only exist to declare which functions may
+        // be referenced with `ref.func`, so keep them along with their
+        // functions.
+        for segment in segments.iter() {
+            if let ElementKind::Declared = segment.kind() {
+                if let SegmentSource::Element(id) = segment.source() {
+                    stack.used.elements.insert(id);
+                }
+                for func in segment.members().iter().filter_map(|f| *f) {
+                    stack.push_func(func);
+                }
+            }
+        }
+
         // Iteratively visit all items until our stack is empty
-        while stack.functions.len() > 0
-            || stack.tables.len() > 0
-            || stack.memories.len() > 0
-            || stack.globals.len() > 0
+        while !stack.functions.is_empty()
+            || !stack.tables.is_empty()
+            || !stack.memories.is_empty()
+            || !stack.globals.is_empty()
+            || !stack.elements.is_empty()
         {
             while let Some(f) = stack.functions.pop() {
                 let func = module.funcs.get(f);
@@ -94,42 +108,42 @@ impl Used {
 
                 match &func.kind {
                     FunctionKind::Local(func) => {
-                        func.entry_block().visit(&mut UsedVisitor {
+                        let mut visitor = UsedVisitor {
                             func,
+                            types: &module.types,
+                            locals: &module.locals,
                             stack: &mut stack,
-                        });
+                        };
+                        dfs_in_order(&mut visitor, func, func.entry_block().into());
                     }
-                    FunctionKind::Import(_) => {}
-                    FunctionKind::Uninitialized(_) => unreachable!(),
+                    FunctionKind::Import(_) | FunctionKind::Uninitialized(_) => {}
                 }
             }
 
+            // Active segments copied into a table are used along with the
+            // table.
             while let Some(t) = stack.tables.pop() {
-                match &module.tables.get(t).kind {
-                    TableKind::Function(list) => {
-                        for id in list.elements.iter() {
-                            if let Some(id) = id {
-                                stack.push_func(*id);
-                            }
-                        }
-                        for (global, list) in list.relative_elements.iter() {
-                            stack.push_global(*global);
-                            for id in list {
-                                stack.push_func(*id);
-                            }
-                        }
+                for segment in segments.iter().filter(|s| s.table() == Some(t)) {
+                    if let SegmentSource::Element(id) = segment.source() {
+                        stack.used.elements.insert(id);
+                    }
+                    for global in segment.offset().iter().flat_map(|o| o.globals()) {
+                        stack.push_global(global);
+                    }
+                    for func in segment.members().iter().filter_map(|f| *f) {
+                        stack.push_func(func);
                     }
-                    TableKind::Anyref(_) => {}
                 }
             }
 
             while let Some(t) = stack.globals.pop() {
-                match &module.globals.get(t).kind {
-                    GlobalKind::Import(_) => {}
-                    GlobalKind::Local(InitExpr::Global(global)) => {
-                        stack.push_global(*global);
+                if let GlobalKind::Local(init) = &module.globals.get(t).kind {
+                    for global in init.globals() {
+                        stack.push_global(global);
+                    }
+                    for func in init.funcs() {
+                        stack.push_func(func);
                     }
-                    GlobalKind::Local(InitExpr::Value(_)) => {}
                 }
             }
 
@@ -138,6 +152,44 @@ impl Used {
                     stack.push_global(global);
                 }
             }
+
+            // Passive segments are only used by `table.init`, which may copy
+            // any of their functions into a table.
+            while let Some(e) = stack.elements.pop() {
+                for func in module.elements.get(e).members.iter().filter_map(|f| *f) {
+                    stack.push_func(func);
+                }
+            }
+        }
+
+        // Tags don't refer to anything but their type, so they don't need a
+        // stack of their own.
+        let tags = used.tags.iter().map(|t| module.tags.get(*t).ty);
+        let tag_types = tags.collect::<Vec<_>>();
+        used.types.extend(tag_types);
+
+        // A type section using the GC proposal is emitted exactly as it was,
+        // so all of its types are always used.
+        let raw_types = module.types.iter().filter(|t| t.raw_index().is_some());
+        used.types.extend(raw_types.map(|t| t.id()));
+
+        // Typed references need the type of the function they refer to, which
+        // can itself refer to other types.
+        let globals = used.globals.iter().map(|g| module.globals.get(*g).ty);
+        let mut types = globals
+            .filter_map(|ty| ty.referenced_type())
+            .chain(used.types.iter().cloned())
+            .collect::<Vec<_>>();
+        while let Some(t) = types.pop() {
+            used.types.insert(t);
+            let ty = module.types.get(t);
+            for r in ty.params().iter().chain(ty.results()) {
+                if let Some(r) = r.referenced_type() {
+                    if !used.types.contains(&r) {
+                        types.push(r);
+                    }
+                }
+            }
         }
 
         used
@@ -150,6 +202,7 @@ struct UsedStack<'a> {
     tables: Vec<TableId>,
     memories: Vec<MemoryId>,
     globals: Vec<GlobalId>,
+    elements: Vec<ElementId>,
 }
 
 impl UsedStack<'_> {
@@ -176,10 +229,18 @@ impl UsedStack<'_> {
             self.memories.push(f);
         }
     }
+
+    fn push_element(&mut self, e: ElementId) {
+        if self.used.elements.insert(e) {
+            self.elements.push(e);
+        }
+    }
 }
 
 struct UsedVisitor<'a, 'b> {
     func: &'a LocalFunction,
+    types: &'a ModuleTypes,
+    locals: &'a ModuleLocals,
     stack: &'a mut UsedStack<'b>,
 }
 
@@ -208,7 +269,35 @@ impl<'expr> Visitor<'expr> for UsedVisitor<'expr, '_> {
         self.stack.used.types.insert(t);
     }
 
+    fn visit_local_id(&mut self, &l: &LocalId) {
+        if let Some(t) = self.locals.get(l).ty().referenced_type() {
+            self.stack.used.types.insert(t);
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        // Multi-value blocks refer to their type by index, so keep it around.
+        if block.needs_type_index() {
+            if let Some(t) = self.types.find(&block.params, &block.results) {
+                self.stack.used.types.insert(t);
+            }
+        }
+        let tys = block.params.iter().chain(block.results.iter());
+        for t in tys.filter_map(ValType::referenced_type) {
+            self.stack.used.types.insert(t);
+        }
+        block.visit(self);
+    }
+
     fn visit_data_id(&mut self, &t: &DataId) {
         self.stack.used.data.insert(t);
     }
+
+    fn visit_element_id(&mut self, &e: &ElementId) {
+        self.stack.push_element(e);
+    }
+
+    fn visit_tag_id(&mut self, &t: &TagId) {
+        self.stack.used.tags.insert(t);
+    }
 }
@@ -4,9 +4,13 @@
 //! eventually this is a full typechecking pass!
 
 use crate::ir::*;
+use crate::Result;
 use crate::ValType;
-use crate::{DataId, Function, FunctionKind, InitExpr, LocalFunction, Result};
-use crate::{Global, GlobalKind, Memory, MemoryId, Module, Table, TableKind};
+use crate::{
+    DataId, Element, ElementId, ElementKind, Function, FunctionKind, InitExpr, InitOp,
+    LocalFunction,
+};
+use crate::{Global, GlobalKind, Memory, MemoryId, Module, Table, TableKind, WasmFeatures};
 use failure::{bail, ResultExt};
 use rayon::prelude::*;
 use std::collections::HashSet;
@@ -26,6 +30,12 @@ pub fn run(module: &Module) -> Result<()> {
 
     for memory in module.memories.iter() {
         validate_memory(memory)?;
+        for (offset, _) in memory.data.iter() {
+            if let InitExpr::Extended(ops) = offset {
+                validate_extended(module, &ops, ValType::I32)
+                    .context("invalid offset of data segment")?;
+            }
+        }
     }
     for table in module.tables.iter() {
         validate_table(table)?;
@@ -33,13 +43,24 @@ pub fn run(module: &Module) -> Result<()> {
     for global in module.globals.iter() {
         validate_global(module, global)?;
     }
+    for element in module.elements.iter() {
+        validate_element(module, element)?;
+    }
+    for tag in module.tags.iter() {
+        if !module.types.get(tag.ty).results().is_empty() {
+            bail!("tag types must not have any results");
+        }
+    }
     validate_exports(module)?;
+    if module.config.skip_ref_func_declarations {
+        validate_ref_func_declarations(module)?;
+    }
 
     // Validate the start function, if present, has the correct signature
     if let Some(start) = module.start {
         let ty = module.funcs.get(start).ty();
         let ty = module.types.get(ty);
-        if ty.results().len() > 0 || ty.params().len() > 0 {
+        if !ty.results().is_empty() || !ty.params().is_empty() {
             bail!("start function must take no arguments and return nothing");
         }
     }
@@ -68,11 +89,11 @@ pub fn run(module: &Module) -> Result<()> {
             a.extend(b);
             a
         });
-    if errs.len() == 0 {
+    if errs.is_empty() {
         return Ok(());
     }
 
-    let mut msg = format!("errors validating module:\n");
+    let mut msg = "errors validating module:\n".to_string();
     for error in errs {
         msg.push_str(&format!("  * {}\n", error));
         for cause in error.iter_causes() {
@@ -86,13 +107,17 @@ fn validate_memory(m: &Memory) -> Result<()> {
     if m.shared && m.maximum.is_none() {
         bail!("shared memories must have a maximum size");
     }
-    validate_limits(m.initial, m.maximum, u32::from(u16::max_value()) + 1)
-        .context("when validating a memory")?;
+    let k = match m.page_size_log2 {
+        None | Some(16) => u32::from(u16::MAX) + 1,
+        Some(0) => u32::MAX,
+        Some(n) => bail!("invalid page size of 2^{} bytes, must be 1 or 65536", n),
+    };
+    validate_limits(m.initial, m.maximum, k).context("when validating a memory")?;
     Ok(())
 }
 
 fn validate_table(t: &Table) -> Result<()> {
-    validate_limits(t.initial, t.maximum, u32::max_value()).context("when validating a table")?;
+    validate_limits(t.initial, t.maximum, u32::MAX).context("when validating a table")?;
 
     // Ensure that the table element type is `anyfunc`. This does
     // nothing, but if new wasm versions and future parity-wasm releases
@@ -100,7 +125,7 @@ fn validate_table(t: &Table) -> Result<()> {
     // something.
     match t.kind {
         TableKind::Function(_) => {}
-        TableKind::Anyref(_) => {}
+        TableKind::Externref(_) => {}
     }
     Ok(())
 }
@@ -132,6 +157,26 @@ fn validate_exports(module: &Module) -> Result<()> {
     Ok(())
 }
 
+fn validate_ref_func_declarations(module: &Module) -> Result<()> {
+    // Without the declarations that emitting adds, every `ref.func` target
+    // has to be declared already.
+    let undeclared = module.undeclared_ref_func_targets();
+    if undeclared.is_empty() {
+        return Ok(());
+    }
+    let names = undeclared
+        .iter()
+        .map(|id| match &module.funcs.get(*id).name {
+            Some(name) => format!("`{}`", name),
+            None => format!("{:?}", id),
+        })
+        .collect::<Vec<_>>();
+    bail!(
+        "functions referenced by `ref.func` but never declared: {}",
+        names.join(", ")
+    )
+}
+
 fn validate_global(module: &Module, global: &Global) -> Result<()> {
     match global.kind {
         GlobalKind::Import(_) => return Ok(()),
@@ -146,10 +191,86 @@ fn validate_global(module: &Module, global: &Global) -> Result<()> {
                     bail!("initializer for local global must be imported global");
                 }
             }
+            if other.mutable {
+                bail!("initializer for local global must be immutable global");
+            }
             if other.ty != global.ty {
                 bail!("locally defined global does not match type of import");
             }
         }
+        GlobalKind::Local(InitExpr::RefFunc(_)) => {
+            if global.ty != ValType::Funcref {
+                bail!("invalid type on global");
+            }
+        }
+        GlobalKind::Local(InitExpr::RefNull(ty)) => {
+            if global.ty != ty.val_type() {
+                bail!("invalid type on global");
+            }
+        }
+        GlobalKind::Local(InitExpr::Extended(ref ops)) => {
+            validate_extended(module, ops, global.ty).context("invalid initializer on global")?;
+        }
+    }
+    Ok(())
+}
+
+/// Type check an extended constant expression, which must produce a single
+/// value of type `ty`.
+fn validate_extended(module: &Module, ops: &[InitOp], ty: ValType) -> Result<()> {
+    let mut stack = Vec::new();
+    for op in ops {
+        let (operand, result) = match *op {
+            InitOp::Value(value) => (None, value.ty()),
+            InitOp::Global(id) => {
+                let global = module.globals.get(id);
+                if global.mutable {
+                    bail!("constant expressions can only read immutable globals");
+                }
+                (None, global.ty)
+            }
+            InitOp::RefFunc(_) => (None, ValType::Funcref),
+            InitOp::RefNull(ty) => (None, ty.val_type()),
+            InitOp::I32Add | InitOp::I32Sub | InitOp::I32Mul => (Some(ValType::I32), ValType::I32),
+            InitOp::I64Add | InitOp::I64Sub | InitOp::I64Mul => (Some(ValType::I64), ValType::I64),
+        };
+        if let Some(operand) = operand {
+            for _ in 0..2 {
+                if stack.pop() != Some(operand) {
+                    bail!("expected two `{}` operands for `{:?}`", operand, op);
+                }
+            }
+        }
+        stack.push(result);
+    }
+    if stack != [ty] {
+        bail!("constant expression does not produce a single `{}`", ty);
+    }
+    Ok(())
+}
+
+fn validate_element(module: &Module, element: &Element) -> Result<()> {
+    match element.ty {
+        ValType::Funcref => {}
+        ValType::Externref => {
+            if element.members.iter().any(|m| m.is_some()) {
+                bail!("element segment of externrefs can only hold `ref.null`");
+            }
+        }
+        _ => bail!("invalid type on element segment"),
+    }
+    if let ElementKind::Active { table, ref offset } = element.kind {
+        if let InitExpr::Extended(ops) = offset {
+            validate_extended(module, ops, ValType::I32)
+                .context("invalid offset of element segment")?;
+        }
+        let table_ty = match module.tables.get(table).kind {
+            TableKind::Function(_) => ValType::Funcref,
+            TableKind::Externref(_) => ValType::Externref,
+        };
+        if table_ty != element.ty {
+            bail!("element segment does not match the type of its table");
+        }
     }
     Ok(())
 }
@@ -184,9 +305,15 @@ impl Validate<'_> {
     }
 
     fn require_shared(&mut self, m: MemoryId) {
-        let mem = self.module.memories.get(m);
-        if !mem.shared {
+        if self.module.memories.get(m).shared {
+            return;
+        }
+        if !self.module.config.allow_unshared_atomics {
             self.err("atomic operations require a shared memory");
+        } else if let Some(name) = &self.function.name {
+            log::warn!("atomic operation on unshared memory in function {}", name);
+        } else {
+            log::warn!("atomic operation on unshared memory");
         }
     }
 
@@ -197,6 +324,16 @@ impl Validate<'_> {
         }
     }
 
+    fn require_features(&mut self, features: WasmFeatures, name: &str) {
+        if features.relaxed_simd && !self.module.config.relaxed_simd {
+            self.err(&format!(
+                "relaxed SIMD instruction `{}` used without enabling \
+                 `ModuleConfig::relaxed_simd`",
+                name
+            ));
+        }
+    }
+
     fn err(&mut self, msg: &str) {
         let mut err = failure::format_err!("{}", msg);
         if let Some(name) = &self.function.name {
@@ -251,6 +388,61 @@ impl<'a> Visitor<'a> for Validate<'a> {
         e.visit(self);
     }
 
+    fn visit_unop(&mut self, e: &Unop) {
+        if let Some((features, name)) = WasmFeatures::for_unop(e.op) {
+            self.require_features(features, name);
+        }
+        e.visit(self);
+    }
+
+    fn visit_binop(&mut self, e: &Binop) {
+        if let Some((features, name)) = WasmFeatures::for_binop(e.op) {
+            self.require_features(features, name);
+        }
+        e.visit(self);
+    }
+
+    fn visit_ternop(&mut self, e: &Ternop) {
+        let (features, name) = WasmFeatures::for_ternop(e.op);
+        self.require_features(features, name);
+        e.visit(self);
+    }
+
+    fn visit_ref_is_null(&mut self, e: &RefIsNull) {
+        // Parsed modules are checked as they're parsed, but nothing stops a
+        // `ref.is_null` of a number from being built.
+        if let Ok(Some((_, results))) = self.local.expr_type(self.module, e.value) {
+            match results.as_slice() {
+                [ValType::Externref] | [ValType::Funcref] | [ValType::Ref { .. }] => {}
+                _ => self.err("ref.is_null requires a reference"),
+            }
+        }
+        e.visit(self);
+    }
+
+    fn visit_ref_as_non_null(&mut self, e: &RefAsNonNull) {
+        if let Ok(Some((_, results))) = self.local.expr_type(self.module, e.value) {
+            match results.as_slice() {
+                [ValType::Externref] | [ValType::Funcref] | [ValType::Ref { .. }] => {}
+                _ => self.err("ref.as_non_null requires a reference"),
+            }
+        }
+        e.visit(self);
+    }
+
+    fn visit_call_ref(&mut self, e: &CallRef) {
+        // The callee has to be a typed reference to a function of exactly
+        // the type being called.
+        if let Ok(Some((_, results))) = self.local.expr_type(self.module, e.func) {
+            match results.as_slice() {
+                [ValType::Ref { ty, .. }] if *ty == e.ty => {}
+                [ValType::Ref { .. }] => self.err("call_ref type does not match its callee"),
+                _ => self.err("call_ref requires a typed function reference"),
+            }
+        }
+        e.visit(self);
+    }
+
     fn visit_data_id(&mut self, id: &DataId) {
         // Catch references in `memory.init` and such instructions to active
         // data segments, which our implementation will generate but in general
@@ -259,4 +451,12 @@ impl<'a> Visitor<'a> for Validate<'a> {
             self.err("referenced data segment is not passive");
         }
     }
+
+    fn visit_element_id(&mut self, id: &ElementId) {
+        // Likewise for active element segments in `table.init` and
+        // `elem.drop`.
+        if let ElementKind::Active { .. } = self.module.elements.get(*id).kind {
+            self.err("referenced element segment is not passive");
+        }
+    }
 }
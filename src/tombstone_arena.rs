@@ -88,6 +88,16 @@ impl<T> TombstoneArena<T> {
         self.inner.len() - self.dead.len()
     }
 
+    /// Reserve space for `additional` items, if nothing has been allocated
+    /// yet. `id_arena` only takes a capacity when an arena is created, and a
+    /// new arena has a new id, so this can't be done once there are ids into
+    /// this one.
+    pub fn reserve(&mut self, additional: usize) {
+        if self.inner.len() == 0 {
+            self.inner = InnerArena::with_capacity(additional);
+        }
+    }
+
     pub fn contains(&self, id: Id<T>) -> bool {
         self.inner.get(id).is_some() && !self.dead.contains(&id)
     }
@@ -98,7 +108,7 @@ impl<T> TombstoneArena<T> {
             .filter(move |&(id, _)| !self.dead.contains(&id))
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         IterMut {
             dead: &self.dead,
             inner: self.inner.iter_mut(),
@@ -114,7 +124,7 @@ impl<T> TombstoneArena<T> {
             .filter(move |&(id, _)| !self.dead.contains(&id))
     }
 
-    pub fn par_iter_mut(&mut self) -> ParIterMut<T>
+    pub fn par_iter_mut(&mut self) -> ParIterMut<'_, T>
     where
         T: Send + Sync,
     {
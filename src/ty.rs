@@ -1,22 +1,35 @@
 //! WebAssembly function and value types.
 
-use crate::emit::{Emit, EmitContext};
+use crate::emit::{Emit, EmitContext, IdsToIndices};
 use crate::encode::Encoder;
-use crate::error::Result;
+use crate::error::{Result, UnsupportedType};
 use crate::tombstone_arena::Tombstone;
 use id_arena::Id;
+use std::convert::TryFrom;
 use std::fmt;
 use std::hash;
+use std::sync::Arc;
 
 /// An identifier for types.
 pub type TypeId = Id<Type>;
 
-/// A function type.
+/// The parameters and results of a function type.
+pub(crate) type Signature = (Box<[ValType]>, Box<[ValType]>);
+
+/// A function type, or a type walrus passes through without understanding
+/// it, such as a struct type from the GC proposal.
 #[derive(Debug, Clone)]
 pub struct Type {
     id: TypeId,
-    params: Box<[ValType]>,
-    results: Box<[ValType]>,
+    params: Arc<[ValType]>,
+    results: Arc<[ValType]>,
+
+    /// The index this type was parsed at, if it's from a type section using
+    /// the GC proposal, which walrus emits exactly as it was.
+    raw: Option<u32>,
+
+    /// Whether this type is opaque, see `is_opaque`.
+    opaque: bool,
 
     /// An optional name for debugging.
     ///
@@ -29,7 +42,10 @@ impl PartialEq for Type {
     #[inline]
     fn eq(&self, rhs: &Type) -> bool {
         // NB: do not compare id or name.
-        self.params == rhs.params && self.results == rhs.results
+        //
+        // Types from a type section using the GC proposal are never equal to
+        // any other type, since they may be in different recursion groups.
+        self.params == rhs.params && self.results == rhs.results && self.raw == rhs.raw
     }
 }
 
@@ -40,14 +56,15 @@ impl hash::Hash for Type {
     fn hash<H: hash::Hasher>(&self, h: &mut H) {
         // Do not hash id or name.
         self.params.hash(h);
-        self.results.hash(h)
+        self.results.hash(h);
+        self.raw.hash(h)
     }
 }
 
 impl Tombstone for Type {
     fn on_delete(&mut self) {
-        self.params = Box::new([]);
-        self.results = Box::new([]);
+        self.params = Arc::new([]);
+        self.results = Arc::new([]);
     }
 }
 
@@ -55,14 +72,35 @@ impl Type {
     /// Construct a new function type.
     #[inline]
     pub fn new(id: TypeId, params: Box<[ValType]>, results: Box<[ValType]>) -> Type {
+        Type::new_shared(id, params.into(), results.into())
+    }
+
+    /// Construct a new function type whose parameters and results may be
+    /// shared with other types.
+    pub(crate) fn new_shared(id: TypeId, params: Arc<[ValType]>, results: Arc<[ValType]>) -> Type {
         Type {
             id,
             params,
             results,
+            raw: None,
+            opaque: false,
             name: None,
         }
     }
 
+    /// Construct the type at `index` of a type section using the GC
+    /// proposal, which is a function type if `func` is given and opaque
+    /// otherwise.
+    pub(crate) fn new_raw(id: TypeId, index: u32, func: Option<Signature>) -> Type {
+        let opaque = func.is_none();
+        let (params, results) = func.unwrap_or_default();
+        Type {
+            raw: Some(index),
+            opaque,
+            ..Type::new(id, params, results)
+        }
+    }
+
     /// Get the id of this type.
     #[inline]
     pub fn id(&self) -> TypeId {
@@ -72,13 +110,52 @@ impl Type {
     /// Get the parameters to this function type.
     #[inline]
     pub fn params(&self) -> &[ValType] {
-        &*self.params
+        &self.params
     }
 
     /// Get the results of this function type.
     #[inline]
     pub fn results(&self) -> &[ValType] {
-        &*self.results
+        &self.results
+    }
+
+    /// Is this a type walrus doesn't understand?
+    ///
+    /// These are struct and array types, and function types using value
+    /// types walrus can't represent, from a module parsed with
+    /// `ModuleConfig::allow_gc_types`. They have no parameters or results.
+    #[inline]
+    pub fn is_opaque(&self) -> bool {
+        self.opaque
+    }
+
+    /// The index this type was parsed at, if it's from a type section which
+    /// walrus emits exactly as it was.
+    pub(crate) fn raw_index(&self) -> Option<u32> {
+        self.raw
+    }
+}
+
+impl fmt::Display for Type {
+    /// Formats the type like `[i32, i32] -> [i64]`, or as `opaque`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter, tys: &[ValType]) -> fmt::Result {
+            f.write_str("[")?;
+            for (i, ty) in tys.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", ty)?;
+            }
+            f.write_str("]")
+        }
+
+        if self.opaque {
+            return f.write_str("opaque");
+        }
+        list(f, self.params())?;
+        f.write_str(" -> ")?;
+        list(f, self.results())
     }
 }
 
@@ -103,8 +180,19 @@ pub enum ValType {
     F64,
     /// 128-bit vector.
     V128,
-    /// The `anyref` opaque value type
-    Anyref,
+    /// An opaque reference from the host, `externref`, which was called
+    /// `anyref` before the reference types proposal was finalized.
+    Externref,
+    /// A reference to a function, `funcref`.
+    Funcref,
+    /// A typed reference to a function of the given type, from the function
+    /// references proposal: `(ref $t)`, or `(ref null $t)` if it's nullable.
+    Ref {
+        /// Whether this reference can be null.
+        nullable: bool,
+        /// The type of the referenced function.
+        ty: TypeId,
+    },
 }
 
 impl ValType {
@@ -118,48 +206,96 @@ impl ValType {
     }
 
     pub(crate) fn parse(input: &wasmparser::Type) -> Result<ValType> {
-        match input {
-            wasmparser::Type::I32 => Ok(ValType::I32),
-            wasmparser::Type::I64 => Ok(ValType::I64),
-            wasmparser::Type::F32 => Ok(ValType::F32),
-            wasmparser::Type::F64 => Ok(ValType::F64),
-            wasmparser::Type::V128 => Ok(ValType::V128),
-            wasmparser::Type::AnyRef => Ok(ValType::Anyref),
-            _ => failure::bail!("not a value type"),
+        Ok(ValType::try_from(*input)?)
+    }
+
+    /// The type of the function referenced by a typed reference, if this is
+    /// one.
+    pub fn referenced_type(&self) -> Option<TypeId> {
+        match self {
+            ValType::Ref { ty, .. } => Some(*ty),
+            _ => None,
         }
     }
 
-    pub(crate) fn emit(&self, encoder: &mut Encoder) {
+    pub(crate) fn emit(&self, encoder: &mut Encoder, indices: &IdsToIndices) {
         match self {
             ValType::I32 => encoder.byte(0x7f),
             ValType::I64 => encoder.byte(0x7e),
             ValType::F32 => encoder.byte(0x7d),
             ValType::F64 => encoder.byte(0x7c),
             ValType::V128 => encoder.byte(0x7b),
-            ValType::Anyref => encoder.byte(0x6f),
+            ValType::Externref => encoder.byte(0x6f),
+            ValType::Funcref => encoder.byte(0x70),
+            ValType::Ref { nullable, ty } => {
+                encoder.byte(if *nullable { 0x63 } else { 0x64 });
+                // The heap type is a type index, as a signed LEB.
+                let index = indices.get_type_index(*ty);
+                encoder.i64(i64::from(index));
+            }
+        }
+    }
+}
+
+impl TryFrom<wasmparser::Type> for ValType {
+    type Error = UnsupportedType;
+
+    fn try_from(ty: wasmparser::Type) -> std::result::Result<ValType, UnsupportedType> {
+        // NB: no wildcard here, so that new `wasmparser` types have to be
+        // considered when upgrading.
+        match ty {
+            wasmparser::Type::I32 => Ok(ValType::I32),
+            wasmparser::Type::I64 => Ok(ValType::I64),
+            wasmparser::Type::F32 => Ok(ValType::F32),
+            wasmparser::Type::F64 => Ok(ValType::F64),
+            wasmparser::Type::V128 => Ok(ValType::V128),
+            wasmparser::Type::AnyRef => Ok(ValType::Externref),
+            wasmparser::Type::AnyFunc => Ok(ValType::Funcref),
+            wasmparser::Type::Func | wasmparser::Type::EmptyBlockType => Err(UnsupportedType {
+                ty,
+                expected: "value type",
+            }),
+        }
+    }
+}
+
+impl From<ValType> for wasmparser::Type {
+    fn from(ty: ValType) -> wasmparser::Type {
+        match ty {
+            ValType::I32 => wasmparser::Type::I32,
+            ValType::I64 => wasmparser::Type::I64,
+            ValType::F32 => wasmparser::Type::F32,
+            ValType::F64 => wasmparser::Type::F64,
+            ValType::V128 => wasmparser::Type::V128,
+            ValType::Externref => wasmparser::Type::AnyRef,
+            // `wasmparser` doesn't know about typed references yet, and these
+            // are the closest it has.
+            ValType::Funcref | ValType::Ref { .. } => wasmparser::Type::AnyFunc,
         }
     }
 }
 
 impl fmt::Display for ValType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                ValType::I32 => "i32",
-                ValType::I64 => "i64",
-                ValType::F32 => "f32",
-                ValType::F64 => "f64",
-                ValType::V128 => "v128",
-                ValType::Anyref => "anyref",
+        let name = match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+            ValType::F32 => "f32",
+            ValType::F64 => "f64",
+            ValType::V128 => "v128",
+            ValType::Externref => "externref",
+            ValType::Funcref => "funcref",
+            ValType::Ref { nullable, ty } => {
+                let null = if *nullable { "null " } else { "" };
+                return write!(f, "(ref {}{})", null, ty.index());
             }
-        )
+        };
+        f.write_str(name)
     }
 }
 
 impl Emit for ValType {
     fn emit(&self, cx: &mut EmitContext) {
-        self.emit(&mut cx.encoder);
+        self.emit(&mut cx.encoder, cx.indices);
     }
 }